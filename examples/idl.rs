@@ -0,0 +1,11 @@
+// Prints the program's IDL; the checked-in idl.json is regenerated with
+// `cargo run --example idl > idl.json`
+fn main() {
+    match energy_trading_program::idl::generate() {
+        Ok(idl) => print!("{}", idl),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,17 @@
+// Malformed instruction data must be rejected with an error, never a panic. What decodes exactly
+// must encode back to the same bytes, and unpack must read the same instruction from the front of
+// anything it accepts
+#![no_main]
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use energy_trading_program::EnergyMarketInstruction;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(instruction) = EnergyMarketInstruction::try_from_slice(data) {
+        assert_eq!(instruction.try_to_vec().unwrap(), data);
+    }
+    if let Ok(instruction) = EnergyMarketInstruction::unpack(data) {
+        assert!(data.starts_with(&instruction.try_to_vec().unwrap()));
+    }
+});
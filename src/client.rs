@@ -0,0 +1,1466 @@
+//! Instruction builders matching the account order and signer flags each processor expects.
+//!
+//! ```
+//! use energy_trading_program::{client, ParticipantMetadata, ParticipantType, TimeInForce};
+//! use solana_program::pubkey::Pubkey;
+//!
+//! let ledger = Pubkey::new_unique();
+//! let wallet = Pubkey::new_unique();
+//!
+//! let metadata = ParticipantMetadata::new("Riverside Bakery", "MTR-0042", 51_507_351, -127_758);
+//! let register = client::register_participant_ix(ledger, wallet, ParticipantType::Consumer, 0, 0, metadata);
+//! let deposit = client::deposit_ix(ledger, wallet, 1_000_000, None);
+//! let delivery_slot = energy_trading_program::delivery_slot_at(1_700_000_000) + 1;
+//! let demand = client::post_demand_ix(ledger, wallet, 10, 50, 0, delivery_slot, TimeInForce::GoodTilCancelled, 0, false, 0, 0, None);
+//! let matching = client::match_transactions_ix(ledger, wallet, 32, client::participant_metas(ledger, &[wallet]));
+//!
+//! assert_eq!(register.program_id, energy_trading_program::id());
+//! assert!(deposit.accounts[0].is_signer);
+//! # let _ = (demand, matching);
+//! ```
+use borsh::BorshSerialize;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_program,
+};
+
+use crate::{
+    find_bilateral_offer_address, find_forward_contract_address, find_order_address, find_participant_address, find_vault_address,
+    ConfigUpdate, EnergyMarketInstruction, EnergySource, GovernanceConfig, HistoryPolicy, ImbalanceConfig, LedgerCapacity, MarketConfig, OrderSide,
+    OrderStorage, ParticipantMetadata, ParticipantType, ReputationConfig, RewardRates, TariffSchedule, TimeInForce,
+};
+
+fn build(instruction: EnergyMarketInstruction, accounts: Vec<AccountMeta>) -> Instruction {
+    Instruction {
+        program_id: crate::id(),
+        accounts,
+        data: instruction.try_to_vec().expect("instruction serialization is infallible"),
+    }
+}
+
+/// Has `delegate` sign an order instruction in place of the owner's wallet: report, post and cancel
+/// instructions accept the participant's delegate, appended as the last account. On account
+/// storage the delegate pays the new order PDA's rent, which is refunded to the owner's wallet.
+pub fn with_delegate(mut instruction: Instruction, delegate: Pubkey) -> Instruction {
+    instruction.accounts[0].is_signer = false;
+    instruction.accounts.push(AccountMeta::new(delegate, true));
+    instruction
+}
+
+/// Adds the co-signing approvers a privileged instruction needs on a multisig ledger; the builder's
+/// own admin argument must be one of the approvers and counts towards the threshold.
+pub fn with_approvers(mut instruction: Instruction, approvers: &[Pubkey]) -> Instruction {
+    instruction.accounts.extend(approvers.iter().map(|approver| AccountMeta::new_readonly(*approver, true)));
+    instruction
+}
+
+fn participant_meta(ledger: Pubkey, wallet: Pubkey) -> AccountMeta {
+    AccountMeta::new(find_participant_address(&crate::id(), &ledger, &wallet).0, false)
+}
+
+fn order_meta(ledger: Pubkey, order_id: u64) -> AccountMeta {
+    AccountMeta::new(find_order_address(&crate::id(), &ledger, order_id).0, false)
+}
+
+/// Remaining accounts for MatchTransactions, RunAuction and PruneExpiredOrders on
+/// `OrderStorage::Ledger`: the participant PDAs of the given wallets.
+pub fn participant_metas(ledger: Pubkey, wallets: &[Pubkey]) -> Vec<AccountMeta> {
+    wallets.iter().map(|wallet| participant_meta(ledger, *wallet)).collect()
+}
+
+/// The order PDAs of the given order ids, read-only.
+pub fn open_order_metas(ledger: Pubkey, order_ids: &[u64]) -> Vec<AccountMeta> {
+    order_ids.iter()
+        .map(|order_id| AccountMeta::new_readonly(find_order_address(&crate::id(), &ledger, *order_id).0, false))
+        .collect()
+}
+
+/// Remaining accounts for MatchTransactions, RunAuction and PruneExpiredOrders on
+/// `OrderStorage::Accounts`: an (order PDA, owner wallet, owner participant PDA) triple per
+/// `(order_id, owner)`.
+pub fn order_metas(ledger: Pubkey, orders: &[(u64, Pubkey)]) -> Vec<AccountMeta> {
+    orders.iter()
+        .flat_map(|(order_id, owner)| [
+            order_meta(ledger, *order_id),
+            AccountMeta::new(*owner, false),
+            participant_meta(ledger, *owner),
+        ])
+        .collect()
+}
+
+/// `payer` becomes the ledger admin and funds the vault PDA. With an `oracle` every production
+/// offer must be attested with [`attest_production_ix`] before it can match. The ledger account must already be
+/// allocated to this program with at least `ledger_space(&capacity)` bytes.
+pub fn initialize_ledger_ix(
+    ledger: Pubkey,
+    payer: Pubkey,
+    capacity: LedgerCapacity,
+    quote_mint: Option<Pubkey>,
+    config: MarketConfig,
+    oracle: Option<Pubkey>,
+) -> Instruction {
+    build(
+        EnergyMarketInstruction::InitializeLedger { capacity, quote_mint, config, oracle },
+        vec![
+            AccountMeta::new(ledger, false),
+            AccountMeta::new(payer, true),
+            AccountMeta::new(find_vault_address(&crate::id(), &ledger).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// ```
+/// use energy_trading_program::{client, find_participant_address, ParticipantMetadata, ParticipantType};
+/// use solana_program::pubkey::Pubkey;
+///
+/// let ledger = Pubkey::new_unique();
+/// let wallet = Pubkey::new_unique();
+/// let ix = client::register_participant_ix(ledger, wallet, ParticipantType::Producer, 0, 500, ParticipantMetadata::default());
+///
+/// let (participant, _) = find_participant_address(&energy_trading_program::id(), &ledger, &wallet);
+/// assert_eq!(ix.accounts[2].pubkey, participant);
+/// ```
+pub fn register_participant_ix(
+    ledger: Pubkey,
+    wallet: Pubkey,
+    participant_type: ParticipantType,
+    zone: u8,
+    registered_capacity: u64,
+    metadata: ParticipantMetadata,
+) -> Instruction {
+    build(
+        EnergyMarketInstruction::RegisterParticipant { participant_type, zone, registered_capacity, metadata },
+        vec![
+            AccountMeta::new(wallet, true),
+            AccountMeta::new(ledger, false),
+            participant_meta(ledger, wallet),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// On `OrderStorage::Accounts` pass the ledger's current `next_order_id` as `order_id` so the
+/// new order PDA is included, then append [`open_order_metas`] for every other open order of the
+/// producer so its offers for the slot can be checked against its registered capacity.
+///
+/// Immediate orders never rest, so they pass `None` as `order_id` and append the resting
+/// orders they may cross, in the [`match_transactions_ix`] layout, after everything else.
+///
+/// On `OrderStorage::Ledger` a resting offer with the same price, slot, expiry, source and fill
+/// options as an open offer of the producer is added to that order, which emits `OrderAmended`;
+/// pass `distinct_lot` to always rest a new order.
+#[allow(clippy::too_many_arguments)]
+pub fn report_production_ix(
+    ledger: Pubkey,
+    producer: Pubkey,
+    energy_amount: u64,
+    price: i64,
+    expires_at: i64,
+    delivery_slot: u32,
+    energy_source: EnergySource,
+    time_in_force: TimeInForce,
+    min_fill: u64,
+    all_or_nothing: bool,
+    client_order_nonce: u64,
+    distinct_lot: bool,
+    order_id: Option<u64>,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(producer, true),
+        AccountMeta::new(ledger, false),
+        participant_meta(ledger, producer),
+    ];
+    if let Some(order_id) = order_id {
+        accounts.push(order_meta(ledger, order_id));
+        accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+    }
+    build(
+        EnergyMarketInstruction::ReportProduction {
+            energy_amount,
+            price,
+            expires_at,
+            delivery_slot,
+            energy_source,
+            time_in_force,
+            min_fill,
+            all_or_nothing,
+            client_order_nonce,
+            distinct_lot,
+        },
+        accounts,
+    )
+}
+
+/// On `OrderStorage::Accounts` pass the ledger's current `next_order_id` as `order_id` so the
+/// new order PDA is included. Immediate orders pass `None` and append the resting orders they
+/// may cross, as for [`report_production_ix`]. A `priority` above the ledger's
+/// `max_unflagged_priority` needs a consumer flagged by [`set_critical_load_ix`].
+#[allow(clippy::too_many_arguments)]
+pub fn post_demand_ix(
+    ledger: Pubkey,
+    consumer: Pubkey,
+    energy_amount: u64,
+    price_limit: i64,
+    expires_at: i64,
+    delivery_slot: u32,
+    time_in_force: TimeInForce,
+    min_fill: u64,
+    all_or_nothing: bool,
+    client_order_nonce: u64,
+    priority: u8,
+    order_id: Option<u64>,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(consumer, true),
+        AccountMeta::new(ledger, false),
+        participant_meta(ledger, consumer),
+    ];
+    if let Some(order_id) = order_id {
+        accounts.push(order_meta(ledger, order_id));
+        accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+    }
+    build(
+        EnergyMarketInstruction::PostDemand {
+            energy_amount,
+            price_limit,
+            expires_at,
+            delivery_slot,
+            time_in_force,
+            min_fill,
+            all_or_nothing,
+            client_order_nonce,
+            priority,
+        },
+        accounts,
+    )
+}
+
+/// `cranker` signs and collects the crank reward in its participant PDA. `remaining` comes from
+/// [`participant_metas`] or [`order_metas`] depending on the ledger's order storage, preceded by
+/// [`trade_log_meta`] on a ledger with a trade log. The return data decodes as
+/// [`MatchRunCompleted`](crate::events::MatchRunCompleted), so simulating it shows whether a crank
+/// would do anything. With a grid fee configured the grid operator's participant PDA must be among
+/// those passed, unless the grid operator cranks.
+pub fn match_transactions_ix(ledger: Pubkey, cranker: Pubkey, max_matches: u16, remaining: Vec<AccountMeta>) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(ledger, false),
+        AccountMeta::new_readonly(cranker, true),
+        participant_meta(ledger, cranker),
+    ];
+    accounts.extend(remaining);
+    build(EnergyMarketInstruction::MatchTransactions { max_matches }, accounts)
+}
+
+/// Registers the wallet and deposits `amount` in one instruction; `token_accounts` as for `deposit_ix`.
+#[allow(clippy::too_many_arguments)]
+pub fn register_and_deposit_ix(
+    ledger: Pubkey,
+    wallet: Pubkey,
+    participant_type: ParticipantType,
+    zone: u8,
+    registered_capacity: u64,
+    metadata: ParticipantMetadata,
+    amount: u64,
+    token_accounts: Option<(Pubkey, Pubkey)>,
+) -> Instruction {
+    let mut accounts = register_participant_ix(ledger, wallet, participant_type.clone(), zone, registered_capacity, metadata).accounts;
+    accounts.extend(deposit_ix(ledger, wallet, amount, token_accounts).accounts.into_iter().skip(3));
+    build(
+        EnergyMarketInstruction::RegisterAndDeposit { participant_type, zone, registered_capacity, metadata, amount },
+        accounts,
+    )
+}
+
+/// Token ledgers pass `Some((source_token_account, vault_token_account))`; native ledgers pass `None`.
+pub fn deposit_ix(ledger: Pubkey, participant: Pubkey, amount: u64, token_accounts: Option<(Pubkey, Pubkey)>) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(participant, true),
+        AccountMeta::new_readonly(ledger, false),
+        participant_meta(ledger, participant),
+        AccountMeta::new(find_vault_address(&crate::id(), &ledger).0, false),
+    ];
+    match token_accounts {
+        Some((source_token_account, vault_token_account)) => {
+            accounts.push(AccountMeta::new(source_token_account, false));
+            accounts.push(AccountMeta::new(vault_token_account, false));
+            accounts.push(AccountMeta::new_readonly(spl_token::id(), false));
+        }
+        None => accounts.push(AccountMeta::new_readonly(system_program::id(), false)),
+    }
+    build(EnergyMarketInstruction::Deposit { amount }, accounts)
+}
+
+/// `destination` is a wallet on native ledgers and a token account of the quote mint on token
+/// ledgers, which also pass the vault token account.
+pub fn withdraw_ix(
+    ledger: Pubkey,
+    participant: Pubkey,
+    destination: Pubkey,
+    amount: u64,
+    vault_token_account: Option<Pubkey>,
+) -> Instruction {
+    build(
+        EnergyMarketInstruction::Withdraw { amount },
+        withdraw_accounts(ledger, participant, destination, vault_token_account),
+    )
+}
+
+/// Withdraws the participant's whole free balance.
+pub fn withdraw_all_ix(ledger: Pubkey, participant: Pubkey, destination: Pubkey, vault_token_account: Option<Pubkey>) -> Instruction {
+    build(
+        EnergyMarketInstruction::WithdrawAll,
+        withdraw_accounts(ledger, participant, destination, vault_token_account),
+    )
+}
+
+/// `amount` of None requests the whole free balance.
+pub fn request_withdrawal_ix(ledger: Pubkey, participant: Pubkey, amount: Option<u64>) -> Instruction {
+    build(
+        EnergyMarketInstruction::RequestWithdrawal { amount },
+        vec![
+            AccountMeta::new_readonly(participant, true),
+            AccountMeta::new_readonly(ledger, false),
+            participant_meta(ledger, participant),
+        ],
+    )
+}
+
+pub fn claim_withdrawal_ix(ledger: Pubkey, participant: Pubkey, destination: Pubkey, vault_token_account: Option<Pubkey>) -> Instruction {
+    build(
+        EnergyMarketInstruction::ClaimWithdrawal,
+        withdraw_accounts(ledger, participant, destination, vault_token_account),
+    )
+}
+
+pub fn veto_withdrawal_ix(ledger: Pubkey, admin: Pubkey, participant: Pubkey) -> Instruction {
+    build(
+        EnergyMarketInstruction::VetoWithdrawal,
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new_readonly(ledger, false),
+            participant_meta(ledger, participant),
+        ],
+    )
+}
+
+fn withdraw_accounts(ledger: Pubkey, participant: Pubkey, destination: Pubkey, vault_token_account: Option<Pubkey>) -> Vec<AccountMeta> {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(participant, true),
+        AccountMeta::new_readonly(ledger, false),
+        participant_meta(ledger, participant),
+        AccountMeta::new(destination, false),
+        AccountMeta::new(find_vault_address(&crate::id(), &ledger).0, false),
+    ];
+    if let Some(vault_token_account) = vault_token_account {
+        accounts.push(AccountMeta::new(vault_token_account, false));
+        accounts.push(AccountMeta::new_readonly(spl_token::id(), false));
+    }
+    accounts
+}
+
+pub fn cancel_demand_ix(ledger: Pubkey, consumer: Pubkey, order_id: u64, order_storage: OrderStorage) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(consumer, true),
+        AccountMeta::new(ledger, false),
+        participant_meta(ledger, consumer),
+    ];
+    if order_storage == OrderStorage::Accounts {
+        accounts.push(order_meta(ledger, order_id));
+    }
+    build(EnergyMarketInstruction::CancelDemand { order_id }, accounts)
+}
+
+pub fn cancel_production_ix(ledger: Pubkey, producer: Pubkey, order_id: u64, order_storage: OrderStorage) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(producer, true),
+        AccountMeta::new(ledger, false),
+        participant_meta(ledger, producer),
+    ];
+    if order_storage == OrderStorage::Accounts {
+        accounts.push(order_meta(ledger, order_id));
+    }
+    build(EnergyMarketInstruction::CancelProduction { order_id }, accounts)
+}
+
+/// `remaining` comes from [`participant_metas`] or [`order_metas`] depending on the ledger's order storage.
+pub fn prune_expired_orders_ix(ledger: Pubkey, remaining: Vec<AccountMeta>) -> Instruction {
+    let mut accounts = vec![AccountMeta::new(ledger, false)];
+    accounts.extend(remaining);
+    build(EnergyMarketInstruction::PruneExpiredOrders, accounts)
+}
+
+/// Same accounts as [`match_transactions_ix`].
+pub fn run_auction_ix(ledger: Pubkey, cranker: Pubkey, remaining: Vec<AccountMeta>) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(ledger, false),
+        AccountMeta::new_readonly(cranker, true),
+        participant_meta(ledger, cranker),
+    ];
+    accounts.extend(remaining);
+    build(EnergyMarketInstruction::RunAuction, accounts)
+}
+
+pub fn resize_ledger_ix(ledger: Pubkey, admin: Pubkey, funding: Pubkey, new_size: u32, capacity: LedgerCapacity) -> Instruction {
+    build(
+        EnergyMarketInstruction::ResizeLedger { new_size, capacity },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+            AccountMeta::new(funding, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+pub fn migrate_ledger_ix(ledger: Pubkey, admin: Pubkey, funding: Pubkey) -> Instruction {
+    build(
+        EnergyMarketInstruction::MigrateLedger,
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+            AccountMeta::new(funding, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+pub fn set_fee_ix(ledger: Pubkey, admin: Pubkey, fee_bps: u16) -> Instruction {
+    build(
+        EnergyMarketInstruction::SetFee { fee_bps },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+        ],
+    )
+}
+
+pub fn set_withdrawal_delay_ix(ledger: Pubkey, admin: Pubkey, withdrawal_delay: i64, withdrawal_threshold: u64) -> Instruction {
+    build(
+        EnergyMarketInstruction::SetWithdrawalDelay { withdrawal_delay, withdrawal_threshold },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+        ],
+    )
+}
+
+pub fn set_deposit_limits_ix(ledger: Pubkey, admin: Pubkey, min_deposit: u64, min_balance_to_post_demand: u64) -> Instruction {
+    build(
+        EnergyMarketInstruction::SetDepositLimits { min_deposit, min_balance_to_post_demand },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+        ],
+    )
+}
+
+pub fn update_config_ix(ledger: Pubkey, admin: Pubkey, update: ConfigUpdate) -> Instruction {
+    build(
+        EnergyMarketInstruction::UpdateConfig { update },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+        ],
+    )
+}
+
+/// `trade_log` must already be allocated to [`crate::trade_log_space`] bytes and assigned to the program.
+pub fn initialize_trade_log_ix(ledger: Pubkey, admin: Pubkey, trade_log: Pubkey, capacity: u32, history_policy: HistoryPolicy) -> Instruction {
+    build(
+        EnergyMarketInstruction::InitializeTradeLog { capacity, history_policy },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+            AccountMeta::new(trade_log, false),
+        ],
+    )
+}
+
+/// Goes first among the accounts a matching instruction crosses, or last for
+/// [`accept_bilateral_offer_ix`], to record its trades in the trade log.
+pub fn trade_log_meta(trade_log: Pubkey) -> AccountMeta {
+    AccountMeta::new(trade_log, false)
+}
+
+/// Token ledgers pass `Some(vault_token_account)`; native ledgers pass `None`.
+pub fn close_ledger_ix(ledger: Pubkey, admin: Pubkey, recipient: Pubkey, vault_token_account: Option<Pubkey>) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(admin, true),
+        AccountMeta::new(ledger, false),
+        AccountMeta::new(recipient, false),
+        AccountMeta::new(find_vault_address(&crate::id(), &ledger).0, false),
+    ];
+    if let Some(vault_token_account) = vault_token_account {
+        accounts.push(AccountMeta::new(vault_token_account, false));
+        accounts.push(AccountMeta::new_readonly(spl_token::id(), false));
+    }
+    build(EnergyMarketInstruction::CloseLedger, accounts)
+}
+
+/// The first approver added also makes the current admin an approver, with a threshold of one.
+pub fn add_approver_ix(ledger: Pubkey, admin: Pubkey, approver: Pubkey) -> Instruction {
+    build(EnergyMarketInstruction::AddApprover { approver }, admin_accounts(ledger, admin))
+}
+
+pub fn remove_approver_ix(ledger: Pubkey, admin: Pubkey, approver: Pubkey) -> Instruction {
+    build(EnergyMarketInstruction::RemoveApprover { approver }, admin_accounts(ledger, admin))
+}
+
+pub fn set_approval_threshold_ix(ledger: Pubkey, admin: Pubkey, threshold: u8) -> Instruction {
+    build(EnergyMarketInstruction::SetApprovalThreshold { threshold }, admin_accounts(ledger, admin))
+}
+
+fn admin_accounts(ledger: Pubkey, admin: Pubkey) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new_readonly(admin, true),
+        AccountMeta::new(ledger, false),
+    ]
+}
+
+/// `effective_at` must be at least the ledger's `config_timelock` seconds away.
+pub fn propose_config_change_ix(ledger: Pubkey, admin: Pubkey, update: ConfigUpdate, effective_at: i64) -> Instruction {
+    build(
+        EnergyMarketInstruction::ProposeConfigChange { update, effective_at },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+        ],
+    )
+}
+
+pub fn apply_config_change_ix(ledger: Pubkey) -> Instruction {
+    build(EnergyMarketInstruction::ApplyConfigChange, vec![AccountMeta::new(ledger, false)])
+}
+
+pub fn cancel_config_change_ix(ledger: Pubkey, admin: Pubkey) -> Instruction {
+    build(
+        EnergyMarketInstruction::CancelConfigChange,
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+        ],
+    )
+}
+
+pub fn set_reputation_config_ix(ledger: Pubkey, admin: Pubkey, reputation: ReputationConfig) -> Instruction {
+    build(
+        EnergyMarketInstruction::SetReputationConfig { reputation },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+        ],
+    )
+}
+
+pub fn set_tariff_schedule_ix(ledger: Pubkey, admin: Pubkey, schedule: TariffSchedule) -> Instruction {
+    build(
+        EnergyMarketInstruction::SetTariffSchedule { schedule },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+        ],
+    )
+}
+
+pub fn set_imbalance_config_ix(ledger: Pubkey, admin: Pubkey, imbalance: ImbalanceConfig) -> Instruction {
+    build(
+        EnergyMarketInstruction::SetImbalanceConfig { imbalance },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+        ],
+    )
+}
+
+/// Starts a new reward epoch; points already accrued are unaffected.
+pub fn set_reward_rates_ix(ledger: Pubkey, admin: Pubkey, rates: RewardRates) -> Instruction {
+    build(
+        EnergyMarketInstruction::SetRewardRates { rates },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+        ],
+    )
+}
+
+pub fn set_governance_config_ix(ledger: Pubkey, admin: Pubkey, governance: GovernanceConfig) -> Instruction {
+    build(
+        EnergyMarketInstruction::SetGovernanceConfig { governance },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+        ],
+    )
+}
+
+/// `withdrawal_limit` of None returns the participant to the ledger-wide limit.
+pub fn set_withdrawal_limit_ix(ledger: Pubkey, admin: Pubkey, wallet: Pubkey, withdrawal_limit: Option<u64>) -> Instruction {
+    build(
+        EnergyMarketInstruction::SetWithdrawalLimit { withdrawal_limit },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new_readonly(ledger, false),
+            participant_meta(ledger, wallet),
+        ],
+    )
+}
+
+pub fn set_order_increments_ix(ledger: Pubkey, admin: Pubkey, price_tick: u64, lot_size: u64) -> Instruction {
+    build(
+        EnergyMarketInstruction::SetOrderIncrements { price_tick, lot_size },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+        ],
+    )
+}
+
+pub fn set_order_size_limits_ix(ledger: Pubkey, admin: Pubkey, min_order_size: u64, max_order_size: u64) -> Instruction {
+    build(
+        EnergyMarketInstruction::SetOrderSizeLimits { min_order_size, max_order_size },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+        ],
+    )
+}
+
+pub fn set_reference_price_ix(ledger: Pubkey, admin: Pubkey, reference_price: i64) -> Instruction {
+    build(
+        EnergyMarketInstruction::SetReferencePrice { reference_price },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+        ],
+    )
+}
+
+/// The admin must be a registered participant; fees land in its wallet_balance.
+pub fn collect_fees_ix(ledger: Pubkey, admin: Pubkey) -> Instruction {
+    build(
+        EnergyMarketInstruction::CollectFees,
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+            participant_meta(ledger, admin),
+        ],
+    )
+}
+
+pub fn set_pending_admin_ix(ledger: Pubkey, admin: Pubkey, new_admin: Pubkey) -> Instruction {
+    build(
+        EnergyMarketInstruction::SetPendingAdmin { new_admin },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+        ],
+    )
+}
+
+pub fn accept_admin_ix(ledger: Pubkey, new_admin: Pubkey) -> Instruction {
+    build(
+        EnergyMarketInstruction::AcceptAdmin,
+        vec![
+            AccountMeta::new_readonly(new_admin, true),
+            AccountMeta::new(ledger, false),
+        ],
+    )
+}
+
+pub fn pause_ix(ledger: Pubkey, admin: Pubkey) -> Instruction {
+    build(
+        EnergyMarketInstruction::Pause,
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+        ],
+    )
+}
+
+pub fn unpause_ix(ledger: Pubkey, admin: Pubkey) -> Instruction {
+    build(
+        EnergyMarketInstruction::Unpause,
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+        ],
+    )
+}
+
+pub fn set_delegate_ix(ledger: Pubkey, wallet: Pubkey, delegate: Pubkey) -> Instruction {
+    build(EnergyMarketInstruction::SetDelegate { delegate }, delegate_accounts(ledger, wallet))
+}
+
+pub fn revoke_delegate_ix(ledger: Pubkey, wallet: Pubkey) -> Instruction {
+    build(EnergyMarketInstruction::RevokeDelegate, delegate_accounts(ledger, wallet))
+}
+
+fn delegate_accounts(ledger: Pubkey, wallet: Pubkey) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new_readonly(wallet, true),
+        AccountMeta::new_readonly(ledger, false),
+        participant_meta(ledger, wallet),
+    ]
+}
+
+/// Changes to or from [`ParticipantType::Storage`] also need the ledger admin's signature.
+pub fn change_participant_type_ix(ledger: Pubkey, wallet: Pubkey, new_type: ParticipantType, admin: Option<Pubkey>) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(wallet, true),
+        AccountMeta::new_readonly(ledger, false),
+        participant_meta(ledger, wallet),
+    ];
+    if let Some(admin) = admin {
+        accounts.push(AccountMeta::new_readonly(admin, true));
+    }
+    build(EnergyMarketInstruction::ChangeParticipantType { new_type }, accounts)
+}
+
+/// Text longer than a field is truncated by [`ParticipantMetadata::new`].
+pub fn update_participant_metadata_ix(ledger: Pubkey, wallet: Pubkey, metadata: ParticipantMetadata) -> Instruction {
+    build(
+        EnergyMarketInstruction::UpdateParticipantMetadata { metadata },
+        vec![
+            AccountMeta::new_readonly(wallet, true),
+            AccountMeta::new_readonly(ledger, false),
+            participant_meta(ledger, wallet),
+        ],
+    )
+}
+
+pub fn unregister_participant_ix(ledger: Pubkey, wallet: Pubkey) -> Instruction {
+    build(
+        EnergyMarketInstruction::UnregisterParticipant,
+        vec![
+            AccountMeta::new(wallet, true),
+            AccountMeta::new(ledger, false),
+            participant_meta(ledger, wallet),
+        ],
+    )
+}
+
+/// `destination` is the wallet itself on native ledgers and a quote-mint token account owned by
+/// the wallet on token ledgers, which also pass the vault token account. On
+/// `OrderStorage::Accounts` list every open order of the wallet in `order_ids`.
+pub fn force_unregister_participant_ix(
+    ledger: Pubkey,
+    admin: Pubkey,
+    wallet: Pubkey,
+    destination: Pubkey,
+    vault_token_account: Option<Pubkey>,
+    order_ids: &[u64],
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(admin, true),
+        AccountMeta::new(ledger, false),
+        AccountMeta::new(wallet, false),
+        participant_meta(ledger, wallet),
+        AccountMeta::new(destination, false),
+        AccountMeta::new(find_vault_address(&crate::id(), &ledger).0, false),
+    ];
+    if let Some(vault_token_account) = vault_token_account {
+        accounts.push(AccountMeta::new(vault_token_account, false));
+        accounts.push(AccountMeta::new_readonly(spl_token::id(), false));
+    }
+    accounts.extend(order_ids.iter().map(|order_id| order_meta(ledger, *order_id)));
+    build(EnergyMarketInstruction::ForceUnregisterParticipant, accounts)
+}
+
+/// On `OrderStorage::Accounts` list the wallet's open orders in `order_ids` to close them.
+pub fn freeze_participant_ix(ledger: Pubkey, admin: Pubkey, wallet: Pubkey, order_ids: &[u64]) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(admin, true),
+        AccountMeta::new(ledger, false),
+        AccountMeta::new(wallet, false),
+        participant_meta(ledger, wallet),
+    ];
+    accounts.extend(order_ids.iter().map(|order_id| order_meta(ledger, *order_id)));
+    build(EnergyMarketInstruction::FreezeParticipant, accounts)
+}
+
+pub fn unfreeze_participant_ix(ledger: Pubkey, admin: Pubkey, wallet: Pubkey) -> Instruction {
+    build(
+        EnergyMarketInstruction::UnfreezeParticipant,
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new_readonly(ledger, false),
+            participant_meta(ledger, wallet),
+        ],
+    )
+}
+
+pub fn set_grid_operator_ix(ledger: Pubkey, admin: Pubkey, grid_operator: Pubkey) -> Instruction {
+    build(
+        EnergyMarketInstruction::SetGridOperator { grid_operator },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+        ],
+    )
+}
+
+/// On `OrderStorage::Accounts` list the producer's open offers in `order_ids` so they can be cut.
+pub fn curtail_ix(
+    ledger: Pubkey,
+    grid_operator: Pubkey,
+    producer: Pubkey,
+    energy_amount: u64,
+    curtailed_until: i64,
+    order_ids: &[u64],
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(grid_operator, true),
+        AccountMeta::new(ledger, false),
+        AccountMeta::new(producer, false),
+        participant_meta(ledger, producer),
+    ];
+    accounts.extend(order_ids.iter().map(|order_id| order_meta(ledger, *order_id)));
+    build(EnergyMarketInstruction::Curtail { energy_amount, curtailed_until }, accounts)
+}
+
+pub fn set_registered_capacity_ix(ledger: Pubkey, admin: Pubkey, wallet: Pubkey, registered_capacity: u64) -> Instruction {
+    build(
+        EnergyMarketInstruction::SetRegisteredCapacity { registered_capacity },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new_readonly(ledger, false),
+            participant_meta(ledger, wallet),
+        ],
+    )
+}
+
+pub fn set_critical_load_ix(ledger: Pubkey, admin: Pubkey, wallet: Pubkey, critical_load: bool) -> Instruction {
+    build(
+        EnergyMarketInstruction::SetCriticalLoad { critical_load },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new_readonly(ledger, false),
+            participant_meta(ledger, wallet),
+        ],
+    )
+}
+
+/// `authority` is the ledger admin or its grid operator.
+pub fn declare_demand_response_ix(
+    ledger: Pubkey,
+    authority: Pubkey,
+    start: i64,
+    end: i64,
+    target_reduction_kwh: u64,
+    incentive_price: u64,
+) -> Instruction {
+    build(
+        EnergyMarketInstruction::DeclareDemandResponse { start, end, target_reduction_kwh, incentive_price },
+        vec![AccountMeta::new_readonly(authority, true), AccountMeta::new(ledger, false)],
+    )
+}
+
+pub fn volunteer_reduction_ix(ledger: Pubkey, wallet: Pubkey, reduction_kwh: u64) -> Instruction {
+    build(
+        EnergyMarketInstruction::VolunteerReduction { reduction_kwh },
+        vec![
+            AccountMeta::new_readonly(wallet, true),
+            AccountMeta::new(ledger, false),
+            participant_meta(ledger, wallet),
+        ],
+    )
+}
+
+/// Pays the `volunteers` listed. On a ledger with an oracle, `settler` is the oracle and
+/// `actual_reductions` holds the metered reduction of each volunteer, in the same order;
+/// otherwise pass `None`.
+pub fn settle_demand_response_ix(
+    ledger: Pubkey,
+    settler: Pubkey,
+    volunteers: &[Pubkey],
+    actual_reductions: Option<Vec<u64>>,
+) -> Instruction {
+    let mut accounts = vec![AccountMeta::new_readonly(settler, true), AccountMeta::new(ledger, false)];
+    accounts.extend(volunteers.iter().map(|wallet| participant_meta(ledger, *wallet)));
+    build(EnergyMarketInstruction::SettleDemandResponse { actual_reductions }, accounts)
+}
+
+pub fn set_oracle_ix(ledger: Pubkey, admin: Pubkey, oracle: Pubkey) -> Instruction {
+    build(
+        EnergyMarketInstruction::SetOracle { oracle },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+        ],
+    )
+}
+
+pub fn attest_production_ix(ledger: Pubkey, oracle: Pubkey, order_id: u64, order_storage: OrderStorage) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(oracle, true),
+        AccountMeta::new(ledger, false),
+    ];
+    if order_storage == OrderStorage::Accounts {
+        accounts.push(order_meta(ledger, order_id));
+    }
+    build(EnergyMarketInstruction::AttestProduction { order_id }, accounts)
+}
+
+/// `confirmer` is the ledger's oracle or the trade's consumer, per its settlement config.
+pub fn confirm_delivery_ix(ledger: Pubkey, confirmer: Pubkey, consumer: Pubkey, producer: Pubkey, trade_id: u64) -> Instruction {
+    build(
+        EnergyMarketInstruction::ConfirmDelivery { trade_id },
+        vec![
+            AccountMeta::new_readonly(confirmer, true),
+            AccountMeta::new(ledger, false),
+            participant_meta(ledger, consumer),
+            participant_meta(ledger, producer),
+        ],
+    )
+}
+
+pub fn settle_defaulted_trade_ix(ledger: Pubkey, consumer: Pubkey, producer: Pubkey, trade_id: u64) -> Instruction {
+    build(
+        EnergyMarketInstruction::SettleDefaultedTrade { trade_id },
+        vec![
+            AccountMeta::new(ledger, false),
+            participant_meta(ledger, consumer),
+            participant_meta(ledger, producer),
+        ],
+    )
+}
+
+pub fn post_collateral_ix(ledger: Pubkey, wallet: Pubkey, amount: u64) -> Instruction {
+    build(
+        EnergyMarketInstruction::PostCollateral { amount },
+        vec![
+            AccountMeta::new_readonly(wallet, true),
+            AccountMeta::new_readonly(ledger, false),
+            participant_meta(ledger, wallet),
+        ],
+    )
+}
+
+pub fn withdraw_collateral_ix(ledger: Pubkey, wallet: Pubkey, amount: u64) -> Instruction {
+    build(
+        EnergyMarketInstruction::WithdrawCollateral { amount },
+        vec![
+            AccountMeta::new_readonly(wallet, true),
+            AccountMeta::new_readonly(ledger, false),
+            participant_meta(ledger, wallet),
+        ],
+    )
+}
+
+/// With `consumer` set the slashed collateral compensates the trade's consumer, otherwise it goes
+/// to the protocol fee pool.
+pub fn slash_collateral_ix(
+    ledger: Pubkey,
+    admin: Pubkey,
+    producer: Pubkey,
+    consumer: Option<Pubkey>,
+    trade_id: u64,
+    amount: u64,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(admin, true),
+        AccountMeta::new(ledger, false),
+        participant_meta(ledger, producer),
+    ];
+    if let Some(consumer) = consumer {
+        accounts.push(participant_meta(ledger, consumer));
+    }
+    build(EnergyMarketInstruction::SlashCollateral { trade_id, amount }, accounts)
+}
+
+pub fn transfer_balance_ix(ledger: Pubkey, wallet: Pubkey, recipient: Pubkey, amount: u64) -> Instruction {
+    build(
+        EnergyMarketInstruction::TransferBalance { amount },
+        vec![
+            AccountMeta::new_readonly(wallet, true),
+            AccountMeta::new(ledger, false),
+            participant_meta(ledger, wallet),
+            participant_meta(ledger, recipient),
+        ],
+    )
+}
+
+pub fn transfer_rec_ix(ledger: Pubkey, wallet: Pubkey, recipient: Pubkey, amount: u64) -> Instruction {
+    build(
+        EnergyMarketInstruction::TransferRec { amount },
+        vec![
+            AccountMeta::new_readonly(wallet, true),
+            AccountMeta::new_readonly(ledger, false),
+            participant_meta(ledger, wallet),
+            participant_meta(ledger, recipient),
+        ],
+    )
+}
+
+pub fn retire_rec_ix(ledger: Pubkey, wallet: Pubkey, amount: u64) -> Instruction {
+    build(
+        EnergyMarketInstruction::RetireRec { amount },
+        vec![
+            AccountMeta::new_readonly(wallet, true),
+            AccountMeta::new_readonly(ledger, false),
+            participant_meta(ledger, wallet),
+        ],
+    )
+}
+
+pub fn set_storage_parameters_ix(
+    ledger: Pubkey,
+    admin: Pubkey,
+    wallet: Pubkey,
+    storage_capacity: u64,
+    storage_efficiency_bps: u16,
+) -> Instruction {
+    build(
+        EnergyMarketInstruction::SetStorageParameters { storage_capacity, storage_efficiency_bps },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new_readonly(ledger, false),
+            participant_meta(ledger, wallet),
+        ],
+    )
+}
+
+/// Simulate this and decode the return data as [`MarketStats`](crate::MarketStats).
+pub fn get_market_stats_ix(ledger: Pubkey) -> Instruction {
+    build(EnergyMarketInstruction::GetMarketStats, vec![AccountMeta::new_readonly(ledger, false)])
+}
+
+/// Simulate this and decode the return data as a borsh `i64`; fails with `NotEnoughData` when
+/// fewer than two samples fall in the window.
+pub fn get_twap_ix(ledger: Pubkey, window_secs: i64) -> Instruction {
+    build(EnergyMarketInstruction::GetTwap { window_secs }, vec![AccountMeta::new_readonly(ledger, false)])
+}
+
+/// Simulate this and decode the return data as [`Participant`](crate::Participant); fails with
+/// `NotFound` when `wallet` is not registered, as do the other participant queries.
+pub fn get_participant_ix(ledger: Pubkey, wallet: Pubkey) -> Instruction {
+    build(EnergyMarketInstruction::GetParticipant { pubkey: wallet }, query_accounts(ledger, wallet))
+}
+
+/// Simulate this and decode the return data as [`BalanceInfo`](crate::BalanceInfo).
+pub fn get_balance_ix(ledger: Pubkey, wallet: Pubkey) -> Instruction {
+    build(EnergyMarketInstruction::GetBalance { pubkey: wallet }, query_accounts(ledger, wallet))
+}
+
+/// Simulate this and decode the return data as [`OpenOrders`](crate::OpenOrders). On account
+/// storage pass the order PDAs to report as `order_ids`; ledger storage ignores them.
+pub fn get_open_orders_ix(ledger: Pubkey, wallet: Pubkey, order_ids: &[u64]) -> Instruction {
+    let mut accounts = query_accounts(ledger, wallet);
+    accounts.extend(order_ids.iter().map(|order_id| AccountMeta::new_readonly(find_order_address(&crate::id(), &ledger, *order_id).0, false)));
+    build(EnergyMarketInstruction::GetOpenOrders { pubkey: wallet }, accounts)
+}
+
+fn query_accounts(ledger: Pubkey, wallet: Pubkey) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new_readonly(ledger, false),
+        AccountMeta::new_readonly(find_participant_address(&crate::id(), &ledger, &wallet).0, false),
+    ]
+}
+
+/// Simulate this with the same `remaining` accounts as [`match_transactions_ix`] and decode the
+/// return data as [`MatchSimulation`](crate::MatchSimulation); `caller` is whose fills are listed.
+pub fn simulate_match_ix(ledger: Pubkey, caller: Pubkey, max_matches: u16, remaining: Vec<AccountMeta>) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(ledger, false),
+        AccountMeta::new_readonly(caller, false),
+    ];
+    accounts.extend(remaining);
+    build(EnergyMarketInstruction::SimulateMatch { max_matches }, accounts)
+}
+
+/// On `OrderStorage::Accounts` the order PDA is included; for a production append
+/// [`open_order_metas`] for every other open order of the producer.
+pub fn replace_order_ix(
+    ledger: Pubkey,
+    owner: Pubkey,
+    order_id: u64,
+    new_price: i64,
+    new_amount: u64,
+    order_storage: OrderStorage,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(owner, true),
+        AccountMeta::new(ledger, false),
+        participant_meta(ledger, owner),
+    ];
+    if order_storage == OrderStorage::Accounts {
+        accounts.push(order_meta(ledger, order_id));
+    }
+    build(EnergyMarketInstruction::ReplaceOrder { order_id, new_price, new_amount }, accounts)
+}
+
+fn bilateral_offer_meta(ledger: Pubkey, offer_id: u64) -> AccountMeta {
+    AccountMeta::new(find_bilateral_offer_address(&crate::id(), &ledger, offer_id).0, false)
+}
+
+/// `offer_id` must be the ledger's current `next_order_id`, which the offer is assigned.
+#[allow(clippy::too_many_arguments)]
+pub fn create_bilateral_offer_ix(
+    ledger: Pubkey,
+    maker: Pubkey,
+    counterparty: Pubkey,
+    side: OrderSide,
+    energy_amount: u64,
+    price: i64,
+    expires_at: i64,
+    offer_id: u64,
+) -> Instruction {
+    build(
+        EnergyMarketInstruction::CreateBilateralOffer { counterparty, side, energy_amount, price, expires_at },
+        vec![
+            AccountMeta::new(maker, true),
+            AccountMeta::new(ledger, false),
+            participant_meta(ledger, maker),
+            bilateral_offer_meta(ledger, offer_id),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+pub fn accept_bilateral_offer_ix(ledger: Pubkey, counterparty: Pubkey, maker: Pubkey, offer_id: u64) -> Instruction {
+    build(
+        EnergyMarketInstruction::AcceptBilateralOffer { offer_id },
+        vec![
+            AccountMeta::new(counterparty, true),
+            AccountMeta::new(ledger, false),
+            participant_meta(ledger, counterparty),
+            AccountMeta::new(maker, false),
+            participant_meta(ledger, maker),
+            bilateral_offer_meta(ledger, offer_id),
+        ],
+    )
+}
+
+/// The first order is posted by the first [`activate_standing_orders_ix`] run at or after
+/// `first_activation`, then one every `interval_secs`, `activations` times in all.
+#[allow(clippy::too_many_arguments)]
+pub fn create_standing_order_ix(
+    ledger: Pubkey,
+    owner: Pubkey,
+    side: OrderSide,
+    energy_amount: u64,
+    price: i64,
+    interval_secs: i64,
+    first_activation: i64,
+    activations: u32,
+) -> Instruction {
+    build(
+        EnergyMarketInstruction::CreateStandingOrder { side, energy_amount, price, interval_secs, first_activation, activations },
+        vec![
+            AccountMeta::new_readonly(owner, true),
+            AccountMeta::new(ledger, false),
+            AccountMeta::new_readonly(find_participant_address(&crate::id(), &ledger, &owner).0, false),
+        ],
+    )
+}
+
+pub fn cancel_standing_order_ix(ledger: Pubkey, owner: Pubkey, standing_order_id: u64) -> Instruction {
+    build(
+        EnergyMarketInstruction::CancelStandingOrder { standing_order_id },
+        vec![AccountMeta::new_readonly(owner, true), AccountMeta::new(ledger, false)],
+    )
+}
+
+/// Only standing orders owned by one of `owners` are activated.
+pub fn activate_standing_orders_ix(ledger: Pubkey, cranker: Pubkey, owners: &[Pubkey]) -> Instruction {
+    let mut accounts = vec![AccountMeta::new_readonly(cranker, true), AccountMeta::new(ledger, false)];
+    accounts.extend(owners.iter().map(|owner| participant_meta(ledger, *owner)));
+    build(EnergyMarketInstruction::ActivateStandingOrders, accounts)
+}
+
+fn forward_contract_meta(ledger: Pubkey, contract_id: u64) -> AccountMeta {
+    AccountMeta::new(find_forward_contract_address(&crate::id(), &ledger, contract_id).0, false)
+}
+
+/// `contract_id` must be the ledger's current `next_order_id`, which the contract is assigned.
+/// A `counterparty` of `None` lets any participant take the contract.
+#[allow(clippy::too_many_arguments)]
+pub fn create_forward_ix(
+    ledger: Pubkey,
+    maker: Pubkey,
+    counterparty: Option<Pubkey>,
+    side: OrderSide,
+    energy_amount: u64,
+    price: i64,
+    delivery_slot: u32,
+    margin_bps: u16,
+    contract_id: u64,
+) -> Instruction {
+    build(
+        EnergyMarketInstruction::CreateForward { counterparty, side, energy_amount, price, delivery_slot, margin_bps },
+        vec![
+            AccountMeta::new(maker, true),
+            AccountMeta::new(ledger, false),
+            participant_meta(ledger, maker),
+            forward_contract_meta(ledger, contract_id),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+pub fn accept_forward_ix(ledger: Pubkey, taker: Pubkey, contract_id: u64) -> Instruction {
+    build(
+        EnergyMarketInstruction::AcceptForward { contract_id },
+        vec![
+            AccountMeta::new_readonly(taker, true),
+            AccountMeta::new_readonly(ledger, false),
+            participant_meta(ledger, taker),
+            forward_contract_meta(ledger, contract_id),
+        ],
+    )
+}
+
+/// `taker` is the wallet that took the contract, or `None` to withdraw one nobody took.
+pub fn settle_forward_ix(ledger: Pubkey, signer: Pubkey, maker: Pubkey, taker: Option<Pubkey>, contract_id: u64) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(signer, true),
+        AccountMeta::new_readonly(ledger, false),
+        AccountMeta::new(maker, false),
+        participant_meta(ledger, maker),
+        forward_contract_meta(ledger, contract_id),
+    ];
+    accounts.extend(taker.map(|taker| participant_meta(ledger, taker)));
+    build(EnergyMarketInstruction::SettleForward { contract_id }, accounts)
+}
+
+/// `signer` is the maker or the counterparty, or anyone once the offer has expired.
+pub fn reject_bilateral_offer_ix(ledger: Pubkey, signer: Pubkey, maker: Pubkey, offer_id: u64) -> Instruction {
+    build(
+        EnergyMarketInstruction::RejectBilateralOffer { offer_id },
+        vec![
+            AccountMeta::new(signer, true),
+            AccountMeta::new_readonly(ledger, false),
+            AccountMeta::new(maker, false),
+            participant_meta(ledger, maker),
+            bilateral_offer_meta(ledger, offer_id),
+        ],
+    )
+}
+
+/// Both wallets sign, the member's signature consenting to aggregated demands drawing on its balance.
+pub fn add_member_ix(ledger: Pubkey, aggregator: Pubkey, member: Pubkey, weight: u32) -> Instruction {
+    build(
+        EnergyMarketInstruction::AddMember { weight },
+        vec![
+            AccountMeta::new_readonly(aggregator, true),
+            AccountMeta::new_readonly(ledger, false),
+            participant_meta(ledger, aggregator),
+            AccountMeta::new_readonly(member, true),
+            participant_meta(ledger, member),
+        ],
+    )
+}
+
+/// `signer` is the aggregator or the member.
+pub fn remove_member_ix(ledger: Pubkey, signer: Pubkey, aggregator: Pubkey, member: Pubkey) -> Instruction {
+    build(
+        EnergyMarketInstruction::RemoveMember,
+        vec![
+            AccountMeta::new_readonly(signer, true),
+            AccountMeta::new_readonly(ledger, false),
+            participant_meta(ledger, aggregator),
+            participant_meta(ledger, member),
+        ],
+    )
+}
+
+/// `members` lists the aggregator's members in the order they joined. `order_id` is required on
+/// account order storage, as for [`post_demand_ix`].
+#[allow(clippy::too_many_arguments)]
+pub fn post_aggregated_demand_ix(
+    ledger: Pubkey,
+    aggregator: Pubkey,
+    members: &[Pubkey],
+    energy_amount: u64,
+    price_limit: i64,
+    expires_at: i64,
+    delivery_slot: u32,
+    order_id: Option<u64>,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(aggregator, true),
+        AccountMeta::new(ledger, false),
+        participant_meta(ledger, aggregator),
+    ];
+    accounts.extend(members.iter().map(|member| participant_meta(ledger, *member)));
+    if let Some(order_id) = order_id {
+        accounts.push(order_meta(ledger, order_id));
+        accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+    }
+    build(EnergyMarketInstruction::PostAggregatedDemand { energy_amount, price_limit, expires_at, delivery_slot }, accounts)
+}
+
+/// `members` lists the aggregator's members in the order they joined.
+pub fn settle_aggregated_demand_ix(ledger: Pubkey, signer: Pubkey, aggregator: Pubkey, members: &[Pubkey]) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(signer, true),
+        AccountMeta::new_readonly(ledger, false),
+        participant_meta(ledger, aggregator),
+    ];
+    accounts.extend(members.iter().map(|member| participant_meta(ledger, *member)));
+    build(EnergyMarketInstruction::SettleAggregatedDemand, accounts)
+}
+
+pub fn set_net_metering_ix(ledger: Pubkey, wallet: Pubkey, enabled: bool) -> Instruction {
+    build(
+        EnergyMarketInstruction::SetNetMetering { enabled },
+        vec![
+            AccountMeta::new_readonly(wallet, true),
+            AccountMeta::new_readonly(ledger, false),
+            participant_meta(ledger, wallet),
+        ],
+    )
+}
+
+/// Signed by the ledger's oracle once `delivery_slot` has ended.
+pub fn report_actuals_ix(
+    ledger: Pubkey,
+    oracle: Pubkey,
+    wallet: Pubkey,
+    delivery_slot: u32,
+    metered_injection: u64,
+    metered_consumption: u64,
+) -> Instruction {
+    build(
+        EnergyMarketInstruction::ReportActuals { delivery_slot, metered_injection, metered_consumption },
+        vec![
+            AccountMeta::new_readonly(oracle, true),
+            AccountMeta::new_readonly(ledger, false),
+            participant_meta(ledger, wallet),
+        ],
+    )
+}
+
+/// Any signer may settle `wallet`'s position for `delivery_slot`.
+pub fn settle_imbalances_ix(ledger: Pubkey, settler: Pubkey, wallet: Pubkey, delivery_slot: u32) -> Instruction {
+    build(
+        EnergyMarketInstruction::SettleImbalances { delivery_slot },
+        vec![
+            AccountMeta::new_readonly(settler, true),
+            AccountMeta::new(ledger, false),
+            participant_meta(ledger, wallet),
+        ],
+    )
+}
+
+/// Funded from the admin's own participant balance.
+pub fn top_up_insurance_ix(ledger: Pubkey, admin: Pubkey, amount: u64) -> Instruction {
+    build(
+        EnergyMarketInstruction::TopUpInsurance { amount },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+            participant_meta(ledger, admin),
+        ],
+    )
+}
+
+/// An `amount` of 0 cancels the pending drain.
+pub fn propose_insurance_drain_ix(ledger: Pubkey, admin: Pubkey, amount: u64) -> Instruction {
+    build(
+        EnergyMarketInstruction::ProposeInsuranceDrain { amount },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+        ],
+    )
+}
+
+pub fn drain_insurance_ix(ledger: Pubkey, admin: Pubkey) -> Instruction {
+    build(
+        EnergyMarketInstruction::DrainInsurance,
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+            participant_meta(ledger, admin),
+        ],
+    )
+}
+
+pub fn claim_insurance_ix(ledger: Pubkey, wallet: Pubkey) -> Instruction {
+    build(
+        EnergyMarketInstruction::ClaimInsurance,
+        vec![
+            AccountMeta::new_readonly(wallet, true),
+            AccountMeta::new(ledger, false),
+            participant_meta(ledger, wallet),
+        ],
+    )
+}
+
+/// Locks `amount` of the seller's wallet balance for at least `lock_duration` seconds.
+pub fn stake_for_priority_ix(ledger: Pubkey, wallet: Pubkey, amount: u64, lock_duration: i64) -> Instruction {
+    build(
+        EnergyMarketInstruction::StakeForPriority { amount, lock_duration },
+        vec![
+            AccountMeta::new_readonly(wallet, true),
+            AccountMeta::new_readonly(ledger, false),
+            participant_meta(ledger, wallet),
+        ],
+    )
+}
+
+/// Fails with `StakeLocked` until the lock expires.
+pub fn unstake_after_cooldown_ix(ledger: Pubkey, wallet: Pubkey, amount: u64) -> Instruction {
+    build(
+        EnergyMarketInstruction::UnstakeAfterCooldown { amount },
+        vec![
+            AccountMeta::new_readonly(wallet, true),
+            AccountMeta::new_readonly(ledger, false),
+            participant_meta(ledger, wallet),
+        ],
+    )
+}
+
+/// Funded from the admin's own participant balance.
+pub fn fund_rewards_ix(ledger: Pubkey, admin: Pubkey, amount: u64) -> Instruction {
+    build(
+        EnergyMarketInstruction::FundRewards { amount },
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(ledger, false),
+            participant_meta(ledger, admin),
+        ],
+    )
+}
+
+pub fn claim_rewards_ix(ledger: Pubkey, wallet: Pubkey) -> Instruction {
+    build(
+        EnergyMarketInstruction::ClaimRewards,
+        vec![
+            AccountMeta::new_readonly(wallet, true),
+            AccountMeta::new(ledger, false),
+            participant_meta(ledger, wallet),
+        ],
+    )
+}
+
+pub fn create_proposal_ix(ledger: Pubkey, proposer: Pubkey, update: ConfigUpdate, voting_deadline: i64) -> Instruction {
+    build(
+        EnergyMarketInstruction::CreateProposal { update, voting_deadline },
+        vec![
+            AccountMeta::new_readonly(proposer, true),
+            AccountMeta::new(ledger, false),
+            participant_meta(ledger, proposer),
+        ],
+    )
+}
+
+/// The vote weighs `voter`'s wallet balance at the time it is cast; the weight is a snapshot and
+/// the balance stays free to move afterwards.
+pub fn cast_vote_ix(ledger: Pubkey, voter: Pubkey, proposal_id: u64, support: bool) -> Instruction {
+    build(
+        EnergyMarketInstruction::CastVote { proposal_id, support },
+        vec![
+            AccountMeta::new_readonly(voter, true),
+            AccountMeta::new(ledger, false),
+            participant_meta(ledger, voter),
+        ],
+    )
+}
+
+pub fn execute_proposal_ix(ledger: Pubkey) -> Instruction {
+    build(EnergyMarketInstruction::ExecuteProposal, vec![AccountMeta::new(ledger, false)])
+}
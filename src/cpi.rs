@@ -0,0 +1,160 @@
+//! Wrappers for invoking this program from another program. Each wrapper builds its instruction
+//! with the matching [`client`](crate::client) builder, so the account order is the one documented
+//! there, and invokes it with [`invoke_signed`].
+//!
+//! `accounts` must hold an [`AccountInfo`] for every account the builder lists, in any order;
+//! `remaining_accounts` are appended to the instruction in the order given, for the trailing
+//! accounts some builders ask the caller to add. The calling instruction must itself have been
+//! passed this program's account.
+//!
+//! A program-derived address can act as a participant wallet: the caller passes its seeds as
+//! `signer_seeds` and the PDA reaches this program as a signer. Registration and native deposits
+//! move lamports out of the wallet with the system program, so such a PDA must be system-owned
+//! and funded; withdrawals and token deposits have no such requirement.
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    program::invoke_signed,
+    pubkey::Pubkey,
+};
+
+use crate::{client, EnergySource, OrderStorage, ParticipantMetadata, ParticipantType, TimeInForce};
+
+pub struct CpiContext<'a, 'b> {
+    pub accounts: &'b [AccountInfo<'a>],
+    pub remaining_accounts: &'b [AccountInfo<'a>],
+    pub signer_seeds: &'b [&'b [&'b [u8]]],
+}
+
+impl<'a, 'b> CpiContext<'a, 'b> {
+    pub fn new(accounts: &'b [AccountInfo<'a>]) -> Self {
+        CpiContext { accounts, remaining_accounts: &[], signer_seeds: &[] }
+    }
+
+    pub fn new_with_signer(accounts: &'b [AccountInfo<'a>], signer_seeds: &'b [&'b [&'b [u8]]]) -> Self {
+        CpiContext { accounts, remaining_accounts: &[], signer_seeds }
+    }
+
+    pub fn with_remaining_accounts(mut self, remaining_accounts: &'b [AccountInfo<'a>]) -> Self {
+        self.remaining_accounts = remaining_accounts;
+        self
+    }
+}
+
+fn invoke(ctx: &CpiContext, mut ix: Instruction) -> ProgramResult {
+    ix.accounts.extend(ctx.remaining_accounts.iter().map(|account| AccountMeta {
+        pubkey: *account.key,
+        is_signer: account.is_signer,
+        is_writable: account.is_writable,
+    }));
+    let infos: Vec<AccountInfo> = ctx.accounts.iter().chain(ctx.remaining_accounts).cloned().collect();
+    invoke_signed(&ix, &infos, ctx.signer_seeds)
+}
+
+pub fn register_participant(
+    ctx: &CpiContext,
+    ledger: Pubkey,
+    wallet: Pubkey,
+    participant_type: ParticipantType,
+    zone: u8,
+    registered_capacity: u64,
+    metadata: ParticipantMetadata,
+) -> ProgramResult {
+    invoke(ctx, client::register_participant_ix(ledger, wallet, participant_type, zone, registered_capacity, metadata))
+}
+
+pub fn deposit(
+    ctx: &CpiContext,
+    ledger: Pubkey,
+    participant: Pubkey,
+    amount: u64,
+    token_accounts: Option<(Pubkey, Pubkey)>,
+) -> ProgramResult {
+    invoke(ctx, client::deposit_ix(ledger, participant, amount, token_accounts))
+}
+
+pub fn withdraw(
+    ctx: &CpiContext,
+    ledger: Pubkey,
+    participant: Pubkey,
+    destination: Pubkey,
+    amount: u64,
+    vault_token_account: Option<Pubkey>,
+) -> ProgramResult {
+    invoke(ctx, client::withdraw_ix(ledger, participant, destination, amount, vault_token_account))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn report_production(
+    ctx: &CpiContext,
+    ledger: Pubkey,
+    producer: Pubkey,
+    energy_amount: u64,
+    price: i64,
+    expires_at: i64,
+    delivery_slot: u32,
+    energy_source: EnergySource,
+    time_in_force: TimeInForce,
+    min_fill: u64,
+    all_or_nothing: bool,
+    client_order_nonce: u64,
+    distinct_lot: bool,
+    order_id: Option<u64>,
+) -> ProgramResult {
+    invoke(ctx, client::report_production_ix(
+        ledger,
+        producer,
+        energy_amount,
+        price,
+        expires_at,
+        delivery_slot,
+        energy_source,
+        time_in_force,
+        min_fill,
+        all_or_nothing,
+        client_order_nonce,
+        distinct_lot,
+        order_id,
+    ))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn post_demand(
+    ctx: &CpiContext,
+    ledger: Pubkey,
+    consumer: Pubkey,
+    energy_amount: u64,
+    price_limit: i64,
+    expires_at: i64,
+    delivery_slot: u32,
+    time_in_force: TimeInForce,
+    min_fill: u64,
+    all_or_nothing: bool,
+    client_order_nonce: u64,
+    priority: u8,
+    order_id: Option<u64>,
+) -> ProgramResult {
+    invoke(ctx, client::post_demand_ix(
+        ledger,
+        consumer,
+        energy_amount,
+        price_limit,
+        expires_at,
+        delivery_slot,
+        time_in_force,
+        min_fill,
+        all_or_nothing,
+        client_order_nonce,
+        priority,
+        order_id,
+    ))
+}
+
+pub fn cancel_demand(ctx: &CpiContext, ledger: Pubkey, consumer: Pubkey, order_id: u64, order_storage: OrderStorage) -> ProgramResult {
+    invoke(ctx, client::cancel_demand_ix(ledger, consumer, order_id, order_storage))
+}
+
+pub fn cancel_production(ctx: &CpiContext, ledger: Pubkey, producer: Pubkey, order_id: u64, order_storage: OrderStorage) -> ProgramResult {
+    invoke(ctx, client::cancel_production_ix(ledger, producer, order_id, order_storage))
+}
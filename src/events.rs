@@ -0,0 +1,592 @@
+// Structured events emitted with sol_log_data. Each event is logged as a single data field holding
+// its 8-byte discriminator followed by the borsh-serialized event, so indexers can decode program
+// logs without parsing msg! text. Discriminators are the first 8 bytes of sha256("event:<Name>").
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{entrypoint::ProgramResult, log::sol_log_data, pubkey::Pubkey};
+
+use crate::{ConfigUpdate, OrderSide, ParticipantType, TradeStatus, Transaction};
+
+pub trait Event: BorshSerialize {
+    const DISCRIMINATOR: [u8; 8];
+}
+
+pub fn emit<E: Event>(event: &E) -> ProgramResult {
+    let mut data = E::DISCRIMINATOR.to_vec();
+    event.serialize(&mut data)?;
+    sol_log_data(&[&data]);
+    Ok(())
+}
+
+// Splits a logged data field back into its discriminator and event payload
+pub fn decode<E: Event + BorshDeserialize>(data: &[u8]) -> Option<E> {
+    let payload = data.strip_prefix(&E::DISCRIMINATOR)?;
+    E::try_from_slice(payload).ok()
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TradeExecuted {
+    pub trade_id: u64,
+    pub demand_order_id: u64,
+    pub production_order_id: u64,
+    pub buyer: Pubkey,
+    pub seller: Pubkey,
+    pub amount: u64,
+    pub price: i64,
+    pub timestamp: i64,
+}
+
+impl Event for TradeExecuted {
+    const DISCRIMINATOR: [u8; 8] = [41, 110, 64, 129, 60, 79, 179, 80];
+}
+
+impl From<&Transaction> for TradeExecuted {
+    fn from(trade: &Transaction) -> Self {
+        TradeExecuted {
+            trade_id: trade.trade_id,
+            demand_order_id: trade.demand_order_id,
+            production_order_id: trade.production_order_id,
+            buyer: trade.from,
+            seller: trade.to,
+            amount: trade.amount,
+            price: trade.price,
+            timestamp: trade.timestamp,
+        }
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct ParticipantRegistered {
+    pub participant: Pubkey,
+    pub participant_type: ParticipantType,
+    pub zone: u8,
+}
+
+impl Event for ParticipantRegistered {
+    const DISCRIMINATOR: [u8; 8] = [47, 115, 159, 109, 135, 121, 70, 193];
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct ParticipantTypeChanged {
+    pub participant: Pubkey,
+    pub old_type: ParticipantType,
+    pub new_type: ParticipantType,
+}
+
+impl Event for ParticipantTypeChanged {
+    const DISCRIMINATOR: [u8; 8] = [7, 200, 169, 124, 17, 242, 38, 98];
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DepositMade {
+    pub participant: Pubkey,
+    pub amount: u64,
+    pub wallet_balance: u64,
+}
+
+impl Event for DepositMade {
+    const DISCRIMINATOR: [u8; 8] = [210, 201, 130, 183, 244, 203, 155, 199];
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WithdrawalMade {
+    pub participant: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub wallet_balance: u64,
+}
+
+impl Event for WithdrawalMade {
+    const DISCRIMINATOR: [u8; 8] = [253, 86, 154, 221, 191, 29, 247, 193];
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WithdrawalRequested {
+    pub participant: Pubkey,
+    pub amount: u64,
+    pub claimable_after: i64,
+}
+
+impl Event for WithdrawalRequested {
+    const DISCRIMINATOR: [u8; 8] = [75, 207, 21, 12, 160, 102, 150, 55];
+}
+
+// The vetoed amount went back to the participant's free balance
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WithdrawalVetoed {
+    pub participant: Pubkey,
+    pub amount: u64,
+}
+
+impl Event for WithdrawalVetoed {
+    const DISCRIMINATOR: [u8; 8] = [148, 155, 194, 178, 121, 233, 102, 106];
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OrderCancelled {
+    pub order_id: u64,
+    pub owner: Pubkey,
+    pub side: OrderSide,
+    pub remaining_amount: u64,
+}
+
+impl Event for OrderCancelled {
+    const DISCRIMINATOR: [u8; 8] = [108, 56, 128, 68, 168, 113, 168, 239];
+}
+
+// Emitted by FreezeParticipant (frozen = true) and UnfreezeParticipant (frozen = false)
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ParticipantFrozen {
+    pub participant: Pubkey,
+    pub frozen: bool,
+}
+
+impl Event for ParticipantFrozen {
+    const DISCRIMINATOR: [u8; 8] = [135, 30, 60, 2, 164, 232, 94, 28];
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CriticalLoadSet {
+    pub participant: Pubkey,
+    pub critical_load: bool,
+}
+
+impl Event for CriticalLoadSet {
+    const DISCRIMINATOR: [u8; 8] = [21, 149, 239, 23, 23, 120, 23, 119];
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DemandResponseDeclared {
+    pub event_id: u64,
+    pub start: i64,
+    pub end: i64,
+    pub target_reduction_kwh: u64,
+    pub incentive_price: u64,
+}
+
+impl Event for DemandResponseDeclared {
+    const DISCRIMINATOR: [u8; 8] = [63, 130, 216, 23, 252, 26, 109, 208];
+}
+
+// `committed_reduction` is the volunteer's total commitment to the event so far
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReductionVolunteered {
+    pub event_id: u64,
+    pub participant: Pubkey,
+    pub reduction_kwh: u64,
+    pub committed_reduction: u64,
+}
+
+impl Event for ReductionVolunteered {
+    const DISCRIMINATOR: [u8; 8] = [132, 161, 219, 250, 68, 81, 111, 50];
+}
+
+// `reduction_kwh` is the reduction paid for, at most what the volunteer committed to
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IncentivePaid {
+    pub event_id: u64,
+    pub participant: Pubkey,
+    pub reduction_kwh: u64,
+    pub amount: u64,
+}
+
+impl Event for IncentivePaid {
+    const DISCRIMINATOR: [u8; 8] = [78, 82, 82, 54, 167, 253, 170, 81];
+}
+
+// A standing order posted order_id; it stays on the ledger while remaining_activations is above 0
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StandingOrderActivated {
+    pub standing_order_id: u64,
+    pub owner: Pubkey,
+    pub order_id: u64,
+    pub next_activation: i64,
+    pub remaining_activations: u32,
+}
+
+impl Event for StandingOrderActivated {
+    const DISCRIMINATOR: [u8; 8] = [4, 73, 38, 205, 109, 145, 198, 15];
+}
+
+// An activation that could not post its order, such as one the owner could not fund; `error` is
+// the EnergyMarketError code posting it directly would have failed with. The activation is used up.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StandingOrderSkipped {
+    pub standing_order_id: u64,
+    pub owner: Pubkey,
+    pub error: u32,
+    pub next_activation: i64,
+    pub remaining_activations: u32,
+}
+
+impl Event for StandingOrderSkipped {
+    const DISCRIMINATOR: [u8; 8] = [76, 8, 231, 77, 111, 142, 93, 216];
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MemberAdded {
+    pub aggregator: Pubkey,
+    pub member: Pubkey,
+    pub weight: u32,
+}
+
+impl Event for MemberAdded {
+    const DISCRIMINATOR: [u8; 8] = [198, 220, 228, 196, 92, 235, 240, 79];
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MemberRemoved {
+    pub aggregator: Pubkey,
+    pub member: Pubkey,
+}
+
+impl Event for MemberRemoved {
+    const DISCRIMINATOR: [u8; 8] = [250, 66, 3, 113, 161, 10, 59, 39];
+}
+
+// A member's share of a settled aggregated demand: the energy bought for it, what that cost and
+// what came back of its contribution
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MemberSettled {
+    pub aggregator: Pubkey,
+    pub member: Pubkey,
+    pub order_id: u64,
+    pub energy_amount: u64,
+    pub paid: u64,
+    pub refund: u64,
+}
+
+impl Event for MemberSettled {
+    const DISCRIMINATOR: [u8; 8] = [72, 233, 24, 245, 134, 123, 209, 78];
+}
+
+// imbalance_kwh is metered minus matched net injection; reported is false when the position
+// settled without actuals after their timeout
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ImbalanceSettled {
+    pub participant: Pubkey,
+    pub delivery_slot: u32,
+    pub imbalance_kwh: i64,
+    pub charged: u64,
+    pub credited: u64,
+    pub reported: bool,
+}
+
+impl Event for ImbalanceSettled {
+    const DISCRIMINATOR: [u8; 8] = [246, 239, 93, 151, 109, 2, 8, 73];
+}
+
+// payout falls short of claim when the pool could not cover every outstanding claim
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct InsuranceClaimed {
+    pub participant: Pubkey,
+    pub claim: u64,
+    pub payout: u64,
+}
+
+impl Event for InsuranceClaimed {
+    const DISCRIMINATOR: [u8; 8] = [97, 216, 53, 106, 37, 221, 65, 78];
+}
+
+// credited falls short of points when the rewards pool ran out; the rest stay on the participant
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RewardsClaimed {
+    pub participant: Pubkey,
+    pub reward_epoch: u64,
+    pub points: u64,
+    pub credited: u64,
+}
+
+impl Event for RewardsClaimed {
+    const DISCRIMINATOR: [u8; 8] = [75, 98, 88, 18, 219, 112, 88, 121];
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProposalCreated {
+    pub proposal_id: u64,
+    pub proposer: Pubkey,
+    pub update: ConfigUpdate,
+    pub voting_deadline: i64,
+}
+
+impl Event for ProposalCreated {
+    const DISCRIMINATOR: [u8; 8] = [186, 8, 160, 108, 81, 13, 51, 206];
+}
+
+// weight is the voter's wallet_balance when it voted
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct VoteCast {
+    pub proposal_id: u64,
+    pub voter: Pubkey,
+    pub support: bool,
+    pub weight: u64,
+}
+
+impl Event for VoteCast {
+    const DISCRIMINATOR: [u8; 8] = [39, 53, 195, 104, 188, 17, 225, 213];
+}
+
+// A passed proposal also emits ConfigUpdated for the change it applied
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProposalResolved {
+    pub proposal_id: u64,
+    pub update: ConfigUpdate,
+    pub votes_for: u64,
+    pub votes_against: u64,
+    pub passed: bool,
+}
+
+impl Event for ProposalResolved {
+    const DISCRIMINATOR: [u8; 8] = [252, 55, 181, 169, 236, 238, 0, 149];
+}
+
+// `side` is the maker's; `margin` is what each side posts
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ForwardCreated {
+    pub contract_id: u64,
+    pub maker: Pubkey,
+    pub counterparty: Option<Pubkey>,
+    pub side: OrderSide,
+    pub energy_amount: u64,
+    pub price: i64,
+    pub delivery_slot: u32,
+    pub margin: u64,
+}
+
+impl Event for ForwardCreated {
+    const DISCRIMINATOR: [u8; 8] = [66, 151, 0, 195, 86, 175, 235, 36];
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ForwardAccepted {
+    pub contract_id: u64,
+    pub taker: Pubkey,
+}
+
+impl Event for ForwardAccepted {
+    const DISCRIMINATOR: [u8; 8] = [150, 223, 250, 255, 201, 82, 187, 2];
+}
+
+// `amount` moved from the payer's margin to the payee; on liquidation it is the payer's whole margin
+// and settlement_price the reference price the contract was marked at
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ForwardSettled {
+    pub contract_id: u64,
+    pub settlement_price: i64,
+    pub payer: Pubkey,
+    pub payee: Pubkey,
+    pub amount: u64,
+    pub liquidated: bool,
+}
+
+impl Event for ForwardSettled {
+    const DISCRIMINATOR: [u8; 8] = [167, 195, 124, 132, 154, 166, 191, 173];
+}
+
+// The contract closed without a payment: it was never taken, or its slot recorded no price
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ForwardCancelled {
+    pub contract_id: u64,
+}
+
+impl Event for ForwardCancelled {
+    const DISCRIMINATOR: [u8; 8] = [74, 220, 220, 182, 254, 51, 155, 197];
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProducerCurtailed {
+    pub producer: Pubkey,
+    pub curtailed_amount: u64,
+    pub curtailed_until: i64,
+}
+
+impl Event for ProducerCurtailed {
+    const DISCRIMINATOR: [u8; 8] = [38, 245, 210, 214, 132, 186, 181, 197];
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProductionAttested {
+    pub order_id: u64,
+    pub producer: Pubkey,
+    pub oracle: Pubkey,
+}
+
+impl Event for ProductionAttested {
+    const DISCRIMINATOR: [u8; 8] = [236, 181, 154, 17, 147, 31, 157, 198];
+}
+
+// Emitted when a deferred trade is confirmed (status Delivered) or defaults (status Defaulted)
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TradeSettled {
+    pub trade_id: u64,
+    pub status: TradeStatus,
+    pub producer_amount: u64,
+    pub consumer_refund: u64,
+}
+
+impl Event for TradeSettled {
+    const DISCRIMINATOR: [u8; 8] = [22, 119, 166, 225, 175, 53, 93, 216];
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CollateralSlashed {
+    pub producer: Pubkey,
+    pub trade_id: u64,
+    pub amount: u64,
+    // False when the slashed collateral went to the protocol fee pool instead of the consumer
+    pub to_consumer: bool,
+}
+
+impl Event for CollateralSlashed {
+    const DISCRIMINATOR: [u8; 8] = [225, 127, 195, 29, 214, 65, 64, 229];
+}
+
+// Emitted whenever a settled trade moves its seller's reputation score
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReputationChanged {
+    pub participant: Pubkey,
+    pub reputation: u32,
+    pub trades_completed: u64,
+    pub trades_defaulted: u64,
+}
+
+impl Event for ReputationChanged {
+    const DISCRIMINATOR: [u8; 8] = [190, 190, 93, 65, 6, 39, 92, 250];
+}
+
+// `amount` is what left the sender; the recipient was credited `amount - fee`
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BalanceTransferred {
+    pub from: Pubkey,
+    pub to: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+}
+
+impl Event for BalanceTransferred {
+    const DISCRIMINATOR: [u8; 8] = [110, 254, 3, 18, 181, 108, 157, 86];
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RecTransferred {
+    pub from: Pubkey,
+    pub to: Pubkey,
+    pub amount: u64,
+}
+
+impl Event for RecTransferred {
+    const DISCRIMINATOR: [u8; 8] = [87, 52, 65, 183, 249, 110, 93, 83];
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RecRetired {
+    pub participant: Pubkey,
+    pub amount: u64,
+    pub retired_total: u64,
+}
+
+impl Event for RecRetired {
+    const DISCRIMINATOR: [u8; 8] = [91, 119, 77, 243, 119, 237, 33, 251];
+}
+
+// A crossing pair skipped because its price fell outside the band around the reference price
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CircuitBreaker {
+    pub demand_order_id: u64,
+    pub production_order_id: u64,
+    pub price: i64,
+    pub reference_price: i64,
+}
+
+impl Event for CircuitBreaker {
+    const DISCRIMINATOR: [u8; 8] = [190, 186, 184, 114, 47, 2, 240, 112];
+}
+
+// Emitted at the end of every MatchTransactions run and also set as its return data, so a keeper
+// simulating the crank can tell whether a real one is worth the fee. Volume and notional cover
+// this run's fills; remaining_open_orders counts the whole book, order accounts included.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MatchRunCompleted {
+    pub trades_executed: u32,
+    pub volume: u64,
+    pub notional: u128,
+    pub skipped_for_balance: u32,
+    pub skipped_for_band: u32,
+    pub remaining_open_orders: u32,
+}
+
+impl Event for MatchRunCompleted {
+    const DISCRIMINATOR: [u8; 8] = [149, 30, 65, 242, 146, 18, 69, 178];
+}
+
+// Emitted by UpdateConfig and by ApplyConfigChange; `previous` holds the same parameter as
+// `update` with the value it replaced
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ConfigUpdated {
+    pub previous: ConfigUpdate,
+    pub update: ConfigUpdate,
+}
+
+impl Event for ConfigUpdated {
+    const DISCRIMINATOR: [u8; 8] = [40, 241, 230, 122, 11, 19, 198, 194];
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ConfigChangeProposed {
+    pub update: ConfigUpdate,
+    pub effective_at: i64,
+}
+
+impl Event for ConfigChangeProposed {
+    const DISCRIMINATOR: [u8; 8] = [57, 212, 96, 3, 9, 29, 225, 198];
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ConfigChangeCancelled {
+    pub update: ConfigUpdate,
+    pub effective_at: i64,
+}
+
+impl Event for ConfigChangeCancelled {
+    const DISCRIMINATOR: [u8; 8] = [66, 48, 129, 141, 104, 33, 113, 2];
+}
+
+// A new offer was merged into an open one of the same producer; energy_amount is the order's new total
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OrderAmended {
+    pub order_id: u64,
+    pub owner: Pubkey,
+    pub added_amount: u64,
+    pub energy_amount: u64,
+}
+
+impl Event for OrderAmended {
+    const DISCRIMINATOR: [u8; 8] = [17, 64, 8, 167, 145, 232, 76, 30];
+}
+
+// An open order the crank removed on its own; its escrow went back to the owner's free balance
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OrderExpired {
+    pub order_id: u64,
+    pub owner: Pubkey,
+    pub side: OrderSide,
+    pub remaining_amount: u64,
+}
+
+impl Event for OrderExpired {
+    const DISCRIMINATOR: [u8; 8] = [241, 55, 48, 196, 160, 51, 40, 213];
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OrderReplaced {
+    pub order_id: u64,
+    pub owner: Pubkey,
+    pub side: OrderSide,
+    pub price: i64,
+    pub energy_amount: u64,
+    // True when the new price improved on the old one and the order lost its queue position
+    pub priority_reset: bool,
+}
+
+impl Event for OrderReplaced {
+    const DISCRIMINATOR: [u8; 8] = [210, 165, 217, 57, 113, 162, 39, 156];
+}
@@ -0,0 +1,739 @@
+//! Interface description for client code generators.
+//!
+//! Instruction arguments, account layouts and type definitions come from the `BorshSchema` of
+//! [`EnergyMarketInstruction`] and the account types, so they cannot drift from the code. The
+//! accounts each instruction takes are listed in [`INSTRUCTIONS`], in the order the processors
+//! read them; [`generate`] fails when that table and the instruction enum disagree, so a new
+//! variant has to be annotated here before the checked-in `idl.json` can be regenerated with
+//! `cargo run --example idl > idl.json`.
+//!
+//! ```
+//! let idl = energy_trading_program::idl::generate().unwrap();
+//! let checked_in = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/idl.json"));
+//! assert!(idl == checked_in, "idl.json is stale, regenerate it with `cargo run --example idl > idl.json`");
+//! ```
+use std::collections::{BTreeMap, HashMap};
+
+use borsh::{
+    schema::{Declaration, Definition, Fields},
+    BorshSchema,
+};
+
+use crate::{BilateralOffer, EnergyMarketError, EnergyMarketInstruction, ForwardContract, Ledger, OrderAccount, Participant, TradeLogHeader};
+
+pub struct IdlAccount {
+    pub name: &'static str,
+    pub writable: bool,
+    pub signer: bool,
+    // Only passed in some configurations, which `docs` names
+    pub optional: bool,
+    pub docs: &'static str,
+}
+
+pub struct IdlInstruction {
+    pub name: &'static str,
+    pub accounts: &'static [IdlAccount],
+    // The variable-length tail some instructions take after their fixed accounts
+    pub remaining_accounts: &'static str,
+}
+
+const fn account(name: &'static str, writable: bool, signer: bool) -> IdlAccount {
+    IdlAccount { name, writable, signer, optional: false, docs: "" }
+}
+
+const fn optional(name: &'static str, writable: bool, signer: bool, docs: &'static str) -> IdlAccount {
+    IdlAccount { name, writable, signer, optional: true, docs }
+}
+
+const fn instruction(name: &'static str, accounts: &'static [IdlAccount], remaining_accounts: &'static str) -> IdlInstruction {
+    IdlInstruction { name, accounts, remaining_accounts }
+}
+
+const SYSTEM_PROGRAM: IdlAccount = account("system_program", false, false);
+const TOKEN_PROGRAM: IdlAccount = optional("token_program", false, false, "token ledgers only");
+const VAULT_TOKEN_ACCOUNT: IdlAccount = optional("vault_token_account", true, false, "token ledgers only");
+
+const ADMIN_ACCOUNTS: &[IdlAccount] = &[account("admin", false, true), account("ledger", true, false)];
+const ADMIN_PARTICIPANT_ACCOUNTS: &[IdlAccount] = &[
+    account("admin", false, true),
+    account("ledger", false, false),
+    account("participant", true, false),
+];
+// Instructions moving funds between a ledger pool and the admin's own participant
+const ADMIN_BALANCE_ACCOUNTS: &[IdlAccount] = &[
+    account("admin", false, true),
+    account("ledger", true, false),
+    account("admin_participant", true, false),
+];
+const WALLET_ACCOUNTS: &[IdlAccount] = &[
+    account("wallet", false, true),
+    account("ledger", false, false),
+    account("participant", true, false),
+];
+const WITHDRAW_ACCOUNTS: &[IdlAccount] = &[
+    account("wallet", false, true),
+    account("ledger", false, false),
+    account("participant", true, false),
+    account("destination", true, false),
+    account("vault", true, false),
+    VAULT_TOKEN_ACCOUNT,
+    TOKEN_PROGRAM,
+];
+const CANCEL_ACCOUNTS: &[IdlAccount] = &[
+    account("owner", true, true),
+    account("ledger", true, false),
+    account("participant", true, false),
+    optional("order", true, false, "account order storage only"),
+];
+const CRANK_ACCOUNTS: &[IdlAccount] = &[
+    account("ledger", true, false),
+    account("cranker", false, true),
+    account("cranker_participant", true, false),
+];
+const QUERY_ACCOUNTS: &[IdlAccount] = &[account("ledger", false, false), account("participant", false, false)];
+
+const APPROVERS: &str = "co-signing approvers on a multisig ledger";
+const DELEGATE: &str = "the participant's delegate, signing in place of the owner's wallet";
+const MATCHED_ORDERS: &str = "the trade log if the ledger has one, then a participant PDA per order owner on ledger \
+    order storage, or an (order PDA, owner wallet, owner participant PDA) triple per order on account order storage";
+const NEW_ORDER: &str = "on account order storage the owner's other open order PDAs for a production; immediate orders \
+    instead take the resting orders they may cross as for MatchTransactions; then the delegate if one signs";
+
+pub const INSTRUCTIONS: &[IdlInstruction] = &[
+    instruction(
+        "InitializeLedger",
+        &[account("ledger", true, false), account("payer", true, true), account("vault", true, false), SYSTEM_PROGRAM],
+        "",
+    ),
+    instruction(
+        "RegisterParticipant",
+        &[account("wallet", true, true), account("ledger", true, false), account("participant", true, false), SYSTEM_PROGRAM],
+        "",
+    ),
+    instruction(
+        "ReportProduction",
+        &[
+            account("producer", true, true),
+            account("ledger", true, false),
+            account("participant", true, false),
+            optional("order", true, false, "resting orders on account order storage"),
+            optional("system_program", false, false, "resting orders on account order storage"),
+        ],
+        NEW_ORDER,
+    ),
+    instruction(
+        "PostDemand",
+        &[
+            account("consumer", true, true),
+            account("ledger", true, false),
+            account("participant", true, false),
+            optional("order", true, false, "resting orders on account order storage"),
+            optional("system_program", false, false, "resting orders on account order storage"),
+        ],
+        NEW_ORDER,
+    ),
+    instruction("MatchTransactions", CRANK_ACCOUNTS, MATCHED_ORDERS),
+    instruction(
+        "Deposit",
+        &[
+            account("wallet", true, true),
+            account("ledger", false, false),
+            account("participant", true, false),
+            account("vault", true, false),
+            optional("source_token_account", true, false, "token ledgers only"),
+            VAULT_TOKEN_ACCOUNT,
+            TOKEN_PROGRAM,
+            optional("system_program", false, false, "native ledgers only"),
+        ],
+        "",
+    ),
+    instruction("Withdraw", WITHDRAW_ACCOUNTS, ""),
+    instruction("CancelDemand", CANCEL_ACCOUNTS, DELEGATE),
+    instruction("CancelProduction", CANCEL_ACCOUNTS, DELEGATE),
+    instruction("PruneExpiredOrders", &[account("ledger", true, false)], MATCHED_ORDERS),
+    instruction("RunAuction", CRANK_ACCOUNTS, MATCHED_ORDERS),
+    instruction(
+        "ResizeLedger",
+        &[account("admin", false, true), account("ledger", true, false), account("funding", true, true), SYSTEM_PROGRAM],
+        APPROVERS,
+    ),
+    instruction(
+        "MigrateLedger",
+        &[account("admin", false, true), account("ledger", true, false), account("funding", true, true), SYSTEM_PROGRAM],
+        "",
+    ),
+    instruction("SetFee", ADMIN_ACCOUNTS, APPROVERS),
+    instruction("CollectFees", ADMIN_BALANCE_ACCOUNTS, APPROVERS),
+    instruction("SetPendingAdmin", ADMIN_ACCOUNTS, APPROVERS),
+    instruction("AcceptAdmin", &[account("new_admin", false, true), account("ledger", true, false)], ""),
+    instruction("Pause", ADMIN_ACCOUNTS, APPROVERS),
+    instruction("Unpause", ADMIN_ACCOUNTS, APPROVERS),
+    instruction(
+        "UnregisterParticipant",
+        &[account("wallet", true, true), account("ledger", true, false), account("participant", true, false)],
+        "",
+    ),
+    instruction(
+        "ForceUnregisterParticipant",
+        &[
+            account("admin", false, true),
+            account("ledger", true, false),
+            account("wallet", true, false),
+            account("participant", true, false),
+            account("destination", true, false),
+            account("vault", true, false),
+            VAULT_TOKEN_ACCOUNT,
+            TOKEN_PROGRAM,
+        ],
+        "the wallet's open order PDAs on account order storage, then co-signing approvers on a multisig ledger",
+    ),
+    instruction(
+        "FreezeParticipant",
+        &[
+            account("admin", false, true),
+            account("ledger", true, false),
+            account("wallet", true, false),
+            account("participant", true, false),
+        ],
+        "the wallet's open order PDAs on account order storage, then co-signing approvers on a multisig ledger",
+    ),
+    instruction("UnfreezeParticipant", ADMIN_PARTICIPANT_ACCOUNTS, APPROVERS),
+    instruction("SetGridOperator", ADMIN_ACCOUNTS, APPROVERS),
+    instruction(
+        "Curtail",
+        &[
+            account("grid_operator", false, true),
+            account("ledger", true, false),
+            account("producer", true, false),
+            account("participant", true, false),
+        ],
+        "the producer's open order PDAs on account order storage",
+    ),
+    instruction("SetRegisteredCapacity", ADMIN_PARTICIPANT_ACCOUNTS, APPROVERS),
+    instruction("SetOracle", ADMIN_ACCOUNTS, APPROVERS),
+    instruction(
+        "AttestProduction",
+        &[
+            account("oracle", false, true),
+            account("ledger", true, false),
+            optional("order", true, false, "account order storage only"),
+        ],
+        "",
+    ),
+    instruction(
+        "ConfirmDelivery",
+        &[
+            account("confirmer", false, true),
+            account("ledger", true, false),
+            account("consumer_participant", true, false),
+            account("producer_participant", true, false),
+        ],
+        "",
+    ),
+    instruction(
+        "SettleDefaultedTrade",
+        &[account("ledger", true, false), account("consumer_participant", true, false), account("producer_participant", true, false)],
+        "",
+    ),
+    instruction("PostCollateral", WALLET_ACCOUNTS, ""),
+    instruction("WithdrawCollateral", WALLET_ACCOUNTS, ""),
+    instruction(
+        "SlashCollateral",
+        &[
+            account("admin", false, true),
+            account("ledger", true, false),
+            account("producer_participant", true, false),
+            optional("consumer_participant", true, false, "when the slashed collateral compensates the consumer"),
+        ],
+        APPROVERS,
+    ),
+    instruction(
+        "TransferRec",
+        &[
+            account("wallet", false, true),
+            account("ledger", false, false),
+            account("participant", true, false),
+            account("recipient_participant", true, false),
+        ],
+        "",
+    ),
+    instruction("RetireRec", WALLET_ACCOUNTS, ""),
+    instruction("SetStorageParameters", ADMIN_PARTICIPANT_ACCOUNTS, APPROVERS),
+    instruction("SetOrderIncrements", ADMIN_ACCOUNTS, APPROVERS),
+    instruction("SetOrderSizeLimits", ADMIN_ACCOUNTS, APPROVERS),
+    instruction("SetReferencePrice", ADMIN_ACCOUNTS, APPROVERS),
+    instruction("GetMarketStats", &[account("ledger", false, false)], ""),
+    instruction("GetTwap", &[account("ledger", false, false)], ""),
+    instruction(
+        "SimulateMatch",
+        &[account("ledger", false, false), account("caller", false, false)],
+        MATCHED_ORDERS,
+    ),
+    instruction(
+        "ReplaceOrder",
+        &[
+            account("owner", true, true),
+            account("ledger", true, false),
+            account("participant", true, false),
+            optional("order", true, false, "account order storage only"),
+        ],
+        "the owner's other open order PDAs when replacing a production on account order storage",
+    ),
+    instruction(
+        "CreateBilateralOffer",
+        &[
+            account("maker", true, true),
+            account("ledger", true, false),
+            account("participant", true, false),
+            account("offer", true, false),
+            SYSTEM_PROGRAM,
+        ],
+        "",
+    ),
+    instruction(
+        "AcceptBilateralOffer",
+        &[
+            account("counterparty", true, true),
+            account("ledger", true, false),
+            account("counterparty_participant", true, false),
+            account("maker", true, false),
+            account("maker_participant", true, false),
+            account("offer", true, false),
+        ],
+        "the trade log if the ledger has one",
+    ),
+    instruction(
+        "RejectBilateralOffer",
+        &[
+            account("signer", true, true),
+            account("ledger", false, false),
+            account("maker", true, false),
+            account("maker_participant", true, false),
+            account("offer", true, false),
+        ],
+        "",
+    ),
+    instruction(
+        "TransferBalance",
+        &[
+            account("wallet", false, true),
+            account("ledger", true, false),
+            account("participant", true, false),
+            account("recipient_participant", true, false),
+        ],
+        "",
+    ),
+    instruction("WithdrawAll", WITHDRAW_ACCOUNTS, ""),
+    instruction("SetWithdrawalDelay", ADMIN_ACCOUNTS, APPROVERS),
+    instruction("RequestWithdrawal", WALLET_ACCOUNTS, ""),
+    instruction("ClaimWithdrawal", WITHDRAW_ACCOUNTS, ""),
+    instruction("VetoWithdrawal", ADMIN_PARTICIPANT_ACCOUNTS, APPROVERS),
+    instruction("SetWithdrawalLimit", ADMIN_PARTICIPANT_ACCOUNTS, APPROVERS),
+    instruction(
+        "RegisterAndDeposit",
+        &[
+            account("wallet", true, true),
+            account("ledger", true, false),
+            account("participant", true, false),
+            SYSTEM_PROGRAM,
+            account("vault", true, false),
+            optional("source_token_account", true, false, "token ledgers only"),
+            VAULT_TOKEN_ACCOUNT,
+            TOKEN_PROGRAM,
+            optional("system_program", false, false, "native ledgers only"),
+        ],
+        "",
+    ),
+    instruction("SetDepositLimits", ADMIN_ACCOUNTS, APPROVERS),
+    instruction("UpdateParticipantMetadata", WALLET_ACCOUNTS, ""),
+    instruction("SetReputationConfig", ADMIN_ACCOUNTS, APPROVERS),
+    instruction("SetDelegate", WALLET_ACCOUNTS, ""),
+    instruction("RevokeDelegate", WALLET_ACCOUNTS, ""),
+    instruction(
+        "ChangeParticipantType",
+        &[
+            account("wallet", false, true),
+            account("ledger", false, false),
+            account("participant", true, false),
+            optional("admin", false, true, "changes to or from Storage"),
+        ],
+        "co-signing approvers on a multisig ledger, for changes to or from Storage",
+    ),
+    instruction("UpdateConfig", ADMIN_ACCOUNTS, APPROVERS),
+    instruction("ProposeConfigChange", ADMIN_ACCOUNTS, APPROVERS),
+    instruction("ApplyConfigChange", &[account("ledger", true, false)], ""),
+    instruction("CancelConfigChange", ADMIN_ACCOUNTS, APPROVERS),
+    instruction("AddApprover", ADMIN_ACCOUNTS, APPROVERS),
+    instruction("RemoveApprover", ADMIN_ACCOUNTS, APPROVERS),
+    instruction("SetApprovalThreshold", ADMIN_ACCOUNTS, APPROVERS),
+    instruction(
+        "CloseLedger",
+        &[
+            account("admin", false, true),
+            account("ledger", true, false),
+            account("recipient", true, false),
+            account("vault", true, false),
+            VAULT_TOKEN_ACCOUNT,
+            TOKEN_PROGRAM,
+        ],
+        APPROVERS,
+    ),
+    instruction(
+        "InitializeTradeLog",
+        &[account("admin", false, true), account("ledger", true, false), account("trade_log", true, false)],
+        APPROVERS,
+    ),
+    instruction("GetParticipant", QUERY_ACCOUNTS, ""),
+    instruction("GetOpenOrders", QUERY_ACCOUNTS, "the participant's open order PDAs on account order storage"),
+    instruction("GetBalance", QUERY_ACCOUNTS, ""),
+    instruction("SetCriticalLoad", ADMIN_PARTICIPANT_ACCOUNTS, APPROVERS),
+    instruction(
+        "DeclareDemandResponse",
+        &[account("authority", false, true), account("ledger", true, false)],
+        "co-signing approvers on a multisig ledger unless the grid operator signs",
+    ),
+    instruction("VolunteerReduction", &[account("wallet", false, true), account("ledger", true, false), account("participant", true, false)], ""),
+    instruction(
+        "SettleDemandResponse",
+        &[account("settler", false, true), account("ledger", true, false)],
+        "the participant PDA of every volunteer to pay",
+    ),
+    instruction(
+        "CreateForward",
+        &[
+            account("maker", true, true),
+            account("ledger", true, false),
+            account("participant", true, false),
+            account("contract", true, false),
+            SYSTEM_PROGRAM,
+        ],
+        "",
+    ),
+    instruction(
+        "AcceptForward",
+        &[
+            account("taker", false, true),
+            account("ledger", false, false),
+            account("participant", true, false),
+            account("contract", true, false),
+        ],
+        "",
+    ),
+    instruction(
+        "SettleForward",
+        &[
+            account("signer", false, true),
+            account("ledger", false, false),
+            account("maker", true, false),
+            account("maker_participant", true, false),
+            account("contract", true, false),
+            optional("taker_participant", true, false, "once the contract has been taken"),
+        ],
+        "",
+    ),
+    instruction(
+        "CreateStandingOrder",
+        &[account("owner", false, true), account("ledger", true, false), account("participant", false, false)],
+        "",
+    ),
+    instruction("CancelStandingOrder", &[account("owner", false, true), account("ledger", true, false)], ""),
+    instruction(
+        "ActivateStandingOrders",
+        &[account("cranker", false, true), account("ledger", true, false)],
+        "the participant PDA of every owner whose due standing orders to activate",
+    ),
+    instruction(
+        "AddMember",
+        &[
+            account("aggregator", false, true),
+            account("ledger", false, false),
+            account("aggregator_participant", true, false),
+            account("member", false, true),
+            account("member_participant", true, false),
+        ],
+        "",
+    ),
+    instruction(
+        "RemoveMember",
+        &[
+            account("signer", false, true),
+            account("ledger", false, false),
+            account("aggregator_participant", true, false),
+            account("member_participant", true, false),
+        ],
+        "",
+    ),
+    instruction(
+        "PostAggregatedDemand",
+        &[account("aggregator", true, true), account("ledger", true, false), account("aggregator_participant", true, false)],
+        "the participant PDA of every member in membership order, then on account order storage the order PDA and the system program",
+    ),
+    instruction(
+        "SettleAggregatedDemand",
+        &[account("signer", false, true), account("ledger", false, false), account("aggregator_participant", true, false)],
+        "the participant PDA of every member in membership order",
+    ),
+    instruction("SetTariffSchedule", ADMIN_ACCOUNTS, APPROVERS),
+    instruction("SetNetMetering", WALLET_ACCOUNTS, ""),
+    instruction("SetImbalanceConfig", ADMIN_ACCOUNTS, APPROVERS),
+    instruction(
+        "ReportActuals",
+        &[account("oracle", false, true), account("ledger", false, false), account("participant", true, false)],
+        "",
+    ),
+    instruction(
+        "SettleImbalances",
+        &[account("settler", false, true), account("ledger", true, false), account("participant", true, false)],
+        "",
+    ),
+    instruction("TopUpInsurance", ADMIN_BALANCE_ACCOUNTS, APPROVERS),
+    instruction("ProposeInsuranceDrain", ADMIN_ACCOUNTS, APPROVERS),
+    instruction("DrainInsurance", ADMIN_BALANCE_ACCOUNTS, APPROVERS),
+    instruction(
+        "ClaimInsurance",
+        &[account("wallet", false, true), account("ledger", true, false), account("participant", true, false)],
+        "",
+    ),
+    instruction("StakeForPriority", WALLET_ACCOUNTS, ""),
+    instruction("UnstakeAfterCooldown", WALLET_ACCOUNTS, ""),
+    instruction("SetRewardRates", ADMIN_ACCOUNTS, APPROVERS),
+    instruction("FundRewards", ADMIN_BALANCE_ACCOUNTS, APPROVERS),
+    instruction(
+        "ClaimRewards",
+        &[account("wallet", false, true), account("ledger", true, false), account("participant", true, false)],
+        "",
+    ),
+    instruction("SetGovernanceConfig", ADMIN_ACCOUNTS, APPROVERS),
+    instruction(
+        "CreateProposal",
+        &[account("wallet", false, true), account("ledger", true, false), account("participant", true, false)],
+        "",
+    ),
+    instruction(
+        "CastVote",
+        &[account("wallet", false, true), account("ledger", true, false), account("participant", true, false)],
+        "",
+    ),
+    instruction("ExecuteProposal", &[account("ledger", true, false)], ""),
+];
+
+// The JSON subset the IDL needs; objects keep their insertion order
+enum Json {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl Json {
+    fn str(value: &str) -> Json {
+        Json::Str(value.to_string())
+    }
+
+    fn write(&self, out: &mut String, indent: usize) {
+        let pad = "  ".repeat(indent + 1);
+        match self {
+            Json::Str(value) => {
+                out.push('"');
+                for c in value.chars() {
+                    match c {
+                        '"' => out.push_str("\\\""),
+                        '\\' => out.push_str("\\\\"),
+                        c => out.push(c),
+                    }
+                }
+                out.push('"');
+            }
+            Json::Int(value) => out.push_str(&value.to_string()),
+            Json::Bool(value) => out.push_str(if *value { "true" } else { "false" }),
+            Json::Array(items) if items.is_empty() => out.push_str("[]"),
+            Json::Array(items) => {
+                out.push_str("[\n");
+                for (index, item) in items.iter().enumerate() {
+                    out.push_str(&pad);
+                    item.write(out, indent + 1);
+                    out.push_str(if index + 1 < items.len() { ",\n" } else { "\n" });
+                }
+                out.push_str(&"  ".repeat(indent));
+                out.push(']');
+            }
+            Json::Object(entries) => {
+                out.push_str("{\n");
+                for (index, (key, value)) in entries.iter().enumerate() {
+                    out.push_str(&pad);
+                    Json::str(key).write(out, indent + 1);
+                    out.push_str(": ");
+                    value.write(out, indent + 1);
+                    out.push_str(if index + 1 < entries.len() { ",\n" } else { "\n" });
+                }
+                out.push_str(&"  ".repeat(indent));
+                out.push('}');
+            }
+        }
+    }
+}
+
+fn fields_json(fields: &Fields) -> Json {
+    match fields {
+        Fields::NamedFields(fields) => Json::Array(fields.iter()
+            .map(|(name, ty)| Json::Object(vec![("name", Json::str(name)), ("type", Json::str(ty))]))
+            .collect()),
+        Fields::UnnamedFields(types) => Json::Array(types.iter()
+            .enumerate()
+            .map(|(index, ty)| Json::Object(vec![("name", Json::Str(index.to_string())), ("type", Json::str(ty))]))
+            .collect()),
+        Fields::Empty => Json::Array(Vec::new()),
+    }
+}
+
+fn variant_fields(definitions: &HashMap<Declaration, Definition>, declaration: &str) -> Json {
+    match definitions.get(declaration) {
+        Some(Definition::Struct { fields }) => fields_json(fields),
+        _ => Json::Array(Vec::new()),
+    }
+}
+
+fn enum_variants(definitions: &HashMap<Declaration, Definition>, declaration: &str) -> Result<Vec<(String, Declaration)>, String> {
+    match definitions.get(declaration) {
+        Some(Definition::Enum { variants }) => Ok(variants.clone()),
+        _ => Err(format!("{} has no enum schema", declaration)),
+    }
+}
+
+// Named structs and enums only: Pubkey, Option, Vec and arrays are spelled out in the
+// declarations that use them, and enum variant payloads are listed with their enum
+fn type_json(definitions: &HashMap<Declaration, Definition>, name: &str, definition: &Definition) -> Option<Json> {
+    if name == "Pubkey" || !name.chars().all(char::is_alphanumeric) {
+        return None;
+    }
+    match definition {
+        Definition::Struct { fields } => Some(Json::Object(vec![
+            ("name", Json::str(name)),
+            ("kind", Json::str("struct")),
+            ("fields", fields_json(fields)),
+        ])),
+        Definition::Enum { variants } => Some(Json::Object(vec![
+            ("name", Json::str(name)),
+            ("kind", Json::str("enum")),
+            ("variants", Json::Array(variants.iter()
+                .map(|(variant, declaration)| Json::Object(vec![
+                    ("name", Json::str(variant)),
+                    ("fields", variant_fields(definitions, declaration)),
+                ]))
+                .collect())),
+        ])),
+        _ => None,
+    }
+}
+
+fn account_json(account: &IdlAccount) -> Json {
+    let mut entries = vec![
+        ("name", Json::str(account.name)),
+        ("writable", Json::Bool(account.writable)),
+        ("signer", Json::Bool(account.signer)),
+    ];
+    if account.optional {
+        entries.push(("optional", Json::Bool(true)));
+        entries.push(("docs", Json::str(account.docs)));
+    }
+    Json::Object(entries)
+}
+
+// Defined state accounts, by the name client code generators should give them
+fn state_accounts() -> Vec<(&'static str, Declaration)> {
+    vec![
+        ("Ledger", Ledger::declaration()),
+        ("Participant", Participant::declaration()),
+        ("OrderAccount", OrderAccount::declaration()),
+        ("BilateralOffer", BilateralOffer::declaration()),
+        ("ForwardContract", ForwardContract::declaration()),
+        ("TradeLogHeader", TradeLogHeader::declaration()),
+    ]
+}
+
+// Fails when INSTRUCTIONS does not list exactly the EnergyMarketInstruction variants, in order
+pub fn generate() -> Result<String, String> {
+    let mut definitions = HashMap::new();
+    EnergyMarketInstruction::add_definitions_recursively(&mut definitions);
+    Ledger::add_definitions_recursively(&mut definitions);
+    Participant::add_definitions_recursively(&mut definitions);
+    OrderAccount::add_definitions_recursively(&mut definitions);
+    BilateralOffer::add_definitions_recursively(&mut definitions);
+    ForwardContract::add_definitions_recursively(&mut definitions);
+    TradeLogHeader::add_definitions_recursively(&mut definitions);
+    EnergyMarketError::add_definitions_recursively(&mut definitions);
+
+    let variants = enum_variants(&definitions, &EnergyMarketInstruction::declaration())?;
+    if variants.len() != INSTRUCTIONS.len() {
+        return Err(format!(
+            "EnergyMarketInstruction has {} variants but INSTRUCTIONS lists {}",
+            variants.len(),
+            INSTRUCTIONS.len()
+        ));
+    }
+    let mut instructions = Vec::new();
+    for (index, ((variant, declaration), annotated)) in variants.iter().zip(INSTRUCTIONS).enumerate() {
+        if variant != annotated.name {
+            return Err(format!("variant {} is {} but INSTRUCTIONS lists {} there", index, variant, annotated.name));
+        }
+        let mut entries = vec![
+            ("name", Json::str(variant)),
+            ("discriminant", Json::Int(index as i64)),
+            ("accounts", Json::Array(annotated.accounts.iter().map(account_json).collect())),
+        ];
+        if !annotated.remaining_accounts.is_empty() {
+            entries.push(("remainingAccounts", Json::str(annotated.remaining_accounts)));
+        }
+        entries.push(("args", variant_fields(&definitions, declaration)));
+        instructions.push(Json::Object(entries));
+    }
+
+    // Instruction payloads are listed with their instruction and state accounts under "accounts"
+    let mut listed_elsewhere = variant_declarations(&definitions);
+    listed_elsewhere.extend(state_accounts().into_iter().map(|(_, declaration)| declaration));
+    listed_elsewhere.push(EnergyMarketInstruction::declaration());
+    listed_elsewhere.push(EnergyMarketError::declaration());
+    let sorted: BTreeMap<&Declaration, &Definition> = definitions.iter().collect();
+    let types = sorted.into_iter()
+        .filter(|(name, _)| !listed_elsewhere.contains(name))
+        .filter_map(|(name, definition)| type_json(&definitions, name, definition))
+        .collect();
+    let accounts = state_accounts().into_iter()
+        .map(|(name, declaration)| {
+            let fields = variant_fields(&definitions, &declaration);
+            Json::Object(vec![("name", Json::str(name)), ("fields", fields)])
+        })
+        .collect();
+
+    // Error codes are the variant indices: the enum numbers its variants from 0 without gaps
+    let errors = enum_variants(&definitions, &EnergyMarketError::declaration())?
+        .into_iter()
+        .enumerate()
+        .map(|(code, (name, _))| Json::Object(vec![("code", Json::Int(code as i64)), ("name", Json::Str(name))]))
+        .collect();
+
+    let idl = Json::Object(vec![
+        ("version", Json::str(env!("CARGO_PKG_VERSION"))),
+        ("name", Json::str(env!("CARGO_PKG_NAME"))),
+        ("address", Json::Str(crate::id().to_string())),
+        ("instructions", Json::Array(instructions)),
+        ("accounts", Json::Array(accounts)),
+        ("types", Json::Array(types)),
+        ("errors", Json::Array(errors)),
+    ]);
+    let mut out = String::new();
+    idl.write(&mut out, 0);
+    out.push('\n');
+    Ok(out)
+}
+
+fn variant_declarations(definitions: &HashMap<Declaration, Definition>) -> Vec<Declaration> {
+    definitions.values()
+        .filter_map(|definition| match definition {
+            Definition::Enum { variants } => Some(variants.iter().map(|(_, declaration)| declaration.clone())),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
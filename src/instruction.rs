@@ -0,0 +1,297 @@
+use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
+use solana_program::{msg, program_error::ProgramError, pubkey::Pubkey};
+
+use crate::{
+    EnergyMarketError, EnergySource, GovernanceConfig, HistoryPolicy, ImbalanceConfig, LedgerCapacity, LossConfig, MarketConfig, OrderSide, ParticipantMetadata,
+    ParticipantType, ReputationConfig, RewardRates, SettlementConfig, TariffSchedule, TimeInForce,
+};
+
+// One tunable market parameter with its new value, as applied by UpdateConfig or a config proposal.
+// Parameters the book depends on, like zones and order storage, are not listed.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ConfigUpdate {
+    FeeBps(u16),
+    ConfigTimelock(i64),
+    AllowSelfTrade(bool),
+    CrankRewardBps(u16),
+    AttestationTimeout(i64),
+    Settlement(SettlementConfig),
+    CollateralBps(u16),
+    KwhPerRec(u64),
+    PreferRenewable(bool),
+    MaxDeviationBps(u16),
+    MaxOpenOrdersPerParticipant(u32),
+    StaleDemandTimeout(i64),
+    GridFeePerKwh(u64),
+    Losses(LossConfig),
+    InsuranceFeeBps(u16),
+}
+
+impl ConfigUpdate {
+    // The same parameter with the value it currently has in `config`
+    pub fn current(&self, config: &MarketConfig) -> ConfigUpdate {
+        match self {
+            ConfigUpdate::FeeBps(_) => ConfigUpdate::FeeBps(config.fee_bps),
+            ConfigUpdate::ConfigTimelock(_) => ConfigUpdate::ConfigTimelock(config.config_timelock),
+            ConfigUpdate::AllowSelfTrade(_) => ConfigUpdate::AllowSelfTrade(config.allow_self_trade),
+            ConfigUpdate::CrankRewardBps(_) => ConfigUpdate::CrankRewardBps(config.crank_reward_bps),
+            ConfigUpdate::AttestationTimeout(_) => ConfigUpdate::AttestationTimeout(config.attestation_timeout),
+            ConfigUpdate::Settlement(_) => ConfigUpdate::Settlement(config.settlement),
+            ConfigUpdate::CollateralBps(_) => ConfigUpdate::CollateralBps(config.collateral_bps),
+            ConfigUpdate::KwhPerRec(_) => ConfigUpdate::KwhPerRec(config.kwh_per_rec),
+            ConfigUpdate::PreferRenewable(_) => ConfigUpdate::PreferRenewable(config.prefer_renewable),
+            ConfigUpdate::MaxDeviationBps(_) => ConfigUpdate::MaxDeviationBps(config.max_deviation_bps),
+            ConfigUpdate::MaxOpenOrdersPerParticipant(_) => {
+                ConfigUpdate::MaxOpenOrdersPerParticipant(config.max_open_orders_per_participant)
+            }
+            ConfigUpdate::StaleDemandTimeout(_) => ConfigUpdate::StaleDemandTimeout(config.stale_demand_timeout),
+            ConfigUpdate::GridFeePerKwh(_) => ConfigUpdate::GridFeePerKwh(config.grid_fee_per_kwh),
+            ConfigUpdate::Losses(_) => ConfigUpdate::Losses(config.losses),
+            ConfigUpdate::InsuranceFeeBps(_) => ConfigUpdate::InsuranceFeeBps(config.insurance_fee_bps),
+        }
+    }
+
+    pub fn apply(self, config: &mut MarketConfig) {
+        match self {
+            ConfigUpdate::FeeBps(value) => config.fee_bps = value,
+            ConfigUpdate::ConfigTimelock(value) => config.config_timelock = value,
+            ConfigUpdate::AllowSelfTrade(value) => config.allow_self_trade = value,
+            ConfigUpdate::CrankRewardBps(value) => config.crank_reward_bps = value,
+            ConfigUpdate::AttestationTimeout(value) => config.attestation_timeout = value,
+            ConfigUpdate::Settlement(value) => config.settlement = value,
+            ConfigUpdate::CollateralBps(value) => config.collateral_bps = value,
+            ConfigUpdate::KwhPerRec(value) => config.kwh_per_rec = value,
+            ConfigUpdate::PreferRenewable(value) => config.prefer_renewable = value,
+            ConfigUpdate::MaxDeviationBps(value) => config.max_deviation_bps = value,
+            ConfigUpdate::MaxOpenOrdersPerParticipant(value) => config.max_open_orders_per_participant = value,
+            ConfigUpdate::StaleDemandTimeout(value) => config.stale_demand_timeout = value,
+            ConfigUpdate::GridFeePerKwh(value) => config.grid_fee_per_kwh = value,
+            ConfigUpdate::Losses(value) => config.losses = value,
+            ConfigUpdate::InsuranceFeeBps(value) => config.insurance_fee_bps = value,
+        }
+    }
+
+    // The economic parameters members may change by vote; timing, settlement and loss rules stay
+    // with the admin
+    pub fn governable(&self) -> bool {
+        matches!(
+            self,
+            ConfigUpdate::FeeBps(_)
+                | ConfigUpdate::CrankRewardBps(_)
+                | ConfigUpdate::CollateralBps(_)
+                | ConfigUpdate::KwhPerRec(_)
+                | ConfigUpdate::PreferRenewable(_)
+                | ConfigUpdate::MaxOpenOrdersPerParticipant(_)
+                | ConfigUpdate::GridFeePerKwh(_)
+                | ConfigUpdate::InsuranceFeeBps(_)
+        )
+    }
+}
+
+// Unlike the state types, instructions keep Pubkey's own serde form of 32 numbers: the BorshSchema
+// derive copies field attributes into structs of its own, where serde(with) does not resolve.
+// An instruction is decoded once per call, so InitializeLedger's inline MarketConfig is not boxed.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[allow(clippy::large_enum_variant)]
+pub enum EnergyMarketInstruction {
+    InitializeLedger {
+        capacity: LedgerCapacity,
+        quote_mint: Option<Pubkey>,
+        config: MarketConfig,
+        oracle: Option<Pubkey>,
+    },
+    RegisterParticipant {
+        participant_type: ParticipantType,
+        zone: u8,
+        registered_capacity: u64,
+        metadata: ParticipantMetadata,
+    },
+    ReportProduction {
+        energy_amount: u64,
+        price: i64,
+        expires_at: i64,
+        delivery_slot: u32,
+        energy_source: EnergySource,
+        time_in_force: TimeInForce,
+        min_fill: u64,
+        all_or_nothing: bool,
+        // Non-zero nonces are remembered per participant so a retried post is rejected; 0 opts out
+        client_order_nonce: u64,
+        // Rest as a new order even when an open offer of the producer could absorb this one
+        distinct_lot: bool,
+    },
+    PostDemand {
+        energy_amount: u64,
+        price_limit: i64,
+        expires_at: i64,
+        delivery_slot: u32,
+        time_in_force: TimeInForce,
+        min_fill: u64,
+        all_or_nothing: bool,
+        client_order_nonce: u64,
+        // Above the ledger's max_unflagged_priority only for participants flagged as critical loads
+        priority: u8,
+    },
+    MatchTransactions { max_matches: u16 },
+    Deposit { amount: u64 },
+    Withdraw { amount: u64 },
+    CancelDemand { order_id: u64 },
+    CancelProduction { order_id: u64 },
+    PruneExpiredOrders,
+    RunAuction,
+    ResizeLedger { new_size: u32, capacity: LedgerCapacity },
+    MigrateLedger,
+    SetFee { fee_bps: u16 },
+    CollectFees,
+    SetPendingAdmin { new_admin: Pubkey },
+    AcceptAdmin,
+    Pause,
+    Unpause,
+    UnregisterParticipant,
+    ForceUnregisterParticipant,
+    FreezeParticipant,
+    UnfreezeParticipant,
+    SetGridOperator { grid_operator: Pubkey },
+    Curtail { energy_amount: u64, curtailed_until: i64 },
+    SetRegisteredCapacity { registered_capacity: u64 },
+    SetOracle { oracle: Pubkey },
+    AttestProduction { order_id: u64 },
+    ConfirmDelivery { trade_id: u64 },
+    SettleDefaultedTrade { trade_id: u64 },
+    PostCollateral { amount: u64 },
+    WithdrawCollateral { amount: u64 },
+    SlashCollateral { trade_id: u64, amount: u64 },
+    TransferRec { amount: u64 },
+    RetireRec { amount: u64 },
+    SetStorageParameters { storage_capacity: u64, storage_efficiency_bps: u16 },
+    SetOrderIncrements { price_tick: u64, lot_size: u64 },
+    SetOrderSizeLimits { min_order_size: u64, max_order_size: u64 },
+    SetReferencePrice { reference_price: i64 },
+    GetMarketStats,
+    GetTwap { window_secs: i64 },
+    SimulateMatch { max_matches: u16 },
+    ReplaceOrder { order_id: u64, new_price: i64, new_amount: u64 },
+    CreateBilateralOffer { counterparty: Pubkey, side: OrderSide, energy_amount: u64, price: i64, expires_at: i64 },
+    AcceptBilateralOffer { offer_id: u64 },
+    RejectBilateralOffer { offer_id: u64 },
+    TransferBalance { amount: u64 },
+    WithdrawAll,
+    SetWithdrawalDelay { withdrawal_delay: i64, withdrawal_threshold: u64 },
+    // None requests the whole free balance
+    RequestWithdrawal { amount: Option<u64> },
+    ClaimWithdrawal,
+    VetoWithdrawal,
+    // None returns the participant to the ledger's withdrawal_limit
+    SetWithdrawalLimit { withdrawal_limit: Option<u64> },
+    RegisterAndDeposit {
+        participant_type: ParticipantType,
+        zone: u8,
+        registered_capacity: u64,
+        metadata: ParticipantMetadata,
+        amount: u64,
+    },
+    SetDepositLimits { min_deposit: u64, min_balance_to_post_demand: u64 },
+    UpdateParticipantMetadata { metadata: ParticipantMetadata },
+    SetReputationConfig { reputation: ReputationConfig },
+    SetDelegate { delegate: Pubkey },
+    RevokeDelegate,
+    ChangeParticipantType { new_type: ParticipantType },
+    UpdateConfig { update: ConfigUpdate },
+    ProposeConfigChange { update: ConfigUpdate, effective_at: i64 },
+    ApplyConfigChange,
+    CancelConfigChange,
+    AddApprover { approver: Pubkey },
+    RemoveApprover { approver: Pubkey },
+    SetApprovalThreshold { threshold: u8 },
+    CloseLedger,
+    InitializeTradeLog { capacity: u32, history_policy: HistoryPolicy },
+    GetParticipant { pubkey: Pubkey },
+    GetOpenOrders { pubkey: Pubkey },
+    GetBalance { pubkey: Pubkey },
+    SetCriticalLoad { critical_load: bool },
+    DeclareDemandResponse { start: i64, end: i64, target_reduction_kwh: u64, incentive_price: u64 },
+    VolunteerReduction { reduction_kwh: u64 },
+    // With an oracle on the ledger, the oracle's metered reduction for each volunteer passed
+    SettleDemandResponse { actual_reductions: Option<Vec<u64>> },
+    // A counterparty of None leaves the contract open to any participant
+    CreateForward { counterparty: Option<Pubkey>, side: OrderSide, energy_amount: u64, price: i64, delivery_slot: u32, margin_bps: u16 },
+    AcceptForward { contract_id: u64 },
+    SettleForward { contract_id: u64 },
+    CreateStandingOrder { side: OrderSide, energy_amount: u64, price: i64, interval_secs: i64, first_activation: i64, activations: u32 },
+    CancelStandingOrder { standing_order_id: u64 },
+    // The participant PDAs of the owners whose due standing orders should be activated follow the ledger
+    ActivateStandingOrders,
+    AddMember { weight: u32 },
+    RemoveMember,
+    // The participant PDAs of all the aggregator's members follow, in membership order
+    PostAggregatedDemand { energy_amount: u64, price_limit: i64, expires_at: i64, delivery_slot: u32 },
+    SettleAggregatedDemand,
+    SetTariffSchedule { schedule: TariffSchedule },
+    SetNetMetering { enabled: bool },
+    SetImbalanceConfig { imbalance: ImbalanceConfig },
+    // Signed by the oracle with the participant's metered energy for a delivery slot that ended
+    ReportActuals { delivery_slot: u32, metered_injection: u64, metered_consumption: u64 },
+    SettleImbalances { delivery_slot: u32 },
+    TopUpInsurance { amount: u64 },
+    // Takes effect config_timelock seconds after it is proposed; an amount of 0 cancels the pending drain
+    ProposeInsuranceDrain { amount: u64 },
+    DrainInsurance,
+    ClaimInsurance,
+    // Sellers only; lock_duration is in seconds and must be positive
+    StakeForPriority { amount: u64, lock_duration: i64 },
+    UnstakeAfterCooldown { amount: u64 },
+    SetRewardRates { rates: RewardRates },
+    FundRewards { amount: u64 },
+    ClaimRewards,
+    SetGovernanceConfig { governance: GovernanceConfig },
+    // Signed by the proposer; votes are taken until voting_deadline, which must leave at least the
+    // config timelock to vote in
+    CreateProposal { update: ConfigUpdate, voting_deadline: i64 },
+    // Weighs the voter's wallet balance as a snapshot taken when the vote is cast; the balance is not
+    // locked, so funds moved on afterwards can be voted with again by their new holder
+    CastVote { proposal_id: u64, support: bool },
+    // Permissionless once voting has closed; applies the update only if the proposal passed
+    ExecuteProposal,
+}
+
+// A transaction is at most 1232 bytes, so no instruction can carry more data than that
+const MAX_INSTRUCTION_DATA_LEN: usize = 1232;
+
+impl EnergyMarketInstruction {
+    // Decodes instruction data, telling an unknown variant and a truncated payload apart from other
+    // malformed data. Bytes after the encoded instruction are ignored, so a newer client appending
+    // fields to a variant this build already knows still gets the old behaviour.
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        let Some(&tag) = data.first() else {
+            msg!("Instruction data is empty");
+            return Err(EnergyMarketError::InstructionDataTooShort.into());
+        };
+        let mut rest = data;
+        if let Ok(instruction) = Self::deserialize(&mut rest) {
+            if !rest.is_empty() {
+                msg!("Ignoring {} trailing bytes of instruction {}", rest.len(), tag);
+            }
+            return Ok(instruction);
+        }
+
+        // Zero bytes decode as a valid value of every field type, so a known variant always decodes
+        // from its tag followed by zeros, and a truncated payload decodes once zeros fill in the rest
+        let mut padded = data.to_vec();
+        padded.resize(data.len() + MAX_INSTRUCTION_DATA_LEN, 0);
+        let mut zeroed = vec![0; MAX_INSTRUCTION_DATA_LEN];
+        zeroed[0] = tag;
+        if Self::deserialize(&mut zeroed.as_slice()).is_err() {
+            msg!("Unknown instruction {}", tag);
+            return Err(EnergyMarketError::UnknownInstruction.into());
+        }
+        let mut rest = padded.as_slice();
+        if Self::deserialize(&mut rest).is_ok() {
+            msg!("Instruction {} needs {} bytes of data, got {}", tag, padded.len() - rest.len(), data.len());
+            return Err(EnergyMarketError::InstructionDataTooShort.into());
+        }
+        msg!("Invalid data for instruction {}", tag);
+        Err(ProgramError::InvalidInstructionData)
+    }
+}
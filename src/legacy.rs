@@ -0,0 +1,225 @@
+// Frozen account layouts from earlier ledger versions. These types must never change: they exist
+// only so MigrateLedger can read accounts written by older program builds.
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+use crate::{
+    delivery_slot_at, EnergyDemand, EnergyProduction, EnergySource, GovernanceConfig, HistoryPolicy, ImbalanceConfig, Ledger,
+    LedgerCapacity, LossConfig, MarketConfig, MarketMode, MarketStats, OrderStorage, PriceSample, ReputationConfig,
+    RewardRates, SettlementConfig, SlotPrice, TariffSchedule, TradeStatus, Transaction, ZoneConfig, LEDGER_VERSION, MAX_APPROVERS,
+    PRICE_HISTORY_LEN, SLOT_PRICE_HISTORY_LEN,
+};
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct MarketConfigV1 {
+    pub allow_self_trade: bool,
+    pub market_mode: MarketMode,
+    pub history_policy: HistoryPolicy,
+    pub order_storage: OrderStorage,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy)]
+pub struct LedgerCapacityV1 {
+    pub max_participants: u32,
+    pub max_open_orders: u32,
+    pub max_transactions: u32,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct EnergyProductionV1 {
+    pub order_id: u64,
+    pub producer_id: Pubkey,
+    pub energy_amount: u64,
+    pub price: u64,
+    pub created_at: i64,
+    pub expires_at: i64,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct EnergyDemandV1 {
+    pub order_id: u64,
+    pub consumer_id: Pubkey,
+    pub energy_amount: u64,
+    pub price_limit: u64,
+    pub created_at: i64,
+    pub expires_at: i64,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct TransactionV1 {
+    pub demand_order_id: u64,
+    pub production_order_id: u64,
+    pub from: Pubkey,
+    pub to: Pubkey,
+    pub amount: u64,
+    pub price: u64,
+    pub timestamp: i64,
+}
+
+// Version 1 had no version byte; its leading is_initialized flag is 1 for every live ledger,
+// which is why LEDGER_VERSION numbering starts the current layout at 2
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct LedgerV1 {
+    pub is_initialized: bool,
+    pub admin: Pubkey,
+    pub vault_bump: u8,
+    pub quote_mint: Pubkey,
+    pub config: MarketConfigV1,
+    pub capacity: LedgerCapacityV1,
+    pub next_order_id: u64,
+    pub last_clearing_price: u64,
+    pub history_head: u32,
+    pub total_trades: u64,
+    pub participant_count: u32,
+    pub open_order_accounts: u32,
+    pub productions: Vec<EnergyProductionV1>,
+    pub demands: Vec<EnergyDemandV1>,
+    pub transactions: Vec<TransactionV1>,
+}
+
+// Carries every balance-relevant field over unchanged; fields new in the current layout get
+// the value a freshly initialized ledger would have. Version 1 orders had no delivery slot, so
+// they are scheduled for the slot current at `now` and keep trading until it ends. Prices became
+// signed after version 1; one too large for an i64 cannot be carried over and fails the migration.
+pub fn migrate_ledger_v1(ledger: LedgerV1, now: i64) -> Result<Ledger, ProgramError> {
+    let delivery_slot = delivery_slot_at(now);
+    // Version 1 trades carried no id; number them back from total_trades in chronological order
+    let head = ledger.history_head as usize;
+    let first_trade_id = ledger.total_trades.saturating_sub(ledger.transactions.len() as u64);
+    let history_len = ledger.transactions.len();
+    Ok(Ledger {
+        version: LEDGER_VERSION,
+        admin: ledger.admin,
+        pending_admin: Pubkey::default(),
+        approvers: [Pubkey::default(); MAX_APPROVERS],
+        approver_count: 0,
+        approval_threshold: 0,
+        paused: false,
+        grid_operator: Pubkey::default(),
+        oracle: Pubkey::default(),
+        trade_log: Pubkey::default(),
+        // The creation time of a version 1 ledger was never recorded
+        created_at: 0,
+        vault_bump: ledger.vault_bump,
+        quote_mint: ledger.quote_mint,
+        config: MarketConfig {
+            allow_self_trade: ledger.config.allow_self_trade,
+            market_mode: ledger.config.market_mode,
+            history_policy: ledger.config.history_policy,
+            order_storage: ledger.config.order_storage,
+            crank_reward_bps: 0,
+            fee_bps: 0,
+            zones: ZoneConfig::default(),
+            attestation_timeout: 0,
+            settlement: SettlementConfig::default(),
+            collateral_bps: 0,
+            kwh_per_rec: 0,
+            prefer_renewable: false,
+            price_tick: 0,
+            lot_size: 0,
+            min_order_size: 0,
+            max_order_size: 0,
+            max_deviation_bps: 0,
+            withdrawal_delay: 0,
+            withdrawal_threshold: 0,
+            withdrawal_limit: 0,
+            max_open_orders_per_participant: 0,
+            min_deposit: 0,
+            min_balance_to_post_demand: 0,
+            reputation: ReputationConfig::default(),
+            config_timelock: 0,
+            stale_demand_timeout: 0,
+            max_unflagged_priority: 0,
+            grid_fee_per_kwh: 0,
+            losses: LossConfig::default(),
+            tariff: TariffSchedule::default(),
+            imbalance: ImbalanceConfig::default(),
+            insurance_fee_bps: 0,
+            rewards: RewardRates::default(),
+            governance: GovernanceConfig::default(),
+        },
+        pending_config_change: None,
+        demand_response: None,
+        demand_response_count: 0,
+        capacity: LedgerCapacity {
+            max_participants: ledger.capacity.max_participants,
+            max_open_orders: ledger.capacity.max_open_orders,
+            max_transactions: ledger.capacity.max_transactions,
+        },
+        next_order_id: ledger.next_order_id,
+        last_clearing_price: signed_price(ledger.last_clearing_price)?,
+        reference_price: 0,
+        history_head: ledger.history_head,
+        total_trades: ledger.total_trades,
+        participant_count: ledger.participant_count,
+        open_order_accounts: ledger.open_order_accounts,
+        protocol_fees: 0,
+        insurance_pool: 0,
+        insurance_claims: 0,
+        insurance_drain: None,
+        rewards_pool: 0,
+        reward_epoch: 0,
+        next_proposal_id: 0,
+        proposal: None,
+        // Statistics count the fills matched from the migration on
+        stats: MarketStats::default(),
+        price_history: [PriceSample::default(); PRICE_HISTORY_LEN],
+        price_history_head: 0,
+        price_history_len: 0,
+        slot_prices: [SlotPrice::default(); SLOT_PRICE_HISTORY_LEN],
+        slot_price_head: 0,
+        standing_orders: Vec::new(),
+        productions: ledger.productions.into_iter().map(|p| Ok(EnergyProduction {
+            order_id: p.order_id,
+            producer_id: p.producer_id,
+            energy_amount: p.energy_amount,
+            price: signed_price(p.price)?,
+            created_at: p.created_at,
+            expires_at: p.expires_at,
+            delivery_slot,
+            zone: 0,
+            verified: true,
+            energy_source: EnergySource::Other,
+            min_fill: 0,
+            all_or_nothing: false,
+        })).collect::<Result<_, ProgramError>>()?,
+        demands: ledger.demands.into_iter().map(|d| Ok(EnergyDemand {
+            order_id: d.order_id,
+            consumer_id: d.consumer_id,
+            energy_amount: d.energy_amount,
+            price_limit: signed_price(d.price_limit)?,
+            created_at: d.created_at,
+            expires_at: d.expires_at,
+            delivery_slot,
+            zone: 0,
+            min_fill: 0,
+            all_or_nothing: false,
+            priority: 0,
+            // Version 1 escrowed the notional at the limit
+            escrow_per_kwh: d.price_limit,
+        })).collect::<Result<_, ProgramError>>()?,
+        transactions: ledger.transactions.into_iter().enumerate().map(|(index, t)| Ok(Transaction {
+            trade_id: first_trade_id + ((index + history_len - head) % history_len) as u64,
+            demand_order_id: t.demand_order_id,
+            production_order_id: t.production_order_id,
+            from: t.from,
+            to: t.to,
+            amount: t.amount,
+            price: signed_price(t.price)?,
+            timestamp: t.timestamp,
+            status: TradeStatus::Settled,
+            settlement_deadline: 0,
+            escrow: 0,
+            proceeds: 0,
+            energy_source: EnergySource::Other,
+            bilateral: false,
+            injected_amount: t.amount,
+            delivered_amount: t.amount,
+            netting: false,
+        })).collect::<Result<_, ProgramError>>()?,
+    })
+}
+
+fn signed_price(price: u64) -> Result<i64, ProgramError> {
+    i64::try_from(price).map_err(|_| ProgramError::InvalidAccountData)
+}
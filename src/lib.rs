@@ -1,316 +1,8003 @@
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
-    entrypoint,
-    entrypoint::ProgramResult,
+    entrypoint::{ProgramResult, MAX_PERMITTED_DATA_INCREASE},
     pubkey::Pubkey,
     msg,
     program_error::ProgramError,
+    program::{invoke, invoke_signed, set_return_data},
+    program_pack::Pack,
+    system_instruction,
+    system_program,
     clock::Clock,
+    rent::Rent,
     sysvar::Sysvar,
 };
-use borsh::{BorshDeserialize, BorshSerialize};
-use std::collections::HashMap;
+use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
 
 // Define the program ID
 solana_program::declare_id!("Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS");
 
-#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "client")]
+pub mod cpi;
+pub mod events;
+#[cfg(feature = "client")]
+pub mod idl;
+// The BorshSchema derive copies each enum variant's fields into structs it never reads
+#[allow(dead_code)]
+mod instruction;
+pub mod legacy;
+pub mod matching;
+#[cfg(feature = "serde")]
+pub mod serde_pubkey;
+
+pub use instruction::{ConfigUpdate, EnergyMarketInstruction};
+
+use events::emit;
+use matching::{crank_reward, match_taker_order, protocol_fee, run_matching, MatchRun};
+
+// Byte 0 of every program account except the vault says what the account holds, so an account
+// passed in the wrong position is rejected before it is read. A ledger keeps its layout version
+// there instead (1 or LEDGER_VERSION), so these tags stay clear of any version a ledger will reach.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AccountType {
+    Participant = 0xa1,
+    Order = 0xa2,
+    BilateralOffer = 0xa3,
+    TradeLog = 0xa4,
+    ForwardContract = 0xa5,
+}
+
+// Written to byte 0 of every account the program closes. The runtime only reclaims an emptied
+// account at the end of the transaction, so until then a later instruction could still read or
+// re-initialize it; the tag makes reads fail and keeps InitializeLedger from reusing it.
+pub const CLOSED_ACCOUNT_TAG: u8 = 0xff;
+
+impl AccountType {
+    pub fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0xa1 => Some(AccountType::Participant),
+            0xa2 => Some(AccountType::Order),
+            0xa3 => Some(AccountType::BilateralOffer),
+            0xa4 => Some(AccountType::TradeLog),
+            0xa5 => Some(AccountType::ForwardContract),
+            _ => None,
+        }
+    }
+}
+
+impl BorshSerialize for AccountType {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        (*self as u8).serialize(writer)
+    }
+}
+
+impl BorshDeserialize for AccountType {
+    fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        let tag = u8::deserialize_reader(reader)?;
+        AccountType::from_tag(tag).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, format!("unknown account type {}", tag))
+        })
+    }
+}
+
+// Serialized as its raw tag byte, so its schema is plain u8
+impl BorshSchema for AccountType {
+    fn declaration() -> borsh::schema::Declaration {
+        u8::declaration()
+    }
+
+    fn add_definitions_recursively(
+        _definitions: &mut borsh::maybestd::collections::HashMap<borsh::schema::Declaration, borsh::schema::Definition>,
+    ) {
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ParticipantType {
     Producer,
     Consumer,
     Prosumer,
+    // A battery: buys energy to charge and sells it back to discharge
+    Storage,
+    // Posts demands on behalf of its members, funded from their balances; it trades nothing itself
+    Aggregator,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Participant {
+    pub account_type: AccountType,
+    pub bump: u8,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub id: Pubkey,
+    pub participant_type: ParticipantType,
+    pub wallet_balance: u64,
+    pub reserved_balance: u64,
+    // Orders this participant still has in the book, in either storage mode, by side
+    pub open_demands: u32,
+    pub open_productions: u32,
+    // Set by the admin: a frozen participant cannot trade or withdraw and its orders never match
+    pub frozen: bool,
+    // Grid zone chosen at registration and stamped onto every order the participant places
+    pub zone: u8,
+    // Set by the grid operator's Curtail: no new production can be reported before this time
+    pub curtailed_until: i64,
+    // Most energy a producer may offer for any one delivery slot; set at registration and by the admin
+    pub registered_capacity: u64,
+    // Backs the participant's production offers and is slashed when a trade defaults on delivery
+    pub collateral_balance: u64,
+    // Renewable energy certificates held, renewable energy bought towards the next one, and
+    // certificates retired so far
+    pub rec_balance: u64,
+    pub rec_accrual: u64,
+    pub retired_recs: u64,
+    // Storage participants only: energy held, the most it can hold, and the share of charged
+    // energy that survives round-trip losses, in basis points
+    pub stored_energy: u64,
+    pub storage_capacity: u64,
+    pub storage_efficiency_bps: u16,
+    // Funds taken out of wallet_balance by RequestWithdrawal, claimable from the given time
+    pub pending_withdrawal: u64,
+    pub withdrawal_claimable_after: i64,
+    // Withdrawn since window_start; the window restarts with the first withdrawal a day after it began
+    pub withdrawn_in_window: u64,
+    pub window_start: i64,
+    // Set by the admin to override the ledger's withdrawal_limit; Some(0) lifts the cap
+    pub withdrawal_limit: Option<u64>,
+    // The last RECENT_NONCES client order nonces seen, oldest overwritten first at recent_nonce_head
+    pub recent_nonces: [u64; RECENT_NONCES],
+    pub recent_nonce_head: u8,
+    pub metadata: ParticipantMetadata,
+    // Outcomes of the trades this participant sold into, and the score derived from them
+    pub trades_completed: u64,
+    pub trades_defaulted: u64,
+    pub reputation: u32,
+    // A hot key allowed to post and cancel this participant's orders, but never to move its funds
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey::option"))]
+    pub delegate: Option<Pubkey>,
+    // Granted by the admin to hospitals, pumps and other critical loads: their demands may claim
+    // any priority, not just up to the ledger's max_unflagged_priority
+    pub critical_load: bool,
+    // The demand-response event this participant last volunteered for, and the reduction it
+    // committed to there that is still unpaid
+    pub demand_response_id: u64,
+    pub committed_reduction: u64,
+    // Forward contracts this participant is party to that have not settled; their margin is held
+    // in reserved_balance
+    pub open_forwards: u32,
+    // The aggregator this participant consented to join
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey::option"))]
+    pub aggregator: Option<Pubkey>,
+    // On an aggregator: the first member_count entries of members, and the aggregated demand they
+    // fund while one is unsettled, with the energy it has bought so far. pooled_balance is what is
+    // left of the members' contributions in the aggregator's balance; the rest is its own.
+    pub members: [AggregatorMember; MAX_AGGREGATOR_MEMBERS],
+    pub member_count: u8,
+    pub aggregated_order_id: Option<u64>,
+    pub aggregated_filled: u64,
+    pub pooled_balance: u64,
+    // Opted into by a prosumer: every match run first offsets its demands against its own offers
+    // for the same delivery slot
+    pub net_metering: bool,
+    // With imbalance settlement enabled, the first position_count entries of positions are the
+    // delivery slots this participant traded or was metered in and that SettleImbalances has not
+    // settled yet
+    pub positions: [SlotPosition; MAX_OPEN_POSITIONS],
+    pub position_count: u8,
+    // What defaulted trades left uncompensated after slashing, payable from the insurance pool
+    pub insurance_claim: u64,
+    // Locked by StakeForPriority until stake_unlocks_at: it ranks the producer's offers ahead of
+    // others at the same price and is neither withdrawable nor collateral
+    pub staked_balance: u64,
+    pub stake_unlocks_at: i64,
+    // Earned on every order book fill at the ledger's reward rates and paid out of the rewards pool
+    // by ClaimRewards, one unit of balance per point; unclaimed points are forfeited on unregistering
+    pub reward_points: u64,
+    // The last governance proposal this participant voted on; proposals run one at a time with
+    // increasing ids, so this is enough to refuse a second vote
+    pub last_voted_proposal: Option<u64>,
+}
+
+pub const MAX_OPEN_POSITIONS: usize = 8;
+
+// Energy matched for one delivery slot, sold and bought, and what the oracle's meter read for the
+// slot once it reported
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SlotPosition {
+    pub delivery_slot: u32,
+    pub sold: u64,
+    pub bought: u64,
+    pub reported: bool,
+    pub metered_injection: u64,
+    pub metered_consumption: u64,
+}
+
+pub const MAX_AGGREGATOR_MEMBERS: usize = 8;
+
+// contribution is what the member put into the aggregator's open aggregated demand
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AggregatorMember {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub wallet: Pubkey,
+    pub weight: u32,
+    pub contribution: u64,
+}
+
+pub const RECENT_NONCES: usize = 16;
+
+// Display details for UIs, fixed-size so participant accounts keep a fixed size. Text fields hold
+// UTF-8 padded with zero bytes; coordinates are in microdegrees. Only the layout is checked
+// on-chain, what the text says is up to clients.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParticipantMetadata {
+    pub name: [u8; 32],
+    pub meter_id: [u8; 16],
+    pub latitude: i32,
+    pub longitude: i32,
+}
+
+impl ParticipantMetadata {
+    // Text longer than a field is cut at the last character boundary that fits
+    pub fn new(name: &str, meter_id: &str, latitude: i32, longitude: i32) -> Self {
+        ParticipantMetadata { name: text_field(name), meter_id: text_field(meter_id), latitude, longitude }
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        field_text(&self.name)
+    }
+
+    pub fn meter_id(&self) -> Option<&str> {
+        field_text(&self.meter_id)
+    }
+}
+
+fn text_field<const N: usize>(text: &str) -> [u8; N] {
+    let mut len = text.len().min(N);
+    while !text.is_char_boundary(len) {
+        len -= 1;
+    }
+    let mut field = [0; N];
+    field[..len].copy_from_slice(&text.as_bytes()[..len]);
+    field
+}
+
+// None unless the field is valid UTF-8 followed only by zero padding
+fn field_text(field: &[u8]) -> Option<&str> {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    if field[len..].iter().any(|&b| b != 0) {
+        return None;
+    }
+    std::str::from_utf8(&field[..len]).ok()
+}
+
+fn assert_valid_metadata(metadata: &ParticipantMetadata) -> ProgramResult {
+    if metadata.name().is_none()
+        || metadata.meter_id().is_none()
+        || !(-90_000_000..=90_000_000).contains(&metadata.latitude)
+        || !(-180_000_000..=180_000_000).contains(&metadata.longitude)
+    {
+        return Err(EnergyMarketError::InvalidMetadata.into());
+    }
+    Ok(())
+}
+
+impl Participant {
+    pub fn open_orders(&self) -> u32 {
+        self.open_demands.saturating_add(self.open_productions)
+    }
+
+    pub fn position(&self, delivery_slot: u32) -> Option<&SlotPosition> {
+        self.positions[..self.position_count as usize].iter().find(|p| p.delivery_slot == delivery_slot)
+    }
+
+    // False when the participant holds no position for the slot and has no room to open one
+    pub fn can_hold_position(&self, delivery_slot: u32) -> bool {
+        self.position(delivery_slot).is_some() || (self.position_count as usize) < MAX_OPEN_POSITIONS
+    }
+
+    // The participant's position for the slot, opened empty if it has none
+    pub fn position_mut(&mut self, delivery_slot: u32) -> Result<&mut SlotPosition, ProgramError> {
+        let count = self.position_count as usize;
+        let index = match self.positions[..count].iter().position(|p| p.delivery_slot == delivery_slot) {
+            Some(index) => index,
+            None if count < MAX_OPEN_POSITIONS => {
+                self.positions[count] = SlotPosition { delivery_slot, ..SlotPosition::default() };
+                self.position_count += 1;
+                count
+            }
+            None => return Err(EnergyMarketError::TooManyOpenPositions.into()),
+        };
+        Ok(&mut self.positions[index])
+    }
+}
+
+// Adds one side of a fill to the participant's position when imbalance settlement is enabled
+pub fn record_position(config: &ImbalanceConfig, participant: &mut Participant, delivery_slot: u32, sold: u64, bought: u64) -> ProgramResult {
+    if !config.enabled() {
+        return Ok(());
+    }
+    let position = participant.position_mut(delivery_slot)?;
+    position.sold = position.sold.checked_add(sold).ok_or(ProgramError::ArithmeticOverflow)?;
+    position.bought = position.bought.checked_add(bought).ok_or(ProgramError::ArithmeticOverflow)?;
+    Ok(())
+}
+
+// Credits one side of a fill with its reward points. Points are not balance until claimed, so
+// they saturate rather than fail the fill.
+pub fn accrue_reward_points(participant: &mut Participant, kwh: u64, points_per_kwh: u64) {
+    participant.reward_points = participant.reward_points.saturating_add(kwh.saturating_mul(points_per_kwh));
+}
+
+// Metered minus matched net injection of a reported position: positive when the participant
+// delivered more or consumed less than it traded, negative for a shortfall
+pub fn position_imbalance(position: &SlotPosition) -> i128 {
+    let metered = position.metered_injection as i128 - position.metered_consumption as i128;
+    let matched = position.sold as i128 - position.bought as i128;
+    metered - matched
+}
+
+// Reputation is the share of a seller's trades that were delivered, out of REPUTATION_SCALE,
+// smoothed by one delivered and one defaulted trade so a newcomer starts halfway and a single
+// outcome never pins the score to either bound
+pub const REPUTATION_SCALE: u32 = 10_000;
+
+pub fn reputation_score(trades_completed: u64, trades_defaulted: u64) -> u32 {
+    let completed = trades_completed as u128 + 1;
+    let total = trades_completed as u128 + trades_defaulted as u128 + 2;
+    (completed * REPUTATION_SCALE as u128 / total) as u32
+}
+
+// Counts a settled trade against its seller and emits the new score when it moved. Self-trades
+// never count, so a prosumer cannot trade with itself to build a record.
+pub fn record_trade_outcome(seller: &mut Participant, delivered: bool) -> ProgramResult {
+    if delivered {
+        seller.trades_completed = seller.trades_completed.saturating_add(1);
+    } else {
+        seller.trades_defaulted = seller.trades_defaulted.saturating_add(1);
+    }
+    let reputation = reputation_score(seller.trades_completed, seller.trades_defaulted);
+    if reputation != seller.reputation {
+        seller.reputation = reputation;
+        emit(&events::ReputationChanged {
+            participant: seller.id,
+            reputation,
+            trades_completed: seller.trades_completed,
+            trades_defaulted: seller.trades_defaulted,
+        })?;
+    }
+    Ok(())
+}
+
+// True when reputation rules keep the offer out of matching: large offers need a seller at or
+// above min_reputation
+pub fn lacks_reputation(config: &ReputationConfig, producer: &Participant, energy_amount: u64) -> bool {
+    config.large_order_size > 0 && energy_amount >= config.large_order_size && producer.reputation < config.min_reputation
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EnergyProduction {
+    pub order_id: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub producer_id: Pubkey,
+    pub energy_amount: u64,
+    // Negative during renewable surplus: the producer pays to have its energy taken
+    pub price: i64,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub delivery_slot: u32,
+    pub zone: u8,
+    // Only attested offers match; always true on ledgers without an oracle
+    pub verified: bool,
+    pub energy_source: EnergySource,
+    // See EnergyDemand::min_fill
+    pub min_fill: u64,
+    pub all_or_nothing: bool,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EnergySource {
+    Solar,
+    Wind,
+    Hydro,
+    Fossil,
+    #[default]
+    Other,
+}
+
+impl EnergySource {
+    pub fn is_renewable(self) -> bool {
+        matches!(self, EnergySource::Solar | EnergySource::Wind | EnergySource::Hydro)
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EnergyDemand {
+    pub order_id: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub consumer_id: Pubkey,
+    pub energy_amount: u64,
+    // A negative limit only accepts offers that pay the consumer at least that much
+    pub price_limit: i64,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub delivery_slot: u32,
+    pub zone: u8,
+    // No fill may be smaller than min_fill unless it completes the order; an all_or_nothing order
+    // only fills its whole remaining amount against a single counter-order
+    pub min_fill: u64,
+    pub all_or_nothing: bool,
+    // Higher priorities fill before lower ones whatever their price; 0 is a normal load
+    pub priority: u8,
+    // Reserved per unit of the remaining energy_amount: the buyer's worst case for one unit at the
+    // rates in force when the demand was posted
+    pub escrow_per_kwh: u64,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Transaction {
+    // Sequence number of the trade, counting every trade the ledger has ever matched
+    pub trade_id: u64,
+    pub demand_order_id: u64,
+    pub production_order_id: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub from: Pubkey,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub to: Pubkey,
+    // The energy paid for, which is delivered_amount unless the consumer bears transmission losses
+    pub amount: u64,
+    pub price: i64,
+    pub timestamp: i64,
+    pub status: TradeStatus,
+    // With deferred settlement: the deadline for ConfirmDelivery, the consumer funds held in its
+    // reserved_balance for the trade, and the part of them due to the producer on delivery
+    pub settlement_deadline: i64,
+    pub escrow: u64,
+    pub proceeds: u64,
+    pub energy_source: EnergySource,
+    // Recorded by AcceptBilateralOffer rather than matched from the book; both order ids are the offer id
+    pub bilateral: bool,
+    // Taken from the offer and received against the demand; the two differ by the transmission loss
+    pub injected_amount: u64,
+    pub delivered_amount: u64,
+    // A net-metering prosumer's own demand offset against its own offer at no cost, see
+    // Participant::net_metering; buyer and seller are the same and the price is zero
+    pub netting: bool,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TradeStatus {
+    // Matched under deferred settlement; the consumer's funds are escrowed until delivery is settled
+    Matched,
+    // Delivery was confirmed and the producer paid out of the escrow
+    Delivered,
+    // Paid out at match time on ledgers without deferred settlement
+    Settled,
+    // Delivery went unconfirmed past the deadline and the consumer was refunded minus the penalty
+    Defaulted,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MarketMode {
+    // MatchTransactions: every fill pays the producer's ask
+    #[default]
+    PayAsBid,
+    // RunAuction: every fill in a run clears at one uniform price
+    UniformPrice,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HistoryPolicy {
+    // A full trade history overwrites its oldest entries so matching never stalls
+    #[default]
+    DropOldest,
+    // A full trade history fails the match run with HistoryFull
+    Reject,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OrderStorage {
+    // Open orders live in the ledger's productions and demands vecs
+    #[default]
+    Ledger,
+    // Every open order lives in its own OrderAccount PDA and the ledger only counts them
+    Accounts,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MarketConfig {
+    pub allow_self_trade: bool,
+    pub market_mode: MarketMode,
+    pub history_policy: HistoryPolicy,
+    pub order_storage: OrderStorage,
+    // Share of every fill's notional paid to whoever cranks matching, in basis points; 0 disables it
+    pub crank_reward_bps: u16,
+    // Protocol fee withheld from the producer's proceeds on every fill, in basis points
+    pub fee_bps: u16,
+    pub zones: ZoneConfig,
+    // With an oracle, offers still unattested this many seconds after creation are purged; 0 keeps them
+    pub attestation_timeout: i64,
+    pub settlement: SettlementConfig,
+    // Collateral a producer must hold against the notional of its open offers, in basis points
+    pub collateral_bps: u16,
+    // Renewable energy a consumer buys per certificate credited; 0 disables RECs
+    pub kwh_per_rec: u64,
+    // Among offers at the same price, renewable ones fill first
+    pub prefer_renewable: bool,
+    // New orders must price in multiples of price_tick and size in multiples of lot_size; 0 disables either check
+    pub price_tick: u64,
+    pub lot_size: u64,
+    // Bounds on the energy amount of new orders; 0 disables either bound
+    pub min_order_size: u64,
+    pub max_order_size: u64,
+    // Trades priced further than this from the ledger's reference_price are skipped, in basis
+    // points of the reference; 0 disables the circuit breaker
+    pub max_deviation_bps: u16,
+    // With a non-zero delay, withdrawals above withdrawal_threshold must be requested and can only
+    // be claimed once the delay has passed, leaving the admin that window to veto them
+    pub withdrawal_delay: i64,
+    pub withdrawal_threshold: u64,
+    // Most a participant may withdraw per rolling day, unless the admin overrides it; 0 is unlimited
+    pub withdrawal_limit: u64,
+    // Most orders one participant may have open at once, across both sides; 0 is unlimited
+    pub max_open_orders_per_participant: u32,
+    // Smallest accepted deposit, and the free balance a consumer needs to post a demand; 0 disables either
+    pub min_deposit: u64,
+    pub min_balance_to_post_demand: u64,
+    pub reputation: ReputationConfig,
+    // With a non-zero timelock, SetFee and UpdateConfig are disabled and those parameters only
+    // change through a proposal that takes effect at least this many seconds after it was made
+    pub config_timelock: i64,
+    // Demands priced below every open offer of their book partition are pruned by the crank once
+    // older than this many seconds; 0 keeps them
+    pub stale_demand_timeout: i64,
+    // Highest demand priority a participant without the critical_load flag may claim
+    pub max_unflagged_priority: u8,
+    // Charged to the buyer per kWh of every matched fill and credited to the grid operator's
+    // participant for the use of the distribution network; 0 disables it
+    pub grid_fee_per_kwh: u64,
+    pub losses: LossConfig,
+    pub tariff: TariffSchedule,
+    pub imbalance: ImbalanceConfig,
+    // Share of every trading fee paid into the insurance pool instead of protocol_fees, in basis points
+    pub insurance_fee_bps: u16,
+    pub rewards: RewardRates,
+    pub governance: GovernanceConfig,
+}
+
+// Set by SetGovernanceConfig. Participants holding at least proposal_threshold of free balance may
+// put a governable ConfigUpdate to a vote, weighted by each voter's wallet_balance when it votes.
+// A proposal passes once votes of at least quorum were cast and at least majority_bps of them,
+// and more than half, were in favour. A majority_bps of 0 disables governance.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GovernanceConfig {
+    pub proposal_threshold: u64,
+    pub quorum: u64,
+    pub majority_bps: u16,
+}
+
+impl GovernanceConfig {
+    pub fn enabled(&self) -> bool {
+        self.majority_bps > 0
+    }
+}
+
+// The open governance proposal and the vote weight cast on each side so far
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Proposal {
+    pub proposal_id: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub proposer: Pubkey,
+    pub update: ConfigUpdate,
+    pub voting_deadline: i64,
+    pub votes_for: u64,
+    pub votes_against: u64,
+}
+
+// Whether the votes cast on `proposal` carry it under `config`
+pub fn proposal_passes(config: &GovernanceConfig, proposal: &Proposal) -> bool {
+    let cast = proposal.votes_for as u128 + proposal.votes_against as u128;
+    cast > 0
+        && cast >= config.quorum as u128
+        && proposal.votes_for > proposal.votes_against
+        && proposal.votes_for as u128 * 10_000 >= cast * config.majority_bps as u128
+}
+
+// Set by SetRewardRates. Reward points earned per kWh of every order book fill by the side whose
+// order was resting in the book, the maker, and by the side whose order arrived later, the taker.
+// Self-trades, netting and bilateral trades earn nothing.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RewardRates {
+    pub maker_points_per_kwh: u64,
+    pub taker_points_per_kwh: u64,
+}
+
+// Set by SetImbalanceConfig. Each delivery slot a participant trades in is tracked as a position,
+// which SettleImbalances squares against the oracle's meter readings: a surplus is credited
+// surplus_price per kWh out of the protocol fee pool and a shortfall charged shortfall_price per
+// kWh into it. A position the oracle has not reported on within actuals_timeout seconds of the
+// slot's end settles with no imbalance; 0 waits for the oracle indefinitely. A zero shortfall_price
+// disables tracking.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ImbalanceConfig {
+    pub shortfall_price: u64,
+    pub surplus_price: u64,
+    pub actuals_timeout: i64,
+}
+
+impl ImbalanceConfig {
+    pub fn enabled(&self) -> bool {
+        self.shortfall_price > 0
+    }
+}
+
+pub const HOURS_PER_DAY: usize = 24;
+
+// Time-of-use price bounds, set by SetTariffSchedule. An order's hour of the day (UTC) comes from
+// its delivery slot; offers for that hour may not ask below base_floor_price and demands may not
+// bid above base_ceiling_price, each scaled by the hour's multiplier_bps and rounded down. A zero
+// base price disables its bound.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TariffSchedule {
+    pub base_floor_price: i64,
+    pub base_ceiling_price: i64,
+    pub multiplier_bps: [u16; HOURS_PER_DAY],
+}
+
+// Share of the energy injected that transmission loses on every matched fill, in basis points;
+// inter_zone_loss_bps replaces loss_factor_bps on cross-zone fills when non-zero. The offer gives
+// up the energy injected and the demand receives what is left, the loss rounding up, while the
+// fill is paid for the energy delivered or, when the consumer bears the losses, injected.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LossConfig {
+    pub loss_factor_bps: u16,
+    pub inter_zone_loss_bps: u16,
+    pub bearer: LossBearer,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LossBearer {
+    // Paid for the energy delivered, so the producer goes unpaid for what was lost
+    #[default]
+    Producer,
+    // Pays for the energy injected, receiving less than it paid for
+    Consumer,
+}
+
+// Declared by the admin or grid operator: consumers commit before `start` to cut consumption by
+// some kWh between `start` and `end`, and are paid incentive_price per kWh out of the protocol fee
+// pool once the window closed. Commitments the pool cannot fund are rejected rather than scaled:
+// each one's incentive moves from protocol_fees into reserved_incentive when it is made, so
+// collecting fees later can never leave a volunteer unpaid.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DemandResponse {
+    pub event_id: u64,
+    pub start: i64,
+    pub end: i64,
+    pub target_reduction_kwh: u64,
+    pub incentive_price: u64,
+    pub committed_kwh: u64,
+    // Volunteers not paid yet, and the incentive still set aside for them
+    pub volunteers: u32,
+    pub reserved_incentive: u64,
+}
+
+// A config update proposed by the admin; anyone may apply it once effective_at has passed
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PendingConfigChange {
+    pub update: ConfigUpdate,
+    pub effective_at: i64,
+}
+
+// Offers of at least large_order_size only match when their producer's reputation reaches
+// min_reputation, out of REPUTATION_SCALE; a large_order_size of 0 disables the rule. With
+// prefer_reputable, offers at the same price fill in order of their producers' reputation.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReputationConfig {
+    pub prefer_reputable: bool,
+    pub min_reputation: u32,
+    pub large_order_size: u64,
+}
+
+// Participants register into one of zone_count grid zones. Orders only match within their zone
+// unless allow_inter_zone is set, in which case every cross-zone fill also charges the buyer
+// wheeling_fee per unit of energy, paid into the protocol fee pool.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ZoneConfig {
+    pub zone_count: u8,
+    pub allow_inter_zone: bool,
+    pub wheeling_fee: u64,
+}
+
+impl Default for ZoneConfig {
+    fn default() -> Self {
+        ZoneConfig { zone_count: 1, allow_inter_zone: false, wheeling_fee: 0 }
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DeliveryConfirmer {
+    #[default]
+    Consumer,
+    Oracle,
+}
+
+// With deferred set, matching only escrows the consumer's payment. The confirmer then has until
+// delivery_timeout seconds after the end of the trade's delivery slot to confirm delivery and pay
+// the producer; after that the trade defaults and the consumer is refunded minus
+// default_penalty_bps of the escrow.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SettlementConfig {
+    pub deferred: bool,
+    pub confirmer: DeliveryConfirmer,
+    pub delivery_timeout: i64,
+    pub default_penalty_bps: u16,
+}
+
+// Start of a TradeLog account: a ring of `capacity` fixed-size slots of TRANSACTION_SIZE bytes
+// follows, each holding one borsh-serialized Transaction. Readers get chronological order from
+// the slots at cursor.. followed by ..cursor once the log has wrapped.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TradeLogHeader {
+    pub account_type: AccountType,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub ledger: Pubkey,
+    pub history_policy: HistoryPolicy,
+    pub capacity: u32,
+    // Next slot written; once len reaches capacity it also holds the oldest entry
+    pub cursor: u32,
+    pub len: u32,
+    // Counts past the capacity so indexers can detect entries overwritten before they read them
+    pub total_appended: u64,
+}
+
+// Upper bounds on every collection in the ledger, fixed at initialization
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LedgerCapacity {
+    pub max_participants: u32,
+    pub max_open_orders: u32,
+    pub max_transactions: u32,
+}
+
+// Layout version stored in the first byte of the ledger account. 0 means uninitialized; older
+// layouts are kept in the legacy module and upgraded with MigrateLedger.
+pub const LEDGER_VERSION: u8 = 2;
+
+pub type Ledger = LedgerV2;
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LedgerV2 {
+    pub version: u8,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub admin: Pubkey,
+    // Set by SetPendingAdmin and promoted by AcceptAdmin; Pubkey::default() when no handover is pending
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub pending_admin: Pubkey,
+    // With approvers set, privileged instructions need approval_threshold of them to sign instead
+    // of the admin alone; the first approver_count entries are live, the rest Pubkey::default()
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey::array"))]
+    pub approvers: [Pubkey; MAX_APPROVERS],
+    pub approver_count: u8,
+    pub approval_threshold: u8,
+    // While paused no orders can be posted or matched; deposits, withdrawals and cancels still work
+    pub paused: bool,
+    // May curtail producers; Pubkey::default() until the admin appoints one with SetGridOperator
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub grid_operator: Pubkey,
+    // Smart-meter oracle that must attest every production offer before it can match;
+    // Pubkey::default() accepts offers unattested
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub oracle: Pubkey,
+    // Set by InitializeTradeLog; Pubkey::default() keeps every trade in the embedded history
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub trade_log: Pubkey,
+    pub created_at: i64,
+    pub vault_bump: u8,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub quote_mint: Pubkey,
+    pub config: MarketConfig,
+    // At most one proposed config change waits for its timelock at a time
+    pub pending_config_change: Option<PendingConfigChange>,
+    // The latest demand-response event, cleared once every volunteer was paid; events are
+    // numbered from 1 by demand_response_count
+    pub demand_response: Option<DemandResponse>,
+    pub demand_response_count: u64,
+    pub capacity: LedgerCapacity,
+    pub next_order_id: u64,
+    pub last_clearing_price: i64,
+    // Volume-weighted average price of the last match run that traded; 0 until the first one
+    pub reference_price: i64,
+    pub history_head: u32,
+    pub total_trades: u64,
+    pub participant_count: u32,
+    pub open_order_accounts: u32,
+    // Fees collected from fills and not yet swept by CollectFees; backed by vault funds like any balance
+    pub protocol_fees: u64,
+    // Funded by insurance_fee_bps of the trading fees, the pool pays out the insurance_claims
+    // consumers hold over defaulted trades; the admin tops it up from its participant and drains
+    // it only through insurance_drain, which waits out the config timelock
+    pub insurance_pool: u64,
+    pub insurance_claims: u64,
+    pub insurance_drain: Option<InsuranceDrain>,
+    // Pre-funded by the admin from its participant to pay out claimed reward points. Each change of
+    // the reward rates starts a new reward_epoch; points already accrued keep the rates they were
+    // earned at.
+    pub rewards_pool: u64,
+    pub reward_epoch: u64,
+    pub next_proposal_id: u64,
+    pub proposal: Option<Proposal>,
+    pub stats: MarketStats,
+    // Ring buffer of the VWAP of recent match runs, oldest overwritten first; price_history_head
+    // is the next slot written and price_history_len how many slots hold a sample
+    pub price_history: [PriceSample; PRICE_HISTORY_LEN],
+    pub price_history_head: u8,
+    pub price_history_len: u8,
+    // Ring buffer of the VWAP each delivery slot traded at, which forward contracts settle against;
+    // a slot's entry is updated in place while it trades and slot_price_head is the next one replaced
+    pub slot_prices: [SlotPrice; SLOT_PRICE_HISTORY_LEN],
+    pub slot_price_head: u8,
+    pub standing_orders: Vec<StandingOrder>,
+    pub productions: Vec<EnergyProduction>,
+    pub demands: Vec<EnergyDemand>,
+    pub transactions: Vec<Transaction>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InsuranceDrain {
+    pub amount: u64,
+    pub effective_at: i64,
+}
+
+// Running totals over every fill the ledger has matched, returned by GetMarketStats.
+// total_notional counts what changed hands regardless of the sign of the price.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MarketStats {
+    pub total_volume_kwh: u128,
+    pub total_notional: u128,
+    pub trade_count: u64,
+    pub last_trade_price: i64,
+    pub last_trade_timestamp: i64,
+    pub vwap_numerator: i128,
+    pub vwap_denominator: u128,
+}
+
+impl MarketStats {
+    pub fn record(&mut self, trade: &Transaction) -> ProgramResult {
+        let amount = trade.amount as u128;
+        self.total_volume_kwh = self.total_volume_kwh.checked_add(amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        self.total_notional = self.total_notional.checked_add(amount * trade.price.unsigned_abs() as u128)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        self.trade_count = self.trade_count.checked_add(1).ok_or(ProgramError::ArithmeticOverflow)?;
+        self.last_trade_price = trade.price;
+        self.last_trade_timestamp = trade.timestamp;
+        self.vwap_numerator = self.vwap_numerator.checked_add(trade.amount as i128 * trade.price as i128)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        self.vwap_denominator = self.vwap_denominator.checked_add(amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    // None before the first fill
+    pub fn vwap(&self) -> Option<i64> {
+        if self.vwap_denominator == 0 {
+            return None;
+        }
+        i64::try_from(self.vwap_numerator / i128::try_from(self.vwap_denominator).ok()?).ok()
+    }
+}
+
+// Returned by SimulateMatch. The price bounds are 0 when nothing would trade; caller_fills lists
+// the caller's side of each fill, up to MAX_SIMULATED_FILLS so the summary fits in return data.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MatchSimulation {
+    pub trade_count: u32,
+    pub total_volume: u64,
+    pub lowest_price: i64,
+    pub highest_price: i64,
+    pub caller_fills: Vec<SimulatedFill>,
+    pub caller_fills_truncated: bool,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SimulatedFill {
+    pub order_id: u64,
+    pub side: OrderSide,
+    pub amount: u64,
+    pub price: i64,
+}
+
+pub const MAX_SIMULATED_FILLS: usize = 32;
+
+// Returned by GetBalance
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BalanceInfo {
+    pub wallet_balance: u64,
+    pub reserved_balance: u64,
+    pub collateral_balance: u64,
+    pub pending_withdrawal: u64,
+}
+
+// Returned by GetOpenOrders. orders holds up to MAX_QUERIED_ORDERS of the participant's orders, so
+// the response fits in return data; open_orders is how many it has in total.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OpenOrders {
+    pub open_orders: u32,
+    pub orders: Vec<OpenOrder>,
 }
 
-#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
-pub struct Participant {
-    pub id: Pubkey,
-    pub participant_type: ParticipantType,
-    pub wallet_balance: u64,
-}
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OpenOrder {
+    pub order_id: u64,
+    pub side: OrderSide,
+    pub energy_amount: u64,
+    pub price: i64,
+    pub expires_at: i64,
+    pub delivery_slot: u32,
+}
+
+pub const MAX_QUERIED_ORDERS: usize = 24;
+
+pub const PRICE_HISTORY_LEN: usize = 24;
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PriceSample {
+    pub timestamp: i64,
+    pub price: i64,
+}
+
+pub const SLOT_PRICE_HISTORY_LEN: usize = 32;
+
+// An entry for delivery slot 0, which no forward contract can be for, is an empty one. price is
+// the VWAP of every trade the slot has had, kept from the running notional and volume.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SlotPrice {
+    pub delivery_slot: u32,
+    pub price: i64,
+    pub notional: i128,
+    pub volume: u64,
+}
+
+// How long a new order may rest in the book
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TimeInForce {
+    // Rests until filled, cancelled or expired
+    #[default]
+    GoodTilCancelled,
+    // Fills what it can against the resting book when posted; the remainder is discarded
+    ImmediateOrCancel,
+    // Fills in full when posted or fails the instruction with OrderNotFilled
+    FillOrKill,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OrderSide {
+    Production,
+    Demand,
+}
+
+// An open order on a ledger using OrderStorage::Accounts. `price` is the ask of a production
+// and the limit of a demand; `energy_amount` is what is still unfilled.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OrderAccount {
+    pub account_type: AccountType,
+    pub bump: u8,
+    pub side: OrderSide,
+    pub order_id: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub owner: Pubkey,
+    pub energy_amount: u64,
+    pub price: i64,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub delivery_slot: u32,
+    pub zone: u8,
+    // See EnergyProduction::verified; demand accounts are always verified
+    pub verified: bool,
+    // Source of a production; EnergySource::Other on demands
+    pub energy_source: EnergySource,
+    pub min_fill: u64,
+    pub all_or_nothing: bool,
+    // See EnergyDemand::priority; 0 on productions
+    pub priority: u8,
+    // See EnergyDemand::escrow_per_kwh; 0 on productions
+    pub escrow_per_kwh: u64,
+}
+
+// A trade negotiated off-chain, waiting for `counterparty` to accept it. `side` is the maker's:
+// Demand when the maker buys. `escrow` is what the maker reserved towards the notional it owes,
+// which is the buyer at a positive price and the seller at a negative one.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BilateralOffer {
+    pub account_type: AccountType,
+    pub bump: u8,
+    pub offer_id: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub maker: Pubkey,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub counterparty: Pubkey,
+    pub side: OrderSide,
+    pub energy_amount: u64,
+    pub price: i64,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub escrow: u64,
+}
+
+// Posts a regular order for the current delivery slot every interval_secs, remaining_activations
+// more times. Nothing is reserved until an activation posts the order, which then needs the escrow
+// or offer limits any order would.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StandingOrder {
+    pub standing_order_id: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub owner: Pubkey,
+    pub side: OrderSide,
+    pub energy_amount: u64,
+    // The demand's price limit or the offer's asking price
+    pub price: i64,
+    pub interval_secs: i64,
+    pub next_activation: i64,
+    pub remaining_activations: u32,
+}
+
+pub const MAX_STANDING_ORDERS: usize = 32;
+pub const MAX_STANDING_ORDERS_PER_PARTICIPANT: usize = 4;
+
+// A cash-settled agreement to trade energy_amount in delivery_slot at price. `side` is the maker's:
+// Demand when the maker buys, so gains as the slot's spot price rises above the contract price.
+// An open contract has no counterparty and any participant may take it; taker is unset until then.
+// Both sides hold `margin` in reserved_balance until the contract settles.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ForwardContract {
+    pub account_type: AccountType,
+    pub bump: u8,
+    pub contract_id: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub maker: Pubkey,
+    pub side: OrderSide,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey::option"))]
+    pub counterparty: Option<Pubkey>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey::option"))]
+    pub taker: Option<Pubkey>,
+    pub energy_amount: u64,
+    pub price: i64,
+    pub delivery_slot: u32,
+    pub margin_bps: u16,
+    pub margin: u64,
+    pub created_at: i64,
+}
+
+// Space formula for the ledger account, so clients can pre-compute the allocation:
+//   LEDGER_HEADER_SIZE + max_open_orders * ORDER_SIZE + max_transactions * TRANSACTION_SIZE
+// Every Vec costs a 4-byte length prefix, which is folded into the header size. ORDER_SIZE is the
+// size of the larger order layout, EnergyDemand.
+// Participants live in their own PDAs of PARTICIPANT_SIZE bytes each.
+pub const MAX_APPROVERS: usize = 5;
+pub const ZONE_CONFIG_SIZE: usize = 1 + 1 + 8;
+pub const SETTLEMENT_CONFIG_SIZE: usize = 1 + 1 + 8 + 2;
+pub const MARKET_CONFIG_SIZE: usize = 1 + 1 + 1 + 1 + 2 + 2 + ZONE_CONFIG_SIZE + 8 + SETTLEMENT_CONFIG_SIZE + 2 + 8 + 1 + 8 + 8 + 8 + 8 + 2 + 8 + 8 + 8 + 4 + 8 + 8
+    + REPUTATION_CONFIG_SIZE + 8 + 8 + 1 + 8 + LOSS_CONFIG_SIZE + TARIFF_SCHEDULE_SIZE + IMBALANCE_CONFIG_SIZE + 2 + REWARD_RATES_SIZE
+    + GOVERNANCE_CONFIG_SIZE;
+pub const REPUTATION_CONFIG_SIZE: usize = 1 + 4 + 8;
+pub const LOSS_CONFIG_SIZE: usize = 2 + 2 + 1;
+pub const TARIFF_SCHEDULE_SIZE: usize = 8 + 8 + HOURS_PER_DAY * 2;
+pub const IMBALANCE_CONFIG_SIZE: usize = 8 + 8 + 8;
+pub const REWARD_RATES_SIZE: usize = 8 + 8;
+pub const GOVERNANCE_CONFIG_SIZE: usize = 8 + 8 + 2;
+// The largest ConfigUpdate variant carries a SettlementConfig
+pub const PENDING_CONFIG_CHANGE_SIZE: usize = 1 + SETTLEMENT_CONFIG_SIZE + 8;
+pub const PROPOSAL_SIZE: usize = 8 + 32 + 1 + SETTLEMENT_CONFIG_SIZE + 8 + 8 + 8;
+pub const DEMAND_RESPONSE_SIZE: usize = 8 + 8 + 8 + 8 + 8 + 8 + 4 + 8;
+pub const LEDGER_CAPACITY_SIZE: usize = 4 + 4 + 4;
+pub const INSURANCE_DRAIN_SIZE: usize = 8 + 8;
+pub const MARKET_STATS_SIZE: usize = 16 + 16 + 8 + 8 + 8 + 16 + 16;
+pub const PRICE_HISTORY_SIZE: usize = PRICE_HISTORY_LEN * (8 + 8) + 1 + 1;
+pub const SLOT_PRICE_HISTORY_SIZE: usize = SLOT_PRICE_HISTORY_LEN * (4 + 8 + 16 + 8) + 1;
+pub const LEDGER_HEADER_SIZE: usize = 1 + 32 + 32 + MAX_APPROVERS * 32 + 1 + 1 + 1 + 32 + 32 + 32 + 8 + 1 + 32 + MARKET_CONFIG_SIZE + 1 + PENDING_CONFIG_CHANGE_SIZE + 1 + DEMAND_RESPONSE_SIZE + 8 + LEDGER_CAPACITY_SIZE + 8 + 8 + 8 + 4 + 8 + 4 + 4 + 8 + 8 + 8 + 1 + INSURANCE_DRAIN_SIZE + 8 + 8 + 8 + 1 + PROPOSAL_SIZE + MARKET_STATS_SIZE + PRICE_HISTORY_SIZE + SLOT_PRICE_HISTORY_SIZE + MAX_STANDING_ORDERS * STANDING_ORDER_SIZE + 4 * 4;
+pub const PARTICIPANT_SIZE: usize = 1 + 1 + 32 + 1 + 8 + 8 + 4 + 4 + 1 + 1 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 2 + 8 + 8 + 8 + 8 + 1 + 8
+    + RECENT_NONCES * 8 + 1 + PARTICIPANT_METADATA_SIZE + 8 + 8 + 4 + 1 + 32 + 1 + 8 + 8 + 4
+    + 1 + 32 + MAX_AGGREGATOR_MEMBERS * AGGREGATOR_MEMBER_SIZE + 1 + 1 + 8 + 8 + 8 + 1 + MAX_OPEN_POSITIONS * SLOT_POSITION_SIZE + 1 + 8 + 8 + 8 + 8 + 1 + 8;
+pub const AGGREGATOR_MEMBER_SIZE: usize = 32 + 4 + 8;
+pub const SLOT_POSITION_SIZE: usize = 4 + 8 + 8 + 1 + 8 + 8;
+pub const PARTICIPANT_METADATA_SIZE: usize = 32 + 16 + 4 + 4;
+pub const ORDER_SIZE: usize = 8 + 32 + 8 + 8 + 8 + 8 + 4 + 1 + 8 + 1 + 1 + 8;
+pub const TRANSACTION_SIZE: usize = 8 + 8 + 8 + 32 + 32 + 8 + 8 + 8 + 1 + 8 + 8 + 8 + 1 + 1 + 8 + 8 + 1;
+pub const ORDER_ACCOUNT_SIZE: usize = 1 + 1 + 1 + 8 + 32 + 8 + 8 + 8 + 8 + 4 + 1 + 1 + 1 + 8 + 1 + 1 + 8;
+pub const BILATERAL_OFFER_SIZE: usize = 1 + 1 + 8 + 32 + 32 + 1 + 8 + 8 + 8 + 8 + 8;
+pub const STANDING_ORDER_SIZE: usize = 8 + 32 + 1 + 8 + 8 + 8 + 8 + 4;
+pub const FORWARD_CONTRACT_SIZE: usize = 1 + 1 + 8 + 32 + 1 + 1 + 32 + 1 + 32 + 8 + 8 + 4 + 2 + 8 + 8;
+
+pub const TRADE_LOG_HEADER_SIZE: usize = 1 + 32 + 1 + 4 + 4 + 4 + 8;
+
+pub fn trade_log_space(capacity: u32) -> usize {
+    TRADE_LOG_HEADER_SIZE + capacity as usize * TRANSACTION_SIZE
+}
+
+pub fn ledger_space(capacity: &LedgerCapacity) -> usize {
+    LEDGER_HEADER_SIZE
+        + capacity.max_open_orders as usize * ORDER_SIZE
+        + capacity.max_transactions as usize * TRANSACTION_SIZE
+}
+
+// Lamports backing every wallet_balance are held by a program-owned vault PDA derived from
+// the ledger. The vault keeps its own rent-exempt reserve on top of the participant funds.
+pub const VAULT_SEED: &[u8] = b"vault";
+
+pub fn find_vault_address(program_id: &Pubkey, ledger: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[VAULT_SEED, ledger.as_ref()], program_id)
+}
+
+// Each participant's balances live in a PDA derived from the ledger and the participant's wallet,
+// so deposits and withdrawals by different wallets never write to the same account
+pub const PARTICIPANT_SEED: &[u8] = b"participant";
+
+pub fn find_participant_address(program_id: &Pubkey, ledger: &Pubkey, wallet: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[PARTICIPANT_SEED, ledger.as_ref(), wallet.as_ref()], program_id)
+}
+
+// Order PDAs are keyed by the ledger's order id counter, so every order id maps to one address
+pub const ORDER_SEED: &[u8] = b"order";
+
+pub fn find_order_address(program_id: &Pubkey, ledger: &Pubkey, order_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[ORDER_SEED, ledger.as_ref(), &order_id.to_le_bytes()], program_id)
+}
+
+// Bilateral offers draw their ids from the same counter as orders
+pub const BILATERAL_SEED: &[u8] = b"bilateral";
+
+pub fn find_bilateral_offer_address(program_id: &Pubkey, ledger: &Pubkey, offer_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[BILATERAL_SEED, ledger.as_ref(), &offer_id.to_le_bytes()], program_id)
+}
+
+// Forward contracts draw their ids from the same counter as orders
+pub const FORWARD_SEED: &[u8] = b"forward";
+
+pub fn find_forward_contract_address(program_id: &Pubkey, ledger: &Pubkey, contract_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[FORWARD_SEED, ledger.as_ref(), &contract_id.to_le_bytes()], program_id)
+}
+
+// Orders are for delivery within a fixed window: slot n covers the hour starting at n * 3600 seconds
+// after the Unix epoch. Only orders for the same slot ever match, and once a slot's window has ended
+// its remaining orders are purged like expired ones.
+pub const DELIVERY_SLOT_SECONDS: i64 = 3600;
+
+pub fn delivery_slot_at(timestamp: i64) -> u32 {
+    (timestamp.max(0) / DELIVERY_SLOT_SECONDS) as u32
+}
+
+pub fn delivery_slot_end(delivery_slot: u32) -> i64 {
+    (delivery_slot as i64 + 1) * DELIVERY_SLOT_SECONDS
+}
+
+// A ledger created with a quote_mint settles in that SPL token instead of lamports. Pubkey::default()
+// selects native settlement. In token mode custody is any token account of the quote mint whose
+// authority is the vault PDA (typically the vault's associated token account).
+pub fn is_native_settlement(ledger: &Ledger) -> bool {
+    ledger.quote_mint == Pubkey::default()
+}
+
+// Custom error codes returned as ProgramError::Custom(code). The numeric values are part
+// of the public interface: never renumber a variant, only append new ones.
+#[derive(BorshSchema, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnergyMarketError {
+    /// 0: the signer is already present in the ledger
+    ParticipantAlreadyRegistered = 0,
+    /// 1: the account is not a registered participant
+    ParticipantNotRegistered = 1,
+    /// 2: the ledger account has not been initialized
+    LedgerNotInitialized = 2,
+    /// 3: the participant's wallet balance cannot cover the operation
+    InsufficientBalance = 3,
+    /// 4: no open order matches the given identifier
+    OrderNotFound = 4,
+    /// 5: the participant type is not allowed to perform the operation
+    InvalidParticipantType = 5,
+    /// 6: the ledger cannot hold any more entries
+    LedgerFull = 6,
+    /// 7: the signer does not own the referenced order
+    NotOrderOwner = 7,
+    /// 8: the serialized ledger no longer fits in the ledger account
+    LedgerAccountTooSmall = 8,
+    /// 9: the vault account is not the PDA derived from the ledger
+    InvalidVaultAccount = 9,
+    /// 10: a token account has the wrong mint or owner for this ledger
+    InvalidTokenAccount = 10,
+    /// 11: the order expiry is already in the past
+    InvalidExpiration = 11,
+    /// 12: the instruction is not available in the ledger's market mode
+    WrongMarketMode = 12,
+    /// 13: the order book already holds max_open_orders orders
+    OrderBookFull = 13,
+    /// 14: the trade history is full and the ledger rejects overflow
+    HistoryFull = 14,
+    /// 15: the participant account is not the PDA derived from the ledger and wallet
+    InvalidParticipantAccount = 15,
+    /// 16: the order account is not the PDA derived from the ledger and order id
+    InvalidOrderAccount = 16,
+    /// 17: the signer is not the ledger admin
+    Unauthorized = 17,
+    /// 18: the ledger account uses a layout this build cannot read; run MigrateLedger
+    UnsupportedLedgerVersion = 18,
+    /// 19: the fee and crank reward rates add up to more than 100%
+    InvalidFeeRate = 19,
+    /// 20: trading is paused by the admin
+    MarketPaused = 20,
+    /// 21: the participant still has orders in the book
+    ParticipantHasOpenOrders = 21,
+    /// 22: the participant still has funds in custody
+    ParticipantHasBalance = 22,
+    /// 23: the participant is frozen by the admin
+    ParticipantFrozen = 23,
+    /// 24: the order's delivery slot has already ended
+    InvalidDeliverySlot = 24,
+    /// 25: the zone does not exist on this ledger
+    InvalidZone = 25,
+    /// 26: the producer is curtailed by the grid operator
+    ProducerCurtailed = 26,
+    /// 27: the producer's open offers for the delivery slot would exceed its registered capacity
+    CapacityExceeded = 27,
+    /// 28: the order's energy amount or price is zero
+    InvalidOrderAmount = 28,
+    /// 29: not every open order account of the participant was supplied
+    MissingOrderAccounts = 29,
+    /// 30: the signer is not the ledger's oracle
+    InvalidOracle = 30,
+    /// 31: no trade in the history has the given identifier
+    TradeNotFound = 31,
+    /// 32: the trade is not awaiting settlement
+    TradeNotPending = 32,
+    /// 33: the trade's settlement deadline has passed
+    SettlementDeadlinePassed = 33,
+    /// 34: the trade's settlement deadline has not passed yet
+    SettlementDeadlineNotReached = 34,
+    /// 35: the participant is party to a trade, a demand-response commitment, a forward contract, a
+    /// slot position or an insurance claim awaiting settlement
+    PendingSettlement = 35,
+    /// 36: the participant's collateral cannot cover the operation
+    InsufficientCollateral = 36,
+    /// 37: the trade has not defaulted
+    TradeNotDefaulted = 37,
+    /// 38: the participant holds fewer RECs than requested
+    InsufficientRecs = 38,
+    /// 39: the storage capacity or efficiency is out of range
+    InvalidStorageParameters = 39,
+    /// 40: the battery cannot discharge more energy than it stores
+    InsufficientStoredEnergy = 40,
+    /// 41: the battery cannot charge beyond its capacity
+    StorageFull = 41,
+    /// 42: the order's price is not a multiple of the ledger's price tick
+    OffTickPrice = 42,
+    /// 43: the order's energy amount is not a multiple of the ledger's lot size
+    OffLotAmount = 43,
+    /// 44: the order's energy amount is below the ledger's minimum order size
+    MinOrderSizeNotMet = 44,
+    /// 45: the order's energy amount is above the ledger's maximum order size
+    MaxOrderSizeExceeded = 45,
+    /// 46: the minimum order size is above the maximum
+    InvalidOrderSizeLimits = 46,
+    /// 47: fewer than two price samples fall within the requested window
+    NotEnoughData = 47,
+    /// 48: the fill-or-kill order could not be filled in full
+    OrderNotFilled = 48,
+    /// 49: the order's minimum fill is larger than the order
+    InvalidMinFill = 49,
+    /// 50: the signer is not the counterparty named in the bilateral offer
+    NotOfferCounterparty = 50,
+    /// 51: a bilateral offer cannot name its own maker as the counterparty
+    InvalidCounterparty = 51,
+    /// 52: the withdrawal is above the ledger's threshold and must go through RequestWithdrawal
+    WithdrawalRequestRequired = 52,
+    /// 53: the participant already has a withdrawal request pending
+    WithdrawalPending = 53,
+    /// 54: the participant has no withdrawal request pending
+    NoPendingWithdrawal = 54,
+    /// 55: the withdrawal delay has not passed yet
+    WithdrawalLocked = 55,
+    /// 56: the withdrawal delay has passed, so the request can no longer be vetoed
+    VetoWindowClosed = 56,
+    /// 57: the withdrawal delay is negative
+    InvalidWithdrawalDelay = 57,
+    /// 58: the withdrawal would exceed the participant's limit for the current window
+    WithdrawalLimitExceeded = 58,
+    /// 59: the account holds a different type of data than the instruction expects at its position
+    WrongAccountType = 59,
+    /// 60: an account the instruction modifies was passed read-only
+    AccountNotWritable = 60,
+    /// 61: the participant recently posted an order with the same client order nonce
+    DuplicateOrder = 61,
+    /// 62: the participant already has the most open orders the ledger allows per participant
+    TooManyOpenOrders = 62,
+    /// 63: the deposit is below the ledger's minimum deposit
+    DepositTooSmall = 63,
+    /// 64: the consumer's free balance is below the ledger's minimum for posting a demand
+    BalanceTooLowToPost = 64,
+    /// 65: a metadata text field is not zero-padded UTF-8 or a coordinate is out of range
+    InvalidMetadata = 65,
+    /// 66: min_reputation is above REPUTATION_SCALE
+    InvalidReputationConfig = 66,
+    /// 67: a delegate cannot be the participant's own wallet or the default pubkey
+    InvalidDelegate = 67,
+    /// 68: the participant already has the requested type, or still holds stored energy or pending
+    /// sales tied to the role it gives up
+    InvalidTypeChange = 68,
+    /// 69: a config update is out of bounds for its parameter
+    InvalidConfigValue = 69,
+    /// 70: the ledger has a config timelock, so the change must be proposed
+    ConfigTimelocked = 70,
+    /// 71: another config change is already pending
+    ConfigChangePending = 71,
+    /// 72: no config change is pending
+    NoPendingConfigChange = 72,
+    /// 73: the proposed effective time is sooner than the config timelock allows
+    InvalidEffectiveTime = 73,
+    /// 74: the pending config change is not effective yet
+    ConfigChangeNotReady = 74,
+    /// 75: fewer approvers signed than the ledger's approval threshold
+    NotEnoughApprovals = 75,
+    /// 76: the approver is already listed, not listed, or would exceed MAX_APPROVERS, or the
+    /// threshold is out of range for the approvers listed
+    InvalidApprovers = 76,
+    /// 77: the ledger still has participants, open orders or uncollected fees
+    LedgerNotEmpty = 77,
+    /// 78: the ledger has been closed
+    LedgerClosed = 78,
+    /// 79: the trade log is not the ledger's or is not initialized
+    InvalidTradeLog = 79,
+    /// 80: the trade log is full and its policy rejects overwriting
+    TradeLogFull = 80,
+    /// 81: a query found nothing for the requested key
+    NotFound = 81,
+    /// 82: the instruction tag names no instruction this program knows
+    UnknownInstruction = 82,
+    /// 83: the instruction data ends before every field of its instruction
+    InstructionDataTooShort = 83,
+    /// 84: filling the order in full would move more than a u64 balance can hold
+    AmountTooLarge = 84,
+    /// 85: the demand claims a priority its owner is not flagged as a critical load for
+    PriorityNotAllowed = 85,
+    /// 86: a demand-response event is already declared and not every volunteer was paid
+    DemandResponseActive = 86,
+    /// 87: no demand-response event is declared, or the participant has no unpaid commitment to it
+    NoDemandResponse = 87,
+    /// 88: the demand-response window is empty or already open, or a target, incentive or
+    /// reduction is zero
+    InvalidDemandResponse = 88,
+    /// 89: the demand-response window is already open, so it takes no more commitments
+    VolunteeringClosed = 89,
+    /// 90: the demand-response window has not closed yet
+    DemandResponseNotOver = 90,
+    /// 91: the protocol fee pool cannot fund the incentive for the commitment
+    IncentivePoolExhausted = 91,
+    /// 92: the commitment would take the event past its target reduction
+    ReductionTargetExceeded = 92,
+    /// 93: the forward contract's margin is outside 1..=10000 basis points or rounds to nothing
+    InvalidMargin = 93,
+    /// 94: the forward contract has already been taken, or its delivery slot has begun
+    ForwardClosed = 94,
+    /// 95: the forward contract's delivery slot has not ended and neither side's margin is exhausted
+    ForwardNotDue = 95,
+    /// 96: the participant or the ledger already holds the most standing orders allowed
+    TooManyStandingOrders = 96,
+    /// 97: the standing order's interval or activation count is zero, or the ledger keeps its
+    /// orders in accounts, where standing orders are not supported
+    InvalidStandingOrder = 97,
+    /// 98: the participant is not a member of the aggregator, or the members were not passed in
+    /// membership order
+    NotAggregatorMember = 98,
+    /// 99: the aggregator already has MAX_AGGREGATOR_MEMBERS members, or the participant already
+    /// belongs to an aggregator
+    MembershipFull = 99,
+    /// 100: the aggregator's aggregated demand has not been settled
+    AggregatedDemandOpen = 100,
+    /// 101: the participant must leave its aggregator first, or as an aggregator remove its members
+    MemberOfAggregator = 101,
+    /// 102: a grid fee needs a grid operator on the ledger to be paid to
+    GridOperatorNotSet = 102,
+    /// 103: the offer asks less than the tariff floor for its delivery hour
+    PriceBelowTariffFloor = 103,
+    /// 104: the demand bids more than the tariff ceiling for its delivery hour
+    PriceAboveTariffCeiling = 104,
+    /// 105: the participant already holds MAX_OPEN_POSITIONS unsettled slot positions
+    TooManyOpenPositions = 105,
+    /// 106: the delivery slot has not ended yet
+    DeliverySlotNotOver = 106,
+    /// 107: the participant holds no position for the delivery slot
+    PositionNotFound = 107,
+    /// 108: the oracle has not reported the position's actuals and their timeout has not passed
+    ActualsNotReported = 108,
+    /// 109: the protocol fee pool cannot fund the surplus credit
+    ImbalancePoolExhausted = 109,
+    /// 110: the participant holds no insurance claim
+    NoInsuranceClaim = 110,
+    /// 111: the insurance pool is empty
+    InsurancePoolEmpty = 111,
+    /// 112: no insurance drain is pending
+    NoInsuranceDrain = 112,
+    /// 113: the pending insurance drain is not effective yet
+    InsuranceDrainNotEffective = 113,
+    /// 114: the stake is still locked
+    StakeLocked = 114,
+    /// 115: the participant has not staked that much
+    InsufficientStake = 115,
+    /// 116: the rewards pool is empty
+    RewardsPoolEmpty = 116,
+    /// 117: the participant has no reward points to claim
+    NoRewardPoints = 117,
+    /// 118: governance is disabled on this ledger
+    GovernanceDisabled = 118,
+    /// 119: the parameter cannot be changed by governance
+    ParameterNotGovernable = 119,
+    /// 120: the proposer's balance is below the proposal threshold
+    BelowProposalThreshold = 120,
+    /// 121: another proposal is still open
+    ProposalOpen = 121,
+    /// 122: no open proposal has that id
+    ProposalNotFound = 122,
+    /// 123: the participant already voted on this proposal
+    AlreadyVoted = 123,
+    /// 124: voting on the proposal has closed
+    VotingClosed = 124,
+    /// 125: voting on the proposal is still open
+    VotingNotOver = 125,
+}
+
+impl From<EnergyMarketError> for ProgramError {
+    fn from(e: EnergyMarketError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
+
+// Crates that embed this program (CPI callers, off-chain clients) enable no-entrypoint so the
+// program symbol is only defined by the deployed build
+#[cfg(not(feature = "no-entrypoint"))]
+solana_program::entrypoint!(process_instruction);
+
+pub fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    let instruction = EnergyMarketInstruction::unpack(instruction_data)?;
+
+    match instruction {
+        EnergyMarketInstruction::InitializeLedger { capacity, quote_mint, config, oracle } => {
+            initialize_ledger(program_id, accounts, capacity, quote_mint, config, oracle)
+        }
+        EnergyMarketInstruction::RegisterParticipant { participant_type, zone, registered_capacity, metadata } => {
+            register_participant(program_id, accounts, participant_type, zone, registered_capacity, metadata)
+        }
+        EnergyMarketInstruction::ReportProduction {
+            energy_amount, price, expires_at, delivery_slot, energy_source, time_in_force, min_fill, all_or_nothing, client_order_nonce,
+            distinct_lot,
+        } => {
+            let fill = FillConstraints { min_fill, all_or_nothing };
+            report_energy_production(
+                program_id, accounts, energy_amount, price, expires_at, delivery_slot, energy_source, time_in_force, fill, client_order_nonce,
+                distinct_lot,
+            )
+        }
+        EnergyMarketInstruction::PostDemand {
+            energy_amount, price_limit, expires_at, delivery_slot, time_in_force, min_fill, all_or_nothing, client_order_nonce,
+            priority,
+        } => {
+            let fill = FillConstraints { min_fill, all_or_nothing };
+            post_energy_demand(
+                program_id, accounts, energy_amount, price_limit, expires_at, delivery_slot, time_in_force, fill, client_order_nonce,
+                priority,
+            )
+        }
+        EnergyMarketInstruction::MatchTransactions { max_matches } => {
+            match_transactions(program_id, accounts, max_matches)
+        }
+        EnergyMarketInstruction::Deposit { amount } => deposit(program_id, accounts, amount),
+        EnergyMarketInstruction::Withdraw { amount } => withdraw(program_id, accounts, Some(amount)),
+        EnergyMarketInstruction::CancelDemand { order_id } => cancel_demand(program_id, accounts, order_id),
+        EnergyMarketInstruction::CancelProduction { order_id } => {
+            cancel_production(program_id, accounts, order_id)
+        }
+        EnergyMarketInstruction::PruneExpiredOrders => prune_expired_orders(program_id, accounts),
+        EnergyMarketInstruction::RunAuction => run_auction(program_id, accounts),
+        EnergyMarketInstruction::ResizeLedger { new_size, capacity } => {
+            resize_ledger(program_id, accounts, new_size, capacity)
+        }
+        EnergyMarketInstruction::MigrateLedger => migrate_ledger(program_id, accounts),
+        EnergyMarketInstruction::SetFee { fee_bps } => set_fee(program_id, accounts, fee_bps),
+        EnergyMarketInstruction::CollectFees => collect_fees(program_id, accounts),
+        EnergyMarketInstruction::SetPendingAdmin { new_admin } => {
+            set_pending_admin(program_id, accounts, new_admin)
+        }
+        EnergyMarketInstruction::AcceptAdmin => accept_admin(program_id, accounts),
+        EnergyMarketInstruction::Pause => set_paused(program_id, accounts, true),
+        EnergyMarketInstruction::Unpause => set_paused(program_id, accounts, false),
+        EnergyMarketInstruction::UnregisterParticipant => unregister_participant(program_id, accounts),
+        EnergyMarketInstruction::ForceUnregisterParticipant => {
+            force_unregister_participant(program_id, accounts)
+        }
+        EnergyMarketInstruction::FreezeParticipant => freeze_participant(program_id, accounts),
+        EnergyMarketInstruction::UnfreezeParticipant => unfreeze_participant(program_id, accounts),
+        EnergyMarketInstruction::SetGridOperator { grid_operator } => {
+            set_grid_operator(program_id, accounts, grid_operator)
+        }
+        EnergyMarketInstruction::Curtail { energy_amount, curtailed_until } => {
+            curtail(program_id, accounts, energy_amount, curtailed_until)
+        }
+        EnergyMarketInstruction::SetRegisteredCapacity { registered_capacity } => {
+            set_registered_capacity(program_id, accounts, registered_capacity)
+        }
+        EnergyMarketInstruction::SetOracle { oracle } => set_oracle(program_id, accounts, oracle),
+        EnergyMarketInstruction::AttestProduction { order_id } => attest_production(program_id, accounts, order_id),
+        EnergyMarketInstruction::ConfirmDelivery { trade_id } => confirm_delivery(program_id, accounts, trade_id),
+        EnergyMarketInstruction::SettleDefaultedTrade { trade_id } => {
+            settle_defaulted_trade(program_id, accounts, trade_id)
+        }
+        EnergyMarketInstruction::PostCollateral { amount } => post_collateral(program_id, accounts, amount),
+        EnergyMarketInstruction::WithdrawCollateral { amount } => withdraw_collateral(program_id, accounts, amount),
+        EnergyMarketInstruction::SlashCollateral { trade_id, amount } => {
+            slash_collateral(program_id, accounts, trade_id, amount)
+        }
+        EnergyMarketInstruction::TransferRec { amount } => transfer_rec(program_id, accounts, amount),
+        EnergyMarketInstruction::RetireRec { amount } => retire_rec(program_id, accounts, amount),
+        EnergyMarketInstruction::SetStorageParameters { storage_capacity, storage_efficiency_bps } => {
+            set_storage_parameters(program_id, accounts, storage_capacity, storage_efficiency_bps)
+        }
+        EnergyMarketInstruction::SetOrderIncrements { price_tick, lot_size } => {
+            set_order_increments(program_id, accounts, price_tick, lot_size)
+        }
+        EnergyMarketInstruction::SetOrderSizeLimits { min_order_size, max_order_size } => {
+            set_order_size_limits(program_id, accounts, min_order_size, max_order_size)
+        }
+        EnergyMarketInstruction::SetReferencePrice { reference_price } => {
+            set_reference_price(program_id, accounts, reference_price)
+        }
+        EnergyMarketInstruction::GetMarketStats => get_market_stats(program_id, accounts),
+        EnergyMarketInstruction::GetTwap { window_secs } => get_twap(program_id, accounts, window_secs),
+        EnergyMarketInstruction::SimulateMatch { max_matches } => simulate_match(program_id, accounts, max_matches),
+        EnergyMarketInstruction::ReplaceOrder { order_id, new_price, new_amount } => {
+            replace_order(program_id, accounts, order_id, new_price, new_amount)
+        }
+        EnergyMarketInstruction::CreateBilateralOffer { counterparty, side, energy_amount, price, expires_at } => {
+            create_bilateral_offer(program_id, accounts, counterparty, side, energy_amount, price, expires_at)
+        }
+        EnergyMarketInstruction::AcceptBilateralOffer { offer_id } => accept_bilateral_offer(program_id, accounts, offer_id),
+        EnergyMarketInstruction::RejectBilateralOffer { offer_id } => reject_bilateral_offer(program_id, accounts, offer_id),
+        EnergyMarketInstruction::TransferBalance { amount } => transfer_balance(program_id, accounts, amount),
+        EnergyMarketInstruction::WithdrawAll => withdraw(program_id, accounts, None),
+        EnergyMarketInstruction::SetWithdrawalDelay { withdrawal_delay, withdrawal_threshold } => {
+            set_withdrawal_delay(program_id, accounts, withdrawal_delay, withdrawal_threshold)
+        }
+        EnergyMarketInstruction::RequestWithdrawal { amount } => request_withdrawal(program_id, accounts, amount),
+        EnergyMarketInstruction::ClaimWithdrawal => claim_withdrawal(program_id, accounts),
+        EnergyMarketInstruction::VetoWithdrawal => veto_withdrawal(program_id, accounts),
+        EnergyMarketInstruction::SetWithdrawalLimit { withdrawal_limit } => {
+            set_withdrawal_limit(program_id, accounts, withdrawal_limit)
+        }
+        EnergyMarketInstruction::RegisterAndDeposit { participant_type, zone, registered_capacity, metadata, amount } => {
+            register_and_deposit(program_id, accounts, participant_type, zone, registered_capacity, metadata, amount)
+        }
+        EnergyMarketInstruction::SetDepositLimits { min_deposit, min_balance_to_post_demand } => {
+            set_deposit_limits(program_id, accounts, min_deposit, min_balance_to_post_demand)
+        }
+        EnergyMarketInstruction::UpdateParticipantMetadata { metadata } => {
+            update_participant_metadata(program_id, accounts, metadata)
+        }
+        EnergyMarketInstruction::SetReputationConfig { reputation } => {
+            set_reputation_config(program_id, accounts, reputation)
+        }
+        EnergyMarketInstruction::SetDelegate { delegate } => set_delegate(program_id, accounts, Some(delegate)),
+        EnergyMarketInstruction::RevokeDelegate => set_delegate(program_id, accounts, None),
+        EnergyMarketInstruction::ChangeParticipantType { new_type } => {
+            change_participant_type(program_id, accounts, new_type)
+        }
+        EnergyMarketInstruction::UpdateConfig { update } => update_config(program_id, accounts, update),
+        EnergyMarketInstruction::ProposeConfigChange { update, effective_at } => {
+            propose_config_change(program_id, accounts, update, effective_at)
+        }
+        EnergyMarketInstruction::ApplyConfigChange => apply_config_change(program_id, accounts),
+        EnergyMarketInstruction::CancelConfigChange => cancel_config_change(program_id, accounts),
+        EnergyMarketInstruction::AddApprover { approver } => add_approver(program_id, accounts, approver),
+        EnergyMarketInstruction::RemoveApprover { approver } => remove_approver(program_id, accounts, approver),
+        EnergyMarketInstruction::SetApprovalThreshold { threshold } => {
+            set_approval_threshold(program_id, accounts, threshold)
+        }
+        EnergyMarketInstruction::CloseLedger => close_ledger(program_id, accounts),
+        EnergyMarketInstruction::InitializeTradeLog { capacity, history_policy } => {
+            initialize_trade_log(program_id, accounts, capacity, history_policy)
+        }
+        EnergyMarketInstruction::GetParticipant { pubkey } => get_participant(program_id, accounts, pubkey),
+        EnergyMarketInstruction::GetOpenOrders { pubkey } => get_open_orders(program_id, accounts, pubkey),
+        EnergyMarketInstruction::GetBalance { pubkey } => get_balance(program_id, accounts, pubkey),
+        EnergyMarketInstruction::SetCriticalLoad { critical_load } => set_critical_load(program_id, accounts, critical_load),
+        EnergyMarketInstruction::DeclareDemandResponse { start, end, target_reduction_kwh, incentive_price } => {
+            declare_demand_response(program_id, accounts, start, end, target_reduction_kwh, incentive_price)
+        }
+        EnergyMarketInstruction::VolunteerReduction { reduction_kwh } => volunteer_reduction(program_id, accounts, reduction_kwh),
+        EnergyMarketInstruction::SettleDemandResponse { actual_reductions } => {
+            settle_demand_response(program_id, accounts, actual_reductions)
+        }
+        EnergyMarketInstruction::CreateForward { counterparty, side, energy_amount, price, delivery_slot, margin_bps } => {
+            create_forward(program_id, accounts, counterparty, side, energy_amount, price, delivery_slot, margin_bps)
+        }
+        EnergyMarketInstruction::AcceptForward { contract_id } => accept_forward(program_id, accounts, contract_id),
+        EnergyMarketInstruction::SettleForward { contract_id } => settle_forward(program_id, accounts, contract_id),
+        EnergyMarketInstruction::CreateStandingOrder { side, energy_amount, price, interval_secs, first_activation, activations } => {
+            create_standing_order(program_id, accounts, side, energy_amount, price, interval_secs, first_activation, activations)
+        }
+        EnergyMarketInstruction::CancelStandingOrder { standing_order_id } => cancel_standing_order(program_id, accounts, standing_order_id),
+        EnergyMarketInstruction::ActivateStandingOrders => activate_standing_orders(program_id, accounts),
+        EnergyMarketInstruction::AddMember { weight } => add_member(program_id, accounts, weight),
+        EnergyMarketInstruction::RemoveMember => remove_member(program_id, accounts),
+        EnergyMarketInstruction::PostAggregatedDemand { energy_amount, price_limit, expires_at, delivery_slot } => {
+            post_aggregated_demand(program_id, accounts, energy_amount, price_limit, expires_at, delivery_slot)
+        }
+        EnergyMarketInstruction::SettleAggregatedDemand => settle_aggregated_demand(program_id, accounts),
+        EnergyMarketInstruction::SetTariffSchedule { schedule } => set_tariff_schedule(program_id, accounts, schedule),
+        EnergyMarketInstruction::SetNetMetering { enabled } => set_net_metering(program_id, accounts, enabled),
+        EnergyMarketInstruction::SetImbalanceConfig { imbalance } => set_imbalance_config(program_id, accounts, imbalance),
+        EnergyMarketInstruction::ReportActuals { delivery_slot, metered_injection, metered_consumption } => {
+            report_actuals(program_id, accounts, delivery_slot, metered_injection, metered_consumption)
+        }
+        EnergyMarketInstruction::SettleImbalances { delivery_slot } => settle_imbalances(program_id, accounts, delivery_slot),
+        EnergyMarketInstruction::TopUpInsurance { amount } => top_up_insurance(program_id, accounts, amount),
+        EnergyMarketInstruction::ProposeInsuranceDrain { amount } => propose_insurance_drain(program_id, accounts, amount),
+        EnergyMarketInstruction::DrainInsurance => drain_insurance(program_id, accounts),
+        EnergyMarketInstruction::ClaimInsurance => claim_insurance(program_id, accounts),
+        EnergyMarketInstruction::StakeForPriority { amount, lock_duration } => {
+            stake_for_priority(program_id, accounts, amount, lock_duration)
+        }
+        EnergyMarketInstruction::UnstakeAfterCooldown { amount } => unstake_after_cooldown(program_id, accounts, amount),
+        EnergyMarketInstruction::SetRewardRates { rates } => set_reward_rates(program_id, accounts, rates),
+        EnergyMarketInstruction::FundRewards { amount } => fund_rewards(program_id, accounts, amount),
+        EnergyMarketInstruction::ClaimRewards => claim_rewards(program_id, accounts),
+        EnergyMarketInstruction::SetGovernanceConfig { governance } => set_governance_config(program_id, accounts, governance),
+        EnergyMarketInstruction::CreateProposal { update, voting_deadline } => {
+            create_proposal(program_id, accounts, update, voting_deadline)
+        }
+        EnergyMarketInstruction::CastVote { proposal_id, support } => cast_vote(program_id, accounts, proposal_id, support),
+        EnergyMarketInstruction::ExecuteProposal => execute_proposal(program_id, accounts),
+    }
+}
+
+// next_account_info that logs which account is missing, so a short account list is easy to diagnose
+fn next_account<'a, 'b, I: Iterator<Item = &'a AccountInfo<'b>>>(account_info_iter: &mut I, name: &str) -> Result<I::Item, ProgramError> {
+    next_account_info(account_info_iter).inspect_err(|_| msg!("Missing the {} account", name))
+}
+
+// Checks byte 0 of an account against the type expected at its position. An account that is not
+// the program's or holds nothing fails with `missing`; one holding another known type, usually a
+// sign of swapped accounts, fails with WrongAccountType.
+fn assert_account_type(program_id: &Pubkey, account: &AccountInfo, expected: AccountType, missing: EnergyMarketError) -> ProgramResult {
+    let tag = if account.owner == program_id { account.data.borrow().first().copied() } else { None };
+    match tag {
+        Some(tag) if tag == expected as u8 => Ok(()),
+        Some(tag) if AccountType::from_tag(tag).is_some() || (1..=LEDGER_VERSION).contains(&tag) => {
+            msg!("Expected a {:?} account at {:?}", expected, account.key);
+            Err(EnergyMarketError::WrongAccountType.into())
+        }
+        _ => Err(missing.into()),
+    }
+}
+
+fn assert_writable(account: &AccountInfo, name: &str) -> ProgramResult {
+    if !account.is_writable {
+        msg!("The {} account {:?} must be writable", name, account.key);
+        return Err(EnergyMarketError::AccountNotWritable.into());
+    }
+    Ok(())
+}
+
+// A PDA signed for by its program through invoke_signed arrives with is_signer set, so program
+// wallets pass this check the same way keypair wallets do
+fn assert_signer(account: &AccountInfo) -> ProgramResult {
+    if !account.is_signer {
+        msg!("Missing required signature for {:?}", account.key);
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    Ok(())
+}
+
+// Orders can be posted and cancelled by the owner's wallet or by its delegate. A delegate signs as
+// one extra account appended after the instruction's usual accounts, with the owner wallet passed
+// unsigned. Returns the usual accounts and the account that signed for the owner.
+fn split_order_authority<'b, 'a>(accounts: &'b [AccountInfo<'a>]) -> Result<(&'b [AccountInfo<'a>], &'b AccountInfo<'a>), ProgramError> {
+    match accounts.first() {
+        Some(owner_account) if owner_account.is_signer => Ok((accounts, owner_account)),
+        Some(owner_account) => {
+            let (authority_account, accounts) = accounts.split_last()
+                .filter(|(_, accounts)| !accounts.is_empty())
+                .ok_or(ProgramError::MissingRequiredSignature)?;
+            if !authority_account.is_signer {
+                msg!("Missing required signature for {:?} or its delegate", owner_account.key);
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            Ok((accounts, authority_account))
+        }
+        None => Err(ProgramError::NotEnoughAccountKeys),
+    }
+}
+
+fn assert_order_authority(participant: &Participant, authority_account: &AccountInfo) -> ProgramResult {
+    if *authority_account.key != participant.id && participant.delegate != Some(*authority_account.key) {
+        return Err(EnergyMarketError::Unauthorized.into());
+    }
+    Ok(())
+}
+
+// Without approvers the admin account must be the ledger admin. With approvers it must be one of
+// them, and together with every other approver signing anywhere in `accounts` reach the threshold.
+fn assert_admin(ledger: &Ledger, admin_account: &AccountInfo, accounts: &[AccountInfo]) -> ProgramResult {
+    assert_signer(admin_account)?;
+    if ledger.approver_count == 0 {
+        if ledger.admin != *admin_account.key {
+            return Err(EnergyMarketError::Unauthorized.into());
+        }
+        return Ok(());
+    }
+
+    let approvers = &ledger.approvers[..ledger.approver_count as usize];
+    if !approvers.contains(admin_account.key) {
+        return Err(EnergyMarketError::Unauthorized.into());
+    }
+    let approvals = approvers.iter()
+        .filter(|approver| accounts.iter().any(|account| account.is_signer && account.key == *approver))
+        .count();
+    if approvals < ledger.approval_threshold as usize {
+        msg!("{} of {} required approvals", approvals, ledger.approval_threshold);
+        return Err(EnergyMarketError::NotEnoughApprovals.into());
+    }
+    Ok(())
+}
+
+fn assert_trading_enabled(ledger: &Ledger) -> ProgramResult {
+    if ledger.paused {
+        return Err(EnergyMarketError::MarketPaused.into());
+    }
+    Ok(())
+}
+
+fn assert_vault(program_id: &Pubkey, ledger_account: &AccountInfo, ledger: &Ledger, vault_account: &AccountInfo) -> ProgramResult {
+    let expected = Pubkey::create_program_address(
+        &[VAULT_SEED, ledger_account.key.as_ref(), &[ledger.vault_bump]],
+        program_id,
+    )?;
+    if expected != *vault_account.key || vault_account.owner != program_id {
+        return Err(EnergyMarketError::InvalidVaultAccount.into());
+    }
+    Ok(())
+}
+
+// Creates a program-owned PDA funded by `payer_account`. Anyone can send lamports to an address
+// before it is created, which would make create_account fail, so a pre-funded PDA is topped up
+// and claimed with allocate + assign instead.
+fn create_pda_account<'a>(
+    program_id: &Pubkey,
+    payer_account: &AccountInfo<'a>,
+    pda_account: &AccountInfo<'a>,
+    system_program_account: &AccountInfo<'a>,
+    space: usize,
+    seeds: &[&[u8]],
+) -> ProgramResult {
+    let required_lamports = Rent::get()?.minimum_balance(space);
+
+    if pda_account.lamports() == 0 {
+        return invoke_signed(
+            &system_instruction::create_account(
+                payer_account.key,
+                pda_account.key,
+                required_lamports,
+                space as u64,
+                program_id,
+            ),
+            &[payer_account.clone(), pda_account.clone(), system_program_account.clone()],
+            &[seeds],
+        );
+    }
+
+    let top_up = required_lamports.saturating_sub(pda_account.lamports());
+    if top_up > 0 {
+        invoke(
+            &system_instruction::transfer(payer_account.key, pda_account.key, top_up),
+            &[payer_account.clone(), pda_account.clone(), system_program_account.clone()],
+        )?;
+    }
+    invoke_signed(
+        &system_instruction::allocate(pda_account.key, space as u64),
+        &[pda_account.clone(), system_program_account.clone()],
+        &[seeds],
+    )?;
+    invoke_signed(
+        &system_instruction::assign(pda_account.key, program_id),
+        &[pda_account.clone(), system_program_account.clone()],
+        &[seeds],
+    )
+}
+
+// Reads a participant PDA, checking it belongs to this ledger and, when `wallet` is given, to that wallet
+fn load_participant(
+    program_id: &Pubkey,
+    ledger_account: &AccountInfo,
+    participant_account: &AccountInfo,
+    wallet: Option<&Pubkey>,
+) -> Result<Participant, ProgramError> {
+    assert_account_type(program_id, participant_account, AccountType::Participant, EnergyMarketError::ParticipantNotRegistered)?;
+    let participant = Participant::deserialize(&mut &participant_account.data.borrow()[..])?;
+
+    let expected = Pubkey::create_program_address(
+        &[PARTICIPANT_SEED, ledger_account.key.as_ref(), participant.id.as_ref(), &[participant.bump]],
+        program_id,
+    )?;
+    if expected != *participant_account.key || wallet.is_some_and(|wallet| participant.id != *wallet) {
+        return Err(EnergyMarketError::InvalidParticipantAccount.into());
+    }
+    Ok(participant)
+}
+
+fn save_participant(participant: &Participant, participant_account: &AccountInfo) -> ProgramResult {
+    assert_writable(participant_account, "participant")?;
+    participant.serialize(&mut &mut participant_account.data.borrow_mut()[..])?;
+    Ok(())
+}
+
+// Accounts handed to a matching instruction after the ledger. On OrderStorage::Ledger these are
+// participant PDAs; on OrderStorage::Accounts they come in triples of order PDA, owner wallet and
+// owner participant PDA. The wallet receives the order's rent once the order is filled or purged.
+struct MatchingAccounts<'a, 'b> {
+    participant_accounts: Vec<&'b AccountInfo<'a>>,
+    participants: Vec<Participant>,
+    order_accounts: Vec<(OrderAccount, &'b AccountInfo<'a>, &'b AccountInfo<'a>)>,
+}
+
+// Loads the participants, and on account storage moves the supplied orders into the ledger's
+// in-memory book so matching runs unchanged. Duplicates are ignored so each account is written once.
+fn load_matching_accounts<'a, 'b>(
+    program_id: &Pubkey,
+    ledger_account: &AccountInfo<'a>,
+    ledger: &mut Ledger,
+    account_info_iter: &mut std::slice::Iter<'b, AccountInfo<'a>>,
+) -> Result<MatchingAccounts<'a, 'b>, ProgramError> {
+    let mut matching_accounts = MatchingAccounts {
+        participant_accounts: Vec::new(),
+        participants: Vec::new(),
+        order_accounts: Vec::new(),
+    };
+
+    while let Some(account) = account_info_iter.next() {
+        let participant_account = if ledger.config.order_storage == OrderStorage::Accounts {
+            let owner_account = next_account(account_info_iter, "owner")?;
+            let participant_account = next_account(account_info_iter, "participant")?;
+            let order = load_order_account(program_id, ledger_account, account)?;
+            if order.owner != *owner_account.key {
+                return Err(EnergyMarketError::NotOrderOwner.into());
+            }
+            if !matching_accounts.order_accounts.iter().any(|(o, _, _)| o.order_id == order.order_id) {
+                match order.side {
+                    OrderSide::Production => ledger.productions.push(order_to_production(&order)),
+                    OrderSide::Demand => ledger.demands.push(order_to_demand(&order)),
+                }
+                matching_accounts.order_accounts.push((order, account, owner_account));
+            }
+            participant_account
+        } else {
+            account
+        };
+
+        let participant = load_participant(program_id, ledger_account, participant_account, None)?;
+        if matching_accounts.participants.iter().any(|p| p.id == participant.id) {
+            continue;
+        }
+        matching_accounts.participant_accounts.push(participant_account);
+        matching_accounts.participants.push(participant);
+    }
+    Ok(matching_accounts)
+}
+
+// Writes the participants back and, on account storage, persists what is left of each supplied
+// order, closing the ones the run filled or purged, before emptying the in-memory book again
+fn save_matching_accounts(ledger: &mut Ledger, matching_accounts: &MatchingAccounts) -> ProgramResult {
+    for (participant_account, participant) in matching_accounts.participant_accounts.iter().zip(&matching_accounts.participants) {
+        save_participant(participant, participant_account)?;
+    }
+    if ledger.config.order_storage != OrderStorage::Accounts {
+        return Ok(());
+    }
+
+    for (order, order_account, owner_account) in &matching_accounts.order_accounts {
+        let remaining = match order.side {
+            OrderSide::Production => ledger.productions.iter()
+                .find(|p| p.order_id == order.order_id)
+                .map(|p| p.energy_amount),
+            OrderSide::Demand => ledger.demands.iter()
+                .find(|d| d.order_id == order.order_id)
+                .map(|d| d.energy_amount),
+        };
+        if let Some(energy_amount) = remaining {
+            let mut order = order.clone();
+            order.energy_amount = energy_amount;
+            order.serialize(&mut &mut order_account.data.borrow_mut()[..])?;
+        } else {
+            close_account(order_account, owner_account)?;
+            ledger.open_order_accounts = ledger.open_order_accounts.checked_sub(1)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+        }
+    }
+    ledger.productions.clear();
+    ledger.demands.clear();
+    Ok(())
+}
+
+// Adds a participant that is not tied to any supplied order, reusing the entry if an order
+// owner already brought it in. Returns its index in matching_accounts.participants.
+fn include_participant<'a, 'b>(
+    program_id: &Pubkey,
+    ledger_account: &AccountInfo<'a>,
+    matching_accounts: &mut MatchingAccounts<'a, 'b>,
+    participant_account: &'b AccountInfo<'a>,
+    wallet: &Pubkey,
+) -> Result<usize, ProgramError> {
+    if let Some(index) = matching_accounts.participants.iter().position(|p| p.id == *wallet) {
+        return Ok(index);
+    }
+    let participant = load_participant(program_id, ledger_account, participant_account, Some(wallet))?;
+    matching_accounts.participant_accounts.push(participant_account);
+    matching_accounts.participants.push(participant);
+    Ok(matching_accounts.participants.len() - 1)
+}
+
+// Immediate orders match inside the posting instruction, which only pay-as-bid ledgers do
+fn assert_time_in_force(ledger: &Ledger, time_in_force: TimeInForce) -> ProgramResult {
+    if time_in_force != TimeInForce::GoodTilCancelled && ledger.config.market_mode != MarketMode::PayAsBid {
+        return Err(EnergyMarketError::WrongMarketMode.into());
+    }
+    Ok(())
+}
+
+// Crosses an immediate order, already in the in-memory book with its owner's order slot and
+// escrow taken, against the resting orders supplied in `account_info_iter` as for
+// MatchTransactions. Whatever is left of it is discarded. The owner cranks its own fill, so it
+// collects the crank reward.
+#[allow(clippy::too_many_arguments)]
+fn execute_taker_order<'a, 'b>(
+    program_id: &Pubkey,
+    ledger_account: &AccountInfo<'a>,
+    ledger: &mut Ledger,
+    owner_participant_account: &'b AccountInfo<'a>,
+    owner: Participant,
+    order_id: u64,
+    time_in_force: TimeInForce,
+    account_info_iter: &mut std::slice::Iter<'b, AccountInfo<'a>>,
+) -> ProgramResult {
+    let trade_log_account = next_trade_log(ledger, account_info_iter);
+    let mut matching_accounts = load_matching_accounts(program_id, ledger_account, ledger, account_info_iter)?;
+    // The owner's in-memory state carries the new order, so it replaces any copy loaded from its account
+    let owner_index = match matching_accounts.participants.iter().position(|p| p.id == owner.id) {
+        Some(index) => {
+            matching_accounts.participants[index] = owner;
+            index
+        }
+        None => {
+            matching_accounts.participant_accounts.push(owner_participant_account);
+            matching_accounts.participants.push(owner);
+            matching_accounts.participants.len() - 1
+        }
+    };
+
+    let timestamp = Clock::get()?.unix_timestamp;
+    let matched_trades = match_taker_order(ledger, &mut matching_accounts.participants, timestamp, order_id)?.trades;
+
+    let owner = &mut matching_accounts.participants[owner_index];
+    let remaining = discard_order(ledger, owner, order_id)?;
+    if remaining > 0 && time_in_force == TimeInForce::FillOrKill {
+        msg!("Order {} left {} unfilled", order_id, remaining);
+        return Err(EnergyMarketError::OrderNotFilled.into());
+    }
+    msg!("Order {} matched {} trades, discarded {}", order_id, matched_trades.len(), remaining);
+    for trade in &matched_trades {
+        emit(&events::TradeExecuted::from(trade))?;
+    }
+    pay_crank_reward(ledger, owner, &matched_trades)?;
+    record_trades(program_id, ledger_account, ledger, trade_log_account, matched_trades)?;
+
+    save_matching_accounts(ledger, &matching_accounts)
+}
+
+// Takes an order out of the in-memory book, returning its owner's order slot and escrow.
+// Returns the unfilled amount, 0 if matching already removed the order.
+fn discard_order(ledger: &mut Ledger, owner: &mut Participant, order_id: u64) -> Result<u64, ProgramError> {
+    if let Some(index) = ledger.productions.iter().position(|p| p.order_id == order_id) {
+        let production = ledger.productions.remove(index);
+        release_order_slot(owner, OrderSide::Production)?;
+        return Ok(production.energy_amount);
+    }
+    if let Some(index) = ledger.demands.iter().position(|d| d.order_id == order_id) {
+        let demand = ledger.demands.remove(index);
+        release_order_slot(owner, OrderSide::Demand)?;
+        release_funds(owner, demand_escrow(&demand)?)?;
+        return Ok(demand.energy_amount);
+    }
+    Ok(0)
+}
+
+fn pay_crank_reward(ledger: &Ledger, cranker: &mut Participant, trades: &[Transaction]) -> ProgramResult {
+    let reward = crank_reward(trades, ledger.config.crank_reward_bps)?;
+    if reward > 0 {
+        cranker.wallet_balance = cranker.wallet_balance.checked_add(reward)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        msg!("Paid crank reward of {} to {:?}", reward, cranker.id);
+    }
+    Ok(())
+}
+
+fn load_order_account(program_id: &Pubkey, ledger_account: &AccountInfo, order_account: &AccountInfo) -> Result<OrderAccount, ProgramError> {
+    assert_account_type(program_id, order_account, AccountType::Order, EnergyMarketError::OrderNotFound)?;
+    let order = OrderAccount::deserialize(&mut &order_account.data.borrow()[..])?;
+
+    let expected = Pubkey::create_program_address(
+        &[ORDER_SEED, ledger_account.key.as_ref(), &order.order_id.to_le_bytes(), &[order.bump]],
+        program_id,
+    )?;
+    if expected != *order_account.key {
+        return Err(EnergyMarketError::InvalidOrderAccount.into());
+    }
+    Ok(order)
+}
+
+// Creates the PDA for a new order on account storage, with the owner paying rent. Expects the
+// order PDA and the system program as the next accounts.
+fn create_order_account<'a>(
+    program_id: &Pubkey,
+    ledger_account: &AccountInfo<'a>,
+    ledger: &mut Ledger,
+    owner_account: &AccountInfo<'a>,
+    account_info_iter: &mut std::slice::Iter<AccountInfo<'a>>,
+    mut order: OrderAccount,
+) -> ProgramResult {
+    let order_account = next_account(account_info_iter, "order")?;
+    let system_program_account = next_account(account_info_iter, "system program")?;
+
+    if *system_program_account.key != system_program::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let (order_key, bump) = find_order_address(program_id, ledger_account.key, order.order_id);
+    if order_key != *order_account.key {
+        return Err(EnergyMarketError::InvalidOrderAccount.into());
+    }
+
+    create_pda_account(
+        program_id,
+        owner_account,
+        order_account,
+        system_program_account,
+        ORDER_ACCOUNT_SIZE,
+        &[ORDER_SEED, ledger_account.key.as_ref(), &order.order_id.to_le_bytes(), &[bump]],
+    )?;
+
+    order.bump = bump;
+    order.serialize(&mut &mut order_account.data.borrow_mut()[..])?;
+
+    ledger.open_order_accounts = ledger.open_order_accounts.checked_add(1)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    Ok(())
+}
+
+// Removes an order from account storage on behalf of its owner: expects the order PDA as the next
+// account and refunds its rent to the owner
+fn take_order_account<'a>(
+    program_id: &Pubkey,
+    ledger_account: &AccountInfo<'a>,
+    ledger: &mut Ledger,
+    owner_account: &AccountInfo<'a>,
+    account_info_iter: &mut std::slice::Iter<AccountInfo<'a>>,
+    side: OrderSide,
+    order_id: u64,
+) -> Result<OrderAccount, ProgramError> {
+    let order_account = next_account(account_info_iter, "order")?;
+    let order = load_order_account(program_id, ledger_account, order_account)?;
+    if order.order_id != order_id || order.side != side {
+        return Err(EnergyMarketError::OrderNotFound.into());
+    }
+    if order.owner != *owner_account.key {
+        return Err(EnergyMarketError::NotOrderOwner.into());
+    }
+
+    close_account(order_account, owner_account)?;
+    ledger.open_order_accounts = ledger.open_order_accounts.checked_sub(1)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    Ok(order)
+}
+
+// Hands every lamport of a program-owned account to `destination_account` and wipes its data,
+// so the runtime reclaims the account at the end of the transaction
+fn close_account(account: &AccountInfo, destination_account: &AccountInfo) -> ProgramResult {
+    let destination_lamports = destination_account.lamports().checked_add(account.lamports())
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    **destination_account.try_borrow_mut_lamports()? = destination_lamports;
+    **account.try_borrow_mut_lamports()? = 0;
+    let mut data = account.data.borrow_mut();
+    data.fill(0);
+    if let Some(tag) = data.first_mut() {
+        *tag = CLOSED_ACCOUNT_TAG;
+    }
+    Ok(())
+}
+
+fn order_to_production(order: &OrderAccount) -> EnergyProduction {
+    EnergyProduction {
+        order_id: order.order_id,
+        producer_id: order.owner,
+        energy_amount: order.energy_amount,
+        price: order.price,
+        created_at: order.created_at,
+        expires_at: order.expires_at,
+        delivery_slot: order.delivery_slot,
+        zone: order.zone,
+        verified: order.verified,
+        energy_source: order.energy_source,
+        min_fill: order.min_fill,
+        all_or_nothing: order.all_or_nothing,
+    }
+}
+
+fn order_to_demand(order: &OrderAccount) -> EnergyDemand {
+    EnergyDemand {
+        order_id: order.order_id,
+        consumer_id: order.owner,
+        energy_amount: order.energy_amount,
+        price_limit: order.price,
+        created_at: order.created_at,
+        expires_at: order.expires_at,
+        delivery_slot: order.delivery_slot,
+        zone: order.zone,
+        min_fill: order.min_fill,
+        all_or_nothing: order.all_or_nothing,
+        priority: order.priority,
+        escrow_per_kwh: order.escrow_per_kwh,
+    }
+}
+
+fn assert_token_account(token_account: &AccountInfo, mint: &Pubkey, owner: Option<&Pubkey>) -> ProgramResult {
+    if *token_account.owner != spl_token::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let state = spl_token::state::Account::unpack(&token_account.data.borrow())?;
+    if state.mint != *mint || owner.is_some_and(|owner| state.owner != *owner) {
+        msg!("Token account {:?} does not match mint {:?}", token_account.key, mint);
+        return Err(EnergyMarketError::InvalidTokenAccount.into());
+    }
+    Ok(())
+}
+
+fn assert_token_program(token_program_account: &AccountInfo) -> ProgramResult {
+    if *token_program_account.key != spl_token::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Ok(())
+}
+
+// Moves `amount` from the participant into custody. Native ledgers expect the system program as the
+// next account; token ledgers expect the source token account, vault token account and token program.
+fn transfer_to_vault<'a>(
+    ledger: &Ledger,
+    participant_account: &AccountInfo<'a>,
+    vault_account: &AccountInfo<'a>,
+    account_info_iter: &mut std::slice::Iter<AccountInfo<'a>>,
+    amount: u64,
+) -> ProgramResult {
+    if is_native_settlement(ledger) {
+        let system_program_account = next_account(account_info_iter, "system program")?;
+        return invoke(
+            &system_instruction::transfer(participant_account.key, vault_account.key, amount),
+            &[participant_account.clone(), vault_account.clone(), system_program_account.clone()],
+        );
+    }
+
+    let source_token_account = next_account(account_info_iter, "source token")?;
+    let vault_token_account = next_account(account_info_iter, "vault token")?;
+    let token_program_account = next_account(account_info_iter, "token program")?;
+
+    assert_token_program(token_program_account)?;
+    assert_token_account(source_token_account, &ledger.quote_mint, Some(participant_account.key))?;
+    assert_token_account(vault_token_account, &ledger.quote_mint, Some(vault_account.key))?;
+
+    invoke(
+        &spl_token::instruction::transfer(
+            token_program_account.key,
+            source_token_account.key,
+            vault_token_account.key,
+            participant_account.key,
+            &[],
+            amount,
+        )?,
+        &[
+            source_token_account.clone(),
+            vault_token_account.clone(),
+            participant_account.clone(),
+            token_program_account.clone(),
+        ],
+    )
+}
+
+// Pays `amount` out of custody to `destination_account`, which is a wallet on native ledgers and a
+// token account of the quote mint on token ledgers (followed by the vault token account and token program).
+fn transfer_from_vault<'a>(
+    ledger: &Ledger,
+    ledger_account: &AccountInfo<'a>,
+    vault_account: &AccountInfo<'a>,
+    destination_account: &AccountInfo<'a>,
+    account_info_iter: &mut std::slice::Iter<AccountInfo<'a>>,
+    amount: u64,
+) -> ProgramResult {
+    if is_native_settlement(ledger) {
+        // The vault is owned by this program, so lamports can be moved out without a CPI
+        let vault_lamports = vault_account.lamports().checked_sub(amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        let destination_lamports = destination_account.lamports().checked_add(amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        **vault_account.try_borrow_mut_lamports()? = vault_lamports;
+        **destination_account.try_borrow_mut_lamports()? = destination_lamports;
+        return Ok(());
+    }
+
+    let vault_token_account = next_account(account_info_iter, "vault token")?;
+    let token_program_account = next_account(account_info_iter, "token program")?;
+
+    assert_token_program(token_program_account)?;
+    assert_token_account(vault_token_account, &ledger.quote_mint, Some(vault_account.key))?;
+    assert_token_account(destination_account, &ledger.quote_mint, None)?;
+
+    invoke_signed(
+        &spl_token::instruction::transfer(
+            token_program_account.key,
+            vault_token_account.key,
+            destination_account.key,
+            vault_account.key,
+            &[],
+            amount,
+        )?,
+        &[
+            vault_token_account.clone(),
+            destination_account.clone(),
+            vault_account.clone(),
+            token_program_account.clone(),
+        ],
+        &[&[VAULT_SEED, ledger_account.key.as_ref(), &[ledger.vault_bump]]],
+    )
+}
+
+// Funds backing an open demand sit in reserved_balance until the demand is filled or removed
+fn demand_escrow(demand: &EnergyDemand) -> Result<u64, ProgramError> {
+    fill_escrow(demand, demand.energy_amount)
+}
+
+// The part of a demand's escrow backing `energy_amount` of it, which a fill of that much releases.
+// Being linear in the amount, the escrow released fill by fill adds up to what was reserved.
+fn fill_escrow(demand: &EnergyDemand, energy_amount: u64) -> Result<u64, ProgramError> {
+    balance_change(energy_amount as u128 * demand.escrow_per_kwh as u128)
+}
+
+// The most one unit of a demand limited at `price_limit` can cost its buyer: the limit, the buyer's
+// half of the crank reward rounded up, the wheeling fee when fills may cross zones, and the grid
+// fee. A demand with a negative limit expects to be paid, and that payment covers its crank share,
+// so it only reserves the per-kWh fees.
+fn escrow_per_kwh(config: &MarketConfig, price_limit: i64) -> Result<u64, ProgramError> {
+    let limit = price_limit.max(0) as u128;
+    let crank_share = (limit * config.crank_reward_bps as u128).div_ceil(20_000);
+    let wheeling_fee = if config.zones.allow_inter_zone { config.zones.wheeling_fee } else { 0 };
+    balance_change(limit + crank_share + wheeling_fee as u128 + config.grid_fee_per_kwh as u128)
+}
+
+// What changes hands for `amount` at `price`, whichever side pays it. Computed in u128, where the
+// product of any amount and price fits; it only has to fit a u64 once it moves a balance.
+pub fn notional(amount: u64, price: i64) -> u128 {
+    amount as u128 * price.unsigned_abs() as u128
+}
+
+pub fn balance_change(amount: u128) -> Result<u64, ProgramError> {
+    u64::try_from(amount).map_err(|_| EnergyMarketError::AmountTooLarge.into())
+}
+
+// The most a fill of the whole order could take from one side: the notional plus that side's crank
+// reward share, protocol fee, wheeling fee and grid fee, each taken at its full rate as an upper bound
+fn max_order_cost(config: &MarketConfig, energy_amount: u64, price: i64) -> u128 {
+    let notional = notional(energy_amount, price);
+    let cut_bps = config.crank_reward_bps as u128 + config.fee_bps as u128;
+    let per_kwh_fees = config.zones.wheeling_fee as u128 + config.grid_fee_per_kwh as u128;
+    notional + (notional * cut_bps).div_ceil(10_000) + energy_amount as u128 * per_kwh_fees
+}
+
+fn reserve_funds(participant: &mut Participant, amount: u64) -> ProgramResult {
+    if participant.wallet_balance < amount {
+        return Err(EnergyMarketError::InsufficientBalance.into());
+    }
+    participant.wallet_balance = participant.wallet_balance.checked_sub(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    participant.reserved_balance = participant.reserved_balance.checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    Ok(())
+}
+
+fn release_funds(participant: &mut Participant, amount: u64) -> ProgramResult {
+    participant.reserved_balance = participant.reserved_balance.checked_sub(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    participant.wallet_balance = participant.wallet_balance.checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    Ok(())
+}
+
+// An expires_at of 0 means the order never expires
+fn is_expired(expires_at: i64, now: i64) -> bool {
+    expires_at != 0 && expires_at < now
+}
+
+fn assert_valid_expiration(expires_at: i64, now: i64) -> ProgramResult {
+    if is_expired(expires_at, now) {
+        return Err(EnergyMarketError::InvalidExpiration.into());
+    }
+    Ok(())
+}
+
+// An empty order never fills, and a zero price leaves open which side pays, so neither is accepted.
+// The full notional must fit in a u64 up front rather than overflowing at match time.
+fn assert_valid_order(config: &MarketConfig, energy_amount: u64, price: i64) -> ProgramResult {
+    if energy_amount == 0 || price == 0 {
+        return Err(EnergyMarketError::InvalidOrderAmount.into());
+    }
+    let max_cost = max_order_cost(config, energy_amount, price);
+    if max_cost > u64::MAX as u128 {
+        msg!("Order of {} at {} could cost {}, more than a balance can hold", energy_amount, price, max_cost);
+        return Err(EnergyMarketError::AmountTooLarge.into());
+    }
+    Ok(())
+}
+
+fn assert_order_increments(config: &MarketConfig, energy_amount: u64, price: i64) -> ProgramResult {
+    if config.price_tick > 0 && !price.unsigned_abs().is_multiple_of(config.price_tick) {
+        return Err(EnergyMarketError::OffTickPrice.into());
+    }
+    if config.lot_size > 0 && !energy_amount.is_multiple_of(config.lot_size) {
+        return Err(EnergyMarketError::OffLotAmount.into());
+    }
+    Ok(())
+}
+
+// The fill options of a new order, as passed to ReportProduction and PostDemand
+struct FillConstraints {
+    min_fill: u64,
+    all_or_nothing: bool,
+}
+
+fn assert_valid_min_fill(fill: &FillConstraints, energy_amount: u64) -> ProgramResult {
+    if fill.min_fill > energy_amount {
+        return Err(EnergyMarketError::InvalidMinFill.into());
+    }
+    Ok(())
+}
+
+fn assert_order_size(config: &MarketConfig, energy_amount: u64) -> ProgramResult {
+    if energy_amount < config.min_order_size {
+        return Err(EnergyMarketError::MinOrderSizeNotMet.into());
+    }
+    if exceeds_max_order_size(config, energy_amount) {
+        return Err(EnergyMarketError::MaxOrderSizeExceeded.into());
+    }
+    Ok(())
+}
+
+// Fills only ever shrink an order, so one above the maximum was placed before the limit was
+// lowered. Remainders below the minimum are the normal result of partial fills and still trade.
+fn exceeds_max_order_size(config: &MarketConfig, energy_amount: u64) -> bool {
+    config.max_order_size > 0 && energy_amount > config.max_order_size
+}
+
+fn assert_valid_order_size_limits(min_order_size: u64, max_order_size: u64) -> ProgramResult {
+    if max_order_size > 0 && min_order_size > max_order_size {
+        return Err(EnergyMarketError::InvalidOrderSizeLimits.into());
+    }
+    Ok(())
+}
+
+fn is_attestation_overdue(production: &EnergyProduction, attestation_timeout: i64, now: i64) -> bool {
+    !production.verified && attestation_timeout > 0 && production.created_at.saturating_add(attestation_timeout) < now
+}
+
+fn is_slot_over(delivery_slot: u32, now: i64) -> bool {
+    delivery_slot_end(delivery_slot) < now
+}
+
+fn assert_valid_delivery_slot(delivery_slot: u32, now: i64) -> ProgramResult {
+    if is_slot_over(delivery_slot, now) {
+        return Err(EnergyMarketError::InvalidDeliverySlot.into());
+    }
+    Ok(())
+}
+
+// The tariff's (floor, ceiling) for the hour of `delivery_slot`, None where disabled
+pub fn tariff_bounds(tariff: &TariffSchedule, delivery_slot: u32) -> (Option<i64>, Option<i64>) {
+    let multiplier_bps = tariff.multiplier_bps[delivery_slot as usize % HOURS_PER_DAY] as i128;
+    let scale = |base: i64| {
+        (base != 0).then(|| (base as i128 * multiplier_bps).div_euclid(10_000).clamp(i64::MIN as i128, i64::MAX as i128) as i64)
+    };
+    (scale(tariff.base_floor_price), scale(tariff.base_ceiling_price))
+}
+
+fn assert_within_tariff(tariff: &TariffSchedule, side: OrderSide, price: i64, delivery_slot: u32) -> ProgramResult {
+    let (floor, ceiling) = tariff_bounds(tariff, delivery_slot);
+    match side {
+        OrderSide::Production if floor.is_some_and(|floor| price < floor) => {
+            msg!("Offer at {} is below the tariff floor of {:?}", price, floor);
+            Err(EnergyMarketError::PriceBelowTariffFloor.into())
+        }
+        OrderSide::Demand if ceiling.is_some_and(|ceiling| price > ceiling) => {
+            msg!("Demand at {} is above the tariff ceiling of {:?}", price, ceiling);
+            Err(EnergyMarketError::PriceAboveTariffCeiling.into())
+        }
+        _ => Ok(()),
+    }
+}
+
+// Some price must pass both bounds in every hour
+fn assert_valid_tariff_schedule(tariff: &TariffSchedule) -> ProgramResult {
+    for hour in 0..HOURS_PER_DAY as u32 {
+        if let (Some(floor), Some(ceiling)) = tariff_bounds(tariff, hour) {
+            if floor > ceiling {
+                return Err(EnergyMarketError::InvalidConfigValue.into());
+            }
+        }
+    }
+    Ok(())
+}
+
+// A shortfall may never pay less than a surplus earns, or under-delivering would pay off
+fn assert_valid_imbalance_config(imbalance: &ImbalanceConfig) -> ProgramResult {
+    if imbalance.surplus_price > imbalance.shortfall_price || imbalance.actuals_timeout < 0 {
+        return Err(EnergyMarketError::InvalidConfigValue.into());
+    }
+    Ok(())
+}
+
+fn assert_valid_governance_config(governance: &GovernanceConfig) -> ProgramResult {
+    if governance.enabled() && !(5_000..=10_000).contains(&governance.majority_bps) {
+        return Err(EnergyMarketError::InvalidConfigValue.into());
+    }
+    Ok(())
+}
+
+// Removes the orders selected by `should_remove` whose owner is among `participants`, freeing the
+// owner's open-order slot. Returns each removed order with the index of its owner.
+fn remove_orders<T>(
+    orders: &mut Vec<T>,
+    side: OrderSide,
+    participants: &mut [Participant],
+    owner_of: impl Fn(&T) -> Pubkey,
+    should_remove: impl Fn(&T) -> bool,
+) -> Result<Vec<(T, usize)>, ProgramError> {
+    let mut removed = Vec::new();
+    let mut index = 0;
+    while index < orders.len() {
+        let owner = owner_of(&orders[index]);
+        match participants.iter().position(|p| p.id == owner) {
+            Some(owner_index) if should_remove(&orders[index]) => {
+                release_order_slot(&mut participants[owner_index], side)?;
+                removed.push((orders.remove(index), owner_index));
+            }
+            _ => index += 1,
+        }
+    }
+    Ok(removed)
+}
+
+fn assert_not_frozen(participant: &Participant) -> ProgramResult {
+    if participant.frozen {
+        return Err(EnergyMarketError::ParticipantFrozen.into());
+    }
+    Ok(())
+}
+
+// Drops every order of the participant in the ledger book plus those among `order_accounts` on
+// account storage, refunding order rent to the wallet and demand escrow to wallet_balance.
+// Returns how many orders were dropped.
+fn drop_participant_orders<'a, 'b>(
+    program_id: &Pubkey,
+    ledger_account: &AccountInfo<'a>,
+    ledger: &mut Ledger,
+    participant: &mut Participant,
+    wallet_account: &AccountInfo<'a>,
+    order_accounts: impl Iterator<Item = &'b AccountInfo<'a>>,
+) -> Result<usize, ProgramError>
+where
+    'a: 'b,
+{
+    let owner = participant.id;
+    let participants = std::slice::from_mut(participant);
+    let demands = remove_orders(&mut ledger.demands, OrderSide::Demand, participants, |d| d.consumer_id, |d| d.consumer_id == owner)?;
+    let productions = remove_orders(
+        &mut ledger.productions, OrderSide::Production, participants, |p| p.producer_id, |p| p.producer_id == owner,
+    )?;
+    for (demand, _) in &demands {
+        release_funds(participant, demand_escrow(demand)?)?;
+    }
+    let mut dropped = demands.len() + productions.len();
+
+    for order_account in order_accounts {
+        let order = load_order_account(program_id, ledger_account, order_account)?;
+        if order.owner != owner {
+            return Err(EnergyMarketError::NotOrderOwner.into());
+        }
+        if order.side == OrderSide::Demand {
+            release_funds(participant, demand_escrow(&order_to_demand(&order))?)?;
+        }
+        close_account(order_account, wallet_account)?;
+        release_order_slot(participant, order.side)?;
+        ledger.open_order_accounts = ledger.open_order_accounts.checked_sub(1)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        dropped += 1;
+    }
+    Ok(dropped)
+}
+
+// Remembers a non-zero client order nonce, rejecting one among the participant's last RECENT_NONCES
+fn record_order_nonce(participant: &mut Participant, client_order_nonce: u64) -> ProgramResult {
+    if client_order_nonce == 0 {
+        return Ok(());
+    }
+    if participant.recent_nonces.contains(&client_order_nonce) {
+        msg!("Order nonce {} was already used", client_order_nonce);
+        return Err(EnergyMarketError::DuplicateOrder.into());
+    }
+    let head = participant.recent_nonce_head as usize % RECENT_NONCES;
+    participant.recent_nonces[head] = client_order_nonce;
+    participant.recent_nonce_head = ((head + 1) % RECENT_NONCES) as u8;
+    Ok(())
+}
+
+fn open_order_count(participant: &mut Participant, side: OrderSide) -> &mut u32 {
+    match side {
+        OrderSide::Demand => &mut participant.open_demands,
+        OrderSide::Production => &mut participant.open_productions,
+    }
+}
+
+fn take_order_slot(participant: &mut Participant, side: OrderSide, config: &MarketConfig) -> ProgramResult {
+    let limit = config.max_open_orders_per_participant;
+    if limit > 0 && participant.open_orders() >= limit {
+        msg!("{:?} already has {} open orders", participant.id, participant.open_orders());
+        return Err(EnergyMarketError::TooManyOpenOrders.into());
+    }
+    let count = open_order_count(participant, side);
+    *count = count.checked_add(1).ok_or(ProgramError::ArithmeticOverflow)?;
+    Ok(())
+}
+
+fn release_order_slot(participant: &mut Participant, side: OrderSide) -> ProgramResult {
+    let count = open_order_count(participant, side);
+    *count = count.checked_sub(1).ok_or(ProgramError::ArithmeticOverflow)?;
+    Ok(())
+}
+
+// Debug builds check after every purge and match run that the order counters of each supplied
+// participant agree with the book. Only the ledger book holds every order, so account storage is
+// not checked.
+fn debug_assert_order_counts(ledger: &Ledger, participants: &[Participant]) {
+    if !cfg!(debug_assertions) || ledger.config.order_storage != OrderStorage::Ledger {
+        return;
+    }
+    for participant in participants {
+        let demands = ledger.demands.iter().filter(|d| d.consumer_id == participant.id).count();
+        let productions = ledger.productions.iter().filter(|p| p.producer_id == participant.id).count();
+        debug_assert_eq!(participant.open_demands as usize, demands, "open demands of {:?}", participant.id);
+        debug_assert_eq!(participant.open_productions as usize, productions, "open productions of {:?}", participant.id);
+    }
+}
+
+// Drops every expired order, every order whose delivery slot has ended and every offer left
+// unattested past the attestation timeout, handing the escrow of dropped demands back to their consumers.
+// An expired order whose owner is not among `participants` stays in the book until a caller
+// supplies that owner's PDA, so escrow and open-order counts are always settled together.
+fn purge_expired_orders(ledger: &mut Ledger, participants: &mut [Participant], now: i64) -> Result<usize, ProgramError> {
+    let expired_demands = remove_orders(
+        &mut ledger.demands, OrderSide::Demand, participants, |d| d.consumer_id, |d| is_expired(d.expires_at, now) || is_slot_over(d.delivery_slot, now),
+    )?;
+    for (demand, consumer_index) in &expired_demands {
+        release_funds(&mut participants[*consumer_index], demand_escrow(demand)?)?;
+    }
+    let attestation_timeout = ledger.config.attestation_timeout;
+    let expired_productions = remove_orders(
+        &mut ledger.productions, OrderSide::Production, participants, |p| p.producer_id, |p| {
+            is_expired(p.expires_at, now)
+                || is_slot_over(p.delivery_slot, now)
+                || is_attestation_overdue(p, attestation_timeout, now)
+        },
+    )?;
+    debug_assert_order_counts(ledger, participants);
+
+    Ok(expired_demands.len() + expired_productions.len())
+}
+
+fn ledger_version(data: &[u8]) -> u8 {
+    data.first().copied().unwrap_or(0)
+}
+
+fn load_ledger(ledger_account: &AccountInfo) -> Result<Ledger, ProgramError> {
+    let data = ledger_account.data.borrow();
+    match ledger_version(&data) {
+        0 => return Err(EnergyMarketError::LedgerNotInitialized.into()),
+        CLOSED_ACCOUNT_TAG => return Err(EnergyMarketError::LedgerClosed.into()),
+        LEDGER_VERSION => {}
+        tag if AccountType::from_tag(tag).is_some() => {
+            msg!("Expected the ledger, got a {:?} account", AccountType::from_tag(tag));
+            return Err(EnergyMarketError::WrongAccountType.into());
+        }
+        version => {
+            msg!("Ledger version {} is not supported, expected {}", version, LEDGER_VERSION);
+            return Err(EnergyMarketError::UnsupportedLedgerVersion.into());
+        }
+    }
+    // The account is usually larger than the serialized ledger, so trailing bytes are ignored
+    Ok(Ledger::deserialize(&mut &data[..])?)
+}
+
+fn save_ledger(ledger: &Ledger, ledger_account: &AccountInfo) -> ProgramResult {
+    assert_writable(ledger_account, "ledger")?;
+    let serialized = ledger.try_to_vec()?;
+    if serialized.len() > ledger_account.data_len() {
+        msg!("Ledger needs {} bytes but the account holds {}", serialized.len(), ledger_account.data_len());
+        return Err(EnergyMarketError::LedgerAccountTooSmall.into());
+    }
+
+    // Zero the tail so a shrinking ledger never leaves stale bytes behind
+    let mut data = ledger_account.data.borrow_mut();
+    data[..serialized.len()].copy_from_slice(&serialized);
+    data[serialized.len()..].fill(0);
+
+    Ok(())
+}
+
+// Orders in the ledger book plus open order accounts; on account storage the book only holds
+// orders loaded for the current instruction, so count once they were saved back
+fn open_orders_in_market(ledger: &Ledger) -> usize {
+    ledger.productions.len() + ledger.demands.len() + ledger.open_order_accounts as usize
+}
+
+fn assert_order_book_capacity(ledger: &Ledger) -> ProgramResult {
+    if open_orders_in_market(ledger) >= ledger.capacity.max_open_orders as usize {
+        return Err(EnergyMarketError::OrderBookFull.into());
+    }
+    Ok(())
+}
+
+// The history is a ring buffer holding at most capacity.max_transactions entries. Once full,
+// history_head points at the oldest entry and each new trade overwrites it, so readers get
+// chronological order from transactions[history_head..] followed by transactions[..history_head].
+// total_trades keeps counting past the capacity so indexers can detect gaps. A trade still
+// awaiting settlement is never dropped: the run fails with HistoryFull instead.
+fn append_transactions(ledger: &mut Ledger, trades: Vec<Transaction>) -> ProgramResult {
+    let capacity = ledger.capacity.max_transactions as usize;
+    if ledger.config.history_policy == HistoryPolicy::Reject && ledger.transactions.len() + trades.len() > capacity {
+        return Err(EnergyMarketError::HistoryFull.into());
+    }
+
+    ledger.total_trades = ledger.total_trades.checked_add(trades.len() as u64)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    if capacity == 0 {
+        if trades.iter().any(|t| t.status == TradeStatus::Matched) {
+            return Err(EnergyMarketError::HistoryFull.into());
+        }
+        return Ok(());
+    }
+
+    for trade in trades {
+        if ledger.transactions.len() < capacity {
+            ledger.transactions.push(trade);
+        } else {
+            let head = ledger.history_head as usize;
+            if ledger.transactions[head].status == TradeStatus::Matched {
+                return Err(EnergyMarketError::HistoryFull.into());
+            }
+            ledger.transactions[head] = trade;
+            ledger.history_head = ((head + 1) % capacity) as u32;
+        }
+    }
+    Ok(())
+}
+
+// The ledger's trade log is optional wherever trades are recorded. It is recognized by its key at
+// the position it may appear in; when it is not there, trades go to the embedded history.
+fn next_trade_log<'a, 'b>(ledger: &Ledger, account_info_iter: &mut std::slice::Iter<'b, AccountInfo<'a>>) -> Option<&'b AccountInfo<'a>> {
+    if ledger.trade_log == Pubkey::default() {
+        return None;
+    }
+    let trade_log_account = account_info_iter.as_slice().first().filter(|account| *account.key == ledger.trade_log)?;
+    account_info_iter.next();
+    Some(trade_log_account)
+}
+
+// With a trade log every trade is appended to it, and only trades still awaiting settlement are
+// kept in the embedded history as well, since that is where settlement looks them up
+fn record_trades(
+    program_id: &Pubkey,
+    ledger_account: &AccountInfo,
+    ledger: &mut Ledger,
+    trade_log_account: Option<&AccountInfo>,
+    trades: Vec<Transaction>,
+) -> ProgramResult {
+    let Some(trade_log_account) = trade_log_account else {
+        return append_transactions(ledger, trades);
+    };
+    append_to_trade_log(program_id, ledger_account, trade_log_account, &trades)?;
+    let (pending, settled): (Vec<_>, Vec<_>) = trades.into_iter().partition(|t| t.status == TradeStatus::Matched);
+    ledger.total_trades = ledger.total_trades.checked_add(settled.len() as u64)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    append_transactions(ledger, pending)
+}
+
+fn append_to_trade_log(program_id: &Pubkey, ledger_account: &AccountInfo, trade_log_account: &AccountInfo, trades: &[Transaction]) -> ProgramResult {
+    assert_account_type(program_id, trade_log_account, AccountType::TradeLog, EnergyMarketError::InvalidTradeLog)?;
+    assert_writable(trade_log_account, "trade log")?;
+    let mut data = trade_log_account.data.borrow_mut();
+    let mut header = TradeLogHeader::deserialize(&mut &data[..])?;
+    if header.ledger != *ledger_account.key {
+        return Err(EnergyMarketError::InvalidTradeLog.into());
+    }
+    let capacity = header.capacity as usize;
+    if header.history_policy == HistoryPolicy::Reject && header.len as usize + trades.len() > capacity {
+        return Err(EnergyMarketError::TradeLogFull.into());
+    }
+    if capacity == 0 {
+        return Ok(());
+    }
+
+    for trade in trades {
+        let offset = TRADE_LOG_HEADER_SIZE + header.cursor as usize * TRANSACTION_SIZE;
+        trade.serialize(&mut &mut data[offset..offset + TRANSACTION_SIZE])?;
+        header.cursor = ((header.cursor as usize + 1) % capacity) as u32;
+        header.len = (header.len + 1).min(header.capacity);
+        header.total_appended = header.total_appended.checked_add(1)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    }
+    header.serialize(&mut &mut data[..TRADE_LOG_HEADER_SIZE])?;
+    Ok(())
+}
+
+fn next_order_id(ledger: &mut Ledger) -> Result<u64, ProgramError> {
+    let order_id = ledger.next_order_id;
+    ledger.next_order_id = order_id.checked_add(1)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    Ok(order_id)
+}
+
+fn initialize_ledger(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    capacity: LedgerCapacity,
+    quote_mint: Option<Pubkey>,
+    config: MarketConfig,
+    oracle: Option<Pubkey>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let payer_account = next_account(account_info_iter, "payer")?;
+    let vault_account = next_account(account_info_iter, "vault")?;
+    let system_program_account = next_account(account_info_iter, "system program")?;
+
+    assert_signer(payer_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    assert_valid_fee_rates(config.fee_bps, config.crank_reward_bps)?;
+    if config.zones.zone_count == 0 {
+        return Err(EnergyMarketError::InvalidZone.into());
+    }
+    assert_valid_order_size_limits(config.min_order_size, config.max_order_size)?;
+    assert_valid_withdrawal_delay(config.withdrawal_delay)?;
+    assert_valid_reputation_config(&config.reputation)?;
+    assert_valid_settlement_config(&config.settlement, oracle.unwrap_or_default())?;
+    assert_valid_loss_config(&config.losses)?;
+    // A new ledger has no grid operator yet to collect the fee
+    if config.grid_fee_per_kwh > 0 {
+        return Err(EnergyMarketError::GridOperatorNotSet.into());
+    }
+    assert_valid_tariff_schedule(&config.tariff)?;
+    assert_valid_imbalance_config(&config.imbalance)?;
+    assert_valid_governance_config(&config.governance)?;
+    if config.collateral_bps > 10_000 || config.insurance_fee_bps > 10_000 || config.attestation_timeout < 0 || config.config_timelock < 0
+        || config.stale_demand_timeout < 0
+    {
+        return Err(EnergyMarketError::InvalidConfigValue.into());
+    }
+
+    let required_space = ledger_space(&capacity);
+    if ledger_account.data_len() < required_space {
+        msg!("Ledger needs at least {} bytes but the account holds {}", required_space, ledger_account.data_len());
+        return Err(EnergyMarketError::LedgerAccountTooSmall.into());
+    }
+
+    let rent = Rent::get()?;
+    if !rent.is_exempt(ledger_account.lamports(), ledger_account.data_len()) {
+        return Err(ProgramError::AccountNotRentExempt);
+    }
+
+    if ledger_version(&ledger_account.data.borrow()) != 0 {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    let (vault_key, vault_bump) = find_vault_address(program_id, ledger_account.key);
+    if vault_key != *vault_account.key {
+        return Err(EnergyMarketError::InvalidVaultAccount.into());
+    }
+    if *system_program_account.key != system_program::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    create_pda_account(
+        program_id,
+        payer_account,
+        vault_account,
+        system_program_account,
+        0,
+        &[VAULT_SEED, ledger_account.key.as_ref(), &[vault_bump]],
+    )?;
+
+    let ledger = Ledger {
+        version: LEDGER_VERSION,
+        admin: *payer_account.key,
+        pending_admin: Pubkey::default(),
+        approvers: [Pubkey::default(); MAX_APPROVERS],
+        approver_count: 0,
+        approval_threshold: 0,
+        grid_operator: Pubkey::default(),
+        oracle: oracle.unwrap_or_default(),
+        trade_log: Pubkey::default(),
+        paused: false,
+        created_at: Clock::get()?.unix_timestamp,
+        vault_bump,
+        quote_mint: quote_mint.unwrap_or_default(),
+        config,
+        pending_config_change: None,
+        demand_response: None,
+        demand_response_count: 0,
+        capacity,
+        next_order_id: 0,
+        last_clearing_price: 0,
+        reference_price: 0,
+        history_head: 0,
+        total_trades: 0,
+        participant_count: 0,
+        open_order_accounts: 0,
+        protocol_fees: 0,
+        insurance_pool: 0,
+        insurance_claims: 0,
+        insurance_drain: None,
+        rewards_pool: 0,
+        reward_epoch: 0,
+        next_proposal_id: 0,
+        proposal: None,
+        stats: MarketStats::default(),
+        price_history: [PriceSample::default(); PRICE_HISTORY_LEN],
+        price_history_head: 0,
+        price_history_len: 0,
+        slot_prices: [SlotPrice::default(); SLOT_PRICE_HISTORY_LEN],
+        slot_price_head: 0,
+        standing_orders: Vec::new(),
+        productions: Vec::new(),
+        demands: Vec::new(),
+        transactions: Vec::new(),
+    };
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+fn register_participant(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    participant_type: ParticipantType,
+    zone: u8,
+    registered_capacity: u64,
+    metadata: ParticipantMetadata,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+    let system_program_account = next_account(account_info_iter, "system program")?;
+
+    assert_signer(wallet_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if *system_program_account.key != system_program::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+
+    let (participant_key, bump) = find_participant_address(program_id, ledger_account.key, wallet_account.key);
+    if participant_key != *participant_account.key {
+        return Err(EnergyMarketError::InvalidParticipantAccount.into());
+    }
+    if participant_account.owner == program_id {
+        return Err(EnergyMarketError::ParticipantAlreadyRegistered.into());
+    }
+    if ledger.participant_count >= ledger.capacity.max_participants {
+        return Err(EnergyMarketError::LedgerFull.into());
+    }
+    if zone >= ledger.config.zones.zone_count {
+        return Err(EnergyMarketError::InvalidZone.into());
+    }
+    assert_valid_metadata(&metadata)?;
+
+    // The wallet pays rent for its own participant account
+    create_pda_account(
+        program_id,
+        wallet_account,
+        participant_account,
+        system_program_account,
+        PARTICIPANT_SIZE,
+        &[PARTICIPANT_SEED, ledger_account.key.as_ref(), wallet_account.key.as_ref(), &[bump]],
+    )?;
+
+    let new_participant = Participant {
+        account_type: AccountType::Participant,
+        bump,
+        id: *wallet_account.key,
+        participant_type,
+        wallet_balance: 0,
+        reserved_balance: 0,
+        open_demands: 0,
+        open_productions: 0,
+        frozen: false,
+        zone,
+        curtailed_until: 0,
+        registered_capacity,
+        collateral_balance: 0,
+        rec_balance: 0,
+        rec_accrual: 0,
+        retired_recs: 0,
+        stored_energy: 0,
+        storage_capacity: 0,
+        storage_efficiency_bps: 10_000,
+        pending_withdrawal: 0,
+        withdrawal_claimable_after: 0,
+        withdrawn_in_window: 0,
+        window_start: 0,
+        withdrawal_limit: None,
+        recent_nonces: [0; RECENT_NONCES],
+        recent_nonce_head: 0,
+        metadata,
+        trades_completed: 0,
+        trades_defaulted: 0,
+        reputation: reputation_score(0, 0),
+        delegate: None,
+        critical_load: false,
+        demand_response_id: 0,
+        committed_reduction: 0,
+        open_forwards: 0,
+        aggregator: None,
+        members: [AggregatorMember::default(); MAX_AGGREGATOR_MEMBERS],
+        member_count: 0,
+        aggregated_order_id: None,
+        aggregated_filled: 0,
+        pooled_balance: 0,
+        net_metering: false,
+        positions: [SlotPosition::default(); MAX_OPEN_POSITIONS],
+        position_count: 0,
+        insurance_claim: 0,
+        staked_balance: 0,
+        stake_unlocks_at: 0,
+        reward_points: 0,
+        last_voted_proposal: None,
+    };
+    save_participant(&new_participant, participant_account)?;
+    emit(&events::ParticipantRegistered {
+        participant: new_participant.id,
+        participant_type: new_participant.participant_type,
+        zone,
+    })?;
+
+    ledger.participant_count = ledger.participant_count.checked_add(1)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Accounts: producer, ledger, producer participant PDA; on account storage a resting order's PDA
+// and the system program, then every other open order PDA of the producer. An immediate order
+// is followed by the resting orders it may cross, see execute_taker_order. Unless distinct_lot is
+// set, a resting offer matching an open one of the producer in everything but its amount is added
+// to that order instead, see mergeable_production.
+#[allow(clippy::too_many_arguments)]
+fn report_energy_production(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    energy_amount: u64,
+    price: i64,
+    expires_at: i64,
+    delivery_slot: u32,
+    energy_source: EnergySource,
+    time_in_force: TimeInForce,
+    fill: FillConstraints,
+    client_order_nonce: u64,
+    distinct_lot: bool,
+) -> ProgramResult {
+    let (accounts, authority_account) = split_order_authority(accounts)?;
+    let account_info_iter = &mut accounts.iter();
+    let producer_account = next_account(account_info_iter, "producer")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let producer_participant_account = next_account(account_info_iter, "producer participant")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_trading_enabled(&ledger)?;
+    assert_time_in_force(&ledger, time_in_force)?;
+
+    let mut producer = load_participant(program_id, ledger_account, producer_participant_account, Some(producer_account.key))?;
+    assert_order_authority(&producer, authority_account)?;
+    if !matches!(producer.participant_type, ParticipantType::Producer | ParticipantType::Prosumer | ParticipantType::Storage) {
+        return Err(EnergyMarketError::InvalidParticipantType.into());
+    }
+    if is_storage(&producer) && energy_amount > producer.stored_energy {
+        return Err(EnergyMarketError::InsufficientStoredEnergy.into());
+    }
+    assert_not_frozen(&producer)?;
+    record_order_nonce(&mut producer, client_order_nonce)?;
+
+    assert_valid_order(&ledger.config, energy_amount, price)?;
+    assert_order_increments(&ledger.config, energy_amount, price)?;
+    assert_order_size(&ledger.config, energy_amount)?;
+    assert_valid_min_fill(&fill, energy_amount)?;
+
+    let created_at = Clock::get()?.unix_timestamp;
+    if producer.curtailed_until > created_at {
+        return Err(EnergyMarketError::ProducerCurtailed.into());
+    }
+    assert_valid_expiration(expires_at, created_at)?;
+    assert_valid_delivery_slot(delivery_slot, created_at)?;
+    assert_within_tariff(&ledger.config.tariff, OrderSide::Production, price, delivery_slot)?;
+
+    let mut production = EnergyProduction {
+        order_id: 0,
+        producer_id: *producer_account.key,
+        energy_amount,
+        price,
+        created_at,
+        expires_at,
+        delivery_slot,
+        zone: producer.zone,
+        verified: ledger.oracle == Pubkey::default(),
+        energy_source,
+        min_fill: fill.min_fill,
+        all_or_nothing: fill.all_or_nothing,
+    };
+
+    if time_in_force == TimeInForce::GoodTilCancelled && !distinct_lot {
+        if let Some(index) = mergeable_production(&ledger, &production) {
+            // The order keeps its queue position, as when ReplaceOrder raises its amount
+            let order_id = ledger.productions[index].order_id;
+            let total = ledger.productions[index].energy_amount.checked_add(energy_amount)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+            let other_orders = producer.open_orders();
+            let open_order_accounts = account_info_iter.by_ref().take(other_orders as usize);
+            let totals = open_offer_totals(program_id, ledger_account, &ledger, &producer, order_id, delivery_slot, other_orders, open_order_accounts)?;
+            assert_offer_limits(&ledger, &producer, &totals, total, price, delivery_slot)?;
+            ledger.productions[index].energy_amount = total;
+            msg!("Added {} to production order {}", energy_amount, order_id);
+            emit(&events::OrderAmended { order_id, owner: production.producer_id, added_amount: energy_amount, energy_amount: total })?;
+            save_participant(&producer, producer_participant_account)?;
+            save_ledger(&ledger, ledger_account)?;
+            return Ok(());
+        }
+    }
+
+    assert_order_book_capacity(&ledger)?;
+    let order_id = next_order_id(&mut ledger)?;
+    production.order_id = order_id;
+
+    // Immediate orders never rest, so they get no order PDA
+    if ledger.config.order_storage == OrderStorage::Accounts && time_in_force == TimeInForce::GoodTilCancelled {
+        let order = OrderAccount {
+            account_type: AccountType::Order,
+            bump: 0,
+            side: OrderSide::Production,
+            order_id,
+            owner: production.producer_id,
+            energy_amount,
+            price,
+            created_at,
+            expires_at,
+            delivery_slot,
+            zone: production.zone,
+            verified: production.verified,
+            energy_source,
+            min_fill: fill.min_fill,
+            all_or_nothing: fill.all_or_nothing,
+            priority: 0,
+            escrow_per_kwh: 0,
+        };
+        create_order_account(program_id, ledger_account, &mut ledger, authority_account, account_info_iter, order)?;
+    } else {
+        ledger.productions.push(production);
+    }
+    let other_orders = producer.open_orders();
+    let open_order_accounts = account_info_iter.by_ref().take(other_orders as usize);
+    let totals = open_offer_totals(program_id, ledger_account, &ledger, &producer, order_id, delivery_slot, other_orders, open_order_accounts)?;
+    assert_offer_limits(&ledger, &producer, &totals, energy_amount, price, delivery_slot)?;
+    take_order_slot(&mut producer, OrderSide::Production, &ledger.config)?;
+    msg!("Production order {} created", order_id);
+
+    if time_in_force == TimeInForce::GoodTilCancelled {
+        save_participant(&producer, producer_participant_account)?;
+    } else {
+        execute_taker_order(
+            program_id, ledger_account, &mut ledger, producer_participant_account, producer, order_id, time_in_force, account_info_iter,
+        )?;
+    }
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// The open offer of the same producer a new resting offer can be added to: one with the same price,
+// delivery slot, zone, expiry, energy source and minimum fill, neither of them all-or-nothing, whose
+// combined amount is still a valid order. Offers awaiting attestation are never merged, so each is
+// attested as reported. On account storage every offer keeps its own PDA and nothing is merged.
+fn mergeable_production(ledger: &Ledger, offer: &EnergyProduction) -> Option<usize> {
+    if ledger.config.order_storage != OrderStorage::Ledger || offer.all_or_nothing || !offer.verified {
+        return None;
+    }
+    ledger.productions.iter().position(|p| {
+        p.producer_id == offer.producer_id
+            && p.price == offer.price
+            && p.delivery_slot == offer.delivery_slot
+            && p.zone == offer.zone
+            && p.expires_at == offer.expires_at
+            && p.energy_source == offer.energy_source
+            && p.min_fill == offer.min_fill
+            && p.verified
+            && !p.all_or_nothing
+            && p.energy_amount.checked_add(offer.energy_amount).is_some_and(|total| {
+                !exceeds_max_order_size(&ledger.config, total) && max_order_cost(&ledger.config, total, offer.price) <= u64::MAX as u128
+            })
+    })
+}
+
+// What the producer's open offers other than `order_id` add up to
+struct OfferTotals {
+    // Energy offered for the delivery slot being checked
+    slot_energy: u64,
+    // Notional of every open offer, across all slots, counting negative prices at their magnitude
+    notional: u128,
+}
+
+// Totals the producer's open offers other than `order_id`. On account storage the PDAs of all
+// `other_orders` other open orders of the producer must be supplied, so none can be left out.
+#[allow(clippy::too_many_arguments)]
+fn open_offer_totals<'a, 'b>(
+    program_id: &Pubkey,
+    ledger_account: &AccountInfo<'a>,
+    ledger: &Ledger,
+    producer: &Participant,
+    order_id: u64,
+    delivery_slot: u32,
+    other_orders: u32,
+    order_accounts: impl Iterator<Item = &'b AccountInfo<'a>>,
+) -> Result<OfferTotals, ProgramError>
+where
+    'a: 'b,
+{
+    let mut totals = OfferTotals { slot_energy: 0, notional: 0 };
+    let mut add_offer = |energy_amount: u64, price: i64, offer_slot: u32| -> ProgramResult {
+        if offer_slot == delivery_slot {
+            totals.slot_energy = totals.slot_energy.checked_add(energy_amount).ok_or(ProgramError::ArithmeticOverflow)?;
+        }
+        totals.notional = totals.notional.checked_add(notional(energy_amount, price))
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        Ok(())
+    };
+
+    if ledger.config.order_storage == OrderStorage::Ledger {
+        for production in &ledger.productions {
+            if production.producer_id == producer.id && production.order_id != order_id {
+                add_offer(production.energy_amount, production.price, production.delivery_slot)?;
+            }
+        }
+        return Ok(totals);
+    }
+
+    let mut seen = Vec::new();
+    for order_account in order_accounts {
+        let order = load_order_account(program_id, ledger_account, order_account)?;
+        if order.owner != producer.id {
+            return Err(EnergyMarketError::NotOrderOwner.into());
+        }
+        if order.order_id == order_id || seen.contains(&order.order_id) {
+            continue;
+        }
+        seen.push(order.order_id);
+        if order.side == OrderSide::Production {
+            add_offer(order.energy_amount, order.price, order.delivery_slot)?;
+        }
+    }
+    if seen.len() != other_orders as usize {
+        return Err(EnergyMarketError::MissingOrderAccounts.into());
+    }
+    Ok(totals)
+}
+
+// An offer of `energy_amount` at `price` for `delivery_slot`, on top of the producer's other
+// offers, must stay within its registered capacity and be backed by enough collateral
+fn assert_offer_limits(
+    ledger: &Ledger,
+    producer: &Participant,
+    totals: &OfferTotals,
+    energy_amount: u64,
+    price: i64,
+    delivery_slot: u32,
+) -> ProgramResult {
+    if totals.slot_energy.checked_add(energy_amount).is_none_or(|total| total > producer.registered_capacity) {
+        msg!("Producer offers {} for slot {} against a capacity of {}", totals.slot_energy, delivery_slot, producer.registered_capacity);
+        return Err(EnergyMarketError::CapacityExceeded.into());
+    }
+    let notional = notional(energy_amount, price).checked_add(totals.notional)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    if producer.collateral_balance < required_collateral(notional, ledger.config.collateral_bps)? {
+        return Err(EnergyMarketError::InsufficientCollateral.into());
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn post_energy_demand(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    energy_amount: u64,
+    price_limit: i64,
+    expires_at: i64,
+    delivery_slot: u32,
+    time_in_force: TimeInForce,
+    fill: FillConstraints,
+    client_order_nonce: u64,
+    priority: u8,
+) -> ProgramResult {
+    let (accounts, authority_account) = split_order_authority(accounts)?;
+    let account_info_iter = &mut accounts.iter();
+    let consumer_account = next_account(account_info_iter, "consumer")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let consumer_participant_account = next_account(account_info_iter, "consumer participant")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_trading_enabled(&ledger)?;
+    assert_time_in_force(&ledger, time_in_force)?;
+
+    assert_order_book_capacity(&ledger)?;
+    assert_valid_order(&ledger.config, energy_amount, price_limit)?;
+    assert_order_increments(&ledger.config, energy_amount, price_limit)?;
+    assert_order_size(&ledger.config, energy_amount)?;
+    assert_valid_min_fill(&fill, energy_amount)?;
+
+    let created_at = Clock::get()?.unix_timestamp;
+    assert_valid_expiration(expires_at, created_at)?;
+    assert_valid_delivery_slot(delivery_slot, created_at)?;
+    assert_within_tariff(&ledger.config.tariff, OrderSide::Demand, price_limit, delivery_slot)?;
+
+    let mut consumer = load_participant(program_id, ledger_account, consumer_participant_account, Some(consumer_account.key))?;
+    assert_order_authority(&consumer, authority_account)?;
+    if !matches!(consumer.participant_type, ParticipantType::Consumer | ParticipantType::Prosumer | ParticipantType::Storage) {
+        return Err(EnergyMarketError::InvalidParticipantType.into());
+    }
+    if is_storage(&consumer) && energy_amount > charge_headroom(&consumer) {
+        return Err(EnergyMarketError::StorageFull.into());
+    }
+    assert_not_frozen(&consumer)?;
+    if consumer.wallet_balance < ledger.config.min_balance_to_post_demand {
+        return Err(EnergyMarketError::BalanceTooLowToPost.into());
+    }
+    if priority > ledger.config.max_unflagged_priority && !consumer.critical_load {
+        msg!("Priority {} needs the critical load flag above {}", priority, ledger.config.max_unflagged_priority);
+        return Err(EnergyMarketError::PriorityNotAllowed.into());
+    }
+    record_order_nonce(&mut consumer, client_order_nonce)?;
+
+    let order_id = next_order_id(&mut ledger)?;
+    let demand = EnergyDemand {
+        order_id,
+        consumer_id: *consumer_account.key,
+        energy_amount,
+        price_limit,
+        created_at,
+        expires_at,
+        delivery_slot,
+        zone: consumer.zone,
+        min_fill: fill.min_fill,
+        all_or_nothing: fill.all_or_nothing,
+        priority,
+        escrow_per_kwh: escrow_per_kwh(&ledger.config, price_limit)?,
+    };
+
+    reserve_funds(&mut consumer, demand_escrow(&demand)?)?;
+
+    if ledger.config.order_storage == OrderStorage::Accounts && time_in_force == TimeInForce::GoodTilCancelled {
+        let order = OrderAccount {
+            account_type: AccountType::Order,
+            bump: 0,
+            side: OrderSide::Demand,
+            order_id,
+            owner: demand.consumer_id,
+            energy_amount,
+            price: price_limit,
+            created_at,
+            expires_at,
+            delivery_slot,
+            zone: demand.zone,
+            verified: true,
+            energy_source: EnergySource::Other,
+            min_fill: fill.min_fill,
+            all_or_nothing: fill.all_or_nothing,
+            priority,
+            escrow_per_kwh: demand.escrow_per_kwh,
+        };
+        create_order_account(program_id, ledger_account, &mut ledger, authority_account, account_info_iter, order)?;
+    } else {
+        ledger.demands.push(demand);
+    }
+    take_order_slot(&mut consumer, OrderSide::Demand, &ledger.config)?;
+    msg!("Demand order {} created", order_id);
+
+    if time_in_force == TimeInForce::GoodTilCancelled {
+        save_participant(&consumer, consumer_participant_account)?;
+    } else {
+        execute_taker_order(
+            program_id, ledger_account, &mut ledger, consumer_participant_account, consumer, order_id, time_in_force, account_info_iter,
+        )?;
+    }
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Both cuts come out of the same fill, so together they may never exceed its notional
+fn assert_valid_fee_rates(fee_bps: u16, crank_reward_bps: u16) -> ProgramResult {
+    if fee_bps as u32 + crank_reward_bps as u32 > 10_000 {
+        return Err(EnergyMarketError::InvalidFeeRate.into());
+    }
+    Ok(())
+}
+
+// The ledger and the signing cranker with its participant PDA are followed by the ledger's trade log,
+// if it has one and the caller wants it used, then the accounts of every order the caller wants
+// crossed, see MatchingAccounts. The crank reward lands in the cranker's wallet_balance.
+fn match_transactions(program_id: &Pubkey, accounts: &[AccountInfo], max_matches: u16) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let cranker_account = next_account(account_info_iter, "cranker")?;
+    let cranker_participant_account = next_account(account_info_iter, "cranker participant")?;
+
+    assert_signer(cranker_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_trading_enabled(&ledger)?;
+    if ledger.config.market_mode != MarketMode::PayAsBid {
+        return Err(EnergyMarketError::WrongMarketMode.into());
+    }
+    let trade_log_account = next_trade_log(&ledger, account_info_iter);
+    let mut matching_accounts = load_matching_accounts(program_id, ledger_account, &mut ledger, account_info_iter)?;
+    let cranker_index = include_participant(
+        program_id, ledger_account, &mut matching_accounts, cranker_participant_account, cranker_account.key,
+    )?;
+
+    // Keepers call this repeatedly until it reports zero new matches
+    let timestamp = Clock::get()?.unix_timestamp;
+    let MatchRun { trades: matched_trades, skipped_for_balance, skipped_for_band } =
+        run_matching(&mut ledger, &mut matching_accounts.participants, timestamp, max_matches as usize)?;
+    msg!("Matched {} trades", matched_trades.len());
+    for trade in &matched_trades {
+        emit(&events::TradeExecuted::from(trade))?;
+    }
+    let trades_executed = u32::try_from(matched_trades.len()).map_err(|_| ProgramError::ArithmeticOverflow)?;
+    let volume = matched_trades.iter().try_fold(0u64, |volume, trade| volume.checked_add(trade.amount))
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    let traded_notional = matched_trades.iter().map(|trade| notional(trade.amount, trade.price)).sum();
+    pay_crank_reward(&ledger, &mut matching_accounts.participants[cranker_index], &matched_trades)?;
+    record_trades(program_id, ledger_account, &mut ledger, trade_log_account, matched_trades)?;
+
+    save_matching_accounts(&mut ledger, &matching_accounts)?;
+    save_ledger(&ledger, ledger_account)?;
+
+    let summary = events::MatchRunCompleted {
+        trades_executed,
+        volume,
+        notional: traded_notional,
+        skipped_for_balance,
+        skipped_for_band,
+        remaining_open_orders: open_orders_in_market(&ledger) as u32,
+    };
+    emit(&summary)?;
+    set_return_data(&summary.try_to_vec()?);
+
+    Ok(())
+}
+
+// Same accounts as match_transactions
+fn run_auction(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let cranker_account = next_account(account_info_iter, "cranker")?;
+    let cranker_participant_account = next_account(account_info_iter, "cranker participant")?;
+
+    assert_signer(cranker_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_trading_enabled(&ledger)?;
+    if ledger.config.market_mode != MarketMode::UniformPrice {
+        return Err(EnergyMarketError::WrongMarketMode.into());
+    }
+    let trade_log_account = next_trade_log(&ledger, account_info_iter);
+    let mut matching_accounts = load_matching_accounts(program_id, ledger_account, &mut ledger, account_info_iter)?;
+    let cranker_index = include_participant(
+        program_id, ledger_account, &mut matching_accounts, cranker_participant_account, cranker_account.key,
+    )?;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+    let matched_trades = run_matching(&mut ledger, &mut matching_accounts.participants, timestamp, usize::MAX)?.trades;
+    msg!("Auction cleared {} trades at {}", matched_trades.len(), ledger.last_clearing_price);
+    for trade in &matched_trades {
+        emit(&events::TradeExecuted::from(trade))?;
+    }
+    pay_crank_reward(&ledger, &mut matching_accounts.participants[cranker_index], &matched_trades)?;
+    record_trades(program_id, ledger_account, &mut ledger, trade_log_account, matched_trades)?;
+
+    save_matching_accounts(&mut ledger, &matching_accounts)?;
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// RegisterParticipant's accounts followed by Deposit's from the vault on. Both legs run in one
+// instruction, so a failed deposit also rolls back the registration.
+#[allow(clippy::too_many_arguments)]
+fn register_and_deposit(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    participant_type: ParticipantType,
+    zone: u8,
+    registered_capacity: u64,
+    metadata: ParticipantMetadata,
+    amount: u64,
+) -> ProgramResult {
+    if accounts.len() < 5 {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    let (register_accounts, deposit_tail) = accounts.split_at(4);
+    register_participant(program_id, register_accounts, participant_type, zone, registered_capacity, metadata)?;
+
+    let deposit_accounts: Vec<AccountInfo> = register_accounts[..3].iter()
+        .chain(deposit_tail)
+        .cloned()
+        .collect();
+    deposit(program_id, &deposit_accounts, amount)
+}
+
+fn deposit(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let participant_account = next_account(account_info_iter, "participant")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_state_account = next_account(account_info_iter, "participant state")?;
+    let vault_account = next_account(account_info_iter, "vault")?;
+
+    assert_signer(participant_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // The ledger is only read here, so deposits from different wallets can run in parallel
+    let ledger = load_ledger(ledger_account)?;
+    assert_vault(program_id, ledger_account, &ledger, vault_account)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_state_account, Some(participant_account.key))?;
+    if amount < ledger.config.min_deposit {
+        msg!("Deposits must be at least {}", ledger.config.min_deposit);
+        return Err(EnergyMarketError::DepositTooSmall.into());
+    }
+
+    transfer_to_vault(&ledger, participant_account, vault_account, account_info_iter, amount)?;
+
+    participant.wallet_balance = participant.wallet_balance.checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    save_participant(&participant, participant_state_account)?;
+    emit(&events::DepositMade {
+        participant: participant.id,
+        amount,
+        wallet_balance: participant.wallet_balance,
+    })?;
+
+    Ok(())
+}
+
+// Pays out immediately. `amount` of None withdraws the whole free balance, so no dust is left
+// behind. Above the ledger's threshold a withdrawal delay forces the two-step path instead.
+fn withdraw(program_id: &Pubkey, accounts: &[AccountInfo], amount: Option<u64>) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let participant_account = next_account(account_info_iter, "participant")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_state_account = next_account(account_info_iter, "participant state")?;
+    let destination_account = next_account(account_info_iter, "destination")?;
+    let vault_account = next_account(account_info_iter, "vault")?;
+
+    assert_signer(participant_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let ledger = load_ledger(ledger_account)?;
+    assert_vault(program_id, ledger_account, &ledger, vault_account)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_state_account, Some(participant_account.key))?;
+    assert_not_frozen(&participant)?;
+    assert_no_pooled_funds(&participant)?;
+
+    let amount = amount.unwrap_or(participant.wallet_balance);
+    if ledger.config.withdrawal_delay > 0 && amount > ledger.config.withdrawal_threshold {
+        return Err(EnergyMarketError::WithdrawalRequestRequired.into());
+    }
+    if participant.wallet_balance < amount {
+        return Err(EnergyMarketError::InsufficientBalance.into());
+    }
+    participant.wallet_balance = participant.wallet_balance.checked_sub(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    count_withdrawal(&ledger, &mut participant, amount, Clock::get()?.unix_timestamp)?;
+
+    transfer_from_vault(&ledger, ledger_account, vault_account, destination_account, account_info_iter, amount)?;
+
+    msg!("Withdrew {} from {:?} to {:?}", amount, participant_account.key, destination_account.key);
+
+    save_participant(&participant, participant_state_account)?;
+    emit(&events::WithdrawalMade {
+        participant: participant.id,
+        destination: *destination_account.key,
+        amount,
+        wallet_balance: participant.wallet_balance,
+    })?;
+
+    Ok(())
+}
+
+pub const WITHDRAWAL_WINDOW_SECONDS: i64 = 86_400;
+
+// Counts `amount` against the participant's withdrawal limit for the current window, starting a
+// new window once the last one is a day old. Claimed requests count when they are paid out.
+fn count_withdrawal(ledger: &Ledger, participant: &mut Participant, amount: u64, now: i64) -> ProgramResult {
+    if now.saturating_sub(participant.window_start) >= WITHDRAWAL_WINDOW_SECONDS {
+        participant.window_start = now;
+        participant.withdrawn_in_window = 0;
+    }
+    let withdrawn = participant.withdrawn_in_window.checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    let limit = participant.withdrawal_limit.unwrap_or(ledger.config.withdrawal_limit);
+    if limit > 0 && withdrawn > limit {
+        msg!(
+            "Withdrawal limit is {}; {} remains until {}",
+            limit,
+            limit.saturating_sub(participant.withdrawn_in_window),
+            participant.window_start.saturating_add(WITHDRAWAL_WINDOW_SECONDS),
+        );
+        return Err(EnergyMarketError::WithdrawalLimitExceeded.into());
+    }
+    participant.withdrawn_in_window = withdrawn;
+    Ok(())
+}
+
+fn assert_valid_withdrawal_delay(withdrawal_delay: i64) -> ProgramResult {
+    if withdrawal_delay < 0 {
+        return Err(EnergyMarketError::InvalidWithdrawalDelay.into());
+    }
+    Ok(())
+}
+
+// Moves funds out of the free balance into a single pending request; they stay in the vault until
+// claimed, and return to the free balance if the admin vetoes the request
+fn request_withdrawal(program_id: &Pubkey, accounts: &[AccountInfo], amount: Option<u64>) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    assert_signer(wallet_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let ledger = load_ledger(ledger_account)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, Some(wallet_account.key))?;
+    assert_not_frozen(&participant)?;
+    assert_no_pooled_funds(&participant)?;
+    if participant.pending_withdrawal > 0 {
+        return Err(EnergyMarketError::WithdrawalPending.into());
+    }
+
+    let amount = amount.unwrap_or(participant.wallet_balance);
+    if amount == 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+    participant.wallet_balance = participant.wallet_balance.checked_sub(amount)
+        .ok_or(EnergyMarketError::InsufficientBalance)?;
+    participant.pending_withdrawal = amount;
+    participant.withdrawal_claimable_after = Clock::get()?.unix_timestamp
+        .checked_add(ledger.config.withdrawal_delay)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    msg!("Withdrawal of {} claimable after {}", amount, participant.withdrawal_claimable_after);
+
+    save_participant(&participant, participant_account)?;
+    emit(&events::WithdrawalRequested {
+        participant: participant.id,
+        amount,
+        claimable_after: participant.withdrawal_claimable_after,
+    })?;
+
+    Ok(())
+}
+
+// Pays out a pending request once its delay has passed. Takes the same accounts as Withdraw.
+fn claim_withdrawal(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+    let destination_account = next_account(account_info_iter, "destination")?;
+    let vault_account = next_account(account_info_iter, "vault")?;
+
+    assert_signer(wallet_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let ledger = load_ledger(ledger_account)?;
+    assert_vault(program_id, ledger_account, &ledger, vault_account)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, Some(wallet_account.key))?;
+    assert_not_frozen(&participant)?;
+
+    let amount = participant.pending_withdrawal;
+    if amount == 0 {
+        return Err(EnergyMarketError::NoPendingWithdrawal.into());
+    }
+    let now = Clock::get()?.unix_timestamp;
+    if now < participant.withdrawal_claimable_after {
+        return Err(EnergyMarketError::WithdrawalLocked.into());
+    }
+    count_withdrawal(&ledger, &mut participant, amount, now)?;
+    participant.pending_withdrawal = 0;
+    participant.withdrawal_claimable_after = 0;
+
+    transfer_from_vault(&ledger, ledger_account, vault_account, destination_account, account_info_iter, amount)?;
+
+    msg!("Claimed withdrawal of {} from {:?} to {:?}", amount, wallet_account.key, destination_account.key);
+
+    save_participant(&participant, participant_account)?;
+    emit(&events::WithdrawalMade {
+        participant: participant.id,
+        destination: *destination_account.key,
+        amount,
+        wallet_balance: participant.wallet_balance,
+    })?;
+
+    Ok(())
+}
+
+fn veto_withdrawal(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, None)?;
+
+    let amount = participant.pending_withdrawal;
+    if amount == 0 {
+        return Err(EnergyMarketError::NoPendingWithdrawal.into());
+    }
+    if Clock::get()?.unix_timestamp >= participant.withdrawal_claimable_after {
+        return Err(EnergyMarketError::VetoWindowClosed.into());
+    }
+    participant.wallet_balance = participant.wallet_balance.checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    participant.pending_withdrawal = 0;
+    participant.withdrawal_claimable_after = 0;
+    msg!("Vetoed withdrawal of {} by {:?}", amount, participant.id);
+
+    save_participant(&participant, participant_account)?;
+    emit(&events::WithdrawalVetoed { participant: participant.id, amount })?;
+
+    Ok(())
+}
+
+fn cancel_demand(program_id: &Pubkey, accounts: &[AccountInfo], order_id: u64) -> ProgramResult {
+    let (accounts, authority_account) = split_order_authority(accounts)?;
+    let account_info_iter = &mut accounts.iter();
+    let consumer_account = next_account(account_info_iter, "consumer")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let consumer_participant_account = next_account(account_info_iter, "consumer participant")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    let mut consumer = load_participant(program_id, ledger_account, consumer_participant_account, Some(consumer_account.key))?;
+    assert_order_authority(&consumer, authority_account)?;
+
+    let demand = if ledger.config.order_storage == OrderStorage::Accounts {
+        let order = take_order_account(
+            program_id, ledger_account, &mut ledger, consumer_account, account_info_iter, OrderSide::Demand, order_id,
+        )?;
+        order_to_demand(&order)
+    } else {
+        let index = ledger.demands.iter().position(|d| d.order_id == order_id)
+            .ok_or(EnergyMarketError::OrderNotFound)?;
+        if ledger.demands[index].consumer_id != *consumer_account.key {
+            return Err(EnergyMarketError::NotOrderOwner.into());
+        }
+        ledger.demands.remove(index)
+    };
+    release_funds(&mut consumer, demand_escrow(&demand)?)?;
+    release_order_slot(&mut consumer, OrderSide::Demand)?;
+    emit(&events::OrderCancelled {
+        order_id,
+        owner: demand.consumer_id,
+        side: OrderSide::Demand,
+        remaining_amount: demand.energy_amount,
+    })?;
+
+    save_participant(&consumer, consumer_participant_account)?;
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+fn cancel_production(program_id: &Pubkey, accounts: &[AccountInfo], order_id: u64) -> ProgramResult {
+    let (accounts, authority_account) = split_order_authority(accounts)?;
+    let account_info_iter = &mut accounts.iter();
+    let producer_account = next_account(account_info_iter, "producer")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let producer_participant_account = next_account(account_info_iter, "producer participant")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    let mut producer = load_participant(program_id, ledger_account, producer_participant_account, Some(producer_account.key))?;
+    assert_order_authority(&producer, authority_account)?;
+
+    // Matched quantity has already been deducted, so this only removes what is still on offer
+    let production = if ledger.config.order_storage == OrderStorage::Accounts {
+        let order = take_order_account(
+            program_id, ledger_account, &mut ledger, producer_account, account_info_iter, OrderSide::Production, order_id,
+        )?;
+        order_to_production(&order)
+    } else {
+        let index = ledger.productions.iter().position(|p| p.order_id == order_id)
+            .ok_or(EnergyMarketError::OrderNotFound)?;
+        if ledger.productions[index].producer_id != *producer_account.key {
+            return Err(EnergyMarketError::NotOrderOwner.into());
+        }
+        ledger.productions.remove(index)
+    };
+    release_order_slot(&mut producer, OrderSide::Production)?;
+    msg!("Cancelled production {} with {} remaining", order_id, production.energy_amount);
+    emit(&events::OrderCancelled {
+        order_id,
+        owner: production.producer_id,
+        side: OrderSide::Production,
+        remaining_amount: production.energy_amount,
+    })?;
+
+    save_participant(&producer, producer_participant_account)?;
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Reprices and resizes an open order in place; `new_amount` replaces what is still unfilled. An
+// order keeps its place in the queue unless the new price is better for the counterparty, a lower
+// ask or a higher bid, in which case it queues behind the orders already resting at that price as
+// if it had just been posted. A fully filled order is gone from the book and fails with
+// OrderNotFound. Accounts: owner, ledger, owner participant PDA, then on account storage the
+// order's PDA followed, for a production, by every other open order PDA of the producer.
+fn replace_order(program_id: &Pubkey, accounts: &[AccountInfo], order_id: u64, new_price: i64, new_amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner_account = next_account(account_info_iter, "owner")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    assert_signer(owner_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_trading_enabled(&ledger)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, Some(owner_account.key))?;
+    assert_not_frozen(&participant)?;
+    assert_no_pooled_funds(&participant)?;
+
+    assert_valid_order(&ledger.config, new_amount, new_price)?;
+    assert_order_increments(&ledger.config, new_amount, new_price)?;
+    assert_order_size(&ledger.config, new_amount)?;
+    let now = Clock::get()?.unix_timestamp;
+
+    let priority_reset;
+    let side;
+    if ledger.config.order_storage == OrderStorage::Accounts {
+        let order_account = next_account(account_info_iter, "order")?;
+        let mut order = load_order_account(program_id, ledger_account, order_account)?;
+        if order.order_id != order_id {
+            return Err(EnergyMarketError::OrderNotFound.into());
+        }
+        if order.owner != *owner_account.key {
+            return Err(EnergyMarketError::NotOrderOwner.into());
+        }
+        side = order.side;
+        priority_reset = match order.side {
+            OrderSide::Production => {
+                let mut production = order_to_production(&order);
+                let reset = replace_production(
+                    program_id, ledger_account, &ledger, &participant, &mut production, new_price, new_amount, now, account_info_iter,
+                )?;
+                order.verified = production.verified;
+                order.created_at = production.created_at;
+                reset
+            }
+            OrderSide::Demand => {
+                let mut demand = order_to_demand(&order);
+                let reset = replace_demand(&ledger.config, &mut participant, &mut demand, new_price, new_amount, now)?;
+                order.created_at = demand.created_at;
+                order.escrow_per_kwh = demand.escrow_per_kwh;
+                reset
+            }
+        };
+        order.price = new_price;
+        order.energy_amount = new_amount;
+        order.serialize(&mut &mut order_account.data.borrow_mut()[..])?;
+    } else if let Some(index) = ledger.productions.iter().position(|p| p.order_id == order_id) {
+        // Taken out while it is checked so the producer's other offers can be totalled around it
+        let mut production = ledger.productions.remove(index);
+        if production.producer_id != *owner_account.key {
+            return Err(EnergyMarketError::NotOrderOwner.into());
+        }
+        side = OrderSide::Production;
+        priority_reset = replace_production(
+            program_id, ledger_account, &ledger, &participant, &mut production, new_price, new_amount, now, account_info_iter,
+        )?;
+        ledger.productions.insert(index, production);
+    } else if let Some(demand) = ledger.demands.iter_mut().find(|d| d.order_id == order_id) {
+        if demand.consumer_id != *owner_account.key {
+            return Err(EnergyMarketError::NotOrderOwner.into());
+        }
+        side = OrderSide::Demand;
+        priority_reset = replace_demand(&ledger.config, &mut participant, demand, new_price, new_amount, now)?;
+    } else {
+        return Err(EnergyMarketError::OrderNotFound.into());
+    }
+
+    msg!("Order {} replaced with {} at {}", order_id, new_amount, new_price);
+    emit(&events::OrderReplaced {
+        order_id,
+        owner: *owner_account.key,
+        side,
+        price: new_price,
+        energy_amount: new_amount,
+        priority_reset,
+    })?;
+
+    save_participant(&participant, participant_account)?;
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Applies a replacement to a production taken out of the book, rechecking it as ReportProduction
+// would. More energy than before needs a fresh attestation. Returns whether priority was reset.
+#[allow(clippy::too_many_arguments)]
+fn replace_production<'a, 'b>(
+    program_id: &Pubkey,
+    ledger_account: &AccountInfo<'a>,
+    ledger: &Ledger,
+    producer: &Participant,
+    production: &mut EnergyProduction,
+    new_price: i64,
+    new_amount: u64,
+    now: i64,
+    order_accounts: impl Iterator<Item = &'b AccountInfo<'a>>,
+) -> Result<bool, ProgramError>
+where
+    'a: 'b,
+{
+    if production.min_fill > new_amount {
+        return Err(EnergyMarketError::InvalidMinFill.into());
+    }
+    assert_within_tariff(&ledger.config.tariff, OrderSide::Production, new_price, production.delivery_slot)?;
+    if is_storage(producer) && new_amount > producer.stored_energy {
+        return Err(EnergyMarketError::InsufficientStoredEnergy.into());
+    }
+    if new_amount > production.energy_amount {
+        if producer.curtailed_until > now {
+            return Err(EnergyMarketError::ProducerCurtailed.into());
+        }
+        production.verified = ledger.oracle == Pubkey::default();
+    }
+    let other_orders = producer.open_orders().checked_sub(1).ok_or(ProgramError::ArithmeticOverflow)?;
+    let totals = open_offer_totals(
+        program_id, ledger_account, ledger, producer, production.order_id, production.delivery_slot, other_orders, order_accounts,
+    )?;
+    assert_offer_limits(ledger, producer, &totals, new_amount, new_price, production.delivery_slot)?;
+
+    let priority_reset = new_price < production.price;
+    if priority_reset {
+        production.created_at = now;
+    }
+    production.price = new_price;
+    production.energy_amount = new_amount;
+    Ok(priority_reset)
+}
+
+// Applies a replacement to a demand, moving the difference between its old and new escrow
+// between the consumer's wallet and reserved balances. Returns whether priority was reset.
+fn replace_demand(
+    config: &MarketConfig,
+    consumer: &mut Participant,
+    demand: &mut EnergyDemand,
+    new_price: i64,
+    new_amount: u64,
+    now: i64,
+) -> Result<bool, ProgramError> {
+    if demand.min_fill > new_amount {
+        return Err(EnergyMarketError::InvalidMinFill.into());
+    }
+    assert_within_tariff(&config.tariff, OrderSide::Demand, new_price, demand.delivery_slot)?;
+    if is_storage(consumer) && new_amount > charge_headroom(consumer) {
+        return Err(EnergyMarketError::StorageFull.into());
+    }
+
+    let old_escrow = demand_escrow(demand)?;
+    let priority_reset = new_price > demand.price_limit;
+    if priority_reset {
+        demand.created_at = now;
+    }
+    demand.price_limit = new_price;
+    demand.energy_amount = new_amount;
+    demand.escrow_per_kwh = escrow_per_kwh(config, new_price)?;
+    let new_escrow = demand_escrow(demand)?;
+    if new_escrow > old_escrow {
+        reserve_funds(consumer, new_escrow - old_escrow)?;
+    } else {
+        release_funds(consumer, old_escrow - new_escrow)?;
+    }
+    Ok(priority_reset)
+}
+
+fn prune_expired_orders(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    let mut matching_accounts = load_matching_accounts(program_id, ledger_account, &mut ledger, account_info_iter)?;
+
+    let expired = purge_expired_orders(&mut ledger, &mut matching_accounts.participants, Clock::get()?.unix_timestamp)?;
+    msg!("Purged {} expired orders", expired);
+
+    save_matching_accounts(&mut ledger, &matching_accounts)?;
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Reallocs the ledger account to `new_size`, with the funding account topping up rent exemption
+fn realloc_ledger<'a>(
+    ledger_account: &AccountInfo<'a>,
+    funding_account: &AccountInfo<'a>,
+    system_program_account: &AccountInfo<'a>,
+    new_size: usize,
+) -> ProgramResult {
+    if *system_program_account.key != system_program::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if new_size.saturating_sub(ledger_account.data_len()) > MAX_PERMITTED_DATA_INCREASE {
+        return Err(ProgramError::InvalidRealloc);
+    }
+
+    let top_up = Rent::get()?.minimum_balance(new_size).saturating_sub(ledger_account.lamports());
+    if top_up > 0 {
+        assert_signer(funding_account)?;
+        invoke(
+            &system_instruction::transfer(funding_account.key, ledger_account.key, top_up),
+            &[funding_account.clone(), ledger_account.clone(), system_program_account.clone()],
+        )?;
+    }
+    ledger_account.realloc(new_size, false)
+}
+
+// Grows (or trims) the ledger account and raises its capacity limits. The funding account tops up
+// rent exemption for the new size. A single call can grow the account by at most
+// MAX_PERMITTED_DATA_INCREASE bytes, so large resizes are split over several calls.
+fn resize_ledger(program_id: &Pubkey, accounts: &[AccountInfo], new_size: u32, capacity: LedgerCapacity) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let funding_account = next_account(account_info_iter, "funding")?;
+    let system_program_account = next_account(account_info_iter, "system program")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+
+    // Limits only ever grow; shrinking them could strand orders or history already stored
+    let current = ledger.capacity;
+    if capacity.max_participants < current.max_participants
+        || capacity.max_open_orders < current.max_open_orders
+        || capacity.max_transactions < current.max_transactions
+    {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let new_size = new_size as usize;
+    let serialized_len = ledger.try_to_vec()?.len();
+    if new_size < ledger_space(&capacity) || new_size < serialized_len {
+        msg!("Ledger needs at least {} bytes", ledger_space(&capacity).max(serialized_len));
+        return Err(EnergyMarketError::LedgerAccountTooSmall.into());
+    }
+
+    realloc_ledger(ledger_account, funding_account, system_program_account, new_size)?;
+
+    // A wrapped history is rotated back into chronological order so the larger ring keeps
+    // appending from the end instead of overwriting entries it now has room for
+    if capacity.max_transactions > current.max_transactions {
+        let head = ledger.history_head as usize;
+        ledger.transactions.rotate_left(head);
+        ledger.history_head = 0;
+    }
+    ledger.capacity = capacity;
+    msg!("Resized ledger to {} bytes", new_size);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Rewrites a version 1 ledger in the current layout, growing the account when the new layout
+// needs more room. Balances, open orders and history carry over unchanged.
+fn migrate_ledger(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let funding_account = next_account(account_info_iter, "funding")?;
+    let system_program_account = next_account(account_info_iter, "system program")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let version = ledger_version(&ledger_account.data.borrow());
+    if version != 1 {
+        msg!("Ledger version {} cannot be migrated", version);
+        return Err(EnergyMarketError::UnsupportedLedgerVersion.into());
+    }
+    let ledger_v1 = legacy::LedgerV1::deserialize(&mut &ledger_account.data.borrow()[..])?;
+    if !admin_account.is_signer || ledger_v1.admin != *admin_account.key {
+        return Err(EnergyMarketError::Unauthorized.into());
+    }
+
+    let ledger = legacy::migrate_ledger_v1(ledger_v1, Clock::get()?.unix_timestamp)?;
+    let required_space = ledger_space(&ledger.capacity).max(ledger.try_to_vec()?.len());
+    if ledger_account.data_len() < required_space {
+        realloc_ledger(ledger_account, funding_account, system_program_account, required_space)?;
+    }
+    msg!("Migrated ledger to version {}", LEDGER_VERSION);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+fn set_fee(program_id: &Pubkey, accounts: &[AccountInfo], fee_bps: u16) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    assert_no_timelock(&ledger)?;
+    assert_valid_fee_rates(fee_bps, ledger.config.crank_reward_bps)?;
+
+    ledger.config.fee_bps = fee_bps;
+    msg!("Protocol fee set to {} bps", fee_bps);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+fn assert_valid_settlement_config(settlement: &SettlementConfig, oracle: Pubkey) -> ProgramResult {
+    if settlement.default_penalty_bps > 10_000 {
+        return Err(EnergyMarketError::InvalidFeeRate.into());
+    }
+    if settlement.delivery_timeout < 0 {
+        return Err(EnergyMarketError::InvalidConfigValue.into());
+    }
+    if settlement.deferred && settlement.confirmer == DeliveryConfirmer::Oracle && oracle == Pubkey::default() {
+        return Err(EnergyMarketError::InvalidOracle.into());
+    }
+    Ok(())
+}
+
+// Changes one tunable parameter, checked against the same bounds InitializeLedger enforces. Takes
+// effect from the next instruction on: matching runs with the new value, while trades already
+// matched keep the deadlines and escrow they were given.
+fn update_config(program_id: &Pubkey, accounts: &[AccountInfo], update: ConfigUpdate) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    assert_no_timelock(&ledger)?;
+    assert_valid_config_update(&ledger, update)?;
+
+    apply_config_update(&mut ledger, update)?;
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+fn assert_valid_config_update(ledger: &Ledger, update: ConfigUpdate) -> ProgramResult {
+    match update {
+        ConfigUpdate::FeeBps(fee_bps) => assert_valid_fee_rates(fee_bps, ledger.config.crank_reward_bps),
+        ConfigUpdate::CrankRewardBps(crank_reward_bps) => assert_valid_fee_rates(ledger.config.fee_bps, crank_reward_bps),
+        ConfigUpdate::Settlement(settlement) => assert_valid_settlement_config(&settlement, ledger.oracle),
+        ConfigUpdate::Losses(losses) => assert_valid_loss_config(&losses),
+        ConfigUpdate::GridFeePerKwh(fee) if fee > 0 && ledger.grid_operator == Pubkey::default() => {
+            Err(EnergyMarketError::GridOperatorNotSet.into())
+        }
+        ConfigUpdate::CollateralBps(value) | ConfigUpdate::InsuranceFeeBps(value) if value > 10_000 => {
+            Err(EnergyMarketError::InvalidConfigValue.into())
+        }
+        ConfigUpdate::AttestationTimeout(value) | ConfigUpdate::ConfigTimelock(value) | ConfigUpdate::StaleDemandTimeout(value)
+            if value < 0 =>
+        {
+            Err(EnergyMarketError::InvalidConfigValue.into())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn apply_config_update(ledger: &mut Ledger, update: ConfigUpdate) -> ProgramResult {
+    let previous = update.current(&ledger.config);
+    update.apply(&mut ledger.config);
+    msg!("Config updated from {:?} to {:?}", previous, update);
+    emit(&events::ConfigUpdated { previous, update })
+}
+
+fn assert_no_timelock(ledger: &Ledger) -> ProgramResult {
+    if ledger.config.config_timelock > 0 {
+        return Err(EnergyMarketError::ConfigTimelocked.into());
+    }
+    Ok(())
+}
+
+// Records a config update to take effect at effective_at, which must leave participants at least
+// config_timelock seconds to react. Works without a timelock too, as a scheduled change.
+fn propose_config_change(program_id: &Pubkey, accounts: &[AccountInfo], update: ConfigUpdate, effective_at: i64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    if ledger.pending_config_change.is_some() {
+        return Err(EnergyMarketError::ConfigChangePending.into());
+    }
+    assert_valid_config_update(&ledger, update)?;
+    let earliest = Clock::get()?.unix_timestamp.checked_add(ledger.config.config_timelock)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    if effective_at < earliest {
+        return Err(EnergyMarketError::InvalidEffectiveTime.into());
+    }
+
+    ledger.pending_config_change = Some(PendingConfigChange { update, effective_at });
+    msg!("Proposed {:?}, effective at {}", update, effective_at);
+
+    save_ledger(&ledger, ledger_account)?;
+    emit(&events::ConfigChangeProposed { update, effective_at })?;
+
+    Ok(())
+}
+
+// Permissionless once the pending change is effective. The update is validated again, as the
+// parameters it is checked against may have changed since it was proposed.
+fn apply_config_change(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    let pending = ledger.pending_config_change.ok_or(EnergyMarketError::NoPendingConfigChange)?;
+    if Clock::get()?.unix_timestamp < pending.effective_at {
+        return Err(EnergyMarketError::ConfigChangeNotReady.into());
+    }
+    assert_valid_config_update(&ledger, pending.update)?;
+
+    ledger.pending_config_change = None;
+    apply_config_update(&mut ledger, pending.update)?;
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+fn cancel_config_change(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    let pending = ledger.pending_config_change.take().ok_or(EnergyMarketError::NoPendingConfigChange)?;
+    msg!("Cancelled proposed {:?}", pending.update);
+
+    save_ledger(&ledger, ledger_account)?;
+    emit(&events::ConfigChangeCancelled { update: pending.update, effective_at: pending.effective_at })?;
+
+    Ok(())
+}
+
+// Applies to requests made from now on; pending requests keep the claim time they were given
+fn set_withdrawal_delay(program_id: &Pubkey, accounts: &[AccountInfo], withdrawal_delay: i64, withdrawal_threshold: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    assert_valid_withdrawal_delay(withdrawal_delay)?;
+
+    ledger.config.withdrawal_delay = withdrawal_delay;
+    ledger.config.withdrawal_threshold = withdrawal_threshold;
+    msg!("Withdrawal delay set to {}s above {}", withdrawal_delay, withdrawal_threshold);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+fn set_deposit_limits(program_id: &Pubkey, accounts: &[AccountInfo], min_deposit: u64, min_balance_to_post_demand: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+
+    ledger.config.min_deposit = min_deposit;
+    ledger.config.min_balance_to_post_demand = min_balance_to_post_demand;
+    msg!("Minimum deposit set to {}, minimum balance to post a demand to {}", min_deposit, min_balance_to_post_demand);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Losing everything injected would deliver nothing
+fn assert_valid_loss_config(losses: &LossConfig) -> ProgramResult {
+    if losses.loss_factor_bps >= 10_000 || losses.inter_zone_loss_bps >= 10_000 {
+        return Err(EnergyMarketError::InvalidConfigValue.into());
+    }
+    Ok(())
+}
+
+fn assert_valid_reputation_config(reputation: &ReputationConfig) -> ProgramResult {
+    if reputation.min_reputation > REPUTATION_SCALE {
+        return Err(EnergyMarketError::InvalidReputationConfig.into());
+    }
+    Ok(())
+}
+
+fn set_reputation_config(program_id: &Pubkey, accounts: &[AccountInfo], reputation: ReputationConfig) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    assert_valid_reputation_config(&reputation)?;
+
+    ledger.config.reputation = reputation;
+    msg!("Reputation config set to {:?}", reputation);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+fn set_withdrawal_limit(program_id: &Pubkey, accounts: &[AccountInfo], withdrawal_limit: Option<u64>) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, None)?;
+
+    participant.withdrawal_limit = withdrawal_limit;
+    msg!("Withdrawal limit of {:?} set to {:?}", participant.id, withdrawal_limit);
+
+    save_participant(&participant, participant_account)?;
+
+    Ok(())
+}
+
+// Only applies to orders placed from now on; orders already in the book keep trading as they are
+fn set_order_increments(program_id: &Pubkey, accounts: &[AccountInfo], price_tick: u64, lot_size: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+
+    ledger.config.price_tick = price_tick;
+    ledger.config.lot_size = lot_size;
+    msg!("Price tick set to {}, lot size to {}", price_tick, lot_size);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+fn set_order_size_limits(program_id: &Pubkey, accounts: &[AccountInfo], min_order_size: u64, max_order_size: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    assert_valid_order_size_limits(min_order_size, max_order_size)?;
+
+    ledger.config.min_order_size = min_order_size;
+    ledger.config.max_order_size = max_order_size;
+    msg!("Order size limits set to {}..{}", min_order_size, max_order_size);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Setting 0 clears the reference, so the next match run trades unbanded and sets a new one
+fn set_reference_price(program_id: &Pubkey, accounts: &[AccountInfo], reference_price: i64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+
+    ledger.reference_price = reference_price;
+    msg!("Reference price set to {}", reference_price);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Sweeps the accumulated protocol fees into the admin's participant balance, from where they
+// leave custody through a regular Withdraw
+fn collect_fees(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let admin_participant_account = next_account(account_info_iter, "admin participant")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    let mut admin = load_participant(program_id, ledger_account, admin_participant_account, Some(admin_account.key))?;
+
+    let fees = ledger.protocol_fees;
+    admin.wallet_balance = admin.wallet_balance.checked_add(fees)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    ledger.protocol_fees = 0;
+    msg!("Collected {} in protocol fees", fees);
+
+    save_participant(&admin, admin_participant_account)?;
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Tags a program-owned account created by the client and points the ledger at it; from then on
+// instructions that are passed it record their trades there. An admin can later point the ledger
+// at a fresh log, leaving the old one for readers.
+fn initialize_trade_log(program_id: &Pubkey, accounts: &[AccountInfo], capacity: u32, history_policy: HistoryPolicy) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let trade_log_account = next_account(account_info_iter, "trade log")?;
+
+    if ledger_account.owner != program_id || trade_log_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    assert_writable(trade_log_account, "trade log")?;
+    if trade_log_account.data.borrow().first().is_some_and(|tag| *tag != 0) {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+    let required_space = trade_log_space(capacity);
+    if trade_log_account.data_len() < required_space {
+        msg!("Trade log needs at least {} bytes but the account holds {}", required_space, trade_log_account.data_len());
+        return Err(EnergyMarketError::InvalidTradeLog.into());
+    }
+    if !Rent::get()?.is_exempt(trade_log_account.lamports(), trade_log_account.data_len()) {
+        return Err(ProgramError::AccountNotRentExempt);
+    }
+
+    let header = TradeLogHeader {
+        account_type: AccountType::TradeLog,
+        ledger: *ledger_account.key,
+        history_policy,
+        capacity,
+        cursor: 0,
+        len: 0,
+        total_appended: 0,
+    };
+    header.serialize(&mut &mut trade_log_account.data.borrow_mut()[..])?;
+    ledger.trade_log = *trade_log_account.key;
+    msg!("Trade log {:?} holds {} trades", trade_log_account.key, capacity);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Closes an emptied ledger and its vault, sending their lamports to the recipient. Every
+// participant must have unregistered first, which also means no order, offer or pending trade is
+// left, and the protocol fees must have been collected. On token ledgers the vault token account,
+// which must be empty, is closed to the recipient as well. Accounts: admin, ledger, recipient,
+// vault, then for token ledgers the vault token account and the token program.
+fn close_ledger(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let recipient_account = next_account(account_info_iter, "recipient")?;
+    let vault_account = next_account(account_info_iter, "vault")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    assert_writable(ledger_account, "ledger")?;
+    assert_vault(program_id, ledger_account, &ledger, vault_account)?;
+    if ledger.participant_count > 0
+        || !ledger.productions.is_empty()
+        || !ledger.demands.is_empty()
+        || ledger.open_order_accounts > 0
+        || ledger.protocol_fees > 0
+        || ledger.insurance_pool > 0
+        || ledger.rewards_pool > 0
+    {
+        return Err(EnergyMarketError::LedgerNotEmpty.into());
+    }
+
+    if !is_native_settlement(&ledger) {
+        let vault_token_account = next_account(account_info_iter, "vault token")?;
+        let token_program_account = next_account(account_info_iter, "token program")?;
+
+        assert_token_program(token_program_account)?;
+        assert_token_account(vault_token_account, &ledger.quote_mint, Some(vault_account.key))?;
+
+        invoke_signed(
+            &spl_token::instruction::close_account(
+                token_program_account.key,
+                vault_token_account.key,
+                recipient_account.key,
+                vault_account.key,
+                &[],
+            )?,
+            &[
+                vault_token_account.clone(),
+                recipient_account.clone(),
+                vault_account.clone(),
+                token_program_account.clone(),
+            ],
+            &[&[VAULT_SEED, ledger_account.key.as_ref(), &[ledger.vault_bump]]],
+        )?;
+    }
+
+    close_account(vault_account, recipient_account)?;
+    close_account(ledger_account, recipient_account)?;
+    msg!("Closed ledger {:?}", ledger_account.key);
+
+    Ok(())
+}
+
+// First half of the admin handover. The current admin keeps full control until the new key
+// accepts, and can overwrite or clear (with Pubkey::default()) the pending admin at any time.
+fn set_pending_admin(program_id: &Pubkey, accounts: &[AccountInfo], new_admin: Pubkey) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+
+    ledger.pending_admin = new_admin;
+    msg!("Pending admin set to {:?}", new_admin);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// The first approver added turns the ledger into a multisig: the admin becomes the first approver
+// alongside the new one, with a threshold of one until SetApprovalThreshold raises it
+fn add_approver(program_id: &Pubkey, accounts: &[AccountInfo], approver: Pubkey) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    if ledger.approver_count == 0 {
+        ledger.approvers[0] = ledger.admin;
+        ledger.approver_count = 1;
+        ledger.approval_threshold = 1;
+    }
+    let count = ledger.approver_count as usize;
+    if approver == Pubkey::default() || ledger.approvers[..count].contains(&approver) || count == MAX_APPROVERS {
+        return Err(EnergyMarketError::InvalidApprovers.into());
+    }
+    ledger.approvers[count] = approver;
+    ledger.approver_count += 1;
+    msg!("Added approver {:?}, {} of {} required", approver, ledger.approval_threshold, ledger.approver_count);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// The threshold must stay reachable, so an approver can only be removed while more remain than
+// the threshold requires
+fn remove_approver(program_id: &Pubkey, accounts: &[AccountInfo], approver: Pubkey) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    let count = ledger.approver_count as usize;
+    let index = ledger.approvers[..count].iter().position(|a| *a == approver)
+        .ok_or(EnergyMarketError::InvalidApprovers)?;
+    if count <= ledger.approval_threshold as usize {
+        return Err(EnergyMarketError::InvalidApprovers.into());
+    }
+    ledger.approvers.copy_within(index + 1..count, index);
+    ledger.approvers[count - 1] = Pubkey::default();
+    ledger.approver_count -= 1;
+    msg!("Removed approver {:?}, {} of {} required", approver, ledger.approval_threshold, ledger.approver_count);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+fn set_approval_threshold(program_id: &Pubkey, accounts: &[AccountInfo], threshold: u8) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    if threshold == 0 || threshold > ledger.approver_count {
+        return Err(EnergyMarketError::InvalidApprovers.into());
+    }
+    ledger.approval_threshold = threshold;
+    msg!("Approval threshold set to {} of {}", threshold, ledger.approver_count);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+fn accept_admin(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let new_admin_account = next_account(account_info_iter, "new admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    assert_signer(new_admin_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    if ledger.pending_admin == Pubkey::default() || ledger.pending_admin != *new_admin_account.key {
+        return Err(EnergyMarketError::Unauthorized.into());
+    }
+
+    ledger.admin = ledger.pending_admin;
+    ledger.pending_admin = Pubkey::default();
+    msg!("Admin handed over to {:?}", ledger.admin);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+fn set_paused(program_id: &Pubkey, accounts: &[AccountInfo], paused: bool) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+
+    ledger.paused = paused;
+    msg!("Trading {}", if paused { "paused" } else { "resumed" });
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+fn update_participant_metadata(program_id: &Pubkey, accounts: &[AccountInfo], metadata: ParticipantMetadata) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    assert_signer(wallet_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut participant = load_participant(program_id, ledger_account, participant_account, Some(wallet_account.key))?;
+    assert_valid_metadata(&metadata)?;
+    participant.metadata = metadata;
+    msg!("Updated metadata of {:?}", participant.id);
+
+    save_participant(&participant, participant_account)?;
+
+    Ok(())
+}
+
+// Signed by the participant's own wallet only; a delegate can neither replace nor revoke itself
+fn set_delegate(program_id: &Pubkey, accounts: &[AccountInfo], delegate: Option<Pubkey>) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    assert_signer(wallet_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    if delegate.is_some_and(|delegate| delegate == *wallet_account.key || delegate == Pubkey::default()) {
+        return Err(EnergyMarketError::InvalidDelegate.into());
+    }
+    let mut participant = load_participant(program_id, ledger_account, participant_account, Some(wallet_account.key))?;
+    participant.delegate = delegate;
+    msg!("Delegate of {:?} set to {:?}", participant.id, delegate);
+
+    save_participant(&participant, participant_account)?;
+
+    Ok(())
+}
+
+// Signed by the participant. Gaining a side of the market is always allowed; giving one up needs
+// the participant to have no open orders on it, and giving up selling also needs every sale it
+// made to have settled, after which its collateral returns to the free balance and its registered
+// capacity is cleared. Becoming or ceasing to be Storage also takes the admin's signature as the
+// last account, and a battery can only stop being one once empty.
+fn change_participant_type(program_id: &Pubkey, accounts: &[AccountInfo], new_type: ParticipantType) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    assert_signer(wallet_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let ledger = load_ledger(ledger_account)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, Some(wallet_account.key))?;
+    let old_type = participant.participant_type.clone();
+    if old_type == new_type {
+        return Err(EnergyMarketError::InvalidTypeChange.into());
+    }
+    // Aggregators only ever hold their members' funds, so nobody becomes or stops being one
+    if matches!(old_type, ParticipantType::Aggregator) || matches!(new_type, ParticipantType::Aggregator) {
+        return Err(EnergyMarketError::InvalidTypeChange.into());
+    }
+
+    let to_storage = matches!(new_type, ParticipantType::Storage);
+    if is_storage(&participant) || to_storage {
+        let admin_account = next_account(account_info_iter, "admin")?;
+        assert_admin(&ledger, admin_account, accounts)?;
+    }
+    if is_storage(&participant) {
+        if participant.stored_energy > 0 {
+            return Err(EnergyMarketError::InvalidTypeChange.into());
+        }
+        participant.storage_capacity = 0;
+        participant.storage_efficiency_bps = 10_000;
+    }
+
+    if buys(&old_type) && !buys(&new_type) && participant.open_demands > 0 {
+        return Err(EnergyMarketError::ParticipantHasOpenOrders.into());
+    }
+    if sells(&old_type) && !sells(&new_type) {
+        if participant.open_productions > 0 {
+            return Err(EnergyMarketError::ParticipantHasOpenOrders.into());
+        }
+        // A pending sale can still default and be slashed out of the collateral
+        if ledger.transactions.iter().any(|t| t.status == TradeStatus::Matched && t.to == participant.id) {
+            return Err(EnergyMarketError::InvalidTypeChange.into());
+        }
+        if participant.staked_balance > 0 {
+            return Err(EnergyMarketError::StakeLocked.into());
+        }
+        participant.wallet_balance = participant.wallet_balance.checked_add(participant.collateral_balance)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        participant.collateral_balance = 0;
+        participant.registered_capacity = 0;
+    }
+
+    if !matches!(new_type, ParticipantType::Prosumer) {
+        participant.net_metering = false;
+    }
+    participant.participant_type = new_type.clone();
+    msg!("Participant {:?} changed from {:?} to {:?}", participant.id, old_type, new_type);
+
+    save_participant(&participant, participant_account)?;
+    emit(&events::ParticipantTypeChanged { participant: participant.id, old_type, new_type })?;
+
+    Ok(())
+}
+
+// A participant can only leave once it has cancelled its orders and withdrawn everything; the
+// participant PDA is closed and its rent returned to the wallet
+fn unregister_participant(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    assert_signer(wallet_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    let participant = load_participant(program_id, ledger_account, participant_account, Some(wallet_account.key))?;
+
+    if participant.open_orders() > 0 {
+        return Err(EnergyMarketError::ParticipantHasOpenOrders.into());
+    }
+    if participant.wallet_balance > 0
+        || participant.reserved_balance > 0
+        || participant.collateral_balance > 0
+        || participant.staked_balance > 0
+        || participant.pending_withdrawal > 0
+    {
+        return Err(EnergyMarketError::ParticipantHasBalance.into());
+    }
+    if has_pending_trades(&ledger, wallet_account.key)
+        || participant.committed_reduction > 0
+        || participant.open_forwards > 0
+        || participant.position_count > 0
+        || participant.insurance_claim > 0
+    {
+        return Err(EnergyMarketError::PendingSettlement.into());
+    }
+    if participant.aggregator.is_some() || participant.member_count > 0 {
+        return Err(EnergyMarketError::MemberOfAggregator.into());
+    }
+
+    close_account(participant_account, wallet_account)?;
+    ledger.participant_count = ledger.participant_count.checked_sub(1)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    // Standing orders hold no funds and leave with their owner
+    ledger.standing_orders.retain(|standing| standing.owner != *wallet_account.key);
+    msg!("Unregistered {:?}", wallet_account.key);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Compliance removal by the admin: every order of the participant is dropped, its whole balance
+// is paid out of the vault to the participant's own wallet (or token account owned by it) and the
+// participant PDA is closed. On OrderStorage::Accounts all of the participant's order PDAs must
+// follow the settlement accounts, otherwise the removal fails with ParticipantHasOpenOrders.
+fn force_unregister_participant(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+    let destination_account = next_account(account_info_iter, "destination")?;
+    let vault_account = next_account(account_info_iter, "vault")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    assert_vault(program_id, ledger_account, &ledger, vault_account)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, Some(wallet_account.key))?;
+    if has_pending_trades(&ledger, wallet_account.key)
+        || participant.committed_reduction > 0
+        || participant.open_forwards > 0
+        || participant.position_count > 0
+        || participant.insurance_claim > 0
+    {
+        return Err(EnergyMarketError::PendingSettlement.into());
+    }
+    if participant.aggregator.is_some() || participant.member_count > 0 {
+        return Err(EnergyMarketError::MemberOfAggregator.into());
+    }
+
+    // Refunds may only ever reach the participant, never an account of the admin's choosing
+    if is_native_settlement(&ledger) {
+        if destination_account.key != wallet_account.key {
+            return Err(ProgramError::InvalidArgument);
+        }
+    } else {
+        assert_token_account(destination_account, &ledger.quote_mint, Some(wallet_account.key))?;
+    }
+
+    // Escrow is part of the payout: every demand holding it is removed below
+    let payout = participant.wallet_balance.checked_add(participant.reserved_balance)
+        .and_then(|payout| payout.checked_add(participant.collateral_balance))
+        .and_then(|payout| payout.checked_add(participant.staked_balance))
+        .and_then(|payout| payout.checked_add(participant.pending_withdrawal))
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    transfer_from_vault(&ledger, ledger_account, vault_account, destination_account, account_info_iter, payout)?;
+
+    drop_participant_orders(program_id, ledger_account, &mut ledger, &mut participant, wallet_account, account_info_iter)?;
+    if participant.open_orders() > 0 {
+        return Err(EnergyMarketError::ParticipantHasOpenOrders.into());
+    }
+
+    close_account(participant_account, wallet_account)?;
+    ledger.participant_count = ledger.participant_count.checked_sub(1)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    ledger.standing_orders.retain(|standing| standing.owner != *wallet_account.key);
+    msg!("Force-unregistered {:?} and refunded {}", wallet_account.key, payout);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Freezing also drops the participant's orders so their escrow is not stuck behind the freeze.
+// On OrderStorage::Accounts the order PDAs to close follow the participant PDA; any left out stay
+// open but never match while the freeze lasts.
+fn freeze_participant(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, Some(wallet_account.key))?;
+
+    let dropped = drop_participant_orders(program_id, ledger_account, &mut ledger, &mut participant, wallet_account, account_info_iter)?;
+    participant.frozen = true;
+    msg!("Froze {:?} and dropped {} orders", participant.id, dropped);
+
+    save_participant(&participant, participant_account)?;
+    save_ledger(&ledger, ledger_account)?;
+    emit(&events::ParticipantFrozen { participant: participant.id, frozen: true })?;
+
+    Ok(())
+}
+
+fn unfreeze_participant(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, None)?;
+
+    participant.frozen = false;
+    msg!("Unfroze {:?}", participant.id);
+
+    save_participant(&participant, participant_account)?;
+    emit(&events::ParticipantFrozen { participant: participant.id, frozen: false })?;
+
+    Ok(())
+}
+
+fn set_grid_operator(program_id: &Pubkey, accounts: &[AccountInfo], grid_operator: Pubkey) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    if grid_operator == Pubkey::default() && ledger.config.grid_fee_per_kwh > 0 {
+        return Err(EnergyMarketError::GridOperatorNotSet.into());
+    }
+
+    ledger.grid_operator = grid_operator;
+    msg!("Grid operator set to {:?}", grid_operator);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Cuts up to `energy_amount` from the given open offers, newest first so the producer's longest
+// standing offers keep their priority. Returns how much energy was cut.
+fn curtail_offers<'a>(offers: impl Iterator<Item = (u64, &'a mut u64)>, energy_amount: u64) -> u64 {
+    let mut offers: Vec<(u64, &mut u64)> = offers.collect();
+    offers.sort_by_key(|(order_id, _)| std::cmp::Reverse(*order_id));
+
+    let mut remaining = energy_amount;
+    for (_, offer_amount) in offers {
+        let cut = remaining.min(*offer_amount);
+        *offer_amount -= cut;
+        remaining -= cut;
+    }
+    energy_amount - remaining
+}
+
+// The grid operator cuts a producer's open offers by up to `energy_amount` and, with a non-zero
+// `curtailed_until`, bars the producer from reporting new production until then. Offers cut to zero
+// leave the book at once; on account storage the producer's order PDAs follow the participant PDA
+// and the rent of closed ones goes back to the wallet.
+fn curtail(program_id: &Pubkey, accounts: &[AccountInfo], energy_amount: u64, curtailed_until: i64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let operator_account = next_account(account_info_iter, "operator")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let producer_participant_account = next_account(account_info_iter, "producer participant")?;
+
+    assert_signer(operator_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    if ledger.grid_operator == Pubkey::default() || ledger.grid_operator != *operator_account.key {
+        return Err(EnergyMarketError::Unauthorized.into());
+    }
+    let mut producer = load_participant(program_id, ledger_account, producer_participant_account, Some(wallet_account.key))?;
+
+    let mut order_accounts = Vec::new();
+    for order_account in account_info_iter {
+        let order = load_order_account(program_id, ledger_account, order_account)?;
+        if order.owner != producer.id || order.side != OrderSide::Production {
+            return Err(EnergyMarketError::NotOrderOwner.into());
+        }
+        if !order_accounts.iter().any(|(o, _): &(OrderAccount, _)| o.order_id == order.order_id) {
+            order_accounts.push((order, order_account));
+        }
+    }
+
+    let owner = producer.id;
+    let ledger_offers = ledger.productions.iter_mut()
+        .filter(|p| p.producer_id == owner)
+        .map(|p| (p.order_id, &mut p.energy_amount));
+    let account_offers = order_accounts.iter_mut().map(|(o, _)| (o.order_id, &mut o.energy_amount));
+    let curtailed_amount = curtail_offers(ledger_offers.chain(account_offers), energy_amount);
+
+    remove_orders(
+        &mut ledger.productions, OrderSide::Production, std::slice::from_mut(&mut producer), |p| p.producer_id,
+        |p| p.producer_id == owner && p.energy_amount == 0,
+    )?;
+    for (order, order_account) in &order_accounts {
+        if order.energy_amount > 0 {
+            order.serialize(&mut &mut order_account.data.borrow_mut()[..])?;
+        } else {
+            close_account(order_account, wallet_account)?;
+            release_order_slot(&mut producer, OrderSide::Production)?;
+            ledger.open_order_accounts = ledger.open_order_accounts.checked_sub(1)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+        }
+    }
+
+    if curtailed_until != 0 {
+        producer.curtailed_until = curtailed_until;
+    }
+    msg!("Curtailed {} of {:?} until {}", curtailed_amount, owner, producer.curtailed_until);
+
+    save_participant(&producer, producer_participant_account)?;
+    save_ledger(&ledger, ledger_account)?;
+    emit(&events::ProducerCurtailed {
+        producer: owner,
+        curtailed_amount,
+        curtailed_until: producer.curtailed_until,
+    })?;
+
+    Ok(())
+}
+
+// The admin corrects a producer's registered capacity; offers already in the book are not touched
+fn set_registered_capacity(program_id: &Pubkey, accounts: &[AccountInfo], registered_capacity: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, None)?;
+
+    participant.registered_capacity = registered_capacity;
+    msg!("Registered capacity of {:?} set to {}", participant.id, registered_capacity);
+
+    save_participant(&participant, participant_account)?;
+
+    Ok(())
+}
+
+// The admin grants or revokes the critical_load flag; demands already in the book keep their priority
+fn set_critical_load(program_id: &Pubkey, accounts: &[AccountInfo], critical_load: bool) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, None)?;
+
+    participant.critical_load = critical_load;
+    msg!("Critical load flag of {:?} set to {}", participant.id, critical_load);
+
+    save_participant(&participant, participant_account)?;
+    emit(&events::CriticalLoadSet { participant: participant.id, critical_load })?;
+
+    Ok(())
+}
+
+// The admin, or the grid operator once one is appointed
+fn assert_admin_or_grid_operator(ledger: &Ledger, authority_account: &AccountInfo, accounts: &[AccountInfo]) -> ProgramResult {
+    assert_signer(authority_account)?;
+    if ledger.grid_operator != Pubkey::default() && ledger.grid_operator == *authority_account.key {
+        return Ok(());
+    }
+    assert_admin(ledger, authority_account, accounts)
+}
+
+// Only one event runs at a time: the next can be declared once the last one's volunteers were all paid
+fn declare_demand_response(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    start: i64,
+    end: i64,
+    target_reduction_kwh: u64,
+    incentive_price: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let authority_account = next_account(account_info_iter, "authority")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin_or_grid_operator(&ledger, authority_account, accounts)?;
+    if ledger.demand_response.is_some() {
+        return Err(EnergyMarketError::DemandResponseActive.into());
+    }
+    let now = Clock::get()?.unix_timestamp;
+    if start <= now || end <= start || target_reduction_kwh == 0 || incentive_price == 0 {
+        return Err(EnergyMarketError::InvalidDemandResponse.into());
+    }
+
+    let event_id = ledger.demand_response_count.checked_add(1)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    ledger.demand_response_count = event_id;
+    ledger.demand_response = Some(DemandResponse {
+        event_id,
+        start,
+        end,
+        target_reduction_kwh,
+        incentive_price,
+        committed_kwh: 0,
+        volunteers: 0,
+        reserved_incentive: 0,
+    });
+    msg!("Demand-response event {} declared from {} to {}", event_id, start, end);
+
+    save_ledger(&ledger, ledger_account)?;
+    emit(&events::DemandResponseDeclared { event_id, start, end, target_reduction_kwh, incentive_price })?;
+
+    Ok(())
+}
+
+// A consumer commits to cutting `reduction_kwh` during the window, on top of what it already
+// committed to the same event. The incentive is set aside from the fee pool right away.
+fn volunteer_reduction(program_id: &Pubkey, accounts: &[AccountInfo], reduction_kwh: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    assert_signer(wallet_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, Some(wallet_account.key))?;
+    if !matches!(participant.participant_type, ParticipantType::Consumer | ParticipantType::Prosumer | ParticipantType::Storage) {
+        return Err(EnergyMarketError::InvalidParticipantType.into());
+    }
+    assert_not_frozen(&participant)?;
+    if reduction_kwh == 0 {
+        return Err(EnergyMarketError::InvalidDemandResponse.into());
+    }
+
+    let now = Clock::get()?.unix_timestamp;
+    let Some(event) = ledger.demand_response.as_mut() else {
+        return Err(EnergyMarketError::NoDemandResponse.into());
+    };
+    if now >= event.start {
+        return Err(EnergyMarketError::VolunteeringClosed.into());
+    }
+    let committed_kwh = event.committed_kwh.checked_add(reduction_kwh)
+        .filter(|committed| *committed <= event.target_reduction_kwh)
+        .ok_or(EnergyMarketError::ReductionTargetExceeded)?;
+    let incentive = reduction_kwh.checked_mul(event.incentive_price)
+        .ok_or(EnergyMarketError::IncentivePoolExhausted)?;
+    ledger.protocol_fees = ledger.protocol_fees.checked_sub(incentive)
+        .ok_or(EnergyMarketError::IncentivePoolExhausted)?;
+    event.reserved_incentive = event.reserved_incentive.checked_add(incentive)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    event.committed_kwh = committed_kwh;
+
+    // Every earlier event was settled before this one was declared, so an older commitment is spent
+    if participant.demand_response_id != event.event_id {
+        participant.demand_response_id = event.event_id;
+        participant.committed_reduction = 0;
+        event.volunteers = event.volunteers.checked_add(1)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    }
+    participant.committed_reduction = participant.committed_reduction.checked_add(reduction_kwh)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    let event_id = event.event_id;
+    msg!("{:?} committed to cut {} kWh in event {}", participant.id, reduction_kwh, event_id);
+
+    save_participant(&participant, participant_account)?;
+    save_ledger(&ledger, ledger_account)?;
+    emit(&events::ReductionVolunteered {
+        event_id,
+        participant: participant.id,
+        reduction_kwh,
+        committed_reduction: participant.committed_reduction,
+    })?;
+
+    Ok(())
+}
+
+// Once the window closed, pays each volunteer whose participant PDA follows the ledger.
+// Without an oracle anyone may settle and volunteers are paid for their whole commitment; with
+// one, only the oracle settles, passing the metered reduction of each volunteer in account order,
+// and pays for no more than was committed. The event is cleared when the last volunteer was paid,
+// returning any incentive left unpaid to the fee pool.
+fn settle_demand_response(program_id: &Pubkey, accounts: &[AccountInfo], actual_reductions: Option<Vec<u64>>) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let settler_account = next_account(account_info_iter, "settler")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    assert_signer(settler_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    let Some(mut event) = ledger.demand_response else {
+        return Err(EnergyMarketError::NoDemandResponse.into());
+    };
+    if Clock::get()?.unix_timestamp < event.end {
+        return Err(EnergyMarketError::DemandResponseNotOver.into());
+    }
+    let volunteer_accounts: Vec<&AccountInfo> = account_info_iter.collect();
+    if ledger.oracle != Pubkey::default() {
+        if *settler_account.key != ledger.oracle {
+            return Err(EnergyMarketError::InvalidOracle.into());
+        }
+        if actual_reductions.as_ref().is_none_or(|actuals| actuals.len() != volunteer_accounts.len()) {
+            msg!("The oracle must report one actual reduction per volunteer");
+            return Err(ProgramError::InvalidArgument);
+        }
+    } else if actual_reductions.is_some() {
+        return Err(EnergyMarketError::InvalidOracle.into());
+    }
+
+    for (index, participant_account) in volunteer_accounts.into_iter().enumerate() {
+        let mut participant = load_participant(program_id, ledger_account, participant_account, None)?;
+        if participant.demand_response_id != event.event_id || participant.committed_reduction == 0 {
+            return Err(EnergyMarketError::NoDemandResponse.into());
+        }
+        let delivered = actual_reductions.as_ref()
+            .map_or(participant.committed_reduction, |actuals| actuals[index].min(participant.committed_reduction));
+        // The commitment's whole incentive was reserved, so paying for part of it always fits
+        let amount = delivered.checked_mul(event.incentive_price)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        event.reserved_incentive = event.reserved_incentive.checked_sub(amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        event.volunteers = event.volunteers.checked_sub(1)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        participant.wallet_balance = participant.wallet_balance.checked_add(amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        participant.committed_reduction = 0;
+
+        save_participant(&participant, participant_account)?;
+        emit(&events::IncentivePaid { event_id: event.event_id, participant: participant.id, reduction_kwh: delivered, amount })?;
+    }
+
+    if event.volunteers == 0 {
+        ledger.protocol_fees = ledger.protocol_fees.checked_add(event.reserved_incentive)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        ledger.demand_response = None;
+        msg!("Demand-response event {} settled, {} returned to the fee pool", event.event_id, event.reserved_incentive);
+    } else {
+        ledger.demand_response = Some(event);
+        msg!("Demand-response event {} has {} volunteers left to pay", event.event_id, event.volunteers);
+    }
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+fn set_oracle(program_id: &Pubkey, accounts: &[AccountInfo], oracle: Pubkey) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+
+    // Offers verified by the previous oracle stay verified
+    ledger.oracle = oracle;
+    msg!("Oracle set to {:?}", oracle);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// The oracle confirms a production offer against its meter readings, making it matchable. On
+// account storage the order PDA follows the ledger.
+fn attest_production(program_id: &Pubkey, accounts: &[AccountInfo], order_id: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let oracle_account = next_account(account_info_iter, "oracle")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    assert_signer(oracle_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    if ledger.oracle == Pubkey::default() || ledger.oracle != *oracle_account.key {
+        return Err(EnergyMarketError::InvalidOracle.into());
+    }
+
+    let producer = if ledger.config.order_storage == OrderStorage::Accounts {
+        let order_account = next_account(account_info_iter, "order")?;
+        let mut order = load_order_account(program_id, ledger_account, order_account)?;
+        if order.order_id != order_id || order.side != OrderSide::Production {
+            return Err(EnergyMarketError::OrderNotFound.into());
+        }
+        order.verified = true;
+        order.serialize(&mut &mut order_account.data.borrow_mut()[..])?;
+        order.owner
+    } else {
+        let production = ledger.productions.iter_mut().find(|p| p.order_id == order_id)
+            .ok_or(EnergyMarketError::OrderNotFound)?;
+        production.verified = true;
+        production.producer_id
+    };
+    msg!("Production {} attested", order_id);
+
+    save_ledger(&ledger, ledger_account)?;
+    emit(&events::ProductionAttested { order_id, producer, oracle: *oracle_account.key })?;
+
+    Ok(())
+}
+
+fn find_pending_trade(ledger: &mut Ledger, trade_id: u64) -> Result<&mut Transaction, ProgramError> {
+    let trade = ledger.transactions.iter_mut().find(|t| t.trade_id == trade_id)
+        .ok_or(EnergyMarketError::TradeNotFound)?;
+    if trade.status != TradeStatus::Matched {
+        return Err(EnergyMarketError::TradeNotPending.into());
+    }
+    Ok(trade)
+}
+
+// Confirms a deferred trade before its settlement deadline, signed by the oracle or the consumer
+// depending on the ledger's settlement config, and pays the producer out of the trade's escrow
+fn confirm_delivery(program_id: &Pubkey, accounts: &[AccountInfo], trade_id: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let confirmer_account = next_account(account_info_iter, "confirmer")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let consumer_participant_account = next_account(account_info_iter, "consumer participant")?;
+    let producer_participant_account = next_account(account_info_iter, "producer participant")?;
+
+    assert_signer(confirmer_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    let confirmer = ledger.config.settlement.confirmer;
+    let oracle = ledger.oracle;
+    let trade = find_pending_trade(&mut ledger, trade_id)?;
+    let authorized = match confirmer {
+        DeliveryConfirmer::Oracle => oracle != Pubkey::default() && oracle == *confirmer_account.key,
+        DeliveryConfirmer::Consumer => trade.from == *confirmer_account.key,
+    };
+    if !authorized {
+        return Err(EnergyMarketError::Unauthorized.into());
+    }
+    if trade.settlement_deadline < Clock::get()?.unix_timestamp {
+        return Err(EnergyMarketError::SettlementDeadlinePassed.into());
+    }
+    trade.status = TradeStatus::Delivered;
+    let trade = trade.clone();
+
+    let mut consumer = load_participant(program_id, ledger_account, consumer_participant_account, Some(&trade.from))?;
+    consumer.reserved_balance = consumer.reserved_balance.checked_sub(trade.escrow)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    accrue_recs(&mut consumer, trade.energy_source, trade.delivered_amount, ledger.config.kwh_per_rec)?;
+    // A self-trade credits the same participant account, so it is only loaded and saved once
+    if trade.from == trade.to {
+        consumer.wallet_balance = consumer.wallet_balance.checked_add(trade.proceeds)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    } else {
+        let mut producer = load_participant(program_id, ledger_account, producer_participant_account, Some(&trade.to))?;
+        producer.wallet_balance = producer.wallet_balance.checked_add(trade.proceeds)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        record_trade_outcome(&mut producer, true)?;
+        save_participant(&producer, producer_participant_account)?;
+    }
+    let fees = trade.escrow.checked_sub(trade.proceeds).ok_or(ProgramError::ArithmeticOverflow)?;
+    collect_trading_fees(&mut ledger, fees)?;
+    msg!("Trade {} delivered, paid {} to {:?}", trade_id, trade.proceeds, trade.to);
+
+    save_participant(&consumer, consumer_participant_account)?;
+    save_ledger(&ledger, ledger_account)?;
+    emit(&events::TradeSettled {
+        trade_id,
+        status: TradeStatus::Delivered,
+        producer_amount: trade.proceeds,
+        consumer_refund: 0,
+    })?;
+
+    Ok(())
+}
+
+// Anyone may default a deferred trade once its settlement deadline has passed unconfirmed. The
+// consumer gets the escrow back minus default_penalty_bps of it, which goes to the protocol, and
+// is compensated out of the producer's collateral with the collateral the trade's notional required.
+fn settle_defaulted_trade(program_id: &Pubkey, accounts: &[AccountInfo], trade_id: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let consumer_participant_account = next_account(account_info_iter, "consumer participant")?;
+    let producer_participant_account = next_account(account_info_iter, "producer participant")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    let penalty_bps = ledger.config.settlement.default_penalty_bps;
+    let collateral_bps = ledger.config.collateral_bps;
+    let trade = find_pending_trade(&mut ledger, trade_id)?;
+    if trade.settlement_deadline >= Clock::get()?.unix_timestamp {
+        return Err(EnergyMarketError::SettlementDeadlineNotReached.into());
+    }
+    trade.status = TradeStatus::Defaulted;
+    let trade = trade.clone();
+
+    let penalty = protocol_fee(trade.escrow as u128, penalty_bps)?;
+    let refund = trade.escrow.checked_sub(penalty).ok_or(ProgramError::ArithmeticOverflow)?;
+    let mut consumer = load_participant(program_id, ledger_account, consumer_participant_account, Some(&trade.from))?;
+    consumer.reserved_balance = consumer.reserved_balance.checked_sub(trade.escrow)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    consumer.wallet_balance = consumer.wallet_balance.checked_add(refund)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    ledger.protocol_fees = ledger.protocol_fees.checked_add(penalty)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    msg!("Trade {} defaulted, refunded {} to {:?}", trade_id, refund, trade.from);
+
+    // Slashing a self-trade would only move funds between the same participant's balances
+    let mut compensation = 0;
+    if trade.from != trade.to {
+        let mut producer = load_participant(program_id, ledger_account, producer_participant_account, Some(&trade.to))?;
+        let slashed = required_collateral(notional(trade.amount, trade.price), collateral_bps)?.min(producer.collateral_balance);
+        if slashed > 0 {
+            slash_collateral_to(&mut producer, slashed, Some(&mut consumer), &mut ledger)?;
+            emit(&events::CollateralSlashed { producer: producer.id, trade_id, amount: slashed, to_consumer: true })?;
+        }
+        // Whatever of the notional the collateral did not cover can be claimed from the insurance pool
+        let uncovered = balance_change(notional(trade.amount, trade.price))?.saturating_sub(slashed);
+        if uncovered > 0 {
+            consumer.insurance_claim = consumer.insurance_claim.checked_add(uncovered)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+            ledger.insurance_claims = ledger.insurance_claims.checked_add(uncovered)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+            msg!("{:?} may claim {} from the insurance pool", consumer.id, uncovered);
+        }
+        record_trade_outcome(&mut producer, false)?;
+        save_participant(&producer, producer_participant_account)?;
+        compensation = slashed;
+    }
+    // What an aggregated demand's trade gets back belongs to the members who funded it
+    if consumer.aggregated_order_id == Some(trade.demand_order_id) {
+        consumer.pooled_balance = consumer.pooled_balance.checked_add(refund)
+            .and_then(|pooled| pooled.checked_add(compensation))
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    }
+
+    save_participant(&consumer, consumer_participant_account)?;
+    save_ledger(&ledger, ledger_account)?;
+    emit(&events::TradeSettled {
+        trade_id,
+        status: TradeStatus::Defaulted,
+        producer_amount: 0,
+        consumer_refund: refund,
+    })?;
+
+    Ok(())
+}
+
+// The part of a trading fee paid into the insurance pool, rounded down
+pub fn insurance_share(fees: u64, insurance_fee_bps: u16) -> u64 {
+    (fees as u128 * insurance_fee_bps as u128 / 10_000) as u64
+}
+
+pub(crate) fn collect_trading_fees(ledger: &mut Ledger, fees: u64) -> ProgramResult {
+    let insured = insurance_share(fees, ledger.config.insurance_fee_bps);
+    ledger.insurance_pool = ledger.insurance_pool.checked_add(insured)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    ledger.protocol_fees = ledger.protocol_fees.checked_add(fees - insured)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    Ok(())
+}
+
+// Collateral backing an offer or trade of the given notional, rounded up like the protocol fee
+pub fn required_collateral(notional: u128, collateral_bps: u16) -> Result<u64, ProgramError> {
+    protocol_fee(notional, collateral_bps)
+}
+
+// Moves slashed collateral to the harmed consumer's wallet_balance, or into the protocol fee pool
+fn slash_collateral_to(producer: &mut Participant, amount: u64, consumer: Option<&mut Participant>, ledger: &mut Ledger) -> ProgramResult {
+    producer.collateral_balance = producer.collateral_balance.checked_sub(amount)
+        .ok_or(EnergyMarketError::InsufficientCollateral)?;
+    match consumer {
+        Some(consumer) => {
+            consumer.wallet_balance = consumer.wallet_balance.checked_add(amount)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+        }
+        None => {
+            ledger.protocol_fees = ledger.protocol_fees.checked_add(amount)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+        }
+    }
+    Ok(())
+}
+
+// True while the wallet is buyer or seller of a deferred trade that has not settled yet
+// While an aggregated demand is unsettled the aggregator's balance belongs to its members
+fn assert_no_pooled_funds(participant: &Participant) -> ProgramResult {
+    if participant.aggregated_order_id.is_some() {
+        return Err(EnergyMarketError::AggregatedDemandOpen.into());
+    }
+    Ok(())
+}
+
+fn has_pending_trades(ledger: &Ledger, wallet: &Pubkey) -> bool {
+    ledger.transactions.iter()
+        .any(|t| t.status == TradeStatus::Matched && (t.from == *wallet || t.to == *wallet))
+}
+
+fn post_collateral(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    assert_signer(wallet_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    load_ledger(ledger_account)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, Some(wallet_account.key))?;
+    assert_no_pooled_funds(&participant)?;
+
+    participant.wallet_balance = participant.wallet_balance.checked_sub(amount)
+        .ok_or(EnergyMarketError::InsufficientBalance)?;
+    participant.collateral_balance = participant.collateral_balance.checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    msg!("Posted {} collateral, {} held", amount, participant.collateral_balance);
+
+    save_participant(&participant, participant_account)?;
+
+    Ok(())
+}
+
+// Collateral only returns to wallet_balance once no open order or unsettled trade relies on it
+fn withdraw_collateral(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    assert_signer(wallet_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let ledger = load_ledger(ledger_account)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, Some(wallet_account.key))?;
+    assert_not_frozen(&participant)?;
+    if participant.open_orders() > 0 {
+        return Err(EnergyMarketError::ParticipantHasOpenOrders.into());
+    }
+    if has_pending_trades(&ledger, wallet_account.key) {
+        return Err(EnergyMarketError::PendingSettlement.into());
+    }
+
+    participant.collateral_balance = participant.collateral_balance.checked_sub(amount)
+        .ok_or(EnergyMarketError::InsufficientCollateral)?;
+    participant.wallet_balance = participant.wallet_balance.checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    msg!("Withdrew {} collateral, {} held", amount, participant.collateral_balance);
+
+    save_participant(&participant, participant_account)?;
+
+    Ok(())
+}
+
+// The admin slashes up to `amount` more of the producer's collateral over a defaulted trade. With
+// the consumer's participant PDA supplied after the producer's the slash compensates the consumer,
+// otherwise it goes to the protocol fee pool.
+fn slash_collateral(program_id: &Pubkey, accounts: &[AccountInfo], trade_id: u64, amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let producer_participant_account = next_account(account_info_iter, "producer participant")?;
+    let consumer_participant_account = account_info_iter.next();
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    let trade = ledger.transactions.iter().find(|t| t.trade_id == trade_id)
+        .ok_or(EnergyMarketError::TradeNotFound)?
+        .clone();
+    if trade.status != TradeStatus::Defaulted {
+        return Err(EnergyMarketError::TradeNotDefaulted.into());
+    }
+
+    let mut producer = load_participant(program_id, ledger_account, producer_participant_account, Some(&trade.to))?;
+    let mut consumer = match consumer_participant_account {
+        Some(account) if trade.from != trade.to => Some(load_participant(program_id, ledger_account, account, Some(&trade.from))?),
+        _ => None,
+    };
+    let amount = amount.min(producer.collateral_balance);
+    slash_collateral_to(&mut producer, amount, consumer.as_mut(), &mut ledger)?;
+    msg!("Slashed {} collateral of {:?} over trade {}", amount, producer.id, trade_id);
+
+    save_participant(&producer, producer_participant_account)?;
+    if let (Some(consumer), Some(account)) = (&consumer, consumer_participant_account) {
+        save_participant(consumer, account)?;
+    }
+    save_ledger(&ledger, ledger_account)?;
+    emit(&events::CollateralSlashed { producer: producer.id, trade_id, amount, to_consumer: consumer.is_some() })?;
+
+    Ok(())
+}
+
+// Credits `energy_amount` of a settled trade towards the consumer's RECs when it came from a
+// renewable source; energy short of a whole certificate carries over in rec_accrual
+fn accrue_recs(consumer: &mut Participant, energy_source: EnergySource, energy_amount: u64, kwh_per_rec: u64) -> ProgramResult {
+    if kwh_per_rec == 0 || !energy_source.is_renewable() {
+        return Ok(());
+    }
+    let accrued = consumer.rec_accrual.checked_add(energy_amount).ok_or(ProgramError::ArithmeticOverflow)?;
+    consumer.rec_balance = consumer.rec_balance.checked_add(accrued / kwh_per_rec)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    consumer.rec_accrual = accrued % kwh_per_rec;
+    Ok(())
+}
+
+// Moves free wallet balance to another registered participant of the same ledger without touching
+// the vault. Reserved funds stay put. The protocol fee, if any, comes out of the amount sent.
+fn transfer_balance(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+    let recipient_participant_account = next_account(account_info_iter, "recipient participant")?;
+
+    assert_signer(wallet_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, Some(wallet_account.key))?;
+    let mut recipient = load_participant(program_id, ledger_account, recipient_participant_account, None)?;
+    if recipient.id == participant.id {
+        return Err(ProgramError::InvalidArgument);
+    }
+    assert_not_frozen(&participant)?;
+    assert_no_pooled_funds(&participant)?;
+
+    let fee = protocol_fee(amount as u128, ledger.config.fee_bps)?;
+    participant.wallet_balance = participant.wallet_balance.checked_sub(amount)
+        .ok_or(EnergyMarketError::InsufficientBalance)?;
+    recipient.wallet_balance = amount.checked_sub(fee)
+        .and_then(|credit| recipient.wallet_balance.checked_add(credit))
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    ledger.protocol_fees = ledger.protocol_fees.checked_add(fee)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    msg!("Transferred {} from {:?} to {:?}", amount, participant.id, recipient.id);
+
+    save_participant(&participant, participant_account)?;
+    save_participant(&recipient, recipient_participant_account)?;
+    save_ledger(&ledger, ledger_account)?;
+    emit(&events::BalanceTransferred { from: participant.id, to: recipient.id, amount, fee })?;
+
+    Ok(())
+}
+
+fn transfer_rec(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+    let recipient_participant_account = next_account(account_info_iter, "recipient participant")?;
+
+    assert_signer(wallet_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    load_ledger(ledger_account)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, Some(wallet_account.key))?;
+    let mut recipient = load_participant(program_id, ledger_account, recipient_participant_account, None)?;
+    if recipient.id == participant.id {
+        return Err(ProgramError::InvalidArgument);
+    }
+    assert_not_frozen(&participant)?;
+
+    participant.rec_balance = participant.rec_balance.checked_sub(amount)
+        .ok_or(EnergyMarketError::InsufficientRecs)?;
+    recipient.rec_balance = recipient.rec_balance.checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    msg!("Transferred {} RECs from {:?} to {:?}", amount, participant.id, recipient.id);
+
+    save_participant(&participant, participant_account)?;
+    save_participant(&recipient, recipient_participant_account)?;
+    emit(&events::RecTransferred { from: participant.id, to: recipient.id, amount })?;
+
+    Ok(())
+}
+
+// Retired certificates are burned: the claim they represent has been used and can never move again
+fn retire_rec(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    assert_signer(wallet_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    load_ledger(ledger_account)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, Some(wallet_account.key))?;
+
+    participant.rec_balance = participant.rec_balance.checked_sub(amount)
+        .ok_or(EnergyMarketError::InsufficientRecs)?;
+    participant.retired_recs = participant.retired_recs.checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    msg!("Retired {} RECs of {:?}", amount, participant.id);
+
+    save_participant(&participant, participant_account)?;
+    emit(&events::RecRetired { participant: participant.id, amount, retired_total: participant.retired_recs })?;
+
+    Ok(())
+}
+
+// Energy a battery ends up storing from `energy_amount` bought, after round-trip losses
+pub fn stored_after_losses(energy_amount: u64, storage_efficiency_bps: u16) -> u64 {
+    (energy_amount as u128 * storage_efficiency_bps as u128 / 10_000) as u64
+}
+
+// Most energy a battery can still buy without its stored energy exceeding its capacity: the
+// largest x with floor(x * efficiency / 10000) <= capacity - stored_energy
+fn charge_headroom(battery: &Participant) -> u64 {
+    if battery.storage_efficiency_bps == 0 {
+        return 0;
+    }
+    let headroom = battery.storage_capacity.saturating_sub(battery.stored_energy) as u128;
+    let chargeable = ((headroom + 1) * 10_000 - 1) / battery.storage_efficiency_bps as u128;
+    u64::try_from(chargeable).unwrap_or(u64::MAX)
+}
+
+fn is_storage(participant: &Participant) -> bool {
+    matches!(participant.participant_type, ParticipantType::Storage)
+}
+
+// The admin records a battery's physical parameters. Both must be consistent with what the
+// battery already stores: capacity cannot drop below stored_energy.
+fn set_storage_parameters(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    storage_capacity: u64,
+    storage_efficiency_bps: u16,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, None)?;
+    if !is_storage(&participant) {
+        return Err(EnergyMarketError::InvalidParticipantType.into());
+    }
+    if storage_efficiency_bps == 0 || storage_efficiency_bps > 10_000 || storage_capacity < participant.stored_energy {
+        return Err(EnergyMarketError::InvalidStorageParameters.into());
+    }
+
+    participant.storage_capacity = storage_capacity;
+    participant.storage_efficiency_bps = storage_efficiency_bps;
+    msg!("Storage of {:?} set to {} at {} bps efficiency", participant.id, storage_capacity, storage_efficiency_bps);
+
+    save_participant(&participant, participant_account)?;
+
+    Ok(())
+}
+
+// Read-only: returns the borsh-serialized MarketStats as return data, so clients can simulate this
+// instead of fetching and decoding the whole ledger
+fn get_market_stats(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let ledger = load_ledger(ledger_account)?;
+    set_return_data(&ledger.stats.try_to_vec()?);
+
+    Ok(())
+}
+
+fn record_price_sample(ledger: &mut Ledger, sample: PriceSample) {
+    ledger.price_history[ledger.price_history_head as usize] = sample;
+    ledger.price_history_head = ((ledger.price_history_head as usize + 1) % PRICE_HISTORY_LEN) as u8;
+    ledger.price_history_len = (ledger.price_history_len as usize + 1).min(PRICE_HISTORY_LEN) as u8;
+}
+
+// A slot trading over several match runs keeps one entry, which adds each run's trades to the
+// slot's VWAP so a small late run cannot reprice the whole slot
+fn record_slot_price(ledger: &mut Ledger, delivery_slot: u32, notional: i128, volume: u64) -> ProgramResult {
+    let index = match ledger.slot_prices.iter().position(|entry| entry.delivery_slot == delivery_slot) {
+        Some(index) => index,
+        None => {
+            let index = ledger.slot_price_head as usize;
+            ledger.slot_prices[index] = SlotPrice { delivery_slot, ..SlotPrice::default() };
+            ledger.slot_price_head = ((index + 1) % SLOT_PRICE_HISTORY_LEN) as u8;
+            index
+        }
+    };
+    let entry = &mut ledger.slot_prices[index];
+    entry.notional = entry.notional.checked_add(notional).ok_or(ProgramError::ArithmeticOverflow)?;
+    entry.volume = entry.volume.checked_add(volume).ok_or(ProgramError::ArithmeticOverflow)?;
+    if entry.volume > 0 {
+        // The average lies between the lowest and highest trade price, so it fits back into an i64
+        entry.price = (entry.notional / entry.volume as i128) as i64;
+    }
+    Ok(())
+}
+
+// None when the slot never traded or its entry has since been replaced
+pub fn slot_price(ledger: &Ledger, delivery_slot: u32) -> Option<i64> {
+    ledger.slot_prices.iter()
+        .find(|entry| delivery_slot != 0 && entry.delivery_slot == delivery_slot)
+        .map(|entry| entry.price)
+}
+
+// Time-weighted average of the price samples taken in the last `window_secs` before `now`. Each
+// sample's price holds until the next sample, so the newest one only closes the last interval.
+// Samples stamped after `now` are ignored as well.
+pub fn compute_twap(ledger: &Ledger, now: i64, window_secs: i64) -> Result<i64, ProgramError> {
+    let start = now.saturating_sub(window_secs);
+    let len = ledger.price_history_len as usize;
+    let oldest = (ledger.price_history_head as usize + PRICE_HISTORY_LEN - len) % PRICE_HISTORY_LEN;
+    let samples: Vec<PriceSample> = (0..len)
+        .map(|offset| ledger.price_history[(oldest + offset) % PRICE_HISTORY_LEN])
+        .filter(|sample| sample.timestamp >= start && sample.timestamp <= now)
+        .collect();
+
+    let duration = match (samples.first(), samples.last()) {
+        (Some(first), Some(last)) => last.timestamp as i128 - first.timestamp as i128,
+        _ => 0,
+    };
+    // Two samples taken at the same timestamp span no time to weight by
+    if samples.len() < 2 || duration == 0 {
+        return Err(EnergyMarketError::NotEnoughData.into());
+    }
+    let weighted = samples.windows(2).try_fold(0i128, |total, pair| {
+        let elapsed = pair[1].timestamp as i128 - pair[0].timestamp as i128;
+        total.checked_add(pair[0].price as i128 * elapsed)
+    }).ok_or(ProgramError::ArithmeticOverflow)?;
+    // An average of i64 prices fits back into an i64
+    Ok((weighted / duration) as i64)
+}
+
+// Read-only like GetMarketStats: returns the borsh-serialized i64 TWAP as return data
+fn get_twap(program_id: &Pubkey, accounts: &[AccountInfo], window_secs: i64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let ledger = load_ledger(ledger_account)?;
+    let twap = compute_twap(&ledger, Clock::get()?.unix_timestamp, window_secs)?;
+    set_return_data(&twap.try_to_vec()?);
+
+    Ok(())
+}
+
+// The participant queries take the ledger and the PDA of `pubkey`'s participant, failing with
+// NotFound when that wallet is not registered
+fn query_participant(
+    program_id: &Pubkey,
+    ledger_account: &AccountInfo,
+    participant_account: &AccountInfo,
+    pubkey: Pubkey,
+) -> Result<Participant, ProgramError> {
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let (expected, _) = find_participant_address(program_id, ledger_account.key, &pubkey);
+    if expected != *participant_account.key {
+        return Err(EnergyMarketError::InvalidParticipantAccount.into());
+    }
+    load_participant(program_id, ledger_account, participant_account, Some(&pubkey)).map_err(|err| {
+        if err == EnergyMarketError::ParticipantNotRegistered.into() {
+            EnergyMarketError::NotFound.into()
+        } else {
+            err
+        }
+    })
+}
+
+// Returns the borsh-serialized Participant
+fn get_participant(program_id: &Pubkey, accounts: &[AccountInfo], pubkey: Pubkey) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    let participant = query_participant(program_id, ledger_account, participant_account, pubkey)?;
+    set_return_data(&participant.try_to_vec()?);
+
+    Ok(())
+}
+
+fn get_balance(program_id: &Pubkey, accounts: &[AccountInfo], pubkey: Pubkey) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    let participant = query_participant(program_id, ledger_account, participant_account, pubkey)?;
+    let balance = BalanceInfo {
+        wallet_balance: participant.wallet_balance,
+        reserved_balance: participant.reserved_balance,
+        collateral_balance: participant.collateral_balance,
+        pending_withdrawal: participant.pending_withdrawal,
+    };
+    set_return_data(&balance.try_to_vec()?);
+
+    Ok(())
+}
+
+// Returns OpenOrders, oldest first. On account storage the ledger cannot list a participant's
+// orders, so the caller passes the order PDAs to report after the participant PDA; PDAs of other
+// owners are skipped.
+fn get_open_orders(program_id: &Pubkey, accounts: &[AccountInfo], pubkey: Pubkey) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    let participant = query_participant(program_id, ledger_account, participant_account, pubkey)?;
+    let ledger = load_ledger(ledger_account)?;
+
+    let mut orders: Vec<OpenOrder> = if ledger.config.order_storage == OrderStorage::Accounts {
+        account_info_iter
+            .map(|order_account| load_order_account(program_id, ledger_account, order_account))
+            .filter(|order| order.as_ref().map_or(true, |order| order.owner == pubkey))
+            .map(|order| order.map(|order| OpenOrder {
+                order_id: order.order_id,
+                side: order.side,
+                energy_amount: order.energy_amount,
+                price: order.price,
+                expires_at: order.expires_at,
+                delivery_slot: order.delivery_slot,
+            }))
+            .collect::<Result<_, ProgramError>>()?
+    } else {
+        let productions = ledger.productions.iter().filter(|p| p.producer_id == pubkey).map(|p| OpenOrder {
+            order_id: p.order_id,
+            side: OrderSide::Production,
+            energy_amount: p.energy_amount,
+            price: p.price,
+            expires_at: p.expires_at,
+            delivery_slot: p.delivery_slot,
+        });
+        let demands = ledger.demands.iter().filter(|d| d.consumer_id == pubkey).map(|d| OpenOrder {
+            order_id: d.order_id,
+            side: OrderSide::Demand,
+            energy_amount: d.energy_amount,
+            price: d.price_limit,
+            expires_at: d.expires_at,
+            delivery_slot: d.delivery_slot,
+        });
+        productions.chain(demands).collect()
+    };
+    orders.sort_by_key(|order| order.order_id);
+    orders.truncate(MAX_QUERIED_ORDERS);
+
+    let open_orders = OpenOrders { open_orders: participant.open_orders(), orders };
+    set_return_data(&open_orders.try_to_vec()?);
+
+    Ok(())
+}
+
+// The ledger and the caller, who need not sign or be registered, followed by the same order
+// accounts as match_transactions. Runs run_matching on the in-memory ledger and returns a
+// MatchSimulation; nothing is persisted.
+fn simulate_match(program_id: &Pubkey, accounts: &[AccountInfo], max_matches: u16) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let caller_account = next_account(account_info_iter, "caller")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_trading_enabled(&ledger)?;
+    let mut matching_accounts = load_matching_accounts(program_id, ledger_account, &mut ledger, account_info_iter)?;
+
+    let timestamp = Clock::get()?.unix_timestamp;
+    let matched_trades = run_matching(&mut ledger, &mut matching_accounts.participants, timestamp, max_matches as usize)?.trades;
+    let simulation = summarize_matches(&matched_trades, caller_account.key)?;
+    // A run the crank could not record fails the same way here
+    append_transactions(&mut ledger, matched_trades)?;
+
+    set_return_data(&simulation.try_to_vec()?);
+
+    Ok(())
+}
+
+pub fn summarize_matches(trades: &[Transaction], caller: &Pubkey) -> Result<MatchSimulation, ProgramError> {
+    let mut simulation = MatchSimulation {
+        trade_count: u32::try_from(trades.len()).map_err(|_| ProgramError::ArithmeticOverflow)?,
+        total_volume: 0,
+        lowest_price: trades.iter().map(|t| t.price).min().unwrap_or(0),
+        highest_price: trades.iter().map(|t| t.price).max().unwrap_or(0),
+        caller_fills: Vec::new(),
+        caller_fills_truncated: false,
+    };
+    for trade in trades {
+        simulation.total_volume = simulation.total_volume.checked_add(trade.amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        let sides = [
+            (trade.from, OrderSide::Demand, trade.demand_order_id, trade.delivered_amount),
+            (trade.to, OrderSide::Production, trade.production_order_id, trade.injected_amount),
+        ];
+        for (owner, side, order_id, amount) in sides {
+            if owner != *caller {
+                continue;
+            }
+            if simulation.caller_fills.len() == MAX_SIMULATED_FILLS {
+                simulation.caller_fills_truncated = true;
+                continue;
+            }
+            simulation.caller_fills.push(SimulatedFill { order_id, side, amount, price: trade.price });
+        }
+    }
+    Ok(simulation)
+}
+
+fn sells(participant_type: &ParticipantType) -> bool {
+    matches!(participant_type, ParticipantType::Producer | ParticipantType::Prosumer | ParticipantType::Storage)
+}
+
+fn buys(participant_type: &ParticipantType) -> bool {
+    matches!(participant_type, ParticipantType::Consumer | ParticipantType::Prosumer | ParticipantType::Storage)
+}
+
+fn assert_can_sell(participant: &Participant) -> ProgramResult {
+    if !sells(&participant.participant_type) {
+        return Err(EnergyMarketError::InvalidParticipantType.into());
+    }
+    Ok(())
+}
+
+fn assert_can_buy(participant: &Participant) -> ProgramResult {
+    if !buys(&participant.participant_type) {
+        return Err(EnergyMarketError::InvalidParticipantType.into());
+    }
+    Ok(())
+}
+
+// The part of the notional the maker of a bilateral offer owes, reserved when the offer is made
+fn bilateral_escrow(side: OrderSide, energy_amount: u64, price: i64) -> Result<u64, ProgramError> {
+    let maker_buys = side == OrderSide::Demand;
+    if maker_buys == (price > 0) {
+        balance_change(notional(energy_amount, price))
+    } else {
+        Ok(0)
+    }
+}
+
+fn load_bilateral_offer(program_id: &Pubkey, ledger_account: &AccountInfo, offer_account: &AccountInfo, offer_id: u64) -> Result<BilateralOffer, ProgramError> {
+    assert_account_type(program_id, offer_account, AccountType::BilateralOffer, EnergyMarketError::OrderNotFound)?;
+    let offer = BilateralOffer::deserialize(&mut &offer_account.data.borrow()[..])?;
+
+    let expected = Pubkey::create_program_address(
+        &[BILATERAL_SEED, ledger_account.key.as_ref(), &offer.offer_id.to_le_bytes(), &[offer.bump]],
+        program_id,
+    )?;
+    if expected != *offer_account.key {
+        return Err(EnergyMarketError::InvalidOrderAccount.into());
+    }
+    if offer.offer_id != offer_id {
+        return Err(EnergyMarketError::OrderNotFound.into());
+    }
+    Ok(offer)
+}
+
+// Records a trade agreed off-chain as a pending offer to one named counterparty, bypassing the
+// book. The maker pays the offer PDA's rent and reserves the notional it would owe on acceptance.
+fn create_bilateral_offer(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    counterparty: Pubkey,
+    side: OrderSide,
+    energy_amount: u64,
+    price: i64,
+    expires_at: i64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let maker_account = next_account(account_info_iter, "maker")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let maker_participant_account = next_account(account_info_iter, "maker participant")?;
+    let offer_account = next_account(account_info_iter, "offer")?;
+    let system_program_account = next_account(account_info_iter, "system program")?;
+
+    assert_signer(maker_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if *system_program_account.key != system_program::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_trading_enabled(&ledger)?;
+
+    let mut maker = load_participant(program_id, ledger_account, maker_participant_account, Some(maker_account.key))?;
+    match side {
+        OrderSide::Production => assert_can_sell(&maker)?,
+        OrderSide::Demand => assert_can_buy(&maker)?,
+    }
+    assert_not_frozen(&maker)?;
+    if counterparty == *maker_account.key {
+        return Err(EnergyMarketError::InvalidCounterparty.into());
+    }
+    assert_valid_order(&ledger.config, energy_amount, price)?;
+
+    let created_at = Clock::get()?.unix_timestamp;
+    assert_valid_expiration(expires_at, created_at)?;
+
+    let offer_id = next_order_id(&mut ledger)?;
+    let (offer_key, bump) = find_bilateral_offer_address(program_id, ledger_account.key, offer_id);
+    if offer_key != *offer_account.key {
+        return Err(EnergyMarketError::InvalidOrderAccount.into());
+    }
+
+    let escrow = bilateral_escrow(side, energy_amount, price)?;
+    reserve_funds(&mut maker, escrow)?;
+
+    create_pda_account(
+        program_id,
+        maker_account,
+        offer_account,
+        system_program_account,
+        BILATERAL_OFFER_SIZE,
+        &[BILATERAL_SEED, ledger_account.key.as_ref(), &offer_id.to_le_bytes(), &[bump]],
+    )?;
+    let offer = BilateralOffer {
+        account_type: AccountType::BilateralOffer,
+        bump,
+        offer_id,
+        maker: *maker_account.key,
+        counterparty,
+        side,
+        energy_amount,
+        price,
+        created_at,
+        expires_at,
+        escrow,
+    };
+    offer.serialize(&mut &mut offer_account.data.borrow_mut()[..])?;
+    msg!("Bilateral offer {} created for {:?}", offer_id, counterparty);
+
+    save_participant(&maker, maker_participant_account)?;
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Settles a bilateral offer at its agreed price. The offer must be accepted by the counterparty it
+// names, whose balance is checked now rather than when the offer was made. Fees and storage limits
+// apply as they would to a matched trade, but the trade always settles at once and leaves the
+// reference price to the book. The offer PDA's rent goes back to the maker. The ledger's trade log
+// may follow the offer account.
+fn accept_bilateral_offer(program_id: &Pubkey, accounts: &[AccountInfo], offer_id: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let counterparty_account = next_account(account_info_iter, "counterparty")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let counterparty_participant_account = next_account(account_info_iter, "counterparty participant")?;
+    let maker_account = next_account(account_info_iter, "maker")?;
+    let maker_participant_account = next_account(account_info_iter, "maker participant")?;
+    let offer_account = next_account(account_info_iter, "offer")?;
+
+    assert_signer(counterparty_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_trading_enabled(&ledger)?;
+
+    let offer = load_bilateral_offer(program_id, ledger_account, offer_account, offer_id)?;
+    if offer.counterparty != *counterparty_account.key {
+        return Err(EnergyMarketError::NotOfferCounterparty.into());
+    }
+    if offer.maker != *maker_account.key {
+        return Err(EnergyMarketError::InvalidParticipantAccount.into());
+    }
+    let now = Clock::get()?.unix_timestamp;
+    if is_expired(offer.expires_at, now) {
+        return Err(EnergyMarketError::InvalidExpiration.into());
+    }
+
+    let mut maker = load_participant(program_id, ledger_account, maker_participant_account, Some(maker_account.key))?;
+    let mut counterparty = load_participant(
+        program_id, ledger_account, counterparty_participant_account, Some(counterparty_account.key),
+    )?;
+    assert_not_frozen(&maker)?;
+    assert_not_frozen(&counterparty)?;
+    release_funds(&mut maker, offer.escrow)?;
+
+    let (buyer, seller) = match offer.side {
+        OrderSide::Demand => (&mut maker, &mut counterparty),
+        OrderSide::Production => (&mut counterparty, &mut maker),
+    };
+    assert_can_buy(buyer)?;
+    assert_can_sell(seller)?;
+    let amount = offer.energy_amount;
+    if is_storage(seller) && amount > seller.stored_energy {
+        return Err(EnergyMarketError::InsufficientStoredEnergy.into());
+    }
+    if is_storage(buyer) && amount > charge_headroom(buyer) {
+        return Err(EnergyMarketError::StorageFull.into());
+    }
+
+    let total_cost = balance_change(notional(amount, offer.price))?;
+    let fee = protocol_fee(total_cost as u128, ledger.config.fee_bps)?;
+    let wheeling_fee = if buyer.zone == seller.zone {
+        0
+    } else {
+        amount.checked_mul(ledger.config.zones.wheeling_fee)
+            .ok_or(ProgramError::ArithmeticOverflow)?
+    };
+
+    // As on the book, the notional is owed by the buyer at a positive price and by the seller at a
+    // negative one; the buyer pays the wheeling fee and the seller the protocol fee
+    let (buyer_cost, buyer_credit, seller_cost, proceeds) = if offer.price < 0 {
+        let seller_cost = total_cost.checked_add(fee).ok_or(ProgramError::ArithmeticOverflow)?;
+        (wheeling_fee, total_cost, seller_cost, 0)
+    } else {
+        let buyer_cost = total_cost.checked_add(wheeling_fee).ok_or(ProgramError::ArithmeticOverflow)?;
+        let proceeds = total_cost.checked_sub(fee).ok_or(ProgramError::ArithmeticOverflow)?;
+        (buyer_cost, 0, 0, proceeds)
+    };
+    if buyer.wallet_balance.saturating_add(buyer_credit) < buyer_cost || seller.wallet_balance < seller_cost {
+        return Err(EnergyMarketError::InsufficientBalance.into());
+    }
+    buyer.wallet_balance = buyer.wallet_balance.checked_add(buyer_credit)
+        .and_then(|balance| balance.checked_sub(buyer_cost))
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    seller.wallet_balance = seller.wallet_balance.checked_add(proceeds)
+        .and_then(|balance| balance.checked_sub(seller_cost))
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    collect_trading_fees(&mut ledger, fee)?;
+    ledger.protocol_fees = ledger.protocol_fees.checked_add(wheeling_fee)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    if is_storage(seller) {
+        seller.stored_energy = seller.stored_energy.checked_sub(amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    }
+    if is_storage(buyer) {
+        buyer.stored_energy = buyer.stored_energy
+            .checked_add(stored_after_losses(amount, buyer.storage_efficiency_bps))
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    }
+
+    record_trade_outcome(seller, true)?;
+    // Bilateral trades deliver in the slot they are accepted in
+    let delivery_slot = delivery_slot_at(now);
+    record_position(&ledger.config.imbalance, seller, delivery_slot, amount, 0)?;
+    record_position(&ledger.config.imbalance, buyer, delivery_slot, 0, amount)?;
+
+    let trade = Transaction {
+        trade_id: ledger.total_trades,
+        demand_order_id: offer.offer_id,
+        production_order_id: offer.offer_id,
+        from: buyer.id,
+        to: seller.id,
+        amount,
+        price: offer.price,
+        timestamp: now,
+        status: TradeStatus::Settled,
+        settlement_deadline: 0,
+        escrow: 0,
+        proceeds,
+        energy_source: EnergySource::Other,
+        bilateral: true,
+        injected_amount: amount,
+        delivered_amount: amount,
+        netting: false,
+    };
+    ledger.stats.record(&trade)?;
+    emit(&events::TradeExecuted::from(&trade))?;
+    let trade_log_account = next_trade_log(&ledger, account_info_iter);
+    record_trades(program_id, ledger_account, &mut ledger, trade_log_account, vec![trade])?;
+    msg!("Bilateral offer {} accepted", offer_id);
+
+    close_account(offer_account, maker_account)?;
+    save_participant(&maker, maker_participant_account)?;
+    save_participant(&counterparty, counterparty_participant_account)?;
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Withdraws a bilateral offer, releasing the maker's escrow and refunding the PDA's rent to the
+// maker. Either party may reject an open offer; once expired anyone may clean it up.
+fn reject_bilateral_offer(program_id: &Pubkey, accounts: &[AccountInfo], offer_id: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let signer_account = next_account(account_info_iter, "signer")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let maker_account = next_account(account_info_iter, "maker")?;
+    let maker_participant_account = next_account(account_info_iter, "maker participant")?;
+    let offer_account = next_account(account_info_iter, "offer")?;
+
+    assert_signer(signer_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let offer = load_bilateral_offer(program_id, ledger_account, offer_account, offer_id)?;
+    if offer.maker != *maker_account.key {
+        return Err(EnergyMarketError::InvalidParticipantAccount.into());
+    }
+    let is_party = *signer_account.key == offer.maker || *signer_account.key == offer.counterparty;
+    if !is_party && !is_expired(offer.expires_at, Clock::get()?.unix_timestamp) {
+        return Err(EnergyMarketError::NotOfferCounterparty.into());
+    }
+
+    let mut maker = load_participant(program_id, ledger_account, maker_participant_account, Some(maker_account.key))?;
+    release_funds(&mut maker, offer.escrow)?;
+    close_account(offer_account, maker_account)?;
+    msg!("Bilateral offer {} withdrawn", offer_id);
+
+    save_participant(&maker, maker_participant_account)?;
+
+    Ok(())
+}
+
+fn load_forward_contract(
+    program_id: &Pubkey,
+    ledger_account: &AccountInfo,
+    contract_account: &AccountInfo,
+    contract_id: u64,
+) -> Result<ForwardContract, ProgramError> {
+    assert_account_type(program_id, contract_account, AccountType::ForwardContract, EnergyMarketError::OrderNotFound)?;
+    let contract = ForwardContract::deserialize(&mut &contract_account.data.borrow()[..])?;
+
+    let expected = Pubkey::create_program_address(
+        &[FORWARD_SEED, ledger_account.key.as_ref(), &contract.contract_id.to_le_bytes(), &[contract.bump]],
+        program_id,
+    )?;
+    if expected != *contract_account.key {
+        return Err(EnergyMarketError::InvalidOrderAccount.into());
+    }
+    if contract.contract_id != contract_id {
+        return Err(EnergyMarketError::OrderNotFound.into());
+    }
+    Ok(contract)
+}
+
+// What each side of a forward posts: margin_bps of the contract's notional, rounded up
+pub fn forward_margin(energy_amount: u64, price: i64, margin_bps: u16) -> Result<u64, ProgramError> {
+    if margin_bps == 0 || margin_bps > 10_000 {
+        return Err(EnergyMarketError::InvalidMargin.into());
+    }
+    let margin = balance_change((notional(energy_amount, price) * margin_bps as u128).div_ceil(10_000))?;
+    if margin == 0 {
+        return Err(EnergyMarketError::InvalidMargin.into());
+    }
+    Ok(margin)
+}
+
+// Offers a forward contract for a future delivery slot, to one counterparty or to anyone. The
+// maker pays the contract PDA's rent and posts its margin from its free balance.
+#[allow(clippy::too_many_arguments)]
+fn create_forward(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    counterparty: Option<Pubkey>,
+    side: OrderSide,
+    energy_amount: u64,
+    price: i64,
+    delivery_slot: u32,
+    margin_bps: u16,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let maker_account = next_account(account_info_iter, "maker")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let maker_participant_account = next_account(account_info_iter, "maker participant")?;
+    let contract_account = next_account(account_info_iter, "contract")?;
+    let system_program_account = next_account(account_info_iter, "system program")?;
+
+    assert_signer(maker_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if *system_program_account.key != system_program::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_trading_enabled(&ledger)?;
+
+    let mut maker = load_participant(program_id, ledger_account, maker_participant_account, Some(maker_account.key))?;
+    match side {
+        OrderSide::Production => assert_can_sell(&maker)?,
+        OrderSide::Demand => assert_can_buy(&maker)?,
+    }
+    assert_not_frozen(&maker)?;
+    if counterparty == Some(*maker_account.key) {
+        return Err(EnergyMarketError::InvalidCounterparty.into());
+    }
+    assert_valid_order(&ledger.config, energy_amount, price)?;
+
+    let created_at = Clock::get()?.unix_timestamp;
+    if delivery_slot <= delivery_slot_at(created_at) {
+        return Err(EnergyMarketError::InvalidDeliverySlot.into());
+    }
+    let margin = forward_margin(energy_amount, price, margin_bps)?;
+
+    let contract_id = next_order_id(&mut ledger)?;
+    let (contract_key, bump) = find_forward_contract_address(program_id, ledger_account.key, contract_id);
+    if contract_key != *contract_account.key {
+        return Err(EnergyMarketError::InvalidOrderAccount.into());
+    }
+
+    reserve_funds(&mut maker, margin)?;
+    maker.open_forwards = maker.open_forwards.checked_add(1).ok_or(ProgramError::ArithmeticOverflow)?;
+
+    create_pda_account(
+        program_id,
+        maker_account,
+        contract_account,
+        system_program_account,
+        FORWARD_CONTRACT_SIZE,
+        &[FORWARD_SEED, ledger_account.key.as_ref(), &contract_id.to_le_bytes(), &[bump]],
+    )?;
+    let contract = ForwardContract {
+        account_type: AccountType::ForwardContract,
+        bump,
+        contract_id,
+        maker: *maker_account.key,
+        side,
+        counterparty,
+        taker: None,
+        energy_amount,
+        price,
+        delivery_slot,
+        margin_bps,
+        margin,
+        created_at,
+    };
+    contract.serialize(&mut &mut contract_account.data.borrow_mut()[..])?;
+    msg!("Forward contract {} created for slot {}", contract_id, delivery_slot);
+
+    save_participant(&maker, maker_participant_account)?;
+    save_ledger(&ledger, ledger_account)?;
+    emit(&events::ForwardCreated {
+        contract_id,
+        maker: contract.maker,
+        counterparty,
+        side,
+        energy_amount,
+        price,
+        delivery_slot,
+        margin,
+    })?;
+
+    Ok(())
+}
+
+// Takes the other side of a forward contract before its delivery slot begins, posting the same
+// margin as the maker
+fn accept_forward(program_id: &Pubkey, accounts: &[AccountInfo], contract_id: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let taker_account = next_account(account_info_iter, "taker")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let taker_participant_account = next_account(account_info_iter, "taker participant")?;
+    let contract_account = next_account(account_info_iter, "contract")?;
+
+    assert_signer(taker_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let ledger = load_ledger(ledger_account)?;
+    assert_trading_enabled(&ledger)?;
+    let mut contract = load_forward_contract(program_id, ledger_account, contract_account, contract_id)?;
+    if contract.taker.is_some() || contract.delivery_slot <= delivery_slot_at(Clock::get()?.unix_timestamp) {
+        return Err(EnergyMarketError::ForwardClosed.into());
+    }
+    if contract.counterparty.is_some_and(|counterparty| counterparty != *taker_account.key) {
+        return Err(EnergyMarketError::NotOfferCounterparty.into());
+    }
+    if contract.maker == *taker_account.key {
+        return Err(EnergyMarketError::InvalidCounterparty.into());
+    }
+
+    let mut taker = load_participant(program_id, ledger_account, taker_participant_account, Some(taker_account.key))?;
+    match contract.side {
+        OrderSide::Production => assert_can_buy(&taker)?,
+        OrderSide::Demand => assert_can_sell(&taker)?,
+    }
+    assert_not_frozen(&taker)?;
+    reserve_funds(&mut taker, contract.margin)?;
+    taker.open_forwards = taker.open_forwards.checked_add(1).ok_or(ProgramError::ArithmeticOverflow)?;
+
+    contract.taker = Some(*taker_account.key);
+    contract.serialize(&mut &mut contract_account.data.borrow_mut()[..])?;
+    msg!("Forward contract {} taken by {:?}", contract_id, taker_account.key);
+
+    save_participant(&taker, taker_participant_account)?;
+    emit(&events::ForwardAccepted { contract_id, taker: *taker_account.key })?;
+
+    Ok(())
+}
+
+// Closes a forward contract and returns its rent to the maker; anyone may crank it.
+// - Never taken: the maker withdraws it at any time, anyone else once its slot has begun.
+// - Once its delivery slot has ended the contract nets against the VWAP the slot traded at: the
+//   side that lost pays (spot - price) * amount out of its margin, capped at the margin. A slot
+//   that recorded no price, or whose entry has been overwritten, voids the contract.
+// - Before then, a side whose loss marked to the slot's VWAP so far, or to the reference price
+//   while the slot has not traded, has used up its margin is liquidated: its whole margin goes to
+//   the counterparty.
+// The taker's participant PDA follows the contract account once the contract has been taken.
+fn settle_forward(program_id: &Pubkey, accounts: &[AccountInfo], contract_id: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let signer_account = next_account(account_info_iter, "signer")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let maker_account = next_account(account_info_iter, "maker")?;
+    let maker_participant_account = next_account(account_info_iter, "maker participant")?;
+    let contract_account = next_account(account_info_iter, "contract")?;
+
+    assert_signer(signer_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let ledger = load_ledger(ledger_account)?;
+    let contract = load_forward_contract(program_id, ledger_account, contract_account, contract_id)?;
+    if contract.maker != *maker_account.key {
+        return Err(EnergyMarketError::InvalidParticipantAccount.into());
+    }
+    let now = Clock::get()?.unix_timestamp;
+    let mut maker = load_participant(program_id, ledger_account, maker_participant_account, Some(maker_account.key))?;
+
+    let Some(taker_key) = contract.taker else {
+        if *signer_account.key != contract.maker && contract.delivery_slot > delivery_slot_at(now) {
+            return Err(EnergyMarketError::ForwardNotDue.into());
+        }
+        close_forward(&mut maker, contract.margin)?;
+        close_account(contract_account, maker_account)?;
+        msg!("Forward contract {} withdrawn", contract_id);
+        save_participant(&maker, maker_participant_account)?;
+        return emit(&events::ForwardCancelled { contract_id });
+    };
+    let taker_participant_account = next_account(account_info_iter, "taker participant")?;
+    let mut taker = load_participant(program_id, ledger_account, taker_participant_account, Some(&taker_key))?;
+
+    let (settlement_price, liquidated) = if now >= delivery_slot_end(contract.delivery_slot) {
+        match slot_price(&ledger, contract.delivery_slot) {
+            Some(price) => (price, false),
+            None => {
+                close_forward(&mut maker, contract.margin)?;
+                close_forward(&mut taker, contract.margin)?;
+                close_account(contract_account, maker_account)?;
+                msg!("Forward contract {} voided: slot {} has no price", contract_id, contract.delivery_slot);
+                save_participant(&maker, maker_participant_account)?;
+                save_participant(&taker, taker_participant_account)?;
+                return emit(&events::ForwardCancelled { contract_id });
+            }
+        }
+    } else {
+        let reference_price = (ledger.reference_price != 0).then_some(ledger.reference_price);
+        let mark = slot_price(&ledger, contract.delivery_slot).or(reference_price)
+            .ok_or(EnergyMarketError::ForwardNotDue)?;
+        (mark, true)
+    };
+
+    // The buyer gains what the spot price rose above the contract price, the seller what it fell
+    let difference = (settlement_price as i128 - contract.price as i128) * contract.energy_amount as i128;
+    let loss = u64::try_from(difference.unsigned_abs()).unwrap_or(u64::MAX);
+    if liquidated && loss < contract.margin {
+        return Err(EnergyMarketError::ForwardNotDue.into());
+    }
+    let amount = loss.min(contract.margin);
+    let maker_buys = contract.side == OrderSide::Demand;
+    let maker_pays = (difference < 0) == maker_buys;
+
+    close_forward(&mut maker, contract.margin)?;
+    close_forward(&mut taker, contract.margin)?;
+    let (payer, payee) = if maker_pays { (&mut maker, &mut taker) } else { (&mut taker, &mut maker) };
+    payer.wallet_balance = payer.wallet_balance.checked_sub(amount).ok_or(ProgramError::ArithmeticOverflow)?;
+    payee.wallet_balance = payee.wallet_balance.checked_add(amount).ok_or(ProgramError::ArithmeticOverflow)?;
+    let (payer, payee) = (payer.id, payee.id);
+
+    close_account(contract_account, maker_account)?;
+    msg!("Forward contract {} settled at {}: {:?} paid {}", contract_id, settlement_price, payer, amount);
+
+    save_participant(&maker, maker_participant_account)?;
+    save_participant(&taker, taker_participant_account)?;
+    emit(&events::ForwardSettled { contract_id, settlement_price, payer, payee, amount, liquidated })?;
+
+    Ok(())
+}
+
+// Releases a side's margin as its contract closes
+fn close_forward(participant: &mut Participant, margin: u64) -> ProgramResult {
+    release_funds(participant, margin)?;
+    participant.open_forwards = participant.open_forwards.checked_sub(1).ok_or(ProgramError::ArithmeticOverflow)?;
+    Ok(())
+}
+
+// Standing orders only ever post to the ledger's own book, so a ledger on account order storage
+// has none
+#[allow(clippy::too_many_arguments)]
+fn create_standing_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    side: OrderSide,
+    energy_amount: u64,
+    price: i64,
+    interval_secs: i64,
+    first_activation: i64,
+    activations: u32,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner_account = next_account(account_info_iter, "owner")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    assert_signer(owner_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_trading_enabled(&ledger)?;
+    if ledger.config.order_storage != OrderStorage::Ledger || interval_secs <= 0 || activations == 0 {
+        return Err(EnergyMarketError::InvalidStandingOrder.into());
+    }
+
+    let participant = load_participant(program_id, ledger_account, participant_account, Some(owner_account.key))?;
+    match side {
+        OrderSide::Production => assert_can_sell(&participant)?,
+        OrderSide::Demand => assert_can_buy(&participant)?,
+    }
+    assert_not_frozen(&participant)?;
+    assert_valid_order(&ledger.config, energy_amount, price)?;
+    assert_order_increments(&ledger.config, energy_amount, price)?;
+    assert_order_size(&ledger.config, energy_amount)?;
+
+    let owned = ledger.standing_orders.iter().filter(|standing| standing.owner == participant.id).count();
+    if owned >= MAX_STANDING_ORDERS_PER_PARTICIPANT || ledger.standing_orders.len() >= MAX_STANDING_ORDERS {
+        return Err(EnergyMarketError::TooManyStandingOrders.into());
+    }
+
+    let standing_order_id = next_order_id(&mut ledger)?;
+    ledger.standing_orders.push(StandingOrder {
+        standing_order_id,
+        owner: participant.id,
+        side,
+        energy_amount,
+        price,
+        interval_secs,
+        next_activation: first_activation,
+        remaining_activations: activations,
+    });
+    msg!("Standing order {} created, first activating at {}", standing_order_id, first_activation);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Orders a standing order already posted stay open
+fn cancel_standing_order(program_id: &Pubkey, accounts: &[AccountInfo], standing_order_id: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner_account = next_account(account_info_iter, "owner")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    assert_signer(owner_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    let index = ledger.standing_orders.iter()
+        .position(|standing| standing.standing_order_id == standing_order_id)
+        .ok_or(EnergyMarketError::OrderNotFound)?;
+    if ledger.standing_orders[index].owner != *owner_account.key {
+        return Err(EnergyMarketError::NotOrderOwner.into());
+    }
+    ledger.standing_orders.remove(index);
+    msg!("Standing order {} cancelled", standing_order_id);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Permissionless crank activating every due standing order whose owner's participant PDA follows
+// the ledger; the others stay due for a later run. Each activation posts a good-til-cancelled order
+// for the delivery slot under way and moves next_activation to the first interval boundary after
+// now, so a crank that comes late posts one order instead of catching up on every missed interval.
+// An activation failing the owner's checks, such as one it cannot fund, is used up and skipped with
+// an event; a full order book ends the run with the remaining standing orders still due.
+fn activate_standing_orders(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let cranker_account = next_account(account_info_iter, "cranker")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    assert_signer(cranker_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_trading_enabled(&ledger)?;
+    let now = Clock::get()?.unix_timestamp;
+
+    let mut participant_accounts = Vec::new();
+    let mut participants: Vec<Participant> = Vec::new();
+    for participant_account in account_info_iter {
+        let participant = load_participant(program_id, ledger_account, participant_account, None)?;
+        if participants.iter().all(|p| p.id != participant.id) {
+            participant_accounts.push(participant_account);
+            participants.push(participant);
+        }
+    }
+
+    let mut index = 0;
+    while index < ledger.standing_orders.len() {
+        let standing = ledger.standing_orders[index].clone();
+        let owner_index = participants.iter().position(|p| p.id == standing.owner);
+        let Some(owner_index) = owner_index.filter(|_| standing.next_activation <= now) else {
+            index += 1;
+            continue;
+        };
+        if assert_order_book_capacity(&ledger).is_err() {
+            msg!("Order book is full; standing orders stay due");
+            break;
+        }
+
+        let mut owner = participants[owner_index].clone();
+        let posted = post_standing_order(program_id, ledger_account, &mut ledger, &mut owner, &standing, now);
+        let missed = (now - standing.next_activation) / standing.interval_secs;
+        let next_activation = missed.checked_add(1)
+            .and_then(|intervals| intervals.checked_mul(standing.interval_secs))
+            .and_then(|elapsed| standing.next_activation.checked_add(elapsed))
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        let remaining_activations = standing.remaining_activations - 1;
+        match posted {
+            Ok(order_id) => {
+                participants[owner_index] = owner;
+                msg!("Standing order {} posted order {}", standing.standing_order_id, order_id);
+                emit(&events::StandingOrderActivated {
+                    standing_order_id: standing.standing_order_id,
+                    owner: standing.owner,
+                    order_id,
+                    next_activation,
+                    remaining_activations,
+                })?;
+            }
+            Err(ProgramError::Custom(error)) => {
+                msg!("Standing order {} skipped with error {}", standing.standing_order_id, error);
+                emit(&events::StandingOrderSkipped {
+                    standing_order_id: standing.standing_order_id,
+                    owner: standing.owner,
+                    error,
+                    next_activation,
+                    remaining_activations,
+                })?;
+            }
+            Err(error) => return Err(error),
+        }
+
+        if remaining_activations == 0 {
+            ledger.standing_orders.remove(index);
+        } else {
+            ledger.standing_orders[index].next_activation = next_activation;
+            ledger.standing_orders[index].remaining_activations = remaining_activations;
+            index += 1;
+        }
+    }
+
+    for (participant_account, participant) in participant_accounts.into_iter().zip(&participants) {
+        save_participant(participant, participant_account)?;
+    }
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Makes the checks PostDemand or ReportProduction would of the standing order's next order and
+// posts it. The ledger is only changed once every check passed.
+fn post_standing_order(
+    program_id: &Pubkey,
+    ledger_account: &AccountInfo,
+    ledger: &mut Ledger,
+    participant: &mut Participant,
+    standing: &StandingOrder,
+    now: i64,
+) -> Result<u64, ProgramError> {
+    let StandingOrder { energy_amount, price, .. } = *standing;
+    assert_valid_order(&ledger.config, energy_amount, price)?;
+    assert_order_increments(&ledger.config, energy_amount, price)?;
+    assert_order_size(&ledger.config, energy_amount)?;
+    assert_not_frozen(participant)?;
+    let delivery_slot = delivery_slot_at(now);
+    assert_within_tariff(&ledger.config.tariff, standing.side, price, delivery_slot)?;
+
+    match standing.side {
+        OrderSide::Demand => {
+            assert_can_buy(participant)?;
+            if is_storage(participant) && energy_amount > charge_headroom(participant) {
+                return Err(EnergyMarketError::StorageFull.into());
+            }
+            if participant.wallet_balance < ledger.config.min_balance_to_post_demand {
+                return Err(EnergyMarketError::BalanceTooLowToPost.into());
+            }
+            let mut demand = EnergyDemand {
+                order_id: 0,
+                consumer_id: participant.id,
+                energy_amount,
+                price_limit: price,
+                created_at: now,
+                expires_at: 0,
+                delivery_slot,
+                zone: participant.zone,
+                min_fill: 0,
+                all_or_nothing: false,
+                priority: 0,
+                escrow_per_kwh: escrow_per_kwh(&ledger.config, price)?,
+            };
+            reserve_funds(participant, demand_escrow(&demand)?)?;
+            take_order_slot(participant, OrderSide::Demand, &ledger.config)?;
+            let order_id = next_order_id(ledger)?;
+            demand.order_id = order_id;
+            ledger.demands.push(demand);
+            Ok(order_id)
+        }
+        OrderSide::Production => {
+            assert_can_sell(participant)?;
+            if is_storage(participant) && energy_amount > participant.stored_energy {
+                return Err(EnergyMarketError::InsufficientStoredEnergy.into());
+            }
+            if participant.curtailed_until > now {
+                return Err(EnergyMarketError::ProducerCurtailed.into());
+            }
+            let other_orders = participant.open_orders();
+            let totals = open_offer_totals(
+                program_id, ledger_account, ledger, participant, 0, delivery_slot, other_orders, std::iter::empty(),
+            )?;
+            assert_offer_limits(ledger, participant, &totals, energy_amount, price, delivery_slot)?;
+            take_order_slot(participant, OrderSide::Production, &ledger.config)?;
+            let order_id = next_order_id(ledger)?;
+            ledger.productions.push(EnergyProduction {
+                order_id,
+                producer_id: participant.id,
+                energy_amount,
+                price,
+                created_at: now,
+                expires_at: 0,
+                delivery_slot,
+                zone: participant.zone,
+                verified: ledger.oracle == Pubkey::default(),
+                energy_source: EnergySource::Other,
+                min_fill: 0,
+                all_or_nothing: false,
+            });
+            Ok(order_id)
+        }
+    }
+}
+
+// Splits `total` in proportion to `weights` so the shares add up to it exactly: every share is
+// rounded down and what is left goes one unit each to the largest remainders, earlier entries
+// first on ties. All-zero weights get nothing.
+pub fn split_proportionally(total: u64, weights: &[u64]) -> Vec<u64> {
+    let weight_sum: u128 = weights.iter().map(|&weight| weight as u128).sum();
+    if weight_sum == 0 {
+        return vec![0; weights.len()];
+    }
+    let exact: Vec<(u64, u128)> = weights.iter()
+        .map(|&weight| {
+            let scaled = total as u128 * weight as u128;
+            ((scaled / weight_sum) as u64, scaled % weight_sum)
+        })
+        .collect();
+    let mut shares: Vec<u64> = exact.iter().map(|&(share, _)| share).collect();
+    let left = total - shares.iter().sum::<u64>();
+    let mut by_remainder: Vec<usize> = (0..weights.len()).collect();
+    by_remainder.sort_by_key(|&index| std::cmp::Reverse(exact[index].1));
+    for &index in by_remainder.iter().take(left as usize) {
+        shares[index] += 1;
+    }
+    shares
+}
+
+// Both the aggregator and the joining member sign, the member's signature being its consent to
+// have aggregated demands drawn from its balance
+fn add_member(program_id: &Pubkey, accounts: &[AccountInfo], weight: u32) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let aggregator_account = next_account(account_info_iter, "aggregator")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let aggregator_participant_account = next_account(account_info_iter, "aggregator participant")?;
+    let member_account = next_account(account_info_iter, "member")?;
+    let member_participant_account = next_account(account_info_iter, "member participant")?;
+
+    assert_signer(aggregator_account)?;
+    assert_signer(member_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if weight == 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let mut aggregator = load_participant(program_id, ledger_account, aggregator_participant_account, Some(aggregator_account.key))?;
+    if !matches!(aggregator.participant_type, ParticipantType::Aggregator) {
+        return Err(EnergyMarketError::InvalidParticipantType.into());
+    }
+    let mut member = load_participant(program_id, ledger_account, member_participant_account, Some(member_account.key))?;
+    assert_can_buy(&member)?;
+    if member.aggregator.is_some() || aggregator.member_count as usize >= MAX_AGGREGATOR_MEMBERS {
+        return Err(EnergyMarketError::MembershipFull.into());
+    }
+
+    aggregator.members[aggregator.member_count as usize] = AggregatorMember { wallet: member.id, weight, contribution: 0 };
+    aggregator.member_count += 1;
+    member.aggregator = Some(aggregator.id);
+    msg!("{:?} joined aggregator {:?} with weight {}", member.id, aggregator.id, weight);
+
+    save_participant(&aggregator, aggregator_participant_account)?;
+    save_participant(&member, member_participant_account)?;
+    emit(&events::MemberAdded { aggregator: aggregator.id, member: member.id, weight })?;
+
+    Ok(())
+}
+
+// Either side may end a membership, though not while the member's funds are pooled
+fn remove_member(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let signer_account = next_account(account_info_iter, "signer")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let aggregator_participant_account = next_account(account_info_iter, "aggregator participant")?;
+    let member_participant_account = next_account(account_info_iter, "member participant")?;
+
+    assert_signer(signer_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut aggregator = load_participant(program_id, ledger_account, aggregator_participant_account, None)?;
+    let mut member = load_participant(program_id, ledger_account, member_participant_account, None)?;
+    if *signer_account.key != aggregator.id && *signer_account.key != member.id {
+        return Err(EnergyMarketError::Unauthorized.into());
+    }
+    assert_no_pooled_funds(&aggregator)?;
+    let count = aggregator.member_count as usize;
+    let index = aggregator.members[..count].iter()
+        .position(|m| m.wallet == member.id)
+        .ok_or(EnergyMarketError::NotAggregatorMember)?;
 
-#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
-pub struct EnergyProduction {
-    pub producer_id: Pubkey,
-    pub energy_amount: u64,
-    pub price: u64,
+    aggregator.members.copy_within(index + 1..count, index);
+    aggregator.members[count - 1] = AggregatorMember::default();
+    aggregator.member_count -= 1;
+    member.aggregator = None;
+    msg!("{:?} left aggregator {:?}", member.id, aggregator.id);
+
+    save_participant(&aggregator, aggregator_participant_account)?;
+    save_participant(&member, member_participant_account)?;
+    emit(&events::MemberRemoved { aggregator: aggregator.id, member: member.id })?;
+
+    Ok(())
 }
 
-#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
-pub struct EnergyDemand {
-    pub consumer_id: Pubkey,
-    pub energy_amount: u64,
-    pub price_limit: u64,
+// One demand on behalf of all the aggregator's members, whose participant PDAs follow in
+// membership order. The demand's escrow, the buyer's worst case for the whole demand, is drawn from
+// the members in proportion to their weights and reserved as for any demand. On account order
+// storage the order PDA and the system program follow the members.
+fn post_aggregated_demand(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    energy_amount: u64,
+    price_limit: i64,
+    expires_at: i64,
+    delivery_slot: u32,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let aggregator_account = next_account(account_info_iter, "aggregator")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let aggregator_participant_account = next_account(account_info_iter, "aggregator participant")?;
+
+    assert_signer(aggregator_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_trading_enabled(&ledger)?;
+    assert_order_book_capacity(&ledger)?;
+    assert_valid_order(&ledger.config, energy_amount, price_limit)?;
+    assert_order_increments(&ledger.config, energy_amount, price_limit)?;
+    assert_order_size(&ledger.config, energy_amount)?;
+
+    let created_at = Clock::get()?.unix_timestamp;
+    assert_valid_expiration(expires_at, created_at)?;
+    assert_valid_delivery_slot(delivery_slot, created_at)?;
+    assert_within_tariff(&ledger.config.tariff, OrderSide::Demand, price_limit, delivery_slot)?;
+
+    let mut aggregator = load_participant(program_id, ledger_account, aggregator_participant_account, Some(aggregator_account.key))?;
+    if !matches!(aggregator.participant_type, ParticipantType::Aggregator) {
+        return Err(EnergyMarketError::InvalidParticipantType.into());
+    }
+    assert_not_frozen(&aggregator)?;
+    assert_no_pooled_funds(&aggregator)?;
+    if aggregator.member_count == 0 {
+        return Err(EnergyMarketError::NotAggregatorMember.into());
+    }
+    // The demand's escrow is the only reservation settlement waits to see released
+    if aggregator.reserved_balance > 0 {
+        return Err(EnergyMarketError::ParticipantHasBalance.into());
+    }
+
+    let mut demand = EnergyDemand {
+        order_id: 0,
+        consumer_id: aggregator.id,
+        energy_amount,
+        price_limit,
+        created_at,
+        expires_at,
+        delivery_slot,
+        zone: aggregator.zone,
+        min_fill: 0,
+        all_or_nothing: false,
+        priority: 0,
+        escrow_per_kwh: escrow_per_kwh(&ledger.config, price_limit)?,
+    };
+    // The members pool exactly what the demand's escrow reserves
+    let count = aggregator.member_count as usize;
+    let pool = demand_escrow(&demand)?;
+    let weights: Vec<u64> = aggregator.members[..count].iter().map(|m| m.weight as u64).collect();
+    let contributions = split_proportionally(pool, &weights);
+    for (index, contribution) in contributions.into_iter().enumerate() {
+        let member_account = next_account(account_info_iter, "member participant")?;
+        let mut member = load_participant(program_id, ledger_account, member_account, None)?;
+        if member.id != aggregator.members[index].wallet {
+            return Err(EnergyMarketError::NotAggregatorMember.into());
+        }
+        assert_not_frozen(&member)?;
+        member.wallet_balance = member.wallet_balance.checked_sub(contribution)
+            .ok_or(EnergyMarketError::InsufficientBalance)?;
+        aggregator.members[index].contribution = contribution;
+        save_participant(&member, member_account)?;
+    }
+    aggregator.wallet_balance = aggregator.wallet_balance.checked_add(pool)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    aggregator.pooled_balance = pool;
+
+    let order_id = next_order_id(&mut ledger)?;
+    demand.order_id = order_id;
+    reserve_funds(&mut aggregator, pool)?;
+
+    if ledger.config.order_storage == OrderStorage::Accounts {
+        let order = OrderAccount {
+            account_type: AccountType::Order,
+            bump: 0,
+            side: OrderSide::Demand,
+            order_id,
+            owner: aggregator.id,
+            energy_amount,
+            price: price_limit,
+            created_at,
+            expires_at,
+            delivery_slot,
+            zone: demand.zone,
+            verified: true,
+            energy_source: EnergySource::Other,
+            min_fill: 0,
+            all_or_nothing: false,
+            priority: 0,
+            escrow_per_kwh: demand.escrow_per_kwh,
+        };
+        create_order_account(program_id, ledger_account, &mut ledger, aggregator_account, account_info_iter, order)?;
+    } else {
+        ledger.demands.push(demand);
+    }
+    take_order_slot(&mut aggregator, OrderSide::Demand, &ledger.config)?;
+    aggregator.aggregated_order_id = Some(order_id);
+    aggregator.aggregated_filled = 0;
+    msg!("Aggregated demand {} created with {} pooled from {} members", order_id, pool, count);
+
+    save_participant(&aggregator, aggregator_participant_account)?;
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
 }
 
-#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
-pub struct Transaction {
-    pub from: Pubkey,
-    pub to: Pubkey,
-    pub amount: u64,
-    pub price: u64,
-    pub timestamp: i64,
+// Once the aggregated demand has left the book and none of its trades awaits settlement, anyone
+// may hand the pooled balance, which is what the fills did not spend, and the energy bought back
+// to the members in proportion to their contributions. The members' participant PDAs follow
+// in membership order.
+fn settle_aggregated_demand(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let signer_account = next_account(account_info_iter, "signer")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let aggregator_participant_account = next_account(account_info_iter, "aggregator participant")?;
+
+    assert_signer(signer_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let ledger = load_ledger(ledger_account)?;
+    let mut aggregator = load_participant(program_id, ledger_account, aggregator_participant_account, None)?;
+    let order_id = aggregator.aggregated_order_id.ok_or(EnergyMarketError::OrderNotFound)?;
+    if aggregator.open_demands > 0 || aggregator.reserved_balance > 0 || has_pending_trades(&ledger, &aggregator.id) {
+        return Err(EnergyMarketError::AggregatedDemandOpen.into());
+    }
+
+    let count = aggregator.member_count as usize;
+    let contributions: Vec<u64> = aggregator.members[..count].iter().map(|m| m.contribution).collect();
+    let refunds = split_proportionally(aggregator.pooled_balance, &contributions);
+    let energy = split_proportionally(aggregator.aggregated_filled, &contributions);
+    for index in 0..count {
+        let member_account = next_account(account_info_iter, "member participant")?;
+        let mut member = load_participant(program_id, ledger_account, member_account, None)?;
+        if member.id != aggregator.members[index].wallet {
+            return Err(EnergyMarketError::NotAggregatorMember.into());
+        }
+        member.wallet_balance = member.wallet_balance.checked_add(refunds[index])
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        aggregator.members[index].contribution = 0;
+        save_participant(&member, member_account)?;
+        emit(&events::MemberSettled {
+            aggregator: aggregator.id,
+            member: member.id,
+            order_id,
+            energy_amount: energy[index],
+            // A refund never exceeds the contribution: the balance shared out is at most the pool
+            paid: contributions[index] - refunds[index],
+            refund: refunds[index],
+        })?;
+    }
+    msg!("Aggregated demand {} settled, {} refunded", order_id, aggregator.pooled_balance);
+    aggregator.wallet_balance = aggregator.wallet_balance.checked_sub(aggregator.pooled_balance)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    aggregator.pooled_balance = 0;
+    aggregator.aggregated_order_id = None;
+    aggregator.aggregated_filled = 0;
+
+    save_participant(&aggregator, aggregator_participant_account)?;
+
+    Ok(())
 }
 
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
-pub struct Ledger {
-    pub participants: Vec<Participant>,
-    pub productions: Vec<EnergyProduction>,
-    pub demands: Vec<EnergyDemand>,
-    pub transactions: Vec<Transaction>,
+// Open orders keep their prices; the schedule applies to orders posted or replaced afterwards
+fn set_tariff_schedule(program_id: &Pubkey, accounts: &[AccountInfo], schedule: TariffSchedule) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    assert_valid_tariff_schedule(&schedule)?;
+
+    ledger.config.tariff = schedule;
+    msg!("Tariff schedule set to {:?}", schedule);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
 }
 
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
-pub enum EnergyMarketInstruction {
-    InitializeLedger,
-    RegisterParticipant { participant_type: ParticipantType },
-    ReportProduction { energy_amount: u64, price: u64 },
-    PostDemand { energy_amount: u64, price_limit: u64 },
-    MatchTransactions,
-    Deposit { amount: u64 },
-    Withdraw { amount: u64 },
+// Only prosumers both buy and sell, so only they have positions to net
+fn set_net_metering(program_id: &Pubkey, accounts: &[AccountInfo], enabled: bool) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    assert_signer(wallet_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut participant = load_participant(program_id, ledger_account, participant_account, Some(wallet_account.key))?;
+    if !matches!(participant.participant_type, ParticipantType::Prosumer) {
+        return Err(EnergyMarketError::InvalidParticipantType.into());
+    }
+    participant.net_metering = enabled;
+    msg!("Net metering for {:?} set to {}", participant.id, enabled);
+
+    save_participant(&participant, participant_account)?;
+
+    Ok(())
 }
 
-entrypoint!(process_instruction);
+// Open positions settle at the prices in force when they are settled. Disabling tracking stops new
+// positions from opening; the open ones still settle, at no charge.
+fn set_imbalance_config(program_id: &Pubkey, accounts: &[AccountInfo], imbalance: ImbalanceConfig) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
 
-pub fn process_instruction(
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    assert_valid_imbalance_config(&imbalance)?;
+
+    ledger.config.imbalance = imbalance;
+    msg!("Imbalance config set to {:?}", imbalance);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// The oracle's meter readings for a participant over a delivery slot that has ended. A participant
+// that metered energy without trading in the slot gets a position for it; a later report for the
+// same slot replaces the earlier one until the position is settled.
+fn report_actuals(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    instruction_data: &[u8],
+    delivery_slot: u32,
+    metered_injection: u64,
+    metered_consumption: u64,
 ) -> ProgramResult {
-    let instruction = EnergyMarketInstruction::try_from_slice(instruction_data)?;
+    let account_info_iter = &mut accounts.iter();
+    let oracle_account = next_account(account_info_iter, "oracle")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
 
-    match instruction {
-        EnergyMarketInstruction::InitializeLedger => initialize_ledger(program_id, accounts),
-        EnergyMarketInstruction::RegisterParticipant { participant_type } => {
-            register_participant(program_id, accounts, participant_type)
-        }
-        EnergyMarketInstruction::ReportProduction { energy_amount, price } => {
-            report_energy_production(program_id, accounts, energy_amount, price)
-        }
-        EnergyMarketInstruction::PostDemand { energy_amount, price_limit } => {
-            post_energy_demand(program_id, accounts, energy_amount, price_limit)
-        }
-        EnergyMarketInstruction::MatchTransactions => match_transactions(program_id, accounts),
-        EnergyMarketInstruction::Deposit { amount } => deposit(program_id, accounts, amount),
-        EnergyMarketInstruction::Withdraw { amount } => withdraw(program_id, accounts, amount),
+    assert_signer(oracle_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let ledger = load_ledger(ledger_account)?;
+    if ledger.oracle == Pubkey::default() || ledger.oracle != *oracle_account.key {
+        return Err(EnergyMarketError::InvalidOracle.into());
+    }
+    if Clock::get()?.unix_timestamp < delivery_slot_end(delivery_slot) {
+        return Err(EnergyMarketError::DeliverySlotNotOver.into());
+    }
+
+    let mut participant = load_participant(program_id, ledger_account, participant_account, None)?;
+    if !ledger.config.imbalance.enabled() && participant.position(delivery_slot).is_none() {
+        return Err(EnergyMarketError::PositionNotFound.into());
     }
+    let position = participant.position_mut(delivery_slot)?;
+    position.reported = true;
+    position.metered_injection = metered_injection;
+    position.metered_consumption = metered_consumption;
+    msg!(
+        "Slot {} actuals for {:?}: {} kWh injected, {} kWh consumed",
+        delivery_slot, participant.id, metered_injection, metered_consumption,
+    );
+
+    save_participant(&participant, participant_account)?;
+
+    Ok(())
 }
 
-fn initialize_ledger(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+// Squares a participant's position for a delivery slot against its actuals; anyone may crank it. A
+// surplus is credited to the free balance out of the fee pool and a shortfall charged from it
+// into the pool, failing while the free balance cannot cover it.
+fn settle_imbalances(program_id: &Pubkey, accounts: &[AccountInfo], delivery_slot: u32) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
-    let ledger_account = next_account_info(account_info_iter)?;
+    let settler_account = next_account(account_info_iter, "settler")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    assert_signer(settler_account)?;
 
     if ledger_account.owner != program_id {
         return Err(ProgramError::IncorrectProgramId);
     }
 
-    let ledger = Ledger {
-        participants: Vec::new(),
-        productions: Vec::new(),
-        demands: Vec::new(),
-        transactions: Vec::new(),
+    let mut ledger = load_ledger(ledger_account)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, None)?;
+    let count = participant.position_count as usize;
+    let index = participant.positions[..count].iter()
+        .position(|p| p.delivery_slot == delivery_slot)
+        .ok_or(EnergyMarketError::PositionNotFound)?;
+    let position = participant.positions[index];
+
+    let imbalance = if position.reported {
+        position_imbalance(&position)
+    } else {
+        let timeout = ledger.config.imbalance.actuals_timeout;
+        if timeout == 0 || Clock::get()?.unix_timestamp < delivery_slot_end(delivery_slot).saturating_add(timeout) {
+            return Err(EnergyMarketError::ActualsNotReported.into());
+        }
+        msg!("No actuals for slot {} of {:?}, settling without imbalance", delivery_slot, participant.id);
+        0
+    };
+    let imbalance_kwh = i64::try_from(imbalance).map_err(|_| ProgramError::ArithmeticOverflow)?;
+    let price = if imbalance > 0 { ledger.config.imbalance.surplus_price } else { ledger.config.imbalance.shortfall_price };
+    let amount = u64::try_from(imbalance.unsigned_abs() * price as u128).map_err(|_| ProgramError::ArithmeticOverflow)?;
+    let (charged, credited) = if imbalance > 0 {
+        ledger.protocol_fees = ledger.protocol_fees.checked_sub(amount)
+            .ok_or(EnergyMarketError::ImbalancePoolExhausted)?;
+        participant.wallet_balance = participant.wallet_balance.checked_add(amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        (0, amount)
+    } else {
+        participant.wallet_balance = participant.wallet_balance.checked_sub(amount)
+            .ok_or(EnergyMarketError::InsufficientBalance)?;
+        ledger.protocol_fees = ledger.protocol_fees.checked_add(amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        (amount, 0)
     };
 
-    ledger.serialize(&mut &mut ledger_account.data.borrow_mut()[..])?;
+    participant.positions.copy_within(index + 1..count, index);
+    participant.positions[count - 1] = SlotPosition::default();
+    participant.position_count -= 1;
+    msg!("Settled slot {} of {:?}: imbalance {} kWh, charged {}, credited {}", delivery_slot, participant.id, imbalance_kwh, charged, credited);
+
+    save_participant(&participant, participant_account)?;
+    save_ledger(&ledger, ledger_account)?;
+    emit(&events::ImbalanceSettled {
+        participant: participant.id,
+        delivery_slot,
+        imbalance_kwh,
+        charged,
+        credited,
+        reported: position.reported,
+    })?;
 
     Ok(())
 }
 
-fn register_participant(program_id: &Pubkey, accounts: &[AccountInfo], participant_type: ParticipantType) -> ProgramResult {
+// Moves free balance of the admin's participant into the insurance pool
+fn top_up_insurance(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
-    let participant_account = next_account_info(account_info_iter)?;
-    let ledger_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let admin_participant_account = next_account(account_info_iter, "admin participant")?;
 
     if ledger_account.owner != program_id {
         return Err(ProgramError::IncorrectProgramId);
     }
 
-    let mut ledger = Ledger::try_from_slice(&ledger_account.data.borrow())?;
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    let mut admin = load_participant(program_id, ledger_account, admin_participant_account, Some(admin_account.key))?;
 
-    let new_participant = Participant {
-        id: *participant_account.key,
-        participant_type,
-        wallet_balance: 0,
-    };
+    admin.wallet_balance = admin.wallet_balance.checked_sub(amount)
+        .ok_or(EnergyMarketError::InsufficientBalance)?;
+    ledger.insurance_pool = ledger.insurance_pool.checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    msg!("Topped up the insurance pool by {} to {}", amount, ledger.insurance_pool);
+
+    save_participant(&admin, admin_participant_account)?;
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Replaces any pending drain, so consumers always get the full timelock to claim before the pool
+// shrinks
+fn propose_insurance_drain(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
 
-    ledger.participants.push(new_participant);
+    if amount == 0 {
+        ledger.insurance_drain.take().ok_or(EnergyMarketError::NoInsuranceDrain)?;
+        msg!("Insurance drain cancelled");
+    } else {
+        let effective_at = Clock::get()?.unix_timestamp.saturating_add(ledger.config.config_timelock);
+        ledger.insurance_drain = Some(InsuranceDrain { amount, effective_at });
+        msg!("Drain of {} from the insurance pool effective at {}", amount, effective_at);
+    }
 
-    ledger.serialize(&mut &mut ledger_account.data.borrow_mut()[..])?;
+    save_ledger(&ledger, ledger_account)?;
 
     Ok(())
 }
 
-fn report_energy_production(program_id: &Pubkey, accounts: &[AccountInfo], energy_amount: u64, price: u64) -> ProgramResult {
+// Pays the drain, or whatever is left of the pool if that is less, to the admin's participant
+fn drain_insurance(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
-    let producer_account = next_account_info(account_info_iter)?;
-    let ledger_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let admin_participant_account = next_account(account_info_iter, "admin participant")?;
 
     if ledger_account.owner != program_id {
         return Err(ProgramError::IncorrectProgramId);
     }
 
-    let mut ledger = Ledger::try_from_slice(&ledger_account.data.borrow())?;
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    let mut admin = load_participant(program_id, ledger_account, admin_participant_account, Some(admin_account.key))?;
+    let drain = ledger.insurance_drain.ok_or(EnergyMarketError::NoInsuranceDrain)?;
+    if Clock::get()?.unix_timestamp < drain.effective_at {
+        return Err(EnergyMarketError::InsuranceDrainNotEffective.into());
+    }
+
+    let amount = drain.amount.min(ledger.insurance_pool);
+    ledger.insurance_pool -= amount;
+    ledger.insurance_drain = None;
+    admin.wallet_balance = admin.wallet_balance.checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    msg!("Drained {} from the insurance pool, {} left", amount, ledger.insurance_pool);
+
+    save_participant(&admin, admin_participant_account)?;
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Pays the consumer's claim in full while the pool covers every outstanding claim, and otherwise
+// the pool's share of it, so claimants are paid alike whatever order they claim in. The claim is
+// closed either way; one made against an empty pool fails and stays open for a top-up.
+fn claim_insurance(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    assert_signer(wallet_account)?;
 
-    if !ledger.participants.iter().any(|p| p.id == *producer_account.key) {
-        return Err(ProgramError::InvalidAccountData);
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
     }
 
-    let production = EnergyProduction {
-        producer_id: *producer_account.key,
-        energy_amount,
-        price,
+    let mut ledger = load_ledger(ledger_account)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, Some(wallet_account.key))?;
+    let claim = participant.insurance_claim;
+    if claim == 0 {
+        return Err(EnergyMarketError::NoInsuranceClaim.into());
+    }
+    if ledger.insurance_pool == 0 {
+        return Err(EnergyMarketError::InsurancePoolEmpty.into());
+    }
+
+    let payout = if ledger.insurance_pool >= ledger.insurance_claims {
+        claim
+    } else {
+        (claim as u128 * ledger.insurance_pool as u128 / ledger.insurance_claims as u128) as u64
     };
+    ledger.insurance_pool -= payout;
+    ledger.insurance_claims = ledger.insurance_claims.checked_sub(claim)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    participant.insurance_claim = 0;
+    participant.wallet_balance = participant.wallet_balance.checked_add(payout)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    msg!("Paid {} of a {} insurance claim to {:?}", payout, claim, participant.id);
+
+    save_participant(&participant, participant_account)?;
+    save_ledger(&ledger, ledger_account)?;
+    emit(&events::InsuranceClaimed { participant: participant.id, claim, payout })?;
+
+    Ok(())
+}
+
+// Locks `amount` of a seller's wallet_balance for at least lock_duration seconds. Staking again
+// adds to the stake and never brings its unlock time forward.
+fn stake_for_priority(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64, lock_duration: i64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    assert_signer(wallet_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut participant = load_participant(program_id, ledger_account, participant_account, Some(wallet_account.key))?;
+    assert_can_sell(&participant)?;
+    assert_no_pooled_funds(&participant)?;
+    if amount == 0 || lock_duration <= 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let unlocks_at = Clock::get()?.unix_timestamp.checked_add(lock_duration)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    participant.wallet_balance = participant.wallet_balance.checked_sub(amount)
+        .ok_or(EnergyMarketError::InsufficientBalance)?;
+    participant.staked_balance = participant.staked_balance.checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    participant.stake_unlocks_at = participant.stake_unlocks_at.max(unlocks_at);
+    msg!("Staked {}, {} locked until {}", amount, participant.staked_balance, participant.stake_unlocks_at);
+
+    save_participant(&participant, participant_account)?;
+
+    Ok(())
+}
+
+fn unstake_after_cooldown(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    assert_signer(wallet_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut participant = load_participant(program_id, ledger_account, participant_account, Some(wallet_account.key))?;
+    assert_not_frozen(&participant)?;
+    if Clock::get()?.unix_timestamp < participant.stake_unlocks_at {
+        return Err(EnergyMarketError::StakeLocked.into());
+    }
 
-    ledger.productions.push(production);
+    participant.staked_balance = participant.staked_balance.checked_sub(amount)
+        .ok_or(EnergyMarketError::InsufficientStake)?;
+    participant.wallet_balance = participant.wallet_balance.checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    msg!("Unstaked {}, {} still staked", amount, participant.staked_balance);
 
-    ledger.serialize(&mut &mut ledger_account.data.borrow_mut()[..])?;
+    save_participant(&participant, participant_account)?;
 
     Ok(())
 }
 
-fn post_energy_demand(program_id: &Pubkey, accounts: &[AccountInfo], energy_amount: u64, price_limit: u64) -> ProgramResult {
+// New rates apply to fills from now on; points are credited at fill time, so none accrued in
+// earlier epochs are revalued
+fn set_reward_rates(program_id: &Pubkey, accounts: &[AccountInfo], rates: RewardRates) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
-    let consumer_account = next_account_info(account_info_iter)?;
-    let ledger_account = next_account_info(account_info_iter)?;
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
 
     if ledger_account.owner != program_id {
         return Err(ProgramError::IncorrectProgramId);
     }
 
-    let mut ledger = Ledger::try_from_slice(&ledger_account.data.borrow())?;
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+
+    ledger.config.rewards = rates;
+    ledger.reward_epoch = ledger.reward_epoch.checked_add(1)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    msg!("Reward epoch {} starts with rates {:?}", ledger.reward_epoch, rates);
+
+    save_ledger(&ledger, ledger_account)?;
+
+    Ok(())
+}
+
+// Moves free balance of the admin's participant into the rewards pool
+fn fund_rewards(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let admin_participant_account = next_account(account_info_iter, "admin participant")?;
 
-    if !ledger.participants.iter().any(|p| p.id == *consumer_account.key) {
-        return Err(ProgramError::InvalidAccountData);
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
     }
 
-    let demand = EnergyDemand {
-        consumer_id: *consumer_account.key,
-        energy_amount,
-        price_limit,
-    };
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    let mut admin = load_participant(program_id, ledger_account, admin_participant_account, Some(admin_account.key))?;
 
-    ledger.demands.push(demand);
+    admin.wallet_balance = admin.wallet_balance.checked_sub(amount)
+        .ok_or(EnergyMarketError::InsufficientBalance)?;
+    ledger.rewards_pool = ledger.rewards_pool.checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    msg!("Funded the rewards pool with {} to {}", amount, ledger.rewards_pool);
 
-    ledger.serialize(&mut &mut ledger_account.data.borrow_mut()[..])?;
+    save_participant(&admin, admin_participant_account)?;
+    save_ledger(&ledger, ledger_account)?;
 
     Ok(())
 }
 
-fn match_transactions(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+// Credits the participant's points one for one while the pool lasts; points it cannot cover stay
+// on the participant for a later claim
+fn claim_rewards(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
-    let ledger_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    assert_signer(wallet_account)?;
 
     if ledger_account.owner != program_id {
         return Err(ProgramError::IncorrectProgramId);
     }
 
-    let mut ledger = Ledger::try_from_slice(&ledger_account.data.borrow())?;
+    let mut ledger = load_ledger(ledger_account)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, Some(wallet_account.key))?;
+    let points = participant.reward_points;
+    if points == 0 {
+        return Err(EnergyMarketError::NoRewardPoints.into());
+    }
+    if ledger.rewards_pool == 0 {
+        return Err(EnergyMarketError::RewardsPoolEmpty.into());
+    }
 
-    ledger.demands.sort_by(|a, b| b.energy_amount.cmp(&a.energy_amount));
-    ledger.productions.sort_by(|a, b| a.price.cmp(&b.price));
+    let credited = points.min(ledger.rewards_pool);
+    ledger.rewards_pool -= credited;
+    participant.reward_points -= credited;
+    participant.wallet_balance = participant.wallet_balance.checked_add(credited)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    msg!("Credited {} of {} reward points to {:?}", credited, points, participant.id);
 
-    let mut matched_trades = Vec::new();
+    save_participant(&participant, participant_account)?;
+    save_ledger(&ledger, ledger_account)?;
+    emit(&events::RewardsClaimed { participant: participant.id, reward_epoch: ledger.reward_epoch, points, credited })?;
 
-    for demand in &mut ledger.demands {
-        for production in &mut ledger.productions {
-            if demand.energy_amount == 0 {
-                break;
-            }
-            if demand.energy_amount <= production.energy_amount && demand.price_limit >= production.price {
-                let trade_amount = demand.energy_amount.min(production.energy_amount);
-                let trade_price = production.price;
-                let total_cost = trade_amount.checked_mul(trade_price)
-                    .ok_or(ProgramError::ArithmeticOverflow)?;
-
-                // Store the IDs instead of references
-                let consumer_id = demand.consumer_id;
-                let producer_id = production.producer_id;
-
-                // Perform the trade if the consumer has enough balance
-                if let (Some(consumer), Some(producer)) = (
-                    ledger.participants.iter_mut().find(|p| p.id == consumer_id),
-                    ledger.participants.iter_mut().find(|p| p.id == producer_id)
-                ) {
-                    if consumer.wallet_balance >= total_cost {
-                        consumer.wallet_balance = consumer.wallet_balance.checked_sub(total_cost)
-                            .ok_or(ProgramError::ArithmeticOverflow)?;
-                        producer.wallet_balance = producer.wallet_balance.checked_add(total_cost)
-                            .ok_or(ProgramError::ArithmeticOverflow)?;
-
-                        demand.energy_amount = demand.energy_amount.checked_sub(trade_amount)
-                            .ok_or(ProgramError::ArithmeticOverflow)?;
-                        production.energy_amount = production.energy_amount.checked_sub(trade_amount)
-                            .ok_or(ProgramError::ArithmeticOverflow)?;
-
-                        matched_trades.push(Transaction {
-                            from: consumer_id,
-                            to: producer_id,
-                            amount: trade_amount,
-                            price: trade_price,
-                            timestamp: Clock::get()?.unix_timestamp,
-                        });
-                    } else {
-                        msg!("Insufficient balance for demand from {:?}", consumer_id);
-                    }
-                }
-            }
-        }
+    Ok(())
+}
+
+// Changing the thresholds affects the open proposal too: it is resolved against the config in
+// force when it is executed
+fn set_governance_config(program_id: &Pubkey, accounts: &[AccountInfo], governance: GovernanceConfig) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account(account_info_iter, "admin")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
     }
 
-    ledger.productions.retain(|p| p.energy_amount > 0);
-    ledger.demands.retain(|d| d.energy_amount > 0);
-    ledger.transactions.extend(matched_trades);
+    let mut ledger = load_ledger(ledger_account)?;
+    assert_admin(&ledger, admin_account, accounts)?;
+    assert_valid_governance_config(&governance)?;
+
+    ledger.config.governance = governance;
+    msg!("Governance config set to {:?}", governance);
 
-    ledger.serialize(&mut &mut ledger_account.data.borrow_mut()[..])?;
+    save_ledger(&ledger, ledger_account)?;
 
     Ok(())
 }
 
-fn deposit(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+// The update is checked against the same bounds as UpdateConfig when proposed, and again when executed
+fn create_proposal(program_id: &Pubkey, accounts: &[AccountInfo], update: ConfigUpdate, voting_deadline: i64) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
-    let participant_account = next_account_info(account_info_iter)?;
-    let ledger_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    assert_signer(wallet_account)?;
 
     if ledger_account.owner != program_id {
         return Err(ProgramError::IncorrectProgramId);
     }
 
-    let mut ledger = Ledger::try_from_slice(&ledger_account.data.borrow())?;
+    let mut ledger = load_ledger(ledger_account)?;
+    let participant = load_participant(program_id, ledger_account, participant_account, Some(wallet_account.key))?;
+    assert_not_frozen(&participant)?;
+    if !ledger.config.governance.enabled() {
+        return Err(EnergyMarketError::GovernanceDisabled.into());
+    }
+    if !update.governable() {
+        return Err(EnergyMarketError::ParameterNotGovernable.into());
+    }
+    if participant.wallet_balance < ledger.config.governance.proposal_threshold {
+        return Err(EnergyMarketError::BelowProposalThreshold.into());
+    }
+    if ledger.proposal.is_some() {
+        return Err(EnergyMarketError::ProposalOpen.into());
+    }
+    assert_valid_config_update(&ledger, update)?;
+    let now = Clock::get()?.unix_timestamp;
+    let earliest = now.checked_add(ledger.config.config_timelock)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    if voting_deadline <= now || voting_deadline < earliest {
+        return Err(EnergyMarketError::InvalidEffectiveTime.into());
+    }
+
+    let proposal_id = ledger.next_proposal_id;
+    ledger.next_proposal_id = proposal_id.checked_add(1)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    ledger.proposal = Some(Proposal {
+        proposal_id,
+        proposer: participant.id,
+        update,
+        voting_deadline,
+        votes_for: 0,
+        votes_against: 0,
+    });
+    msg!("Proposal {} by {:?}: {:?}, voting until {}", proposal_id, participant.id, update, voting_deadline);
 
-    if let Some(participant) = ledger.participants.iter_mut().find(|p| p.id == *participant_account.key) {
-        participant.wallet_balance = participant.wallet_balance.checked_add(amount)
-            .ok_or(ProgramError::ArithmeticOverflow)?;
-    } else {
-        return Err(ProgramError::InvalidAccountData);
+    save_ledger(&ledger, ledger_account)?;
+    emit(&events::ProposalCreated { proposal_id, proposer: participant.id, update, voting_deadline })?;
+
+    Ok(())
+}
+
+// A vote weighs the voter's wallet_balance at the time it is cast and cannot be changed. The weight
+// is a snapshot: nothing holds the balance afterwards, so a voter who moves its funds on, by a
+// transfer, a withdrawal or a trade, lets their new holder vote with them as well
+fn cast_vote(program_id: &Pubkey, accounts: &[AccountInfo], proposal_id: u64, support: bool) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let wallet_account = next_account(account_info_iter, "wallet")?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
+    let participant_account = next_account(account_info_iter, "participant")?;
+
+    assert_signer(wallet_account)?;
+
+    if ledger_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut ledger = load_ledger(ledger_account)?;
+    let mut participant = load_participant(program_id, ledger_account, participant_account, Some(wallet_account.key))?;
+    assert_not_frozen(&participant)?;
+    let proposal = ledger.proposal.as_mut()
+        .filter(|proposal| proposal.proposal_id == proposal_id)
+        .ok_or(EnergyMarketError::ProposalNotFound)?;
+    if Clock::get()?.unix_timestamp >= proposal.voting_deadline {
+        return Err(EnergyMarketError::VotingClosed.into());
     }
+    if participant.last_voted_proposal == Some(proposal_id) {
+        return Err(EnergyMarketError::AlreadyVoted.into());
+    }
+
+    let weight = participant.wallet_balance;
+    let tally = if support { &mut proposal.votes_for } else { &mut proposal.votes_against };
+    *tally = tally.checked_add(weight).ok_or(ProgramError::ArithmeticOverflow)?;
+    participant.last_voted_proposal = Some(proposal_id);
+    msg!("{:?} voted {} on proposal {} with {}", participant.id, if support { "for" } else { "against" }, proposal_id, weight);
 
-    ledger.serialize(&mut &mut ledger_account.data.borrow_mut()[..])?;
+    save_participant(&participant, participant_account)?;
+    save_ledger(&ledger, ledger_account)?;
+    emit(&events::VoteCast { proposal_id, voter: participant.id, support, weight })?;
 
     Ok(())
 }
 
-fn withdraw(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+// Closes the proposal once voting is over. A passing proposal is applied through the same path as
+// UpdateConfig; one that no longer validates against the current config is closed without effect.
+fn execute_proposal(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
-    let participant_account = next_account_info(account_info_iter)?;
-    let ledger_account = next_account_info(account_info_iter)?;
+    let ledger_account = next_account(account_info_iter, "ledger")?;
 
     if ledger_account.owner != program_id {
         return Err(ProgramError::IncorrectProgramId);
     }
 
-    let mut ledger = Ledger::try_from_slice(&ledger_account.data.borrow())?;
+    let mut ledger = load_ledger(ledger_account)?;
+    let proposal = ledger.proposal.ok_or(EnergyMarketError::ProposalNotFound)?;
+    if Clock::get()?.unix_timestamp < proposal.voting_deadline {
+        return Err(EnergyMarketError::VotingNotOver.into());
+    }
 
-    if let Some(participant) = ledger.participants.iter_mut().find(|p| p.id == *participant_account.key) {
-        if participant.wallet_balance < amount {
-            return Err(ProgramError::InsufficientFunds);
-        }
-        participant.wallet_balance = participant.wallet_balance.checked_sub(amount)
-            .ok_or(ProgramError::ArithmeticOverflow)?;
-    } else {
-        return Err(ProgramError::InvalidAccountData);
+    let mut passed = proposal_passes(&ledger.config.governance, &proposal);
+    if passed && assert_valid_config_update(&ledger, proposal.update).is_err() {
+        msg!("Proposal {} no longer fits the current config", proposal.proposal_id);
+        passed = false;
+    }
+    ledger.proposal = None;
+    if passed {
+        apply_config_update(&mut ledger, proposal.update)?;
     }
+    msg!("Proposal {} {}: {} for, {} against", proposal.proposal_id, if passed { "passed" } else { "failed" }, proposal.votes_for, proposal.votes_against);
 
-    ledger.serialize(&mut &mut ledger_account.data.borrow_mut()[..])?;
+    save_ledger(&ledger, ledger_account)?;
+    emit(&events::ProposalResolved {
+        proposal_id: proposal.proposal_id,
+        update: proposal.update,
+        votes_for: proposal.votes_for,
+        votes_against: proposal.votes_against,
+        passed,
+    })?;
 
     Ok(())
 }
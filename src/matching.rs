@@ -0,0 +1,742 @@
+// The matching engine: orders the book, crosses it pay-as-bid or by uniform-price auction and
+// settles fills on the in-memory ledger and participants. Nothing here touches accounts or the
+// runtime, so the instruction handlers only load and save state around these functions.
+use solana_program::{msg, program_error::ProgramError, pubkey::Pubkey};
+use std::collections::HashMap;
+
+use crate::{
+    accrue_recs, accrue_reward_points, balance_change, charge_headroom, collect_trading_fees, debug_assert_order_counts,
+    delivery_slot_end, demand_escrow, events::{self, emit}, exceeds_max_order_size, fill_escrow, is_storage, lacks_reputation, notional,
+    purge_expired_orders, record_position, record_price_sample, record_slot_price, record_trade_outcome, release_funds,
+    remove_orders, stored_after_losses, EnergyDemand, EnergyProduction, Ledger, LossBearer, MarketConfig, MarketMode, OrderSide,
+    OrderStorage, Participant, PriceSample, TradeStatus, Transaction,
+};
+
+// Price-time priority: best price first, ties broken by creation time and then by submission
+// order. Demands of a higher priority class all come before lower ones, whatever their price.
+// ReplaceOrder moves created_at forward to reset an order's queue position. Order ids are unique
+// and monotonic, so replaying the same ledger always yields the same trades. Offers at the same
+// price fill in order of their producers' stake. Offers from producers missing from `participants`
+// rank as if their producer had neither stake nor reputation.
+fn sort_order_book(ledger: &mut Ledger, participants: &[Participant]) {
+    ledger.demands.sort_by_key(|d| (std::cmp::Reverse(d.priority), std::cmp::Reverse(d.price_limit), d.created_at, d.order_id));
+    let prefer_renewable = ledger.config.prefer_renewable;
+    let stakes: HashMap<Pubkey, u64> = participants.iter().map(|p| (p.id, p.staked_balance)).collect();
+    let reputations: Option<HashMap<Pubkey, u32>> = ledger.config.reputation.prefer_reputable
+        .then(|| participants.iter().map(|p| (p.id, p.reputation)).collect());
+    ledger.productions.sort_by_key(|p| (
+        p.price,
+        prefer_renewable && !p.energy_source.is_renewable(),
+        std::cmp::Reverse(stakes.get(&p.producer_id).copied().unwrap_or(0)),
+        reputations.as_ref().map(|r| std::cmp::Reverse(r.get(&p.producer_id).copied().unwrap_or(0))),
+        p.created_at,
+        p.order_id,
+    ));
+}
+
+// What one crossing run did: the fills it made and how many crossing pairs it passed over
+#[derive(Debug, Default)]
+pub struct MatchRun {
+    pub trades: Vec<Transaction>,
+    // Pairs left unfilled because a side's balance could not pay for the fill
+    pub skipped_for_balance: u32,
+    // Pairs left unfilled because their price fell outside the circuit breaker's band
+    pub skipped_for_band: u32,
+}
+
+// Crosses the open demands against the open productions and settles the fills on the
+// participants' balances. Pure with respect to the runtime so it can be exercised off-chain.
+// Orders whose owners are missing from `participants` are left untouched.
+pub fn match_orders(
+    ledger: &mut Ledger,
+    participants: &mut [Participant],
+    timestamp: i64,
+    max_matches: usize,
+) -> Result<MatchRun, ProgramError> {
+    sort_order_book(ledger, participants);
+    cross_orders(ledger, participants, timestamp, None, max_matches, None)
+}
+
+// Crosses one incoming order, already in the book, against the resting orders of the opposite side
+// the way match_orders would, after purging expired orders. Resting orders do not trade with each
+// other. Only meaningful on pay-as-bid ledgers.
+pub fn match_taker_order(
+    ledger: &mut Ledger,
+    participants: &mut [Participant],
+    timestamp: i64,
+    order_id: u64,
+) -> Result<MatchRun, ProgramError> {
+    let expired = purge_expired_orders(ledger, participants, timestamp)?;
+    if expired > 0 {
+        msg!("Purged {} expired orders", expired);
+    }
+    sort_order_book(ledger, participants);
+    cross_orders(ledger, participants, timestamp, None, usize::MAX, Some(order_id))
+}
+
+// Orders only ever cross within one partition of the book: a delivery slot, further narrowed to a
+// single zone unless the ledger allows inter-zone trading
+pub type BookPartition = (u32, Option<u8>);
+
+pub fn book_partition(config: &MarketConfig, delivery_slot: u32, zone: u8) -> BookPartition {
+    (delivery_slot, (!config.zones.allow_inter_zone).then_some(zone))
+}
+
+// Uniform-price double auction, run independently for every book partition: finds the single price
+// where the partition's aggregate supply and demand curves intersect and executes every fill
+// clearable at it. Partitions whose curves do not cross are left untouched. last_clearing_price
+// records the price of the latest partition that cleared.
+pub fn run_uniform_auction(
+    ledger: &mut Ledger,
+    participants: &mut [Participant],
+    timestamp: i64,
+) -> Result<MatchRun, ProgramError> {
+    sort_order_book(ledger, participants);
+    let config = ledger.config.clone();
+    // Offers reputation rules keep out of matching add no supply to the curves either
+    let producers: HashMap<Pubkey, &Participant> = participants.iter().map(|p| (p.id, p)).collect();
+    let excluded = |p: &EnergyProduction| producers.get(&p.producer_id)
+        .is_some_and(|producer| lacks_reputation(&config.reputation, producer, p.energy_amount));
+    let mut partitions: Vec<BookPartition> = ledger.demands.iter()
+        .map(|d| book_partition(&config, d.delivery_slot, d.zone))
+        .collect();
+    partitions.sort_unstable();
+    partitions.dedup();
+
+    let mut clearing_prices = HashMap::new();
+    for partition in partitions {
+        let mut demands: Vec<EnergyDemand> = ledger.demands.iter()
+            .filter(|d| book_partition(&config, d.delivery_slot, d.zone) == partition && !exceeds_max_order_size(&config, d.energy_amount))
+            .cloned()
+            .collect();
+        // Priority classes only decide who fills first at the clearing price; the demand curve
+        // itself runs down by price
+        demands.sort_by_key(|d| std::cmp::Reverse(d.price_limit));
+        let productions: Vec<EnergyProduction> = ledger.productions.iter()
+            .filter(|p| book_partition(&config, p.delivery_slot, p.zone) == partition && !exceeds_max_order_size(&config, p.energy_amount) && !excluded(p))
+            .cloned()
+            .collect();
+        if let Some(clearing_price) = compute_clearing_price(&demands, &productions) {
+            clearing_prices.insert(partition, clearing_price);
+            ledger.last_clearing_price = clearing_price;
+        }
+    }
+    if clearing_prices.is_empty() {
+        return Ok(MatchRun::default());
+    }
+    cross_orders(ledger, participants, timestamp, Some(&clearing_prices), usize::MAX, None)
+}
+
+// Expects demands by descending limit, productions sorted by sort_order_book, and both holding a
+// single book partition. Walks the two curves until the next bid no longer covers the next ask;
+// the last crossing pair bounds the clearing price, narrowed so that no order left over after the
+// crossing would have wanted to trade at it.
+pub fn compute_clearing_price(demands: &[EnergyDemand], productions: &[EnergyProduction]) -> Option<i64> {
+    let mut bids = demands.iter().filter(|d| d.energy_amount > 0).map(|d| (d.price_limit, d.energy_amount));
+    let mut asks = productions.iter().filter(|p| p.energy_amount > 0 && p.verified).map(|p| (p.price, p.energy_amount));
+    let mut bid = bids.next();
+    let mut ask = asks.next();
+    let mut marginal = None;
+
+    while let (Some((limit, demand_left)), Some((price, supply_left))) = (bid, ask) {
+        if limit < price {
+            break;
+        }
+        marginal = Some((limit, price));
+        let quantity = demand_left.min(supply_left);
+        bid = if demand_left > quantity { Some((limit, demand_left - quantity)) } else { bids.next() };
+        ask = if supply_left > quantity { Some((price, supply_left - quantity)) } else { asks.next() };
+    }
+
+    let (marginal_bid, marginal_ask) = marginal?;
+    let upper = ask.map_or(marginal_bid, |(price, _)| marginal_bid.min(price));
+    let lower = bid.map_or(marginal_ask, |(limit, _)| marginal_ask.max(limit));
+
+    // A vertical overlap leaves a whole range that clears the same volume; take its midpoint.
+    // Half the width of any i64 range fits in an i64, even when it straddles zero.
+    Some(lower + ((upper as i128 - lower as i128) / 2) as i64)
+}
+
+// The crank reward on a fill is floor(notional * bps / 10000), split evenly between buyer and
+// seller with the seller covering the odd lamport. Returns (buyer share, seller share).
+pub fn split_crank_reward(notional: u128, crank_reward_bps: u16) -> Result<(u64, u64), ProgramError> {
+    let reward = notional * crank_reward_bps as u128 / 10_000;
+    let reward = balance_change(reward)?;
+    let buyer_share = reward / 2;
+    Ok((buyer_share, reward - buyer_share))
+}
+
+// The protocol fee rounds up, so the protocol gains at most one lamport per fill from rounding
+pub fn protocol_fee(notional: u128, fee_bps: u16) -> Result<u64, ProgramError> {
+    balance_change((notional * fee_bps as u128).div_ceil(10_000))
+}
+
+// The loss rate of a fill; cross-zone fills use the inter-zone rate when one is set
+fn transmission_loss_bps(config: &MarketConfig, cross_zone: bool) -> u16 {
+    if cross_zone && config.losses.inter_zone_loss_bps > 0 {
+        config.losses.inter_zone_loss_bps
+    } else {
+        config.losses.loss_factor_bps
+    }
+}
+
+// Energy that arrives from `injected_amount` once `loss_bps` of it, rounded up, is lost
+pub fn delivered_after_losses(injected_amount: u64, loss_bps: u16) -> u64 {
+    (injected_amount as u128 * (10_000 - loss_bps as u128) / 10_000) as u64
+}
+
+// The least energy to inject for `delivered_amount` to arrive, saturating at u64::MAX. Config
+// validation keeps loss_bps below 10000.
+pub fn injected_for_delivery(delivered_amount: u64, loss_bps: u16) -> u64 {
+    let injected = (delivered_amount as u128 * 10_000).div_ceil(10_000 - loss_bps as u128);
+    injected.min(u64::MAX as u128) as u64
+}
+
+// A trade may deviate from the reference price by at most max_deviation_bps of it. With no
+// reference yet, as on the first match run, or with the breaker disabled every price passes.
+pub fn within_price_band(reference_price: i64, price: i64, max_deviation_bps: u16) -> bool {
+    if reference_price == 0 || max_deviation_bps == 0 {
+        return true;
+    }
+    let deviation = (price as i128 - reference_price as i128).unsigned_abs();
+    deviation * 10_000 <= reference_price.unsigned_abs() as u128 * max_deviation_bps as u128
+}
+
+// None when no energy traded
+pub fn volume_weighted_price(trades: &[Transaction]) -> Result<Option<i64>, ProgramError> {
+    let (value, volume) = trade_totals(trades)?;
+    if volume == 0 {
+        return Ok(None);
+    }
+    // The average lies between the lowest and highest trade price, so it fits back into an i64
+    Ok(Some((value / volume) as i64))
+}
+
+// The notional and volume of a set of trades
+fn trade_totals(trades: &[Transaction]) -> Result<(i128, i128), ProgramError> {
+    trades.iter().try_fold((0i128, 0i128), |(value, volume), trade| {
+        let value = value.checked_add(trade.amount as i128 * trade.price as i128)?;
+        Some((value, volume.checked_add(trade.amount as i128)?))
+    }).ok_or(ProgramError::ArithmeticOverflow)
+}
+
+pub(crate) fn crank_reward(trades: &[Transaction], crank_reward_bps: u16) -> Result<u64, ProgramError> {
+    trades.iter().try_fold(0u64, |total, trade| {
+        let (buyer_share, seller_share) = split_crank_reward(notional(trade.amount, trade.price), crank_reward_bps)?;
+        total.checked_add(buyer_share + seller_share).ok_or(ProgramError::ArithmeticOverflow)
+    })
+}
+
+// The smallest fill an order accepts: its whole remaining amount when all-or-nothing, otherwise
+// min_fill, lowered to what remains so a partly filled order can still complete
+fn smallest_fill(energy_amount: u64, min_fill: u64, all_or_nothing: bool) -> u64 {
+    if all_or_nothing {
+        energy_amount
+    } else {
+        min_fill.min(energy_amount)
+    }
+}
+
+// What one fill moves, each part narrowed to the u64 it lands in
+struct FillCosts {
+    buyer_cost: u64,
+    buyer_credit: u64,
+    seller_cost: u64,
+    proceeds: u64,
+    // Protocol and wheeling fees, owed to the fee pool
+    fees: u64,
+    // Owed to the grid operator
+    grid_fee: u64,
+}
+
+// Each side always pays its own cuts; the notional is owed by the consumer at a positive price and
+// by the producer at a negative one. The buyer pays the wheeling and grid fees, the seller the
+// protocol fee.
+fn fill_costs(config: &MarketConfig, trade_amount: u64, trade_price: i64, cross_zone: bool) -> Result<FillCosts, ProgramError> {
+    let total_cost = notional(trade_amount, trade_price);
+    let (buyer_reward_share, seller_reward_share) = split_crank_reward(total_cost, config.crank_reward_bps)?;
+    let fee = protocol_fee(total_cost, config.fee_bps)? as u128;
+    let wheeling_fee = if cross_zone { trade_amount as u128 * config.zones.wheeling_fee as u128 } else { 0 };
+    let grid_fee = trade_amount as u128 * config.grid_fee_per_kwh as u128;
+    let buyer_cuts = buyer_reward_share as u128 + wheeling_fee + grid_fee;
+    let (buyer_cost, buyer_credit, seller_cost, proceeds) = if trade_price < 0 {
+        (buyer_cuts, total_cost, total_cost + seller_reward_share as u128 + fee, 0)
+    } else {
+        let proceeds = total_cost.checked_sub(seller_reward_share as u128 + fee)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        (total_cost + buyer_cuts, 0, 0, proceeds)
+    };
+    Ok(FillCosts {
+        buyer_cost: balance_change(buyer_cost)?,
+        buyer_credit: balance_change(buyer_credit)?,
+        seller_cost: balance_change(seller_cost)?,
+        proceeds: balance_change(proceeds)?,
+        fees: balance_change(fee + wheeling_fee)?,
+        grid_fee: balance_change(grid_fee)?,
+    })
+}
+
+// Only orders in the same book partition cross. With no clearing prices each fill pays the
+// producer's ask, otherwise every fill pays its partition's clearing price and only orders willing
+// to trade at it take part; partitions without a clearing price do not trade. Stops after max_matches
+// fills; the remaining quantities are persisted so the next call picks up where this one ended.
+// Below zero the payment runs the other way: the producer pays the consumer to take the energy.
+// With a taker order id only pairs involving that order cross.
+fn cross_orders(
+    ledger: &mut Ledger,
+    participants: &mut [Participant],
+    timestamp: i64,
+    clearing_prices: Option<&HashMap<BookPartition, i64>>,
+    max_matches: usize,
+    taker: Option<u64>,
+) -> Result<MatchRun, ProgramError> {
+    // Resolve participants once per run instead of scanning the slice on every fill
+    let participant_index: HashMap<Pubkey, usize> = participants.iter()
+        .enumerate()
+        .map(|(index, participant)| (participant.id, index))
+        .collect();
+
+    let mut matched_trades = Vec::new();
+    // The delivery slot of each matched trade, by index
+    let mut trade_slots = Vec::new();
+    let mut skipped_self_trades = 0u32;
+    let mut skipped_for_balance = 0u32;
+    let mut skipped_for_band = 0u32;
+    let mut skipped_for_positions = 0u32;
+    let mut skipped_for_grid_operator = 0u32;
+    // Fees of the fills settled at match time, collected once the book is no longer borrowed
+    let mut settled_fees = 0u64;
+
+    // Each demand sweeps the productions in price order, taking partial fills from every
+    // compatible lot until it is satisfied or the consumer runs out of balance
+    'demands: for demand in &mut ledger.demands {
+        if exceeds_max_order_size(&ledger.config, demand.energy_amount) {
+            continue;
+        }
+        for production in &mut ledger.productions {
+            if matched_trades.len() >= max_matches {
+                break 'demands;
+            }
+            if demand.energy_amount == 0 {
+                break;
+            }
+            if taker.is_some_and(|order_id| demand.order_id != order_id && production.order_id != order_id) {
+                continue;
+            }
+            let partition = book_partition(&ledger.config, demand.delivery_slot, demand.zone);
+            if production.energy_amount == 0 || !production.verified || exceeds_max_order_size(&ledger.config, production.energy_amount) {
+                continue;
+            }
+            if book_partition(&ledger.config, production.delivery_slot, production.zone) != partition {
+                continue;
+            }
+            let clearing_price = clearing_prices.map(|prices| prices.get(&partition).copied());
+            let trade_price = match clearing_price {
+                Some(Some(price)) if demand.price_limit >= price && production.price <= price => price,
+                None if demand.price_limit >= production.price => production.price,
+                _ => continue,
+            };
+            if !within_price_band(ledger.reference_price, trade_price, ledger.config.max_deviation_bps) {
+                emit(&events::CircuitBreaker {
+                    demand_order_id: demand.order_id,
+                    production_order_id: production.order_id,
+                    price: trade_price,
+                    reference_price: ledger.reference_price,
+                })?;
+                skipped_for_band += 1;
+                continue;
+            }
+
+            // Store the IDs instead of references
+            let consumer_id = demand.consumer_id;
+            let producer_id = production.producer_id;
+
+            // Prosumers only net against themselves when the ledger explicitly allows it
+            if consumer_id == producer_id && !ledger.config.allow_self_trade {
+                skipped_self_trades += 1;
+                continue;
+            }
+
+            let (Some(&consumer_index), Some(&producer_index)) = (
+                participant_index.get(&consumer_id),
+                participant_index.get(&producer_id)
+            ) else {
+                continue;
+            };
+            if participants[consumer_index].frozen {
+                break;
+            }
+            if participants[producer_index].frozen
+                || lacks_reputation(&ledger.config.reputation, &participants[producer_index], production.energy_amount)
+            {
+                continue;
+            }
+            // A side with no room left for this slot's position cannot trade in it until it settles one
+            if ledger.config.imbalance.enabled()
+                && !(participants[consumer_index].can_hold_position(demand.delivery_slot)
+                    && participants[producer_index].can_hold_position(production.delivery_slot))
+            {
+                skipped_for_positions += 1;
+                continue;
+            }
+
+            // The offer is debited the energy injected and the demand the energy delivered after
+            // transmission losses. Batteries sell no more than they store and buy no more than
+            // their capacity absorbs; the rest of their order stays in the book.
+            let cross_zone = demand.zone != production.zone;
+            let loss_bps = transmission_loss_bps(&ledger.config, cross_zone);
+            let mut injected_amount = injected_for_delivery(demand.energy_amount, loss_bps).min(production.energy_amount);
+            if is_storage(&participants[producer_index]) {
+                injected_amount = injected_amount.min(participants[producer_index].stored_energy);
+                if injected_amount == 0 {
+                    continue;
+                }
+            }
+            let mut delivered_amount = delivered_after_losses(injected_amount, loss_bps);
+            if is_storage(&participants[consumer_index]) {
+                let headroom = charge_headroom(&participants[consumer_index]);
+                if headroom == 0 {
+                    break;
+                }
+                if delivered_amount > headroom {
+                    delivered_amount = headroom;
+                    injected_amount = injected_for_delivery(headroom, loss_bps);
+                }
+            }
+            // Too small a fill for either side, or an offer too small to deliver anything once the
+            // loss is rounded up, leaves both orders untouched for a better match
+            if delivered_amount == 0
+                || delivered_amount < smallest_fill(demand.energy_amount, demand.min_fill, demand.all_or_nothing)
+                || injected_amount < smallest_fill(production.energy_amount, production.min_fill, production.all_or_nothing)
+            {
+                continue;
+            }
+            let trade_amount = match ledger.config.losses.bearer {
+                LossBearer::Producer => delivered_amount,
+                LossBearer::Consumer => injected_amount,
+            };
+            let negative_price = trade_price < 0;
+            // Posting bounds every order's cost, but a later fee change can still push a fill past
+            // what a balance holds; such a pair is skipped rather than failing the whole run
+            let Ok(FillCosts { buyer_cost, buyer_credit, seller_cost, proceeds, fees, grid_fee }) =
+                fill_costs(&ledger.config, trade_amount, trade_price, cross_zone)
+            else {
+                msg!("Fill of demand {} against offer {} is too large to settle", demand.order_id, production.order_id);
+                skipped_for_balance += 1;
+                continue;
+            };
+
+            // The grid fee has nowhere to go without the grid operator's participant, so the pair
+            // waits for a crank that passes it
+            let operator_index = match participant_index.get(&ledger.grid_operator) {
+                _ if grid_fee == 0 => None,
+                Some(&operator_index) => Some(operator_index),
+                None => {
+                    skipped_for_grid_operator += 1;
+                    continue;
+                }
+            };
+
+            // The fill consumes its share of the demand's escrow, the buyer's worst case for the
+            // delivered energy, and hands back whatever the trade price and fees did not use
+            let escrow = fill_escrow(demand, delivered_amount)?;
+
+            // Stop filling this demand once the escrow and free balance can no longer pay; earlier fills stand
+            let consumer = &participants[consumer_index];
+            let consumer_funds = consumer.wallet_balance.saturating_add(escrow).saturating_add(buyer_credit);
+            // An aggregated demand spends only what its members pooled, never the aggregator's own funds
+            let pooled_short = consumer.aggregated_order_id == Some(demand.order_id)
+                && consumer.pooled_balance.saturating_add(buyer_credit) < buyer_cost;
+            if consumer.reserved_balance < escrow || consumer_funds < buyer_cost || pooled_short {
+                msg!("Insufficient balance for demand from {:?}", consumer_id);
+                skipped_for_balance += 1;
+                break;
+            }
+            // A producer that can no longer pay for its negative-price offer is skipped; the
+            // offer stays in the book in case it is topped up
+            if participants[producer_index].wallet_balance < seller_cost {
+                msg!("Insufficient balance for offer from {:?}", producer_id);
+                skipped_for_balance += 1;
+                continue;
+            }
+
+            let consumer = &mut participants[consumer_index];
+            release_funds(consumer, escrow)?;
+            consumer.wallet_balance = consumer.wallet_balance.checked_add(buyer_credit)
+                .and_then(|balance| balance.checked_sub(buyer_cost))
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+            if consumer.aggregated_order_id == Some(demand.order_id) {
+                consumer.pooled_balance = consumer.pooled_balance.checked_add(buyer_credit)
+                    .and_then(|pooled| pooled.checked_sub(buyer_cost))
+                    .ok_or(ProgramError::ArithmeticOverflow)?;
+            }
+
+            // Deferred settlement keeps the producer's proceeds and the fees in the consumer's
+            // reserved_balance until the trade is confirmed or defaults; the crank reward is earned now.
+            // Negative-price trades always settle at match time, as the consumer has nothing to escrow.
+            let (status, settlement_deadline, settlement_escrow) = if ledger.config.settlement.deferred && !negative_price {
+                let settlement_escrow = proceeds.checked_add(fees).ok_or(ProgramError::ArithmeticOverflow)?;
+                consumer.reserved_balance = consumer.reserved_balance.checked_add(settlement_escrow)
+                    .ok_or(ProgramError::ArithmeticOverflow)?;
+                let settlement_deadline = delivery_slot_end(demand.delivery_slot)
+                    .saturating_add(ledger.config.settlement.delivery_timeout);
+                (TradeStatus::Matched, settlement_deadline, settlement_escrow)
+            } else {
+                let producer = &mut participants[producer_index];
+                producer.wallet_balance = producer.wallet_balance.checked_add(proceeds)
+                    .and_then(|balance| balance.checked_sub(seller_cost))
+                    .ok_or(ProgramError::ArithmeticOverflow)?;
+                settled_fees = settled_fees.checked_add(fees).ok_or(ProgramError::ArithmeticOverflow)?;
+                (TradeStatus::Settled, 0, 0)
+            };
+            // The network is used whether or not a deferred trade is later confirmed, so the grid
+            // fee is paid at match time
+            if let Some(operator_index) = operator_index {
+                let operator = &mut participants[operator_index];
+                operator.wallet_balance = operator.wallet_balance.checked_add(grid_fee)
+                    .ok_or(ProgramError::ArithmeticOverflow)?;
+            }
+            if status == TradeStatus::Settled {
+                accrue_recs(&mut participants[consumer_index], production.energy_source, delivered_amount, ledger.config.kwh_per_rec)?;
+                if consumer_id != producer_id {
+                    record_trade_outcome(&mut participants[producer_index], true)?;
+                }
+            }
+
+            record_position(&ledger.config.imbalance, &mut participants[producer_index], production.delivery_slot, injected_amount, 0)?;
+            record_position(&ledger.config.imbalance, &mut participants[consumer_index], demand.delivery_slot, 0, delivered_amount)?;
+            if consumer_id != producer_id {
+                let rates = ledger.config.rewards;
+                let (consumer_rate, producer_rate) = if (demand.created_at, demand.order_id) < (production.created_at, production.order_id) {
+                    (rates.maker_points_per_kwh, rates.taker_points_per_kwh)
+                } else {
+                    (rates.taker_points_per_kwh, rates.maker_points_per_kwh)
+                };
+                accrue_reward_points(&mut participants[consumer_index], trade_amount, consumer_rate);
+                accrue_reward_points(&mut participants[producer_index], trade_amount, producer_rate);
+            }
+
+            let producer = &mut participants[producer_index];
+            if is_storage(producer) {
+                producer.stored_energy = producer.stored_energy.checked_sub(injected_amount)
+                    .ok_or(ProgramError::ArithmeticOverflow)?;
+            }
+            let consumer = &mut participants[consumer_index];
+            if consumer.aggregated_order_id == Some(demand.order_id) {
+                consumer.aggregated_filled = consumer.aggregated_filled.checked_add(delivered_amount)
+                    .ok_or(ProgramError::ArithmeticOverflow)?;
+            }
+            if is_storage(consumer) {
+                consumer.stored_energy = consumer.stored_energy
+                    .checked_add(stored_after_losses(delivered_amount, consumer.storage_efficiency_bps))
+                    .ok_or(ProgramError::ArithmeticOverflow)?;
+            }
+
+            demand.energy_amount = demand.energy_amount.checked_sub(delivered_amount)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+            production.energy_amount = production.energy_amount.checked_sub(injected_amount)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+
+            let trade = Transaction {
+                trade_id: ledger.total_trades + matched_trades.len() as u64,
+                demand_order_id: demand.order_id,
+                production_order_id: production.order_id,
+                from: consumer_id,
+                to: producer_id,
+                amount: trade_amount,
+                price: trade_price,
+                timestamp,
+                status,
+                settlement_deadline,
+                escrow: settlement_escrow,
+                proceeds,
+                energy_source: production.energy_source,
+                bilateral: false,
+                injected_amount,
+                delivered_amount,
+                netting: false,
+            };
+            ledger.stats.record(&trade)?;
+            matched_trades.push(trade);
+            trade_slots.push(demand.delivery_slot);
+        }
+    }
+
+    if skipped_self_trades > 0 {
+        msg!("Skipped {} self-matches", skipped_self_trades);
+    }
+    if skipped_for_positions > 0 {
+        msg!("Skipped {} fills for participants with no room for another open position", skipped_for_positions);
+    }
+    collect_trading_fees(ledger, settled_fees)?;
+    if skipped_for_grid_operator > 0 {
+        msg!("Skipped {} fills owing a grid fee without the grid operator's participant", skipped_for_grid_operator);
+    }
+
+    remove_orders(&mut ledger.productions, OrderSide::Production, participants, |p| p.producer_id, |p| p.energy_amount == 0)?;
+    remove_orders(&mut ledger.demands, OrderSide::Demand, participants, |d| d.consumer_id, |d| d.energy_amount == 0)?;
+    debug_assert_order_counts(ledger, participants);
+
+    if let Some(reference_price) = volume_weighted_price(&matched_trades)? {
+        ledger.reference_price = reference_price;
+        record_price_sample(ledger, PriceSample { timestamp, price: reference_price });
+    }
+    let mut slots = trade_slots.clone();
+    slots.sort_unstable();
+    slots.dedup();
+    for slot in slots {
+        let slot_trades: Vec<Transaction> = matched_trades.iter()
+            .zip(&trade_slots)
+            .filter(|(_, &trade_slot)| trade_slot == slot)
+            .map(|(trade, _)| trade.clone())
+            .collect();
+        let (notional, volume) = trade_totals(&slot_trades)?;
+        let volume = u64::try_from(volume).map_err(|_| ProgramError::ArithmeticOverflow)?;
+        record_slot_price(ledger, slot, notional, volume)?;
+    }
+
+    Ok(MatchRun { trades: matched_trades, skipped_for_balance, skipped_for_band })
+}
+
+// The matching step shared by MatchTransactions, RunAuction and SimulateMatch, so a simulation
+// always predicts what the crank would do: purges expired orders, crosses the book the way the
+// ledger's market mode prescribes and then prunes stale demands no offer can fill. max_matches only
+// bounds pay-as-bid runs; an auction clears at once.
+pub fn run_matching(
+    ledger: &mut Ledger,
+    participants: &mut [Participant],
+    timestamp: i64,
+    max_matches: usize,
+) -> Result<MatchRun, ProgramError> {
+    let expired = purge_expired_orders(ledger, participants, timestamp)?;
+    if expired > 0 {
+        msg!("Purged {} expired orders", expired);
+    }
+    // Nettings count against the same budget as the fills that follow them
+    let netted = net_prosumer_positions(ledger, participants, timestamp, max_matches)?;
+    let mut run = match ledger.config.market_mode {
+        MarketMode::PayAsBid => match_orders(ledger, participants, timestamp, max_matches - netted.len())?,
+        MarketMode::UniformPrice => run_uniform_auction(ledger, participants, timestamp)?,
+    };
+    if !netted.is_empty() {
+        msg!("Netted {} prosumer positions", netted.len());
+        // The nettings are recorded first and the market's fills numbered after them
+        run.trades.splice(0..0, netted);
+        for (index, trade) in run.trades.iter_mut().enumerate() {
+            trade.trade_id = ledger.total_trades + index as u64;
+        }
+    }
+    let pruned = prune_unmatchable_demands(ledger, participants, timestamp)?;
+    if pruned > 0 {
+        msg!("Pruned {} unmatchable demands", pruned);
+    }
+    Ok(run)
+}
+
+// Offsets every net-metering prosumer's demands against its own attested offers for the same
+// delivery slot, in the order they were posted, before the book crosses. The netted energy is
+// produced and consumed behind the meter, so it costs nothing: the demand's escrow for it is
+// released and no fees or crank reward apply. A netting below either order's minimum fill is left
+// to the market, which trades whatever remains. Stops after max_matches nettings.
+fn net_prosumer_positions(
+    ledger: &mut Ledger,
+    participants: &mut [Participant],
+    timestamp: i64,
+    max_matches: usize,
+) -> Result<Vec<Transaction>, ProgramError> {
+    let netting: HashMap<Pubkey, usize> = participants.iter()
+        .enumerate()
+        .filter(|(_, participant)| participant.net_metering && !participant.frozen)
+        .map(|(index, participant)| (participant.id, index))
+        .collect();
+    let mut trades = Vec::new();
+    if netting.is_empty() {
+        return Ok(trades);
+    }
+
+    'demands: for demand in &mut ledger.demands {
+        let Some(&index) = netting.get(&demand.consumer_id) else {
+            continue;
+        };
+        for production in &mut ledger.productions {
+            if trades.len() >= max_matches {
+                break 'demands;
+            }
+            if demand.energy_amount == 0 {
+                break;
+            }
+            if production.producer_id != demand.consumer_id
+                || production.delivery_slot != demand.delivery_slot
+                || production.energy_amount == 0
+                || !production.verified
+            {
+                continue;
+            }
+            let amount = demand.energy_amount.min(production.energy_amount);
+            if amount < smallest_fill(demand.energy_amount, demand.min_fill, demand.all_or_nothing)
+                || amount < smallest_fill(production.energy_amount, production.min_fill, production.all_or_nothing)
+            {
+                continue;
+            }
+            release_funds(&mut participants[index], fill_escrow(demand, amount)?)?;
+            demand.energy_amount -= amount;
+            production.energy_amount -= amount;
+            trades.push(Transaction {
+                // Numbered once the run's fills are known
+                trade_id: 0,
+                demand_order_id: demand.order_id,
+                production_order_id: production.order_id,
+                from: demand.consumer_id,
+                to: demand.consumer_id,
+                amount,
+                price: 0,
+                timestamp,
+                status: TradeStatus::Settled,
+                settlement_deadline: 0,
+                escrow: 0,
+                proceeds: 0,
+                energy_source: production.energy_source,
+                bilateral: false,
+                injected_amount: amount,
+                delivered_amount: amount,
+                netting: true,
+            });
+        }
+    }
+
+    remove_orders(&mut ledger.productions, OrderSide::Production, participants, |p| p.producer_id, |p| p.energy_amount == 0)?;
+    remove_orders(&mut ledger.demands, OrderSide::Demand, participants, |d| d.consumer_id, |d| d.energy_amount == 0)?;
+    Ok(trades)
+}
+
+// Removes demands priced below every open offer of their book partition once they are older than
+// stale_demand_timeout, releasing their escrow. Runs after the book crossed, so a demand that could
+// still trade has an offer at or under its limit and is kept. On account storage the crank only
+// loads the orders it is handed, which need not include the cheapest offer, so nothing is pruned.
+fn prune_unmatchable_demands(ledger: &mut Ledger, participants: &mut [Participant], now: i64) -> Result<usize, ProgramError> {
+    let timeout = ledger.config.stale_demand_timeout;
+    if timeout == 0 || ledger.config.order_storage != OrderStorage::Ledger {
+        return Ok(0);
+    }
+    let config = &ledger.config;
+    let mut best_asks: HashMap<BookPartition, i64> = HashMap::new();
+    for production in ledger.productions.iter().filter(|p| p.energy_amount > 0) {
+        let ask = best_asks.entry(book_partition(config, production.delivery_slot, production.zone)).or_insert(production.price);
+        *ask = (*ask).min(production.price);
+    }
+    let pruned = remove_orders(&mut ledger.demands, OrderSide::Demand, participants, |d| d.consumer_id, |d| {
+        now.saturating_sub(d.created_at) > timeout
+            && best_asks.get(&book_partition(config, d.delivery_slot, d.zone)).is_some_and(|&ask| d.price_limit < ask)
+    })?;
+    for (demand, consumer_index) in &pruned {
+        release_funds(&mut participants[*consumer_index], demand_escrow(demand)?)?;
+        emit(&events::OrderExpired {
+            order_id: demand.order_id,
+            owner: demand.consumer_id,
+            side: OrderSide::Demand,
+            remaining_amount: demand.energy_amount,
+        })?;
+    }
+    debug_assert_order_counts(ledger, participants);
+    Ok(pruned.len())
+}
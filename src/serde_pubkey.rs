@@ -0,0 +1,66 @@
+//! `serde(with)` adapters writing public keys as base58 strings, as explorers and the CLI show
+//! them, instead of the 32-number arrays Pubkey's own serde impl produces. The state types use
+//! them for every key they hold, so account data read over RPC converts straight to JSON:
+//!
+//! ```
+//! use borsh::{BorshDeserialize, BorshSerialize};
+//! use energy_trading_program::{AccountType, Participant, PARTICIPANT_SIZE};
+//!
+//! // The data of a participant account, as getAccountInfo returns it
+//! let mut data = vec![0; PARTICIPANT_SIZE];
+//! data[0] = AccountType::Participant as u8;
+//! let participant = Participant::deserialize(&mut data.as_slice()).unwrap();
+//!
+//! let json = serde_json::to_string_pretty(&participant).unwrap();
+//! assert!(json.contains(r#""id": "11111111111111111111111111111111""#));
+//!
+//! let decoded: Participant = serde_json::from_str(&json).unwrap();
+//! assert_eq!(decoded.try_to_vec().unwrap(), participant.try_to_vec().unwrap());
+//! ```
+use std::str::FromStr;
+
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+use solana_program::pubkey::Pubkey;
+
+pub fn serialize<S: Serializer>(pubkey: &Pubkey, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(pubkey)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pubkey, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    Pubkey::from_str(&text).map_err(|err| D::Error::custom(format!("invalid public key {}: {}", text, err)))
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(transparent)]
+struct Base58(#[serde(with = "self")] Pubkey);
+
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(pubkey: &Option<Pubkey>, serializer: S) -> Result<S::Ok, S::Error> {
+        pubkey.map(Base58).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Pubkey>, D::Error> {
+        Ok(Option::<Base58>::deserialize(deserializer)?.map(|key| key.0))
+    }
+}
+
+pub mod array {
+    use super::*;
+
+    pub fn serialize<S: Serializer, const N: usize>(pubkeys: &[Pubkey; N], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(pubkeys.iter().map(|pubkey| Base58(*pubkey)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(deserializer: D) -> Result<[Pubkey; N], D::Error> {
+        let keys = Vec::<Base58>::deserialize(deserializer)?;
+        let len = keys.len();
+        keys.into_iter()
+            .map(|key| key.0)
+            .collect::<Vec<_>>()
+            .try_into()
+            .map_err(|_| D::Error::invalid_length(len, &format!("{} public keys", N).as_str()))
+    }
+}
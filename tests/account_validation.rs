@@ -0,0 +1,88 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, delivery_slot_at, EnergyMarketError, MarketConfig, OrderStorage, ParticipantType, TimeInForce,
+};
+use solana_program::{instruction::Instruction, program_error::ProgramError, pubkey::Pubkey};
+
+fn demand_ix(market: &Market, consumer: Pubkey, order_id: Option<u64>) -> Instruction {
+    let slot = delivery_slot_at(market.bank.now);
+    client::post_demand_ix(market.ledger, consumer, 10, 10, 0, slot, TimeInForce::GoodTilCancelled, 0, false, 0, 0, order_id)
+}
+
+#[test]
+fn swapped_ledger_and_participant_accounts_are_the_wrong_type() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producer, 10, 10).unwrap();
+
+    let mut deposit = client::deposit_ix(market.ledger, consumer, 100, None);
+    deposit.accounts.swap(1, 2);
+    assert_eq!(market.bank.process(&deposit).unwrap_err(), custom(EnergyMarketError::WrongAccountType));
+    let mut demand = demand_ix(&market, consumer, None);
+    demand.accounts.swap(1, 2);
+    assert_eq!(market.bank.process(&demand).unwrap_err(), custom(EnergyMarketError::WrongAccountType));
+    let mut crank = client::match_transactions_ix(market.ledger, producer, 16, client::participant_metas(market.ledger, &[consumer]));
+    crank.accounts.swap(0, 2);
+    assert_eq!(market.bank.process(&crank).unwrap_err(), custom(EnergyMarketError::WrongAccountType));
+
+    // The ledger where a participant belongs is caught the same way
+    let mut withdraw = client::withdraw_ix(market.ledger, consumer, consumer, 100, None);
+    withdraw.accounts[2].pubkey = market.ledger;
+    assert_eq!(market.bank.process(&withdraw).unwrap_err(), custom(EnergyMarketError::WrongAccountType));
+
+    let participant = market.bank.participant(&market.ledger, &consumer);
+    assert_eq!((participant.wallet_balance, participant.reserved_balance), (1_000, 0));
+    assert!(market.bank.ledger(&market.ledger).demands.is_empty());
+}
+
+#[test]
+fn order_account_swapped_with_the_participant_is_the_wrong_type() {
+    let mut market = Market::new(MarketConfig { order_storage: OrderStorage::Accounts, ..MarketConfig::default() });
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    let order_id = market.bank.ledger(&market.ledger).next_order_id;
+    market.bank.process(&demand_ix(&market, consumer, Some(order_id))).unwrap();
+
+    let mut cancel = client::cancel_demand_ix(market.ledger, consumer, order_id, OrderStorage::Accounts);
+    cancel.accounts.swap(2, 3);
+    assert_eq!(market.bank.process(&cancel).unwrap_err(), custom(EnergyMarketError::WrongAccountType));
+    market.bank.process(&client::cancel_demand_ix(market.ledger, consumer, order_id, OrderStorage::Accounts)).unwrap();
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).wallet_balance, 1_000);
+}
+
+#[test]
+fn read_only_ledger_is_rejected_by_mutating_handlers() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producer, 10, 10).unwrap();
+    let ledger = market.bank.account(&market.ledger).unwrap().clone();
+
+    // The harness panics if a read-only account changes, so each of these fails before writing
+    let mut demand = demand_ix(&market, consumer, None);
+    let mut crank = client::match_transactions_ix(market.ledger, consumer, 16, client::participant_metas(market.ledger, &[producer]));
+    let mut cancel = client::cancel_production_ix(market.ledger, producer, 0, OrderStorage::Ledger);
+    let mut set_fee = client::set_fee_ix(market.ledger, market.admin, 100);
+    for (instruction, index) in [(&mut demand, 1), (&mut crank, 0), (&mut cancel, 1), (&mut set_fee, 1)] {
+        assert_eq!(instruction.accounts[index].pubkey, market.ledger);
+        instruction.accounts[index].is_writable = false;
+        assert_eq!(market.bank.process(instruction).unwrap_err(), custom(EnergyMarketError::AccountNotWritable));
+    }
+    assert_eq!(market.bank.account(&market.ledger).unwrap(), &ledger);
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).reserved_balance, 0);
+}
+
+#[test]
+fn short_account_lists_fail_up_front() {
+    let mut market = Market::new(MarketConfig::default());
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    let mut demand = demand_ix(&market, consumer, None);
+    demand.accounts.truncate(2);
+    assert_eq!(market.bank.process(&demand).unwrap_err(), ProgramError::NotEnoughAccountKeys);
+    let mut withdraw = client::withdraw_ix(market.ledger, consumer, consumer, 100, None);
+    withdraw.accounts.truncate(4);
+    assert_eq!(market.bank.process(&withdraw).unwrap_err(), ProgramError::NotEnoughAccountKeys);
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).wallet_balance, 1_000);
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{client, ledger_space, EnergyMarketError, MarketConfig, ParticipantType};
+use solana_program::{instruction::Instruction, pubkey::Pubkey};
+
+fn privileged(market: &Market, signer: Pubkey) -> Vec<Instruction> {
+    let capacity = market.bank.ledger(&market.ledger).capacity;
+    vec![
+        client::set_fee_ix(market.ledger, signer, 100),
+        client::pause_ix(market.ledger, signer),
+        client::resize_ledger_ix(market.ledger, signer, signer, ledger_space(&capacity) as u32, capacity),
+        client::set_pending_admin_ix(market.ledger, signer, signer),
+    ]
+}
+
+#[test]
+fn privileged_instructions_need_the_admin() {
+    let mut market = Market::new(MarketConfig::default());
+    let stranger = market.register(ParticipantType::Producer, 0);
+    assert_eq!(market.bank.ledger(&market.ledger).admin, market.admin);
+    for instruction in privileged(&market, stranger) {
+        assert_eq!(market.bank.process(&instruction).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    }
+    for instruction in privileged(&market, market.admin) {
+        market.bank.process(&instruction).unwrap();
+    }
+}
+
+#[test]
+fn admin_hands_over_in_two_steps() {
+    let mut market = Market::new(MarketConfig::default());
+    let successor = market.bank.funded_wallet(1);
+    market.bank.process(&client::set_pending_admin_ix(market.ledger, market.admin, successor)).unwrap();
+    assert_eq!(market.bank.ledger(&market.ledger).admin, market.admin);
+
+    // The old admin keeps its rights until the successor signs for them
+    market.bank.process(&client::set_fee_ix(market.ledger, market.admin, 100)).unwrap();
+    market.bank.process(&client::accept_admin_ix(market.ledger, successor)).unwrap();
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.admin, ledger.pending_admin), (successor, Pubkey::default()));
+    let by_old_admin = client::set_fee_ix(market.ledger, market.admin, 200);
+    assert_eq!(market.bank.process(&by_old_admin).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    market.bank.process(&client::set_fee_ix(market.ledger, successor, 200)).unwrap();
+    let accept_again = client::accept_admin_ix(market.ledger, successor);
+    assert_eq!(market.bank.process(&accept_again).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+}
+
+#[test]
+fn mistyped_pending_admin_is_replaced_before_acceptance() {
+    let mut market = Market::new(MarketConfig::default());
+    let (typo, intended) = (market.bank.funded_wallet(1), market.bank.funded_wallet(1));
+    market.bank.process(&client::set_pending_admin_ix(market.ledger, market.admin, typo)).unwrap();
+    market.bank.process(&client::set_pending_admin_ix(market.ledger, market.admin, intended)).unwrap();
+
+    assert_eq!(market.bank.process(&client::accept_admin_ix(market.ledger, typo)).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    market.bank.process(&client::accept_admin_ix(market.ledger, intended)).unwrap();
+    assert_eq!(market.bank.ledger(&market.ledger).admin, intended);
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, delivery_slot_at, events::{MemberAdded, MemberSettled}, split_proportionally, EnergyMarketError, MarketConfig,
+    OrderStorage, ParticipantType, MAX_AGGREGATOR_MEMBERS,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+fn join(market: &mut Market, aggregator: Pubkey, member: Pubkey, weight: u32) -> Result<(), ProgramError> {
+    market.bank.process(&client::add_member_ix(market.ledger, aggregator, member, weight))
+}
+
+fn post(market: &mut Market, aggregator: Pubkey, members: &[Pubkey], energy_amount: u64, price_limit: i64) -> Result<(), ProgramError> {
+    let slot = delivery_slot_at(market.bank.now);
+    market.bank.process(&client::post_aggregated_demand_ix(market.ledger, aggregator, members, energy_amount, price_limit, 0, slot, None))
+}
+
+fn wallet_balance(market: &Market, wallet: &Pubkey) -> u64 {
+    market.bank.participant(&market.ledger, wallet).wallet_balance
+}
+
+#[test]
+fn members_join_only_with_their_own_signature() {
+    let mut market = Market::new(MarketConfig::default());
+    let aggregator = market.register(ParticipantType::Aggregator, 0);
+    let household = market.register(ParticipantType::Consumer, 0);
+    let producer = market.register(ParticipantType::Producer, 0);
+
+    let mut unsigned = client::add_member_ix(market.ledger, aggregator, household, 1);
+    unsigned.accounts[3].is_signer = false;
+    assert_eq!(market.bank.process(&unsigned).unwrap_err(), ProgramError::MissingRequiredSignature);
+    assert_eq!(join(&mut market, aggregator, producer, 1).unwrap_err(), custom(EnergyMarketError::InvalidParticipantType));
+    assert_eq!(join(&mut market, household, aggregator, 1).unwrap_err(), custom(EnergyMarketError::InvalidParticipantType));
+    assert_eq!(join(&mut market, aggregator, household, 0).unwrap_err(), ProgramError::InvalidArgument);
+
+    join(&mut market, aggregator, household, 3).unwrap();
+    assert_eq!(market.bank.events::<MemberAdded>(), vec![MemberAdded { aggregator, member: household, weight: 3 }]);
+    assert_eq!(join(&mut market, aggregator, household, 3).unwrap_err(), custom(EnergyMarketError::MembershipFull));
+    assert_eq!(
+        market.bank.process(&client::unregister_participant_ix(market.ledger, household)).unwrap_err(),
+        custom(EnergyMarketError::MemberOfAggregator),
+    );
+    let others: Vec<Pubkey> = (1..MAX_AGGREGATOR_MEMBERS).map(|_| market.register(ParticipantType::Consumer, 0)).collect();
+    for &member in &others {
+        join(&mut market, aggregator, member, 1).unwrap();
+    }
+    let late = market.register(ParticipantType::Consumer, 0);
+    assert_eq!(join(&mut market, aggregator, late, 1).unwrap_err(), custom(EnergyMarketError::MembershipFull));
+
+    // Only the aggregator or the member itself ends a membership
+    let by_stranger = client::remove_member_ix(market.ledger, late, aggregator, household);
+    assert_eq!(market.bank.process(&by_stranger).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    market.bank.process(&client::remove_member_ix(market.ledger, household, aggregator, household)).unwrap();
+    let aggregator_record = market.bank.participant(&market.ledger, &aggregator);
+    let members: Vec<Pubkey> = aggregator_record.members[..aggregator_record.member_count as usize].iter().map(|m| m.wallet).collect();
+    assert_eq!(members, others);
+    assert!(market.bank.participant(&market.ledger, &household).aggregator.is_none());
+    market.bank.process(&client::unregister_participant_ix(market.ledger, household)).unwrap();
+}
+
+#[test]
+fn escrow_fills_and_refunds_are_shared_by_weight() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let aggregator = market.register(ParticipantType::Aggregator, 0);
+    let members: Vec<Pubkey> = (0..3).map(|_| market.register(ParticipantType::Consumer, 500)).collect();
+    for &member in &members {
+        join(&mut market, aggregator, member, 1).unwrap();
+    }
+
+    // 1_000 lamports of escrow do not split evenly three ways; the first member makes up the rest
+    assert_eq!(post(&mut market, aggregator, &members[..2], 100, 10).unwrap_err(), ProgramError::NotEnoughAccountKeys);
+    post(&mut market, aggregator, &members, 100, 10).unwrap();
+    let balances: Vec<u64> = members.iter().map(|m| wallet_balance(&market, m)).collect();
+    assert_eq!(balances, vec![166, 167, 167]);
+    let record = market.bank.participant(&market.ledger, &aggregator);
+    assert_eq!((record.wallet_balance, record.reserved_balance), (0, 1_000));
+    assert_eq!(post(&mut market, aggregator, &members, 10, 10).unwrap_err(), custom(EnergyMarketError::AggregatedDemandOpen));
+    let leave = client::remove_member_ix(market.ledger, members[0], aggregator, members[0]);
+    assert_eq!(market.bank.process(&leave).unwrap_err(), custom(EnergyMarketError::AggregatedDemandOpen));
+
+    market.report_production(producer, 60, 10).unwrap();
+    market.match_orders(producer, &[producer, aggregator]).unwrap();
+    let settle = client::settle_aggregated_demand_ix(market.ledger, producer, aggregator, &members);
+    assert_eq!(market.bank.process(&settle).unwrap_err(), custom(EnergyMarketError::AggregatedDemandOpen));
+    let order_id = market.bank.ledger(&market.ledger).demands[0].order_id;
+    market.bank.process(&client::cancel_demand_ix(market.ledger, aggregator, order_id, OrderStorage::Ledger)).unwrap();
+
+    // The 400 left over and the 60 kWh bought go back in proportion to the 334/333/333 put in
+    market.bank.process(&settle).unwrap();
+    let settled = market.bank.events::<MemberSettled>();
+    let shares: Vec<(Pubkey, u64, u64, u64)> = settled.iter().map(|s| (s.member, s.energy_amount, s.paid, s.refund)).collect();
+    assert_eq!(shares, vec![(members[0], 20, 200, 134), (members[1], 20, 200, 133), (members[2], 20, 200, 133)]);
+    let balances: Vec<u64> = members.iter().map(|m| wallet_balance(&market, m)).collect();
+    assert_eq!(balances, vec![300, 300, 300]);
+    let record = market.bank.participant(&market.ledger, &aggregator);
+    assert_eq!((record.wallet_balance, record.reserved_balance, record.aggregated_order_id), (0, 0, None));
+    assert_eq!(wallet_balance(&market, &producer), 600);
+}
+
+#[test]
+fn settlement_shares_out_only_the_pooled_funds() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let aggregator = market.register(ParticipantType::Aggregator, 250);
+    let members: Vec<Pubkey> = (0..2).map(|_| market.register(ParticipantType::Consumer, 500)).collect();
+    for &member in &members {
+        join(&mut market, aggregator, member, 1).unwrap();
+    }
+
+    // The aggregator's own 250, and what it is sent while the demand is open, stay its own
+    post(&mut market, aggregator, &members, 100, 10).unwrap();
+    let sender = market.register(ParticipantType::Consumer, 50);
+    market.bank.process(&client::transfer_balance_ix(market.ledger, sender, aggregator, 50)).unwrap();
+    let record = market.bank.participant(&market.ledger, &aggregator);
+    assert_eq!((record.wallet_balance, record.reserved_balance, record.pooled_balance), (300, 1_000, 1_000));
+
+    market.report_production(producer, 60, 10).unwrap();
+    market.match_orders(producer, &[producer, aggregator]).unwrap();
+    let order_id = market.bank.ledger(&market.ledger).demands[0].order_id;
+    market.bank.process(&client::cancel_demand_ix(market.ledger, aggregator, order_id, OrderStorage::Ledger)).unwrap();
+    market.bank.process(&client::settle_aggregated_demand_ix(market.ledger, producer, aggregator, &members)).unwrap();
+
+    let refunds: Vec<u64> = market.bank.events::<MemberSettled>().iter().map(|s| s.refund).collect();
+    assert_eq!(refunds, vec![200, 200]);
+    let record = market.bank.participant(&market.ledger, &aggregator);
+    assert_eq!((record.wallet_balance, record.pooled_balance), (300, 0));
+    let balances: Vec<u64> = members.iter().map(|m| wallet_balance(&market, m)).collect();
+    assert_eq!(balances, vec![200, 200]);
+}
+
+#[test]
+fn proportional_split_always_sums_to_the_total() {
+    assert_eq!(split_proportionally(1_000, &[1, 1, 1]), vec![334, 333, 333]);
+    assert_eq!(split_proportionally(10, &[1, 2, 3, 0]), vec![2, 3, 5, 0]);
+    assert_eq!(split_proportionally(7, &[0, 0]), vec![0, 0]);
+    for total in [0, 1, 99, 1_001, u64::MAX] {
+        let weights = [7, 13, 1, 29, 3];
+        assert_eq!(split_proportionally(total, &weights).iter().map(|&s| s as u128).sum::<u128>(), total as u128);
+    }
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, events::{ProductionAttested, TradeExecuted}, EnergyMarketError, MarketConfig, OrderStorage, ParticipantType,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+const TIMEOUT: i64 = 600;
+
+// Offers need the oracle's attestation and are dropped when left unattested for ten minutes
+fn attested_market(oracle: Pubkey) -> Market {
+    Market::builder(MarketConfig { attestation_timeout: TIMEOUT, ..MarketConfig::default() })
+        .admin_instruction(move |ledger, admin| client::set_oracle_ix(ledger, admin, oracle))
+        .build()
+}
+
+fn attest(market: &mut Market, oracle: Pubkey, order_id: u64) -> Result<(), ProgramError> {
+    market.bank.process(&client::attest_production_ix(market.ledger, oracle, order_id, OrderStorage::Ledger))
+}
+
+#[test]
+fn only_offers_attested_by_the_oracle_match() {
+    let oracle = Pubkey::new_unique();
+    let mut market = attested_market(oracle);
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producer, 10, 10).unwrap();
+    market.report_production(producer, 10, 11).unwrap();
+    let ids: Vec<_> = market.bank.ledger(&market.ledger).productions.iter().map(|p| (p.order_id, p.verified)).collect();
+    assert_eq!(ids, vec![(0, false), (1, false)]);
+
+    attest(&mut market, oracle, 0).unwrap();
+    assert_eq!(market.bank.events::<ProductionAttested>(), vec![ProductionAttested { order_id: 0, producer, oracle }]);
+    assert_eq!(attest(&mut market, producer, 1).unwrap_err(), custom(EnergyMarketError::InvalidOracle));
+    assert_eq!(attest(&mut market, oracle, 7).unwrap_err(), custom(EnergyMarketError::OrderNotFound));
+
+    market.post_demand(consumer, 20, 11).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    let fills: Vec<_> = market.bank.events::<TradeExecuted>().iter().map(|t| (t.production_order_id, t.amount)).collect();
+    assert_eq!(fills, vec![(0, 10)]);
+    assert_eq!(market.bank.ledger(&market.ledger).productions[0].order_id, 1);
+}
+
+#[test]
+fn unattested_offers_are_purged_after_the_timeout() {
+    let oracle = Pubkey::new_unique();
+    let mut market = attested_market(oracle);
+    let producer = market.register(ParticipantType::Producer, 0);
+    market.report_production(producer, 10, 10).unwrap();
+    market.report_production(producer, 10, 11).unwrap();
+    attest(&mut market, oracle, 1).unwrap();
+
+    market.bank.now += TIMEOUT;
+    market.match_orders(producer, &[producer]).unwrap();
+    assert_eq!(market.bank.ledger(&market.ledger).productions.len(), 2);
+    market.bank.now += 1;
+    market.match_orders(producer, &[producer]).unwrap();
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!(ledger.productions.iter().map(|p| p.order_id).collect::<Vec<_>>(), vec![1]);
+    assert_eq!(market.bank.participant(&market.ledger, &producer).open_productions, 1);
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{client, events::TradeExecuted, EnergyMarketError, MarketConfig, MarketMode, ParticipantType};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+fn auction_market() -> Market {
+    Market::new(MarketConfig { market_mode: MarketMode::UniformPrice, ..MarketConfig::default() })
+}
+
+fn run_auction(market: &mut Market, wallets: &[Pubkey]) -> Result<Vec<(u64, i64)>, ProgramError> {
+    let remaining = client::participant_metas(market.ledger, wallets);
+    market.bank.process(&client::run_auction_ix(market.ledger, wallets[0], remaining))?;
+    Ok(market.bank.events::<TradeExecuted>().iter().map(|t| (t.amount, t.price)).collect())
+}
+
+#[test]
+fn every_fill_clears_at_the_curves_intersection() {
+    let mut market = auction_market();
+    let wallets = [(); 4].map(|_| market.register(ParticipantType::Prosumer, 1_000));
+    market.report_production(wallets[0], 40, 6).unwrap();
+    market.report_production(wallets[1], 40, 9).unwrap();
+    market.post_demand(wallets[2], 50, 12).unwrap();
+    market.post_demand(wallets[3], 50, 10).unwrap();
+
+    // All 80 kWh of supply sells; 20 kWh of the demand at 10 is left over, which pins the price there
+    assert_eq!(market.match_orders(wallets[0], &wallets).unwrap_err(), custom(EnergyMarketError::WrongMarketMode));
+    assert_eq!(run_auction(&mut market, &wallets).unwrap(), vec![(40, 10), (10, 10), (30, 10)]);
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.last_clearing_price, ledger.demands.len(), ledger.demands[0].energy_amount), (10, 1, 20));
+    let buyer = market.bank.participant(&market.ledger, &wallets[2]);
+    assert_eq!((buyer.wallet_balance, buyer.reserved_balance), (1_000 - 500, 0));
+}
+
+#[test]
+fn curves_that_do_not_cross_leave_the_book_and_price_alone() {
+    let mut market = auction_market();
+    let wallets = [(); 2].map(|_| market.register(ParticipantType::Prosumer, 1_000));
+    market.report_production(wallets[0], 10, 7).unwrap();
+    market.post_demand(wallets[1], 10, 7).unwrap();
+    assert_eq!(run_auction(&mut market, &wallets).unwrap(), vec![(10, 7)]);
+
+    market.report_production(wallets[0], 50, 8).unwrap();
+    market.post_demand(wallets[1], 50, 5).unwrap();
+    assert!(run_auction(&mut market, &wallets).unwrap().is_empty());
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.last_clearing_price, ledger.productions.len(), ledger.demands.len()), (7, 1, 1));
+}
+
+#[test]
+fn vertical_overlap_clears_at_the_midpoint_of_the_tie() {
+    let mut market = auction_market();
+    let wallets = [(); 2].map(|_| market.register(ParticipantType::Prosumer, 1_000));
+    // Supply and demand are both exactly 50, so any price from 6 to 12 clears all of it
+    market.report_production(wallets[0], 50, 6).unwrap();
+    market.post_demand(wallets[1], 50, 12).unwrap();
+    assert_eq!(run_auction(&mut market, &wallets).unwrap(), vec![(50, 9)]);
+    assert_eq!(market.bank.ledger(&market.ledger).last_clearing_price, 9);
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use borsh::BorshSerialize;
+use common::{custom, program_id, Market};
+use energy_trading_program::{
+    client, events::BalanceTransferred, find_participant_address, EnergyMarketError, MarketConfig, ParticipantType,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+fn transfer(market: &mut Market, from: Pubkey, to: Pubkey, amount: u64) -> Result<(), ProgramError> {
+    market.bank.process(&client::transfer_balance_ix(market.ledger, from, to, amount))
+}
+
+fn wallet_balance(market: &Market, wallet: &Pubkey) -> u64 {
+    market.bank.participant(&market.ledger, wallet).wallet_balance
+}
+
+#[test]
+fn transfers_move_free_balance_less_the_protocol_fee() {
+    let mut market = Market::new(MarketConfig { fee_bps: 100, ..MarketConfig::default() });
+    let member = market.register(ParticipantType::Consumer, 1_000);
+    let other = market.register(ParticipantType::Consumer, 0);
+    transfer(&mut market, member, other, 1_000).unwrap();
+    assert_eq!(
+        market.bank.events::<BalanceTransferred>(),
+        vec![BalanceTransferred { from: member, to: other, amount: 1_000, fee: 10 }],
+    );
+    assert_eq!((wallet_balance(&market, &member), wallet_balance(&market, &other)), (0, 990));
+    assert_eq!(market.bank.ledger(&market.ledger).protocol_fees, 10);
+    assert_eq!(transfer(&mut market, other, other, 1).unwrap_err(), ProgramError::InvalidArgument);
+}
+
+#[test]
+fn destination_must_be_registered_on_the_ledger() {
+    let mut market = Market::new(MarketConfig::default());
+    let member = market.register(ParticipantType::Consumer, 1_000);
+    let outsider = market.bank.funded_wallet(1);
+    assert_eq!(transfer(&mut market, member, outsider, 1).unwrap_err(), custom(EnergyMarketError::ParticipantNotRegistered));
+
+    // A participant of another ledger is no member of this one
+    let mut elsewhere = Market::new(MarketConfig::default());
+    let stranger = elsewhere.register(ParticipantType::Consumer, 0);
+    assert_eq!(transfer(&mut market, member, stranger, 1).unwrap_err(), custom(EnergyMarketError::ParticipantNotRegistered));
+    assert_eq!(wallet_balance(&market, &member), 1_000);
+}
+
+#[test]
+fn reserved_balance_cannot_be_transferred() {
+    let mut market = Market::new(MarketConfig::default());
+    let member = market.register(ParticipantType::Consumer, 100);
+    let other = market.register(ParticipantType::Consumer, 0);
+    market.post_demand(member, 8, 10).unwrap();
+    assert_eq!(transfer(&mut market, member, other, 21).unwrap_err(), custom(EnergyMarketError::InsufficientBalance));
+    transfer(&mut market, member, other, 20).unwrap();
+    let participant = market.bank.participant(&market.ledger, &member);
+    assert_eq!((participant.wallet_balance, participant.reserved_balance), (0, 80));
+}
+
+#[test]
+fn extreme_amounts_fail_cleanly() {
+    let mut market = Market::new(MarketConfig::default());
+    let member = market.register(ParticipantType::Consumer, 100);
+    let other = market.register(ParticipantType::Consumer, 0);
+    assert_eq!(transfer(&mut market, member, other, u64::MAX).unwrap_err(), custom(EnergyMarketError::InsufficientBalance));
+
+    // No deposit can get a balance this high; it is written in to reach the recipient's overflow check
+    let mut full = market.bank.participant(&market.ledger, &other);
+    full.wallet_balance = u64::MAX;
+    let (address, _) = find_participant_address(&program_id(), &market.ledger, &other);
+    full.serialize(&mut market.bank.account_mut(&address).data.as_mut_slice()).unwrap();
+    assert_eq!(transfer(&mut market, member, other, 1).unwrap_err(), ProgramError::ArithmeticOverflow);
+    assert_eq!(wallet_balance(&market, &member), 100);
+}
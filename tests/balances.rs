@@ -0,0 +1,37 @@
+mod common;
+
+use common::Market;
+use energy_trading_program::{client, MarketConfig, ParticipantType};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+fn wallet_balance(market: &Market, wallet: &Pubkey) -> u64 {
+    market.bank.participant(&market.ledger, wallet).wallet_balance
+}
+
+#[test]
+fn deposits_and_withdrawals_need_the_participant_signature() {
+    let mut market = Market::new(MarketConfig::default());
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+
+    let mut deposit = client::deposit_ix(market.ledger, consumer, 500, None);
+    deposit.accounts[0].is_signer = false;
+    assert_eq!(market.bank.process(&deposit).unwrap_err(), ProgramError::MissingRequiredSignature);
+    let thief = market.bank.funded_wallet(1);
+    let mut withdraw = client::withdraw_ix(market.ledger, consumer, thief, 1_000, None);
+    withdraw.accounts[0].is_signer = false;
+    assert_eq!(market.bank.process(&withdraw).unwrap_err(), ProgramError::MissingRequiredSignature);
+    assert_eq!(wallet_balance(&market, &consumer), 1_000);
+}
+
+#[test]
+fn signed_withdrawal_can_pay_another_destination() {
+    let mut market = Market::new(MarketConfig::default());
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    let destination = market.bank.funded_wallet(1);
+    let (consumer_lamports, destination_lamports) = (market.bank.lamports(&consumer), market.bank.lamports(&destination));
+
+    market.bank.process(&client::withdraw_ix(market.ledger, consumer, destination, 400, None)).unwrap();
+    assert_eq!(wallet_balance(&market, &consumer), 600);
+    assert_eq!(market.bank.lamports(&destination), destination_lamports + 400);
+    assert_eq!(market.bank.lamports(&consumer), consumer_lamports);
+}
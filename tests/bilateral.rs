@@ -0,0 +1,83 @@
+mod common;
+
+use common::{custom, program_id, Market};
+use energy_trading_program::{
+    client, events::TradeExecuted, find_bilateral_offer_address, EnergyMarketError, MarketConfig, OrderSide, ParticipantType,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+// Offers 10 kWh at 12 to `counterparty`, returning the offer id
+fn offer(market: &mut Market, maker: Pubkey, counterparty: Pubkey, side: OrderSide, expires_at: i64) -> Result<u64, ProgramError> {
+    let offer_id = market.bank.ledger(&market.ledger).next_order_id;
+    market.bank.process(&client::create_bilateral_offer_ix(market.ledger, maker, counterparty, side, 10, 12, expires_at, offer_id))?;
+    Ok(offer_id)
+}
+
+fn balances(market: &Market, wallet: &Pubkey) -> (u64, u64) {
+    let participant = market.bank.participant(&market.ledger, wallet);
+    (participant.wallet_balance, participant.reserved_balance)
+}
+
+fn offer_account(market: &Market, offer_id: u64) -> Pubkey {
+    find_bilateral_offer_address(&program_id(), &market.ledger, offer_id).0
+}
+
+#[test]
+fn only_the_named_counterparty_accepts_and_the_trade_settles_at_once() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    let stranger = market.register(ParticipantType::Producer, 0);
+    let lamports = market.bank.lamports(&consumer);
+    let offer_id = offer(&mut market, consumer, producer, OrderSide::Demand, 0).unwrap();
+    assert_eq!(balances(&market, &consumer), (880, 120));
+
+    let by_stranger = client::accept_bilateral_offer_ix(market.ledger, stranger, consumer, offer_id);
+    assert_eq!(market.bank.process(&by_stranger).unwrap_err(), custom(EnergyMarketError::NotOfferCounterparty));
+    market.bank.process(&client::accept_bilateral_offer_ix(market.ledger, producer, consumer, offer_id)).unwrap();
+
+    let trade = market.bank.events::<TradeExecuted>().remove(0);
+    assert_eq!((trade.buyer, trade.seller, trade.amount, trade.price), (consumer, producer, 10, 12));
+    assert!(market.bank.ledger(&market.ledger).transactions[0].bilateral);
+    assert_eq!((balances(&market, &consumer), balances(&market, &producer)), ((880, 0), (120, 0)));
+    // The offer account is closed and its rent returned to the maker
+    assert_eq!(market.bank.lamports(&offer_account(&market, offer_id)), 0);
+    assert_eq!(market.bank.lamports(&consumer), lamports);
+}
+
+#[test]
+fn acceptor_needs_the_balance_when_accepting() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 100);
+    let offer_id = offer(&mut market, producer, consumer, OrderSide::Production, 0).unwrap();
+    assert_eq!(balances(&market, &producer), (0, 0));
+
+    let accept = client::accept_bilateral_offer_ix(market.ledger, consumer, producer, offer_id);
+    assert_eq!(market.bank.process(&accept).unwrap_err(), custom(EnergyMarketError::InsufficientBalance));
+    market.bank.process(&client::deposit_ix(market.ledger, consumer, 20, None)).unwrap();
+    market.bank.process(&accept).unwrap();
+    assert_eq!((balances(&market, &consumer), balances(&market, &producer)), ((0, 0), (120, 0)));
+}
+
+#[test]
+fn expired_offers_cannot_be_accepted_and_anyone_may_clean_them_up() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    let stranger = market.register(ParticipantType::Producer, 0);
+    let lamports = market.bank.lamports(&consumer);
+    let expires_at = market.bank.now + 60;
+    let offer_id = offer(&mut market, consumer, producer, OrderSide::Demand, expires_at).unwrap();
+
+    let reject = client::reject_bilateral_offer_ix(market.ledger, stranger, consumer, offer_id);
+    assert_eq!(market.bank.process(&reject).unwrap_err(), custom(EnergyMarketError::NotOfferCounterparty));
+    market.bank.now = expires_at + 1;
+    let accept = client::accept_bilateral_offer_ix(market.ledger, producer, consumer, offer_id);
+    assert_eq!(market.bank.process(&accept).unwrap_err(), custom(EnergyMarketError::InvalidExpiration));
+
+    market.bank.process(&reject).unwrap();
+    assert_eq!(balances(&market, &consumer), (1_000, 0));
+    assert_eq!(market.bank.lamports(&consumer), lamports);
+    assert!(market.bank.ledger(&market.ledger).transactions.is_empty());
+}
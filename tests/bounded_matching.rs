@@ -0,0 +1,58 @@
+mod common;
+
+use borsh::BorshSerialize;
+use common::{crossing_book, Market};
+use energy_trading_program::{client, events::MatchRunCompleted, find_participant_address};
+use solana_program::pubkey::Pubkey;
+
+const TRADERS_PER_SIDE: usize = 8;
+
+// Cranks with `max_matches` until a run reports no fills, returning the fills of each run
+fn drain(market: &mut Market, wallets: &[Pubkey], max_matches: u16) -> Vec<u32> {
+    let mut runs = Vec::new();
+    loop {
+        let remaining = client::participant_metas(market.ledger, wallets);
+        market.bank.process(&client::match_transactions_ix(market.ledger, wallets[0], max_matches, remaining)).unwrap();
+        let trades_executed = market.bank.events::<MatchRunCompleted>()[0].trades_executed;
+        if trades_executed == 0 {
+            return runs;
+        }
+        runs.push(trades_executed);
+    }
+}
+
+#[test]
+fn keeper_drains_a_large_book_in_capped_runs() {
+    let (mut market, wallets) = crossing_book(TRADERS_PER_SIDE, 500);
+    let runs = drain(&mut market, &wallets, 16);
+    assert!(runs.len() > 1 && runs.iter().all(|&fills| fills <= 16), "{:?}", runs);
+
+    // Everything left rests on one side: no offer is priced within the limit of any open demand
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!(ledger.total_trades, runs.iter().map(|&fills| fills as u64).sum::<u64>());
+    let best_bid = ledger.demands.iter().map(|d| d.price_limit).max();
+    let best_ask = ledger.productions.iter().map(|p| p.price).min();
+    assert!(best_bid.zip(best_ask).is_none_or(|(bid, ask)| bid < ask));
+}
+
+#[test]
+fn capped_runs_end_where_one_unbounded_run_does() {
+    let (mut bounded, wallets) = crossing_book(TRADERS_PER_SIDE, 40);
+    let mut unbounded = bounded.clone();
+    drain(&mut bounded, &wallets, 3);
+    assert_eq!(drain(&mut unbounded, &wallets, u16::MAX).len(), 1);
+
+    // Both start from the same accounts, so the ledger and every participant end byte for byte
+    // equal, apart from the clearing price each run records and moves the reference price to
+    let per_run_prices_cleared = |market: &Market| {
+        let mut ledger = market.bank.ledger(&market.ledger);
+        ledger.reference_price = 0;
+        (ledger.price_history, ledger.price_history_head, ledger.price_history_len) = Default::default();
+        ledger.try_to_vec().unwrap()
+    };
+    assert_eq!(per_run_prices_cleared(&bounded), per_run_prices_cleared(&unbounded));
+    for wallet in &wallets {
+        let (participant, _) = find_participant_address(&energy_trading_program::id(), &bounded.ledger, wallet);
+        assert_eq!(bounded.bank.account(&participant), unbounded.bank.account(&participant));
+    }
+}
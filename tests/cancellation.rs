@@ -0,0 +1,78 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{client, EnergyMarketError, MarketConfig, OrderStorage, ParticipantType};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+fn cancel_demand(market: &mut Market, signer: Pubkey, order_id: u64) -> Result<(), ProgramError> {
+    market.bank.process(&client::cancel_demand_ix(market.ledger, signer, order_id, OrderStorage::Ledger))
+}
+
+#[test]
+fn only_the_consumer_cancels_its_open_demand() {
+    let mut market = Market::new(MarketConfig::default());
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    let other = market.register(ParticipantType::Consumer, 0);
+    market.post_demand(consumer, 50, 10).unwrap();
+    let order_id = market.bank.ledger(&market.ledger).demands[0].order_id;
+
+    assert_eq!(cancel_demand(&mut market, other, order_id).unwrap_err(), custom(EnergyMarketError::NotOrderOwner));
+    assert_eq!(cancel_demand(&mut market, consumer, order_id + 1).unwrap_err(), custom(EnergyMarketError::OrderNotFound));
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).reserved_balance, 500);
+
+    cancel_demand(&mut market, consumer, order_id).unwrap();
+    assert!(market.bank.ledger(&market.ledger).demands.is_empty());
+    let participant = market.bank.participant(&market.ledger, &consumer);
+    assert_eq!((participant.wallet_balance, participant.reserved_balance, participant.open_demands), (1_000, 0, 0));
+}
+
+#[test]
+fn matched_demand_can_no_longer_be_cancelled() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producer, 50, 10).unwrap();
+    market.post_demand(consumer, 50, 10).unwrap();
+    let order_id = market.bank.ledger(&market.ledger).demands[0].order_id;
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+
+    assert_eq!(cancel_demand(&mut market, consumer, order_id).unwrap_err(), custom(EnergyMarketError::OrderNotFound));
+}
+
+fn cancel_production(market: &mut Market, signer: Pubkey, order_id: u64) -> Result<(), ProgramError> {
+    market.bank.process(&client::cancel_production_ix(market.ledger, signer, order_id, OrderStorage::Ledger))
+}
+
+#[test]
+fn only_the_producer_cancels_its_offer() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let other = market.register(ParticipantType::Producer, 0);
+    market.report_production(producer, 50, 10).unwrap();
+    let order_id = market.bank.ledger(&market.ledger).productions[0].order_id;
+
+    assert_eq!(cancel_production(&mut market, other, order_id).unwrap_err(), custom(EnergyMarketError::NotOrderOwner));
+    assert_eq!(cancel_production(&mut market, producer, order_id + 1).unwrap_err(), custom(EnergyMarketError::OrderNotFound));
+    cancel_production(&mut market, producer, order_id).unwrap();
+    assert!(market.bank.ledger(&market.ledger).productions.is_empty());
+    assert_eq!(market.bank.participant(&market.ledger, &producer).open_productions, 0);
+    assert_eq!(cancel_production(&mut market, producer, order_id).unwrap_err(), custom(EnergyMarketError::OrderNotFound));
+}
+
+#[test]
+fn cancelling_a_partly_filled_offer_removes_only_the_remnant() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producer, 100, 10).unwrap();
+    market.post_demand(consumer, 30, 10).unwrap();
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+    let offer = market.bank.ledger(&market.ledger).productions[0].clone();
+    assert_eq!(offer.energy_amount, 70);
+
+    cancel_production(&mut market, producer, offer.order_id).unwrap();
+    let ledger = market.bank.ledger(&market.ledger);
+    assert!(ledger.productions.is_empty());
+    assert_eq!((ledger.transactions.len(), ledger.transactions[0].amount), (1, 30));
+    assert_eq!(market.bank.participant(&market.ledger, &producer).wallet_balance, 300);
+}
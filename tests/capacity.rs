@@ -0,0 +1,62 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, EnergyMarketError, HistoryPolicy, LedgerCapacity, MarketConfig, OrderStorage, ParticipantMetadata, ParticipantType,
+};
+
+fn market_of(capacity: LedgerCapacity, history_policy: HistoryPolicy) -> Market {
+    Market::builder(MarketConfig { history_policy, ..MarketConfig::default() }).capacity(capacity).build()
+}
+
+#[test]
+fn registrations_stop_at_max_participants() {
+    let mut market = market_of(LedgerCapacity { max_participants: 1, max_open_orders: 4, max_transactions: 4 }, HistoryPolicy::default());
+    market.register(ParticipantType::Consumer, 0);
+    let late = market.bank.funded_wallet(1);
+    let register = client::register_participant_ix(market.ledger, late, ParticipantType::Producer, 0, 0, ParticipantMetadata::default());
+    assert_eq!(market.bank.process(&register).unwrap_err(), custom(EnergyMarketError::LedgerFull));
+    assert_eq!(market.bank.ledger(&market.ledger).participant_count, 1);
+}
+
+#[test]
+fn orders_stop_at_max_open_orders_and_resume_once_one_leaves() {
+    let mut market = market_of(LedgerCapacity { max_participants: 4, max_open_orders: 2, max_transactions: 4 }, HistoryPolicy::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producer, 10, 10).unwrap();
+    market.post_demand(consumer, 10, 5).unwrap();
+
+    // Both sides count towards the one limit
+    assert_eq!(market.report_production(producer, 10, 11).unwrap_err(), custom(EnergyMarketError::OrderBookFull));
+    assert_eq!(market.post_demand(consumer, 10, 5).unwrap_err(), custom(EnergyMarketError::OrderBookFull));
+    let order_id = market.bank.ledger(&market.ledger).demands[0].order_id;
+    market.bank.process(&client::cancel_demand_ix(market.ledger, consumer, order_id, OrderStorage::Ledger)).unwrap();
+    market.post_demand(consumer, 10, 10).unwrap();
+}
+
+#[test]
+fn full_history_drops_the_oldest_trade_or_rejects_the_run() {
+    let capacity = LedgerCapacity { max_participants: 4, max_open_orders: 4, max_transactions: 1 };
+    for policy in [HistoryPolicy::DropOldest, HistoryPolicy::Reject] {
+        let mut market = market_of(capacity, policy);
+        let producer = market.register(ParticipantType::Producer, 0);
+        let consumer = market.register(ParticipantType::Consumer, 1_000);
+        market.report_production(producer, 10, 10).unwrap();
+        market.report_production(producer, 10, 11).unwrap();
+        market.post_demand(consumer, 20, 20).unwrap();
+
+        let run = market.match_orders(producer, &[producer, consumer]);
+        let ledger = market.bank.ledger(&market.ledger);
+        match policy {
+            HistoryPolicy::DropOldest => {
+                run.unwrap();
+                assert_eq!((ledger.total_trades, ledger.transactions.len(), ledger.transactions[0].price), (2, 1, 11));
+            }
+            HistoryPolicy::Reject => {
+                assert_eq!(run.unwrap_err(), custom(EnergyMarketError::HistoryFull));
+                assert_eq!((ledger.total_trades, ledger.productions.len()), (0, 2));
+            }
+        }
+    }
+}
@@ -0,0 +1,46 @@
+// The builders in client are the one place that encodes account order and privileges, so they
+// are checked against the account table the IDL publishes for each instruction
+use energy_trading_program::{client, idl, LedgerCapacity, MarketConfig, OrderStorage, ParticipantMetadata, ParticipantType};
+use solana_program::{instruction::Instruction, pubkey::Pubkey};
+
+fn builders(ledger: Pubkey, wallet: Pubkey) -> Vec<Instruction> {
+    let capacity = LedgerCapacity::default();
+    vec![
+        client::initialize_ledger_ix(ledger, wallet, capacity, None, MarketConfig::default(), None),
+        client::register_participant_ix(ledger, wallet, ParticipantType::Prosumer, 0, 0, ParticipantMetadata::default()),
+        client::match_transactions_ix(ledger, wallet, 16, Vec::new()),
+        client::deposit_ix(ledger, wallet, 1, None),
+        client::withdraw_ix(ledger, wallet, wallet, 1, None),
+        client::cancel_demand_ix(ledger, wallet, 0, OrderStorage::Ledger),
+        client::cancel_production_ix(ledger, wallet, 0, OrderStorage::Ledger),
+        client::prune_expired_orders_ix(ledger, Vec::new()),
+        client::run_auction_ix(ledger, wallet, Vec::new()),
+        client::resize_ledger_ix(ledger, wallet, wallet, 0, capacity),
+        client::migrate_ledger_ix(ledger, wallet, wallet),
+        client::set_fee_ix(ledger, wallet, 0),
+        client::collect_fees_ix(ledger, wallet),
+        client::transfer_balance_ix(ledger, wallet, Pubkey::new_unique(), 1),
+        client::pause_ix(ledger, wallet),
+        client::unregister_participant_ix(ledger, wallet),
+    ]
+}
+
+#[test]
+fn builders_pass_the_accounts_the_idl_lists_in_order() {
+    let (ledger, wallet) = (Pubkey::new_unique(), Pubkey::new_unique());
+    for instruction in builders(ledger, wallet) {
+        assert_eq!(instruction.program_id, energy_trading_program::id());
+        let described = &idl::INSTRUCTIONS[instruction.data[0] as usize];
+        let required: Vec<_> = described.accounts.iter().take_while(|account| !account.optional).collect();
+        assert!(instruction.accounts.len() >= required.len(), "{} passes too few accounts", described.name);
+        for (meta, account) in instruction.accounts.iter().zip(required) {
+            assert_eq!(
+                (meta.is_writable, meta.is_signer),
+                (account.writable, account.signer),
+                "{}: privileges of {}",
+                described.name,
+                account.name,
+            );
+        }
+    }
+}
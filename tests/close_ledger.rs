@@ -0,0 +1,116 @@
+mod common;
+
+use common::{custom, program_id, Market};
+use energy_trading_program::{
+    client, delivery_slot_at, find_order_address, find_vault_address, EnergyMarketError, LedgerCapacity, MarketConfig, OrderStorage,
+    ParticipantMetadata, ParticipantType, TimeInForce, CLOSED_ACCOUNT_TAG,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey, rent::Rent};
+
+fn close(market: &mut Market, recipient: Pubkey) -> Result<(), ProgramError> {
+    market.bank.process(&client::close_ledger_ix(market.ledger, market.admin, recipient, None))
+}
+
+#[test]
+fn ledger_stays_open_while_anything_is_left_in_it() {
+    let mut market = Market::builder(MarketConfig { fee_bps: 100, ..MarketConfig::default() }).registered_admin(0).build();
+    let recipient = market.bank.funded_wallet(1);
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producer, 100, 10).unwrap();
+    market.post_demand(consumer, 100, 10).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+
+    let not_empty = custom(EnergyMarketError::LedgerNotEmpty);
+    assert_eq!(close(&mut market, recipient).unwrap_err(), not_empty);
+    for wallet in [producer, consumer] {
+        let balance = market.bank.participant(&market.ledger, &wallet).wallet_balance;
+        market.bank.process(&client::withdraw_ix(market.ledger, wallet, wallet, balance, None)).unwrap();
+        market.bank.process(&client::unregister_participant_ix(market.ledger, wallet)).unwrap();
+    }
+    // The admin still holds the fees it collects as a participant
+    market.bank.process(&client::collect_fees_ix(market.ledger, market.admin)).unwrap();
+    assert_eq!(close(&mut market, recipient).unwrap_err(), not_empty);
+    let admin = market.admin;
+    market.bank.process(&client::withdraw_ix(market.ledger, admin, admin, 10, None)).unwrap();
+    market.bank.process(&client::unregister_participant_ix(market.ledger, admin)).unwrap();
+
+    let stranger = market.bank.funded_wallet(1);
+    let by_stranger = client::close_ledger_ix(market.ledger, stranger, recipient, None);
+    assert_eq!(market.bank.process(&by_stranger).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    close(&mut market, recipient).unwrap();
+}
+
+#[test]
+fn closing_hands_ledger_and_vault_rent_to_the_recipient() {
+    let mut market = Market::new(MarketConfig::default());
+    let recipient = market.bank.funded_wallet(1);
+    let (vault, _) = find_vault_address(&program_id(), &market.ledger);
+    let expected = market.bank.lamports(&recipient) + market.bank.lamports(&market.ledger) + market.bank.lamports(&vault);
+
+    close(&mut market, recipient).unwrap();
+    assert_eq!(market.bank.lamports(&recipient), expected);
+    assert_eq!((market.bank.lamports(&market.ledger), market.bank.lamports(&vault)), (0, 0));
+    let data = &market.bank.account(&market.ledger).unwrap().data;
+    assert_eq!(data[0], CLOSED_ACCOUNT_TAG);
+    assert!(data[1..].iter().all(|byte| *byte == 0));
+}
+
+#[test]
+fn a_closed_ledger_rejects_every_later_instruction() {
+    let mut market = Market::new(MarketConfig::default());
+    let recipient = market.bank.funded_wallet(1);
+    close(&mut market, recipient).unwrap();
+
+    let closed = custom(EnergyMarketError::LedgerClosed);
+    let wallet = market.bank.funded_wallet(1);
+    let metadata = ParticipantMetadata::default();
+    let register = client::register_participant_ix(market.ledger, wallet, ParticipantType::Consumer, 0, 0, metadata);
+    assert_eq!(market.bank.process(&register).unwrap_err(), closed);
+    assert_eq!(market.bank.process(&client::set_fee_ix(market.ledger, market.admin, 100)).unwrap_err(), closed);
+    assert_eq!(close(&mut market, recipient).unwrap_err(), closed);
+
+    // Re-funding the account does not let it be initialized over again
+    let space = market.bank.account(&market.ledger).unwrap().data.len();
+    market.bank.account_mut(&market.ledger).lamports = Rent::default().minimum_balance(space);
+    let initialize = client::initialize_ledger_ix(market.ledger, wallet, LedgerCapacity::default(), None, MarketConfig::default(), None);
+    assert_eq!(market.bank.process(&initialize).unwrap_err(), ProgramError::AccountAlreadyInitialized);
+}
+
+#[test]
+fn token_ledgers_close_the_empty_vault_token_account_too() {
+    let mint = Pubkey::new_unique();
+    let mut market = Market::builder(MarketConfig::default()).quote_mint(mint).build();
+    let (vault, _) = find_vault_address(&program_id(), &market.ledger);
+    let vault_tokens = market.bank.token_account(&mint, &vault, 0);
+    let recipient = market.bank.funded_wallet(1);
+    let expected = market.bank.lamports(&recipient) + market.bank.lamports(&vault_tokens)
+        + market.bank.lamports(&market.ledger) + market.bank.lamports(&vault);
+
+    let native = client::close_ledger_ix(market.ledger, market.admin, recipient, None);
+    assert_eq!(market.bank.process(&native).unwrap_err(), ProgramError::NotEnoughAccountKeys);
+    market.bank.process(&client::close_ledger_ix(market.ledger, market.admin, recipient, Some(vault_tokens))).unwrap();
+    assert_eq!(market.bank.lamports(&recipient), expected);
+    assert_eq!(market.bank.lamports(&vault_tokens), 0);
+}
+
+#[test]
+fn cancelled_order_accounts_are_closed_to_their_owner() {
+    let mut market = Market::new(MarketConfig { order_storage: OrderStorage::Accounts, ..MarketConfig::default() });
+    let consumer = market.register(ParticipantType::Consumer, 100);
+    let order_id = market.bank.ledger(&market.ledger).next_order_id;
+    let slot = delivery_slot_at(market.bank.now);
+    let post = client::post_demand_ix(
+        market.ledger, consumer, 10, 10, 0, slot, TimeInForce::GoodTilCancelled, 0, false, 0, 0, Some(order_id),
+    );
+    market.bank.process(&post).unwrap();
+    let (address, _) = find_order_address(&program_id(), &market.ledger, order_id);
+    let (rent, lamports) = (market.bank.lamports(&address), market.bank.lamports(&consumer));
+
+    let cancel = client::cancel_demand_ix(market.ledger, consumer, order_id, OrderStorage::Accounts);
+    market.bank.process(&cancel).unwrap();
+    assert_eq!((market.bank.lamports(&address), market.bank.lamports(&consumer)), (0, lamports + rent));
+    assert_eq!(market.bank.account(&address).unwrap().data[0], CLOSED_ACCOUNT_TAG);
+    assert_eq!(market.bank.ledger(&market.ledger).open_order_accounts, 0);
+    assert_eq!(market.bank.process(&cancel).unwrap_err(), custom(EnergyMarketError::OrderNotFound));
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, events::CollateralSlashed, DeliveryConfirmer, EnergyMarketError, MarketConfig, OrderStorage, ParticipantType,
+    SettlementConfig,
+};
+use solana_program::pubkey::Pubkey;
+
+const TIMEOUT: i64 = 3_600;
+
+// Offers must be half covered by collateral; consumers confirm deliveries and a default costs them nothing
+fn collateralised_market() -> Market {
+    let settlement = SettlementConfig { deferred: true, confirmer: DeliveryConfirmer::Consumer, delivery_timeout: TIMEOUT, default_penalty_bps: 0 };
+    Market::new(MarketConfig { collateral_bps: 5_000, settlement, ..MarketConfig::default() })
+}
+
+fn collateral(market: &Market, wallet: &Pubkey) -> (u64, u64) {
+    let participant = market.bank.participant(&market.ledger, wallet);
+    (participant.wallet_balance, participant.collateral_balance)
+}
+
+// Every lamport the ledger owes: participants' free, reserved and collateral balances plus the fee pool
+fn total_value(market: &Market, wallets: &[Pubkey]) -> u64 {
+    let held: u64 = wallets.iter()
+        .map(|wallet| market.bank.participant(&market.ledger, wallet))
+        .map(|p| p.wallet_balance + p.reserved_balance + p.collateral_balance)
+        .sum();
+    held + market.bank.ledger(&market.ledger).protocol_fees
+}
+
+#[test]
+fn offers_need_collateral_that_stays_locked_while_they_are_open() {
+    let mut market = collateralised_market();
+    let producer = market.register(ParticipantType::Producer, 100);
+    let ledger = market.ledger;
+    let post = move |amount| client::post_collateral_ix(ledger, producer, amount);
+    assert_eq!(market.bank.process(&post(101)).unwrap_err(), custom(EnergyMarketError::InsufficientBalance));
+    market.bank.process(&post(49)).unwrap();
+    assert_eq!(collateral(&market, &producer), (51, 49));
+
+    // 10 kWh at 10 needs half of its 100 notional
+    assert_eq!(market.report_production(producer, 10, 10).unwrap_err(), custom(EnergyMarketError::InsufficientCollateral));
+    market.bank.process(&post(1)).unwrap();
+    market.report_production(producer, 10, 10).unwrap();
+    let withdraw = move |amount| client::withdraw_collateral_ix(ledger, producer, amount);
+    assert_eq!(market.bank.process(&withdraw(1)).unwrap_err(), custom(EnergyMarketError::ParticipantHasOpenOrders));
+
+    market.bank.process(&client::cancel_production_ix(market.ledger, producer, 0, OrderStorage::Ledger)).unwrap();
+    assert_eq!(market.bank.process(&withdraw(51)).unwrap_err(), custom(EnergyMarketError::InsufficientCollateral));
+    market.bank.process(&withdraw(50)).unwrap();
+    assert_eq!(collateral(&market, &producer), (100, 0));
+}
+
+#[test]
+fn defaults_slash_collateral_without_creating_or_losing_value() {
+    let mut market = collateralised_market();
+    let producer = market.register(ParticipantType::Producer, 100);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    let wallets = [producer, consumer];
+    let total = total_value(&market, &wallets);
+    market.bank.process(&client::post_collateral_ix(market.ledger, producer, 80)).unwrap();
+    market.report_production(producer, 10, 10).unwrap();
+    market.post_demand(consumer, 10, 10).unwrap();
+    market.match_orders(consumer, &wallets).unwrap();
+    assert_eq!(total_value(&market, &wallets), total);
+
+    let withdraw = client::withdraw_collateral_ix(market.ledger, producer, 1);
+    assert_eq!(market.bank.process(&withdraw).unwrap_err(), custom(EnergyMarketError::PendingSettlement));
+    let (ledger, admin) = (market.ledger, market.admin);
+    let slash = move |admin, consumer, amount| client::slash_collateral_ix(ledger, admin, producer, consumer, 0, amount);
+    assert_eq!(market.bank.process(&slash(admin, None, 10)).unwrap_err(), custom(EnergyMarketError::TradeNotDefaulted));
+
+    // The default hands the consumer its escrow back along with the collateral that backed the trade
+    market.bank.now = market.bank.ledger(&market.ledger).transactions[0].settlement_deadline + 1;
+    market.bank.process(&client::settle_defaulted_trade_ix(market.ledger, consumer, producer, 0)).unwrap();
+    assert_eq!(
+        market.bank.events::<CollateralSlashed>(),
+        vec![CollateralSlashed { producer, trade_id: 0, amount: 50, to_consumer: true }],
+    );
+    assert_eq!((collateral(&market, &producer), collateral(&market, &consumer)), ((20, 30), (1_050, 0)));
+    assert_eq!(total_value(&market, &wallets), total);
+
+    // The admin may slash what is left, to the consumer or to the fee pool, never more than is held
+    assert_eq!(market.bank.process(&slash(consumer, None, 10)).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    market.bank.process(&slash(admin, Some(consumer), 10)).unwrap();
+    market.bank.process(&slash(admin, None, 100)).unwrap();
+    assert_eq!(market.bank.events::<CollateralSlashed>()[0].amount, 20);
+    assert_eq!((collateral(&market, &producer), collateral(&market, &consumer)), ((20, 0), (1_060, 0)));
+    assert_eq!(market.bank.ledger(&market.ledger).protocol_fees, 20);
+    assert_eq!(total_value(&market, &wallets), total);
+}
@@ -0,0 +1,648 @@
+// In-process harness for driving the program the way the runtime would. solana-program-test cannot
+// be resolved for this toolchain, so instructions go straight to process_instruction over accounts
+// kept in memory and laid out as the runtime would pass them, and the syscall stubs stand in for the
+// sysvars, CPIs into the system and token programs, return data and event logs. Tests can add
+// programs of their own that call into this one by CPI. Like the runtime, a
+// failed instruction leaves every account untouched, and an instruction that writes to an account
+// its metas mark read-only, or creates or destroys lamports, panics the test.
+#![allow(dead_code)]
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    sync::Once,
+};
+
+use borsh::BorshDeserialize;
+use energy_trading_program::{
+    client, delivery_slot_at,
+    events::{decode, Event},
+    find_participant_address, ledger_space, EnergyMarketError, EnergySource, Ledger, LedgerCapacity, MarketConfig, Participant,
+    ParticipantMetadata, ParticipantType, TimeInForce,
+};
+use solana_program::{
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::{ProgramResult, MAX_PERMITTED_DATA_INCREASE, SUCCESS},
+    instruction::Instruction,
+    native_token::LAMPORTS_PER_SOL,
+    program_error::ProgramError,
+    program_option::COption,
+    program_pack::Pack,
+    program_stubs::{set_syscall_stubs, SyscallStubs},
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction::SystemInstruction,
+    system_program,
+};
+use spl_token::{
+    error::TokenError,
+    instruction::TokenInstruction,
+    state::{Account as TokenAccount, AccountState},
+};
+
+pub use energy_trading_program::id as program_id;
+
+thread_local! {
+    static NOW: Cell<i64> = const { Cell::new(0) };
+    static RETURN_DATA: RefCell<Option<(Pubkey, Vec<u8>)>> = const { RefCell::new(None) };
+    static LOGGED_DATA: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+    // The program whose instruction is running, which derives the PDAs its CPIs sign for
+    static CURRENT_PROGRAM: Cell<Pubkey> = const { Cell::new(Pubkey::new_from_array([0; 32])) };
+}
+
+pub type Entrypoint = for<'a> fn(&Pubkey, &[AccountInfo<'a>], &[u8]) -> ProgramResult;
+
+struct Stubs;
+
+impl SyscallStubs for Stubs {
+    fn sol_log(&self, message: &str) {
+        if std::env::var_os("PROGRAM_LOG").is_some() {
+            println!("Program log: {}", message);
+        }
+    }
+
+    fn sol_log_data(&self, fields: &[&[u8]]) {
+        LOGGED_DATA.with(|logged| logged.borrow_mut().extend(fields.iter().map(|field| field.to_vec())));
+    }
+
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        let clock = Clock { unix_timestamp: NOW.with(Cell::get), ..Clock::default() };
+        unsafe { std::ptr::write_unaligned(var_addr as *mut Clock, clock) };
+        SUCCESS
+    }
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { std::ptr::write_unaligned(var_addr as *mut Rent, Rent::default()) };
+        SUCCESS
+    }
+
+    fn sol_set_return_data(&self, data: &[u8]) {
+        RETURN_DATA.with(|return_data| *return_data.borrow_mut() = Some((CURRENT_PROGRAM.with(Cell::get), data.to_vec())));
+    }
+
+    fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+        RETURN_DATA.with(|return_data| return_data.borrow().clone())
+    }
+
+    fn sol_invoke_signed(&self, instruction: &Instruction, account_infos: &[AccountInfo], signers_seeds: &[&[&[u8]]]) -> ProgramResult {
+        if instruction.program_id == spl_token::id() {
+            return invoke_token_program(instruction, account_infos, signers_seeds);
+        }
+        if instruction.program_id == program_id() {
+            return invoke_program(instruction, account_infos, signers_seeds);
+        }
+        if instruction.program_id != system_program::id() {
+            panic!("unexpected CPI into {}", instruction.program_id);
+        }
+        invoke_system_program(instruction, account_infos, signers_seeds)
+    }
+}
+
+fn signed_by(info: &AccountInfo, signers_seeds: &[&[&[u8]]]) -> ProgramResult {
+    let caller = CURRENT_PROGRAM.with(Cell::get);
+    let derived = signers_seeds.iter()
+        .any(|seeds| Pubkey::create_program_address(seeds, &caller).is_ok_and(|key| key == *info.key));
+    if info.is_signer || derived { Ok(()) } else { Err(ProgramError::MissingRequiredSignature) }
+}
+
+// CPIs into this program from a program a test adds. As with the runtime, the callee sees each
+// account with the privileges its meta asks for: a signature the caller must hold already or grant
+// by signing for a PDA of its own, and a write access the caller must hold already.
+fn invoke_program(instruction: &Instruction, account_infos: &[AccountInfo], signers_seeds: &[&[&[u8]]]) -> ProgramResult {
+    let infos = instruction.accounts.iter()
+        .map(|meta| {
+            let mut info = account_infos.iter()
+                .find(|info| *info.key == meta.pubkey)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?
+                .clone();
+            if meta.is_signer {
+                signed_by(&info, signers_seeds)?;
+            }
+            if meta.is_writable && !info.is_writable {
+                panic!("{} is writable in the CPI but read-only in its caller", meta.pubkey);
+            }
+            info.is_signer = meta.is_signer;
+            info.is_writable = meta.is_writable;
+            Ok(info)
+        })
+        .collect::<Result<Vec<_>, ProgramError>>()?;
+
+    let caller = CURRENT_PROGRAM.with(|current| current.replace(instruction.program_id));
+    let result = energy_trading_program::process_instruction(&instruction.program_id, &infos, &instruction.data);
+    CURRENT_PROGRAM.with(|current| current.set(caller));
+    result
+}
+
+fn cpi_account<'a, 'b>(instruction: &Instruction, account_infos: &'a [AccountInfo<'b>], index: usize) -> Result<&'a AccountInfo<'b>, ProgramError> {
+    let key = instruction.accounts.get(index).ok_or(ProgramError::NotEnoughAccountKeys)?.pubkey;
+    account_infos.iter().find(|info| *info.key == key).ok_or(ProgramError::NotEnoughAccountKeys)
+}
+
+// The token program instructions the program issues against token ledgers' custody: transfers in
+// and out of the vault token account, and closing it along with the ledger
+fn invoke_token_program(instruction: &Instruction, account_infos: &[AccountInfo], signers_seeds: &[&[&[u8]]]) -> ProgramResult {
+    let token_account = |info: &AccountInfo| -> Result<TokenAccount, ProgramError> {
+        if *info.owner != spl_token::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        TokenAccount::unpack(&info.data.borrow())
+    };
+    match TokenInstruction::unpack(&instruction.data)? {
+        TokenInstruction::Transfer { amount } => {
+            let (source, destination, authority) =
+                (cpi_account(instruction, account_infos, 0)?, cpi_account(instruction, account_infos, 1)?, cpi_account(instruction, account_infos, 2)?);
+            signed_by(authority, signers_seeds)?;
+            let (mut from, mut to) = (token_account(source)?, token_account(destination)?);
+            if from.owner != *authority.key {
+                return Err(TokenError::OwnerMismatch.into());
+            }
+            if from.mint != to.mint {
+                return Err(TokenError::MintMismatch.into());
+            }
+            from.amount = from.amount.checked_sub(amount).ok_or(TokenError::InsufficientFunds)?;
+            to.amount = to.amount.checked_add(amount).ok_or(TokenError::Overflow)?;
+            TokenAccount::pack(from, &mut source.data.borrow_mut())?;
+            TokenAccount::pack(to, &mut destination.data.borrow_mut())?;
+        }
+        TokenInstruction::CloseAccount => {
+            let (closed, destination, authority) =
+                (cpi_account(instruction, account_infos, 0)?, cpi_account(instruction, account_infos, 1)?, cpi_account(instruction, account_infos, 2)?);
+            signed_by(authority, signers_seeds)?;
+            let state = token_account(closed)?;
+            if state.owner != *authority.key {
+                return Err(TokenError::OwnerMismatch.into());
+            }
+            if state.amount != 0 {
+                return Err(TokenError::NonNativeHasBalance.into());
+            }
+            move_lamports(closed, destination, closed.lamports())?;
+            closed.data.borrow_mut().fill(0);
+        }
+        other => panic!("unexpected token instruction {:?}", other),
+    }
+    Ok(())
+}
+
+// The system program instructions the program issues: creating, funding, allocating and
+// assigning its PDAs and native deposits
+fn invoke_system_program(instruction: &Instruction, account_infos: &[AccountInfo], signers_seeds: &[&[&[u8]]]) -> ProgramResult {
+    let account = |index: usize| cpi_account(instruction, account_infos, index);
+    let signed = |info: &AccountInfo| signed_by(info, signers_seeds);
+    let system_owned = |info: &AccountInfo| -> ProgramResult {
+        if *info.owner == system_program::id() { Ok(()) } else { Err(ProgramError::InvalidAccountOwner) }
+    };
+
+    match bincode::deserialize(&instruction.data).map_err(|_| ProgramError::InvalidInstructionData)? {
+        SystemInstruction::CreateAccount { lamports, space, owner } => {
+            let (from, to) = (account(0)?, account(1)?);
+            signed(from)?;
+            signed(to)?;
+            system_owned(from)?;
+            if to.lamports() != 0 || !to.data_is_empty() || *to.owner != system_program::id() {
+                return Err(ProgramError::AccountAlreadyInitialized);
+            }
+            move_lamports(from, to, lamports)?;
+            allocate(to, space);
+            to.assign(&owner);
+        }
+        SystemInstruction::Transfer { lamports } => {
+            let (from, to) = (account(0)?, account(1)?);
+            signed(from)?;
+            system_owned(from)?;
+            move_lamports(from, to, lamports)?;
+        }
+        SystemInstruction::Allocate { space } => {
+            let target = account(0)?;
+            signed(target)?;
+            system_owned(target)?;
+            allocate(target, space);
+        }
+        SystemInstruction::Assign { owner } => {
+            let target = account(0)?;
+            signed(target)?;
+            system_owned(target)?;
+            target.assign(&owner);
+        }
+        other => panic!("unexpected system instruction {:?}", other),
+    }
+    Ok(())
+}
+
+fn move_lamports(from: &AccountInfo, to: &AccountInfo, lamports: u64) -> ProgramResult {
+    let remaining = from.lamports().checked_sub(lamports).ok_or(ProgramError::InsufficientFunds)?;
+    **from.lamports.borrow_mut() = remaining;
+    **to.lamports.borrow_mut() += lamports;
+    Ok(())
+}
+
+// The harness copies account data back once the instruction ends, so the new buffer only has to
+// outlive this instruction
+fn allocate(info: &AccountInfo, space: u64) {
+    *info.data.borrow_mut() = Box::leak(vec![0; space as usize].into_boxed_slice());
+}
+
+// Accounts are laid out for an instruction the way the runtime serializes them, which
+// AccountInfo::realloc relies on: the original data length in the four bytes before the key, the
+// data length in the eight before the data and room after it for the data to grow into
+#[repr(C)]
+struct SerializedKey {
+    _padding: [u8; 4],
+    original_data_len: u32,
+    key: Pubkey,
+}
+
+fn serialized_data(data: &[u8]) -> Vec<u8> {
+    let mut buffer = vec![0; 8 + data.len() + MAX_PERMITTED_DATA_INCREASE];
+    buffer[..8].copy_from_slice(&(data.len() as u64).to_le_bytes());
+    buffer[8..8 + data.len()].copy_from_slice(data);
+    buffer
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestAccount {
+    pub lamports: u64,
+    pub data: Vec<u8>,
+    pub owner: Pubkey,
+    pub executable: bool,
+}
+
+#[derive(Clone)]
+pub struct Bank {
+    accounts: HashMap<Pubkey, TestAccount>,
+    programs: HashMap<Pubkey, Entrypoint>,
+    pub now: i64,
+}
+
+impl Bank {
+    pub fn new() -> Self {
+        static STUBS: Once = Once::new();
+        STUBS.call_once(|| {
+            set_syscall_stubs(Box::new(Stubs));
+        });
+        Bank { accounts: HashMap::new(), programs: HashMap::new(), now: 1_700_000_000 }
+    }
+
+    // Instructions for `program_id` go to `entrypoint`, which may call into this program by CPI
+    pub fn add_program(&mut self, program_id: Pubkey, entrypoint: Entrypoint) {
+        self.programs.insert(program_id, entrypoint);
+    }
+
+    pub fn account(&self, key: &Pubkey) -> Option<&TestAccount> {
+        self.accounts.get(key)
+    }
+
+    // For setting up account states no instruction produces
+    pub fn account_mut(&mut self, key: &Pubkey) -> &mut TestAccount {
+        self.accounts.get_mut(key).expect("unknown account")
+    }
+
+    pub fn lamports(&self, key: &Pubkey) -> u64 {
+        self.accounts.get(key).map_or(0, |account| account.lamports)
+    }
+
+    // A system-owned wallet holding `sol` SOL
+    pub fn funded_wallet(&mut self, sol: u64) -> Pubkey {
+        let wallet = Pubkey::new_unique();
+        self.accounts.insert(wallet, TestAccount {
+            lamports: sol * LAMPORTS_PER_SOL,
+            data: Vec::new(),
+            owner: system_program::id(),
+            executable: false,
+        });
+        wallet
+    }
+
+    // An initialized token account of `mint` owned by `owner`, holding `amount`
+    pub fn token_account(&mut self, mint: &Pubkey, owner: &Pubkey, amount: u64) -> Pubkey {
+        let state = TokenAccount {
+            mint: *mint,
+            owner: *owner,
+            amount,
+            delegate: COption::None,
+            state: AccountState::Initialized,
+            is_native: COption::None,
+            delegated_amount: 0,
+            close_authority: COption::None,
+        };
+        let mut data = vec![0; TokenAccount::LEN];
+        TokenAccount::pack(state, &mut data).unwrap();
+        let key = Pubkey::new_unique();
+        self.accounts.insert(key, TestAccount {
+            lamports: Rent::default().minimum_balance(TokenAccount::LEN),
+            data,
+            owner: spl_token::id(),
+            executable: false,
+        });
+        key
+    }
+
+    pub fn token_balance(&self, key: &Pubkey) -> u64 {
+        TokenAccount::unpack(&self.accounts[key].data).unwrap().amount
+    }
+
+    // A system-owned account at a given address, for wallets that are another program's PDAs
+    pub fn fund(&mut self, key: Pubkey, lamports: u64) {
+        self.accounts.insert(key, TestAccount { lamports, data: Vec::new(), owner: system_program::id(), executable: false });
+    }
+
+    pub fn airdrop(&mut self, wallet: &Pubkey, lamports: u64) {
+        self.accounts.get_mut(wallet).expect("unknown wallet").lamports += lamports;
+    }
+
+    // A rent-exempt, zeroed account of `space` bytes already assigned to the program, as a client
+    // creates the ledger or trade log before initializing it
+    pub fn program_account(&mut self, space: usize) -> Pubkey {
+        let key = Pubkey::new_unique();
+        self.accounts.insert(key, TestAccount {
+            lamports: Rent::default().minimum_balance(space),
+            data: vec![0; space],
+            owner: program_id(),
+            executable: false,
+        });
+        key
+    }
+
+    pub fn process(&mut self, instruction: &Instruction) -> ProgramResult {
+        NOW.with(|now| now.set(self.now));
+        RETURN_DATA.with(|return_data| *return_data.borrow_mut() = None);
+        LOGGED_DATA.with(|logged| logged.borrow_mut().clear());
+
+        // Duplicate metas share one account, with the union of their privileges
+        let mut keys: Vec<Pubkey> = Vec::new();
+        let mut signer = Vec::new();
+        let mut writable = Vec::new();
+        for meta in &instruction.accounts {
+            match keys.iter().position(|key| *key == meta.pubkey) {
+                Some(index) => {
+                    signer[index] |= meta.is_signer;
+                    writable[index] |= meta.is_writable;
+                }
+                None => {
+                    keys.push(meta.pubkey);
+                    signer.push(meta.is_signer);
+                    writable.push(meta.is_writable);
+                }
+            }
+        }
+        let before: Vec<TestAccount> = keys.iter()
+            .map(|key| self.accounts.get(key).cloned().unwrap_or(TestAccount {
+                lamports: 0,
+                data: Vec::new(),
+                owner: system_program::id(),
+                executable: false,
+            }))
+            .collect();
+
+        let serialized_keys: Vec<SerializedKey> = keys.iter()
+            .zip(&before)
+            .map(|(key, account)| SerializedKey { _padding: [0; 4], original_data_len: account.data.len() as u32, key: *key })
+            .collect();
+        let mut lamports: Vec<u64> = before.iter().map(|account| account.lamports).collect();
+        let mut buffers: Vec<Vec<u8>> = before.iter().map(|account| serialized_data(&account.data)).collect();
+        let data: Vec<&mut [u8]> = buffers.iter_mut()
+            .zip(&before)
+            .map(|(buffer, account)| &mut buffer[8..8 + account.data.len()])
+            .collect();
+        let owners: Vec<Pubkey> = before.iter().map(|account| account.owner).collect();
+        let infos: Vec<AccountInfo> = serialized_keys.iter()
+            .map(|serialized| &serialized.key)
+            .zip(lamports.iter_mut())
+            .zip(data)
+            .zip(owners.iter())
+            .enumerate()
+            .map(|(index, (((key, lamports), data), owner))| {
+                AccountInfo::new(key, signer[index], writable[index], lamports, data, owner, before[index].executable, 0)
+            })
+            .collect();
+        let passed: Vec<AccountInfo> = instruction.accounts.iter()
+            .map(|meta| infos[keys.iter().position(|key| *key == meta.pubkey).unwrap()].clone())
+            .collect();
+
+        let entrypoint: Entrypoint = if instruction.program_id == program_id() {
+            energy_trading_program::process_instruction
+        } else {
+            *self.programs.get(&instruction.program_id).expect("unknown program")
+        };
+        CURRENT_PROGRAM.with(|current| current.set(instruction.program_id));
+        entrypoint(&instruction.program_id, &passed, &instruction.data)?;
+
+        let after: Vec<TestAccount> = infos.iter()
+            .zip(&before)
+            .map(|(info, before)| TestAccount {
+                lamports: info.lamports(),
+                data: info.data.borrow().to_vec(),
+                owner: *info.owner,
+                executable: before.executable,
+            })
+            .collect();
+        drop(passed);
+        drop(infos);
+
+        let total_before: u128 = before.iter().map(|account| account.lamports as u128).sum();
+        let total_after: u128 = after.iter().map(|account| account.lamports as u128).sum();
+        assert_eq!(total_before, total_after, "instruction changed the total lamports of its accounts");
+        for (index, key) in keys.iter().enumerate() {
+            if !writable[index] && after[index] != before[index] {
+                panic!("{} was modified but its meta is read-only", key);
+            }
+            self.accounts.insert(*key, after[index].clone());
+        }
+        Ok(())
+    }
+
+    // The custom error code of a failed instruction, for asserting on EnergyMarketError variants
+    pub fn process_err(&mut self, instruction: &Instruction) -> ProgramError {
+        self.process(instruction).expect_err("instruction unexpectedly succeeded")
+    }
+
+    pub fn ledger(&self, ledger: &Pubkey) -> Ledger {
+        Ledger::deserialize(&mut self.accounts[ledger].data.as_slice()).unwrap()
+    }
+
+    pub fn participant(&self, ledger: &Pubkey, wallet: &Pubkey) -> Participant {
+        let (address, _) = find_participant_address(&program_id(), ledger, wallet);
+        Participant::deserialize(&mut self.accounts[&address].data.as_slice()).unwrap()
+    }
+
+    pub fn return_data(&self) -> Option<Vec<u8>> {
+        RETURN_DATA.with(|return_data| return_data.borrow().as_ref().map(|(_, data)| data.clone()))
+    }
+
+    // The events of the last processed instruction, in emission order
+    pub fn events<E: Event + BorshDeserialize>(&self) -> Vec<E> {
+        LOGGED_DATA.with(|logged| logged.borrow().iter().filter_map(|data| decode(data)).collect())
+    }
+}
+
+pub fn custom(error: EnergyMarketError) -> ProgramError {
+    ProgramError::Custom(error as u32)
+}
+
+pub const CAPACITY: LedgerCapacity = LedgerCapacity { max_participants: 16, max_open_orders: 64, max_transactions: 64 };
+
+// A native ledger initialized by a funded admin, with shortcuts for registering, trading and cranking on it
+#[derive(Clone)]
+pub struct Market {
+    pub bank: Bank,
+    pub ledger: Pubkey,
+    pub admin: Pubkey,
+}
+
+impl Market {
+    pub fn new(config: MarketConfig) -> Self {
+        Market::builder(config).build()
+    }
+
+    pub fn builder(config: MarketConfig) -> MarketBuilder {
+        MarketBuilder { config, capacity: CAPACITY, quote_mint: None, admin_deposit: None, admin_instructions: Vec::new() }
+    }
+
+    pub fn register(&mut self, participant_type: ParticipantType, deposit: u64) -> Pubkey {
+        let wallet = self.bank.funded_wallet(10);
+        self.bank.process(&client::register_participant_ix(
+            self.ledger,
+            wallet,
+            participant_type,
+            0,
+            1_000_000,
+            ParticipantMetadata::default(),
+        )).unwrap();
+        if deposit > 0 {
+            self.bank.process(&client::deposit_ix(self.ledger, wallet, deposit, None)).unwrap();
+        }
+        wallet
+    }
+
+    pub fn report_production(&mut self, producer: Pubkey, energy_amount: u64, price: i64) -> Result<(), ProgramError> {
+        self.report_production_for(producer, energy_amount, price, delivery_slot_at(self.bank.now))
+    }
+
+    pub fn report_production_for(&mut self, producer: Pubkey, energy_amount: u64, price: i64, slot: u32) -> Result<(), ProgramError> {
+        self.bank.process(&client::report_production_ix(
+            self.ledger,
+            producer,
+            energy_amount,
+            price,
+            0,
+            slot,
+            EnergySource::Solar,
+            TimeInForce::GoodTilCancelled,
+            0,
+            false,
+            0,
+            false,
+            None,
+        ))
+    }
+
+    pub fn post_demand(&mut self, consumer: Pubkey, energy_amount: u64, price_limit: i64) -> Result<(), ProgramError> {
+        self.post_demand_for(consumer, energy_amount, price_limit, delivery_slot_at(self.bank.now))
+    }
+
+    pub fn post_demand_for(&mut self, consumer: Pubkey, energy_amount: u64, price_limit: i64, slot: u32) -> Result<(), ProgramError> {
+        self.bank.process(&client::post_demand_ix(
+            self.ledger,
+            consumer,
+            energy_amount,
+            price_limit,
+            0,
+            slot,
+            TimeInForce::GoodTilCancelled,
+            0,
+            false,
+            0,
+            0,
+            None,
+        ))
+    }
+
+    pub fn match_orders(&mut self, cranker: Pubkey, wallets: &[Pubkey]) -> Result<(), ProgramError> {
+        self.bank.process(&client::match_transactions_ix(
+            self.ledger,
+            cranker,
+            16,
+            client::participant_metas(self.ledger, wallets),
+        ))
+    }
+}
+
+type AdminInstruction = Box<dyn FnOnce(Pubkey, Pubkey) -> Instruction>;
+
+// Sets a Market up past initialization: a ledger of CAPACITY settling in lamports unless told
+// otherwise, the admin registered as a consumer with a deposit, so it can fund pools from its
+// balance, and admin instructions built from the ledger and admin keys, processed in order once
+// the ledger exists
+pub struct MarketBuilder {
+    config: MarketConfig,
+    capacity: LedgerCapacity,
+    quote_mint: Option<Pubkey>,
+    admin_deposit: Option<u64>,
+    admin_instructions: Vec<AdminInstruction>,
+}
+
+impl MarketBuilder {
+    pub fn capacity(mut self, capacity: LedgerCapacity) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    // Settles in the SPL token `mint` instead of lamports
+    pub fn quote_mint(mut self, mint: Pubkey) -> Self {
+        self.quote_mint = Some(mint);
+        self
+    }
+
+    pub fn registered_admin(mut self, deposit: u64) -> Self {
+        self.admin_deposit = Some(deposit);
+        self
+    }
+
+    pub fn admin_instruction(mut self, instruction: impl FnOnce(Pubkey, Pubkey) -> Instruction + 'static) -> Self {
+        self.admin_instructions.push(Box::new(instruction));
+        self
+    }
+
+    pub fn build(self) -> Market {
+        let mut bank = Bank::new();
+        let admin = bank.funded_wallet(10);
+        let ledger = bank.program_account(ledger_space(&self.capacity));
+        bank.process(&client::initialize_ledger_ix(ledger, admin, self.capacity, self.quote_mint, self.config, None)).unwrap();
+        if let Some(deposit) = self.admin_deposit {
+            bank.process(&client::register_participant_ix(
+                ledger, admin, ParticipantType::Consumer, 0, 0, ParticipantMetadata::default(),
+            )).unwrap();
+            if deposit > 0 {
+                bank.process(&client::deposit_ix(ledger, admin, deposit, None)).unwrap();
+            }
+        }
+        for instruction in self.admin_instructions {
+            bank.process(&instruction(ledger, admin)).unwrap();
+        }
+        Market { bank, ledger, admin }
+    }
+}
+
+// A market of `traders_per_side` producers and consumers with `orders` crossing orders, alternately
+// offers and demands, of varied sizes and prices so fills split orders across runs. Returns the
+// producers' wallets, then the consumers'.
+pub fn crossing_book(traders_per_side: usize, orders: usize) -> (Market, Vec<Pubkey>) {
+    let capacity = LedgerCapacity {
+        max_participants: 2 * traders_per_side as u32,
+        max_open_orders: orders as u32,
+        max_transactions: orders as u32 * 2,
+    };
+    let mut market = Market::builder(MarketConfig::default()).capacity(capacity).build();
+    let producers: Vec<Pubkey> = (0..traders_per_side).map(|_| market.register(ParticipantType::Producer, 0)).collect();
+    let consumers: Vec<Pubkey> = (0..traders_per_side).map(|_| market.register(ParticipantType::Consumer, 1_000_000)).collect();
+    for index in 0..orders {
+        let trader = index / 2 % traders_per_side;
+        let amount = 5 + (index % 7) as u64 * 3;
+        if index % 2 == 0 {
+            market.report_production(producers[trader], amount, 8 + (index % 3) as i64).unwrap();
+        } else {
+            market.post_demand(consumers[trader], amount, 10 + (index % 4) as i64).unwrap();
+        }
+    }
+    (market, producers.into_iter().chain(consumers).collect())
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, events::ConfigUpdated, ConfigUpdate, DeliveryConfirmer, EnergyMarketError, LossBearer, LossConfig, MarketConfig,
+    ParticipantType, SettlementConfig,
+};
+use solana_program::program_error::ProgramError;
+
+fn update(market: &mut Market, update: ConfigUpdate) -> Result<(), ProgramError> {
+    market.bank.process(&client::update_config_ix(market.ledger, market.admin, update))
+}
+
+#[test]
+fn fee_rates_are_bounded_together() {
+    let mut market = Market::new(MarketConfig::default());
+    let invalid = custom(EnergyMarketError::InvalidFeeRate);
+    assert_eq!(update(&mut market, ConfigUpdate::FeeBps(10_001)).unwrap_err(), invalid);
+    update(&mut market, ConfigUpdate::FeeBps(10_000)).unwrap();
+    assert_eq!(update(&mut market, ConfigUpdate::CrankRewardBps(1)).unwrap_err(), invalid);
+
+    update(&mut market, ConfigUpdate::FeeBps(9_900)).unwrap();
+    update(&mut market, ConfigUpdate::CrankRewardBps(100)).unwrap();
+    assert_eq!(update(&mut market, ConfigUpdate::FeeBps(9_901)).unwrap_err(), invalid);
+    let config = market.bank.ledger(&market.ledger).config;
+    assert_eq!((config.fee_bps, config.crank_reward_bps), (9_900, 100));
+}
+
+#[test]
+fn out_of_range_values_are_rejected_at_their_bounds() {
+    let mut market = Market::new(MarketConfig::default());
+    let invalid = custom(EnergyMarketError::InvalidConfigValue);
+    let cases = [
+        (ConfigUpdate::CollateralBps(10_001), ConfigUpdate::CollateralBps(10_000)),
+        (ConfigUpdate::InsuranceFeeBps(10_001), ConfigUpdate::InsuranceFeeBps(10_000)),
+        (ConfigUpdate::AttestationTimeout(-1), ConfigUpdate::AttestationTimeout(0)),
+        (ConfigUpdate::StaleDemandTimeout(-1), ConfigUpdate::StaleDemandTimeout(0)),
+        (ConfigUpdate::ConfigTimelock(-1), ConfigUpdate::ConfigTimelock(0)),
+        (
+            ConfigUpdate::Losses(LossConfig { loss_factor_bps: 10_000, inter_zone_loss_bps: 0, bearer: LossBearer::default() }),
+            ConfigUpdate::Losses(LossConfig { loss_factor_bps: 9_999, inter_zone_loss_bps: 9_999, bearer: LossBearer::default() }),
+        ),
+    ];
+    for (rejected, accepted) in cases {
+        assert_eq!(update(&mut market, rejected).unwrap_err(), invalid, "{:?}", rejected);
+        update(&mut market, accepted).unwrap();
+    }
+
+    // Oracle confirmation needs an oracle to be set
+    let oracle = SettlementConfig { deferred: true, confirmer: DeliveryConfirmer::Oracle, ..SettlementConfig::default() };
+    assert_eq!(update(&mut market, ConfigUpdate::Settlement(oracle)).unwrap_err(), custom(EnergyMarketError::InvalidOracle));
+    let penalty = SettlementConfig { default_penalty_bps: 10_001, ..SettlementConfig::default() };
+    assert_eq!(update(&mut market, ConfigUpdate::Settlement(penalty)).unwrap_err(), custom(EnergyMarketError::InvalidFeeRate));
+    let timeout = SettlementConfig { delivery_timeout: -1, ..SettlementConfig::default() };
+    assert_eq!(update(&mut market, ConfigUpdate::Settlement(timeout)).unwrap_err(), invalid);
+    assert_eq!(update(&mut market, ConfigUpdate::GridFeePerKwh(1)).unwrap_err(), custom(EnergyMarketError::GridOperatorNotSet));
+}
+
+#[test]
+fn each_change_is_admin_only_and_reports_the_value_it_replaced() {
+    let mut market = Market::new(MarketConfig { fee_bps: 25, ..MarketConfig::default() });
+    let producer = market.register(ParticipantType::Producer, 0);
+    let by_producer = client::update_config_ix(market.ledger, producer, ConfigUpdate::FeeBps(0));
+    assert_eq!(market.bank.process(&by_producer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+
+    update(&mut market, ConfigUpdate::FeeBps(50)).unwrap();
+    assert_eq!(
+        market.bank.events::<ConfigUpdated>(),
+        vec![ConfigUpdated { previous: ConfigUpdate::FeeBps(25), update: ConfigUpdate::FeeBps(50) }],
+    );
+}
+
+#[test]
+fn new_values_apply_from_the_next_match() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 10_000);
+    let cranker = market.register(ParticipantType::Consumer, 0);
+    let trade = |market: &mut Market| {
+        market.report_production(producer, 100, 10).unwrap();
+        market.post_demand(consumer, 100, 10).unwrap();
+        market.match_orders(cranker, &[producer, consumer]).unwrap();
+    };
+    trade(&mut market);
+    assert_eq!(market.bank.ledger(&market.ledger).protocol_fees, 0);
+
+    // On a notional of 1_000, 1% goes to the fee pool and 0.5% to the cranker
+    update(&mut market, ConfigUpdate::FeeBps(100)).unwrap();
+    update(&mut market, ConfigUpdate::CrankRewardBps(50)).unwrap();
+    trade(&mut market);
+    assert_eq!(market.bank.ledger(&market.ledger).protocol_fees, 10);
+    assert_eq!(market.bank.participant(&market.ledger, &cranker).wallet_balance, 5);
+
+    update(&mut market, ConfigUpdate::MaxOpenOrdersPerParticipant(1)).unwrap();
+    market.post_demand(consumer, 10, 10).unwrap();
+    assert_eq!(market.post_demand(consumer, 10, 10).unwrap_err(), custom(EnergyMarketError::TooManyOpenOrders));
+}
@@ -0,0 +1,143 @@
+mod common;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use common::{custom, program_id, Market};
+use energy_trading_program::{
+    client,
+    cpi::{self, CpiContext},
+    delivery_slot_at, EnergyMarketError, MarketConfig, ParticipantMetadata, ParticipantType, TimeInForce,
+};
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    native_token::LAMPORTS_PER_SOL,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+const CALLER: Pubkey = Pubkey::new_from_array([7; 32]);
+
+#[derive(BorshSerialize, BorshDeserialize)]
+enum CallerInstruction {
+    Register,
+    Deposit { amount: u64 },
+    PostDemand { energy_amount: u64, price_limit: i64, delivery_slot: u32 },
+    Withdraw { amount: u64 },
+}
+
+fn wallet_of(user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"wallet", user.as_ref()], &CALLER)
+}
+
+// A partner program that keeps one PDA wallet per user and trades from it when the user signs.
+// Accounts: the user, then the accounts of the energy market instruction, wallet and ledger first.
+fn process_caller(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let [user, wallet, ledger, ..] = accounts else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+    if !user.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let (_, bump) = Pubkey::find_program_address(&[b"wallet", user.key.as_ref()], program_id);
+    let bump = [bump];
+    let seeds: &[&[u8]] = &[b"wallet", user.key.as_ref(), &bump];
+    let signer_seeds = [seeds];
+    let ctx = CpiContext::new_with_signer(accounts, &signer_seeds);
+
+    let (wallet, ledger) = (*wallet.key, *ledger.key);
+    match CallerInstruction::try_from_slice(data).map_err(|_| ProgramError::InvalidInstructionData)? {
+        CallerInstruction::Register => {
+            cpi::register_participant(&ctx, ledger, wallet, ParticipantType::Consumer, 0, 0, ParticipantMetadata::default())
+        }
+        CallerInstruction::Deposit { amount } => cpi::deposit(&ctx, ledger, wallet, amount, None),
+        CallerInstruction::PostDemand { energy_amount, price_limit, delivery_slot } => cpi::post_demand(
+            &ctx, ledger, wallet, energy_amount, price_limit, 0, delivery_slot, TimeInForce::GoodTilCancelled, 0, false, 0, 0, None,
+        ),
+        CallerInstruction::Withdraw { amount } => cpi::withdraw(&ctx, ledger, wallet, *user.key, amount, None),
+    }
+}
+
+// Wraps an energy market instruction for `wallet` into a call to the partner program signed by
+// `user`; only the partner can sign for the wallet, so the outer transaction does not
+fn via_caller(user: Pubkey, call: CallerInstruction, inner: Instruction) -> Instruction {
+    let mut accounts = vec![AccountMeta::new_readonly(user, true)];
+    accounts.extend(inner.accounts.into_iter().map(|meta| AccountMeta { is_signer: false, ..meta }));
+    accounts.push(AccountMeta::new_readonly(program_id(), false));
+    Instruction { program_id: CALLER, accounts, data: call.try_to_vec().unwrap() }
+}
+
+// A market with the partner program deployed and a user whose PDA wallet holds 1 SOL
+fn partner_market() -> (Market, Pubkey, Pubkey) {
+    let mut market = Market::new(MarketConfig::default());
+    market.bank.add_program(CALLER, process_caller);
+    let user = market.bank.funded_wallet(1);
+    let (wallet, _) = wallet_of(&user);
+    market.bank.fund(wallet, LAMPORTS_PER_SOL);
+    (market, user, wallet)
+}
+
+fn register_and_deposit(market: &mut Market, user: Pubkey, wallet: Pubkey, amount: u64) {
+    let metadata = ParticipantMetadata::default();
+    let register = client::register_participant_ix(market.ledger, wallet, ParticipantType::Consumer, 0, 0, metadata);
+    market.bank.process(&via_caller(user, CallerInstruction::Register, register)).unwrap();
+    let deposit = client::deposit_ix(market.ledger, wallet, amount, None);
+    market.bank.process(&via_caller(user, CallerInstruction::Deposit { amount }, deposit)).unwrap();
+}
+
+#[test]
+fn partner_program_registers_its_pda_and_posts_a_demand() {
+    let (mut market, user, wallet) = partner_market();
+    register_and_deposit(&mut market, user, wallet, 1_000);
+    assert_eq!(market.bank.participant(&market.ledger, &wallet).wallet_balance, 1_000);
+
+    let delivery_slot = delivery_slot_at(market.bank.now);
+    let post = client::post_demand_ix(
+        market.ledger, wallet, 10, 10, 0, delivery_slot, TimeInForce::GoodTilCancelled, 0, false, 0, 0, None,
+    );
+    let call = CallerInstruction::PostDemand { energy_amount: 10, price_limit: 10, delivery_slot };
+    market.bank.process(&via_caller(user, call, post)).unwrap();
+    let demand = market.bank.ledger(&market.ledger).demands[0].clone();
+    assert_eq!((demand.consumer_id, demand.energy_amount, demand.price_limit), (wallet, 10, 10));
+
+    // The PDA's demand trades like any other
+    let producer = market.register(ParticipantType::Producer, 0);
+    market.report_production(producer, 10, 10).unwrap();
+    market.match_orders(producer, &[producer, wallet]).unwrap();
+    assert_eq!(market.bank.participant(&market.ledger, &producer).wallet_balance, 100);
+    assert_eq!(market.bank.participant(&market.ledger, &wallet).wallet_balance, 900);
+}
+
+#[test]
+fn partner_program_withdraws_to_the_user() {
+    let (mut market, user, wallet) = partner_market();
+    register_and_deposit(&mut market, user, wallet, 1_000);
+    let lamports = market.bank.lamports(&user);
+    let withdraw = client::withdraw_ix(market.ledger, wallet, user, 400, None);
+    market.bank.process(&via_caller(user, CallerInstruction::Withdraw { amount: 400 }, withdraw)).unwrap();
+    assert_eq!(market.bank.lamports(&user), lamports + 400);
+    assert_eq!(market.bank.participant(&market.ledger, &wallet).wallet_balance, 600);
+}
+
+#[test]
+fn only_the_deriving_program_can_sign_for_the_pda() {
+    let (mut market, user, wallet) = partner_market();
+    register_and_deposit(&mut market, user, wallet, 1_000);
+
+    // Called directly, the PDA cannot sign
+    let mut withdraw = client::withdraw_ix(market.ledger, wallet, user, 400, None);
+    withdraw.accounts[0].is_signer = false;
+    assert_eq!(market.bank.process(&withdraw).unwrap_err(), ProgramError::MissingRequiredSignature);
+
+    // Nor can another user of the partner program, whose seeds derive a different wallet
+    let intruder = market.bank.funded_wallet(1);
+    let withdraw = client::withdraw_ix(market.ledger, wallet, intruder, 400, None);
+    let call = via_caller(intruder, CallerInstruction::Withdraw { amount: 400 }, withdraw);
+    assert_eq!(market.bank.process(&call).unwrap_err(), ProgramError::MissingRequiredSignature);
+    assert_eq!(market.bank.participant(&market.ledger, &wallet).wallet_balance, 1_000);
+
+    // The caller still goes through the program's own checks
+    let withdraw = client::withdraw_ix(market.ledger, wallet, user, 1_001, None);
+    let call = via_caller(user, CallerInstruction::Withdraw { amount: 1_001 }, withdraw);
+    assert_eq!(market.bank.process(&call).unwrap_err(), custom(EnergyMarketError::InsufficientBalance));
+}
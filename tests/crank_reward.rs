@@ -0,0 +1,56 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{client, EnergyMarketError, MarketConfig, ParticipantType};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+// A crossing pair of 100 kWh at 1_001 and a separate keeper to crank it
+fn crossed_market(crank_reward_bps: u16) -> (Market, Pubkey, Pubkey, Pubkey) {
+    let mut market = Market::new(MarketConfig { crank_reward_bps, ..MarketConfig::default() });
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 200_000);
+    let keeper = market.register(ParticipantType::Consumer, 0);
+    market.report_production(producer, 100, 1_001).unwrap();
+    market.post_demand(consumer, 100, 1_001).unwrap();
+    (market, producer, consumer, keeper)
+}
+
+fn balance(market: &Market, wallet: &Pubkey) -> u64 {
+    let participant = market.bank.participant(&market.ledger, wallet);
+    participant.wallet_balance + participant.reserved_balance
+}
+
+#[test]
+fn keeper_earns_its_cut_of_the_notional_from_both_sides() {
+    let (mut market, producer, consumer, keeper) = crossed_market(100);
+    market.match_orders(keeper, &[producer, consumer]).unwrap();
+
+    // 1% of 100_100 is 1_001: the buyer pays 500 of it on top of the price and the seller the odd 501
+    assert_eq!(balance(&market, &keeper), 1_001);
+    assert_eq!(balance(&market, &consumer), 200_000 - 100_100 - 500);
+    assert_eq!(balance(&market, &producer), 100_100 - 501);
+    let total: u64 = [producer, consumer, keeper].iter().map(|wallet| balance(&market, wallet)).sum();
+    assert_eq!(total + market.bank.ledger(&market.ledger).protocol_fees, 200_000);
+}
+
+#[test]
+fn zero_reward_leaves_the_keeper_unpaid() {
+    let (mut market, producer, consumer, keeper) = crossed_market(0);
+    market.match_orders(keeper, &[producer, consumer]).unwrap();
+    assert_eq!(balance(&market, &keeper), 0);
+    assert_eq!((balance(&market, &consumer), balance(&market, &producer)), (200_000 - 100_100, 100_100));
+}
+
+#[test]
+fn keeper_must_sign_and_be_registered() {
+    let (mut market, producer, consumer, keeper) = crossed_market(100);
+    let remaining = client::participant_metas(market.ledger, &[producer, consumer]);
+    let mut unsigned = client::match_transactions_ix(market.ledger, keeper, 16, remaining);
+    unsigned.accounts[1].is_signer = false;
+    assert_eq!(market.bank.process(&unsigned).unwrap_err(), ProgramError::MissingRequiredSignature);
+
+    let stranger = market.bank.funded_wallet(1);
+    let error = market.match_orders(stranger, &[producer, consumer]).unwrap_err();
+    assert_eq!(error, custom(EnergyMarketError::ParticipantNotRegistered));
+    assert_eq!(market.bank.ledger(&market.ledger).total_trades, 0);
+}
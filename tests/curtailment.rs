@@ -0,0 +1,62 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, events::{ProducerCurtailed, TradeExecuted}, EnergyMarketError, MarketConfig, ParticipantType,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+fn market_with_operator() -> (Market, Pubkey) {
+    let mut market = Market::new(MarketConfig::default());
+    let operator = market.bank.funded_wallet(1);
+    market.bank.process(&client::set_grid_operator_ix(market.ledger, market.admin, operator)).unwrap();
+    (market, operator)
+}
+
+fn curtail(market: &mut Market, operator: Pubkey, producer: Pubkey, amount: u64, until: i64) -> Result<ProducerCurtailed, ProgramError> {
+    market.bank.process(&client::curtail_ix(market.ledger, operator, producer, amount, until, &[]))?;
+    Ok(market.bank.events::<ProducerCurtailed>().remove(0))
+}
+
+fn offers(market: &Market) -> Vec<u64> {
+    market.bank.ledger(&market.ledger).productions.iter().map(|p| p.energy_amount).collect()
+}
+
+#[test]
+fn curtailment_cuts_the_newest_offers_first_including_partly_matched_ones() {
+    let (mut market, operator) = market_with_operator();
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producer, 100, 10).unwrap();
+    market.post_demand(consumer, 40, 10).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    market.report_production(producer, 50, 11).unwrap();
+    assert_eq!(offers(&market), vec![60, 50]);
+
+    assert_eq!(curtail(&mut market, consumer, producer, 10, 0).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    let cut = curtail(&mut market, operator, producer, 80, 0).unwrap();
+    assert_eq!(cut, ProducerCurtailed { producer, curtailed_amount: 80, curtailed_until: 0 });
+    assert_eq!(offers(&market), vec![30]);
+    assert_eq!(market.bank.participant(&market.ledger, &producer).open_productions, 1);
+
+    // Asking for more than is left takes the rest, and the freed demand finds nothing to match
+    assert_eq!(curtail(&mut market, operator, producer, 100, 0).unwrap().curtailed_amount, 30);
+    assert!(offers(&market).is_empty());
+    market.post_demand(consumer, 10, 20).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    assert!(market.bank.events::<TradeExecuted>().is_empty());
+}
+
+#[test]
+fn curtailed_producer_reports_nothing_until_the_window_ends() {
+    let (mut market, operator) = market_with_operator();
+    let producer = market.register(ParticipantType::Producer, 0);
+    let until = market.bank.now + 600;
+    assert_eq!(curtail(&mut market, operator, producer, 0, until).unwrap().curtailed_until, until);
+
+    market.bank.now = until - 1;
+    assert_eq!(market.report_production(producer, 10, 10).unwrap_err(), custom(EnergyMarketError::ProducerCurtailed));
+    market.bank.now = until;
+    market.report_production(producer, 10, 10).unwrap();
+    assert_eq!(offers(&market), vec![10]);
+}
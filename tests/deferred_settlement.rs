@@ -0,0 +1,103 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, delivery_slot_at, delivery_slot_end, events::TradeSettled, ConfigUpdate, DeliveryConfirmer, EnergyMarketError,
+    MarketConfig, OrderStorage, ParticipantType, SettlementConfig, TradeStatus,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+const TIMEOUT: i64 = 3_600;
+
+// Consumers confirm their own deliveries within an hour of the slot ending; a default costs them 10%
+fn deferred_market() -> Market {
+    let settlement = SettlementConfig {
+        deferred: true,
+        confirmer: DeliveryConfirmer::Consumer,
+        delivery_timeout: TIMEOUT,
+        default_penalty_bps: 1_000,
+    };
+    Market::new(MarketConfig { settlement, ..MarketConfig::default() })
+}
+
+// 10 kWh at 10 matched into trade 0, its 100 held in the consumer's escrow
+fn matched_trade(market: &mut Market) -> (Pubkey, Pubkey) {
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producer, 10, 10).unwrap();
+    market.post_demand(consumer, 10, 10).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    (producer, consumer)
+}
+
+fn balances(market: &Market, wallet: &Pubkey) -> (u64, u64) {
+    let participant = market.bank.participant(&market.ledger, wallet);
+    (participant.wallet_balance, participant.reserved_balance)
+}
+
+fn confirm(market: &mut Market, confirmer: Pubkey, consumer: Pubkey, producer: Pubkey) -> Result<TradeSettled, ProgramError> {
+    market.bank.process(&client::confirm_delivery_ix(market.ledger, confirmer, consumer, producer, 0))?;
+    Ok(market.bank.events::<TradeSettled>().remove(0))
+}
+
+#[test]
+fn confirmed_delivery_pays_the_producer_once() {
+    let mut market = deferred_market();
+    let (producer, consumer) = matched_trade(&mut market);
+    let trade = market.bank.ledger(&market.ledger).transactions[0].clone();
+    assert_eq!((trade.status, trade.escrow), (TradeStatus::Matched, 100));
+    assert_eq!(trade.settlement_deadline, delivery_slot_end(delivery_slot_at(market.bank.now)) + TIMEOUT);
+    assert_eq!((balances(&market, &consumer), balances(&market, &producer)), ((900, 100), (0, 0)));
+
+    assert_eq!(confirm(&mut market, producer, consumer, producer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    assert_eq!(
+        confirm(&mut market, consumer, consumer, producer).unwrap(),
+        TradeSettled { trade_id: 0, status: TradeStatus::Delivered, producer_amount: 100, consumer_refund: 0 },
+    );
+    assert_eq!((balances(&market, &consumer), balances(&market, &producer)), ((900, 0), (100, 0)));
+    assert_eq!(confirm(&mut market, consumer, consumer, producer).unwrap_err(), custom(EnergyMarketError::TradeNotPending));
+    let default = client::settle_defaulted_trade_ix(market.ledger, consumer, producer, 0);
+    assert_eq!(market.bank.process(&default).unwrap_err(), custom(EnergyMarketError::TradeNotPending));
+}
+
+#[test]
+fn unconfirmed_trade_defaults_after_the_deadline_with_a_penalty() {
+    let mut market = deferred_market();
+    let (producer, consumer) = matched_trade(&mut market);
+    let deadline = market.bank.ledger(&market.ledger).transactions[0].settlement_deadline;
+    let default = client::settle_defaulted_trade_ix(market.ledger, consumer, producer, 0);
+
+    market.bank.now = deadline;
+    assert_eq!(market.bank.process(&default).unwrap_err(), custom(EnergyMarketError::SettlementDeadlineNotReached));
+    market.bank.now = deadline + 1;
+    assert_eq!(confirm(&mut market, consumer, consumer, producer).unwrap_err(), custom(EnergyMarketError::SettlementDeadlinePassed));
+    market.bank.process(&default).unwrap();
+    assert_eq!(
+        market.bank.events::<TradeSettled>(),
+        vec![TradeSettled { trade_id: 0, status: TradeStatus::Defaulted, producer_amount: 0, consumer_refund: 90 }],
+    );
+    assert_eq!((balances(&market, &consumer), balances(&market, &producer)), ((990, 0), (0, 0)));
+    assert_eq!(market.bank.ledger(&market.ledger).protocol_fees, 10);
+    assert_eq!(market.bank.process(&default).unwrap_err(), custom(EnergyMarketError::TradeNotPending));
+}
+
+#[test]
+fn oracle_confirms_when_configured_to() {
+    let oracle = Pubkey::new_unique();
+    let settlement = SettlementConfig { deferred: true, confirmer: DeliveryConfirmer::Oracle, delivery_timeout: TIMEOUT, default_penalty_bps: 0 };
+    let mut market = Market::builder(MarketConfig::default())
+        .admin_instruction(move |ledger, admin| client::set_oracle_ix(ledger, admin, oracle))
+        .admin_instruction(move |ledger, admin| {
+            client::update_config_ix(ledger, admin, ConfigUpdate::Settlement(settlement))
+        })
+        .build();
+    let (producer, consumer) = matched_trade(&mut market);
+    // Offers need attesting on a ledger with an oracle; without it nothing matched
+    assert!(market.bank.ledger(&market.ledger).transactions.is_empty());
+    let attest = client::attest_production_ix(market.ledger, oracle, 0, OrderStorage::Ledger);
+    market.bank.process(&attest).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+
+    assert_eq!(confirm(&mut market, consumer, consumer, producer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    assert_eq!(confirm(&mut market, oracle, consumer, producer).unwrap().producer_amount, 100);
+}
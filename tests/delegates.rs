@@ -0,0 +1,99 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client::{self, with_delegate}, delivery_slot_at, EnergyMarketError, EnergySource, MarketConfig, OrderStorage, ParticipantType,
+    TimeInForce, ORDER_ACCOUNT_SIZE,
+};
+use solana_program::{instruction::Instruction, program_error::ProgramError, pubkey::Pubkey, rent::Rent};
+
+fn demand_ix(market: &Market, consumer: Pubkey, order_id: Option<u64>) -> Instruction {
+    let slot = delivery_slot_at(market.bank.now);
+    client::post_demand_ix(market.ledger, consumer, 10, 10, 0, slot, TimeInForce::GoodTilCancelled, 0, false, 0, 0, order_id)
+}
+
+// A consumer with 1_000 deposited whose hot key is set as its delegate
+fn delegated_consumer(market: &mut Market) -> (Pubkey, Pubkey) {
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    let bot = market.bank.funded_wallet(1);
+    market.bank.process(&client::set_delegate_ix(market.ledger, consumer, bot)).unwrap();
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).delegate, Some(bot));
+    (consumer, bot)
+}
+
+#[test]
+fn delegate_posts_and_cancels_on_the_owners_behalf() {
+    let mut market = Market::new(MarketConfig::default());
+    let (consumer, bot) = delegated_consumer(&mut market);
+    market.bank.process(&with_delegate(demand_ix(&market, consumer, None), bot)).unwrap();
+    let demand = market.bank.ledger(&market.ledger).demands[0].clone();
+    assert_eq!(demand.consumer_id, consumer);
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).reserved_balance, 100);
+
+    let cancel = client::cancel_demand_ix(market.ledger, consumer, demand.order_id, OrderStorage::Ledger);
+    market.bank.process(&with_delegate(cancel, bot)).unwrap();
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).wallet_balance, 1_000);
+
+    // Producers delegate the same way
+    let producer = market.register(ParticipantType::Producer, 0);
+    market.bank.process(&client::set_delegate_ix(market.ledger, producer, bot)).unwrap();
+    let slot = delivery_slot_at(market.bank.now);
+    let report = client::report_production_ix(
+        market.ledger, producer, 10, 10, 0, slot, EnergySource::Wind, TimeInForce::GoodTilCancelled, 0, false, 0, false, None,
+    );
+    market.bank.process(&with_delegate(report, bot)).unwrap();
+    assert_eq!(market.bank.ledger(&market.ledger).productions[0].producer_id, producer);
+}
+
+#[test]
+fn delegate_cannot_withdraw_or_manage_the_delegation() {
+    let mut market = Market::new(MarketConfig::default());
+    let (consumer, bot) = delegated_consumer(&mut market);
+    let withdraw = with_delegate(client::withdraw_ix(market.ledger, consumer, bot, 1_000, None), bot);
+    assert_eq!(market.bank.process(&withdraw).unwrap_err(), ProgramError::MissingRequiredSignature);
+    let withdraw_all = with_delegate(client::withdraw_all_ix(market.ledger, consumer, bot, None), bot);
+    assert_eq!(market.bank.process(&withdraw_all).unwrap_err(), ProgramError::MissingRequiredSignature);
+    let revoke = with_delegate(client::revoke_delegate_ix(market.ledger, consumer), bot);
+    assert_eq!(market.bank.process(&revoke).unwrap_err(), ProgramError::MissingRequiredSignature);
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).wallet_balance, 1_000);
+
+    let to_self = client::set_delegate_ix(market.ledger, consumer, consumer);
+    assert_eq!(market.bank.process(&to_self).unwrap_err(), custom(EnergyMarketError::InvalidDelegate));
+    let to_default = client::set_delegate_ix(market.ledger, consumer, Pubkey::default());
+    assert_eq!(market.bank.process(&to_default).unwrap_err(), custom(EnergyMarketError::InvalidDelegate));
+}
+
+#[test]
+fn revoked_or_foreign_delegates_are_rejected() {
+    let mut market = Market::new(MarketConfig::default());
+    let (consumer, bot) = delegated_consumer(&mut market);
+    let stranger = market.bank.funded_wallet(1);
+    let by_stranger = with_delegate(demand_ix(&market, consumer, None), stranger);
+    assert_eq!(market.bank.process(&by_stranger).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+
+    market.bank.process(&client::revoke_delegate_ix(market.ledger, consumer)).unwrap();
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).delegate, None);
+    let by_bot = with_delegate(demand_ix(&market, consumer, None), bot);
+    assert_eq!(market.bank.process(&by_bot).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+
+    // An unsigned owner with no delegate account appended is still refused
+    let mut unsigned = demand_ix(&market, consumer, None);
+    unsigned.accounts[0].is_signer = false;
+    assert_eq!(market.bank.process(&unsigned).unwrap_err(), ProgramError::MissingRequiredSignature);
+    assert!(market.bank.ledger(&market.ledger).demands.is_empty());
+}
+
+#[test]
+fn delegate_fronts_order_account_rent_that_returns_to_the_owner() {
+    let mut market = Market::new(MarketConfig { order_storage: OrderStorage::Accounts, ..MarketConfig::default() });
+    let (consumer, bot) = delegated_consumer(&mut market);
+    let (consumer_lamports, bot_lamports) = (market.bank.lamports(&consumer), market.bank.lamports(&bot));
+    let rent = Rent::default().minimum_balance(ORDER_ACCOUNT_SIZE);
+
+    let order_id = market.bank.ledger(&market.ledger).next_order_id;
+    market.bank.process(&with_delegate(demand_ix(&market, consumer, Some(order_id)), bot)).unwrap();
+    assert_eq!(market.bank.lamports(&bot), bot_lamports - rent);
+    let cancel = client::cancel_demand_ix(market.ledger, consumer, order_id, OrderStorage::Accounts);
+    market.bank.process(&with_delegate(cancel, bot)).unwrap();
+    assert_eq!(market.bank.lamports(&consumer), consumer_lamports + rent);
+}
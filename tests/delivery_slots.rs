@@ -0,0 +1,48 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, delivery_slot_at, delivery_slot_end, events::TradeExecuted, EnergyMarketError, MarketConfig, ParticipantType,
+};
+
+#[test]
+fn orders_only_cross_within_their_delivery_slot() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 10_000);
+    let now = delivery_slot_at(market.bank.now);
+    // Were slots ignored, every demand would fill from the cheapest offer, the one in the last slot
+    for (offset, price) in [(0, 30), (1, 20), (2, 10)] {
+        market.report_production_for(producer, 10, price, now + offset).unwrap();
+    }
+    for (offset, amount, limit) in [(0, 5, 30), (1, 6, 25), (2, 7, 15)] {
+        market.post_demand_for(consumer, amount, limit, now + offset).unwrap();
+    }
+
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    let fills: Vec<_> = market.bank.events::<TradeExecuted>().iter().map(|t| (t.amount, t.price)).collect();
+    assert_eq!(fills, vec![(5, 30), (6, 20), (7, 10)]);
+    let ledger = market.bank.ledger(&market.ledger);
+    let mut left: Vec<_> = ledger.productions.iter().map(|p| (p.delivery_slot - now, p.energy_amount)).collect();
+    left.sort_unstable();
+    assert_eq!(left, vec![(0, 5), (1, 4), (2, 3)]);
+}
+
+#[test]
+fn ended_slots_are_rejected_and_purged_with_their_escrow() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    let slot = delivery_slot_at(market.bank.now);
+    assert_eq!(market.post_demand_for(consumer, 10, 10, slot - 1).unwrap_err(), custom(EnergyMarketError::InvalidDeliverySlot));
+    market.post_demand_for(consumer, 10, 10, slot).unwrap();
+    market.report_production_for(producer, 10, 10, slot + 1).unwrap();
+
+    market.bank.now = delivery_slot_end(slot) + 1;
+    let prune = client::prune_expired_orders_ix(market.ledger, client::participant_metas(market.ledger, &[producer, consumer]));
+    market.bank.process(&prune).unwrap();
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.demands.len(), ledger.productions.len()), (0, 1));
+    let participant = market.bank.participant(&market.ledger, &consumer);
+    assert_eq!((participant.wallet_balance, participant.reserved_balance), (1_000, 0));
+}
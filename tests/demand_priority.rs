@@ -0,0 +1,63 @@
+mod common;
+
+use common::Market;
+use energy_trading_program::{
+    client, delivery_slot_at, events::{CriticalLoadSet, TradeExecuted}, EnergyMarketError, MarketConfig, ParticipantType,
+    TimeInForce,
+};
+use solana_program::{instruction::Instruction, program_error::ProgramError, pubkey::Pubkey};
+
+fn post_demand_ix(market: &Market, consumer: Pubkey, energy_amount: u64, price_limit: i64, priority: u8) -> Instruction {
+    let slot = delivery_slot_at(market.bank.now);
+    client::post_demand_ix(
+        market.ledger, consumer, energy_amount, price_limit, 0, slot, TimeInForce::GoodTilCancelled, 0, false, 0, priority, None,
+    )
+}
+
+#[test]
+fn small_critical_demand_fills_before_a_huge_normal_one() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let factory = market.register(ParticipantType::Consumer, 100_000);
+    let hospital = market.register(ParticipantType::Consumer, 1_000);
+
+    market.bank.process(&client::set_critical_load_ix(market.ledger, market.admin, hospital, true)).unwrap();
+    assert_eq!(market.bank.events::<CriticalLoadSet>(), vec![CriticalLoadSet { participant: hospital, critical_load: true }]);
+
+    market.report_production(producer, 100, 10).unwrap();
+    // The factory bids higher and was first in the book, for far more than the offer holds
+    market.post_demand(factory, 1_000, 20).unwrap();
+    market.bank.process(&post_demand_ix(&market, hospital, 10, 10, 2)).unwrap();
+    market.match_orders(producer, &[producer, factory, hospital]).unwrap();
+
+    let trades: Vec<TradeExecuted> = market.bank.events();
+    let fills: Vec<_> = trades.iter().map(|t| (t.buyer, t.amount)).collect();
+    assert_eq!(fills, vec![(hospital, 10), (factory, 90)]);
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!(ledger.demands.iter().map(|d| (d.consumer_id, d.energy_amount)).collect::<Vec<_>>(), vec![(factory, 910)]);
+}
+
+#[test]
+fn unflagged_participants_cannot_claim_high_priority() {
+    let mut market = Market::new(MarketConfig { max_unflagged_priority: 1, ..MarketConfig::default() });
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+
+    market.bank.process(&post_demand_ix(&market, consumer, 10, 10, 1)).unwrap();
+    let error = market.bank.process(&post_demand_ix(&market, consumer, 10, 10, 2)).unwrap_err();
+    assert_eq!(error, ProgramError::Custom(EnergyMarketError::PriorityNotAllowed as u32));
+
+    market.bank.process(&client::set_critical_load_ix(market.ledger, market.admin, consumer, true)).unwrap();
+    market.bank.process(&post_demand_ix(&market, consumer, 10, 10, u8::MAX)).unwrap();
+
+    // Revoking the flag leaves the open demands alone but stops new critical ones
+    market.bank.process(&client::set_critical_load_ix(market.ledger, market.admin, consumer, false)).unwrap();
+    let error = market.bank.process(&post_demand_ix(&market, consumer, 10, 10, 2)).unwrap_err();
+    assert_eq!(error, ProgramError::Custom(EnergyMarketError::PriorityNotAllowed as u32));
+    let priorities: Vec<u8> = market.bank.ledger(&market.ledger).demands.iter().map(|d| d.priority).collect();
+    assert_eq!(priorities, vec![1, u8::MAX]);
+
+    // Only the admin grants the flag
+    let error = market.bank.process(&client::set_critical_load_ix(market.ledger, consumer, consumer, true)).unwrap_err();
+    assert_eq!(error, ProgramError::Custom(EnergyMarketError::Unauthorized as u32));
+    assert!(!market.bank.participant(&market.ledger, &consumer).critical_load);
+}
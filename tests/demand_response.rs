@@ -0,0 +1,134 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, events::{IncentivePaid, ReductionVolunteered}, DemandResponse, EnergyMarketError, MarketConfig, ParticipantType,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+// A market whose fee pool holds 100 lamports from one trade
+fn market_with_fees() -> Market {
+    let mut market = Market::new(MarketConfig { fee_bps: 1_000, ..MarketConfig::default() });
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 2_000);
+    market.report_production(producer, 100, 10).unwrap();
+    market.post_demand(consumer, 100, 10).unwrap();
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+    assert_eq!(market.bank.ledger(&market.ledger).protocol_fees, 100);
+    market
+}
+
+fn declare(market: &mut Market, authority: Pubkey, target_reduction_kwh: u64, incentive_price: u64) -> Result<(), ProgramError> {
+    let now = market.bank.now;
+    market.bank.process(&client::declare_demand_response_ix(
+        market.ledger, authority, now + 100, now + 200, target_reduction_kwh, incentive_price,
+    ))
+}
+
+fn volunteer(market: &mut Market, wallet: Pubkey, reduction_kwh: u64) -> Result<(), ProgramError> {
+    market.bank.process(&client::volunteer_reduction_ix(market.ledger, wallet, reduction_kwh))
+}
+
+fn demand_response(market: &Market) -> Option<DemandResponse> {
+    market.bank.ledger(&market.ledger).demand_response
+}
+
+#[test]
+fn volunteers_are_paid_from_the_fee_pool_once_the_window_closed() {
+    let mut market = market_with_fees();
+    let admin = market.admin;
+    let operator = market.bank.funded_wallet(1);
+    market.bank.process(&client::set_grid_operator_ix(market.ledger, market.admin, operator)).unwrap();
+    let school = market.register(ParticipantType::Consumer, 0);
+    let mall = market.register(ParticipantType::Prosumer, 0);
+    let late = market.register(ParticipantType::Consumer, 0);
+
+    assert_eq!(declare(&mut market, school, 50, 1).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    declare(&mut market, operator, 50, 1).unwrap();
+    assert_eq!(declare(&mut market, admin, 50, 1).unwrap_err(), custom(EnergyMarketError::DemandResponseActive));
+
+    volunteer(&mut market, school, 30).unwrap();
+    volunteer(&mut market, school, 10).unwrap();
+    assert_eq!(
+        market.bank.events::<ReductionVolunteered>(),
+        vec![ReductionVolunteered { event_id: 1, participant: school, reduction_kwh: 10, committed_reduction: 40 }],
+    );
+    volunteer(&mut market, mall, 10).unwrap();
+    assert_eq!(volunteer(&mut market, late, 1).unwrap_err(), custom(EnergyMarketError::ReductionTargetExceeded));
+    let event = demand_response(&market).unwrap();
+    assert_eq!((event.committed_kwh, event.volunteers, event.reserved_incentive), (50, 2, 50));
+    assert_eq!(market.bank.ledger(&market.ledger).protocol_fees, 50);
+
+    // Commitments close when the window opens and settlement waits for it to end
+    market.bank.now += 100;
+    assert_eq!(volunteer(&mut market, late, 1).unwrap_err(), custom(EnergyMarketError::VolunteeringClosed));
+    let settle_school = client::settle_demand_response_ix(market.ledger, late, &[school], None);
+    assert_eq!(market.bank.process(&settle_school).unwrap_err(), custom(EnergyMarketError::DemandResponseNotOver));
+    assert_eq!(market.bank.process(&client::unregister_participant_ix(market.ledger, school)).unwrap_err(),
+        custom(EnergyMarketError::PendingSettlement));
+
+    market.bank.now += 100;
+    market.bank.process(&settle_school).unwrap();
+    assert_eq!(
+        market.bank.events::<IncentivePaid>(),
+        vec![IncentivePaid { event_id: 1, participant: school, reduction_kwh: 40, amount: 40 }],
+    );
+    assert_eq!(market.bank.participant(&market.ledger, &school).wallet_balance, 40);
+    assert_eq!(demand_response(&market).unwrap().volunteers, 1);
+    assert_eq!(market.bank.process(&settle_school).unwrap_err(), custom(EnergyMarketError::NoDemandResponse));
+
+    market.bank.process(&client::settle_demand_response_ix(market.ledger, late, &[mall], None)).unwrap();
+    assert_eq!(market.bank.participant(&market.ledger, &mall).wallet_balance, 10);
+    assert!(demand_response(&market).is_none());
+    assert_eq!(market.bank.ledger(&market.ledger).protocol_fees, 50);
+
+    // With the last event settled the next one can be declared, and numbered after it
+    declare(&mut market, admin, 10, 1).unwrap();
+    assert_eq!(demand_response(&market).unwrap().event_id, 2);
+}
+
+#[test]
+fn commitments_the_fee_pool_cannot_fund_are_rejected() {
+    let mut market = market_with_fees();
+    let admin = market.admin;
+    let consumer = market.register(ParticipantType::Consumer, 0);
+    let producer = market.register(ParticipantType::Producer, 0);
+    declare(&mut market, admin, 1_000, 3).unwrap();
+
+    assert_eq!(volunteer(&mut market, producer, 10).unwrap_err(), custom(EnergyMarketError::InvalidParticipantType));
+    assert_eq!(volunteer(&mut market, consumer, 0).unwrap_err(), custom(EnergyMarketError::InvalidDemandResponse));
+    volunteer(&mut market, consumer, 30).unwrap();
+    assert_eq!(volunteer(&mut market, consumer, 4).unwrap_err(), custom(EnergyMarketError::IncentivePoolExhausted));
+    volunteer(&mut market, consumer, 3).unwrap();
+
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.protocol_fees, ledger.demand_response.unwrap().reserved_incentive), (1, 99));
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).committed_reduction, 33);
+}
+
+#[test]
+fn oracle_settles_with_metered_reductions() {
+    let mut market = market_with_fees();
+    let admin = market.admin;
+    let oracle = market.bank.funded_wallet(1);
+    let school = market.register(ParticipantType::Consumer, 0);
+    let mall = market.register(ParticipantType::Consumer, 0);
+    declare(&mut market, admin, 50, 1).unwrap();
+    volunteer(&mut market, school, 30).unwrap();
+    volunteer(&mut market, mall, 20).unwrap();
+    market.bank.process(&client::set_oracle_ix(market.ledger, market.admin, oracle)).unwrap();
+    market.bank.now += 200;
+
+    let unmetered = client::settle_demand_response_ix(market.ledger, school, &[school, mall], None);
+    assert_eq!(market.bank.process(&unmetered).unwrap_err(), custom(EnergyMarketError::InvalidOracle));
+    let short = client::settle_demand_response_ix(market.ledger, oracle, &[school, mall], Some(vec![10]));
+    assert_eq!(market.bank.process(&short).unwrap_err(), ProgramError::InvalidArgument);
+
+    // The school only cut 10 of its 30 kWh; the mall cut more than it promised and is paid its commitment
+    market.bank.process(&client::settle_demand_response_ix(market.ledger, oracle, &[school, mall], Some(vec![10, 50]))).unwrap();
+    let paid: Vec<_> = market.bank.events::<IncentivePaid>().into_iter().map(|p| (p.participant, p.amount)).collect();
+    assert_eq!(paid, vec![(school, 10), (mall, 20)]);
+    let ledger = market.bank.ledger(&market.ledger);
+    assert!(ledger.demand_response.is_none());
+    assert_eq!(ledger.protocol_fees, 100 - 50 + 20);
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{client, EnergyMarketError, MarketConfig, ParticipantType};
+
+// Deposits must be at least 50, and a consumer needs a free balance of 100 to post a demand
+fn limited_market() -> Market {
+    Market::builder(MarketConfig::default())
+        .admin_instruction(|ledger, admin| client::set_deposit_limits_ix(ledger, admin, 50, 100))
+        .build()
+}
+
+#[test]
+fn deposits_below_the_minimum_are_rejected() {
+    let mut market = limited_market();
+    let consumer = market.register(ParticipantType::Consumer, 0);
+    let deposit = |amount| client::deposit_ix(market.ledger, consumer, amount, None);
+
+    assert_eq!(market.bank.process(&deposit(49)).unwrap_err(), custom(EnergyMarketError::DepositTooSmall));
+    assert_eq!(market.bank.process(&deposit(1)).unwrap_err(), custom(EnergyMarketError::DepositTooSmall));
+    market.bank.process(&deposit(50)).unwrap();
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).wallet_balance, 50);
+}
+
+#[test]
+fn demands_need_the_posting_balance() {
+    let mut market = limited_market();
+    let consumer = market.register(ParticipantType::Consumer, 99);
+    assert_eq!(market.post_demand(consumer, 1, 1).unwrap_err(), custom(EnergyMarketError::BalanceTooLowToPost));
+    assert!(market.bank.ledger(&market.ledger).demands.is_empty());
+
+    // The threshold applies to the free balance before the new demand's escrow
+    market.bank.process(&client::deposit_ix(market.ledger, consumer, 51, None)).unwrap();
+    market.post_demand(consumer, 5, 10).unwrap();
+    market.post_demand(consumer, 10, 10).unwrap();
+    assert_eq!(market.post_demand(consumer, 1, 1).unwrap_err(), custom(EnergyMarketError::BalanceTooLowToPost));
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).wallet_balance, 0);
+
+    // Offers are not held to it
+    let producer = market.register(ParticipantType::Producer, 0);
+    market.report_production(producer, 10, 10).unwrap();
+}
+
+#[test]
+fn zero_limits_disable_both_checks_and_only_the_admin_sets_them() {
+    let mut market = limited_market();
+    let consumer = market.register(ParticipantType::Consumer, 0);
+    let by_consumer = client::set_deposit_limits_ix(market.ledger, consumer, 0, 0);
+    assert_eq!(market.bank.process(&by_consumer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+
+    market.bank.process(&client::set_deposit_limits_ix(market.ledger, market.admin, 0, 0)).unwrap();
+    let config = market.bank.ledger(&market.ledger).config;
+    assert_eq!((config.min_deposit, config.min_balance_to_post_demand), (0, 0));
+    market.bank.process(&client::deposit_ix(market.ledger, consumer, 1, None)).unwrap();
+    market.post_demand(consumer, 1, 1).unwrap();
+}
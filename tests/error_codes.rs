@@ -0,0 +1,43 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, EnergyMarketError, LedgerCapacity, MarketConfig, OrderStorage, ParticipantMetadata, ParticipantType,
+};
+use solana_program::program_error::ProgramError;
+
+// Clients map these numbers to messages, so they must never change
+#[test]
+fn error_codes_are_stable() {
+    let codes = [
+        (EnergyMarketError::ParticipantAlreadyRegistered, 0),
+        (EnergyMarketError::ParticipantNotRegistered, 1),
+        (EnergyMarketError::LedgerNotInitialized, 2),
+        (EnergyMarketError::InsufficientBalance, 3),
+        (EnergyMarketError::OrderNotFound, 4),
+        (EnergyMarketError::InvalidParticipantType, 5),
+        (EnergyMarketError::LedgerFull, 6),
+    ];
+    for (error, code) in codes {
+        assert_eq!(ProgramError::from(error), ProgramError::Custom(code));
+    }
+}
+
+#[test]
+fn representative_failures_return_their_codes() {
+    let capacity = LedgerCapacity { max_participants: 2, max_open_orders: 8, max_transactions: 8 };
+    let mut market = Market::builder(MarketConfig::default()).capacity(capacity).build();
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 100);
+
+    let stranger = market.bank.funded_wallet(1);
+    assert_eq!(market.post_demand(stranger, 10, 1).unwrap_err(), custom(EnergyMarketError::ParticipantNotRegistered));
+    let overdrawn = client::withdraw_ix(market.ledger, consumer, consumer, 101, None);
+    assert_eq!(market.bank.process(&overdrawn).unwrap_err(), custom(EnergyMarketError::InsufficientBalance));
+    let cancel = client::cancel_demand_ix(market.ledger, consumer, 7, OrderStorage::Ledger);
+    assert_eq!(market.bank.process(&cancel).unwrap_err(), custom(EnergyMarketError::OrderNotFound));
+    assert_eq!(market.post_demand(producer, 10, 1).unwrap_err(), custom(EnergyMarketError::InvalidParticipantType));
+
+    let late = client::register_participant_ix(market.ledger, stranger, ParticipantType::Consumer, 0, 0, ParticipantMetadata::default());
+    assert_eq!(market.bank.process(&late).unwrap_err(), custom(EnergyMarketError::LedgerFull));
+}
@@ -0,0 +1,40 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{client, EnergyMarketError, MarketConfig, OrderStorage, ParticipantType};
+
+#[test]
+fn demand_must_be_covered_when_it_is_posted() {
+    let mut market = Market::new(MarketConfig::default());
+    let consumer = market.register(ParticipantType::Consumer, 999);
+    assert_eq!(market.post_demand(consumer, 100, 10).unwrap_err(), custom(EnergyMarketError::InsufficientBalance));
+    assert!(market.bank.ledger(&market.ledger).demands.is_empty());
+
+    // Escrow is taken out of the spendable balance, so it can neither back a second demand nor be withdrawn
+    market.post_demand(consumer, 90, 10).unwrap();
+    let participant = market.bank.participant(&market.ledger, &consumer);
+    assert_eq!((participant.wallet_balance, participant.reserved_balance), (99, 900));
+    assert_eq!(market.post_demand(consumer, 10, 10).unwrap_err(), custom(EnergyMarketError::InsufficientBalance));
+    let withdraw = client::withdraw_ix(market.ledger, consumer, consumer, 100, None);
+    assert_eq!(market.bank.process(&withdraw).unwrap_err(), custom(EnergyMarketError::InsufficientBalance));
+}
+
+#[test]
+fn unspent_escrow_is_released_on_a_cheaper_fill_and_on_cancellation() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.post_demand(consumer, 100, 10).unwrap();
+    let order_id = market.bank.ledger(&market.ledger).demands[0].order_id;
+
+    // 60 kWh at 7 cost 420 of the 600 reserved for them; the 180 saved goes back to the wallet
+    market.report_production(producer, 60, 7).unwrap();
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+    let participant = market.bank.participant(&market.ledger, &consumer);
+    assert_eq!((participant.wallet_balance, participant.reserved_balance), (180, 400));
+
+    market.bank.process(&client::cancel_demand_ix(market.ledger, consumer, order_id, OrderStorage::Ledger)).unwrap();
+    let participant = market.bank.participant(&market.ledger, &consumer);
+    assert_eq!((participant.wallet_balance, participant.reserved_balance), (1_000 - 420, 0));
+    assert_eq!(market.bank.participant(&market.ledger, &producer).wallet_balance, 420);
+}
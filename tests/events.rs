@@ -0,0 +1,79 @@
+mod common;
+
+use common::Market;
+use energy_trading_program::{
+    client,
+    events::{DepositMade, Event, OrderCancelled, ParticipantRegistered, TradeExecuted, WithdrawalMade},
+    MarketConfig, OrderSide, OrderStorage, ParticipantMetadata, ParticipantType,
+};
+
+#[test]
+fn balance_and_order_handlers_emit_their_events() {
+    let mut market = Market::new(MarketConfig::default());
+    let wallet = market.bank.funded_wallet(1);
+    let register = client::register_participant_ix(market.ledger, wallet, ParticipantType::Consumer, 0, 0, ParticipantMetadata::default());
+    market.bank.process(&register).unwrap();
+    let registered = market.bank.events::<ParticipantRegistered>();
+    assert_eq!(registered.len(), 1);
+    assert_eq!((registered[0].participant, registered[0].zone), (wallet, 0));
+    assert_eq!(registered[0].participant_type, ParticipantType::Consumer);
+
+    market.bank.process(&client::deposit_ix(market.ledger, wallet, 700, None)).unwrap();
+    let deposits = market.bank.events::<DepositMade>();
+    assert_eq!(deposits.len(), 1);
+    assert_eq!((deposits[0].participant, deposits[0].amount, deposits[0].wallet_balance), (wallet, 700, 700));
+
+    market.post_demand(wallet, 20, 10).unwrap();
+    let order_id = market.bank.ledger(&market.ledger).demands[0].order_id;
+    market.bank.process(&client::cancel_demand_ix(market.ledger, wallet, order_id, OrderStorage::Ledger)).unwrap();
+    assert_eq!(
+        market.bank.events::<OrderCancelled>(),
+        vec![OrderCancelled { order_id, owner: wallet, side: OrderSide::Demand, remaining_amount: 20 }],
+    );
+
+    let destination = market.bank.funded_wallet(1);
+    market.bank.process(&client::withdraw_ix(market.ledger, wallet, destination, 300, None)).unwrap();
+    assert_eq!(
+        market.bank.events::<WithdrawalMade>(),
+        vec![WithdrawalMade { participant: wallet, destination, amount: 300, wallet_balance: 400 }],
+    );
+}
+
+#[test]
+fn each_fill_logs_one_trade_event_only_its_own_type_decodes() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producer, 30, 8).unwrap();
+    market.post_demand(consumer, 30, 10).unwrap();
+    let ledger = market.bank.ledger(&market.ledger);
+    let (production_order_id, demand_order_id) = (ledger.productions[0].order_id, ledger.demands[0].order_id);
+
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+    let trade_id = market.bank.ledger(&market.ledger).transactions[0].trade_id;
+    assert_eq!(
+        market.bank.events::<TradeExecuted>(),
+        vec![TradeExecuted {
+            trade_id,
+            demand_order_id,
+            production_order_id,
+            buyer: consumer,
+            seller: producer,
+            amount: 30,
+            price: 8,
+            timestamp: market.bank.now,
+        }],
+    );
+    assert!(market.bank.events::<DepositMade>().is_empty() && market.bank.events::<OrderCancelled>().is_empty());
+
+    let discriminators = [
+        TradeExecuted::DISCRIMINATOR,
+        ParticipantRegistered::DISCRIMINATOR,
+        DepositMade::DISCRIMINATOR,
+        WithdrawalMade::DISCRIMINATOR,
+        OrderCancelled::DISCRIMINATOR,
+    ];
+    for (index, discriminator) in discriminators.iter().enumerate() {
+        assert!(!discriminators[index + 1..].contains(discriminator), "discriminator {} is reused", index);
+    }
+}
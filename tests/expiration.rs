@@ -0,0 +1,64 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, delivery_slot_at, events::TradeExecuted, EnergyMarketError, EnergySource, MarketConfig, ParticipantType, TimeInForce,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+const VALIDITY: i64 = 60;
+
+fn offer_until(market: &mut Market, producer: Pubkey, energy_amount: u64, price: i64, expires_at: i64) -> Result<(), ProgramError> {
+    let slot = delivery_slot_at(market.bank.now) + 1;
+    market.bank.process(&client::report_production_ix(
+        market.ledger, producer, energy_amount, price, expires_at, slot, EnergySource::Solar, TimeInForce::GoodTilCancelled, 0, false, 0,
+        false, None,
+    ))
+}
+
+fn demand_until(market: &mut Market, consumer: Pubkey, energy_amount: u64, price_limit: i64, expires_at: i64) -> Result<(), ProgramError> {
+    let slot = delivery_slot_at(market.bank.now) + 1;
+    market.bank.process(&client::post_demand_ix(
+        market.ledger, consumer, energy_amount, price_limit, expires_at, slot, TimeInForce::GoodTilCancelled, 0, false, 0, 0, None,
+    ))
+}
+
+#[test]
+fn expired_orders_never_match_and_leave_the_book() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    let (expired, expires_at) = (market.bank.now - 1, market.bank.now + VALIDITY);
+    assert_eq!(offer_until(&mut market, producer, 50, 10, expired).unwrap_err(), custom(EnergyMarketError::InvalidExpiration));
+    offer_until(&mut market, producer, 50, 10, expires_at).unwrap();
+    demand_until(&mut market, consumer, 50, 10, 0).unwrap();
+
+    // The offer is still valid at its expiry and gone a second later; the demand that never expires rests on
+    market.bank.now = expires_at + 1;
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+    assert!(market.bank.events::<TradeExecuted>().is_empty());
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.productions.len(), ledger.demands.len(), ledger.total_trades), (0, 1, 0));
+    assert_eq!(market.bank.participant(&market.ledger, &producer).open_productions, 0);
+}
+
+#[test]
+fn anyone_can_prune_expired_orders_to_release_their_escrow() {
+    let mut market = Market::new(MarketConfig::default());
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    let expires_at = market.bank.now + VALIDITY;
+    demand_until(&mut market, consumer, 50, 10, expires_at).unwrap();
+    demand_until(&mut market, consumer, 20, 10, 0).unwrap();
+
+    let prune = client::prune_expired_orders_ix(market.ledger, client::participant_metas(market.ledger, &[consumer]));
+    market.bank.now = expires_at;
+    market.bank.process(&prune).unwrap();
+    assert_eq!(market.bank.ledger(&market.ledger).demands.len(), 2);
+
+    market.bank.now += 1;
+    market.bank.process(&prune).unwrap();
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.demands.len(), ledger.demands[0].energy_amount), (1, 20));
+    let participant = market.bank.participant(&market.ledger, &consumer);
+    assert_eq!((participant.wallet_balance, participant.reserved_balance, participant.open_demands), (800, 200, 1));
+}
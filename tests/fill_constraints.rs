@@ -0,0 +1,87 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, delivery_slot_at, events::TradeExecuted, EnergyMarketError, EnergySource, MarketConfig, ParticipantType,
+    TimeInForce,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+fn offer(market: &mut Market, producer: Pubkey, amount: u64, price: i64, min_fill: u64, all_or_nothing: bool) -> Result<(), ProgramError> {
+    let slot = delivery_slot_at(market.bank.now);
+    market.bank.process(&client::report_production_ix(
+        market.ledger, producer, amount, price, 0, slot, EnergySource::Solar, TimeInForce::GoodTilCancelled, min_fill,
+        all_or_nothing, 0, false, None,
+    ))
+}
+
+fn demand(market: &mut Market, consumer: Pubkey, amount: u64, min_fill: u64, all_or_nothing: bool) -> Result<(), ProgramError> {
+    let slot = delivery_slot_at(market.bank.now);
+    market.bank.process(&client::post_demand_ix(
+        market.ledger, consumer, amount, 10, 0, slot, TimeInForce::GoodTilCancelled, min_fill, all_or_nothing, 0, 0, None,
+    ))
+}
+
+fn fills(market: &Market) -> Vec<(u64, u64)> {
+    market.bank.events::<TradeExecuted>().iter().map(|t| (t.production_order_id, t.amount)).collect()
+}
+
+#[test]
+fn all_or_nothing_demand_waits_for_an_offer_that_covers_it() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    offer(&mut market, producer, 30, 8, 0, false).unwrap();
+    offer(&mut market, producer, 30, 9, 0, false).unwrap();
+    demand(&mut market, consumer, 50, 0, true).unwrap();
+
+    // 60 kWh are on offer, but no single offer can take the whole 50 in one fill
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    assert!(fills(&market).is_empty());
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).reserved_balance, 500);
+
+    offer(&mut market, producer, 60, 10, 0, false).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    assert_eq!(fills(&market), vec![(3, 50)]);
+    let participant = market.bank.participant(&market.ledger, &consumer);
+    assert_eq!((participant.wallet_balance, participant.reserved_balance), (500, 0));
+}
+
+#[test]
+fn all_or_nothing_offer_is_not_split_across_demands() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumers = [market.register(ParticipantType::Consumer, 1_000), market.register(ParticipantType::Consumer, 1_000)];
+    offer(&mut market, producer, 50, 10, 0, true).unwrap();
+    demand(&mut market, consumers[0], 30, 0, false).unwrap();
+    demand(&mut market, consumers[1], 30, 0, false).unwrap();
+    market.match_orders(producer, &[producer, consumers[0], consumers[1]]).unwrap();
+    assert!(fills(&market).is_empty());
+    assert_eq!(market.bank.ledger(&market.ledger).productions[0].energy_amount, 50);
+}
+
+#[test]
+fn min_fill_keeps_dust_fills_out_and_lets_the_remainder_complete() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 10_000);
+    assert_eq!(offer(&mut market, producer, 10, 10, 11, false).unwrap_err(), custom(EnergyMarketError::InvalidMinFill));
+    offer(&mut market, producer, 40, 10, 20, false).unwrap();
+
+    demand(&mut market, consumer, 5, 0, false).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    assert!(fills(&market).is_empty());
+
+    // A fill of 25 is large enough. The 15 left is below the minimum and may still complete the
+    // offer, but only in a single fill, which the dust demand cannot take either
+    demand(&mut market, consumer, 25, 0, false).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    assert_eq!(fills(&market), vec![(0, 25)]);
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    assert!(fills(&market).is_empty());
+    demand(&mut market, consumer, 15, 0, false).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    assert_eq!(fills(&market), vec![(0, 15)]);
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.productions.len(), ledger.demands[0].energy_amount), (0, 5));
+}
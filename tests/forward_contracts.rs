@@ -0,0 +1,197 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, delivery_slot_at, delivery_slot_end, events::{ForwardCancelled, ForwardSettled}, find_forward_contract_address,
+    slot_price, EnergyMarketError, MarketConfig, OrderSide, ParticipantType,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+// A forward for 100 kWh with 20% margin, so 200 lamports a side at a price of 10
+fn create(market: &mut Market, maker: Pubkey, counterparty: Option<Pubkey>, side: OrderSide, price: i64, slot: u32) -> Result<u64, ProgramError> {
+    let contract_id = market.bank.ledger(&market.ledger).next_order_id;
+    market.bank.process(&client::create_forward_ix(market.ledger, maker, counterparty, side, 100, price, slot, 2_000, contract_id))?;
+    Ok(contract_id)
+}
+
+fn settle(market: &mut Market, maker: Pubkey, taker: Option<Pubkey>, contract_id: u64) -> Result<(), ProgramError> {
+    let cranker = market.bank.funded_wallet(1);
+    market.bank.process(&client::settle_forward_ix(market.ledger, cranker, maker, taker, contract_id))
+}
+
+// Trades 10 kWh at `price` between two fresh participants, for the slot the clock is in
+fn trade_spot(market: &mut Market, price: i64) {
+    let slot = delivery_slot_at(market.bank.now);
+    trade_for(market, slot, 10, price);
+}
+
+fn trade_for(market: &mut Market, slot: u32, amount: u64, price: i64) {
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, amount * price as u64);
+    market.report_production_for(producer, amount, price, slot).unwrap();
+    market.post_demand_for(consumer, amount, price, slot).unwrap();
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+}
+
+fn balances(market: &Market, wallet: Pubkey) -> (u64, u64, u32) {
+    let participant = market.bank.participant(&market.ledger, &wallet);
+    (participant.wallet_balance, participant.reserved_balance, participant.open_forwards)
+}
+
+#[test]
+fn forwards_net_against_the_delivery_slot_price() {
+    let mut market = Market::new(MarketConfig::default());
+    let buyer = market.register(ParticipantType::Consumer, 1_000);
+    let seller = market.register(ParticipantType::Producer, 1_000);
+    let outsider = market.register(ParticipantType::Producer, 1_000);
+    let slot = delivery_slot_at(market.bank.now) + 1;
+
+    assert_eq!(
+        create(&mut market, buyer, None, OrderSide::Demand, 10, slot - 1).unwrap_err(),
+        custom(EnergyMarketError::InvalidDeliverySlot),
+    );
+    // The buyer locks in 10 with the seller and, on an open contract, 12 with whoever takes it
+    let cheap = create(&mut market, buyer, Some(seller), OrderSide::Demand, 10, slot).unwrap();
+    let dear = create(&mut market, buyer, None, OrderSide::Demand, 12, slot).unwrap();
+    assert_eq!(balances(&market, buyer), (1_000 - 200 - 240, 440, 2));
+
+    let take_cheap = client::accept_forward_ix(market.ledger, outsider, cheap);
+    assert_eq!(market.bank.process(&take_cheap).unwrap_err(), custom(EnergyMarketError::NotOfferCounterparty));
+    market.bank.process(&client::accept_forward_ix(market.ledger, seller, cheap)).unwrap();
+    market.bank.process(&client::accept_forward_ix(market.ledger, seller, dear)).unwrap();
+    assert_eq!(
+        market.bank.process(&client::accept_forward_ix(market.ledger, outsider, dear)).unwrap_err(),
+        custom(EnergyMarketError::ForwardClosed),
+    );
+    assert_eq!(balances(&market, seller), (1_000 - 440, 440, 2));
+
+    // The slot opens and trades at 11; the contracts only settle once it has ended
+    market.bank.now = delivery_slot_end(slot) - 1_800;
+    trade_spot(&mut market, 11);
+    assert_eq!(settle(&mut market, buyer, Some(seller), cheap).unwrap_err(), custom(EnergyMarketError::ForwardNotDue));
+    market.bank.now = delivery_slot_end(slot);
+
+    // In the money for the buyer at 10, out of the money at 12
+    settle(&mut market, buyer, Some(seller), cheap).unwrap();
+    assert_eq!(market.bank.events::<ForwardSettled>(), vec![ForwardSettled {
+        contract_id: cheap,
+        settlement_price: 11,
+        payer: seller,
+        payee: buyer,
+        amount: 100,
+        liquidated: false,
+    }]);
+    settle(&mut market, buyer, Some(seller), dear).unwrap();
+    assert_eq!(market.bank.events::<ForwardSettled>(), vec![ForwardSettled {
+        contract_id: dear,
+        settlement_price: 11,
+        payer: buyer,
+        payee: seller,
+        amount: 100,
+        liquidated: false,
+    }]);
+
+    assert_eq!(balances(&market, buyer), (1_000, 0, 0));
+    assert_eq!(balances(&market, seller), (1_000, 0, 0));
+    let (contract, _) = find_forward_contract_address(&energy_trading_program::id(), &market.ledger, cheap);
+    assert_eq!(market.bank.lamports(&contract), 0);
+}
+
+#[test]
+fn exhausted_margin_is_liquidated_to_the_counterparty() {
+    let mut market = Market::new(MarketConfig::default());
+    let buyer = market.register(ParticipantType::Consumer, 1_000);
+    let seller = market.register(ParticipantType::Producer, 1_000);
+    let slot = delivery_slot_at(market.bank.now) + 2;
+
+    // The seller offers both contracts; the buyer takes them
+    let liquidated = create(&mut market, seller, None, OrderSide::Production, 10, slot).unwrap();
+    let capped = create(&mut market, seller, None, OrderSide::Production, 10, slot).unwrap();
+    market.bank.process(&client::accept_forward_ix(market.ledger, buyer, liquidated)).unwrap();
+    market.bank.process(&client::accept_forward_ix(market.ledger, buyer, capped)).unwrap();
+
+    // A spot trade at 11 leaves the seller's loss within its margin
+    trade_spot(&mut market, 11);
+    assert_eq!(settle(&mut market, seller, Some(buyer), liquidated).unwrap_err(), custom(EnergyMarketError::ForwardNotDue));
+
+    // At 13 the seller's loss of 300 passes its margin of 200 well before delivery
+    trade_spot(&mut market, 13);
+    settle(&mut market, seller, Some(buyer), liquidated).unwrap();
+    assert_eq!(market.bank.events::<ForwardSettled>(), vec![ForwardSettled {
+        contract_id: liquidated,
+        settlement_price: 13,
+        payer: seller,
+        payee: buyer,
+        amount: 200,
+        liquidated: true,
+    }]);
+
+    // Held to delivery, the slot clears at 20 and the seller's payment stops at its margin
+    market.bank.now = delivery_slot_end(slot) - 1_800;
+    trade_spot(&mut market, 20);
+    market.bank.now = delivery_slot_end(slot);
+    settle(&mut market, seller, Some(buyer), capped).unwrap();
+    let settled = market.bank.events::<ForwardSettled>();
+    assert_eq!((settled[0].settlement_price, settled[0].amount, settled[0].liquidated), (20, 200, false));
+
+    assert_eq!(balances(&market, buyer), (1_400, 0, 0));
+    assert_eq!(balances(&market, seller), (600, 0, 0));
+}
+
+#[test]
+fn slot_price_accumulates_across_match_runs() {
+    let mut market = Market::new(MarketConfig::default());
+    let buyer = market.register(ParticipantType::Consumer, 1_000);
+    let seller = market.register(ParticipantType::Producer, 1_000);
+    let slot = delivery_slot_at(market.bank.now) + 1;
+    let contract = create(&mut market, seller, None, OrderSide::Production, 10, slot).unwrap();
+    market.bank.process(&client::accept_forward_ix(market.ledger, buyer, contract)).unwrap();
+
+    // The slot trades ahead at 10; the spot market rising to 13 does not liquidate a contract
+    // its own slot still prices at 10
+    trade_for(&mut market, slot, 100, 10);
+    trade_spot(&mut market, 13);
+    assert_eq!(market.bank.ledger(&market.ledger).reference_price, 13);
+    assert_eq!(settle(&mut market, seller, Some(buyer), contract).unwrap_err(), custom(EnergyMarketError::ForwardNotDue));
+
+    // A tiny late run at 40 moves the slot's VWAP to (1_000 + 40) / 101, not to 40
+    market.bank.now = delivery_slot_end(slot) - 1_800;
+    trade_for(&mut market, slot, 1, 40);
+    assert_eq!(slot_price(&market.bank.ledger(&market.ledger), slot), Some(10));
+    market.bank.now = delivery_slot_end(slot);
+    settle(&mut market, seller, Some(buyer), contract).unwrap();
+    let settled = market.bank.events::<ForwardSettled>();
+    assert_eq!((settled[0].settlement_price, settled[0].amount, settled[0].liquidated), (10, 0, false));
+}
+
+#[test]
+fn untaken_and_unpriced_forwards_refund_their_margin() {
+    let mut market = Market::new(MarketConfig::default());
+    let buyer = market.register(ParticipantType::Consumer, 1_000);
+    let seller = market.register(ParticipantType::Producer, 1_000);
+    let slot = delivery_slot_at(market.bank.now) + 1;
+
+    assert_eq!(
+        market.bank.process(&client::create_forward_ix(market.ledger, buyer, None, OrderSide::Demand, 100, 10, slot, 0, 0)).unwrap_err(),
+        custom(EnergyMarketError::InvalidMargin),
+    );
+    let untaken = create(&mut market, buyer, None, OrderSide::Demand, 10, slot).unwrap();
+    let unpriced = create(&mut market, buyer, None, OrderSide::Demand, 10, slot).unwrap();
+    market.bank.process(&client::accept_forward_ix(market.ledger, seller, unpriced)).unwrap();
+    assert_eq!(
+        market.bank.process(&client::unregister_participant_ix(market.ledger, seller)).unwrap_err(),
+        custom(EnergyMarketError::ParticipantHasBalance),
+    );
+
+    // Only the maker withdraws an untaken contract before its slot begins
+    assert_eq!(settle(&mut market, buyer, None, untaken).unwrap_err(), custom(EnergyMarketError::ForwardNotDue));
+    market.bank.process(&client::settle_forward_ix(market.ledger, buyer, buyer, None, untaken)).unwrap();
+    assert_eq!(market.bank.events::<ForwardCancelled>(), vec![ForwardCancelled { contract_id: untaken }]);
+
+    // Nothing traded in the slot, so the taken contract is voided
+    market.bank.now = delivery_slot_end(slot);
+    settle(&mut market, buyer, Some(seller), unpriced).unwrap();
+    assert_eq!(market.bank.events::<ForwardCancelled>(), vec![ForwardCancelled { contract_id: unpriced }]);
+    assert_eq!(balances(&market, buyer), (1_000, 0, 0));
+    assert_eq!(balances(&market, seller), (1_000, 0, 0));
+}
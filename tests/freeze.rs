@@ -0,0 +1,49 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{client, events::ParticipantFrozen, EnergyMarketError, MarketConfig, ParticipantType};
+
+#[test]
+fn frozen_producer_offers_no_longer_match() {
+    let mut market = Market::new(MarketConfig::default());
+    let meter = market.register(ParticipantType::Producer, 0);
+    let honest = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(meter, 10, 5).unwrap();
+    market.report_production(honest, 10, 8).unwrap();
+
+    let by_consumer = client::freeze_participant_ix(market.ledger, consumer, meter, &[]);
+    assert_eq!(market.bank.process(&by_consumer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    market.bank.process(&client::freeze_participant_ix(market.ledger, market.admin, meter, &[])).unwrap();
+    assert_eq!(market.bank.events::<ParticipantFrozen>(), vec![ParticipantFrozen { participant: meter, frozen: true }]);
+
+    // The cheaper offer of the frozen meter left the book with the freeze, so the demand fills at 8
+    market.post_demand(consumer, 10, 10).unwrap();
+    market.match_orders(consumer, &[meter, honest, consumer]).unwrap();
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.transactions.len(), ledger.transactions[0].to, ledger.transactions[0].price), (1, honest, 8));
+    assert!(ledger.productions.is_empty());
+    assert_eq!(market.bank.participant(&market.ledger, &meter).wallet_balance, 0);
+}
+
+#[test]
+fn frozen_participant_may_only_deposit_until_unfrozen() {
+    let mut market = Market::new(MarketConfig::default());
+    let prosumer = market.register(ParticipantType::Prosumer, 1_000);
+    market.post_demand(prosumer, 10, 10).unwrap();
+    market.bank.process(&client::freeze_participant_ix(market.ledger, market.admin, prosumer, &[])).unwrap();
+    let participant = market.bank.participant(&market.ledger, &prosumer);
+    assert_eq!((participant.frozen, participant.wallet_balance, participant.reserved_balance), (true, 1_000, 0));
+
+    let frozen = custom(EnergyMarketError::ParticipantFrozen);
+    assert_eq!(market.report_production(prosumer, 10, 10).unwrap_err(), frozen);
+    assert_eq!(market.post_demand(prosumer, 10, 10).unwrap_err(), frozen);
+    let withdraw = client::withdraw_ix(market.ledger, prosumer, prosumer, 1, None);
+    assert_eq!(market.bank.process(&withdraw).unwrap_err(), frozen);
+    market.bank.process(&client::deposit_ix(market.ledger, prosumer, 100, None)).unwrap();
+
+    market.bank.process(&client::unfreeze_participant_ix(market.ledger, market.admin, prosumer)).unwrap();
+    assert_eq!(market.bank.events::<ParticipantFrozen>(), vec![ParticipantFrozen { participant: prosumer, frozen: false }]);
+    market.bank.process(&withdraw).unwrap();
+    market.post_demand(prosumer, 10, 10).unwrap();
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, events::ProposalResolved, ConfigUpdate, EnergyMarketError, GovernanceConfig, MarketConfig, ParticipantType,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+const VOTING_PERIOD: i64 = 3_600;
+
+// Proposing takes a balance of 100; a proposal needs 1_000 of votes with two thirds in favour
+fn governed_market() -> Market {
+    let governance = GovernanceConfig { proposal_threshold: 100, quorum: 1_000, majority_bps: 6_667 };
+    Market::builder(MarketConfig { fee_bps: 100, ..MarketConfig::default() })
+        .admin_instruction(move |ledger, admin| client::set_governance_config_ix(ledger, admin, governance))
+        .build()
+}
+
+fn propose(market: &mut Market, proposer: Pubkey, update: ConfigUpdate) -> Result<(), ProgramError> {
+    let voting_deadline = market.bank.now + VOTING_PERIOD;
+    market.bank.process(&client::create_proposal_ix(market.ledger, proposer, update, voting_deadline))
+}
+
+fn vote(market: &mut Market, voter: Pubkey, proposal_id: u64, support: bool) -> Result<(), ProgramError> {
+    market.bank.process(&client::cast_vote_ix(market.ledger, voter, proposal_id, support))
+}
+
+fn execute(market: &mut Market) -> Result<ProposalResolved, ProgramError> {
+    market.bank.process(&client::execute_proposal_ix(market.ledger))?;
+    Ok(market.bank.events::<ProposalResolved>().remove(0))
+}
+
+#[test]
+fn passing_proposal_applies_its_change() {
+    let mut market = governed_market();
+    let proposer = market.register(ParticipantType::Producer, 700);
+    let member = market.register(ParticipantType::Consumer, 300);
+    propose(&mut market, proposer, ConfigUpdate::FeeBps(250)).unwrap();
+    vote(&mut market, proposer, 0, true).unwrap();
+    vote(&mut market, member, 0, false).unwrap();
+
+    market.bank.now += VOTING_PERIOD - 1;
+    assert_eq!(execute(&mut market).unwrap_err(), custom(EnergyMarketError::VotingNotOver));
+    market.bank.now += 1;
+    let latecomer = market.register(ParticipantType::Consumer, 5_000);
+    assert_eq!(vote(&mut market, latecomer, 0, false).unwrap_err(), custom(EnergyMarketError::VotingClosed));
+
+    // 700 of the 1_000 cast is above two thirds
+    let resolved = execute(&mut market).unwrap();
+    assert_eq!((resolved.votes_for, resolved.votes_against, resolved.passed), (700, 300, true));
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.config.fee_bps, ledger.proposal), (250, None));
+    assert_eq!(execute(&mut market).unwrap_err(), custom(EnergyMarketError::ProposalNotFound));
+}
+
+#[test]
+fn proposal_short_of_quorum_or_majority_fails_without_effect() {
+    let mut market = governed_market();
+    let proposer = market.register(ParticipantType::Producer, 700);
+    propose(&mut market, proposer, ConfigUpdate::FeeBps(250)).unwrap();
+    vote(&mut market, proposer, 0, true).unwrap();
+    market.bank.now += VOTING_PERIOD;
+    let resolved = execute(&mut market).unwrap();
+    assert_eq!((resolved.votes_for, resolved.votes_against, resolved.passed), (700, 0, false));
+    assert_eq!(market.bank.ledger(&market.ledger).config.fee_bps, 100);
+
+    // The failed proposal is closed, so the next one can be put to a vote; 700 of 1_200 is quorate
+    // but not two thirds
+    let member = market.register(ParticipantType::Consumer, 500);
+    propose(&mut market, proposer, ConfigUpdate::FeeBps(250)).unwrap();
+    vote(&mut market, proposer, 1, true).unwrap();
+    vote(&mut market, member, 1, false).unwrap();
+    market.bank.now += VOTING_PERIOD;
+    let resolved = execute(&mut market).unwrap();
+    assert_eq!((resolved.proposal_id, resolved.passed), (1, false));
+    assert_eq!(market.bank.ledger(&market.ledger).config.fee_bps, 100);
+}
+
+#[test]
+fn each_participant_votes_once_on_whitelisted_parameters() {
+    let mut market = governed_market();
+    let proposer = market.register(ParticipantType::Producer, 700);
+    let small = market.register(ParticipantType::Consumer, 99);
+
+    assert_eq!(propose(&mut market, small, ConfigUpdate::FeeBps(250)).unwrap_err(), custom(EnergyMarketError::BelowProposalThreshold));
+    assert_eq!(
+        propose(&mut market, proposer, ConfigUpdate::ConfigTimelock(0)).unwrap_err(),
+        custom(EnergyMarketError::ParameterNotGovernable),
+    );
+    assert_eq!(propose(&mut market, proposer, ConfigUpdate::FeeBps(10_001)).unwrap_err(), custom(EnergyMarketError::InvalidFeeRate));
+    propose(&mut market, proposer, ConfigUpdate::FeeBps(250)).unwrap();
+    assert_eq!(propose(&mut market, proposer, ConfigUpdate::FeeBps(300)).unwrap_err(), custom(EnergyMarketError::ProposalOpen));
+
+    vote(&mut market, proposer, 0, true).unwrap();
+    assert_eq!(vote(&mut market, proposer, 0, true).unwrap_err(), custom(EnergyMarketError::AlreadyVoted));
+    assert_eq!(vote(&mut market, proposer, 0, false).unwrap_err(), custom(EnergyMarketError::AlreadyVoted));
+    assert_eq!(vote(&mut market, small, 1, true).unwrap_err(), custom(EnergyMarketError::ProposalNotFound));
+    let proposal = market.bank.ledger(&market.ledger).proposal.unwrap();
+    assert_eq!((proposal.votes_for, proposal.votes_against), (700, 0));
+}
+
+#[test]
+fn vote_weight_is_a_snapshot_of_the_balance() {
+    let mut market = governed_market();
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    let producer = market.register(ParticipantType::Producer, 0);
+    propose(&mut market, consumer, ConfigUpdate::FeeBps(250)).unwrap();
+    vote(&mut market, consumer, 0, true).unwrap();
+
+    // The voted balance is not held, so buying with it hands it to a seller who votes with it again
+    market.report_production(producer, 100, 10).unwrap();
+    market.post_demand(consumer, 100, 10).unwrap();
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+    assert_eq!(market.bank.participant(&market.ledger, &producer).wallet_balance, 990);
+    vote(&mut market, producer, 0, true).unwrap();
+    assert_eq!(market.bank.ledger(&market.ledger).proposal.unwrap().votes_for, 1_990);
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{client, events::TradeExecuted, ConfigUpdate, EnergyMarketError, MarketConfig, ParticipantType};
+use solana_program::pubkey::Pubkey;
+
+// A market charging 2 lamports per kWh for the network, with a registered grid operator
+fn market_with_grid_fee(config: MarketConfig) -> (Market, Pubkey) {
+    let mut market = Market::new(config);
+    let operator = market.register(ParticipantType::Consumer, 0);
+    market.bank.process(&client::set_grid_operator_ix(market.ledger, market.admin, operator)).unwrap();
+    market.bank.process(&client::update_config_ix(market.ledger, market.admin, ConfigUpdate::GridFeePerKwh(2))).unwrap();
+    (market, operator)
+}
+
+fn wallet_balance(market: &Market, wallet: &Pubkey) -> u64 {
+    market.bank.participant(&market.ledger, wallet).wallet_balance
+}
+
+#[test]
+fn buyer_pays_the_grid_fee_on_top_of_the_protocol_split() {
+    let (mut market, operator) = market_with_grid_fee(MarketConfig { fee_bps: 1_000, ..MarketConfig::default() });
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_200);
+    market.report_production(producer, 100, 10).unwrap();
+    market.post_demand(consumer, 100, 10).unwrap();
+
+    // Without the grid operator's participant the fee cannot be paid, so the pair waits in the book
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+    assert!(market.bank.events::<TradeExecuted>().is_empty());
+    assert_eq!(market.bank.ledger(&market.ledger).demands.len(), 1);
+    market.match_orders(producer, &[producer, consumer, operator]).unwrap();
+
+    // 1_200 paid in: 900 to the producer, 100 in protocol fees and 200 to the grid operator
+    assert_eq!(wallet_balance(&market, &consumer), 0);
+    assert_eq!(wallet_balance(&market, &producer), 900);
+    assert_eq!(wallet_balance(&market, &operator), 200);
+    assert_eq!(market.bank.ledger(&market.ledger).protocol_fees, 100);
+}
+
+#[test]
+fn buyer_short_of_the_grid_fee_is_skipped_until_topped_up() {
+    let (mut market, operator) = market_with_grid_fee(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_200);
+    market.report_production(producer, 100, 10).unwrap();
+    market.post_demand(consumer, 100, 10).unwrap();
+
+    // The escrow was taken at a grid fee of 2, so once it rises to 3 the pair is left in the book
+    market.bank.process(&client::update_config_ix(market.ledger, market.admin, ConfigUpdate::GridFeePerKwh(3))).unwrap();
+    market.match_orders(producer, &[producer, consumer, operator]).unwrap();
+    assert!(market.bank.events::<TradeExecuted>().is_empty());
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).reserved_balance, 1_200);
+
+    market.bank.process(&client::deposit_ix(market.ledger, consumer, 100, None)).unwrap();
+    // The grid operator cranking passes its own participant
+    market.match_orders(operator, &[producer, consumer]).unwrap();
+    assert_eq!(market.bank.events::<TradeExecuted>().len(), 1);
+    assert_eq!(wallet_balance(&market, &producer), 1_000);
+    assert_eq!(wallet_balance(&market, &operator), 300);
+    assert_eq!(wallet_balance(&market, &consumer), 0);
+}
+
+#[test]
+fn full_balance_demand_fills_at_its_limit_and_splits_exactly() {
+    let config = MarketConfig { fee_bps: 1_000, crank_reward_bps: 200, ..MarketConfig::default() };
+    let (mut market, operator) = market_with_grid_fee(config);
+    let producer = market.register(ParticipantType::Producer, 0);
+    // 13 per kWh: the limit, the buyer's half of the crank reward rounded up and the grid fee
+    let consumer = market.register(ParticipantType::Consumer, 1_300);
+    market.report_production(producer, 100, 10).unwrap();
+    market.post_demand(consumer, 100, 10).unwrap();
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).reserved_balance, 1_300);
+
+    let keeper = market.register(ParticipantType::Consumer, 0);
+    market.match_orders(keeper, &[producer, consumer, operator]).unwrap();
+    assert_eq!(market.bank.events::<TradeExecuted>().len(), 1);
+
+    // The buyer paid 1_000 + 10 + 200 and got the unused 90 back; every reserved lamport is accounted for
+    let buyer = market.bank.participant(&market.ledger, &consumer);
+    assert_eq!((buyer.wallet_balance, buyer.reserved_balance), (90, 0));
+    assert_eq!(wallet_balance(&market, &producer), 890);
+    assert_eq!(wallet_balance(&market, &keeper), 20);
+    assert_eq!(wallet_balance(&market, &operator), 200);
+    let protocol_fees = market.bank.ledger(&market.ledger).protocol_fees;
+    assert_eq!(protocol_fees, 100);
+    assert_eq!(buyer.wallet_balance + 890 + 20 + 200 + protocol_fees, 1_300);
+}
+
+#[test]
+fn grid_fee_needs_a_grid_operator() {
+    let mut market = Market::new(MarketConfig::default());
+    let fee = client::update_config_ix(market.ledger, market.admin, ConfigUpdate::GridFeePerKwh(2));
+    assert_eq!(market.bank.process(&fee).unwrap_err(), custom(EnergyMarketError::GridOperatorNotSet));
+
+    let (mut market, _) = market_with_grid_fee(MarketConfig::default());
+    let unset = client::set_grid_operator_ix(market.ledger, market.admin, Pubkey::default());
+    assert_eq!(market.bank.process(&unset).unwrap_err(), custom(EnergyMarketError::GridOperatorNotSet));
+    market.bank.process(&client::update_config_ix(market.ledger, market.admin, ConfigUpdate::GridFeePerKwh(0))).unwrap();
+    market.bank.process(&unset).unwrap();
+}
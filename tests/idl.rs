@@ -0,0 +1,59 @@
+mod common;
+
+use common::custom;
+use energy_trading_program::{idl, EnergyMarketError, EnergyMarketInstruction};
+use serde_json::Value;
+
+fn checked_in() -> Value {
+    serde_json::from_str(include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/idl.json"))).unwrap()
+}
+
+#[test]
+fn generated_idl_parses_and_matches_the_checked_in_one() {
+    let generated: Value = serde_json::from_str(&idl::generate().unwrap()).unwrap();
+    assert_eq!(generated, checked_in());
+    assert_eq!(generated["address"], energy_trading_program::id().to_string());
+}
+
+#[test]
+fn idl_enumerates_every_instruction_with_its_accounts() {
+    let idl = checked_in();
+    let instructions = idl["instructions"].as_array().unwrap();
+    assert_eq!(instructions.len(), idl::INSTRUCTIONS.len());
+    for (tag, (instruction, annotated)) in instructions.iter().zip(idl::INSTRUCTIONS).enumerate() {
+        assert_eq!((instruction["name"].as_str().unwrap(), instruction["discriminant"].as_u64().unwrap()), (annotated.name, tag as u64));
+        let accounts = instruction["accounts"].as_array().unwrap();
+        assert_eq!(accounts.len(), annotated.accounts.len(), "{}", annotated.name);
+        for (account, annotated) in accounts.iter().zip(annotated.accounts) {
+            assert_eq!(account["name"], annotated.name);
+            assert_eq!((account["writable"].as_bool(), account["signer"].as_bool()), (Some(annotated.writable), Some(annotated.signer)));
+        }
+        assert!(instruction["args"].is_array(), "{}", annotated.name);
+
+        // Every tag the IDL lists is one the program decodes
+        let unpacked = EnergyMarketInstruction::unpack(&[tag as u8]);
+        assert_ne!(unpacked.err(), Some(custom(EnergyMarketError::UnknownInstruction)), "{}", annotated.name);
+    }
+    let next_tag = instructions.len() as u8;
+    assert_eq!(EnergyMarketInstruction::unpack(&[next_tag]).unwrap_err(), custom(EnergyMarketError::UnknownInstruction));
+}
+
+#[test]
+fn idl_lists_the_error_codes_the_program_returns() {
+    let idl = checked_in();
+    let errors = idl["errors"].as_array().unwrap();
+    for (code, error) in errors.iter().enumerate() {
+        assert_eq!(error["code"].as_u64(), Some(code as u64));
+    }
+    for error in [
+        EnergyMarketError::ParticipantAlreadyRegistered,
+        EnergyMarketError::InsufficientBalance,
+        EnergyMarketError::LedgerClosed,
+        EnergyMarketError::NotFound,
+        EnergyMarketError::VotingNotOver,
+    ] {
+        let code = error as usize;
+        assert_eq!(errors[code]["name"], format!("{:?}", error));
+    }
+    assert_eq!(errors.len(), EnergyMarketError::VotingNotOver as usize + 1);
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, delivery_slot_at, delivery_slot_end, events::ImbalanceSettled, EnergyMarketError, ImbalanceConfig, MarketConfig,
+    ParticipantType,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+const ACTUALS_TIMEOUT: i64 = 3_600;
+
+// A shortfall costs 20 per kWh and a surplus earns 5. The producer sells 100 kWh at 10 to the
+// consumer, which leaves the fee pool holding the 100 withheld from the producer's proceeds.
+struct Traded {
+    market: Market,
+    producer: Pubkey,
+    consumer: Pubkey,
+    oracle: Pubkey,
+    slot: u32,
+}
+
+fn traded() -> Traded {
+    let mut market = Market::new(MarketConfig { fee_bps: 1_000, ..MarketConfig::default() });
+    let imbalance = ImbalanceConfig { shortfall_price: 20, surplus_price: 5, actuals_timeout: ACTUALS_TIMEOUT };
+    market.bank.process(&client::set_imbalance_config_ix(market.ledger, market.admin, imbalance)).unwrap();
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_100);
+    market.report_production(producer, 100, 10).unwrap();
+    market.post_demand(consumer, 100, 10).unwrap();
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+
+    let oracle = market.bank.funded_wallet(1);
+    market.bank.process(&client::set_oracle_ix(market.ledger, market.admin, oracle)).unwrap();
+    let slot = delivery_slot_at(market.bank.now);
+    Traded { market, producer, consumer, oracle, slot }
+}
+
+impl Traded {
+    fn report(&mut self, wallet: Pubkey, metered_injection: u64, metered_consumption: u64) -> Result<(), ProgramError> {
+        let market = &mut self.market;
+        market.bank.process(&client::report_actuals_ix(market.ledger, self.oracle, wallet, self.slot, metered_injection, metered_consumption))
+    }
+
+    fn settle(&mut self, wallet: Pubkey) -> Result<Vec<ImbalanceSettled>, ProgramError> {
+        let market = &mut self.market;
+        let settler = market.bank.funded_wallet(1);
+        market.bank.process(&client::settle_imbalances_ix(market.ledger, settler, wallet, self.slot))?;
+        Ok(market.bank.events())
+    }
+
+    fn wallet_balance(&self, wallet: &Pubkey) -> u64 {
+        self.market.bank.participant(&self.market.ledger, wallet).wallet_balance
+    }
+}
+
+#[test]
+fn over_delivery_is_credited_and_under_delivery_charged() {
+    let mut traded = traded();
+    let (producer, consumer, slot) = (traded.producer, traded.consumer, traded.slot);
+    let position = traded.market.bank.participant(&traded.market.ledger, &producer).positions[0];
+    assert_eq!((position.delivery_slot, position.sold, position.bought, position.reported), (slot, 100, 0, false));
+
+    // The producer injected 10 kWh more than it sold and the consumer drew 4 kWh more than it bought
+    traded.market.bank.now = delivery_slot_end(slot);
+    traded.report(producer, 110, 0).unwrap();
+    traded.report(consumer, 0, 104).unwrap();
+    let settled = |participant, imbalance_kwh, charged, credited| {
+        vec![ImbalanceSettled { participant, delivery_slot: slot, imbalance_kwh, charged, credited, reported: true }]
+    };
+    assert_eq!(traded.settle(producer).unwrap(), settled(producer, 10, 0, 50));
+    assert_eq!(traded.settle(consumer).unwrap(), settled(consumer, -4, 80, 0));
+
+    assert_eq!(traded.wallet_balance(&producer), 900 + 50);
+    assert_eq!(traded.wallet_balance(&consumer), 100 - 80);
+    assert_eq!(traded.market.bank.ledger(&traded.market.ledger).protocol_fees, 100 - 50 + 80);
+    assert_eq!(traded.settle(producer).unwrap_err(), custom(EnergyMarketError::PositionNotFound));
+}
+
+#[test]
+fn exact_delivery_settles_at_no_cost() {
+    let mut traded = traded();
+    let (producer, consumer, slot) = (traded.producer, traded.consumer, traded.slot);
+    assert_eq!(traded.report(producer, 100, 0).unwrap_err(), custom(EnergyMarketError::DeliverySlotNotOver));
+    traded.market.bank.now = delivery_slot_end(slot);
+    assert_eq!(traded.settle(producer).unwrap_err(), custom(EnergyMarketError::ActualsNotReported));
+    let by_stranger = client::report_actuals_ix(traded.market.ledger, consumer, producer, slot, 100, 0);
+    assert_eq!(traded.market.bank.process(&by_stranger).unwrap_err(), custom(EnergyMarketError::InvalidOracle));
+
+    traded.report(producer, 100, 0).unwrap();
+    traded.report(consumer, 0, 100).unwrap();
+    for wallet in [producer, consumer] {
+        let settled = traded.settle(wallet).unwrap();
+        assert_eq!((settled[0].imbalance_kwh, settled[0].charged, settled[0].credited), (0, 0, 0));
+        assert_eq!(traded.market.bank.participant(&traded.market.ledger, &wallet).position_count, 0);
+    }
+    assert_eq!((traded.wallet_balance(&producer), traded.wallet_balance(&consumer)), (900, 100));
+    assert_eq!(traded.market.bank.ledger(&traded.market.ledger).protocol_fees, 100);
+}
+
+#[test]
+fn positions_without_actuals_settle_flat_after_the_timeout() {
+    let mut traded = traded();
+    let (producer, slot) = (traded.producer, traded.slot);
+    let deadline = delivery_slot_end(slot) + ACTUALS_TIMEOUT;
+
+    // An open position keeps the participant registered
+    traded.market.bank.now = deadline - 1;
+    assert_eq!(traded.settle(producer).unwrap_err(), custom(EnergyMarketError::ActualsNotReported));
+    traded.market.bank.process(&client::withdraw_ix(traded.market.ledger, producer, producer, 900, None)).unwrap();
+    assert_eq!(
+        traded.market.bank.process(&client::unregister_participant_ix(traded.market.ledger, producer)).unwrap_err(),
+        custom(EnergyMarketError::PendingSettlement),
+    );
+
+    traded.market.bank.now = deadline;
+    assert_eq!(
+        traded.settle(producer).unwrap(),
+        vec![ImbalanceSettled { participant: producer, delivery_slot: slot, imbalance_kwh: 0, charged: 0, credited: 0, reported: false }],
+    );
+    traded.market.bank.process(&client::unregister_participant_ix(traded.market.ledger, producer)).unwrap();
+}
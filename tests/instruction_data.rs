@@ -0,0 +1,73 @@
+mod common;
+
+use common::{custom, Market, CAPACITY};
+use energy_trading_program::{
+    client, idl, EnergyMarketError, EnergyMarketInstruction, MarketConfig, ParticipantType,
+};
+use solana_program::{instruction::Instruction, program_error::ProgramError, pubkey::Pubkey};
+
+fn with_data(instruction: &Instruction, data: Vec<u8>) -> Instruction {
+    Instruction { data, ..instruction.clone() }
+}
+
+#[test]
+fn truncated_data_is_too_short() {
+    let ledger = Pubkey::new_unique();
+    let wallet = Pubkey::new_unique();
+    let data = client::initialize_ledger_ix(ledger, wallet, CAPACITY, None, MarketConfig::default(), None).data;
+
+    assert_eq!(EnergyMarketInstruction::unpack(&[]).unwrap_err(), custom(EnergyMarketError::InstructionDataTooShort));
+    for len in 1..data.len() {
+        assert_eq!(
+            EnergyMarketInstruction::unpack(&data[..len]).unwrap_err(),
+            custom(EnergyMarketError::InstructionDataTooShort),
+            "{} of {} bytes",
+            len,
+            data.len(),
+        );
+    }
+}
+
+#[test]
+fn unknown_tag_is_reported() {
+    for tag in [u8::MAX, 200] {
+        assert_eq!(EnergyMarketInstruction::unpack(&[tag, 0, 0, 0]).unwrap_err(), custom(EnergyMarketError::UnknownInstruction));
+    }
+    // The tag after the last variant is the first one a newer deployment could add
+    let next_tag = idl::INSTRUCTIONS.len() as u8;
+    assert_eq!(EnergyMarketInstruction::unpack(&[next_tag]).unwrap_err(), custom(EnergyMarketError::UnknownInstruction));
+}
+
+#[test]
+fn malformed_fields_are_invalid_data() {
+    // InitializeLedger's quote_mint follows the tag and the three u32 capacity fields; 2 is no Option tag
+    let mut data = client::initialize_ledger_ix(Pubkey::new_unique(), Pubkey::new_unique(), CAPACITY, None, MarketConfig::default(), None).data;
+    data[13] = 2;
+    assert_eq!(EnergyMarketInstruction::unpack(&data).unwrap_err(), ProgramError::InvalidInstructionData);
+}
+
+#[test]
+fn trailing_bytes_are_ignored() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+
+    let instruction = client::deposit_ix(market.ledger, producer, 5_000, None);
+    let mut padded = instruction.data.clone();
+    padded.extend_from_slice(&[7; 16]);
+    assert!(matches!(EnergyMarketInstruction::unpack(&padded).unwrap(), EnergyMarketInstruction::Deposit { amount: 5_000 }));
+
+    market.bank.process(&with_data(&instruction, padded)).unwrap();
+    assert_eq!(market.bank.participant(&market.ledger, &producer).wallet_balance, 5_000);
+}
+
+#[test]
+fn malformed_data_fails_the_instruction() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let instruction = client::deposit_ix(market.ledger, producer, 5_000, None);
+
+    let truncated = instruction.data[..instruction.data.len() - 1].to_vec();
+    assert_eq!(market.bank.process_err(&with_data(&instruction, truncated)), custom(EnergyMarketError::InstructionDataTooShort));
+    assert_eq!(market.bank.process_err(&with_data(&instruction, vec![u8::MAX])), custom(EnergyMarketError::UnknownInstruction));
+    assert_eq!(market.bank.participant(&market.ledger, &producer).wallet_balance, 0);
+}
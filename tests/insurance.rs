@@ -0,0 +1,140 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, delivery_slot_at, delivery_slot_end, events::InsuranceClaimed, DeliveryConfirmer, EnergyMarketError, MarketConfig,
+    ParticipantType, SettlementConfig,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+const DELIVERY_TIMEOUT: i64 = 60;
+
+// Deferred settlement with collateral of 20% of every offer's notional, and half of every
+// trading fee paid into the insurance pool. The admin registers with `admin_deposit` to fund it.
+fn insured_market(config_timelock: i64, admin_deposit: u64) -> Market {
+    Market::builder(MarketConfig {
+        fee_bps: 1_000,
+        insurance_fee_bps: 5_000,
+        collateral_bps: 2_000,
+        config_timelock,
+        settlement: SettlementConfig {
+            deferred: true,
+            confirmer: DeliveryConfirmer::Consumer,
+            delivery_timeout: DELIVERY_TIMEOUT,
+            default_penalty_bps: 0,
+        },
+        ..MarketConfig::default()
+    })
+    .registered_admin(admin_deposit)
+    .build()
+}
+
+// A producer backing its offer with the 200 of collateral required sells 100 kWh at 10 to a new
+// consumer; returns the pending trade's id with both wallets
+fn matched(market: &mut Market) -> (u64, Pubkey, Pubkey) {
+    let producer = market.register(ParticipantType::Producer, 200);
+    market.bank.process(&client::post_collateral_ix(market.ledger, producer, 200)).unwrap();
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producer, 100, 10).unwrap();
+    market.post_demand(consumer, 100, 10).unwrap();
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+    let trade_id = market.bank.ledger(&market.ledger).transactions.last().unwrap().trade_id;
+    (trade_id, producer, consumer)
+}
+
+// The consumer is refunded in full and handed the producer's 200 of collateral, leaving 800 of
+// the notional to claim
+fn defaulted(market: &mut Market) -> Pubkey {
+    let (trade_id, producer, consumer) = matched(market);
+    market.bank.now = delivery_slot_end(delivery_slot_at(market.bank.now)) + DELIVERY_TIMEOUT + 1;
+    market.bank.process(&client::settle_defaulted_trade_ix(market.ledger, consumer, producer, trade_id)).unwrap();
+    consumer
+}
+
+fn claim(market: &mut Market, consumer: Pubkey) -> Result<Vec<InsuranceClaimed>, ProgramError> {
+    market.bank.process(&client::claim_insurance_ix(market.ledger, consumer))?;
+    Ok(market.bank.events())
+}
+
+fn wallet_balance(market: &Market, wallet: &Pubkey) -> u64 {
+    market.bank.participant(&market.ledger, wallet).wallet_balance
+}
+
+#[test]
+fn claim_is_paid_in_full_while_the_pool_covers_it() {
+    let mut market = insured_market(0, 1_000);
+    market.bank.process(&client::top_up_insurance_ix(market.ledger, market.admin, 1_000)).unwrap();
+    let consumer = defaulted(&mut market);
+    assert_eq!(wallet_balance(&market, &consumer), 1_200);
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.insurance_pool, ledger.insurance_claims), (1_000, 800));
+
+    assert_eq!(claim(&mut market, consumer).unwrap(), vec![InsuranceClaimed { participant: consumer, claim: 800, payout: 800 }]);
+    assert_eq!(wallet_balance(&market, &consumer), 2_000);
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.insurance_pool, ledger.insurance_claims), (200, 0));
+    assert_eq!(claim(&mut market, consumer).unwrap_err(), custom(EnergyMarketError::NoInsuranceClaim));
+}
+
+#[test]
+fn short_pool_pays_every_claimant_the_same_share() {
+    let mut market = insured_market(0, 800);
+    let first = defaulted(&mut market);
+    let second = defaulted(&mut market);
+    market.bank.process(&client::top_up_insurance_ix(market.ledger, market.admin, 800)).unwrap();
+
+    // 800 in the pool against 1_600 claimed pays half of each claim, whoever claims first
+    assert_eq!(claim(&mut market, second).unwrap()[0].payout, 400);
+    assert_eq!(claim(&mut market, first).unwrap()[0].payout, 400);
+    assert_eq!((wallet_balance(&market, &first), wallet_balance(&market, &second)), (1_600, 1_600));
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.insurance_pool, ledger.insurance_claims), (0, 0));
+}
+
+#[test]
+fn claim_on_an_empty_pool_waits_for_a_top_up() {
+    let mut market = insured_market(0, 300);
+    let consumer = defaulted(&mut market);
+    assert_eq!(claim(&mut market, consumer).unwrap_err(), custom(EnergyMarketError::InsurancePoolEmpty));
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).insurance_claim, 800);
+    market.bank.process(&client::withdraw_all_ix(market.ledger, consumer, consumer, None)).unwrap();
+    assert_eq!(
+        market.bank.process(&client::unregister_participant_ix(market.ledger, consumer)).unwrap_err(),
+        custom(EnergyMarketError::PendingSettlement),
+    );
+
+    market.bank.process(&client::top_up_insurance_ix(market.ledger, market.admin, 300)).unwrap();
+    assert_eq!(claim(&mut market, consumer).unwrap()[0].payout, 300);
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).insurance_claim, 0);
+    assert_eq!(market.bank.ledger(&market.ledger).insurance_claims, 0);
+}
+
+#[test]
+fn fee_slice_funds_the_pool_and_drains_wait_out_the_timelock() {
+    let mut market = insured_market(100, 0);
+    let (trade_id, producer, consumer) = matched(&mut market);
+    market.bank.process(&client::confirm_delivery_ix(market.ledger, consumer, consumer, producer, trade_id)).unwrap();
+
+    // Of the 100 withheld from the producer, half goes to the pool; the 1_200 deposited stay put
+    let balances = |market: &Market| {
+        let ledger = market.bank.ledger(&market.ledger);
+        let held: u64 = [market.admin, producer, consumer].iter()
+            .map(|wallet| market.bank.participant(&market.ledger, wallet))
+            .map(|p| p.wallet_balance + p.reserved_balance + p.collateral_balance)
+            .sum();
+        (ledger.insurance_pool, ledger.protocol_fees, held + ledger.insurance_pool + ledger.protocol_fees)
+    };
+    assert_eq!(balances(&market), (50, 50, 1_200));
+
+    let by_producer = client::propose_insurance_drain_ix(market.ledger, producer, 30);
+    assert_eq!(market.bank.process(&by_producer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    market.bank.process(&client::propose_insurance_drain_ix(market.ledger, market.admin, 30)).unwrap();
+    market.bank.now += 99;
+    let drain = client::drain_insurance_ix(market.ledger, market.admin);
+    assert_eq!(market.bank.process(&drain).unwrap_err(), custom(EnergyMarketError::InsuranceDrainNotEffective));
+    market.bank.now += 1;
+    market.bank.process(&drain).unwrap();
+    assert_eq!(balances(&market), (20, 50, 1_200));
+    assert_eq!(wallet_balance(&market, &market.admin), 30);
+    assert_eq!(market.bank.process(&drain).unwrap_err(), custom(EnergyMarketError::NoInsuranceDrain));
+}
@@ -0,0 +1,68 @@
+#![cfg(feature = "serde")]
+
+mod common;
+
+use borsh::BorshSerialize;
+use common::Market;
+use energy_trading_program::{client, ConfigUpdate, Ledger, MarketConfig, Participant, ParticipantType};
+use serde_json::Value;
+use solana_program::pubkey::Pubkey;
+
+// A ledger with approvers, a pending change, open orders and trade history, so most fields are set
+fn busy_market() -> (Market, Pubkey, Pubkey) {
+    let mut market = Market::new(MarketConfig { config_timelock: 60, ..MarketConfig::default() });
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    let bot = market.bank.funded_wallet(1);
+    market.bank.process(&client::set_delegate_ix(market.ledger, consumer, bot)).unwrap();
+    market.bank.process(&client::add_approver_ix(market.ledger, market.admin, bot)).unwrap();
+    let effective_at = market.bank.now + 60;
+    market.bank.process(&client::propose_config_change_ix(market.ledger, market.admin, ConfigUpdate::FeeBps(50), effective_at)).unwrap();
+    market.report_production(producer, 30, 10).unwrap();
+    market.post_demand(consumer, 20, 10).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    market.post_demand(consumer, 5, 8).unwrap();
+    (market, consumer, bot)
+}
+
+#[test]
+fn ledger_roundtrips_through_json() {
+    let (market, consumer, bot) = busy_market();
+    let ledger = market.bank.ledger(&market.ledger);
+    let json = serde_json::to_string(&ledger).unwrap();
+    let decoded: Ledger = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded.try_to_vec().unwrap(), ledger.try_to_vec().unwrap());
+
+    // Keys are written as base58, wherever they sit
+    let value: Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(value["admin"], market.admin.to_string());
+    assert_eq!(value["approvers"][1], bot.to_string());
+    assert_eq!(value["approvers"][2], Pubkey::default().to_string());
+    assert_eq!(value["transactions"][0]["from"], consumer.to_string());
+}
+
+#[test]
+fn participant_roundtrips_through_json() {
+    let (market, consumer, bot) = busy_market();
+    let participant = market.bank.participant(&market.ledger, &consumer);
+    let json = serde_json::to_string_pretty(&participant).unwrap();
+    let decoded: Participant = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded.try_to_vec().unwrap(), participant.try_to_vec().unwrap());
+
+    let value: Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(value["id"], consumer.to_string());
+    assert_eq!((&value["delegate"], &value["aggregator"]), (&Value::from(bot.to_string()), &Value::Null));
+}
+
+#[test]
+fn malformed_keys_are_rejected() {
+    let (market, consumer, _) = busy_market();
+    let mut value = serde_json::to_value(market.bank.participant(&market.ledger, &consumer)).unwrap();
+    value["id"] = Value::from("not a key");
+    let err = serde_json::from_value::<Participant>(value).unwrap_err();
+    assert!(err.to_string().contains("invalid public key not a key"), "{}", err);
+
+    let mut value = serde_json::to_value(market.bank.ledger(&market.ledger)).unwrap();
+    value["approvers"].as_array_mut().unwrap().pop();
+    assert!(serde_json::from_value::<Ledger>(value).is_err());
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use common::Market;
+use energy_trading_program::{
+    client, delivery_slot_at, events::TradeExecuted, ConfigUpdate, EnergyMarketError, EnergySource, MarketConfig,
+    ParticipantType, TimeInForce,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+// Registers a participant able to offer or escrow amounts far beyond what the shared helpers allow
+fn register_large(market: &mut Market, participant_type: ParticipantType, deposit: u64) -> Pubkey {
+    let wallet = market.register(participant_type, 0);
+    market.bank.process(&client::set_registered_capacity_ix(market.ledger, market.admin, wallet, u64::MAX)).unwrap();
+    if deposit > 0 {
+        market.bank.airdrop(&wallet, deposit);
+        market.bank.process(&client::deposit_ix(market.ledger, wallet, deposit, None)).unwrap();
+    }
+    wallet
+}
+
+#[test]
+fn fifty_megawatt_hours_at_a_wh_price_is_too_large() {
+    // 50 MWh in Wh at a price of 10^12 lamports per Wh is 5 * 10^19 lamports, past u64::MAX
+    let mut market = Market::new(MarketConfig::default());
+    let producer = register_large(&mut market, ParticipantType::Producer, 0);
+
+    let error = market.report_production(producer, 50_000_000, 1_000_000_000_000).unwrap_err();
+    assert_eq!(error, ProgramError::Custom(EnergyMarketError::AmountTooLarge as u32));
+
+    // The order only fits once the fee and crank reward on top of its notional fit as well
+    let config = MarketConfig { fee_bps: 100, ..MarketConfig::default() };
+    let mut market = Market::new(config);
+    let producer = register_large(&mut market, ParticipantType::Producer, 0);
+    let error = market.report_production(producer, u64::MAX / 2, 2).unwrap_err();
+    assert_eq!(error, ProgramError::Custom(EnergyMarketError::AmountTooLarge as u32));
+}
+
+#[test]
+fn near_max_fill_settles() {
+    let config = MarketConfig { fee_bps: 100, crank_reward_bps: 20, ..MarketConfig::default() };
+    let mut market = Market::new(config);
+    let amount = u64::MAX / 5;
+    let escrow = amount * 3;
+    let producer = register_large(&mut market, ParticipantType::Producer, 0);
+    // The escrow holds a whole lamport per kWh for the buyer's share of the reward, rounded up
+    let consumer = register_large(&mut market, ParticipantType::Consumer, amount * 4);
+
+    market.report_production(producer, amount, 3).unwrap();
+    market.post_demand(consumer, amount, 3).unwrap();
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+
+    let trades: Vec<TradeExecuted> = market.bank.events();
+    assert_eq!(trades.len(), 1);
+    assert_eq!((trades[0].amount, trades[0].price), (amount, 3));
+    let ledger = market.bank.ledger(&market.ledger);
+    assert!(ledger.productions.is_empty() && ledger.demands.is_empty());
+    assert_eq!(ledger.stats.total_notional, escrow as u128);
+    assert_eq!(ledger.reference_price, 3);
+
+    // The consumer paid the notional and half the crank reward; the producer received the notional
+    // less the protocol fee and its half of the reward, and then the whole reward as the cranker
+    let reward = (escrow as u128 * 20 / 10_000) as u64;
+    let fee = (escrow as u128 * 100).div_ceil(10_000) as u64;
+    let buyer = market.bank.participant(&market.ledger, &consumer);
+    assert_eq!((buyer.wallet_balance, buyer.reserved_balance), (amount - reward / 2, 0));
+    assert_eq!(market.bank.participant(&market.ledger, &producer).wallet_balance, escrow - fee + reward / 2);
+    assert_eq!(ledger.protocol_fees, fee);
+}
+
+#[test]
+fn fill_pushed_past_a_balance_by_a_fee_change_is_skipped() {
+    let mut market = Market::new(MarketConfig::default());
+    let amount = u64::MAX / 20 * 9 / 2;
+    let big_producer = register_large(&mut market, ParticipantType::Producer, 0);
+    let big_consumer = register_large(&mut market, ParticipantType::Consumer, amount * 2);
+    market.report_production(big_producer, amount, 2).unwrap();
+    market.post_demand(big_consumer, amount, 2).unwrap();
+
+    // A small pair for the next delivery slot, which never crosses the large orders
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 10_000);
+    let next_slot = delivery_slot_at(market.bank.now) + 1;
+    market.bank.process(&client::report_production_ix(
+        market.ledger, producer, 10, 100, 0, next_slot, EnergySource::Wind, TimeInForce::GoodTilCancelled, 0, false, 0, false, None,
+    )).unwrap();
+    market.bank.process(&client::post_demand_ix(
+        market.ledger, consumer, 10, 100, 0, next_slot, TimeInForce::GoodTilCancelled, 0, false, 0, 0, None,
+    )).unwrap();
+
+    // With a 25% crank reward the large consumer's half of it takes its cost past u64::MAX
+    market.bank.process(&client::update_config_ix(market.ledger, market.admin, ConfigUpdate::CrankRewardBps(2_500))).unwrap();
+    market.match_orders(producer, &[big_producer, big_consumer, producer, consumer]).unwrap();
+
+    let trades: Vec<TradeExecuted> = market.bank.events();
+    assert_eq!(trades.len(), 1);
+    assert_eq!((trades[0].buyer, trades[0].seller), (consumer, producer));
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!(ledger.productions.iter().map(|p| p.producer_id).collect::<Vec<_>>(), vec![big_producer]);
+    assert_eq!(ledger.demands.iter().map(|d| d.consumer_id).collect::<Vec<_>>(), vec![big_consumer]);
+    assert_eq!(market.bank.participant(&market.ledger, &big_consumer).reserved_balance, amount * 2);
+}
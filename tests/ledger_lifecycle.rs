@@ -0,0 +1,74 @@
+mod common;
+
+use borsh::BorshSerialize;
+use common::{custom, Bank, Market, CAPACITY};
+use energy_trading_program::{client, ledger_space, EnergyMarketError, MarketConfig, OrderStorage, ParticipantType};
+use solana_program::{program_error::ProgramError, rent::Rent};
+
+#[test]
+fn initialized_ledger_cannot_be_initialized_again() {
+    let mut market = Market::new(MarketConfig::default());
+    let consumer = market.register(ParticipantType::Consumer, 500);
+
+    let again = client::initialize_ledger_ix(market.ledger, market.admin, CAPACITY, None, MarketConfig::default(), None);
+    assert_eq!(market.bank.process(&again).unwrap_err(), ProgramError::AccountAlreadyInitialized);
+    let intruder = market.bank.funded_wallet(1);
+    let hijack = client::initialize_ledger_ix(market.ledger, intruder, CAPACITY, None, MarketConfig::default(), None);
+    assert_eq!(market.bank.process(&hijack).unwrap_err(), ProgramError::AccountAlreadyInitialized);
+
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.admin, ledger.participant_count), (market.admin, 1));
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).wallet_balance, 500);
+}
+
+#[test]
+fn instructions_fail_cleanly_on_an_uninitialized_ledger() {
+    let mut market = Market::new(MarketConfig::default());
+    let blank = market.bank.program_account(ledger_space(&CAPACITY));
+    let wallet = market.bank.funded_wallet(1);
+
+    let deposit = client::deposit_ix(blank, wallet, 100, None);
+    assert_eq!(market.bank.process(&deposit).unwrap_err(), custom(EnergyMarketError::LedgerNotInitialized));
+}
+
+#[test]
+fn ledger_outgrowing_its_account_fails_before_writing() {
+    let mut market = Market::new(MarketConfig::default());
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.post_demand(consumer, 10, 10).unwrap();
+    market.post_demand(consumer, 10, 10).unwrap();
+
+    // An account left exactly as large as the ledger with two demands takes no third
+    let serialized_len = market.bank.ledger(&market.ledger).try_to_vec().unwrap().len();
+    market.bank.account_mut(&market.ledger).data.truncate(serialized_len);
+    let before = market.bank.account(&market.ledger).unwrap().clone();
+    assert_eq!(market.post_demand(consumer, 10, 10).unwrap_err(), custom(EnergyMarketError::LedgerAccountTooSmall));
+    assert_eq!(market.bank.account(&market.ledger), Some(&before));
+
+    // A shrinking ledger zeroes the bytes it no longer uses
+    let order_id = market.bank.ledger(&market.ledger).demands[0].order_id;
+    market.bank.process(&client::cancel_demand_ix(market.ledger, consumer, order_id, OrderStorage::Ledger)).unwrap();
+    let shrunk_len = market.bank.ledger(&market.ledger).try_to_vec().unwrap().len();
+    assert!(shrunk_len < serialized_len);
+    assert!(market.bank.account(&market.ledger).unwrap().data[shrunk_len..].iter().all(|&byte| byte == 0));
+    market.post_demand(consumer, 10, 10).unwrap();
+}
+
+#[test]
+fn ledger_account_must_fit_its_capacity_and_be_rent_exempt() {
+    let mut bank = Bank::new();
+    let admin = bank.funded_wallet(10);
+    let required = ledger_space(&CAPACITY);
+
+    let undersized = bank.program_account(required - 1);
+    let initialize = |ledger| client::initialize_ledger_ix(ledger, admin, CAPACITY, None, MarketConfig::default(), None);
+    assert_eq!(bank.process(&initialize(undersized)).unwrap_err(), custom(EnergyMarketError::LedgerAccountTooSmall));
+
+    let unfunded = bank.program_account(required);
+    bank.account_mut(&unfunded).lamports = Rent::default().minimum_balance(required) - 1;
+    assert_eq!(bank.process(&initialize(unfunded)).unwrap_err(), ProgramError::AccountNotRentExempt);
+
+    let ledger = bank.program_account(required);
+    bank.process(&initialize(ledger)).unwrap();
+    assert_eq!(bank.ledger(&ledger).capacity.max_open_orders, CAPACITY.max_open_orders);
+}
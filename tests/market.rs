@@ -0,0 +1,114 @@
+mod common;
+
+use common::{custom, program_id, Market};
+use energy_trading_program::{
+    client, events::TradeExecuted, find_vault_address, ConfigUpdate, EnergyMarketError, MarketConfig, ParticipantType,
+};
+use solana_program::native_token::LAMPORTS_PER_SOL;
+
+#[test]
+fn trade_settles_and_proceeds_can_be_withdrawn() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, LAMPORTS_PER_SOL);
+    let consumer_lamports = market.bank.lamports(&consumer);
+
+    market.report_production(producer, 100, 1_000).unwrap();
+    market.post_demand(consumer, 100, 1_500).unwrap();
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).reserved_balance, 150_000);
+
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+    let trades: Vec<TradeExecuted> = market.bank.events();
+    assert_eq!(trades.len(), 1);
+    assert_eq!((trades[0].buyer, trades[0].seller), (consumer, producer));
+    assert_eq!((trades[0].amount, trades[0].price), (100, 1_000));
+
+    // Pay-as-bid: the consumer pays the producer's asking price and the rest of its escrow is released
+    let ledger = market.bank.ledger(&market.ledger);
+    assert!(ledger.productions.is_empty() && ledger.demands.is_empty());
+    assert_eq!(ledger.total_trades, 1);
+    let seller = market.bank.participant(&market.ledger, &producer);
+    let buyer = market.bank.participant(&market.ledger, &consumer);
+    assert_eq!(seller.wallet_balance, 100_000);
+    assert_eq!((buyer.wallet_balance, buyer.reserved_balance), (LAMPORTS_PER_SOL - 100_000, 0));
+
+    let producer_lamports = market.bank.lamports(&producer);
+    let vault = find_vault_address(&program_id(), &market.ledger).0;
+    let vault_lamports = market.bank.lamports(&vault);
+    market.bank.process(&client::withdraw_ix(market.ledger, producer, producer, 100_000, None)).unwrap();
+    assert_eq!(market.bank.lamports(&producer), producer_lamports + 100_000);
+    assert_eq!(market.bank.lamports(&vault), vault_lamports - 100_000);
+    assert_eq!(market.bank.participant(&market.ledger, &producer).wallet_balance, 0);
+
+    // The consumer's lamports left its wallet only through the deposit
+    assert_eq!(market.bank.lamports(&consumer), consumer_lamports);
+}
+
+#[test]
+fn unregistered_producer_cannot_report_production() {
+    let mut market = Market::new(MarketConfig::default());
+    let stranger = market.bank.funded_wallet(1);
+
+    let error = market.report_production(stranger, 100, 1_000).unwrap_err();
+    assert_eq!(error, custom(EnergyMarketError::ParticipantNotRegistered));
+    assert!(market.bank.ledger(&market.ledger).productions.is_empty());
+}
+
+#[test]
+fn demand_the_consumer_cannot_pay_for_is_skipped() {
+    // A crank reward switched on after posting comes on top of the escrow, so a consumer holding
+    // nothing else cannot cover it and the crossing pair is left in the book
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 100_000);
+
+    market.report_production(producer, 100, 1_000).unwrap();
+    market.post_demand(consumer, 100, 1_000).unwrap();
+    market.bank.process(&client::update_config_ix(market.ledger, market.admin, ConfigUpdate::CrankRewardBps(100))).unwrap();
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+
+    assert!(market.bank.events::<TradeExecuted>().is_empty());
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.productions.len(), ledger.demands.len()), (1, 1));
+    assert_eq!(ledger.total_trades, 0);
+    let buyer = market.bank.participant(&market.ledger, &consumer);
+    assert_eq!((buyer.wallet_balance, buyer.reserved_balance), (0, 100_000));
+    assert_eq!(market.bank.participant(&market.ledger, &producer).wallet_balance, 0);
+}
+
+#[test]
+fn order_too_large_for_a_balance_is_rejected() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, LAMPORTS_PER_SOL);
+
+    assert_eq!(market.report_production(producer, 1_000_000, i64::MAX).unwrap_err(), custom(EnergyMarketError::AmountTooLarge));
+    assert_eq!(market.post_demand(consumer, 3, i64::MAX).unwrap_err(), custom(EnergyMarketError::AmountTooLarge));
+    let ledger = market.bank.ledger(&market.ledger);
+    assert!(ledger.productions.is_empty() && ledger.demands.is_empty());
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).reserved_balance, 0);
+}
+
+#[test]
+fn empty_and_zero_priced_orders_are_rejected_on_both_sides() {
+    let mut market = Market::new(MarketConfig { fee_bps: 100, ..MarketConfig::default() });
+    let producer = market.register(ParticipantType::Producer, LAMPORTS_PER_SOL);
+    let consumer = market.register(ParticipantType::Consumer, LAMPORTS_PER_SOL);
+
+    assert_eq!(market.report_production(producer, 0, 10).unwrap_err(), custom(EnergyMarketError::InvalidOrderAmount));
+    assert_eq!(market.report_production(producer, 10, 0).unwrap_err(), custom(EnergyMarketError::InvalidOrderAmount));
+    assert_eq!(market.post_demand(consumer, 0, 10).unwrap_err(), custom(EnergyMarketError::InvalidOrderAmount));
+    assert_eq!(market.post_demand(consumer, 10, 0).unwrap_err(), custom(EnergyMarketError::InvalidOrderAmount));
+
+    // A notional that fits is still too large once the 1% fee is added on top, whichever way the price points
+    assert_eq!(market.post_demand(consumer, u64::MAX / 2, 2).unwrap_err(), custom(EnergyMarketError::AmountTooLarge));
+    assert_eq!(market.report_production(producer, u64::MAX / 2, -2).unwrap_err(), custom(EnergyMarketError::AmountTooLarge));
+    assert_eq!(market.report_production(producer, 2, i64::MIN).unwrap_err(), custom(EnergyMarketError::AmountTooLarge));
+
+    let ledger = market.bank.ledger(&market.ledger);
+    assert!(ledger.productions.is_empty() && ledger.demands.is_empty());
+    assert_eq!(ledger.next_order_id, 0);
+    for wallet in [producer, consumer] {
+        assert_eq!(market.bank.participant(&market.ledger, &wallet).reserved_balance, 0);
+    }
+}
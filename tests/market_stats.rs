@@ -0,0 +1,70 @@
+mod common;
+
+use borsh::BorshDeserialize;
+use common::Market;
+use energy_trading_program::{client, delivery_slot_at, delivery_slot_end, MarketConfig, MarketStats, ParticipantType};
+
+fn queried_stats(market: &mut Market) -> MarketStats {
+    market.bank.process(&client::get_market_stats_ix(market.ledger)).unwrap();
+    MarketStats::try_from_slice(&market.bank.return_data().unwrap()).unwrap()
+}
+
+#[test]
+fn stats_follow_a_scripted_sequence_of_trades() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 1_000);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    assert_eq!(queried_stats(&mut market), MarketStats::default());
+    assert_eq!(MarketStats::default().vwap(), None);
+
+    market.report_production(producer, 10, 10).unwrap();
+    market.report_production(producer, 30, 14).unwrap();
+    market.post_demand(consumer, 40, 14).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    let first_run = market.bank.now;
+
+    // A negative price adds its magnitude to the notional but pulls the average down
+    market.bank.now += 60;
+    market.report_production(producer, 20, -5).unwrap();
+    market.post_demand(consumer, 20, 1).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+
+    let stats = queried_stats(&mut market);
+    assert_eq!(stats, MarketStats {
+        total_volume_kwh: 60,
+        total_notional: 100 + 420 + 100,
+        trade_count: 3,
+        last_trade_price: -5,
+        last_trade_timestamp: first_run + 60,
+        vwap_numerator: 100 + 420 - 100,
+        vwap_denominator: 60,
+    });
+    assert_eq!(stats.vwap(), Some(7));
+    assert_eq!(stats, market.bank.ledger(&market.ledger).stats);
+}
+
+#[test]
+fn stats_accumulate_past_what_a_u64_holds() {
+    let mut market = Market::new(MarketConfig::default());
+    let amount = u64::MAX / 4;
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 0);
+    for wallet in [producer, consumer] {
+        market.bank.process(&client::set_registered_capacity_ix(market.ledger, market.admin, wallet, u64::MAX)).unwrap();
+    }
+    market.bank.airdrop(&consumer, amount);
+    market.bank.process(&client::deposit_ix(market.ledger, consumer, amount, None)).unwrap();
+
+    // The same funds go round five times, each trade paying them to the producer, who hands them back.
+    // Every round trades a later delivery slot, whose own VWAP volume is kept in a u64.
+    for _ in 0..5 {
+        market.bank.now = delivery_slot_end(delivery_slot_at(market.bank.now)) + 1;
+        market.report_production(producer, amount, 1).unwrap();
+        market.post_demand(consumer, amount, 1).unwrap();
+        market.match_orders(consumer, &[producer, consumer]).unwrap();
+        market.bank.process(&client::transfer_balance_ix(market.ledger, producer, consumer, amount)).unwrap();
+    }
+    let stats = queried_stats(&mut market);
+    assert_eq!((stats.total_volume_kwh, stats.total_notional, stats.trade_count), (5 * amount as u128, 5 * amount as u128, 5));
+    assert_eq!((stats.vwap_denominator, stats.vwap()), (5 * amount as u128, Some(1)));
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use borsh::BorshDeserialize;
+use common::Market;
+use energy_trading_program::{
+    client, delivery_slot_at, events::MatchRunCompleted, ConfigUpdate, EnergySource, MarketConfig, ParticipantType,
+    TimeInForce,
+};
+use solana_program::pubkey::Pubkey;
+
+fn crank(market: &mut Market, cranker: Pubkey, wallets: &[Pubkey], max_matches: u16) -> MatchRunCompleted {
+    market.bank.process(&client::match_transactions_ix(
+        market.ledger,
+        cranker,
+        max_matches,
+        client::participant_metas(market.ledger, wallets),
+    )).unwrap();
+    let summary = MatchRunCompleted::try_from_slice(&market.bank.return_data().unwrap()).unwrap();
+    assert_eq!(market.bank.events::<MatchRunCompleted>(), vec![summary.clone()]);
+    summary
+}
+
+#[test]
+fn match_run_summary_counts_fills_and_skipped_pairs() {
+    let mut market = Market::new(MarketConfig { max_deviation_bps: 1_000, ..MarketConfig::default() });
+    let cheap_producer = market.register(ParticipantType::Producer, 0);
+    let dear_producer = market.register(ParticipantType::Producer, 0);
+    let buyer = market.register(ParticipantType::Consumer, 3_000);
+    let next_slot_producer = market.register(ParticipantType::Producer, 0);
+    let next_slot_buyer = market.register(ParticipantType::Consumer, 500);
+    let wallets = [cheap_producer, dear_producer, buyer, next_slot_producer, next_slot_buyer];
+
+    market.report_production(cheap_producer, 10, 100).unwrap();
+    market.report_production(dear_producer, 10, 200).unwrap();
+    market.post_demand(buyer, 15, 200).unwrap();
+    // The next delivery slot holds a pair whose buyer escrowed exactly the notional
+    let next_slot = delivery_slot_at(market.bank.now) + 1;
+    market.bank.process(&client::report_production_ix(
+        market.ledger, next_slot_producer, 5, 100, 0, next_slot, EnergySource::Solar, TimeInForce::GoodTilCancelled, 0, false, 0, false, None,
+    )).unwrap();
+    market.bank.process(&client::post_demand_ix(
+        market.ledger, next_slot_buyer, 5, 100, 0, next_slot, TimeInForce::GoodTilCancelled, 0, false, 0, 0, None,
+    )).unwrap();
+
+    // The cap stops the run after its first fill, before any other pair was looked at
+    let summary = crank(&mut market, cheap_producer, &wallets, 1);
+    assert_eq!(summary, MatchRunCompleted {
+        trades_executed: 1,
+        volume: 10,
+        notional: 1_000,
+        skipped_for_balance: 0,
+        skipped_for_band: 0,
+        remaining_open_orders: 4,
+    });
+
+    // The offer at 200 is now outside the band around the reference price of 100, and a crank
+    // reward leaves the next slot's buyer short of its share
+    market.bank.process(&client::update_config_ix(market.ledger, market.admin, ConfigUpdate::CrankRewardBps(100))).unwrap();
+    let summary = crank(&mut market, cheap_producer, &wallets, 16);
+    assert_eq!(summary, MatchRunCompleted {
+        trades_executed: 0,
+        volume: 0,
+        notional: 0,
+        skipped_for_balance: 1,
+        skipped_for_band: 1,
+        remaining_open_orders: 4,
+    });
+}
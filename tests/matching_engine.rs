@@ -0,0 +1,63 @@
+// The pure matcher run off-chain, without a runtime, on books posted through the program
+mod common;
+
+use borsh::BorshSerialize;
+use common::Market;
+use energy_trading_program::{matching::match_orders, Ledger, MarketConfig, Participant, ParticipantType};
+use solana_program::pubkey::Pubkey;
+
+// Two producers and two consumers whose orders all cross
+fn crossing_book() -> (Market, Vec<Pubkey>) {
+    let mut market = Market::new(MarketConfig::default());
+    let wallets = vec![
+        market.register(ParticipantType::Producer, 0),
+        market.register(ParticipantType::Producer, 0),
+        market.register(ParticipantType::Consumer, 1_000),
+        market.register(ParticipantType::Consumer, 1_000),
+    ];
+    market.report_production(wallets[0], 20, 8).unwrap();
+    market.report_production(wallets[1], 20, 9).unwrap();
+    market.post_demand(wallets[2], 15, 10).unwrap();
+    market.post_demand(wallets[3], 15, 10).unwrap();
+    (market, wallets)
+}
+
+fn load(market: &Market, wallets: &[Pubkey]) -> (Ledger, Vec<Participant>) {
+    let participants = wallets.iter().map(|wallet| market.bank.participant(&market.ledger, wallet)).collect();
+    (market.bank.ledger(&market.ledger), participants)
+}
+
+// The serialized orders of `owners`, demands first
+fn orders_of(ledger: &Ledger, owners: &[Pubkey]) -> Vec<Vec<u8>> {
+    let demands = ledger.demands.iter().filter(|d| owners.contains(&d.consumer_id)).map(|d| d.try_to_vec().unwrap());
+    let offers = ledger.productions.iter().filter(|p| owners.contains(&p.producer_id)).map(|p| p.try_to_vec().unwrap());
+    demands.chain(offers).collect()
+}
+
+#[test]
+fn orders_of_absent_participants_are_left_untouched() {
+    let (market, wallets) = crossing_book();
+    let absent = [wallets[1], wallets[3]];
+    let (mut ledger, mut participants) = load(&market, &[wallets[0], wallets[2]]);
+    let run = match_orders(&mut ledger, &mut participants, market.bank.now, usize::MAX).unwrap();
+
+    let fills: Vec<_> = run.trades.iter().map(|t| (t.from, t.to, t.amount)).collect();
+    assert_eq!(fills, vec![(wallets[2], wallets[0], 15)]);
+    assert_eq!(orders_of(&ledger, &absent), orders_of(&market.bank.ledger(&market.ledger), &absent));
+}
+
+#[test]
+fn participant_order_does_not_change_the_outcome() {
+    let (market, wallets) = crossing_book();
+    let (mut ledger, mut participants) = load(&market, &wallets);
+    let trades = match_orders(&mut ledger, &mut participants, market.bank.now, usize::MAX).unwrap().trades;
+    assert_eq!(trades.iter().map(|t| t.amount).sum::<u64>(), 30);
+
+    let reversed: Vec<Pubkey> = wallets.iter().rev().copied().collect();
+    let (mut reversed_ledger, mut reversed_participants) = load(&market, &reversed);
+    let replayed = match_orders(&mut reversed_ledger, &mut reversed_participants, market.bank.now, usize::MAX).unwrap().trades;
+    assert_eq!(replayed.try_to_vec().unwrap(), trades.try_to_vec().unwrap());
+    assert_eq!(reversed_ledger.try_to_vec().unwrap(), ledger.try_to_vec().unwrap());
+    reversed_participants.reverse();
+    assert_eq!(reversed_participants.try_to_vec().unwrap(), participants.try_to_vec().unwrap());
+}
@@ -0,0 +1,240 @@
+// Randomized checks of the matching engine. Each case registers a handful of prosumers with random
+// deposits and posts random orders through the program, so the book is one the instruction handlers
+// would accept, then runs the pure matcher on the stored ledger and participants. Cases are seeded by
+// their index: a failure names the seed, and MATCHING_CASES raises the number of cases run.
+mod common;
+
+use std::collections::HashMap;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use common::Market;
+use energy_trading_program::{
+    client, delivery_slot_at,
+    events::TradeExecuted,
+    matching::{run_matching, split_crank_reward},
+    notional, EnergyMarketInstruction, EnergySource, Ledger, MarketConfig, MarketMode, OrderStorage,
+    Participant, ParticipantMetadata, ParticipantType, TimeInForce,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use solana_program::pubkey::Pubkey;
+
+fn cases() -> u64 {
+    std::env::var("MATCHING_CASES").ok().and_then(|cases| cases.parse().ok()).unwrap_or(64)
+}
+
+struct Book {
+    market: Market,
+    wallets: Vec<Pubkey>,
+}
+
+fn random_book(rng: &mut StdRng) -> Book {
+    let market_mode = if rng.gen_bool(0.3) { MarketMode::UniformPrice } else { MarketMode::PayAsBid };
+    let crank_reward_bps = rng.gen_range(0..=200);
+    let config = MarketConfig {
+        allow_self_trade: rng.gen_bool(0.5),
+        market_mode,
+        crank_reward_bps,
+        fee_bps: rng.gen_range(0..=500),
+        insurance_fee_bps: rng.gen_range(0..=10_000),
+        ..MarketConfig::default()
+    };
+    let mut market = Market::new(config);
+    let wallets: Vec<Pubkey> = (0..rng.gen_range(2..=5))
+        .map(|_| market.register(ParticipantType::Prosumer, rng.gen_range(0..=2_000_000)))
+        .collect();
+
+    let slot = delivery_slot_at(market.bank.now);
+    for _ in 0..rng.gen_range(0..=24) {
+        let wallet = wallets[rng.gen_range(0..wallets.len())];
+        let energy_amount = rng.gen_range(1..=1_000);
+        let price = match rng.gen_range(-50..=1_000) {
+            0 => 1,
+            price => price,
+        };
+        let delivery_slot = slot + rng.gen_range(0..=1);
+        let min_fill = if rng.gen_bool(0.2) { rng.gen_range(1..=energy_amount) } else { 0 };
+        let all_or_nothing = rng.gen_bool(0.1);
+        let instruction = if rng.gen_bool(0.5) {
+            client::report_production_ix(
+                market.ledger, wallet, energy_amount, price, 0, delivery_slot, EnergySource::Solar,
+                TimeInForce::GoodTilCancelled, min_fill, all_or_nothing, 0, rng.gen_bool(0.5), None,
+            )
+        } else {
+            client::post_demand_ix(
+                market.ledger, wallet, energy_amount, price, 0, delivery_slot,
+                TimeInForce::GoodTilCancelled, min_fill, all_or_nothing, 0, 0, None,
+            )
+        };
+        // Orders the program turns down, such as demands beyond the consumer's balance, are simply not in the book
+        let _ = market.bank.process(&instruction);
+    }
+    Book { market, wallets }
+}
+
+fn load(book: &Book) -> (Ledger, Vec<Participant>) {
+    let ledger = book.market.bank.ledger(&book.market.ledger);
+    let participants = book.wallets.iter().map(|wallet| book.market.bank.participant(&book.market.ledger, wallet)).collect();
+    (ledger, participants)
+}
+
+fn total_balance(participants: &[Participant]) -> u128 {
+    participants.iter().map(|p| p.wallet_balance as u128 + p.reserved_balance as u128).sum()
+}
+
+#[test]
+fn matching_preserves_the_market_invariants() {
+    for seed in 0..cases() {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let book = random_book(&mut rng);
+        let (ledger, participants) = load(&book);
+        let now = book.market.bank.now;
+
+        let (mut matched_ledger, mut matched_participants) = load(&book);
+        let trades = run_matching(&mut matched_ledger, &mut matched_participants, now, usize::MAX).map(|run| run.trades)
+            .unwrap_or_else(|error| panic!("seed {}: matching failed with {:?}", seed, error));
+
+        // Every lamport leaving a balance went to the fee pool, the insurance pool or the crank reward
+        let mut crank_reward = 0u128;
+        for trade in &trades {
+            let (buyer_share, seller_share) = split_crank_reward(notional(trade.amount, trade.price), ledger.config.crank_reward_bps).unwrap();
+            crank_reward += buyer_share as u128 + seller_share as u128;
+        }
+        let fees = (matched_ledger.protocol_fees - ledger.protocol_fees) as u128
+            + (matched_ledger.insurance_pool - ledger.insurance_pool) as u128;
+        assert_eq!(total_balance(&participants), total_balance(&matched_participants) + fees + crank_reward, "seed {}: balance not conserved", seed);
+
+        // Each fill trades within both orders' prices, between the orders' owners, for the same delivery slot
+        let demands: HashMap<u64, _> = ledger.demands.iter().map(|d| (d.order_id, d)).collect();
+        let productions: HashMap<u64, _> = ledger.productions.iter().map(|p| (p.order_id, p)).collect();
+        let mut filled: HashMap<u64, u64> = HashMap::new();
+        for trade in &trades {
+            let demand = demands[&trade.demand_order_id];
+            let production = productions[&trade.production_order_id];
+            assert!(trade.amount > 0, "seed {}: empty fill", seed);
+            assert!(production.price <= trade.price && trade.price <= demand.price_limit, "seed {}: trade {:?} outside its orders' prices", seed, trade);
+            assert_eq!((trade.from, trade.to), (demand.consumer_id, production.producer_id), "seed {}", seed);
+            assert_eq!(demand.delivery_slot, production.delivery_slot, "seed {}", seed);
+            if !ledger.config.allow_self_trade {
+                assert_ne!(trade.from, trade.to, "seed {}: self-trade on a ledger that forbids it", seed);
+            }
+            *filled.entry(trade.demand_order_id).or_default() += trade.amount;
+            *filled.entry(trade.production_order_id).or_default() += trade.amount;
+        }
+
+        // An order keeps what its fills left of it and leaves the book once nothing is left
+        let remaining: HashMap<u64, u64> = matched_ledger.demands.iter().map(|d| (d.order_id, d.energy_amount))
+            .chain(matched_ledger.productions.iter().map(|p| (p.order_id, p.energy_amount)))
+            .collect();
+        let original = ledger.demands.iter().map(|d| (d.order_id, d.energy_amount))
+            .chain(ledger.productions.iter().map(|p| (p.order_id, p.energy_amount)));
+        for (order_id, amount) in original {
+            let filled = filled.get(&order_id).copied().unwrap_or(0);
+            assert!(filled <= amount, "seed {}: order {} overfilled", seed, order_id);
+            let left = amount - filled;
+            assert_eq!(remaining.get(&order_id).copied().unwrap_or(0), left, "seed {}: order {} remainder", seed, order_id);
+            assert_eq!(remaining.contains_key(&order_id), left > 0, "seed {}: order {} membership", seed, order_id);
+        }
+
+        // Reservations still cover the escrow of every demand left in the book
+        for participant in &matched_participants {
+            let escrow: u128 = matched_ledger.demands.iter()
+                .filter(|d| d.consumer_id == participant.id)
+                .map(|d| notional(d.energy_amount, d.price_limit.max(0)))
+                .sum();
+            assert!(participant.reserved_balance as u128 >= escrow, "seed {}: {} reserves less than its escrow", seed, participant.id);
+        }
+
+        // The same input always yields the same output
+        let (mut replayed_ledger, mut replayed_participants) = load(&book);
+        let replayed = run_matching(&mut replayed_ledger, &mut replayed_participants, now, usize::MAX).unwrap().trades;
+        assert_eq!(replayed.try_to_vec().unwrap(), trades.try_to_vec().unwrap(), "seed {}: trades differ on replay", seed);
+        assert_eq!(replayed_ledger.try_to_vec().unwrap(), matched_ledger.try_to_vec().unwrap(), "seed {}: ledger differs on replay", seed);
+        assert_eq!(replayed_participants.try_to_vec().unwrap(), matched_participants.try_to_vec().unwrap(), "seed {}", seed);
+    }
+}
+
+#[test]
+fn crank_executes_the_trades_matching_predicts() {
+    for seed in 0..cases() {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut book = random_book(&mut rng);
+        let (mut ledger, mut participants) = load(&book);
+        let predicted = run_matching(&mut ledger, &mut participants, book.market.bank.now, usize::MAX).unwrap().trades;
+
+        let remaining = client::participant_metas(book.market.ledger, &book.wallets);
+        let instruction = match ledger.config.market_mode {
+            MarketMode::PayAsBid => client::match_transactions_ix(book.market.ledger, book.wallets[0], u16::MAX, remaining),
+            MarketMode::UniformPrice => client::run_auction_ix(book.market.ledger, book.wallets[0], remaining),
+        };
+        let (ledger_before, participants_before) = load(&book);
+        let before = total_balance(&participants_before) + ledger_before.protocol_fees as u128 + ledger_before.insurance_pool as u128;
+        book.market.bank.process(&instruction).unwrap_or_else(|error| panic!("seed {}: crank failed with {:?}", seed, error));
+        let executed: Vec<TradeExecuted> = book.market.bank.events();
+        let predicted: Vec<TradeExecuted> = predicted.iter().map(TradeExecuted::from).collect();
+        assert_eq!(executed, predicted, "seed {}", seed);
+
+        // On chain the crank reward is paid to the cranker, so nothing leaves the market
+        let (ledger, participants) = load(&book);
+        assert_eq!(total_balance(&participants) + ledger.protocol_fees as u128 + ledger.insurance_pool as u128, before, "seed {}", seed);
+    }
+}
+
+// Valid encodings of a spread of instructions, as seeds for the mutations below
+fn valid_instruction_data() -> Vec<Vec<u8>> {
+    let ledger = Pubkey::new_unique();
+    let wallet = Pubkey::new_unique();
+    vec![
+        client::report_production_ix(ledger, wallet, 100, -5, 0, 1, EnergySource::Wind, TimeInForce::FillOrKill, 10, true, 7, true, Some(3)).data,
+        client::post_demand_ix(ledger, wallet, 100, 50, 0, 1, TimeInForce::ImmediateOrCancel, 0, false, 8, 0, None).data,
+        client::register_participant_ix(ledger, wallet, ParticipantType::Storage, 2, 500, ParticipantMetadata::new("Plant", "M-1", 1, -1)).data,
+        client::initialize_ledger_ix(ledger, wallet, common::CAPACITY, Some(wallet), MarketConfig::default(), None).data,
+        client::match_transactions_ix(ledger, wallet, 10, Vec::new()).data,
+        client::withdraw_ix(ledger, wallet, wallet, 1, None).data,
+        client::cancel_demand_ix(ledger, wallet, 4, OrderStorage::Accounts).data,
+        client::get_twap_ix(ledger, 3600).data,
+    ]
+}
+
+// Decoding must never panic, and whatever decodes is canonical: borsh reads every byte, so
+// encoding the instruction again gives back exactly the input. unpack accepts the same instructions,
+// and more only when trailing bytes follow one.
+fn assert_decodes_canonically(data: &[u8]) {
+    let unpacked = EnergyMarketInstruction::unpack(data);
+    if let Ok(instruction) = EnergyMarketInstruction::try_from_slice(data) {
+        assert_eq!(instruction.try_to_vec().unwrap(), data, "{:?} decoded from non-canonical data", instruction);
+        assert_eq!(unpacked.unwrap().try_to_vec().unwrap(), data);
+    } else if let Ok(instruction) = unpacked {
+        assert!(data.starts_with(&instruction.try_to_vec().unwrap()), "{:?} unpacked from mismatched data", instruction);
+    }
+}
+
+#[test]
+fn malformed_instruction_data_is_rejected_without_panicking() {
+    let valid = valid_instruction_data();
+    for data in &valid {
+        EnergyMarketInstruction::try_from_slice(data).unwrap();
+        assert_decodes_canonically(data);
+    }
+
+    let mut rng = StdRng::seed_from_u64(0);
+    for _ in 0..cases() * 256 {
+        // Random bytes behind a valid discriminant reach the field decoders of every variant
+        let len = rng.gen_range(0..=256);
+        let mut data: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+        if let Some(discriminant) = data.first_mut() {
+            *discriminant %= 80;
+        }
+        assert_decodes_canonically(&data);
+
+        let mut data = valid[rng.gen_range(0..valid.len())].clone();
+        match rng.gen_range(0..3) {
+            0 => data.truncate(rng.gen_range(0..data.len())),
+            1 => data.push(rng.gen()),
+            _ => {
+                let index = rng.gen_range(0..data.len());
+                data[index] ^= 1 << rng.gen_range(0..8);
+            }
+        }
+        assert_decodes_canonically(&data);
+    }
+}
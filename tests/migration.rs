@@ -0,0 +1,85 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{client, EnergyMarketError, MarketConfig, ParticipantType, LEDGER_VERSION};
+use solana_program::pubkey::Pubkey;
+
+// Writes the version 1 layout field by field, independently of the frozen types in legacy
+#[derive(Default)]
+struct V1Writer(Vec<u8>);
+
+impl V1Writer {
+    fn u8(mut self, value: u8) -> Self {
+        self.0.push(value);
+        self
+    }
+
+    fn u32(mut self, value: u32) -> Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn u64(mut self, value: u64) -> Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn key(mut self, key: &Pubkey) -> Self {
+        self.0.extend_from_slice(key.as_ref());
+        self
+    }
+
+    // Production and demand orders share one layout: id, owner, amount, price, created_at, expires_at
+    fn order(self, order_id: u64, owner: &Pubkey, energy_amount: u64, price: u64, created_at: i64) -> Self {
+        self.u64(order_id).key(owner).u64(energy_amount).u64(price).u64(created_at as u64).u64(0)
+    }
+}
+
+#[test]
+fn migrated_ledger_keeps_its_balances_orders_and_history() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producer, 50, 8).unwrap();
+    market.post_demand(consumer, 50, 10).unwrap();
+    let current = market.bank.ledger(&market.ledger);
+    let (offer, demand) = (&current.productions[0], &current.demands[0]);
+
+    // The same book as version 1 stored it, with one earlier trade of the three ever matched
+    let blob = V1Writer::default()
+        .u8(1).key(&current.admin).u8(current.vault_bump).key(&Pubkey::default())
+        .u8(0).u8(0).u8(0).u8(0)
+        .u32(current.capacity.max_participants).u32(current.capacity.max_open_orders).u32(current.capacity.max_transactions)
+        .u64(current.next_order_id).u64(0).u32(0).u64(3).u32(2).u32(0)
+        .u32(1).order(offer.order_id, &offer.producer_id, 50, 8, offer.created_at)
+        .u32(1).order(demand.order_id, &demand.consumer_id, 50, 10, demand.created_at)
+        .u32(1).u64(90).u64(91).key(&consumer).key(&producer).u64(5).u64(7).u64(market.bank.now as u64 - 60)
+        .0;
+    let data = &mut market.bank.account_mut(&market.ledger).data;
+    data.fill(0);
+    data[..blob.len()].copy_from_slice(&blob);
+
+    assert_eq!(market.match_orders(producer, &[producer, consumer]).unwrap_err(), custom(EnergyMarketError::UnsupportedLedgerVersion));
+    let by_producer = client::migrate_ledger_ix(market.ledger, producer, producer);
+    assert_eq!(market.bank.process(&by_producer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    market.bank.process(&client::migrate_ledger_ix(market.ledger, market.admin, market.admin)).unwrap();
+
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.version, ledger.admin, ledger.total_trades), (LEDGER_VERSION, current.admin, 3));
+    assert_eq!(ledger.next_order_id, current.next_order_id);
+    assert_eq!((ledger.productions[0].order_id, ledger.productions[0].price), (offer.order_id, 8));
+    assert_eq!((ledger.demands[0].order_id, ledger.demands[0].price_limit), (demand.order_id, 10));
+    let trade = &ledger.transactions[0];
+    assert_eq!((trade.trade_id, trade.demand_order_id, trade.from, trade.amount, trade.price), (2, 90, consumer, 5, 7));
+    let buyer = market.bank.participant(&market.ledger, &consumer);
+    assert_eq!((buyer.wallet_balance, buyer.reserved_balance), (500, 500));
+
+    // The migrated book trades on: the demand's escrow pays for the offer
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.total_trades, ledger.transactions[1].trade_id), (4, 3));
+    assert_eq!(market.bank.participant(&market.ledger, &producer).wallet_balance, 400);
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).wallet_balance, 600);
+    let again = client::migrate_ledger_ix(market.ledger, market.admin, market.admin);
+    assert_eq!(market.bank.process(&again).unwrap_err(), custom(EnergyMarketError::UnsupportedLedgerVersion));
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{client::{self, with_approvers}, EnergyMarketError, MarketConfig, MAX_APPROVERS};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+// A ledger whose admin and two more approvers must sign two at a time
+fn two_of_three() -> (Market, Pubkey, Pubkey) {
+    let mut market = Market::new(MarketConfig::default());
+    let (first, second) = (market.bank.funded_wallet(1), market.bank.funded_wallet(1));
+    market.bank.process(&client::add_approver_ix(market.ledger, market.admin, first)).unwrap();
+    market.bank.process(&client::add_approver_ix(market.ledger, market.admin, second)).unwrap();
+    market.bank.process(&client::set_approval_threshold_ix(market.ledger, market.admin, 2)).unwrap();
+    (market, first, second)
+}
+
+fn set_fee(market: &mut Market, admin: Pubkey, approvers: &[Pubkey], fee_bps: u16) -> Result<(), ProgramError> {
+    market.bank.process(&with_approvers(client::set_fee_ix(market.ledger, admin, fee_bps), approvers))
+}
+
+#[test]
+fn a_single_approver_is_not_enough_for_two_of_three() {
+    let (mut market, first, second) = two_of_three();
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!(&ledger.approvers[..3], &[market.admin, first, second]);
+    assert_eq!((ledger.approver_count, ledger.approval_threshold), (3, 2));
+
+    let admin = market.admin;
+    let not_enough = custom(EnergyMarketError::NotEnoughApprovals);
+    assert_eq!(set_fee(&mut market, admin, &[], 100).unwrap_err(), not_enough);
+    assert_eq!(set_fee(&mut market, first, &[], 100).unwrap_err(), not_enough);
+    // Signing twice still counts once
+    assert_eq!(set_fee(&mut market, admin, &[admin], 100).unwrap_err(), not_enough);
+    let stranger = market.bank.funded_wallet(1);
+    assert_eq!(set_fee(&mut market, admin, &[stranger], 100).unwrap_err(), not_enough);
+    assert_eq!(market.bank.ledger(&market.ledger).config.fee_bps, 0);
+}
+
+#[test]
+fn any_two_approvers_pass_a_privileged_instruction() {
+    let (mut market, first, second) = two_of_three();
+    let admin = market.admin;
+    set_fee(&mut market, admin, &[first], 100).unwrap();
+    set_fee(&mut market, first, &[second], 200).unwrap();
+    set_fee(&mut market, second, &[admin, first], 300).unwrap();
+    assert_eq!(market.bank.ledger(&market.ledger).config.fee_bps, 300);
+
+    // The signer in the admin slot must itself be an approver
+    let stranger = market.bank.funded_wallet(1);
+    assert_eq!(set_fee(&mut market, stranger, &[first, second], 400).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+}
+
+#[test]
+fn approvers_rotate_under_the_threshold_they_enforce() {
+    let (mut market, first, second) = two_of_three();
+    let admin = market.admin;
+    let replacement = market.bank.funded_wallet(1);
+
+    // Managing approvers is itself privileged
+    let add = client::add_approver_ix(market.ledger, admin, replacement);
+    assert_eq!(market.bank.process(&add).unwrap_err(), custom(EnergyMarketError::NotEnoughApprovals));
+    market.bank.process(&with_approvers(add, &[first])).unwrap();
+    let remove = client::remove_approver_ix(market.ledger, first, admin);
+    market.bank.process(&with_approvers(remove, &[second])).unwrap();
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!(&ledger.approvers[..3], &[first, second, replacement]);
+    assert_eq!(ledger.approver_count, 3);
+
+    // The removed admin key no longer counts, the new approver does
+    assert_eq!(set_fee(&mut market, admin, &[first], 100).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    assert_eq!(set_fee(&mut market, first, &[admin], 100).unwrap_err(), custom(EnergyMarketError::NotEnoughApprovals));
+    set_fee(&mut market, replacement, &[second], 100).unwrap();
+
+    // Removals stop where the threshold would become unreachable
+    let remove = with_approvers(client::remove_approver_ix(market.ledger, first, replacement), &[second]);
+    market.bank.process(&remove).unwrap();
+    let remove = with_approvers(client::remove_approver_ix(market.ledger, first, second), &[second]);
+    assert_eq!(market.bank.process(&remove).unwrap_err(), custom(EnergyMarketError::InvalidApprovers));
+    let lower = with_approvers(client::set_approval_threshold_ix(market.ledger, first, 1), &[second]);
+    market.bank.process(&lower).unwrap();
+    market.bank.process(&remove).unwrap();
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.approvers[0], ledger.approver_count, ledger.approval_threshold), (first, 1, 1));
+    set_fee(&mut market, first, &[], 200).unwrap();
+}
+
+#[test]
+fn approver_lists_and_thresholds_stay_valid() {
+    let mut market = Market::new(MarketConfig::default());
+    let admin = market.admin;
+    // A ledger without approvers still takes its admin alone
+    set_fee(&mut market, admin, &[], 100).unwrap();
+    let invalid = custom(EnergyMarketError::InvalidApprovers);
+    let threshold = client::set_approval_threshold_ix(market.ledger, admin, 1);
+    assert_eq!(market.bank.process(&threshold).unwrap_err(), invalid);
+
+    for _ in 1..MAX_APPROVERS {
+        let approver = market.bank.funded_wallet(1);
+        market.bank.process(&client::add_approver_ix(market.ledger, admin, approver)).unwrap();
+    }
+    let sixth = market.bank.funded_wallet(1);
+    assert_eq!(market.bank.process(&client::add_approver_ix(market.ledger, admin, sixth)).unwrap_err(), invalid);
+    let removed = market.bank.ledger(&market.ledger).approvers[1];
+    market.bank.process(&client::remove_approver_ix(market.ledger, admin, removed)).unwrap();
+    for approver in [admin, Pubkey::default()] {
+        assert_eq!(market.bank.process(&client::add_approver_ix(market.ledger, admin, approver)).unwrap_err(), invalid);
+    }
+    assert_eq!(market.bank.process(&client::remove_approver_ix(market.ledger, admin, removed)).unwrap_err(), invalid);
+    for threshold in [0, MAX_APPROVERS as u8] {
+        let threshold = client::set_approval_threshold_ix(market.ledger, admin, threshold);
+        assert_eq!(market.bank.process(&threshold).unwrap_err(), invalid);
+    }
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::Market;
+use energy_trading_program::{client, events::TradeExecuted, MarketConfig, ParticipantType};
+use solana_program::pubkey::Pubkey;
+
+fn balances(market: &Market, wallet: &Pubkey) -> (u64, u64) {
+    let participant = market.bank.participant(&market.ledger, wallet);
+    (participant.wallet_balance, participant.reserved_balance)
+}
+
+#[test]
+fn producer_pays_the_consumer_below_zero() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 500);
+    let consumer = market.register(ParticipantType::Consumer, 100);
+    market.report_production(producer, 10, -5).unwrap();
+    // A positive limit takes any negative offer, and its escrow comes back untouched
+    market.post_demand(consumer, 10, 10).unwrap();
+    assert_eq!(balances(&market, &consumer), (0, 100));
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+
+    let trades = market.bank.events::<TradeExecuted>();
+    assert_eq!((trades.len(), trades[0].price, trades[0].amount), (1, -5, 10));
+    assert_eq!(market.bank.ledger(&market.ledger).transactions[0].price, -5);
+    assert_eq!((balances(&market, &producer), balances(&market, &consumer)), ((450, 0), (150, 0)));
+
+    market.bank.process(&client::withdraw_ix(market.ledger, consumer, consumer, 150, None)).unwrap();
+    assert_eq!(balances(&market, &consumer), (0, 0));
+}
+
+#[test]
+fn negative_limit_only_takes_offers_paying_at_least_that_much() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 500);
+    let consumer = market.register(ParticipantType::Consumer, 0);
+    market.report_production(producer, 10, -5).unwrap();
+    // Expecting to be paid 6 per kWh, the consumer reserves nothing and passes on an offer paying 5
+    market.post_demand(consumer, 10, -6).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    assert!(market.bank.events::<TradeExecuted>().is_empty());
+    assert_eq!(balances(&market, &consumer), (0, 0));
+
+    market.report_production(producer, 10, -8).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    let fills: Vec<_> = market.bank.events::<TradeExecuted>().iter().map(|t| (t.price, t.amount)).collect();
+    assert_eq!(fills, vec![(-8, 10)]);
+    assert_eq!((balances(&market, &producer), balances(&market, &consumer)), ((420, 0), (80, 0)));
+}
+
+#[test]
+fn producer_that_cannot_pay_does_not_match() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 500);
+    let consumer = market.register(ParticipantType::Consumer, 0);
+    market.report_production(producer, 100, -5).unwrap();
+    // The payment is only taken at match time, so nothing keeps the producer from emptying its balance
+    market.bank.process(&client::withdraw_ix(market.ledger, producer, producer, 500, None)).unwrap();
+    market.post_demand(consumer, 100, -1).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    assert!(market.bank.events::<TradeExecuted>().is_empty());
+    assert_eq!(market.bank.ledger(&market.ledger).productions[0].energy_amount, 100);
+    assert_eq!(balances(&market, &consumer), (0, 0));
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use common::Market;
+use energy_trading_program::{client, events::TradeExecuted, EnergyMarketError, MarketConfig, ParticipantType, TradeStatus};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+fn net_metered_prosumer(market: &mut Market, deposit: u64) -> Pubkey {
+    let prosumer = market.register(ParticipantType::Prosumer, deposit);
+    market.bank.process(&client::set_net_metering_ix(market.ledger, prosumer, true)).unwrap();
+    prosumer
+}
+
+#[test]
+fn own_demand_is_fully_offset_at_no_cost() {
+    let mut market = Market::new(MarketConfig { fee_bps: 1_000, ..MarketConfig::default() });
+    let prosumer = net_metered_prosumer(&mut market, 1_000);
+    market.report_production(prosumer, 100, 8).unwrap();
+    market.post_demand(prosumer, 100, 10).unwrap();
+    market.match_orders(prosumer, &[prosumer]).unwrap();
+
+    // The netting is reported like a trade with the prosumer on both sides, at a price of zero
+    let trades = market.bank.events::<TradeExecuted>();
+    assert_eq!(trades.iter().map(|t| (t.buyer, t.seller, t.amount, t.price)).collect::<Vec<_>>(), vec![(prosumer, prosumer, 100, 0)]);
+    let ledger = market.bank.ledger(&market.ledger);
+    assert!(ledger.productions.is_empty() && ledger.demands.is_empty());
+    let netting = ledger.transactions.last().unwrap();
+    assert!(netting.netting);
+    assert_eq!((netting.from, netting.to, netting.amount, netting.price), (prosumer, prosumer, 100, 0));
+    assert_eq!((netting.status, netting.trade_id), (TradeStatus::Settled, ledger.total_trades - 1));
+    assert_eq!(ledger.protocol_fees, 0);
+    let participant = market.bank.participant(&market.ledger, &prosumer);
+    assert_eq!((participant.wallet_balance, participant.reserved_balance), (1_000, 0));
+}
+
+#[test]
+fn remaining_imbalance_trades_with_the_market() {
+    let mut market = Market::new(MarketConfig::default());
+    let prosumer = net_metered_prosumer(&mut market, 1_000);
+    let neighbour = market.register(ParticipantType::Producer, 0);
+    market.report_production(prosumer, 40, 8).unwrap();
+    market.post_demand(prosumer, 100, 10).unwrap();
+    market.report_production(neighbour, 60, 9).unwrap();
+    market.match_orders(neighbour, &[prosumer, neighbour]).unwrap();
+
+    // 40 kWh are offset behind the meter and the other 60 are bought from the neighbour
+    let trades = market.bank.events::<TradeExecuted>();
+    let fills: Vec<(u64, Pubkey, u64, i64)> = trades.iter().map(|t| (t.trade_id, t.seller, t.amount, t.price)).collect();
+    assert_eq!(fills, vec![(0, prosumer, 40, 0), (1, neighbour, 60, 9)]);
+    let ledger = market.bank.ledger(&market.ledger);
+    let recorded: Vec<(u64, u64, bool)> = ledger.transactions.iter().map(|t| (t.trade_id, t.amount, t.netting)).collect();
+    assert_eq!(recorded, vec![(0, 40, true), (1, 60, false)]);
+    assert!(ledger.productions.is_empty() && ledger.demands.is_empty());
+    assert_eq!(market.bank.participant(&market.ledger, &prosumer).wallet_balance, 1_000 - 540);
+    assert_eq!(market.bank.participant(&market.ledger, &neighbour).wallet_balance, 540);
+}
+
+#[test]
+fn without_the_flag_own_orders_are_left_to_the_market() {
+    let mut market = Market::new(MarketConfig::default());
+    let prosumer = market.register(ParticipantType::Prosumer, 1_000);
+    let consumer = market.register(ParticipantType::Consumer, 0);
+    assert_eq!(
+        market.bank.process(&client::set_net_metering_ix(market.ledger, consumer, true)).unwrap_err(),
+        ProgramError::Custom(EnergyMarketError::InvalidParticipantType as u32),
+    );
+
+    // Self-trades are not allowed, so the prosumer's two orders stay in the book
+    market.report_production(prosumer, 100, 8).unwrap();
+    market.post_demand(prosumer, 100, 10).unwrap();
+    market.match_orders(prosumer, &[prosumer]).unwrap();
+    let ledger = market.bank.ledger(&market.ledger);
+    assert!(ledger.transactions.is_empty());
+    assert_eq!((ledger.productions.len(), ledger.demands.len()), (1, 1));
+
+    // Switching it on and back off again changes nothing either
+    market.bank.process(&client::set_net_metering_ix(market.ledger, prosumer, true)).unwrap();
+    market.bank.process(&client::set_net_metering_ix(market.ledger, prosumer, false)).unwrap();
+    market.match_orders(prosumer, &[prosumer]).unwrap();
+    assert!(market.bank.ledger(&market.ledger).transactions.is_empty());
+    assert_eq!(market.bank.participant(&market.ledger, &prosumer).reserved_balance, 1_000);
+}
+
+#[test]
+fn nettings_count_against_the_match_budget() {
+    let mut market = Market::new(MarketConfig::default());
+    let prosumer = net_metered_prosumer(&mut market, 1_000);
+    let neighbour = market.register(ParticipantType::Producer, 0);
+    market.report_production(prosumer, 50, 8).unwrap();
+    market.report_production(prosumer, 30, 9).unwrap();
+    market.report_production(neighbour, 20, 10).unwrap();
+    market.post_demand(prosumer, 100, 10).unwrap();
+
+    // A budget of one covers the first netting only; the next crank nets the rest and buys from the neighbour
+    let crank = |max_matches| client::match_transactions_ix(
+        market.ledger, neighbour, max_matches, client::participant_metas(market.ledger, &[prosumer, neighbour]),
+    );
+    market.bank.process(&crank(1)).unwrap();
+    let fills = |market: &Market| market.bank.events::<TradeExecuted>().iter().map(|t| (t.seller, t.amount)).collect::<Vec<_>>();
+    assert_eq!(fills(&market), vec![(prosumer, 50)]);
+    market.bank.process(&crank(2)).unwrap();
+    assert_eq!(fills(&market), vec![(prosumer, 30), (neighbour, 20)]);
+}
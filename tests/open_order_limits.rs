@@ -0,0 +1,128 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, delivery_slot_at, EnergyMarketError, EnergySource, MarketConfig, OrderStorage, ParticipantType, TimeInForce,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+fn limited_market(max_open_orders_per_participant: u32) -> Market {
+    Market::new(MarketConfig { max_open_orders_per_participant, allow_self_trade: true, ..MarketConfig::default() })
+}
+
+fn demand(market: &mut Market, consumer: Pubkey, energy_amount: u64, expires_at: i64) -> Result<(), ProgramError> {
+    let slot = delivery_slot_at(market.bank.now);
+    market.bank.process(&client::post_demand_ix(
+        market.ledger, consumer, energy_amount, 10, expires_at, slot, TimeInForce::GoodTilCancelled, 0, false, 0, 0, None,
+    ))
+}
+
+// Each offer is its own lot, so same-price offers stay separate orders
+fn offer(market: &mut Market, producer: Pubkey, energy_amount: u64, expires_at: i64) -> Result<(), ProgramError> {
+    let slot = delivery_slot_at(market.bank.now);
+    market.bank.process(&client::report_production_ix(
+        market.ledger, producer, energy_amount, 10, expires_at, slot, EnergySource::Solar, TimeInForce::GoodTilCancelled, 0, false, 0,
+        true, None,
+    ))
+}
+
+fn open_orders(market: &Market, wallet: &Pubkey) -> (u32, u32) {
+    let participant = market.bank.participant(&market.ledger, wallet);
+    (participant.open_demands, participant.open_productions)
+}
+
+// The counters of every wallet agree with what the book holds for it
+fn assert_counts_match_book(market: &Market, wallets: &[Pubkey]) {
+    let ledger = market.bank.ledger(&market.ledger);
+    for wallet in wallets {
+        let demands = ledger.demands.iter().filter(|d| d.consumer_id == *wallet).count() as u32;
+        let productions = ledger.productions.iter().filter(|p| p.producer_id == *wallet).count() as u32;
+        assert_eq!(open_orders(market, wallet), (demands, productions), "open orders of {}", wallet);
+    }
+}
+
+#[test]
+fn limit_counts_both_sides_together() {
+    let mut market = limited_market(3);
+    let prosumer = market.register(ParticipantType::Prosumer, 1_000);
+    demand(&mut market, prosumer, 10, 0).unwrap();
+    demand(&mut market, prosumer, 10, 0).unwrap();
+    offer(&mut market, prosumer, 10, 0).unwrap();
+    assert_eq!(offer(&mut market, prosumer, 10, 0).unwrap_err(), custom(EnergyMarketError::TooManyOpenOrders));
+    assert_eq!(demand(&mut market, prosumer, 10, 0).unwrap_err(), custom(EnergyMarketError::TooManyOpenOrders));
+    assert_eq!(open_orders(&market, &prosumer), (2, 1));
+
+    // Cancelling an order frees its slot, and other participants have their own
+    market.bank.process(&client::cancel_demand_ix(market.ledger, prosumer, 0, OrderStorage::Ledger)).unwrap();
+    offer(&mut market, prosumer, 10, 0).unwrap();
+    assert_eq!(open_orders(&market, &prosumer), (1, 2));
+    let other = market.register(ParticipantType::Consumer, 1_000);
+    demand(&mut market, other, 10, 0).unwrap();
+}
+
+#[test]
+fn fills_and_expiry_free_slots_but_partial_fills_do_not() {
+    let mut market = limited_market(2);
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    offer(&mut market, producer, 30, 0).unwrap();
+    let expires_at = market.bank.now + 60;
+    offer(&mut market, producer, 10, expires_at).unwrap();
+    demand(&mut market, consumer, 20, 0).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+
+    // The demand filled and is gone; the first offer still has 10 left
+    assert_eq!(open_orders(&market, &consumer), (0, 0));
+    assert_eq!(open_orders(&market, &producer), (0, 2));
+    assert_eq!(offer(&mut market, producer, 10, 0).unwrap_err(), custom(EnergyMarketError::TooManyOpenOrders));
+
+    market.bank.now += 61;
+    market.bank.process(&client::prune_expired_orders_ix(market.ledger, client::participant_metas(market.ledger, &[producer]))).unwrap();
+    assert_eq!(open_orders(&market, &producer), (0, 1));
+    offer(&mut market, producer, 10, 0).unwrap();
+    assert_counts_match_book(&market, &[producer, consumer]);
+}
+
+#[test]
+fn counters_stay_consistent_across_random_interleavings() {
+    for seed in 0..16 {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut market = limited_market(4);
+        let wallets: Vec<Pubkey> = (0..4).map(|_| market.register(ParticipantType::Prosumer, 1_000_000)).collect();
+
+        for _ in 0..60 {
+            let wallet = wallets[rng.gen_range(0..wallets.len())];
+            let expires_at = if rng.gen_bool(0.3) { market.bank.now + rng.gen_range(1..120) } else { 0 };
+            let (demands, productions) = open_orders(&market, &wallet);
+            let result = match rng.gen_range(0..6) {
+                0 => demand(&mut market, wallet, rng.gen_range(1..50), expires_at),
+                1 => offer(&mut market, wallet, rng.gen_range(1..50), expires_at),
+                2 => {
+                    let ledger = market.bank.ledger(&market.ledger);
+                    match ledger.demands.iter().find(|d| d.consumer_id == wallet) {
+                        Some(d) => market.bank.process(&client::cancel_demand_ix(market.ledger, wallet, d.order_id, OrderStorage::Ledger)),
+                        None => Ok(()),
+                    }
+                }
+                3 => {
+                    let ledger = market.bank.ledger(&market.ledger);
+                    match ledger.productions.iter().find(|p| p.producer_id == wallet) {
+                        Some(p) => market.bank.process(&client::cancel_production_ix(market.ledger, wallet, p.order_id, OrderStorage::Ledger)),
+                        None => Ok(()),
+                    }
+                }
+                4 => market.match_orders(wallet, &wallets),
+                _ => {
+                    market.bank.now += rng.gen_range(1..90);
+                    market.bank.process(&client::prune_expired_orders_ix(market.ledger, client::participant_metas(market.ledger, &wallets)))
+                }
+            };
+            if let Err(error) = result {
+                assert_eq!(error, custom(EnergyMarketError::TooManyOpenOrders), "seed {}", seed);
+                assert_eq!(demands + productions, 4, "seed {}", seed);
+            }
+            assert_counts_match_book(&market, &wallets);
+        }
+    }
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use borsh::BorshDeserialize;
+use common::{custom, program_id, Market};
+use energy_trading_program::{
+    client, delivery_slot_at, find_order_address, EnergyMarketError, EnergySource, MarketConfig, OrderAccount, OrderStorage,
+    ParticipantType, TimeInForce, ORDER_ACCOUNT_SIZE,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey, rent::Rent};
+
+fn accounts_market() -> Market {
+    Market::new(MarketConfig { order_storage: OrderStorage::Accounts, ..MarketConfig::default() })
+}
+
+// Posts an offer or demand into its own PDA, returning the order id and address
+fn post(market: &mut Market, owner: Pubkey, production: bool, energy_amount: u64, price: i64) -> Result<(u64, Pubkey), ProgramError> {
+    let order_id = market.bank.ledger(&market.ledger).next_order_id;
+    let slot = delivery_slot_at(market.bank.now);
+    let instruction = if production {
+        client::report_production_ix(
+            market.ledger, owner, energy_amount, price, 0, slot, EnergySource::Solar, TimeInForce::GoodTilCancelled, 0, false, 0, false,
+            Some(order_id),
+        )
+    } else {
+        client::post_demand_ix(
+            market.ledger, owner, energy_amount, price, 0, slot, TimeInForce::GoodTilCancelled, 0, false, 0, 0, Some(order_id),
+        )
+    };
+    market.bank.process(&instruction)?;
+    Ok((order_id, find_order_address(&program_id(), &market.ledger, order_id).0))
+}
+
+#[test]
+fn owner_pays_for_the_order_account_and_gets_it_back_on_cancel() {
+    let mut market = accounts_market();
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    let lamports = market.bank.lamports(&consumer);
+    let (order_id, address) = post(&mut market, consumer, false, 10, 10).unwrap();
+
+    let rent = Rent::default().minimum_balance(ORDER_ACCOUNT_SIZE);
+    let account = market.bank.account(&address).unwrap();
+    assert_eq!((account.owner, account.lamports), (program_id(), rent));
+    assert_eq!(market.bank.lamports(&consumer), lamports - rent);
+    let ledger = market.bank.ledger(&market.ledger);
+    assert!(ledger.demands.is_empty());
+    assert_eq!(ledger.open_order_accounts, 1);
+
+    market.bank.process(&client::cancel_demand_ix(market.ledger, consumer, order_id, OrderStorage::Accounts)).unwrap();
+    assert_eq!((market.bank.lamports(&address), market.bank.lamports(&consumer)), (0, lamports));
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).wallet_balance, 1_000);
+}
+
+#[test]
+fn crank_crosses_the_order_accounts_it_is_given_and_closes_filled_ones() {
+    let mut market = accounts_market();
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    let (offer, offer_address) = post(&mut market, producer, true, 30, 10).unwrap();
+    let (demand, demand_address) = post(&mut market, consumer, false, 10, 10).unwrap();
+
+    // A program-owned copy of the demand at another address does not pass for its PDA
+    let copy = market.bank.program_account(ORDER_ACCOUNT_SIZE);
+    market.bank.account_mut(&copy).data = market.bank.account(&demand_address).unwrap().data.clone();
+    let mut remaining = client::order_metas(market.ledger, &[(offer, producer), (demand, consumer)]);
+    remaining[3].pubkey = copy;
+    let crank = client::match_transactions_ix(market.ledger, producer, 16, remaining);
+    assert_eq!(market.bank.process(&crank).unwrap_err(), custom(EnergyMarketError::InvalidOrderAccount));
+
+    let remaining = client::order_metas(market.ledger, &[(offer, producer), (demand, consumer)]);
+    market.bank.process(&client::match_transactions_ix(market.ledger, producer, 16, remaining)).unwrap();
+    assert_eq!(market.bank.lamports(&demand_address), 0);
+    let offer_account = OrderAccount::deserialize(&mut market.bank.account(&offer_address).unwrap().data.as_slice()).unwrap();
+    assert_eq!(offer_account.energy_amount, 20);
+    assert_eq!(market.bank.ledger(&market.ledger).open_order_accounts, 1);
+    assert_eq!(market.bank.participant(&market.ledger, &producer).wallet_balance, 100);
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use common::Market;
+use energy_trading_program::{Ledger, MarketConfig, ParticipantType};
+
+#[test]
+fn orders_get_increasing_ids_that_trades_record() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producer, 30, 10).unwrap();
+    market.post_demand(consumer, 30, 10).unwrap();
+    market.post_demand(consumer, 30, 10).unwrap();
+
+    // Two demands alike in every other field are told apart by their ids
+    let ledger = market.bank.ledger(&market.ledger);
+    let offer_id = ledger.productions[0].order_id;
+    let demand_ids: Vec<u64> = ledger.demands.iter().map(|d| d.order_id).collect();
+    assert_eq!(demand_ids, vec![offer_id + 1, offer_id + 2]);
+    assert_eq!(ledger.next_order_id, offer_id + 3);
+
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+    let ledger = market.bank.ledger(&market.ledger);
+    let trade = &ledger.transactions[0];
+    assert_eq!((trade.production_order_id, trade.demand_order_id), (offer_id, offer_id + 1));
+    assert_eq!(ledger.demands[0].order_id, offer_id + 2);
+}
+
+#[test]
+fn ledger_with_orders_and_trades_roundtrips() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producer, 50, 10).unwrap();
+    market.post_demand(consumer, 20, 10).unwrap();
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+    market.post_demand(consumer, 5, 9).unwrap();
+
+    let ledger = market.bank.ledger(&market.ledger);
+    assert!(!ledger.productions.is_empty() && !ledger.demands.is_empty() && !ledger.transactions.is_empty());
+    let bytes = ledger.try_to_vec().unwrap();
+    let decoded = Ledger::try_from_slice(&bytes).unwrap();
+    assert_eq!(decoded.try_to_vec().unwrap(), bytes);
+    assert_eq!(format!("{:?}", decoded), format!("{:?}", ledger));
+}
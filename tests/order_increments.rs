@@ -0,0 +1,49 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{client, EnergyMarketError, MarketConfig, ParticipantType};
+
+// Prices move in steps of 5 and amounts in lots of 10 kWh
+fn incremented_market() -> Market {
+    Market::new(MarketConfig { price_tick: 5, lot_size: 10, ..MarketConfig::default() })
+}
+
+#[test]
+fn orders_on_the_tick_and_lot_are_accepted() {
+    let mut market = incremented_market();
+    let producer = market.register(ParticipantType::Producer, 1_000);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producer, 20, 10).unwrap();
+    market.report_production(producer, 10, -5).unwrap();
+    market.post_demand(consumer, 30, 15).unwrap();
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.config.price_tick, ledger.config.lot_size), (5, 10));
+}
+
+#[test]
+fn off_tick_and_off_lot_orders_are_rejected_on_both_sides() {
+    let mut market = incremented_market();
+    let producer = market.register(ParticipantType::Producer, 1_000);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    assert_eq!(market.report_production(producer, 20, 7).unwrap_err(), custom(EnergyMarketError::OffTickPrice));
+    assert_eq!(market.report_production(producer, 20, -7).unwrap_err(), custom(EnergyMarketError::OffTickPrice));
+    assert_eq!(market.report_production(producer, 15, 10).unwrap_err(), custom(EnergyMarketError::OffLotAmount));
+    assert_eq!(market.post_demand(consumer, 20, 12).unwrap_err(), custom(EnergyMarketError::OffTickPrice));
+    assert_eq!(market.post_demand(consumer, 5, 10).unwrap_err(), custom(EnergyMarketError::OffLotAmount));
+    let ledger = market.bank.ledger(&market.ledger);
+    assert!(ledger.productions.is_empty() && ledger.demands.is_empty());
+}
+
+#[test]
+fn zero_increments_disable_the_checks() {
+    let mut market = incremented_market();
+    let producer = market.register(ParticipantType::Producer, 0);
+    let by_producer = client::set_order_increments_ix(market.ledger, producer, 0, 0);
+    assert_eq!(market.bank.process(&by_producer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+
+    market.bank.process(&client::set_order_increments_ix(market.ledger, market.admin, 0, 0)).unwrap();
+    market.report_production(producer, 15, 7).unwrap();
+    market.bank.process(&client::set_order_increments_ix(market.ledger, market.admin, 2, 0)).unwrap();
+    assert_eq!(market.report_production(producer, 15, 9).unwrap_err(), custom(EnergyMarketError::OffTickPrice));
+    market.report_production(producer, 15, 8).unwrap();
+}
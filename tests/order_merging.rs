@@ -0,0 +1,69 @@
+mod common;
+
+use common::Market;
+use energy_trading_program::{
+    client, delivery_slot_at,
+    events::{OrderAmended, OrderCancelled},
+    EnergySource, MarketConfig, OrderSide, OrderStorage, ParticipantType, TimeInForce,
+};
+use solana_program::pubkey::Pubkey;
+
+fn report_lot(market: &mut Market, producer: Pubkey, energy_amount: u64, price: i64, distinct_lot: bool) {
+    let slot = delivery_slot_at(market.bank.now);
+    market.bank.process(&client::report_production_ix(
+        market.ledger, producer, energy_amount, price, 0, slot, EnergySource::Solar, TimeInForce::GoodTilCancelled, 0, false, 0,
+        distinct_lot, None,
+    )).unwrap();
+}
+
+#[test]
+fn same_price_offers_merge_into_one_order() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+
+    market.report_production(producer, 100, 1_000).unwrap();
+    let order_id = market.bank.ledger(&market.ledger).productions[0].order_id;
+    market.report_production(producer, 40, 1_000).unwrap();
+    assert_eq!(market.bank.events::<OrderAmended>(), vec![OrderAmended { order_id, owner: producer, added_amount: 40, energy_amount: 140 }]);
+
+    // A different price rests on its own
+    market.report_production(producer, 10, 1_100).unwrap();
+    assert!(market.bank.events::<OrderAmended>().is_empty());
+
+    let ledger = market.bank.ledger(&market.ledger);
+    let offers: Vec<(u64, u64, i64)> = ledger.productions.iter().map(|p| (p.order_id, p.energy_amount, p.price)).collect();
+    assert_eq!(offers, vec![(order_id, 140, 1_000), (ledger.next_order_id - 1, 10, 1_100)]);
+    assert_eq!(market.bank.participant(&market.ledger, &producer).open_productions, 2);
+}
+
+#[test]
+fn distinct_lot_keeps_offers_apart() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+
+    report_lot(&mut market, producer, 100, 1_000, true);
+    report_lot(&mut market, producer, 40, 1_000, true);
+    assert!(market.bank.events::<OrderAmended>().is_empty());
+    // A merging offer may still join one of the distinct lots
+    report_lot(&mut market, producer, 5, 1_000, false);
+
+    let amounts: Vec<u64> = market.bank.ledger(&market.ledger).productions.iter().map(|p| p.energy_amount).collect();
+    assert_eq!(amounts, vec![105, 40]);
+    assert_eq!(market.bank.participant(&market.ledger, &producer).open_productions, 2);
+}
+
+#[test]
+fn merged_order_cancels_as_a_unit() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    market.report_production(producer, 100, 1_000).unwrap();
+    market.report_production(producer, 25, 1_000).unwrap();
+    market.report_production(producer, 75, 1_000).unwrap();
+    let order_id = market.bank.ledger(&market.ledger).productions[0].order_id;
+
+    market.bank.process(&client::cancel_production_ix(market.ledger, producer, order_id, OrderStorage::Ledger)).unwrap();
+    let cancelled: Vec<OrderCancelled> = market.bank.events();
+    assert_eq!(cancelled, vec![OrderCancelled { order_id, owner: producer, side: OrderSide::Production, remaining_amount: 200 }]);
+    assert!(market.bank.ledger(&market.ledger).productions.is_empty());
+    assert_eq!(market.bank.participant(&market.ledger, &producer).open_productions, 0);
+}
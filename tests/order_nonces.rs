@@ -0,0 +1,79 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, delivery_slot_at, EnergyMarketError, EnergySource, MarketConfig, ParticipantType, TimeInForce, RECENT_NONCES,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+fn demand(market: &mut Market, consumer: Pubkey, nonce: u64) -> Result<(), ProgramError> {
+    let slot = delivery_slot_at(market.bank.now);
+    market.bank.process(&client::post_demand_ix(
+        market.ledger, consumer, 10, 10, 0, slot, TimeInForce::GoodTilCancelled, 0, false, nonce, 0, None,
+    ))
+}
+
+fn offer(market: &mut Market, producer: Pubkey, nonce: u64) -> Result<(), ProgramError> {
+    let slot = delivery_slot_at(market.bank.now);
+    market.bank.process(&client::report_production_ix(
+        market.ledger, producer, 10, 10, 0, slot, EnergySource::Solar, TimeInForce::GoodTilCancelled, 0, false, nonce, false, None,
+    ))
+}
+
+#[test]
+fn replayed_nonce_is_a_duplicate_order() {
+    let mut market = Market::new(MarketConfig::default());
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    let producer = market.register(ParticipantType::Producer, 0);
+
+    demand(&mut market, consumer, 7).unwrap();
+    assert_eq!(demand(&mut market, consumer, 7).unwrap_err(), custom(EnergyMarketError::DuplicateOrder));
+    demand(&mut market, consumer, 8).unwrap();
+    offer(&mut market, producer, 7).unwrap();
+    assert_eq!(offer(&mut market, producer, 7).unwrap_err(), custom(EnergyMarketError::DuplicateOrder));
+
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.demands.len(), ledger.productions.len()), (2, 1));
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).reserved_balance, 200);
+}
+
+#[test]
+fn zero_nonce_opts_out() {
+    let mut market = Market::new(MarketConfig::default());
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    for _ in 0..3 {
+        demand(&mut market, consumer, 0).unwrap();
+    }
+    assert_eq!(market.bank.ledger(&market.ledger).demands.len(), 3);
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).recent_nonces, [0; RECENT_NONCES]);
+}
+
+#[test]
+fn oldest_nonce_is_evicted_once_the_window_is_full() {
+    let mut market = Market::new(MarketConfig::default());
+    let consumer = market.register(ParticipantType::Consumer, 10_000);
+    for nonce in 1..=RECENT_NONCES as u64 {
+        demand(&mut market, consumer, nonce).unwrap();
+    }
+    assert_eq!(demand(&mut market, consumer, 1).unwrap_err(), custom(EnergyMarketError::DuplicateOrder));
+
+    // The 17th nonce overwrites the 1st, which can then be used again; that evicts the 2nd in turn,
+    // while the 3rd is still held
+    demand(&mut market, consumer, 100).unwrap();
+    demand(&mut market, consumer, 1).unwrap();
+    assert_eq!(demand(&mut market, consumer, 3).unwrap_err(), custom(EnergyMarketError::DuplicateOrder));
+    assert_eq!(market.bank.ledger(&market.ledger).demands.len(), RECENT_NONCES + 2);
+}
+
+#[test]
+fn rejected_post_leaves_its_nonce_unused() {
+    let mut market = Market::new(MarketConfig::default());
+    let consumer = market.register(ParticipantType::Consumer, 50);
+    let other = market.register(ParticipantType::Consumer, 1_000);
+
+    assert_eq!(demand(&mut market, consumer, 9).unwrap_err(), custom(EnergyMarketError::InsufficientBalance));
+    market.bank.process(&client::deposit_ix(market.ledger, consumer, 50, None)).unwrap();
+    demand(&mut market, consumer, 9).unwrap();
+    // Nonces are per participant
+    demand(&mut market, other, 9).unwrap();
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{client, events::TradeExecuted, EnergyMarketError, MarketConfig, ParticipantType};
+
+fn sized_market() -> Market {
+    Market::new(MarketConfig { min_order_size: 10, max_order_size: 100, ..MarketConfig::default() })
+}
+
+#[test]
+fn orders_at_exactly_the_limits_are_accepted() {
+    let mut market = sized_market();
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 10_000);
+    assert_eq!(market.report_production(producer, 9, 10).unwrap_err(), custom(EnergyMarketError::MinOrderSizeNotMet));
+    assert_eq!(market.report_production(producer, 101, 11).unwrap_err(), custom(EnergyMarketError::MaxOrderSizeExceeded));
+    market.report_production(producer, 10, 10).unwrap();
+    market.report_production(producer, 100, 11).unwrap();
+
+    assert_eq!(market.post_demand(consumer, 9, 5).unwrap_err(), custom(EnergyMarketError::MinOrderSizeNotMet));
+    assert_eq!(market.post_demand(consumer, 101, 5).unwrap_err(), custom(EnergyMarketError::MaxOrderSizeExceeded));
+    market.post_demand(consumer, 10, 5).unwrap();
+    market.post_demand(consumer, 100, 5).unwrap();
+}
+
+#[test]
+fn orders_above_a_lowered_maximum_are_skipped_but_small_remainders_trade() {
+    let mut market = sized_market();
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 10_000);
+    market.report_production(producer, 100, 10).unwrap();
+    market.bank.process(&client::set_order_size_limits_ix(market.ledger, market.admin, 10, 50)).unwrap();
+    market.post_demand(consumer, 50, 10).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    assert!(market.bank.events::<TradeExecuted>().is_empty());
+
+    // Back under a maximum of 100 the offer trades down to a remainder below the minimum, which still fills
+    market.bank.process(&client::set_order_size_limits_ix(market.ledger, market.admin, 10, 100)).unwrap();
+    market.post_demand(consumer, 45, 10).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    assert_eq!(market.bank.ledger(&market.ledger).productions[0].energy_amount, 5);
+    market.post_demand(consumer, 10, 10).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    assert_eq!(market.bank.events::<TradeExecuted>()[0].amount, 5);
+    assert!(market.bank.ledger(&market.ledger).productions.is_empty());
+}
+
+#[test]
+fn only_the_admin_sets_consistent_limits() {
+    let mut market = sized_market();
+    let producer = market.register(ParticipantType::Producer, 0);
+    let by_producer = client::set_order_size_limits_ix(market.ledger, producer, 0, 0);
+    assert_eq!(market.bank.process(&by_producer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    let inverted = client::set_order_size_limits_ix(market.ledger, market.admin, 100, 10);
+    assert_eq!(market.bank.process(&inverted).unwrap_err(), custom(EnergyMarketError::InvalidOrderSizeLimits));
+
+    // A zero maximum lifts the cap while the minimum still applies
+    market.bank.process(&client::set_order_size_limits_ix(market.ledger, market.admin, 10, 0)).unwrap();
+    market.report_production(producer, 1_000, 10).unwrap();
+    assert_eq!(market.report_production(producer, 9, 11).unwrap_err(), custom(EnergyMarketError::MinOrderSizeNotMet));
+}
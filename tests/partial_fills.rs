@@ -0,0 +1,63 @@
+mod common;
+
+use common::Market;
+use energy_trading_program::{client, ConfigUpdate, MarketConfig, ParticipantType};
+use solana_program::pubkey::Pubkey;
+
+fn wallet_balance(market: &Market, wallet: &Pubkey) -> u64 {
+    market.bank.participant(&market.ledger, wallet).wallet_balance
+}
+
+#[test]
+fn large_demand_sweeps_smaller_offers_in_price_order() {
+    let mut market = Market::new(MarketConfig::default());
+    let producers: Vec<Pubkey> = (0..3).map(|_| market.register(ParticipantType::Producer, 0)).collect();
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producers[0], 40, 9).unwrap();
+    market.report_production(producers[1], 40, 8).unwrap();
+    market.report_production(producers[2], 40, 10).unwrap();
+    market.post_demand(consumer, 100, 10).unwrap();
+
+    let mut wallets = producers.clone();
+    wallets.push(consumer);
+    market.match_orders(consumer, &wallets).unwrap();
+    let ledger = market.bank.ledger(&market.ledger);
+    let trades: Vec<(Pubkey, u64, i64)> = ledger.transactions.iter().map(|t| (t.to, t.amount, t.price)).collect();
+    assert_eq!(trades, vec![(producers[1], 40, 8), (producers[0], 40, 9), (producers[2], 20, 10)]);
+    assert!(ledger.transactions.iter().all(|t| t.from == consumer));
+
+    // The two cheaper offers are used up and the dearest keeps its remaining 20 kWh
+    assert!(ledger.demands.is_empty());
+    let remaining: Vec<(Pubkey, u64)> = ledger.productions.iter().map(|p| (p.producer_id, p.energy_amount)).collect();
+    assert_eq!(remaining, vec![(producers[2], 20)]);
+    let proceeds: Vec<u64> = producers.iter().map(|p| wallet_balance(&market, p)).collect();
+    assert_eq!(proceeds, vec![360, 320, 200]);
+    assert_eq!(wallet_balance(&market, &consumer), 1_000 - 880);
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).reserved_balance, 0);
+}
+
+#[test]
+fn sweep_stops_once_the_consumer_cannot_pay() {
+    // The buyer's half of a 20% crank reward switched on after posting comes on top of the escrow,
+    // and the 50 spare covers it for one 40 kWh fill only
+    let mut market = Market::new(MarketConfig::default());
+    let producers: Vec<Pubkey> = (0..3).map(|_| market.register(ParticipantType::Producer, 0)).collect();
+    let consumer = market.register(ParticipantType::Consumer, 1_050);
+    for &producer in &producers {
+        market.report_production(producer, 40, 10).unwrap();
+    }
+    market.post_demand(consumer, 100, 10).unwrap();
+    market.bank.process(&client::update_config_ix(market.ledger, market.admin, ConfigUpdate::CrankRewardBps(2_000))).unwrap();
+
+    let cranker = market.register(ParticipantType::Consumer, 0);
+    let mut wallets = producers.clone();
+    wallets.push(consumer);
+    market.match_orders(cranker, &wallets).unwrap();
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!(ledger.transactions.len(), 1);
+    assert_eq!((ledger.transactions[0].to, ledger.transactions[0].amount), (producers[0], 40));
+    assert_eq!(ledger.demands[0].energy_amount, 60);
+    assert_eq!(ledger.productions.len(), 2);
+    let buyer = market.bank.participant(&market.ledger, &consumer);
+    assert_eq!((buyer.wallet_balance, buyer.reserved_balance), (10, 600));
+}
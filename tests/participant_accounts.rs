@@ -0,0 +1,53 @@
+mod common;
+
+use common::{custom, program_id, Market};
+use energy_trading_program::{
+    client, find_participant_address, EnergyMarketError, MarketConfig, ParticipantMetadata, ParticipantType, PARTICIPANT_SIZE,
+};
+use solana_program::{instruction::AccountMeta, rent::Rent};
+
+#[test]
+fn registration_creates_a_rent_exempt_participant_pda_the_wallet_pays_for() {
+    let mut market = Market::new(MarketConfig::default());
+    let wallet = market.bank.funded_wallet(1);
+    let lamports = market.bank.lamports(&wallet);
+    let register = client::register_participant_ix(market.ledger, wallet, ParticipantType::Producer, 0, 0, ParticipantMetadata::default());
+    market.bank.process(&register).unwrap();
+
+    let (address, _) = find_participant_address(&program_id(), &market.ledger, &wallet);
+    let account = market.bank.account(&address).unwrap().clone();
+    assert_eq!((account.owner, account.data.len()), (program_id(), PARTICIPANT_SIZE));
+    assert_eq!(account.lamports, Rent::default().minimum_balance(PARTICIPANT_SIZE));
+    assert_eq!(market.bank.lamports(&wallet), lamports - account.lamports);
+}
+
+#[test]
+fn deposits_and_withdrawals_leave_the_ledger_untouched() {
+    let mut market = Market::new(MarketConfig::default());
+    let wallet = market.register(ParticipantType::Consumer, 0);
+    let ledger = market.bank.account(&market.ledger).unwrap().clone();
+
+    market.bank.process(&client::deposit_ix(market.ledger, wallet, 500, None)).unwrap();
+    market.bank.process(&client::withdraw_ix(market.ledger, wallet, wallet, 200, None)).unwrap();
+    assert_eq!(market.bank.account(&market.ledger).unwrap(), &ledger);
+    assert_eq!(market.bank.participant(&market.ledger, &wallet).wallet_balance, 300);
+}
+
+#[test]
+fn crank_rejects_a_participant_account_that_is_not_the_pda() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producer, 10, 10).unwrap();
+    market.post_demand(consumer, 10, 10).unwrap();
+
+    // A program-owned copy of the consumer's participant account at another address
+    let (address, _) = find_participant_address(&program_id(), &market.ledger, &consumer);
+    let copy = market.bank.program_account(PARTICIPANT_SIZE);
+    market.bank.account_mut(&copy).data = market.bank.account(&address).unwrap().data.clone();
+    let mut remaining = client::participant_metas(market.ledger, &[producer]);
+    remaining.push(AccountMeta::new(copy, false));
+    let crank = client::match_transactions_ix(market.ledger, producer, 16, remaining);
+    assert_eq!(market.bank.process(&crank).unwrap_err(), custom(EnergyMarketError::InvalidParticipantAccount));
+    assert_eq!(market.bank.ledger(&market.ledger).total_trades, 0);
+}
@@ -0,0 +1,53 @@
+// The compute unit measurement on 200 participants and 200 orders still needs a run on the BPF VM,
+// which the native harness does not meter (see the README). What it can check is that a crank over
+// a few hundred participants credits each fill to the right participant PDA, whatever order the
+// PDAs are passed in.
+mod common;
+
+use common::Market;
+use energy_trading_program::{client, LedgerCapacity, MarketConfig, ParticipantType};
+use solana_program::pubkey::Pubkey;
+
+const PARTICIPANTS: usize = 200;
+
+fn crowded_market() -> (Market, Vec<Pubkey>) {
+    let capacity = LedgerCapacity {
+        max_participants: PARTICIPANTS as u32,
+        max_open_orders: PARTICIPANTS as u32,
+        max_transactions: PARTICIPANTS as u32,
+    };
+    let mut market = Market::builder(MarketConfig::default()).capacity(capacity).build();
+    let wallets: Vec<Pubkey> = (0..PARTICIPANTS)
+        .map(|index| {
+            let producer = index % 2 == 0;
+            let wallet = market.register(if producer { ParticipantType::Producer } else { ParticipantType::Consumer }, 10_000);
+            let amount = 1 + (index / 2) as u64 % 10;
+            if producer {
+                market.report_production(wallet, amount, 10).unwrap();
+            } else {
+                market.post_demand(wallet, amount, 10).unwrap();
+            }
+            wallet
+        })
+        .collect();
+    (market, wallets)
+}
+
+#[test]
+fn fills_credit_the_right_participants_in_any_account_order() {
+    let (mut market, wallets) = crowded_market();
+    let mut shuffled = wallets.clone();
+    shuffled.reverse();
+    shuffled.rotate_left(PARTICIPANTS / 3);
+    let remaining = client::participant_metas(market.ledger, &shuffled);
+    market.bank.process(&client::match_transactions_ix(market.ledger, wallets[0], u16::MAX, remaining)).unwrap();
+
+    // Producer 2k and consumer 2k+1 post the same amount, so every pair fills in full
+    let ledger = market.bank.ledger(&market.ledger);
+    assert!(ledger.productions.is_empty() && ledger.demands.is_empty());
+    for (index, wallet) in wallets.iter().enumerate() {
+        let amount = 1 + (index / 2) as u64 % 10;
+        let expected = if index % 2 == 0 { 10_000 + amount * 10 } else { 10_000 - amount * 10 };
+        assert_eq!(market.bank.participant(&market.ledger, wallet).wallet_balance, expected, "participant {}", index);
+    }
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use common::{custom, program_id, Market};
+use energy_trading_program::{
+    client, find_participant_address, EnergyMarketError, MarketConfig, ParticipantMetadata, ParticipantType,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+fn metadata_of(market: &Market, wallet: &Pubkey) -> ParticipantMetadata {
+    market.bank.participant(&market.ledger, wallet).metadata
+}
+
+#[test]
+fn registration_stores_the_metadata_and_the_participant_updates_it() {
+    let mut market = Market::new(MarketConfig::default());
+    let wallet = market.bank.funded_wallet(1);
+    let metadata = ParticipantMetadata::new("Rooftop 12", "MTR-0042", 48_856_600, 2_352_200);
+    market.bank.process(&client::register_participant_ix(market.ledger, wallet, ParticipantType::Prosumer, 0, 0, metadata)).unwrap();
+    let stored = metadata_of(&market, &wallet);
+    assert_eq!((stored.name(), stored.meter_id(), stored.latitude, stored.longitude), (Some("Rooftop 12"), Some("MTR-0042"), 48_856_600, 2_352_200));
+
+    let moved = ParticipantMetadata::new("Rooftop 12b", "MTR-0043", -33_868_800, 151_209_300);
+    market.bank.process(&client::update_participant_metadata_ix(market.ledger, wallet, moved)).unwrap();
+    assert_eq!(metadata_of(&market, &wallet), moved);
+}
+
+#[test]
+fn only_the_participant_updates_its_metadata() {
+    let mut market = Market::new(MarketConfig::default());
+    let owner = market.register(ParticipantType::Producer, 0);
+    let stranger = market.register(ParticipantType::Consumer, 0);
+    let defaced = ParticipantMetadata::new("Defaced", "", 0, 0);
+
+    let mut unsigned = client::update_participant_metadata_ix(market.ledger, owner, defaced);
+    unsigned.accounts[0].is_signer = false;
+    assert_eq!(market.bank.process(&unsigned).unwrap_err(), ProgramError::MissingRequiredSignature);
+
+    // A signed stranger pointing at the owner's participant account
+    let mut foreign = client::update_participant_metadata_ix(market.ledger, stranger, defaced);
+    foreign.accounts[2].pubkey = find_participant_address(&program_id(), &market.ledger, &owner).0;
+    assert_eq!(market.bank.process(&foreign).unwrap_err(), custom(EnergyMarketError::InvalidParticipantAccount));
+    assert_eq!(metadata_of(&market, &owner), ParticipantMetadata::default());
+}
+
+#[test]
+fn over_long_text_is_cut_at_a_character_boundary() {
+    let name = "Sunny Meadows Community Solar Cooperative";
+    let metadata = ParticipantMetadata::new(name, "METER-ID-0123456789", 0, 0);
+    assert_eq!((metadata.name(), metadata.meter_id()), (Some(&name[..32]), Some("METER-ID-0123456")));
+
+    // "é" takes two bytes and would straddle the end of the name field, so it is dropped whole
+    let accented = format!("{}é", "a".repeat(31));
+    assert_eq!(ParticipantMetadata::new(&accented, "", 0, 0).name(), Some(&accented[..31]));
+
+    let mut market = Market::new(MarketConfig::default());
+    let wallet = market.register(ParticipantType::Producer, 0);
+    market.bank.process(&client::update_participant_metadata_ix(market.ledger, wallet, metadata)).unwrap();
+    assert_eq!(metadata_of(&market, &wallet).name(), Some(&name[..32]));
+}
+
+#[test]
+fn malformed_metadata_is_rejected() {
+    let mut market = Market::new(MarketConfig::default());
+    let wallet = market.register(ParticipantType::Producer, 0);
+    let mut invalid_utf8 = ParticipantMetadata::default();
+    invalid_utf8.name[0] = 0xff;
+    let mut gap_in_padding = ParticipantMetadata::new("Meter", "", 0, 0);
+    gap_in_padding.name[10] = b'x';
+    let malformed = [
+        invalid_utf8,
+        gap_in_padding,
+        ParticipantMetadata::new("", "", 90_000_001, 0),
+        ParticipantMetadata::new("", "", 0, -180_000_001),
+    ];
+    for metadata in malformed {
+        let update = client::update_participant_metadata_ix(market.ledger, wallet, metadata);
+        assert_eq!(market.bank.process(&update).unwrap_err(), custom(EnergyMarketError::InvalidMetadata));
+    }
+
+    // Registration checks the same way, and the poles and antimeridian are in range
+    let newcomer = market.bank.funded_wallet(1);
+    let register = client::register_participant_ix(market.ledger, newcomer, ParticipantType::Consumer, 0, 0, malformed[2]);
+    assert_eq!(market.bank.process(&register).unwrap_err(), custom(EnergyMarketError::InvalidMetadata));
+    let edge = ParticipantMetadata::new("", "", -90_000_000, 180_000_000);
+    market.bank.process(&client::update_participant_metadata_ix(market.ledger, wallet, edge)).unwrap();
+}
@@ -0,0 +1,114 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, events::ParticipantTypeChanged, DeliveryConfirmer, EnergyMarketError, MarketConfig, OrderStorage, ParticipantType,
+    SettlementConfig,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+fn change(market: &mut Market, wallet: Pubkey, new_type: ParticipantType, admin: Option<Pubkey>) -> Result<(), ProgramError> {
+    market.bank.process(&client::change_participant_type_ix(market.ledger, wallet, new_type, admin))
+}
+
+fn participant_type(market: &Market, wallet: &Pubkey) -> ParticipantType {
+    market.bank.participant(&market.ledger, wallet).participant_type
+}
+
+#[test]
+fn consumers_and_producers_become_prosumers_freely() {
+    let mut market = Market::new(MarketConfig::default());
+    let consumer = market.register(ParticipantType::Consumer, 100);
+    let producer = market.register(ParticipantType::Producer, 0);
+    market.post_demand(consumer, 10, 10).unwrap();
+    market.report_production(producer, 10, 20).unwrap();
+
+    change(&mut market, consumer, ParticipantType::Prosumer, None).unwrap();
+    let changed = market.bank.events::<ParticipantTypeChanged>().remove(0);
+    assert_eq!((changed.participant, changed.old_type, changed.new_type), (consumer, ParticipantType::Consumer, ParticipantType::Prosumer));
+    change(&mut market, producer, ParticipantType::Prosumer, None).unwrap();
+    assert_eq!(participant_type(&market, &consumer), ParticipantType::Prosumer);
+    assert_eq!(participant_type(&market, &producer), ParticipantType::Prosumer);
+
+    // The new side is open to them straight away
+    market.report_production(consumer, 10, 20).unwrap();
+    assert_eq!(change(&mut market, consumer, ParticipantType::Prosumer, None).unwrap_err(), custom(EnergyMarketError::InvalidTypeChange));
+}
+
+#[test]
+fn downgrades_need_the_dropped_side_to_be_empty() {
+    let mut market = Market::new(MarketConfig { collateral_bps: 10_000, ..MarketConfig::default() });
+    let prosumer = market.register(ParticipantType::Prosumer, 1_000);
+    market.bank.process(&client::post_collateral_ix(market.ledger, prosumer, 200)).unwrap();
+    market.bank.process(&client::set_registered_capacity_ix(market.ledger, market.admin, prosumer, 500)).unwrap();
+    market.post_demand(prosumer, 10, 10).unwrap();
+    market.report_production(prosumer, 10, 20).unwrap();
+
+    let open = custom(EnergyMarketError::ParticipantHasOpenOrders);
+    assert_eq!(change(&mut market, prosumer, ParticipantType::Consumer, None).unwrap_err(), open);
+    assert_eq!(change(&mut market, prosumer, ParticipantType::Producer, None).unwrap_err(), open);
+
+    // Giving up selling hands the collateral back and clears the capacity; the open demand stays
+    market.bank.process(&client::cancel_production_ix(market.ledger, prosumer, 1, OrderStorage::Ledger)).unwrap();
+    change(&mut market, prosumer, ParticipantType::Consumer, None).unwrap();
+    let participant = market.bank.participant(&market.ledger, &prosumer);
+    assert_eq!(
+        (participant.wallet_balance, participant.collateral_balance, participant.registered_capacity, participant.open_demands),
+        (900, 0, 0, 1),
+    );
+    assert_eq!(market.report_production(prosumer, 10, 20).unwrap_err(), custom(EnergyMarketError::InvalidParticipantType));
+}
+
+#[test]
+fn pending_sales_block_giving_up_selling() {
+    let settlement = SettlementConfig {
+        deferred: true,
+        confirmer: DeliveryConfirmer::Consumer,
+        delivery_timeout: 3_600,
+        default_penalty_bps: 0,
+    };
+    let mut market = Market::new(MarketConfig { settlement, ..MarketConfig::default() });
+    let prosumer = market.register(ParticipantType::Prosumer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 100);
+    market.report_production(prosumer, 10, 10).unwrap();
+    market.post_demand(consumer, 10, 10).unwrap();
+    market.match_orders(consumer, &[prosumer, consumer]).unwrap();
+
+    assert_eq!(change(&mut market, prosumer, ParticipantType::Consumer, None).unwrap_err(), custom(EnergyMarketError::InvalidTypeChange));
+    market.bank.process(&client::confirm_delivery_ix(market.ledger, consumer, consumer, prosumer, 0)).unwrap();
+    change(&mut market, prosumer, ParticipantType::Consumer, None).unwrap();
+}
+
+#[test]
+fn storage_transitions_take_the_admin() {
+    let mut market = Market::new(MarketConfig::default());
+    let prosumer = market.register(ParticipantType::Prosumer, 1_000);
+    let stranger = market.register(ParticipantType::Consumer, 0);
+
+    assert_eq!(change(&mut market, prosumer, ParticipantType::Storage, None).unwrap_err(), ProgramError::NotEnoughAccountKeys);
+    let by_stranger = change(&mut market, prosumer, ParticipantType::Storage, Some(stranger));
+    assert_eq!(by_stranger.unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    let admin = market.admin;
+    change(&mut market, prosumer, ParticipantType::Storage, Some(admin)).unwrap();
+    assert_eq!(participant_type(&market, &prosumer), ParticipantType::Storage);
+
+    // A charged battery stays one until it is empty
+    market.bank.process(&client::set_storage_parameters_ix(market.ledger, admin, prosumer, 100, 10_000)).unwrap();
+    let producer = market.register(ParticipantType::Producer, 0);
+    market.report_production(producer, 10, 10).unwrap();
+    market.post_demand(prosumer, 10, 10).unwrap();
+    market.match_orders(prosumer, &[producer, prosumer]).unwrap();
+    assert_eq!(change(&mut market, prosumer, ParticipantType::Prosumer, None).unwrap_err(), ProgramError::NotEnoughAccountKeys);
+    let charged = change(&mut market, prosumer, ParticipantType::Prosumer, Some(admin));
+    assert_eq!(charged.unwrap_err(), custom(EnergyMarketError::InvalidTypeChange));
+}
+
+#[test]
+fn aggregators_neither_join_nor_leave_their_type() {
+    let mut market = Market::new(MarketConfig::default());
+    let aggregator = market.register(ParticipantType::Aggregator, 0);
+    let consumer = market.register(ParticipantType::Consumer, 0);
+    let invalid = custom(EnergyMarketError::InvalidTypeChange);
+    assert_eq!(change(&mut market, aggregator, ParticipantType::Consumer, None).unwrap_err(), invalid);
+    assert_eq!(change(&mut market, consumer, ParticipantType::Aggregator, None).unwrap_err(), invalid);
+}
@@ -0,0 +1,24 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{EnergyMarketError, MarketConfig, ParticipantType};
+
+#[test]
+fn only_sellers_offer_and_only_buyers_bid() {
+    let mut market = Market::new(MarketConfig::default());
+    let cases = [
+        (ParticipantType::Producer, true, false),
+        (ParticipantType::Consumer, false, true),
+        (ParticipantType::Prosumer, true, true),
+    ];
+    for (participant_type, sells, buys) in cases {
+        let wallet = market.register(participant_type.clone(), 1_000);
+        let offer = market.report_production(wallet, 10, 5);
+        let bid = market.post_demand(wallet, 10, 5);
+        let expected = |allowed: bool| if allowed { Ok(()) } else { Err(custom(EnergyMarketError::InvalidParticipantType)) };
+        assert_eq!(offer, expected(sells), "{:?} reporting production", participant_type);
+        assert_eq!(bid, expected(buys), "{:?} posting demand", participant_type);
+    }
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.productions.len(), ledger.demands.len()), (2, 2));
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{client, EnergyMarketError, MarketConfig, OrderStorage, ParticipantType};
+
+#[test]
+fn paused_market_stops_trading_but_lets_participants_exit() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producer, 10, 10).unwrap();
+    market.post_demand(consumer, 10, 5).unwrap();
+    let ledger = market.bank.ledger(&market.ledger);
+    let (offer, demand) = (ledger.productions[0].order_id, ledger.demands[0].order_id);
+
+    let by_producer = client::pause_ix(market.ledger, producer);
+    assert_eq!(market.bank.process(&by_producer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    market.bank.process(&client::pause_ix(market.ledger, market.admin)).unwrap();
+    assert!(market.bank.ledger(&market.ledger).paused);
+
+    let paused = custom(EnergyMarketError::MarketPaused);
+    assert_eq!(market.report_production(producer, 10, 10).unwrap_err(), paused);
+    assert_eq!(market.post_demand(consumer, 10, 10).unwrap_err(), paused);
+    assert_eq!(market.match_orders(producer, &[producer, consumer]).unwrap_err(), paused);
+
+    market.bank.process(&client::cancel_production_ix(market.ledger, producer, offer, OrderStorage::Ledger)).unwrap();
+    market.bank.process(&client::cancel_demand_ix(market.ledger, consumer, demand, OrderStorage::Ledger)).unwrap();
+    market.bank.process(&client::withdraw_ix(market.ledger, consumer, consumer, 1_000, None)).unwrap();
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).wallet_balance, 0);
+}
+
+#[test]
+fn unpausing_resumes_trading() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.bank.process(&client::pause_ix(market.ledger, market.admin)).unwrap();
+    let by_producer = client::unpause_ix(market.ledger, producer);
+    assert_eq!(market.bank.process(&by_producer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+
+    market.bank.process(&client::unpause_ix(market.ledger, market.admin)).unwrap();
+    market.report_production(producer, 10, 10).unwrap();
+    market.post_demand(consumer, 10, 10).unwrap();
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+    assert_eq!(market.bank.ledger(&market.ledger).total_trades, 1);
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, events::{CircuitBreaker, TradeExecuted}, EnergyMarketError, MarketConfig, ParticipantType,
+};
+use solana_program::pubkey::Pubkey;
+
+fn fills(market: &Market) -> Vec<(i64, u64)> {
+    market.bank.events::<TradeExecuted>().iter().map(|t| (t.price, t.amount)).collect()
+}
+
+fn reference_price(market: &Market) -> i64 {
+    market.bank.ledger(&market.ledger).reference_price
+}
+
+// Trades may stray 10% from the reference. The first run has none and trades 10 at 10 and 30 at
+// 14, setting the reference to their average of 13.
+fn banded_market() -> (Market, Pubkey, Pubkey) {
+    let mut market = Market::new(MarketConfig { max_deviation_bps: 1_000, ..MarketConfig::default() });
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 10_000);
+    market.report_production(producer, 10, 10).unwrap();
+    market.report_production(producer, 30, 14).unwrap();
+    market.post_demand(consumer, 40, 14).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    assert_eq!(fills(&market), vec![(10, 10), (14, 30)]);
+    (market, producer, consumer)
+}
+
+#[test]
+fn out_of_band_pairs_are_skipped_and_in_band_ones_move_the_reference() {
+    let (mut market, producer, consumer) = banded_market();
+    assert_eq!(reference_price(&market), 13);
+
+    // 11 is more than 10% below 13 and trips the breaker; 14 is within it and fills
+    market.report_production(producer, 10, 11).unwrap();
+    market.report_production(producer, 10, 14).unwrap();
+    market.post_demand(consumer, 20, 14).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    assert_eq!(
+        market.bank.events::<CircuitBreaker>(),
+        vec![CircuitBreaker { demand_order_id: 5, production_order_id: 3, price: 11, reference_price: 13 }],
+    );
+    assert_eq!(fills(&market), vec![(14, 10)]);
+    assert_eq!(reference_price(&market), 14);
+    assert_eq!(market.bank.ledger(&market.ledger).productions[0].price, 11);
+}
+
+#[test]
+fn admin_reset_lets_the_next_run_through_the_band() {
+    let (mut market, producer, consumer) = banded_market();
+    market.report_production(producer, 10, 20).unwrap();
+    market.post_demand(consumer, 10, 20).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    assert!(fills(&market).is_empty());
+
+    let by_consumer = client::set_reference_price_ix(market.ledger, consumer, 0);
+    assert_eq!(market.bank.process(&by_consumer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    market.bank.process(&client::set_reference_price_ix(market.ledger, market.admin, 0)).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    assert_eq!(fills(&market), vec![(20, 10)]);
+    assert_eq!(reference_price(&market), 20);
+}
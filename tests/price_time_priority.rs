@@ -0,0 +1,70 @@
+// The ordering rules of the pure matcher, run on books posted through the program but crossed off-chain
+mod common;
+
+use common::Market;
+use energy_trading_program::{matching::match_orders, Ledger, MarketConfig, Participant, ParticipantType, Transaction};
+use solana_program::pubkey::Pubkey;
+
+struct Book {
+    market: Market,
+    producers: [Pubkey; 3],
+    bulk: Pubkey,
+    small: Pubkey,
+}
+
+// Two offers at 8, the second a minute later, and one at 9; a bulk demand at 10 before a small one at 12
+fn book() -> Book {
+    let mut market = Market::new(MarketConfig::default());
+    let producers = [(); 3].map(|_| market.register(ParticipantType::Producer, 0));
+    let bulk = market.register(ParticipantType::Consumer, 1_000);
+    let small = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producers[0], 30, 8).unwrap();
+    market.bank.now += 60;
+    market.report_production(producers[1], 30, 8).unwrap();
+    market.report_production(producers[2], 30, 9).unwrap();
+    market.post_demand(bulk, 60, 10).unwrap();
+    market.post_demand(small, 10, 12).unwrap();
+    Book { market, producers, bulk, small }
+}
+
+fn load(book: &Book) -> (Ledger, Vec<Participant>) {
+    let wallets = [book.producers.as_slice(), &[book.bulk, book.small]].concat();
+    let participants = wallets.iter().map(|wallet| book.market.bank.participant(&book.market.ledger, wallet)).collect();
+    (book.market.bank.ledger(&book.market.ledger), participants)
+}
+
+fn fills(trades: &[Transaction]) -> Vec<(Pubkey, Pubkey, u64, i64)> {
+    trades.iter().map(|t| (t.from, t.to, t.amount, t.price)).collect()
+}
+
+#[test]
+fn best_price_fills_first_then_the_earlier_order() {
+    let book = book();
+    let (mut ledger, mut participants) = load(&book);
+    let run = match_orders(&mut ledger, &mut participants, book.market.bank.now, usize::MAX).unwrap();
+
+    let ids: Vec<Pubkey> = participants.iter().map(|p| p.id).collect();
+    let [first, second, dearer] = [ids[0], ids[1], ids[2]];
+    let (bulk, small) = (ids[3], ids[4]);
+    assert_eq!(
+        fills(&run.trades),
+        vec![(small, first, 10, 8), (bulk, first, 20, 8), (bulk, second, 30, 8), (bulk, dearer, 10, 9)],
+    );
+    assert_eq!((ledger.productions.len(), ledger.productions[0].energy_amount), (1, 20));
+}
+
+#[test]
+fn replaying_a_book_in_any_stored_order_gives_the_same_trades() {
+    let book = book();
+    let (mut ledger, mut participants) = load(&book);
+    let expected = match_orders(&mut ledger, &mut participants, book.market.bank.now, usize::MAX).unwrap().trades;
+
+    for rotation in 1..5 {
+        let (mut ledger, mut participants) = load(&book);
+        ledger.demands.reverse();
+        ledger.productions.rotate_left(rotation % 3);
+        participants.rotate_left(rotation);
+        let trades = match_orders(&mut ledger, &mut participants, book.market.bank.now, usize::MAX).unwrap().trades;
+        assert_eq!(format!("{:?}", trades), format!("{:?}", expected), "rotation {}", rotation);
+    }
+}
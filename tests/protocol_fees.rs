@@ -0,0 +1,87 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, events::TradeExecuted, matching::protocol_fee, notional, EnergyMarketError, MarketConfig, ParticipantType,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use solana_program::pubkey::Pubkey;
+
+const CASES: u64 = 64;
+
+// Everything the market holds for someone: balances and escrow, plus the fee and insurance pools
+fn market_value(market: &Market, wallets: &[Pubkey]) -> u128 {
+    let ledger = market.bank.ledger(&market.ledger);
+    let balances: u128 = wallets.iter()
+        .map(|wallet| market.bank.participant(&market.ledger, wallet))
+        .map(|p| p.wallet_balance as u128 + p.reserved_balance as u128)
+        .sum();
+    balances + ledger.protocol_fees as u128 + ledger.insurance_pool as u128
+}
+
+#[test]
+fn match_runs_move_value_between_balances_and_fees_without_creating_any() {
+    let mut fills = 0;
+    for seed in 0..CASES {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let fee_bps = rng.gen_range(0..=1_000);
+        let mut market = Market::new(MarketConfig { fee_bps, ..MarketConfig::default() });
+        let wallets: Vec<Pubkey> = (0..rng.gen_range(2..=4))
+            .map(|_| market.register(ParticipantType::Prosumer, rng.gen_range(0..=100_000)))
+            .collect();
+        for _ in 0..rng.gen_range(1..=12) {
+            let wallet = wallets[rng.gen_range(0..wallets.len())];
+            let (amount, price) = (rng.gen_range(1..=100), rng.gen_range(1..=997));
+            // Demands beyond a balance are turned down and simply not in the book
+            let _ = if rng.gen_bool(0.5) {
+                market.report_production(wallet, amount, price)
+            } else {
+                market.post_demand(wallet, amount, price)
+            };
+        }
+
+        let (value, fees) = (market_value(&market, &wallets), market.bank.ledger(&market.ledger).protocol_fees);
+        market.match_orders(wallets[0], &wallets).unwrap_or_else(|error| panic!("seed {}: {:?}", seed, error));
+        assert_eq!(market_value(&market, &wallets), value, "seed {}", seed);
+
+        let trades = market.bank.events::<TradeExecuted>();
+        let charged: u64 = trades.iter().map(|t| protocol_fee(notional(t.amount, t.price), fee_bps).unwrap()).sum();
+        assert_eq!(market.bank.ledger(&market.ledger).protocol_fees - fees, charged, "seed {}", seed);
+        fills += trades.len();
+    }
+    assert!(fills > CASES as usize, "only {} fills across all cases", fills);
+}
+
+#[test]
+fn fee_rounds_up_by_less_than_a_lamport() {
+    let mut rng = StdRng::seed_from_u64(0);
+    for _ in 0..1_000 {
+        let (notional, fee_bps) = (rng.gen_range(0..=u64::MAX as u128), rng.gen_range(0..=10_000));
+        let exact = notional * fee_bps as u128;
+        let fee = protocol_fee(notional, fee_bps).unwrap() as u128 * 10_000;
+        assert!(fee >= exact && fee < exact + 10_000, "{} at {} bps", notional, fee_bps);
+    }
+}
+
+#[test]
+fn only_the_admin_collects_fees_into_its_balance() {
+    let mut market = Market::builder(MarketConfig { fee_bps: 250, ..MarketConfig::default() }).registered_admin(0).build();
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 10_000);
+    market.report_production(producer, 40, 101).unwrap();
+    market.post_demand(consumer, 40, 101).unwrap();
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+    // 2.5% of 4_040 is 101
+    assert_eq!(market.bank.ledger(&market.ledger).protocol_fees, 101);
+
+    let by_producer = client::collect_fees_ix(market.ledger, producer);
+    assert_eq!(market.bank.process(&by_producer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    market.bank.process(&client::collect_fees_ix(market.ledger, market.admin)).unwrap();
+    assert_eq!(market.bank.ledger(&market.ledger).protocol_fees, 0);
+    assert_eq!(market.bank.participant(&market.ledger, &market.admin).wallet_balance, 101);
+
+    let by_producer = client::set_fee_ix(market.ledger, producer, 0);
+    assert_eq!(market.bank.process(&by_producer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    market.bank.process(&client::set_fee_ix(market.ledger, market.admin, 0)).unwrap();
+    assert_eq!(market.bank.ledger(&market.ledger).config.fee_bps, 0);
+}
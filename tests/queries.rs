@@ -0,0 +1,97 @@
+mod common;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use common::{custom, Bank, Market};
+use energy_trading_program::{
+    client, delivery_slot_at, BalanceInfo, EnergyMarketError, EnergySource, MarketConfig, OpenOrder, OpenOrders, OrderSide, OrderStorage,
+    Participant, ParticipantType, TimeInForce, MAX_QUERIED_ORDERS,
+};
+use solana_program::{instruction::Instruction, program_error::ProgramError, pubkey::Pubkey};
+
+fn query<T: BorshDeserialize>(bank: &mut Bank, instruction: Instruction) -> Result<T, ProgramError> {
+    bank.process(&instruction)?;
+    Ok(T::try_from_slice(&bank.return_data().unwrap()).unwrap())
+}
+
+#[test]
+fn participant_query_returns_the_stored_participant() {
+    let mut market = Market::new(MarketConfig::default());
+    let consumer = market.register(ParticipantType::Consumer, 500);
+    market.post_demand(consumer, 10, 10).unwrap();
+    let participant: Participant = query(&mut market.bank, client::get_participant_ix(market.ledger, consumer)).unwrap();
+    assert_eq!(participant.try_to_vec().unwrap(), market.bank.participant(&market.ledger, &consumer).try_to_vec().unwrap());
+
+    let stranger = Pubkey::new_unique();
+    let unregistered = query::<Participant>(&mut market.bank, client::get_participant_ix(market.ledger, stranger));
+    assert_eq!(unregistered.unwrap_err(), custom(EnergyMarketError::NotFound));
+    let unregistered = query::<BalanceInfo>(&mut market.bank, client::get_balance_ix(market.ledger, stranger));
+    assert_eq!(unregistered.unwrap_err(), custom(EnergyMarketError::NotFound));
+    let unregistered = query::<OpenOrders>(&mut market.bank, client::get_open_orders_ix(market.ledger, stranger, &[]));
+    assert_eq!(unregistered.unwrap_err(), custom(EnergyMarketError::NotFound));
+
+    // The PDA passed has to be the queried wallet's
+    let mut mismatched = client::get_participant_ix(market.ledger, stranger);
+    mismatched.accounts[1] = client::get_participant_ix(market.ledger, consumer).accounts[1].clone();
+    assert_eq!(query::<Participant>(&mut market.bank, mismatched).unwrap_err(), custom(EnergyMarketError::InvalidParticipantAccount));
+}
+
+#[test]
+fn balance_query_splits_free_reserved_and_collateral_funds() {
+    let mut market = Market::new(MarketConfig { collateral_bps: 10_000, ..MarketConfig::default() });
+    let prosumer = market.register(ParticipantType::Prosumer, 1_000);
+    market.bank.process(&client::post_collateral_ix(market.ledger, prosumer, 200)).unwrap();
+    market.post_demand(prosumer, 10, 10).unwrap();
+    let balance: BalanceInfo = query(&mut market.bank, client::get_balance_ix(market.ledger, prosumer)).unwrap();
+    assert_eq!(balance, BalanceInfo { wallet_balance: 700, reserved_balance: 100, collateral_balance: 200, pending_withdrawal: 0 });
+}
+
+#[test]
+fn open_orders_query_lists_both_sides_oldest_first() {
+    let mut market = Market::new(MarketConfig::default());
+    let prosumer = market.register(ParticipantType::Prosumer, 10_000);
+    let other = market.register(ParticipantType::Consumer, 1_000);
+    market.post_demand(prosumer, 10, 8).unwrap();
+    market.post_demand(other, 10, 9).unwrap();
+    market.report_production(prosumer, 20, 12).unwrap();
+
+    let slot = delivery_slot_at(market.bank.now);
+    let orders: OpenOrders = query(&mut market.bank, client::get_open_orders_ix(market.ledger, prosumer, &[])).unwrap();
+    assert_eq!(orders, OpenOrders {
+        open_orders: 2,
+        orders: vec![
+            OpenOrder { order_id: 0, side: OrderSide::Demand, energy_amount: 10, price: 8, expires_at: 0, delivery_slot: slot },
+            OpenOrder { order_id: 2, side: OrderSide::Production, energy_amount: 20, price: 12, expires_at: 0, delivery_slot: slot },
+        ],
+    });
+
+    // Past MAX_QUERIED_ORDERS the count stays exact while the list is cut
+    for price in 0..MAX_QUERIED_ORDERS as i64 {
+        market.post_demand(prosumer, 1, 1 + price).unwrap();
+    }
+    let orders: OpenOrders = query(&mut market.bank, client::get_open_orders_ix(market.ledger, prosumer, &[])).unwrap();
+    assert_eq!((orders.open_orders as usize, orders.orders.len()), (MAX_QUERIED_ORDERS + 2, MAX_QUERIED_ORDERS));
+    assert_eq!(orders.orders[1].order_id, 2);
+}
+
+#[test]
+fn open_orders_query_reads_the_order_accounts_passed_in() {
+    let mut market = Market::new(MarketConfig { order_storage: OrderStorage::Accounts, ..MarketConfig::default() });
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    let slot = delivery_slot_at(market.bank.now);
+    market.bank.process(&client::report_production_ix(
+        market.ledger, producer, 20, 12, 0, slot, EnergySource::Solar, TimeInForce::GoodTilCancelled, 0, false, 0, false, Some(0),
+    )).unwrap();
+    market.bank.process(&client::post_demand_ix(
+        market.ledger, consumer, 10, 8, 0, slot, TimeInForce::GoodTilCancelled, 0, false, 0, 0, Some(1),
+    )).unwrap();
+
+    // The consumer's order is skipped when listed for the producer
+    let orders: OpenOrders = query(&mut market.bank, client::get_open_orders_ix(market.ledger, producer, &[1, 0])).unwrap();
+    assert_eq!(orders.open_orders, 1);
+    assert_eq!(orders.orders, vec![
+        OpenOrder { order_id: 0, side: OrderSide::Production, energy_amount: 20, price: 12, expires_at: 0, delivery_slot: slot },
+    ]);
+    let orders: OpenOrders = query(&mut market.bank, client::get_open_orders_ix(market.ledger, consumer, &[])).unwrap();
+    assert_eq!((orders.open_orders, orders.orders.len()), (1, 0));
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, delivery_slot_at, events::{RecRetired, RecTransferred, TradeExecuted}, EnergyMarketError, EnergySource, MarketConfig,
+    ParticipantType, TimeInForce,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+fn rec_market(prefer_renewable: bool) -> Market {
+    Market::new(MarketConfig { kwh_per_rec: 1_000, prefer_renewable, ..MarketConfig::default() })
+}
+
+fn offer(market: &mut Market, producer: Pubkey, energy_amount: u64, price: i64, source: EnergySource) -> Result<(), ProgramError> {
+    let slot = delivery_slot_at(market.bank.now);
+    market.bank.process(&client::report_production_ix(
+        market.ledger, producer, energy_amount, price, 0, slot, source, TimeInForce::GoodTilCancelled, 0, false, 0, false, None,
+    ))
+}
+
+fn buy(market: &mut Market, consumer: Pubkey, producer: Pubkey, energy_amount: u64, source: EnergySource) {
+    offer(market, producer, energy_amount, 1, source).unwrap();
+    market.post_demand(consumer, energy_amount, 1).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+}
+
+fn recs(market: &Market, wallet: &Pubkey) -> (u64, u64, u64) {
+    let participant = market.bank.participant(&market.ledger, wallet);
+    (participant.rec_balance, participant.rec_accrual, participant.retired_recs)
+}
+
+#[test]
+fn renewable_purchases_accrue_one_rec_per_thousand_kwh() {
+    let mut market = rec_market(false);
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 10_000);
+    buy(&mut market, consumer, producer, 1_500, EnergySource::Wind);
+    assert_eq!(market.bank.ledger(&market.ledger).transactions[0].energy_source, EnergySource::Wind);
+    assert_eq!(recs(&market, &consumer), (1, 500, 0));
+
+    // The remainder carries over to the next purchase; fossil energy earns nothing
+    buy(&mut market, consumer, producer, 600, EnergySource::Solar);
+    assert_eq!(recs(&market, &consumer), (2, 100, 0));
+    buy(&mut market, consumer, producer, 2_000, EnergySource::Fossil);
+    buy(&mut market, consumer, producer, 2_000, EnergySource::Other);
+    assert_eq!(recs(&market, &consumer), (2, 100, 0));
+    assert_eq!(recs(&market, &producer), (0, 0, 0));
+}
+
+#[test]
+fn recs_move_between_participants_until_they_are_retired() {
+    let mut market = rec_market(false);
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 10_000);
+    let other = market.register(ParticipantType::Consumer, 0);
+    buy(&mut market, consumer, producer, 3_000, EnergySource::Hydro);
+
+    let transfer = |wallet, recipient, amount| client::transfer_rec_ix(market.ledger, wallet, recipient, amount);
+    assert_eq!(market.bank.process(&transfer(consumer, other, 4)).unwrap_err(), custom(EnergyMarketError::InsufficientRecs));
+    assert_eq!(market.bank.process(&transfer(consumer, consumer, 1)).unwrap_err(), ProgramError::InvalidArgument);
+    market.bank.process(&transfer(consumer, other, 2)).unwrap();
+    assert_eq!(market.bank.events::<RecTransferred>(), vec![RecTransferred { from: consumer, to: other, amount: 2 }]);
+    assert_eq!((recs(&market, &consumer).0, recs(&market, &other).0), (1, 2));
+
+    market.bank.process(&client::retire_rec_ix(market.ledger, other, 2)).unwrap();
+    assert_eq!(market.bank.events::<RecRetired>(), vec![RecRetired { participant: other, amount: 2, retired_total: 2 }]);
+    assert_eq!(recs(&market, &other), (0, 0, 2));
+    // Retired certificates neither move nor retire again
+    let back = client::transfer_rec_ix(market.ledger, other, consumer, 1);
+    assert_eq!(market.bank.process(&back).unwrap_err(), custom(EnergyMarketError::InsufficientRecs));
+    let again = client::retire_rec_ix(market.ledger, other, 1);
+    assert_eq!(market.bank.process(&again).unwrap_err(), custom(EnergyMarketError::InsufficientRecs));
+    assert_eq!(recs(&market, &other), (0, 0, 2));
+}
+
+#[test]
+fn renewable_offers_can_jump_the_queue_at_equal_price() {
+    for prefer_renewable in [false, true] {
+        let mut market = rec_market(prefer_renewable);
+        let fossil = market.register(ParticipantType::Producer, 0);
+        let solar = market.register(ParticipantType::Producer, 0);
+        let consumer = market.register(ParticipantType::Consumer, 1_000);
+        offer(&mut market, fossil, 10, 10, EnergySource::Fossil).unwrap();
+        offer(&mut market, solar, 10, 10, EnergySource::Solar).unwrap();
+        market.post_demand(consumer, 10, 10).unwrap();
+        market.match_orders(consumer, &[fossil, solar, consumer]).unwrap();
+        let seller = market.bank.events::<TradeExecuted>()[0].seller;
+        assert_eq!(seller, if prefer_renewable { solar } else { fossil });
+    }
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{client, delivery_slot_at, EnergyMarketError, MarketConfig, ParticipantType};
+use solana_program::pubkey::Pubkey;
+
+// A producer registered for 100 kWh per slot
+fn market_with_producer() -> (Market, Pubkey) {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let capacity = client::set_registered_capacity_ix(market.ledger, market.admin, producer, 100);
+    market.bank.process(&capacity).unwrap();
+    (market, producer)
+}
+
+#[test]
+fn offers_up_to_the_registered_capacity_are_accepted() {
+    let (mut market, producer) = market_with_producer();
+    assert_eq!(market.report_production(producer, 101, 10).unwrap_err(), custom(EnergyMarketError::CapacityExceeded));
+    market.report_production(producer, 100, 10).unwrap();
+    assert_eq!(market.bank.participant(&market.ledger, &producer).registered_capacity, 100);
+
+    let by_producer = client::set_registered_capacity_ix(market.ledger, producer, producer, u64::MAX);
+    assert_eq!(market.bank.process(&by_producer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+}
+
+#[test]
+fn open_offers_for_a_slot_add_up_against_the_capacity() {
+    let (mut market, producer) = market_with_producer();
+    market.report_production(producer, 60, 10).unwrap();
+    market.report_production(producer, 40, 11).unwrap();
+    assert_eq!(market.report_production(producer, 1, 12).unwrap_err(), custom(EnergyMarketError::CapacityExceeded));
+    // Merging into an open offer counts the same as posting a new one
+    assert_eq!(market.report_production(producer, 1, 10).unwrap_err(), custom(EnergyMarketError::CapacityExceeded));
+
+    // The next slot has its own allowance
+    let next_slot = delivery_slot_at(market.bank.now) + 1;
+    market.report_production_for(producer, 100, 10, next_slot).unwrap();
+    assert_eq!(market.bank.ledger(&market.ledger).productions.len(), 3);
+}
+
+#[test]
+fn empty_and_zero_priced_reports_are_rejected() {
+    let (mut market, producer) = market_with_producer();
+    assert_eq!(market.report_production(producer, 0, 10).unwrap_err(), custom(EnergyMarketError::InvalidOrderAmount));
+    assert_eq!(market.report_production(producer, 10, 0).unwrap_err(), custom(EnergyMarketError::InvalidOrderAmount));
+    assert!(market.bank.ledger(&market.ledger).productions.is_empty());
+}
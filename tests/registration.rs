@@ -0,0 +1,79 @@
+mod common;
+
+use common::{custom, program_id, Market};
+use energy_trading_program::{
+    client, find_participant_address, find_vault_address, EnergyMarketError, MarketConfig, ParticipantMetadata, ParticipantType,
+};
+use solana_program::{instruction::Instruction, program_error::ProgramError, pubkey::Pubkey};
+
+fn register_ix(market: &Market, wallet: Pubkey) -> Instruction {
+    client::register_participant_ix(market.ledger, wallet, ParticipantType::Producer, 0, 0, ParticipantMetadata::default())
+}
+
+#[test]
+fn registration_needs_the_wallet_signature() {
+    let mut market = Market::new(MarketConfig::default());
+    let wallet = market.bank.funded_wallet(1);
+
+    let mut unsigned = register_ix(&market, wallet);
+    unsigned.accounts[0].is_signer = false;
+    assert_eq!(market.bank.process(&unsigned).unwrap_err(), ProgramError::MissingRequiredSignature);
+    assert_eq!(market.bank.ledger(&market.ledger).participant_count, 0);
+
+    market.bank.process(&register_ix(&market, wallet)).unwrap();
+    let participant = market.bank.participant(&market.ledger, &wallet);
+    assert_eq!((participant.id, participant.participant_type), (wallet, ParticipantType::Producer));
+    assert_eq!(market.bank.ledger(&market.ledger).participant_count, 1);
+}
+
+#[test]
+fn second_registration_of_a_wallet_is_rejected() {
+    let mut market = Market::new(MarketConfig::default());
+    let wallet = market.register(ParticipantType::Consumer, 100);
+
+    let again = client::register_participant_ix(market.ledger, wallet, ParticipantType::Producer, 0, 0, ParticipantMetadata::default());
+    assert_eq!(market.bank.process(&again).unwrap_err(), custom(EnergyMarketError::ParticipantAlreadyRegistered));
+    assert_eq!(market.bank.ledger(&market.ledger).participant_count, 1);
+    let participant = market.bank.participant(&market.ledger, &wallet);
+    assert_eq!((participant.participant_type, participant.wallet_balance), (ParticipantType::Consumer, 100));
+}
+
+#[test]
+fn register_and_deposit_funds_the_vault_in_one_instruction() {
+    let mut market = Market::new(MarketConfig::default());
+    let wallet = market.bank.funded_wallet(1);
+    let vault = find_vault_address(&program_id(), &market.ledger).0;
+    let (wallet_lamports, vault_lamports) = (market.bank.lamports(&wallet), market.bank.lamports(&vault));
+
+    let onboard = client::register_and_deposit_ix(
+        market.ledger, wallet, ParticipantType::Consumer, 0, 0, ParticipantMetadata::default(), 500, None,
+    );
+    market.bank.process(&onboard).unwrap();
+    let participant = market.bank.participant(&market.ledger, &wallet);
+    assert_eq!((participant.participant_type, participant.wallet_balance), (ParticipantType::Consumer, 500));
+    assert_eq!(market.bank.lamports(&vault), vault_lamports + 500);
+    assert!(market.bank.lamports(&wallet) <= wallet_lamports - 500);
+    assert_eq!(market.bank.ledger(&market.ledger).participant_count, 1);
+}
+
+#[test]
+fn failed_deposit_leg_leaves_no_participant_registered() {
+    let mut market = Market::builder(MarketConfig::default())
+        .admin_instruction(|ledger, admin| client::set_deposit_limits_ix(ledger, admin, 100, 0))
+        .build();
+    let wallet = market.bank.funded_wallet(1);
+    let participant_key = find_participant_address(&program_id(), &market.ledger, &wallet).0;
+    let onboard = |amount| client::register_and_deposit_ix(
+        market.ledger, wallet, ParticipantType::Consumer, 0, 0, ParticipantMetadata::default(), amount, None,
+    );
+
+    assert_eq!(market.bank.process(&onboard(99)).unwrap_err(), custom(EnergyMarketError::DepositTooSmall));
+    // More than the wallet holds fails in the transfer into the vault
+    assert!(market.bank.process(&onboard(u64::MAX)).is_err());
+    assert!(market.bank.account(&participant_key).is_none_or(|account| account.data.is_empty()));
+    assert_eq!(market.bank.ledger(&market.ledger).participant_count, 0);
+
+    // Nothing is left behind to block a retry
+    market.bank.process(&onboard(100)).unwrap();
+    assert_eq!(market.bank.participant(&market.ledger, &wallet).wallet_balance, 100);
+}
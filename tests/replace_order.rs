@@ -0,0 +1,78 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, events::{OrderReplaced, TradeExecuted}, EnergyMarketError, MarketConfig, OrderSide, OrderStorage, ParticipantType,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+fn replace(market: &mut Market, owner: Pubkey, order_id: u64, new_price: i64, new_amount: u64) -> Result<OrderReplaced, ProgramError> {
+    market.bank.process(&client::replace_order_ix(market.ledger, owner, order_id, new_price, new_amount, OrderStorage::Ledger))?;
+    Ok(market.bank.events::<OrderReplaced>().remove(0))
+}
+
+fn balances(market: &Market, wallet: &Pubkey) -> (u64, u64) {
+    let participant = market.bank.participant(&market.ledger, wallet);
+    (participant.wallet_balance, participant.reserved_balance)
+}
+
+#[test]
+fn replacing_a_demand_moves_the_escrow_difference() {
+    let mut market = Market::new(MarketConfig::default());
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.post_demand(consumer, 10, 10).unwrap();
+    assert_eq!(balances(&market, &consumer), (900, 100));
+
+    // A lower bid keeps the demand's place; a higher one queues it afresh
+    assert_eq!(
+        replace(&mut market, consumer, 0, 8, 20).unwrap(),
+        OrderReplaced { order_id: 0, owner: consumer, side: OrderSide::Demand, price: 8, energy_amount: 20, priority_reset: false },
+    );
+    assert_eq!(balances(&market, &consumer), (840, 160));
+    assert!(replace(&mut market, consumer, 0, 12, 5).unwrap().priority_reset);
+    assert_eq!(balances(&market, &consumer), (940, 60));
+    assert_eq!(replace(&mut market, consumer, 0, 101, 10).unwrap_err(), custom(EnergyMarketError::InsufficientBalance));
+    assert_eq!(replace(&mut market, consumer, 0, 12, 0).unwrap_err(), custom(EnergyMarketError::InvalidOrderAmount));
+
+    let demand = &market.bank.ledger(&market.ledger).demands[0];
+    assert_eq!((demand.price_limit, demand.energy_amount), (12, 5));
+    assert_eq!(balances(&market, &consumer), (940, 60));
+}
+
+#[test]
+fn only_a_better_price_gives_up_the_place_in_the_queue() {
+    let mut market = Market::new(MarketConfig::default());
+    let first = market.register(ParticipantType::Producer, 0);
+    let resting = market.register(ParticipantType::Producer, 0);
+    let second = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(first, 10, 10).unwrap();
+    market.report_production(resting, 10, 9).unwrap();
+    market.bank.now += 60;
+    market.report_production(second, 10, 10).unwrap();
+
+    // Shrinking keeps the first offer ahead of the second; lowering the second's ask to 9 puts it
+    // behind the offer already resting there
+    assert!(!replace(&mut market, first, 0, 10, 5).unwrap().priority_reset);
+    assert!(replace(&mut market, second, 2, 9, 10).unwrap().priority_reset);
+    market.post_demand(consumer, 25, 10).unwrap();
+    market.match_orders(consumer, &[first, resting, second, consumer]).unwrap();
+    let fills: Vec<_> = market.bank.events::<TradeExecuted>().iter().map(|t| (t.seller, t.amount)).collect();
+    assert_eq!(fills, vec![(resting, 10), (second, 10), (first, 5)]);
+}
+
+#[test]
+fn only_the_unfilled_part_of_an_open_order_can_be_replaced() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producer, 30, 10).unwrap();
+    market.post_demand(consumer, 20, 10).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+
+    assert_eq!(replace(&mut market, consumer, 0, 10, 5).unwrap_err(), custom(EnergyMarketError::NotOrderOwner));
+    assert_eq!(replace(&mut market, consumer, 1, 10, 5).unwrap_err(), custom(EnergyMarketError::OrderNotFound));
+    replace(&mut market, producer, 0, 11, 5).unwrap();
+    let offer = &market.bank.ledger(&market.ledger).productions[0];
+    assert_eq!((offer.price, offer.energy_amount), (11, 5));
+}
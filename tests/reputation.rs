@@ -0,0 +1,122 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, events::{ReputationChanged, TradeExecuted}, reputation_score, DeliveryConfirmer, EnergyMarketError, MarketConfig,
+    ParticipantType, ReputationConfig, SettlementConfig, REPUTATION_SCALE,
+};
+use solana_program::pubkey::Pubkey;
+
+fn reputation(market: &Market, wallet: &Pubkey) -> (u32, u64, u64) {
+    let participant = market.bank.participant(&market.ledger, wallet);
+    (participant.reputation, participant.trades_completed, participant.trades_defaulted)
+}
+
+// One trade of `amount` kWh at 10 from the producer to a fresh consumer
+fn trade(market: &mut Market, producer: Pubkey, amount: u64) {
+    let consumer = market.register(ParticipantType::Consumer, 10 * amount);
+    market.report_production(producer, amount, 10).unwrap();
+    market.post_demand(consumer, amount, 10).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+}
+
+#[test]
+fn score_starts_halfway_and_moves_with_each_outcome() {
+    assert_eq!(reputation_score(0, 0), REPUTATION_SCALE / 2);
+    assert_eq!((reputation_score(1, 0), reputation_score(0, 1), reputation_score(98, 0)), (6_666, 3_333, 9_900));
+
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    assert_eq!(reputation(&market, &producer), (5_000, 0, 0));
+    trade(&mut market, producer, 10);
+    assert_eq!(
+        market.bank.events::<ReputationChanged>(),
+        vec![ReputationChanged { participant: producer, reputation: 6_666, trades_completed: 1, trades_defaulted: 0 }],
+    );
+    assert_eq!(reputation(&market, &producer), (6_666, 1, 0));
+}
+
+#[test]
+fn defaults_lower_the_score_and_confirmations_raise_it() {
+    let settlement = SettlementConfig {
+        deferred: true,
+        confirmer: DeliveryConfirmer::Consumer,
+        delivery_timeout: 3_600,
+        default_penalty_bps: 0,
+    };
+    let mut market = Market::new(MarketConfig { settlement, ..MarketConfig::default() });
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    for _ in 0..2 {
+        market.report_production(producer, 10, 10).unwrap();
+        market.post_demand(consumer, 10, 10).unwrap();
+        market.match_orders(consumer, &[producer, consumer]).unwrap();
+    }
+    // Matching alone settles nothing, so the score has not moved
+    assert_eq!(reputation(&market, &producer), (5_000, 0, 0));
+
+    market.bank.process(&client::confirm_delivery_ix(market.ledger, consumer, consumer, producer, 0)).unwrap();
+    assert_eq!(reputation(&market, &producer), (6_666, 1, 0));
+    market.bank.now = market.bank.ledger(&market.ledger).transactions[1].settlement_deadline + 1;
+    market.bank.process(&client::settle_defaulted_trade_ix(market.ledger, consumer, producer, 1)).unwrap();
+    assert_eq!(
+        market.bank.events::<ReputationChanged>(),
+        vec![ReputationChanged { participant: producer, reputation: 5_000, trades_completed: 1, trades_defaulted: 1 }],
+    );
+    assert_eq!(reputation(&market, &consumer), (5_000, 0, 0));
+}
+
+#[test]
+fn reputable_producers_fill_first_at_an_equal_price_only_when_preferred() {
+    for prefer_reputable in [false, true] {
+        let config = ReputationConfig { prefer_reputable, ..ReputationConfig::default() };
+        let mut market = Market::builder(MarketConfig::default())
+            .admin_instruction(move |ledger, admin| client::set_reputation_config_ix(ledger, admin, config))
+            .build();
+        let newcomer = market.register(ParticipantType::Producer, 0);
+        let proven = market.register(ParticipantType::Producer, 0);
+        trade(&mut market, proven, 10);
+
+        let consumer = market.register(ParticipantType::Consumer, 1_000);
+        market.report_production(newcomer, 20, 10).unwrap();
+        market.report_production(proven, 20, 10).unwrap();
+        market.post_demand(consumer, 20, 10).unwrap();
+        market.match_orders(consumer, &[newcomer, proven, consumer]).unwrap();
+        let sellers: Vec<_> = market.bank.events::<TradeExecuted>().iter().map(|t| t.seller).collect();
+        assert_eq!(sellers, vec![if prefer_reputable { proven } else { newcomer }]);
+    }
+}
+
+#[test]
+fn large_offers_need_the_minimum_reputation() {
+    let config = ReputationConfig { prefer_reputable: false, min_reputation: 6_000, large_order_size: 50 };
+    let mut market = Market::builder(MarketConfig::default())
+        .admin_instruction(move |ledger, admin| client::set_reputation_config_ix(ledger, admin, config))
+        .build();
+    let newcomer = market.register(ParticipantType::Producer, 0);
+    let proven = market.register(ParticipantType::Producer, 0);
+    // A small trade lifts one producer to 6_666
+    trade(&mut market, proven, 49);
+
+    // The newcomer's cheaper offer is large enough to need the reputation and is skipped, while
+    // its offer below the threshold still trades
+    let consumer = market.register(ParticipantType::Consumer, 10_000);
+    market.report_production(newcomer, 50, 9).unwrap();
+    market.report_production(newcomer, 49, 10).unwrap();
+    market.report_production(proven, 50, 10).unwrap();
+    market.post_demand(consumer, 99, 10).unwrap();
+    market.match_orders(consumer, &[newcomer, proven, consumer]).unwrap();
+    let fills: Vec<_> = market.bank.events::<TradeExecuted>().iter().map(|t| (t.seller, t.amount)).collect();
+    assert_eq!(fills, vec![(newcomer, 49), (proven, 50)]);
+}
+
+#[test]
+fn only_the_admin_sets_a_valid_reputation_config() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let config = ReputationConfig { min_reputation: REPUTATION_SCALE + 1, ..ReputationConfig::default() };
+    let too_high = client::set_reputation_config_ix(market.ledger, market.admin, config);
+    assert_eq!(market.bank.process(&too_high).unwrap_err(), custom(EnergyMarketError::InvalidReputationConfig));
+    let by_producer = client::set_reputation_config_ix(market.ledger, producer, ReputationConfig::default());
+    assert_eq!(market.bank.process(&by_producer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{client, ledger_space, EnergyMarketError, LedgerCapacity, MarketConfig, ParticipantMetadata, ParticipantType};
+use solana_program::{entrypoint::MAX_PERMITTED_DATA_INCREASE, program_error::ProgramError, rent::Rent};
+
+const FULL: LedgerCapacity = LedgerCapacity { max_participants: 1, max_open_orders: 2, max_transactions: 4 };
+const GROWN: LedgerCapacity = LedgerCapacity { max_participants: 2, max_open_orders: 4, max_transactions: 4 };
+
+#[test]
+fn grown_ledger_takes_the_participant_and_orders_it_had_no_room_for() {
+    let mut market = Market::builder(MarketConfig::default()).capacity(FULL).build();
+    let producer = market.register(ParticipantType::Producer, 0);
+    market.report_production(producer, 10, 10).unwrap();
+    market.report_production(producer, 10, 11).unwrap();
+    let late = market.bank.funded_wallet(1);
+    let register = client::register_participant_ix(market.ledger, late, ParticipantType::Consumer, 0, 0, ParticipantMetadata::default());
+    assert_eq!(market.bank.process(&register).unwrap_err(), custom(EnergyMarketError::LedgerFull));
+
+    // The admin's wallet tops up rent exemption for the larger account
+    let new_size = ledger_space(&GROWN);
+    let admin_lamports = market.bank.lamports(&market.admin);
+    let ledger_lamports = market.bank.lamports(&market.ledger);
+    market.bank.process(&client::resize_ledger_ix(market.ledger, market.admin, market.admin, new_size as u32, GROWN)).unwrap();
+    let top_up = Rent::default().minimum_balance(new_size) - ledger_lamports;
+    assert_eq!(market.bank.account(&market.ledger).unwrap().data.len(), new_size);
+    assert_eq!(market.bank.lamports(&market.ledger), ledger_lamports + top_up);
+    assert_eq!(market.bank.lamports(&market.admin), admin_lamports - top_up);
+
+    market.bank.process(&register).unwrap();
+    market.bank.process(&client::deposit_ix(market.ledger, late, 1_000, None)).unwrap();
+    market.report_production(producer, 10, 12).unwrap();
+    market.post_demand(late, 30, 12).unwrap();
+    market.match_orders(late, &[producer, late]).unwrap();
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.capacity.max_participants, ledger.total_trades, ledger.productions.len()), (2, 3, 0));
+}
+
+#[test]
+fn resize_never_shrinks_and_grows_at_most_the_realloc_limit_per_call() {
+    let mut market = Market::builder(MarketConfig::default()).capacity(FULL).build();
+    let resize = |market: &Market, new_size: usize, capacity| {
+        client::resize_ledger_ix(market.ledger, market.admin, market.admin, new_size as u32, capacity)
+    };
+
+    let fewer = LedgerCapacity { max_open_orders: 1, ..FULL };
+    assert_eq!(market.bank.process(&resize(&market, ledger_space(&FULL), fewer)).unwrap_err(), ProgramError::InvalidArgument);
+    let too_small = resize(&market, ledger_space(&GROWN) - 1, GROWN);
+    assert_eq!(market.bank.process(&too_small).unwrap_err(), custom(EnergyMarketError::LedgerAccountTooSmall));
+    let too_far = resize(&market, ledger_space(&FULL) + MAX_PERMITTED_DATA_INCREASE + 1, FULL);
+    assert_eq!(market.bank.process(&too_far).unwrap_err(), ProgramError::InvalidRealloc);
+
+    let stranger = market.register(ParticipantType::Producer, 0);
+    let by_stranger = client::resize_ledger_ix(market.ledger, stranger, stranger, ledger_space(&GROWN) as u32, GROWN);
+    assert_eq!(market.bank.process(&by_stranger).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    assert_eq!(market.bank.account(&market.ledger).unwrap().data.len(), ledger_space(&FULL));
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, events::RewardsClaimed, EnergyMarketError, MarketConfig, ParticipantType, RewardRates,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+// Makers earn 3 points per kWh and takers 1. The admin registers with `admin_deposit` to fund the pool.
+fn rewarded_market(admin_deposit: u64) -> Market {
+    let rates = RewardRates { maker_points_per_kwh: 3, taker_points_per_kwh: 1 };
+    Market::builder(MarketConfig::default())
+        .registered_admin(admin_deposit)
+        .admin_instruction(move |ledger, admin| client::set_reward_rates_ix(ledger, admin, rates))
+        .build()
+}
+
+// The producer's offer of `amount` kWh at 1 rests in the book before the consumer's demand arrives
+fn trade(market: &mut Market, amount: u64) -> (Pubkey, Pubkey) {
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, amount);
+    market.report_production(producer, amount, 1).unwrap();
+    market.post_demand(consumer, amount, 1).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    (producer, consumer)
+}
+
+fn points(market: &Market, wallet: &Pubkey) -> u64 {
+    market.bank.participant(&market.ledger, wallet).reward_points
+}
+
+fn claim(market: &mut Market, wallet: Pubkey) -> Result<Vec<RewardsClaimed>, ProgramError> {
+    market.bank.process(&client::claim_rewards_ix(market.ledger, wallet))?;
+    Ok(market.bank.events())
+}
+
+#[test]
+fn makers_and_takers_accrue_at_their_own_rates() {
+    let mut market = rewarded_market(0);
+    let (producer, consumer) = trade(&mut market, 100);
+    assert_eq!((points(&market, &producer), points(&market, &consumer)), (300, 100));
+
+    // A demand resting in the book makes its consumer the maker
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 50);
+    market.post_demand(consumer, 50, 1).unwrap();
+    market.report_production(producer, 50, 1).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+    assert_eq!((points(&market, &producer), points(&market, &consumer)), (50, 150));
+}
+
+#[test]
+fn claims_credit_points_from_the_pool() {
+    let mut market = rewarded_market(1_000);
+    market.bank.process(&client::fund_rewards_ix(market.ledger, market.admin, 1_000)).unwrap();
+    let (producer, _) = trade(&mut market, 100);
+
+    assert_eq!(
+        claim(&mut market, producer).unwrap(),
+        vec![RewardsClaimed { participant: producer, reward_epoch: 1, points: 300, credited: 300 }],
+    );
+    let participant = market.bank.participant(&market.ledger, &producer);
+    assert_eq!((participant.wallet_balance, participant.reward_points), (100 + 300, 0));
+    assert_eq!(market.bank.ledger(&market.ledger).rewards_pool, 700);
+    assert_eq!(claim(&mut market, producer).unwrap_err(), custom(EnergyMarketError::NoRewardPoints));
+}
+
+#[test]
+fn exhausted_pool_rejects_claims_until_it_is_funded_again() {
+    let mut market = rewarded_market(200);
+    let (producer, _) = trade(&mut market, 100);
+    assert_eq!(claim(&mut market, producer).unwrap_err(), custom(EnergyMarketError::RewardsPoolEmpty));
+    assert_eq!(points(&market, &producer), 300);
+
+    // A pool short of the points pays what it holds and leaves the rest to claim later
+    market.bank.process(&client::fund_rewards_ix(market.ledger, market.admin, 200)).unwrap();
+    assert_eq!(claim(&mut market, producer).unwrap()[0].credited, 200);
+    assert_eq!(points(&market, &producer), 100);
+    assert_eq!(claim(&mut market, producer).unwrap_err(), custom(EnergyMarketError::RewardsPoolEmpty));
+    let overdrawn = client::fund_rewards_ix(market.ledger, market.admin, 1);
+    assert_eq!(market.bank.process(&overdrawn).unwrap_err(), custom(EnergyMarketError::InsufficientBalance));
+}
+
+#[test]
+fn rate_changes_start_a_new_epoch_without_revaluing_points() {
+    let mut market = rewarded_market(0);
+    let (producer, consumer) = trade(&mut market, 100);
+    let by_producer = client::set_reward_rates_ix(market.ledger, producer, RewardRates::default());
+    assert_eq!(market.bank.process(&by_producer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+
+    let doubled = RewardRates { maker_points_per_kwh: 6, taker_points_per_kwh: 2 };
+    market.bank.process(&client::set_reward_rates_ix(market.ledger, market.admin, doubled)).unwrap();
+    assert_eq!(market.bank.ledger(&market.ledger).reward_epoch, 2);
+    assert_eq!((points(&market, &producer), points(&market, &consumer)), (300, 100));
+
+    let (producer, consumer) = trade(&mut market, 100);
+    assert_eq!((points(&market, &producer), points(&market, &consumer)), (600, 200));
+    market.bank.process(&client::set_reward_rates_ix(market.ledger, market.admin, RewardRates::default())).unwrap();
+    let (producer, consumer) = trade(&mut market, 100);
+    assert_eq!((points(&market, &producer), points(&market, &consumer)), (0, 0));
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use common::Market;
+use energy_trading_program::{client, events::TradeExecuted, ConfigUpdate, MarketConfig, ParticipantType};
+
+#[test]
+fn prosumer_orders_do_not_cross_each_other_by_default() {
+    let mut market = Market::new(MarketConfig::default());
+    let prosumer = market.register(ParticipantType::Prosumer, 1_000);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(prosumer, 50, 10).unwrap();
+    market.post_demand(prosumer, 50, 10).unwrap();
+    market.post_demand(consumer, 30, 10).unwrap();
+
+    // The prosumer's own demand is ahead in time, but its offer goes to the other consumer
+    market.match_orders(consumer, &[prosumer, consumer]).unwrap();
+    let fills: Vec<_> = market.bank.events::<TradeExecuted>().iter().map(|t| (t.buyer, t.seller, t.amount)).collect();
+    assert_eq!(fills, vec![(consumer, prosumer, 30)]);
+    let ledger = market.bank.ledger(&market.ledger);
+    let prosumer_id = market.bank.participant(&market.ledger, &prosumer).id;
+    assert_eq!((ledger.productions[0].energy_amount, ledger.demands[0].consumer_id), (20, prosumer_id));
+}
+
+#[test]
+fn ledger_can_opt_in_to_internal_netting() {
+    let mut market = Market::new(MarketConfig::default());
+    market.bank.process(&client::update_config_ix(market.ledger, market.admin, ConfigUpdate::AllowSelfTrade(true))).unwrap();
+    let prosumer = market.register(ParticipantType::Prosumer, 1_000);
+    market.report_production(prosumer, 50, 10).unwrap();
+    market.post_demand(prosumer, 50, 10).unwrap();
+
+    market.match_orders(prosumer, &[prosumer]).unwrap();
+    let fills: Vec<_> = market.bank.events::<TradeExecuted>().iter().map(|t| (t.buyer, t.seller, t.amount)).collect();
+    assert_eq!(fills, vec![(prosumer, prosumer, 50)]);
+    let ledger = market.bank.ledger(&market.ledger);
+    assert!(ledger.productions.is_empty() && ledger.demands.is_empty());
+    let participant = market.bank.participant(&market.ledger, &prosumer);
+    assert_eq!((participant.wallet_balance, participant.reserved_balance), (1_000, 0));
+}
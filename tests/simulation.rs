@@ -0,0 +1,88 @@
+mod common;
+
+use borsh::BorshDeserialize;
+use common::{program_id, Market, TestAccount};
+use energy_trading_program::{
+    client, events::TradeExecuted, find_participant_address, MarketConfig, MatchSimulation, OrderSide, ParticipantType,
+    SimulatedFill, MAX_SIMULATED_FILLS,
+};
+use solana_program::pubkey::Pubkey;
+
+fn simulate(market: &mut Market, caller: Pubkey, max_matches: u16, wallets: &[Pubkey]) -> MatchSimulation {
+    let remaining = client::participant_metas(market.ledger, wallets);
+    market.bank.process(&client::simulate_match_ix(market.ledger, caller, max_matches, remaining)).unwrap();
+    MatchSimulation::try_from_slice(&market.bank.return_data().unwrap()).unwrap()
+}
+
+// The ledger and every participant PDA, byte for byte
+fn snapshot(market: &Market, wallets: &[Pubkey]) -> Vec<TestAccount> {
+    let participants = wallets.iter().map(|wallet| find_participant_address(&program_id(), &market.ledger, wallet).0);
+    std::iter::once(market.ledger).chain(participants).map(|key| market.bank.account(&key).unwrap().clone()).collect()
+}
+
+#[test]
+fn simulation_predicts_the_crank_without_writing_anything() {
+    let mut market = Market::new(MarketConfig::default());
+    let producers = [market.register(ParticipantType::Producer, 0), market.register(ParticipantType::Producer, 0)];
+    let consumers = [market.register(ParticipantType::Consumer, 10_000), market.register(ParticipantType::Consumer, 10_000)];
+    market.report_production(producers[0], 30, 9).unwrap();
+    market.report_production(producers[1], 40, 11).unwrap();
+    market.report_production(producers[0], 50, 14).unwrap();
+    market.post_demand(consumers[0], 60, 12).unwrap();
+    market.post_demand(consumers[1], 50, 15).unwrap();
+    let wallets = [producers[0], producers[1], consumers[0], consumers[1]];
+
+    let before = snapshot(&market, &wallets);
+    let simulation = simulate(&mut market, consumers[1], 16, &wallets);
+    assert_eq!(snapshot(&market, &wallets), before);
+    assert!(market.bank.events::<TradeExecuted>().is_empty());
+
+    market.match_orders(producers[0], &wallets).unwrap();
+    let trades = market.bank.events::<TradeExecuted>();
+    let caller_fills: Vec<_> = trades.iter()
+        .filter(|t| t.buyer == consumers[1])
+        .map(|t| SimulatedFill { order_id: t.demand_order_id, side: OrderSide::Demand, amount: t.amount, price: t.price })
+        .collect();
+    assert!(!caller_fills.is_empty());
+    assert_eq!(simulation, MatchSimulation {
+        trade_count: trades.len() as u32,
+        total_volume: trades.iter().map(|t| t.amount).sum(),
+        lowest_price: trades.iter().map(|t| t.price).min().unwrap(),
+        highest_price: trades.iter().map(|t| t.price).max().unwrap(),
+        caller_fills,
+        caller_fills_truncated: false,
+    });
+}
+
+#[test]
+fn simulation_honours_max_matches_and_caps_the_caller_fills() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 10_000);
+    let offers = MAX_SIMULATED_FILLS as i64 + 1;
+    for price in 1..=offers {
+        market.report_production(producer, 1, price).unwrap();
+    }
+    market.post_demand(consumer, offers as u64, offers).unwrap();
+
+    let capped = simulate(&mut market, consumer, 2, &[producer, consumer]);
+    assert_eq!((capped.trade_count, capped.total_volume, capped.lowest_price, capped.highest_price), (2, 2, 1, 2));
+    assert_eq!((capped.caller_fills.len(), capped.caller_fills_truncated), (2, false));
+
+    let all = simulate(&mut market, producer, u16::MAX, &[producer, consumer]);
+    assert_eq!((all.trade_count, all.highest_price), (offers as u32, offers));
+    assert_eq!((all.caller_fills.len(), all.caller_fills_truncated), (MAX_SIMULATED_FILLS, true));
+    assert!(all.caller_fills.iter().all(|fill| fill.side == OrderSide::Production));
+
+    // Nothing crossing leaves the price bounds at 0
+    let mut idle = Market::new(MarketConfig::default());
+    let wallet = idle.register(ParticipantType::Consumer, 0);
+    assert_eq!(simulate(&mut idle, wallet, 16, &[wallet]), MatchSimulation {
+        trade_count: 0,
+        total_volume: 0,
+        lowest_price: 0,
+        highest_price: 0,
+        caller_fills: Vec::new(),
+        caller_fills_truncated: false,
+    });
+}
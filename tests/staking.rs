@@ -0,0 +1,68 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{client, events::TradeExecuted, EnergyMarketError, MarketConfig, ParticipantType};
+
+const LOCK: i64 = 3_600;
+
+#[test]
+fn higher_stake_fills_first_at_equal_price() {
+    let mut market = Market::new(MarketConfig::default());
+    let early = market.register(ParticipantType::Producer, 100);
+    let staked = market.register(ParticipantType::Producer, 500);
+    let cheaper = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.bank.process(&client::stake_for_priority_ix(market.ledger, early, 100, LOCK)).unwrap();
+    market.bank.process(&client::stake_for_priority_ix(market.ledger, staked, 500, LOCK)).unwrap();
+
+    // The stake breaks the tie between the two offers at 10, ahead of time priority, but never
+    // beats a better price
+    market.report_production(early, 50, 10).unwrap();
+    market.report_production(staked, 50, 10).unwrap();
+    market.report_production(cheaper, 30, 9).unwrap();
+    market.post_demand(consumer, 60, 10).unwrap();
+    market.match_orders(consumer, &[early, staked, cheaper, consumer]).unwrap();
+    let fills: Vec<_> = market.bank.events::<TradeExecuted>().iter().map(|t| (t.seller, t.amount)).collect();
+    assert_eq!(fills, vec![(cheaper, 30), (staked, 30)]);
+}
+
+#[test]
+fn stake_stays_locked_until_the_cooldown_ends() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 1_000);
+    market.bank.process(&client::stake_for_priority_ix(market.ledger, producer, 400, LOCK)).unwrap();
+    let unlocks_at = market.bank.now + LOCK;
+
+    // Staking again adds to the stake without shortening the lock
+    market.bank.process(&client::stake_for_priority_ix(market.ledger, producer, 100, 10)).unwrap();
+    let participant = market.bank.participant(&market.ledger, &producer);
+    assert_eq!((participant.wallet_balance, participant.staked_balance, participant.stake_unlocks_at), (500, 500, unlocks_at));
+
+    let unstake = |amount| client::unstake_after_cooldown_ix(market.ledger, producer, amount);
+    market.bank.now = unlocks_at - 1;
+    assert_eq!(market.bank.process(&unstake(500)).unwrap_err(), custom(EnergyMarketError::StakeLocked));
+    market.bank.now = unlocks_at;
+    assert_eq!(market.bank.process(&unstake(501)).unwrap_err(), custom(EnergyMarketError::InsufficientStake));
+    market.bank.process(&unstake(500)).unwrap();
+    let participant = market.bank.participant(&market.ledger, &producer);
+    assert_eq!((participant.wallet_balance, participant.staked_balance), (1_000, 0));
+
+    let consumer = market.register(ParticipantType::Consumer, 100);
+    let by_consumer = client::stake_for_priority_ix(market.ledger, consumer, 100, LOCK);
+    assert_eq!(market.bank.process(&by_consumer).unwrap_err(), custom(EnergyMarketError::InvalidParticipantType));
+}
+
+#[test]
+fn stake_is_neither_withdrawable_nor_collateral() {
+    let mut market = Market::new(MarketConfig { collateral_bps: 10_000, ..MarketConfig::default() });
+    let producer = market.register(ParticipantType::Producer, 1_000);
+    market.bank.process(&client::stake_for_priority_ix(market.ledger, producer, 1_000, LOCK)).unwrap();
+
+    let withdraw = client::withdraw_ix(market.ledger, producer, producer, 1, None);
+    assert_eq!(market.bank.process(&withdraw).unwrap_err(), custom(EnergyMarketError::InsufficientBalance));
+    assert_eq!(market.report_production(producer, 10, 10).unwrap_err(), custom(EnergyMarketError::InsufficientCollateral));
+    assert_eq!(
+        market.bank.process(&client::unregister_participant_ix(market.ledger, producer)).unwrap_err(),
+        custom(EnergyMarketError::ParticipantHasBalance),
+    );
+}
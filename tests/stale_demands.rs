@@ -0,0 +1,75 @@
+mod common;
+
+use common::Market;
+use energy_trading_program::{
+    client, delivery_slot_at, events::{OrderExpired, TradeExecuted}, ConfigUpdate, MarketConfig, OrderSide, ParticipantType,
+    TimeInForce,
+};
+
+#[test]
+fn crank_prunes_stale_demands_no_offer_can_fill() {
+    let mut market = Market::new(MarketConfig { stale_demand_timeout: 600, ..MarketConfig::default() });
+    let producer = market.register(ParticipantType::Producer, 0);
+    let hopeless_stale = market.register(ParticipantType::Consumer, 1_000);
+    let matchable_stale = market.register(ParticipantType::Consumer, 1_000);
+    let hopeless_fresh = market.register(ParticipantType::Consumer, 1_000);
+    let no_offers = market.register(ParticipantType::Consumer, 1_000);
+
+    market.report_production(producer, 50, 10).unwrap();
+    market.post_demand(hopeless_stale, 10, 5).unwrap();
+    market.post_demand(matchable_stale, 20, 12).unwrap();
+    // A partition without any offer gives no evidence that its demands cannot fill
+    let next_slot = delivery_slot_at(market.bank.now) + 1;
+    market.bank.process(&client::post_demand_ix(
+        market.ledger, no_offers, 10, 5, 0, next_slot, TimeInForce::GoodTilCancelled, 0, false, 0, 0, None,
+    )).unwrap();
+    market.bank.now += 1_000;
+    market.post_demand(hopeless_fresh, 10, 7).unwrap();
+    let pruned_order_id = market.bank.ledger(&market.ledger).demands.iter()
+        .find(|d| d.consumer_id == hopeless_stale)
+        .unwrap()
+        .order_id;
+
+    market.match_orders(producer, &[producer, hopeless_stale, matchable_stale, hopeless_fresh, no_offers]).unwrap();
+
+    let trades: Vec<TradeExecuted> = market.bank.events();
+    assert_eq!(trades.len(), 1);
+    assert_eq!((trades[0].buyer, trades[0].amount, trades[0].price), (matchable_stale, 20, 10));
+    assert_eq!(market.bank.events::<OrderExpired>(), vec![OrderExpired {
+        order_id: pruned_order_id,
+        owner: hopeless_stale,
+        side: OrderSide::Demand,
+        remaining_amount: 10,
+    }]);
+    let ledger = market.bank.ledger(&market.ledger);
+    let mut remaining: Vec<_> = ledger.demands.iter().map(|d| d.consumer_id).collect();
+    remaining.sort();
+    let mut expected = vec![hopeless_fresh, no_offers];
+    expected.sort();
+    assert_eq!(remaining, expected);
+
+    // The pruned demand's escrow went back to its owner
+    let consumer = market.bank.participant(&market.ledger, &hopeless_stale);
+    assert_eq!((consumer.wallet_balance, consumer.reserved_balance, consumer.open_demands), (1_000, 0, 0));
+    assert_eq!(market.bank.participant(&market.ledger, &hopeless_fresh).reserved_balance, 70);
+}
+
+#[test]
+fn stale_demands_are_kept_until_a_timeout_is_set() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producer, 50, 10).unwrap();
+    market.post_demand(consumer, 10, 5).unwrap();
+    market.bank.now += 1_000;
+
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+    assert!(market.bank.events::<OrderExpired>().is_empty());
+    assert_eq!(market.bank.ledger(&market.ledger).demands.len(), 1);
+
+    market.bank.process(&client::update_config_ix(market.ledger, market.admin, ConfigUpdate::StaleDemandTimeout(600))).unwrap();
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+    assert_eq!(market.bank.events::<OrderExpired>().len(), 1);
+    assert!(market.bank.ledger(&market.ledger).demands.is_empty());
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).wallet_balance, 1_000);
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, delivery_slot_at, events::{StandingOrderActivated, StandingOrderSkipped, TradeExecuted}, EnergyMarketError, MarketConfig,
+    OrderSide, ParticipantType, MAX_STANDING_ORDERS_PER_PARTICIPANT,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+const DAY: i64 = 86_400;
+
+fn create(market: &mut Market, owner: Pubkey, side: OrderSide, price: i64, activations: u32) -> Result<u64, ProgramError> {
+    let standing_order_id = market.bank.ledger(&market.ledger).next_order_id;
+    let now = market.bank.now;
+    market.bank.process(&client::create_standing_order_ix(market.ledger, owner, side, 10, price, DAY, now, activations))?;
+    Ok(standing_order_id)
+}
+
+fn activate(market: &mut Market, owners: &[Pubkey]) {
+    let cranker = market.bank.funded_wallet(1);
+    market.bank.process(&client::activate_standing_orders_ix(market.ledger, cranker, owners)).unwrap();
+}
+
+#[test]
+fn standing_demand_posts_once_per_interval_until_used_up() {
+    let mut market = Market::new(MarketConfig::default());
+    let household = market.register(ParticipantType::Consumer, 250);
+    let start = market.bank.now;
+    let standing_order_id = create(&mut market, household, OrderSide::Demand, 10, 3).unwrap();
+
+    activate(&mut market, &[household]);
+    let activated = market.bank.events::<StandingOrderActivated>();
+    assert_eq!(activated.len(), 1);
+    assert_eq!((activated[0].next_activation, activated[0].remaining_activations), (start + DAY, 2));
+    let demand = market.bank.ledger(&market.ledger).demands[0].clone();
+    assert_eq!(
+        (demand.order_id, demand.energy_amount, demand.price_limit, demand.delivery_slot),
+        (activated[0].order_id, 10, 10, delivery_slot_at(start)),
+    );
+    assert_eq!(market.bank.participant(&market.ledger, &household).reserved_balance, 100);
+
+    // Nothing is due again until the interval has passed
+    market.bank.now += DAY - 1;
+    activate(&mut market, &[household]);
+    assert!(market.bank.events::<StandingOrderActivated>().is_empty());
+
+    market.bank.now += 1;
+    activate(&mut market, &[household]);
+    assert_eq!(market.bank.events::<StandingOrderActivated>()[0].remaining_activations, 1);
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!(ledger.demands.last().unwrap().delivery_slot, delivery_slot_at(start + DAY));
+
+    // A crank two and a half days late posts a single order, which the household can no longer fund
+    market.bank.now += DAY * 5 / 2;
+    activate(&mut market, &[household]);
+    assert!(market.bank.events::<StandingOrderActivated>().is_empty());
+    assert_eq!(market.bank.events::<StandingOrderSkipped>(), vec![StandingOrderSkipped {
+        standing_order_id,
+        owner: household,
+        error: EnergyMarketError::InsufficientBalance as u32,
+        next_activation: start + 4 * DAY,
+        remaining_activations: 0,
+    }]);
+    let ledger = market.bank.ledger(&market.ledger);
+    assert!(ledger.standing_orders.is_empty());
+    assert_eq!(ledger.demands.len(), 2);
+    let participant = market.bank.participant(&market.ledger, &household);
+    assert_eq!((participant.wallet_balance, participant.reserved_balance), (50, 200));
+}
+
+#[test]
+fn standing_orders_on_both_sides_trade_every_day() {
+    let mut market = Market::new(MarketConfig::default());
+    let solar_roof = market.register(ParticipantType::Producer, 0);
+    let household = market.register(ParticipantType::Consumer, 1_000);
+    let bystander = market.register(ParticipantType::Consumer, 1_000);
+    create(&mut market, solar_roof, OrderSide::Production, 8, 2).unwrap();
+    create(&mut market, household, OrderSide::Demand, 10, 2).unwrap();
+    create(&mut market, bystander, OrderSide::Demand, 10, 2).unwrap();
+
+    for day in 0..3 {
+        // The bystander's participant is never passed, so its standing order stays due
+        activate(&mut market, &[solar_roof, household, household]);
+        market.match_orders(solar_roof, &[solar_roof, household]).unwrap();
+        let trades: Vec<TradeExecuted> = market.bank.events();
+        if day < 2 {
+            assert_eq!(trades.iter().map(|t| (t.buyer, t.seller, t.amount, t.price)).collect::<Vec<_>>(), vec![(household, solar_roof, 10, 8)]);
+        } else {
+            assert!(trades.is_empty());
+        }
+        market.bank.now += DAY;
+    }
+
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!(ledger.standing_orders.iter().map(|s| (s.owner, s.remaining_activations)).collect::<Vec<_>>(), vec![(bystander, 2)]);
+    assert_eq!(market.bank.participant(&market.ledger, &solar_roof).wallet_balance, 160);
+}
+
+#[test]
+fn standing_orders_are_capped_and_cancelled_by_their_owner() {
+    let mut market = Market::new(MarketConfig::default());
+    let household = market.register(ParticipantType::Consumer, 1_000);
+    let neighbour = market.register(ParticipantType::Consumer, 0);
+
+    assert_eq!(create(&mut market, household, OrderSide::Demand, 10, 0).unwrap_err(), custom(EnergyMarketError::InvalidStandingOrder));
+    assert_eq!(
+        create(&mut market, household, OrderSide::Production, 10, 1).unwrap_err(),
+        custom(EnergyMarketError::InvalidParticipantType),
+    );
+    let ids: Vec<u64> = (0..MAX_STANDING_ORDERS_PER_PARTICIPANT)
+        .map(|_| create(&mut market, household, OrderSide::Demand, 10, 1).unwrap())
+        .collect();
+    assert_eq!(create(&mut market, household, OrderSide::Demand, 10, 1).unwrap_err(), custom(EnergyMarketError::TooManyStandingOrders));
+    create(&mut market, neighbour, OrderSide::Demand, 10, 1).unwrap();
+
+    let cancel = client::cancel_standing_order_ix(market.ledger, neighbour, ids[0]);
+    assert_eq!(market.bank.process(&cancel).unwrap_err(), custom(EnergyMarketError::NotOrderOwner));
+    market.bank.process(&client::cancel_standing_order_ix(market.ledger, household, ids[0])).unwrap();
+    create(&mut market, household, OrderSide::Demand, 10, 1).unwrap();
+    assert_eq!(
+        market.bank.process(&client::cancel_standing_order_ix(market.ledger, household, ids[0])).unwrap_err(),
+        custom(EnergyMarketError::OrderNotFound),
+    );
+
+    // Unregistering drops the participant's standing orders along with it
+    market.bank.process(&client::unregister_participant_ix(market.ledger, neighbour)).unwrap();
+    let owners: Vec<Pubkey> = market.bank.ledger(&market.ledger).standing_orders.iter().map(|s| s.owner).collect();
+    assert_eq!(owners, vec![household; MAX_STANDING_ORDERS_PER_PARTICIPANT]);
+}
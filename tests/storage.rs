@@ -0,0 +1,78 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{client, events::TradeExecuted, EnergyMarketError, MarketConfig, ParticipantType};
+use solana_program::pubkey::Pubkey;
+
+// A 100 kWh battery keeping 80% of what it buys
+fn market_with_battery() -> (Market, Pubkey) {
+    let mut market = Market::new(MarketConfig::default());
+    let battery = market.register(ParticipantType::Storage, 10_000);
+    market.bank.process(&client::set_storage_parameters_ix(market.ledger, market.admin, battery, 100, 8_000)).unwrap();
+    (market, battery)
+}
+
+fn stored(market: &Market, battery: &Pubkey) -> u64 {
+    market.bank.participant(&market.ledger, battery).stored_energy
+}
+
+// The battery buys 50 kWh and stores 40 of it
+fn charge(market: &mut Market, battery: Pubkey) -> Pubkey {
+    let producer = market.register(ParticipantType::Producer, 0);
+    market.report_production(producer, 50, 10).unwrap();
+    market.post_demand(battery, 50, 10).unwrap();
+    market.match_orders(battery, &[producer, battery]).unwrap();
+    producer
+}
+
+#[test]
+fn battery_charges_with_losses_up_to_its_capacity() {
+    let (mut market, battery) = market_with_battery();
+    charge(&mut market, battery);
+    assert_eq!(stored(&market, &battery), 40);
+
+    // 76 kWh bought fill the remaining 60 after losses; one more would overflow
+    assert_eq!(market.post_demand(battery, 77, 10).unwrap_err(), custom(EnergyMarketError::StorageFull));
+    let producer = market.register(ParticipantType::Producer, 0);
+    market.report_production(producer, 100, 10).unwrap();
+    market.post_demand(battery, 76, 10).unwrap();
+    market.match_orders(battery, &[producer, battery]).unwrap();
+    assert_eq!(stored(&market, &battery), 100);
+    // A full battery may still buy the 1 kWh that rounds down to nothing stored
+    assert_eq!(market.post_demand(battery, 2, 10).unwrap_err(), custom(EnergyMarketError::StorageFull));
+}
+
+#[test]
+fn battery_cannot_sell_energy_it_has_not_charged() {
+    let (mut market, battery) = market_with_battery();
+    assert_eq!(market.report_production(battery, 1, 10).unwrap_err(), custom(EnergyMarketError::InsufficientStoredEnergy));
+    charge(&mut market, battery);
+    assert_eq!(market.report_production(battery, 41, 10).unwrap_err(), custom(EnergyMarketError::InsufficientStoredEnergy));
+
+    // Two offers may each stand against the stored 40, but together they only ever deliver it once
+    market.report_production(battery, 40, 10).unwrap();
+    market.report_production(battery, 40, 11).unwrap();
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.post_demand(consumer, 80, 11).unwrap();
+    market.match_orders(consumer, &[battery, consumer]).unwrap();
+    let sold: u64 = market.bank.events::<TradeExecuted>().iter().map(|t| t.amount).sum();
+    assert_eq!((sold, stored(&market, &battery)), (40, 0));
+    assert_eq!(market.bank.ledger(&market.ledger).productions[0].energy_amount, 40);
+}
+
+#[test]
+fn storage_parameters_are_checked_against_the_battery() {
+    let (mut market, battery) = market_with_battery();
+    charge(&mut market, battery);
+    let (ledger, admin) = (market.ledger, market.admin);
+    let set = move |wallet, capacity, efficiency| client::set_storage_parameters_ix(ledger, admin, wallet, capacity, efficiency);
+    assert_eq!(market.bank.process(&set(battery, 39, 8_000)).unwrap_err(), custom(EnergyMarketError::InvalidStorageParameters));
+    assert_eq!(market.bank.process(&set(battery, 100, 0)).unwrap_err(), custom(EnergyMarketError::InvalidStorageParameters));
+    assert_eq!(market.bank.process(&set(battery, 100, 10_001)).unwrap_err(), custom(EnergyMarketError::InvalidStorageParameters));
+    let consumer = market.register(ParticipantType::Consumer, 0);
+    assert_eq!(market.bank.process(&set(consumer, 100, 8_000)).unwrap_err(), custom(EnergyMarketError::InvalidParticipantType));
+
+    market.bank.process(&set(battery, 40, 10_000)).unwrap();
+    let participant = market.bank.participant(&market.ledger, &battery);
+    assert_eq!((participant.storage_capacity, participant.storage_efficiency_bps), (40, 10_000));
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, delivery_slot_at, delivery_slot_end, tariff_bounds, EnergyMarketError, EnergySource, MarketConfig, OrderStorage,
+    ParticipantType, TariffSchedule, TimeInForce, HOURS_PER_DAY,
+};
+
+// The harness clock starts in hour 22 UTC, which is peak at 1.5x; hour 23 is off-peak at 0.5x
+fn peak_schedule() -> TariffSchedule {
+    let mut multiplier_bps = [10_000; HOURS_PER_DAY];
+    multiplier_bps[22] = 15_000;
+    multiplier_bps[23] = 5_000;
+    TariffSchedule { base_floor_price: 10, base_ceiling_price: 20, multiplier_bps }
+}
+
+fn market_with_tariff() -> Market {
+    let market = Market::builder(MarketConfig::default())
+        .admin_instruction(|ledger, admin| client::set_tariff_schedule_ix(ledger, admin, peak_schedule()))
+        .build();
+    assert_eq!(delivery_slot_at(market.bank.now) % 24, 22);
+    market
+}
+
+#[test]
+fn offers_below_the_floor_of_their_delivery_hour_are_rejected() {
+    let mut market = market_with_tariff();
+    let producer = market.register(ParticipantType::Producer, 0);
+
+    assert_eq!(market.report_production(producer, 10, 14).unwrap_err(), custom(EnergyMarketError::PriceBelowTariffFloor));
+    market.report_production(producer, 10, 15).unwrap();
+
+    // The hour comes from the delivery slot, so an offer for later in the day sees that hour's floor
+    let midnight = delivery_slot_at(market.bank.now) + 2;
+    let offer_for = |slot: u32, price: i64| client::report_production_ix(
+        market.ledger, producer, 10, price, 0, slot, EnergySource::Solar, TimeInForce::GoodTilCancelled, 0, false, 0, true, None,
+    );
+    let (too_low, at_floor) = (offer_for(midnight, 9), offer_for(midnight, 10));
+    assert_eq!(market.bank.process(&too_low).unwrap_err(), custom(EnergyMarketError::PriceBelowTariffFloor));
+    market.bank.process(&at_floor).unwrap();
+
+    // Until the last second of the peak hour an offer for the current slot pays the peak floor
+    let slot = delivery_slot_at(market.bank.now);
+    market.bank.now = delivery_slot_end(slot) - 1;
+    assert_eq!(market.report_production(producer, 10, 5).unwrap_err(), custom(EnergyMarketError::PriceBelowTariffFloor));
+    market.bank.now += 1;
+    market.report_production(producer, 10, 5).unwrap();
+    assert_eq!(market.report_production(producer, 10, 4).unwrap_err(), custom(EnergyMarketError::PriceBelowTariffFloor));
+}
+
+#[test]
+fn demands_are_capped_at_the_ceiling() {
+    let mut market = market_with_tariff();
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+
+    assert_eq!(market.post_demand(consumer, 10, 31).unwrap_err(), custom(EnergyMarketError::PriceAboveTariffCeiling));
+    market.post_demand(consumer, 10, 30).unwrap();
+    let order_id = market.bank.ledger(&market.ledger).demands[0].order_id;
+    let raise = client::replace_order_ix(market.ledger, consumer, order_id, 31, 10, OrderStorage::Ledger);
+    assert_eq!(market.bank.process(&raise).unwrap_err(), custom(EnergyMarketError::PriceAboveTariffCeiling));
+}
+
+#[test]
+fn schedule_is_set_by_the_admin_and_bounds_round_down() {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+
+    let by_producer = client::set_tariff_schedule_ix(market.ledger, producer, peak_schedule());
+    assert_eq!(market.bank.process(&by_producer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    let inverted = TariffSchedule { base_floor_price: 30, ..peak_schedule() };
+    let set_inverted = client::set_tariff_schedule_ix(market.ledger, market.admin, inverted);
+    assert_eq!(market.bank.process(&set_inverted).unwrap_err(), custom(EnergyMarketError::InvalidConfigValue));
+
+    assert_eq!(tariff_bounds(&peak_schedule(), 22), (Some(15), Some(30)));
+    assert_eq!(tariff_bounds(&peak_schedule(), 24 + 23), (Some(5), Some(10)));
+    let negative = TariffSchedule { base_floor_price: -7, base_ceiling_price: 0, ..peak_schedule() };
+    assert_eq!(tariff_bounds(&negative, 22), (Some(-11), None));
+    assert_eq!(tariff_bounds(&TariffSchedule::default(), 22), (None, None));
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, delivery_slot_at, events::TradeExecuted, EnergyMarketError, EnergySource, MarketConfig, ParticipantType,
+    TimeInForce,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+// A demand posted with `time_in_force`, passing the resting offers of `producers` it may cross
+fn demand(
+    market: &mut Market,
+    consumer: Pubkey,
+    energy_amount: u64,
+    time_in_force: TimeInForce,
+    producers: &[Pubkey],
+) -> Result<(), ProgramError> {
+    let slot = delivery_slot_at(market.bank.now);
+    let mut instruction = client::post_demand_ix(market.ledger, consumer, energy_amount, 10, 0, slot, time_in_force, 0, false, 0, 0, None);
+    instruction.accounts.extend(client::participant_metas(market.ledger, producers));
+    market.bank.process(&instruction)
+}
+
+// 30 kWh on offer at 10 against a consumer holding 1_000
+fn partial_liquidity() -> (Market, Pubkey, Pubkey) {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.report_production(producer, 30, 10).unwrap();
+    (market, producer, consumer)
+}
+
+fn balances(market: &Market, wallet: &Pubkey) -> (u64, u64) {
+    let participant = market.bank.participant(&market.ledger, wallet);
+    (participant.wallet_balance, participant.reserved_balance)
+}
+
+#[test]
+fn good_til_cancelled_rests_without_matching() {
+    let (mut market, producer, consumer) = partial_liquidity();
+    demand(&mut market, consumer, 50, TimeInForce::GoodTilCancelled, &[producer]).unwrap();
+    assert!(market.bank.events::<TradeExecuted>().is_empty());
+    assert_eq!(market.bank.ledger(&market.ledger).demands[0].energy_amount, 50);
+    assert_eq!(balances(&market, &consumer), (500, 500));
+}
+
+#[test]
+fn immediate_or_cancel_fills_what_it_can_and_drops_the_rest() {
+    let (mut market, producer, consumer) = partial_liquidity();
+    demand(&mut market, consumer, 50, TimeInForce::ImmediateOrCancel, &[producer]).unwrap();
+    let fills: Vec<_> = market.bank.events::<TradeExecuted>().iter().map(|t| (t.seller, t.amount)).collect();
+    assert_eq!(fills, vec![(producer, 30)]);
+    let ledger = market.bank.ledger(&market.ledger);
+    assert!(ledger.demands.is_empty() && ledger.productions.is_empty());
+    assert_eq!(balances(&market, &consumer), (700, 0));
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).open_demands, 0);
+
+    // An immediate offer likewise takes the resting demand and leaves nothing behind
+    market.post_demand(consumer, 20, 10).unwrap();
+    let slot = delivery_slot_at(market.bank.now);
+    let mut offer = client::report_production_ix(
+        market.ledger, producer, 50, 10, 0, slot, EnergySource::Solar, TimeInForce::ImmediateOrCancel, 0, false, 0, false, None,
+    );
+    offer.accounts.extend(client::participant_metas(market.ledger, &[consumer]));
+    market.bank.process(&offer).unwrap();
+    assert_eq!(market.bank.events::<TradeExecuted>()[0].amount, 20);
+    let ledger = market.bank.ledger(&market.ledger);
+    assert!(ledger.demands.is_empty() && ledger.productions.is_empty());
+}
+
+#[test]
+fn fill_or_kill_fills_in_full_or_not_at_all() {
+    let (mut market, producer, consumer) = partial_liquidity();
+    assert_eq!(
+        demand(&mut market, consumer, 50, TimeInForce::FillOrKill, &[producer]).unwrap_err(),
+        custom(EnergyMarketError::OrderNotFilled),
+    );
+    assert_eq!(market.bank.ledger(&market.ledger).productions[0].energy_amount, 30);
+    assert_eq!(balances(&market, &consumer), (1_000, 0));
+
+    demand(&mut market, consumer, 30, TimeInForce::FillOrKill, &[producer]).unwrap();
+    assert_eq!(market.bank.events::<TradeExecuted>()[0].amount, 30);
+    assert_eq!(balances(&market, &consumer), (700, 0));
+    assert!(market.bank.ledger(&market.ledger).productions.is_empty());
+}
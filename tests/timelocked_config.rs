@@ -0,0 +1,119 @@
+mod common;
+
+use borsh::BorshSerialize;
+use common::{custom, Market};
+use energy_trading_program::{
+    client,
+    events::{ConfigChangeCancelled, ConfigChangeProposed, ConfigUpdated},
+    ConfigUpdate, EnergyMarketError, MarketConfig, ParticipantType, PendingConfigChange,
+};
+use solana_program::program_error::ProgramError;
+
+const TIMELOCK: i64 = 86_400;
+
+fn timelocked_market() -> Market {
+    Market::new(MarketConfig { fee_bps: 100, config_timelock: TIMELOCK, ..MarketConfig::default() })
+}
+
+fn propose(market: &mut Market, update: ConfigUpdate, effective_at: i64) -> Result<(), ProgramError> {
+    market.bank.process(&client::propose_config_change_ix(market.ledger, market.admin, update, effective_at))
+}
+
+fn apply(market: &mut Market) -> Result<(), ProgramError> {
+    market.bank.process(&client::apply_config_change_ix(market.ledger))
+}
+
+#[test]
+fn change_applies_once_the_clock_passes_effective_at() {
+    let mut market = timelocked_market();
+    let effective_at = market.bank.now + TIMELOCK;
+    let early = propose(&mut market, ConfigUpdate::FeeBps(300), effective_at - 1);
+    assert_eq!(early.unwrap_err(), custom(EnergyMarketError::InvalidEffectiveTime));
+    propose(&mut market, ConfigUpdate::FeeBps(300), effective_at).unwrap();
+    assert_eq!(
+        market.bank.events::<ConfigChangeProposed>(),
+        vec![ConfigChangeProposed { update: ConfigUpdate::FeeBps(300), effective_at }],
+    );
+    assert_eq!(
+        market.bank.ledger(&market.ledger).pending_config_change,
+        Some(PendingConfigChange { update: ConfigUpdate::FeeBps(300), effective_at }),
+    );
+
+    market.bank.now = effective_at - 1;
+    assert_eq!(apply(&mut market).unwrap_err(), custom(EnergyMarketError::ConfigChangeNotReady));
+    assert_eq!(market.bank.ledger(&market.ledger).config.fee_bps, 100);
+
+    // Anyone may apply it once it is due
+    market.bank.now = effective_at;
+    apply(&mut market).unwrap();
+    assert_eq!(
+        market.bank.events::<ConfigUpdated>(),
+        vec![ConfigUpdated { previous: ConfigUpdate::FeeBps(100), update: ConfigUpdate::FeeBps(300) }],
+    );
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.config.fee_bps, ledger.pending_config_change), (300, None));
+    assert_eq!(apply(&mut market).unwrap_err(), custom(EnergyMarketError::NoPendingConfigChange));
+}
+
+#[test]
+fn timelock_disables_direct_updates() {
+    let mut market = timelocked_market();
+    let set_fee = client::set_fee_ix(market.ledger, market.admin, 300);
+    assert_eq!(market.bank.process(&set_fee).unwrap_err(), custom(EnergyMarketError::ConfigTimelocked));
+    let update = client::update_config_ix(market.ledger, market.admin, ConfigUpdate::FeeBps(300));
+    assert_eq!(market.bank.process(&update).unwrap_err(), custom(EnergyMarketError::ConfigTimelocked));
+
+    // Without a timelock a proposal only needs to be in the present or future
+    let mut market = Market::new(MarketConfig::default());
+    let now = market.bank.now;
+    assert_eq!(propose(&mut market, ConfigUpdate::FeeBps(300), now - 1).unwrap_err(), custom(EnergyMarketError::InvalidEffectiveTime));
+    propose(&mut market, ConfigUpdate::FeeBps(300), now).unwrap();
+    apply(&mut market).unwrap();
+    assert_eq!(market.bank.ledger(&market.ledger).config.fee_bps, 300);
+}
+
+#[test]
+fn admin_cancels_the_single_pending_change() {
+    let mut market = timelocked_market();
+    let effective_at = market.bank.now + TIMELOCK;
+    propose(&mut market, ConfigUpdate::FeeBps(300), effective_at).unwrap();
+    let second = propose(&mut market, ConfigUpdate::FeeBps(200), effective_at);
+    assert_eq!(second.unwrap_err(), custom(EnergyMarketError::ConfigChangePending));
+
+    let producer = market.register(ParticipantType::Producer, 0);
+    let by_producer = client::cancel_config_change_ix(market.ledger, producer);
+    assert_eq!(market.bank.process(&by_producer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    let by_producer = client::propose_config_change_ix(market.ledger, producer, ConfigUpdate::FeeBps(0), effective_at);
+    assert_eq!(market.bank.process(&by_producer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+
+    let cancel = client::cancel_config_change_ix(market.ledger, market.admin);
+    market.bank.process(&cancel).unwrap();
+    assert_eq!(
+        market.bank.events::<ConfigChangeCancelled>(),
+        vec![ConfigChangeCancelled { update: ConfigUpdate::FeeBps(300), effective_at }],
+    );
+    market.bank.now = effective_at;
+    assert_eq!(apply(&mut market).unwrap_err(), custom(EnergyMarketError::NoPendingConfigChange));
+    assert_eq!(market.bank.process(&cancel).unwrap_err(), custom(EnergyMarketError::NoPendingConfigChange));
+    assert_eq!(market.bank.ledger(&market.ledger).config.fee_bps, 100);
+
+    // With the slot free again a new proposal can be made
+    let effective_at = market.bank.now + TIMELOCK;
+    propose(&mut market, ConfigUpdate::FeeBps(200), effective_at).unwrap();
+}
+
+#[test]
+fn invalid_change_is_rejected_when_proposed_and_again_when_applied() {
+    let mut market = Market::new(MarketConfig { crank_reward_bps: 100, config_timelock: TIMELOCK, ..MarketConfig::default() });
+    let effective_at = market.bank.now + TIMELOCK;
+    assert_eq!(propose(&mut market, ConfigUpdate::FeeBps(9_901), effective_at).unwrap_err(), custom(EnergyMarketError::InvalidFeeRate));
+    propose(&mut market, ConfigUpdate::FeeBps(9_900), effective_at).unwrap();
+
+    // The crank reward it was checked against goes up before it is applied
+    let mut ledger = market.bank.ledger(&market.ledger);
+    ledger.config.crank_reward_bps = 200;
+    ledger.serialize(&mut market.bank.account_mut(&market.ledger).data.as_mut_slice()).unwrap();
+    market.bank.now = effective_at;
+    assert_eq!(apply(&mut market).unwrap_err(), custom(EnergyMarketError::InvalidFeeRate));
+    assert_eq!(market.bank.ledger(&market.ledger).config.fee_bps, 0);
+}
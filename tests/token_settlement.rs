@@ -0,0 +1,59 @@
+mod common;
+
+use common::{custom, program_id, Market};
+use energy_trading_program::{client, find_vault_address, EnergyMarketError, MarketConfig, ParticipantType};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+// A market settling in a quote token, with the vault PDA's token account
+fn token_market() -> (Market, Pubkey, Pubkey) {
+    let mint = Pubkey::new_unique();
+    let mut market = Market::builder(MarketConfig::default()).quote_mint(mint).build();
+    let vault = find_vault_address(&program_id(), &market.ledger).0;
+    let vault_tokens = market.bank.token_account(&mint, &vault, 0);
+    (market, mint, vault_tokens)
+}
+
+#[test]
+fn deposits_trades_and_withdrawals_move_tokens_through_the_vault() {
+    let (mut market, mint, vault_tokens) = token_market();
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 0);
+    let consumer_tokens = market.bank.token_account(&mint, &consumer, 1_000);
+    let producer_tokens = market.bank.token_account(&mint, &producer, 0);
+
+    market.bank.process(&client::deposit_ix(market.ledger, consumer, 600, Some((consumer_tokens, vault_tokens)))).unwrap();
+    assert_eq!((market.bank.token_balance(&consumer_tokens), market.bank.token_balance(&vault_tokens)), (400, 600));
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).wallet_balance, 600);
+
+    market.report_production(producer, 50, 10).unwrap();
+    market.post_demand(consumer, 50, 10).unwrap();
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+    assert_eq!(market.bank.participant(&market.ledger, &producer).wallet_balance, 500);
+
+    market.bank.process(&client::withdraw_ix(market.ledger, producer, producer_tokens, 500, Some(vault_tokens))).unwrap();
+    assert_eq!((market.bank.token_balance(&producer_tokens), market.bank.token_balance(&vault_tokens)), (500, 100));
+    let balances: u64 = [producer, consumer].iter().map(|w| market.bank.participant(&market.ledger, w).wallet_balance).sum();
+    assert_eq!(balances, market.bank.token_balance(&vault_tokens));
+}
+
+#[test]
+fn token_accounts_must_match_the_mint_and_owner() {
+    let (mut market, mint, vault_tokens) = token_market();
+    let consumer = market.register(ParticipantType::Consumer, 0);
+    let other_mint = market.bank.token_account(&Pubkey::new_unique(), &consumer, 1_000);
+    let someone_elses = market.bank.token_account(&mint, &Pubkey::new_unique(), 1_000);
+    let not_the_vaults = market.bank.token_account(&mint, &consumer, 0);
+    let consumer_tokens = market.bank.token_account(&mint, &consumer, 1_000);
+
+    let deposit = |source, vault_tokens| client::deposit_ix(market.ledger, consumer, 100, Some((source, vault_tokens)));
+    for (source, vault) in [(other_mint, vault_tokens), (someone_elses, vault_tokens), (consumer_tokens, not_the_vaults)] {
+        assert_eq!(market.bank.process(&deposit(source, vault)).unwrap_err(), custom(EnergyMarketError::InvalidTokenAccount));
+    }
+    let wallet = market.bank.funded_wallet(1);
+    assert_eq!(market.bank.process(&deposit(wallet, vault_tokens)).unwrap_err(), ProgramError::IncorrectProgramId);
+    assert_eq!(market.bank.token_balance(&consumer_tokens), 1_000);
+
+    market.bank.process(&deposit(consumer_tokens, vault_tokens)).unwrap();
+    let wrong_destination = client::withdraw_ix(market.ledger, consumer, other_mint, 100, Some(vault_tokens));
+    assert_eq!(market.bank.process(&wrong_destination).unwrap_err(), custom(EnergyMarketError::InvalidTokenAccount));
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use borsh::BorshSerialize;
+use common::Market;
+use energy_trading_program::{ledger_space, Ledger, LedgerCapacity, MarketConfig, ParticipantType};
+use solana_program::pubkey::Pubkey;
+
+const HISTORY: usize = 4;
+
+fn history_market() -> (Market, Pubkey, Pubkey) {
+    let capacity = LedgerCapacity { max_participants: 4, max_open_orders: 4, max_transactions: HISTORY as u32 };
+    let mut market = Market::builder(MarketConfig::default()).capacity(capacity).build();
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 1_000_000);
+    (market, producer, consumer)
+}
+
+fn trade(market: &mut Market, producer: Pubkey, consumer: Pubkey) {
+    market.report_production(producer, 10, 10).unwrap();
+    market.post_demand(consumer, 10, 10).unwrap();
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+}
+
+// The stored history oldest first, as the ring buffer's head defines it
+fn chronological_trade_ids(ledger: &Ledger) -> Vec<u64> {
+    let (newer, older) = ledger.transactions.split_at(ledger.history_head as usize);
+    older.iter().chain(newer).map(|t| t.trade_id).collect()
+}
+
+#[test]
+fn history_wraps_and_keeps_the_latest_trades_in_order() {
+    let (mut market, producer, consumer) = history_market();
+    for _ in 0..HISTORY * 2 + 1 {
+        trade(&mut market, producer, consumer);
+    }
+
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.total_trades, ledger.transactions.len(), ledger.history_head), (9, HISTORY, 1));
+    let last = ledger.transactions.iter().map(|t| t.trade_id).max().unwrap();
+    assert_eq!(chronological_trade_ids(&ledger), (last + 1 - HISTORY as u64..=last).collect::<Vec<_>>());
+
+    // Matching carries on after the wrap
+    trade(&mut market, producer, consumer);
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!((ledger.total_trades, ledger.history_head), (10, 2));
+}
+
+#[test]
+fn ledger_footprint_stops_growing_once_the_history_is_full() {
+    let (mut market, producer, consumer) = history_market();
+    let mut sizes = Vec::new();
+    for _ in 0..HISTORY * 3 {
+        trade(&mut market, producer, consumer);
+        sizes.push(market.bank.ledger(&market.ledger).try_to_vec().unwrap().len());
+    }
+
+    assert!(sizes[..HISTORY].windows(2).all(|pair| pair[0] < pair[1]), "{:?}", sizes);
+    assert!(sizes[HISTORY - 1..].iter().all(|&size| size == sizes[HISTORY - 1]), "{:?}", sizes);
+    let capacity = market.bank.ledger(&market.ledger).capacity;
+    assert!(sizes[HISTORY - 1] <= ledger_space(&capacity));
+    assert_eq!(market.bank.account(&market.ledger).unwrap().data.len(), ledger_space(&capacity));
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use borsh::BorshDeserialize;
+use common::{custom, Market};
+use energy_trading_program::{
+    client, trade_log_space, EnergyMarketError, HistoryPolicy, MarketConfig, ParticipantType, TradeLogHeader, Transaction,
+    TRADE_LOG_HEADER_SIZE, TRANSACTION_SIZE,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+struct Traders {
+    market: Market,
+    trade_log: Pubkey,
+    producer: Pubkey,
+    consumer: Pubkey,
+}
+
+fn with_trade_log(capacity: u32, history_policy: HistoryPolicy) -> Traders {
+    let mut market = Market::new(MarketConfig::default());
+    let trade_log = market.bank.program_account(trade_log_space(capacity));
+    market.bank.process(&client::initialize_trade_log_ix(market.ledger, market.admin, trade_log, capacity, history_policy)).unwrap();
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 10_000);
+    Traders { market, trade_log, producer, consumer }
+}
+
+// Matches one trade of `amount` kWh, recording it in the trade log when `logged`
+fn trade(traders: &mut Traders, amount: u64, logged: bool) -> Result<(), ProgramError> {
+    let Traders { market, trade_log, producer, consumer } = traders;
+    market.report_production(*producer, amount, 10).unwrap();
+    market.post_demand(*consumer, amount, 10).unwrap();
+    let mut remaining = client::participant_metas(market.ledger, &[*producer, *consumer]);
+    if logged {
+        remaining.insert(0, client::trade_log_meta(*trade_log));
+    }
+    market.bank.process(&client::match_transactions_ix(market.ledger, *consumer, 16, remaining))
+}
+
+// The header and the logged trades, oldest first
+fn read_log(traders: &Traders) -> (TradeLogHeader, Vec<Transaction>) {
+    let data = &traders.market.bank.account(&traders.trade_log).unwrap().data;
+    let header = TradeLogHeader::deserialize(&mut &data[..TRADE_LOG_HEADER_SIZE]).unwrap();
+    let start = if header.len < header.capacity { 0 } else { header.cursor };
+    let trades = (0..header.len)
+        .map(|i| {
+            let offset = TRADE_LOG_HEADER_SIZE + ((start + i) % header.capacity) as usize * TRANSACTION_SIZE;
+            Transaction::deserialize(&mut &data[offset..offset + TRANSACTION_SIZE]).unwrap()
+        })
+        .collect();
+    (header, trades)
+}
+
+#[test]
+fn cursor_wraps_over_the_oldest_trades() {
+    let mut traders = with_trade_log(3, HistoryPolicy::DropOldest);
+    for amount in 1..=2 {
+        trade(&mut traders, amount, true).unwrap();
+    }
+    let (header, _) = read_log(&traders);
+    assert_eq!((header.cursor, header.len, header.total_appended), (2, 2, 2));
+
+    for amount in 3..=5 {
+        trade(&mut traders, amount, true).unwrap();
+    }
+    let (header, trades) = read_log(&traders);
+    assert_eq!((header.cursor, header.len, header.total_appended), (2, 3, 5));
+    let amounts: Vec<_> = trades.iter().map(|t| (t.trade_id, t.amount)).collect();
+    assert_eq!(amounts, vec![(2, 3), (3, 4), (4, 5)]);
+
+    // Settled trades live only in the log
+    let ledger = traders.market.bank.ledger(&traders.market.ledger);
+    assert_eq!((ledger.transactions.len(), ledger.total_trades), (0, 5));
+}
+
+#[test]
+fn full_log_either_wraps_or_fails_the_match() {
+    let mut traders = with_trade_log(2, HistoryPolicy::Reject);
+    trade(&mut traders, 1, true).unwrap();
+    trade(&mut traders, 2, true).unwrap();
+    assert_eq!(trade(&mut traders, 3, true).unwrap_err(), custom(EnergyMarketError::TradeLogFull));
+    let ledger = traders.market.bank.ledger(&traders.market.ledger);
+    assert_eq!((ledger.productions.len(), ledger.demands.len(), ledger.total_trades), (1, 1, 2));
+    assert_eq!(read_log(&traders).0.total_appended, 2);
+
+    let mut traders = with_trade_log(2, HistoryPolicy::DropOldest);
+    for amount in 1..=3 {
+        trade(&mut traders, amount, true).unwrap();
+    }
+    let (header, trades) = read_log(&traders);
+    assert_eq!((header.len, trades.last().unwrap().amount), (2, 3));
+}
+
+#[test]
+fn matching_without_the_log_falls_back_to_the_embedded_history() {
+    let mut traders = with_trade_log(2, HistoryPolicy::DropOldest);
+    trade(&mut traders, 1, false).unwrap();
+    let ledger = traders.market.bank.ledger(&traders.market.ledger);
+    assert_eq!((ledger.transactions.len(), ledger.transactions[0].amount), (1, 1));
+    assert_eq!(read_log(&traders).0.total_appended, 0);
+
+    // Trade ids keep counting across both
+    trade(&mut traders, 2, true).unwrap();
+    assert_eq!(read_log(&traders).1[0].trade_id, 1);
+}
+
+#[test]
+fn trade_log_is_sized_and_tagged_once_by_the_admin() {
+    let mut market = Market::new(MarketConfig::default());
+    let trade_log = market.bank.program_account(trade_log_space(4));
+    let too_small = market.bank.program_account(trade_log_space(4) - 1);
+    let producer = market.register(ParticipantType::Producer, 0);
+
+    let by_producer = client::initialize_trade_log_ix(market.ledger, producer, trade_log, 4, HistoryPolicy::DropOldest);
+    assert_eq!(market.bank.process(&by_producer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    let short = client::initialize_trade_log_ix(market.ledger, market.admin, too_small, 4, HistoryPolicy::DropOldest);
+    assert_eq!(market.bank.process(&short).unwrap_err(), custom(EnergyMarketError::InvalidTradeLog));
+
+    let initialize = client::initialize_trade_log_ix(market.ledger, market.admin, trade_log, 4, HistoryPolicy::Reject);
+    market.bank.process(&initialize).unwrap();
+    assert_eq!(market.bank.ledger(&market.ledger).trade_log, trade_log);
+    assert_eq!(market.bank.process(&initialize).unwrap_err(), ProgramError::AccountAlreadyInitialized);
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use common::Market;
+use energy_trading_program::{
+    client, events::TradeExecuted,
+    matching::{delivered_after_losses, injected_for_delivery},
+    ConfigUpdate, EnergyMarketError, LossBearer, LossConfig, MarketConfig, ParticipantType, Transaction,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+fn market_with_losses(loss_factor_bps: u16, bearer: LossBearer) -> Market {
+    Market::new(MarketConfig { losses: LossConfig { loss_factor_bps, inter_zone_loss_bps: 0, bearer }, ..MarketConfig::default() })
+}
+
+// One offer against one demand, both at a price of 10; returns the trade, if any, and the two wallets
+fn cross(market: &mut Market, offered: u64, demanded: u64, deposit: u64) -> (Option<Transaction>, Pubkey, Pubkey) {
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, deposit);
+    market.report_production(producer, offered, 10).unwrap();
+    market.post_demand(consumer, demanded, 10).unwrap();
+    market.match_orders(producer, &[producer, consumer]).unwrap();
+    let traded = !market.bank.events::<TradeExecuted>().is_empty();
+    (traded.then(|| market.bank.ledger(&market.ledger).transactions.last().unwrap().clone()), producer, consumer)
+}
+
+fn amounts(trade: &Transaction) -> (u64, u64, u64) {
+    (trade.amount, trade.injected_amount, trade.delivered_amount)
+}
+
+#[test]
+fn zero_loss_factor_delivers_what_was_injected() {
+    let mut market = market_with_losses(0, LossBearer::Consumer);
+    let (trade, producer, consumer) = cross(&mut market, 100, 100, 1_000);
+    assert_eq!(amounts(&trade.unwrap()), (100, 100, 100));
+    assert_eq!(market.bank.participant(&market.ledger, &producer).wallet_balance, 1_000);
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).wallet_balance, 0);
+}
+
+#[test]
+fn three_percent_loss_is_paid_by_the_configured_side() {
+    // Delivering the 97 kWh demanded takes all 100 kWh offered
+    let mut market = market_with_losses(300, LossBearer::Producer);
+    let (trade, producer, consumer) = cross(&mut market, 100, 97, 970);
+    assert_eq!(amounts(&trade.unwrap()), (97, 100, 97));
+    assert_eq!(market.bank.participant(&market.ledger, &producer).wallet_balance, 970);
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).wallet_balance, 0);
+    let ledger = market.bank.ledger(&market.ledger);
+    assert!(ledger.productions.is_empty() && ledger.demands.is_empty());
+
+    // Bearing the loss, the consumer pays for all 100 kWh out of its escrow of 970 and 30 more
+    let mut market = market_with_losses(300, LossBearer::Consumer);
+    let (trade, producer, consumer) = cross(&mut market, 100, 97, 1_000);
+    assert_eq!(amounts(&trade.unwrap()), (100, 100, 97));
+    assert_eq!(market.bank.participant(&market.ledger, &producer).wallet_balance, 1_000);
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).wallet_balance, 0);
+
+    // Without those 30 the fill is skipped rather than failing
+    let mut market = market_with_losses(300, LossBearer::Consumer);
+    let (trade, _, consumer) = cross(&mut market, 100, 97, 970);
+    assert!(trade.is_none());
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).reserved_balance, 970);
+}
+
+#[test]
+fn losses_round_up_on_single_units() {
+    assert_eq!(delivered_after_losses(1, 300), 0);
+    assert_eq!(delivered_after_losses(2, 300), 1);
+    assert_eq!(delivered_after_losses(34, 300), 32);
+    assert_eq!(injected_for_delivery(1, 300), 2);
+    assert_eq!(injected_for_delivery(0, 300), 0);
+    assert_eq!(injected_for_delivery(u64::MAX, 9_999), u64::MAX);
+    for delivered in [1, 2, 32, 33, 97, 1_000_003] {
+        assert_eq!(delivered_after_losses(injected_for_delivery(delivered, 300), 300), delivered);
+    }
+
+    // A single unit offered delivers nothing, so it does not trade; two units deliver one
+    let mut market = market_with_losses(300, LossBearer::Producer);
+    let (trade, _, _) = cross(&mut market, 1, 1, 10);
+    assert!(trade.is_none());
+    let mut market = market_with_losses(300, LossBearer::Producer);
+    let (trade, _, _) = cross(&mut market, 2, 1, 10);
+    assert_eq!(amounts(&trade.unwrap()), (1, 2, 1));
+
+    let everything = LossConfig { loss_factor_bps: 10_000, ..LossConfig::default() };
+    let update = client::update_config_ix(market.ledger, market.admin, ConfigUpdate::Losses(everything));
+    assert_eq!(market.bank.process(&update).unwrap_err(), ProgramError::Custom(EnergyMarketError::InvalidConfigValue as u32));
+}
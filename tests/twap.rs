@@ -0,0 +1,63 @@
+mod common;
+
+use borsh::BorshDeserialize;
+use common::{custom, Market};
+use energy_trading_program::{client, compute_twap, EnergyMarketError, MarketConfig, ParticipantType, PRICE_HISTORY_LEN};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+// One match run trading 10 kWh at `price`, which samples that price at the current time
+fn run_at(market: &mut Market, wallets: (Pubkey, Pubkey), price: i64) {
+    let (producer, consumer) = wallets;
+    market.report_production(producer, 10, price).unwrap();
+    market.post_demand(consumer, 10, price).unwrap();
+    market.match_orders(consumer, &[producer, consumer]).unwrap();
+}
+
+fn twap(market: &mut Market, window_secs: i64) -> Result<i64, ProgramError> {
+    market.bank.process(&client::get_twap_ix(market.ledger, window_secs))?;
+    Ok(i64::try_from_slice(&market.bank.return_data().unwrap()).unwrap())
+}
+
+fn trading_market() -> (Market, (Pubkey, Pubkey)) {
+    let mut market = Market::new(MarketConfig::default());
+    let producer = market.register(ParticipantType::Producer, 0);
+    let consumer = market.register(ParticipantType::Consumer, 100_000);
+    (market, (producer, consumer))
+}
+
+#[test]
+fn twap_weights_each_price_by_how_long_it_held() {
+    let (mut market, wallets) = trading_market();
+    run_at(&mut market, wallets, 10);
+    assert_eq!(twap(&mut market, 3_600).unwrap_err(), custom(EnergyMarketError::NotEnoughData));
+    market.bank.now += 100;
+    run_at(&mut market, wallets, 20);
+    market.bank.now += 200;
+    run_at(&mut market, wallets, 40);
+
+    // 10 for 100 seconds and 20 for 200; the newest sample only closes the last interval
+    assert_eq!(twap(&mut market, 300).unwrap(), (10 * 100 + 20 * 200) / 300);
+    assert_eq!(twap(&mut market, 200).unwrap(), 20);
+    assert_eq!(twap(&mut market, 100).unwrap_err(), custom(EnergyMarketError::NotEnoughData));
+
+    // Once the clock moves past the window, every sample has aged out of it
+    market.bank.now += 1_000;
+    assert_eq!(twap(&mut market, 300).unwrap_err(), custom(EnergyMarketError::NotEnoughData));
+    assert_eq!(twap(&mut market, 1_300).unwrap(), (10 * 100 + 20 * 200) / 300);
+}
+
+#[test]
+fn history_keeps_the_latest_samples_once_it_wraps() {
+    let (mut market, wallets) = trading_market();
+    for price in 1..=30 {
+        run_at(&mut market, wallets, price);
+        market.bank.now += 10;
+    }
+    let ledger = market.bank.ledger(&market.ledger);
+    assert_eq!(ledger.price_history_len as usize, PRICE_HISTORY_LEN);
+
+    // Prices 7 to 30 remain, each but the last holding for 10 seconds
+    let expected = (7..30).sum::<i64>() / 23;
+    assert_eq!(compute_twap(&ledger, market.bank.now, i64::MAX).unwrap(), expected);
+    assert_eq!(twap(&mut market, i64::MAX).unwrap(), expected);
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use common::{custom, program_id, Market};
+use energy_trading_program::{client, find_participant_address, EnergyMarketError, MarketConfig, OrderStorage, ParticipantType};
+use solana_program::program_error::ProgramError;
+
+#[test]
+fn participant_leaves_only_once_it_has_no_orders_or_balance() {
+    let mut market = Market::new(MarketConfig::default());
+    let consumer = market.register(ParticipantType::Consumer, 100);
+    market.post_demand(consumer, 10, 10).unwrap();
+    let order_id = market.bank.ledger(&market.ledger).demands[0].order_id;
+    let unregister = client::unregister_participant_ix(market.ledger, consumer);
+    assert_eq!(market.bank.process(&unregister).unwrap_err(), custom(EnergyMarketError::ParticipantHasOpenOrders));
+
+    market.bank.process(&client::cancel_demand_ix(market.ledger, consumer, order_id, OrderStorage::Ledger)).unwrap();
+    assert_eq!(market.bank.process(&unregister).unwrap_err(), custom(EnergyMarketError::ParticipantHasBalance));
+
+    // Leaving closes the participant PDA and hands its rent back to the wallet
+    market.bank.process(&client::withdraw_ix(market.ledger, consumer, consumer, 100, None)).unwrap();
+    let (address, _) = find_participant_address(&program_id(), &market.ledger, &consumer);
+    let (rent, lamports) = (market.bank.lamports(&address), market.bank.lamports(&consumer));
+    market.bank.process(&unregister).unwrap();
+    assert_eq!((market.bank.lamports(&address), market.bank.lamports(&consumer)), (0, lamports + rent));
+    assert_eq!(market.bank.ledger(&market.ledger).participant_count, 0);
+
+    // The wallet is free to register again
+    market.register(ParticipantType::Producer, 0);
+}
+
+#[test]
+fn admin_removal_refunds_balance_and_escrow_to_the_wallet() {
+    let mut market = Market::new(MarketConfig::default());
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.post_demand(consumer, 10, 10).unwrap();
+    let (address, _) = find_participant_address(&program_id(), &market.ledger, &consumer);
+    let lamports = market.bank.lamports(&consumer) + market.bank.lamports(&address);
+
+    let force = |admin, destination| client::force_unregister_participant_ix(market.ledger, admin, consumer, destination, None, &[]);
+    let by_consumer = force(consumer, consumer);
+    assert_eq!(market.bank.process(&by_consumer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    let to_admin = force(market.admin, market.admin);
+    assert_eq!(market.bank.process(&to_admin).unwrap_err(), ProgramError::InvalidArgument);
+
+    market.bank.process(&force(market.admin, consumer)).unwrap();
+    assert_eq!(market.bank.lamports(&consumer), lamports + 1_000);
+    let ledger = market.bank.ledger(&market.ledger);
+    assert!(ledger.demands.is_empty());
+    assert_eq!(ledger.participant_count, 0);
+}
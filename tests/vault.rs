@@ -0,0 +1,44 @@
+mod common;
+
+use common::{custom, program_id, Market};
+use energy_trading_program::{client, find_vault_address, EnergyMarketError, MarketConfig, ParticipantType};
+use solana_program::{pubkey::Pubkey, rent::Rent};
+
+fn vault(market: &Market) -> Pubkey {
+    find_vault_address(&program_id(), &market.ledger).0
+}
+
+#[test]
+fn vault_holds_every_deposited_lamport() {
+    let mut market = Market::new(MarketConfig::default());
+    let rent_reserve = market.bank.lamports(&vault(&market));
+    assert!(rent_reserve >= Rent::default().minimum_balance(0));
+    let consumer = market.register(ParticipantType::Consumer, 0);
+    let producer = market.register(ParticipantType::Producer, 0);
+
+    let consumer_lamports = market.bank.lamports(&consumer);
+    market.bank.process(&client::deposit_ix(market.ledger, consumer, 700, None)).unwrap();
+    market.bank.process(&client::deposit_ix(market.ledger, producer, 300, None)).unwrap();
+    assert_eq!(market.bank.lamports(&consumer), consumer_lamports - 700);
+    assert_eq!(market.bank.lamports(&vault(&market)), rent_reserve + 1_000);
+
+    market.bank.process(&client::withdraw_ix(market.ledger, consumer, consumer, 200, None)).unwrap();
+    assert_eq!(market.bank.lamports(&consumer), consumer_lamports - 500);
+    let balances: u64 = [consumer, producer].iter().map(|w| market.bank.participant(&market.ledger, w).wallet_balance).sum();
+    assert_eq!(market.bank.lamports(&vault(&market)), rent_reserve + balances);
+}
+
+#[test]
+fn deposits_and_withdrawals_reject_a_foreign_vault() {
+    let mut market = Market::new(MarketConfig::default());
+    let consumer = market.register(ParticipantType::Consumer, 500);
+    let impostor = market.bank.funded_wallet(1);
+
+    let mut deposit = client::deposit_ix(market.ledger, consumer, 100, None);
+    deposit.accounts[3].pubkey = impostor;
+    assert_eq!(market.bank.process(&deposit).unwrap_err(), custom(EnergyMarketError::InvalidVaultAccount));
+    let mut withdraw = client::withdraw_ix(market.ledger, consumer, consumer, 100, None);
+    withdraw.accounts[4].pubkey = impostor;
+    assert_eq!(market.bank.process(&withdraw).unwrap_err(), custom(EnergyMarketError::InvalidVaultAccount));
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).wallet_balance, 500);
+}
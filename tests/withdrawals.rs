@@ -0,0 +1,176 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, events::{WithdrawalRequested, WithdrawalVetoed}, EnergyMarketError, MarketConfig, ParticipantType,
+    WITHDRAWAL_WINDOW_SECONDS,
+};
+use solana_program::pubkey::Pubkey;
+
+const DELAY: i64 = 3_600;
+
+// Withdrawals above 100 wait out DELAY before they can be claimed
+fn delayed_market() -> Market {
+    Market::builder(MarketConfig::default())
+        .admin_instruction(|ledger, admin| client::set_withdrawal_delay_ix(ledger, admin, DELAY, 100))
+        .build()
+}
+
+fn balances(market: &Market, wallet: &Pubkey) -> (u64, u64) {
+    let participant = market.bank.participant(&market.ledger, wallet);
+    (participant.wallet_balance, participant.pending_withdrawal)
+}
+
+#[test]
+fn withdraw_all_empties_the_free_balance() {
+    let mut market = Market::new(MarketConfig::default());
+    let consumer = market.register(ParticipantType::Consumer, 1_234);
+    let producer = market.register(ParticipantType::Producer, 0);
+    market.post_demand(consumer, 10, 10).unwrap();
+    let lamports = market.bank.lamports(&consumer);
+
+    // What the demand holds in escrow stays behind
+    market.bank.process(&client::withdraw_all_ix(market.ledger, consumer, consumer, None)).unwrap();
+    assert_eq!(balances(&market, &consumer), (0, 0));
+    assert_eq!(market.bank.lamports(&consumer), lamports + 1_134);
+    assert_eq!(market.bank.participant(&market.ledger, &consumer).reserved_balance, 100);
+
+    market.bank.process(&client::withdraw_all_ix(market.ledger, producer, producer, None)).unwrap();
+    assert_eq!(balances(&market, &producer), (0, 0));
+}
+
+#[test]
+fn immediate_path_stays_the_default_without_a_delay() {
+    let mut market = Market::new(MarketConfig::default());
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.bank.process(&client::withdraw_ix(market.ledger, consumer, consumer, 1_000, None)).unwrap();
+    assert_eq!(balances(&market, &consumer), (0, 0));
+
+    // Requests still work and are claimable at once
+    market.bank.process(&client::deposit_ix(market.ledger, consumer, 500, None)).unwrap();
+    market.bank.process(&client::request_withdrawal_ix(market.ledger, consumer, None)).unwrap();
+    assert_eq!(balances(&market, &consumer), (0, 500));
+    market.bank.process(&client::claim_withdrawal_ix(market.ledger, consumer, consumer, None)).unwrap();
+    assert_eq!(balances(&market, &consumer), (0, 0));
+}
+
+#[test]
+fn large_withdrawals_wait_out_the_delay() {
+    let mut market = delayed_market();
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    let lamports = market.bank.lamports(&consumer);
+
+    // Up to the threshold is paid straight away
+    market.bank.process(&client::withdraw_ix(market.ledger, consumer, consumer, 100, None)).unwrap();
+    let direct = client::withdraw_ix(market.ledger, consumer, consumer, 101, None);
+    assert_eq!(market.bank.process(&direct).unwrap_err(), custom(EnergyMarketError::WithdrawalRequestRequired));
+    let all = client::withdraw_all_ix(market.ledger, consumer, consumer, None);
+    assert_eq!(market.bank.process(&all).unwrap_err(), custom(EnergyMarketError::WithdrawalRequestRequired));
+
+    let claim = client::claim_withdrawal_ix(market.ledger, consumer, consumer, None);
+    assert_eq!(market.bank.process(&claim).unwrap_err(), custom(EnergyMarketError::NoPendingWithdrawal));
+    market.bank.process(&client::request_withdrawal_ix(market.ledger, consumer, Some(600))).unwrap();
+    let claimable_after = market.bank.now + DELAY;
+    assert_eq!(
+        market.bank.events::<WithdrawalRequested>(),
+        vec![WithdrawalRequested { participant: consumer, amount: 600, claimable_after }],
+    );
+    assert_eq!(balances(&market, &consumer), (300, 600));
+    let again = client::request_withdrawal_ix(market.ledger, consumer, Some(1));
+    assert_eq!(market.bank.process(&again).unwrap_err(), custom(EnergyMarketError::WithdrawalPending));
+
+    market.bank.now = claimable_after - 1;
+    assert_eq!(market.bank.process(&claim).unwrap_err(), custom(EnergyMarketError::WithdrawalLocked));
+    market.bank.now = claimable_after;
+    market.bank.process(&claim).unwrap();
+    assert_eq!(balances(&market, &consumer), (300, 0));
+    assert_eq!(market.bank.lamports(&consumer), lamports + 700);
+    assert_eq!(market.bank.process(&claim).unwrap_err(), custom(EnergyMarketError::NoPendingWithdrawal));
+}
+
+#[test]
+fn admin_veto_returns_the_request_to_the_free_balance() {
+    let mut market = delayed_market();
+    let consumer = market.register(ParticipantType::Consumer, 1_000);
+    market.bank.process(&client::request_withdrawal_ix(market.ledger, consumer, None)).unwrap();
+    assert_eq!(balances(&market, &consumer), (0, 1_000));
+
+    let by_consumer = client::veto_withdrawal_ix(market.ledger, consumer, consumer);
+    assert_eq!(market.bank.process(&by_consumer).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+    market.bank.process(&client::veto_withdrawal_ix(market.ledger, market.admin, consumer)).unwrap();
+    assert_eq!(market.bank.events::<WithdrawalVetoed>(), vec![WithdrawalVetoed { participant: consumer, amount: 1_000 }]);
+    assert_eq!(balances(&market, &consumer), (1_000, 0));
+    let claim = client::claim_withdrawal_ix(market.ledger, consumer, consumer, None);
+    assert_eq!(market.bank.process(&claim).unwrap_err(), custom(EnergyMarketError::NoPendingWithdrawal));
+
+    // Once the request is claimable the veto window has closed
+    market.bank.process(&client::request_withdrawal_ix(market.ledger, consumer, Some(500))).unwrap();
+    market.bank.now += DELAY;
+    let veto = client::veto_withdrawal_ix(market.ledger, market.admin, consumer);
+    assert_eq!(market.bank.process(&veto).unwrap_err(), custom(EnergyMarketError::VetoWindowClosed));
+    market.bank.process(&claim).unwrap();
+    assert_eq!(balances(&market, &consumer), (500, 0));
+}
+
+#[test]
+fn daily_limit_resets_once_the_window_is_a_day_old() {
+    let mut market = Market::new(MarketConfig { withdrawal_limit: 500, ..MarketConfig::default() });
+    let consumer = market.register(ParticipantType::Consumer, 2_000);
+    let withdraw = |amount| client::withdraw_ix(market.ledger, consumer, consumer, amount, None);
+
+    market.bank.process(&withdraw(300)).unwrap();
+    let window_start = market.bank.now;
+    market.bank.now += 3_600;
+    assert_eq!(market.bank.process(&withdraw(201)).unwrap_err(), custom(EnergyMarketError::WithdrawalLimitExceeded));
+    market.bank.process(&withdraw(200)).unwrap();
+    let participant = market.bank.participant(&market.ledger, &consumer);
+    assert_eq!((participant.withdrawn_in_window, participant.window_start), (500, window_start));
+
+    market.bank.now = window_start + WITHDRAWAL_WINDOW_SECONDS - 1;
+    assert_eq!(market.bank.process(&withdraw(1)).unwrap_err(), custom(EnergyMarketError::WithdrawalLimitExceeded));
+    market.bank.now += 1;
+    market.bank.process(&withdraw(500)).unwrap();
+    let participant = market.bank.participant(&market.ledger, &consumer);
+    assert_eq!((participant.withdrawn_in_window, participant.window_start, participant.wallet_balance), (500, market.bank.now, 1_000));
+}
+
+#[test]
+fn claimed_requests_count_against_the_limit() {
+    let mut market = Market::builder(MarketConfig { withdrawal_limit: 500, ..MarketConfig::default() })
+        .admin_instruction(|ledger, admin| client::set_withdrawal_delay_ix(ledger, admin, DELAY, 100))
+        .build();
+    let consumer = market.register(ParticipantType::Consumer, 2_000);
+    market.bank.process(&client::withdraw_ix(market.ledger, consumer, consumer, 100, None)).unwrap();
+    market.bank.process(&client::request_withdrawal_ix(market.ledger, consumer, Some(401))).unwrap();
+    market.bank.now += DELAY;
+
+    // The request stays pending for a claim in the next window
+    let claim = client::claim_withdrawal_ix(market.ledger, consumer, consumer, None);
+    assert_eq!(market.bank.process(&claim).unwrap_err(), custom(EnergyMarketError::WithdrawalLimitExceeded));
+    assert_eq!(balances(&market, &consumer), (1_499, 401));
+    market.bank.now += WITHDRAWAL_WINDOW_SECONDS;
+    market.bank.process(&claim).unwrap();
+    assert_eq!(balances(&market, &consumer), (1_499, 0));
+}
+
+#[test]
+fn admin_overrides_apply_to_one_participant() {
+    let mut market = Market::new(MarketConfig { withdrawal_limit: 500, ..MarketConfig::default() });
+    let trusted = market.register(ParticipantType::Consumer, 2_000);
+    let capped = market.register(ParticipantType::Consumer, 2_000);
+    let set_limit = |wallet, limit| client::set_withdrawal_limit_ix(market.ledger, market.admin, wallet, limit);
+    let by_participant = client::set_withdrawal_limit_ix(market.ledger, trusted, trusted, Some(0));
+    assert_eq!(market.bank.process(&by_participant).unwrap_err(), custom(EnergyMarketError::Unauthorized));
+
+    // Some(0) lifts the cap, Some(n) replaces it and None brings back the ledger's
+    market.bank.process(&set_limit(trusted, Some(0))).unwrap();
+    market.bank.process(&set_limit(capped, Some(100))).unwrap();
+    market.bank.process(&client::withdraw_ix(market.ledger, trusted, trusted, 1_500, None)).unwrap();
+    let withdraw = client::withdraw_ix(market.ledger, capped, capped, 101, None);
+    assert_eq!(market.bank.process(&withdraw).unwrap_err(), custom(EnergyMarketError::WithdrawalLimitExceeded));
+
+    market.bank.process(&set_limit(trusted, None)).unwrap();
+    let withdraw = client::withdraw_ix(market.ledger, trusted, trusted, 1, None);
+    assert_eq!(market.bank.process(&withdraw).unwrap_err(), custom(EnergyMarketError::WithdrawalLimitExceeded));
+    assert_eq!(market.bank.participant(&market.ledger, &trusted).withdrawal_limit, None);
+}
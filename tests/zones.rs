@@ -0,0 +1,61 @@
+mod common;
+
+use common::{custom, Market};
+use energy_trading_program::{
+    client, events::TradeExecuted, EnergyMarketError, MarketConfig, ParticipantMetadata, ParticipantType, ZoneConfig,
+};
+use solana_program::pubkey::Pubkey;
+
+fn zoned_market(allow_inter_zone: bool, wheeling_fee: u64) -> Market {
+    Market::new(MarketConfig { zones: ZoneConfig { zone_count: 2, allow_inter_zone, wheeling_fee }, ..MarketConfig::default() })
+}
+
+fn register_in(market: &mut Market, participant_type: ParticipantType, zone: u8, deposit: u64) -> Pubkey {
+    let wallet = market.bank.funded_wallet(1);
+    let register = client::register_participant_ix(market.ledger, wallet, participant_type, zone, 1_000_000, ParticipantMetadata::default());
+    market.bank.process(&register).unwrap();
+    if deposit > 0 {
+        market.bank.process(&client::deposit_ix(market.ledger, wallet, deposit, None)).unwrap();
+    }
+    wallet
+}
+
+#[test]
+fn orders_cross_only_within_their_zone_by_default() {
+    let mut market = zoned_market(false, 0);
+    let producer = register_in(&mut market, ParticipantType::Producer, 0, 0);
+    let far = register_in(&mut market, ParticipantType::Consumer, 1, 1_000);
+    let near = register_in(&mut market, ParticipantType::Consumer, 0, 1_000);
+    market.report_production(producer, 20, 10).unwrap();
+    // The far consumer bids higher and first, but sits on the other feeder
+    market.post_demand(far, 10, 12).unwrap();
+    market.post_demand(near, 10, 10).unwrap();
+    assert_eq!(market.bank.ledger(&market.ledger).demands[0].zone, 1);
+
+    market.match_orders(producer, &[producer, far, near]).unwrap();
+    let fills: Vec<_> = market.bank.events::<TradeExecuted>().iter().map(|t| (t.buyer, t.amount)).collect();
+    assert_eq!(fills, vec![(near, 10)]);
+    assert_eq!(market.bank.ledger(&market.ledger).demands[0].consumer_id, far);
+
+    let wallet = market.bank.funded_wallet(1);
+    let outside = client::register_participant_ix(market.ledger, wallet, ParticipantType::Consumer, 2, 0, ParticipantMetadata::default());
+    assert_eq!(market.bank.process(&outside).unwrap_err(), custom(EnergyMarketError::InvalidZone));
+}
+
+#[test]
+fn inter_zone_trades_pay_the_wheeling_fee_into_the_fee_pool() {
+    let mut market = zoned_market(true, 2);
+    let producer = register_in(&mut market, ParticipantType::Producer, 0, 0);
+    let far = register_in(&mut market, ParticipantType::Consumer, 1, 1_000);
+    let near = register_in(&mut market, ParticipantType::Consumer, 0, 1_000);
+    market.report_production(producer, 20, 10).unwrap();
+    market.post_demand(far, 10, 10).unwrap();
+    market.post_demand(near, 10, 10).unwrap();
+
+    market.match_orders(producer, &[producer, far, near]).unwrap();
+    assert_eq!(market.bank.events::<TradeExecuted>().len(), 2);
+    // 2 per kWh on the 10 kWh wheeled across; the near consumer pays only the price
+    let balance = |wallet| market.bank.participant(&market.ledger, &wallet).wallet_balance;
+    assert_eq!((balance(far), balance(near), balance(producer)), (1_000 - 100 - 20, 1_000 - 100, 200));
+    assert_eq!(market.bank.ledger(&market.ledger).protocol_fees, 20);
+}